@@ -0,0 +1,43 @@
+// records the versions of the native libraries ovid is built with, for `ovid doctor`
+
+use std::process::Command;
+
+fn main() {
+    println!("cargo:rerun-if-changed=Cargo.lock");
+    println!("cargo:rerun-if-changed=build.rs");
+
+    // resolved crate versions, from the lockfile when there is one
+    let lock = std::fs::read_to_string("Cargo.lock").unwrap_or_default();
+    for (name, var) in [
+        ("mupdf-sys", "OVID_MUPDF_SYS_VERSION"),
+        ("turbojpeg", "OVID_TURBOJPEG_VERSION"),
+        ("image", "OVID_IMAGE_VERSION"),
+        ("lopdf", "OVID_LOPDF_VERSION"),
+    ] {
+        let version = locked_version(&lock, name).unwrap_or_else(|| "unknown".to_string());
+        println!("cargo:rustc-env={}={}", var, version);
+    }
+
+    // turbojpeg links the system libjpeg-turbo found by pkg-config
+    let libjpeg_turbo = Command::new("pkg-config")
+        .args(["--modversion", "libturbojpeg"])
+        .output()
+        .ok()
+        .filter(|out| out.status.success())
+        .map(|out| String::from_utf8_lossy(&out.stdout).trim().to_string())
+        .filter(|version| !version.is_empty())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=OVID_LIBJPEG_TURBO_VERSION={}", libjpeg_turbo);
+}
+
+/// version of package `name` in Cargo.lock
+fn locked_version(lock: &str, name: &str) -> Option<String> {
+    let mut lines = lock.lines();
+    while let Some(line) = lines.next() {
+        if line.trim() == format!("name = \"{}\"", name) {
+            let version = lines.next()?.trim().strip_prefix("version = \"")?;
+            return Some(version.trim_end_matches('"').to_string());
+        }
+    }
+    None
+}
@@ -0,0 +1,52 @@
+/* C API for ovid's split and merge pipelines.
+ *
+ * Build the shared library with
+ *     cargo rustc --release --lib --features ffi --crate-type cdylib
+ * and link against libovid.so / libovid.dylib / ovid.dll.
+ *
+ * Functions return 0 on success, or -1 and, if `error` isn't NULL, a message
+ * in *error to be freed with ovid_string_free. Runs use ovid's thread pool;
+ * progress callbacks may come from any of its threads, but never two at once.
+ */
+#ifndef OVID_H
+#define OVID_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* pages done, pages in total, and the user_data given to the call */
+typedef void (*ovid_progress_fn)(size_t done, size_t total, void *user_data);
+
+/* Render the pages of the PDF `input` to images in `output_dir`, named
+ * <stem>_0001.png, ... as `ovid split` does.
+ *   format       "png" or "jpg"; NULL for png
+ *   dpi          72-2400; 0 for 300
+ *   pages        page selection like "1,3-5"; NULL for every page
+ *   quality      JPEG quality 1-100; 0 for 75
+ *   progress_fn  optional callback, called as each page is saved */
+int ovid_split(const char *input, const char *output_dir, const char *format,
+               uint32_t dpi, const char *pages, uint8_t quality, bool gray,
+               ovid_progress_fn progress_fn, void *user_data, char **error);
+
+/* Build a PDF with a page per image, in order, as `ovid merge` does; an
+ * `output` ending in .epub gets a fixed-layout EPUB instead.
+ *   pagesize     "a4", "letter", "legal" or "a3"; NULL sizes pages to the images
+ *   dpi          for sizing pages; 0 takes each image's own, or 300
+ *   progress_fn  optional callback, called as each image is added */
+int ovid_merge(const char *const *images, size_t count, const char *output,
+               const char *pagesize, uint32_t dpi, ovid_progress_fn progress_fn,
+               void *user_data, char **error);
+
+/* Free an error message; NULL is ignored. */
+void ovid_string_free(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* OVID_H */
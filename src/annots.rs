@@ -0,0 +1,226 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, FixedOffset, NaiveDate};
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+use tracing::info;
+
+use crate::pdf;
+
+/// annotation types that are part of how the document works rather than comments
+/// on it; they're neither exported nor stripped. popups only show their parent
+const KEPT: [&[u8]; 2] = [b"Link", b"Widget"];
+
+/// print a PDF's comments and markup (notes, highlights, shapes, stamps...) as a
+/// JSON array, one annotation per line, or write it to `output`. with `strip`,
+/// also save a copy of the PDF without them
+pub fn export_annotations(
+    input: &Path,
+    output: Option<&Path>,
+    strip: Option<&Path>,
+    quiet: bool,
+) -> Result<()> {
+    let mut doc = pdf::load(input)?;
+    let annots = page_annotations(&doc);
+    // replies point at the annotation they answer by its place in the list
+    let numbers: HashMap<ObjectId, usize> = annots
+        .iter()
+        .enumerate()
+        .filter_map(|(i, (_, id, _))| Some(((*id)?, i + 1)))
+        .collect();
+    let lines: Vec<String> = annots
+        .iter()
+        .enumerate()
+        .map(|(i, &(page, _, annot))| {
+            let annot = to_json(&doc, i + 1, page, annot, &numbers);
+            format!("  {}", serde_json::to_string(&annot).expect("annotations are plain objects"))
+        })
+        .collect();
+    let text = if lines.is_empty() {
+        "[]\n".to_string()
+    } else {
+        format!("[\n{}\n]\n", lines.join(",\n"))
+    };
+    match output {
+        Some(path) if path != Path::new("-") => std::fs::write(path, text)
+            .with_context(|| format!("Failed to save {}", path.display()))?,
+        _ => print!("{}", text),
+    }
+
+    if let Some(strip) = strip {
+        let removed = strip_annotations(&mut doc)?;
+        doc.prune_objects();
+        pdf::save(&mut doc, strip)?;
+        if !quiet {
+            info!("{} annotation(s) removed -> {}", removed, strip.display());
+        }
+    }
+    Ok(())
+}
+
+/// (page number, object id if indirect, dictionary) of every exported annotation
+fn page_annotations(doc: &Document) -> Vec<(u32, Option<ObjectId>, &Dictionary)> {
+    let mut found = Vec::new();
+    for (page, page_id) in doc.get_pages() {
+        let Ok(Ok((_, Object::Array(annots)))) = doc
+            .get_dictionary(page_id)
+            .and_then(|p| p.get(b"Annots"))
+            .map(|a| doc.dereference(a))
+        else {
+            continue;
+        };
+        for annot in annots {
+            let Ok((id, Object::Dictionary(dict))) = doc.dereference(annot) else {
+                continue;
+            };
+            let subtype = dict.get(b"Subtype").and_then(Object::as_name).unwrap_or(b"");
+            if !KEPT.contains(&subtype) && subtype != b"Popup" {
+                found.push((page, id, dict));
+            }
+        }
+    }
+    found
+}
+
+/// an annotation as `annots` exports it; `id` is its place in the list
+#[derive(Serialize)]
+struct AnnotJson {
+    id: usize,
+    page: u32,
+    #[serde(rename = "type")]
+    kind: String,
+    rect: Option<Vec<f64>>,
+    contents: Option<String>,
+    author: Option<String>,
+    subject: Option<String>,
+    modified: Option<String>,
+    color: Option<Vec<f64>>,
+    quads: Option<Vec<Vec<f64>>>,
+    reply_to: Option<usize>,
+}
+
+fn to_json(
+    doc: &Document,
+    number: usize,
+    page: u32,
+    annot: &Dictionary,
+    numbers: &HashMap<ObjectId, usize>,
+) -> AnnotJson {
+    let text = |key: &[u8]| match annot.get(key).map(|v| doc.dereference(v)) {
+        Ok(Ok((_, Object::String(bytes, _)))) => Some(pdf::decode_text(bytes)),
+        _ => None,
+    };
+    let numbers_of = |key: &[u8]| -> Option<Vec<f64>> {
+        let (_, value) = doc.dereference(annot.get(key).ok()?).ok()?;
+        let values = value.as_array().ok()?.iter().filter_map(pdf::number);
+        Some(values.map(round).collect())
+    };
+    let subtype = annot.get(b"Subtype").and_then(Object::as_name).unwrap_or(b"");
+    // text markup covers one quadrilateral per line: x1 y1 x2 y2 x3 y3 x4 y4
+    let quads = numbers_of(b"QuadPoints")
+        .map(|points| points.chunks_exact(8).map(<[f64]>::to_vec).collect());
+    AnnotJson {
+        id: number,
+        page,
+        kind: String::from_utf8_lossy(subtype).into_owned(),
+        rect: numbers_of(b"Rect"),
+        contents: text(b"Contents"),
+        author: text(b"T"),
+        subject: text(b"Subj"),
+        modified: text(b"M").map(|m| read_date(&m).map_or(m, |date| date.to_rfc3339())),
+        color: numbers_of(b"C"),
+        quads,
+        reply_to: annot
+            .get(b"IRT")
+            .and_then(Object::as_reference)
+            .ok()
+            .and_then(|id| numbers.get(&id).copied()),
+    }
+}
+
+fn round(n: f32) -> f64 {
+    (n as f64 * 100.0).round() / 100.0
+}
+
+/// take the exported annotations and their popups off every page
+fn strip_annotations(doc: &mut Document) -> Result<usize> {
+    let mut removed = 0;
+    for page_id in doc.get_pages().into_values() {
+        let annots = match doc.get_dictionary(page_id)?.get(b"Annots") {
+            Ok(annots) => match doc.dereference(annots) {
+                Ok((_, Object::Array(annots))) => annots.clone(),
+                _ => continue,
+            },
+            Err(_) => continue,
+        };
+        let kept: Vec<Object> = annots
+            .iter()
+            .filter(|annot| {
+                let subtype = doc
+                    .dereference(annot)
+                    .ok()
+                    .and_then(|(_, a)| a.as_dict().ok())
+                    .and_then(|a| a.get(b"Subtype").and_then(Object::as_name).ok());
+                // unreadable entries are left for viewers to judge
+                subtype.is_none_or(|s| KEPT.contains(&s))
+            })
+            .cloned()
+            .collect();
+        removed += annots.len() - kept.len();
+        let page = doc.get_dictionary_mut(page_id)?;
+        if kept.is_empty() {
+            page.remove(b"Annots");
+        } else {
+            page.set("Annots", kept);
+        }
+    }
+    Ok(removed)
+}
+
+/// a PDF date, D:YYYYMMDDHHmmSSOHH'mm', where everything after the year is
+/// optional; no offset is taken as UTC
+fn read_date(text: &str) -> Option<DateTime<FixedOffset>> {
+    let text = text.strip_prefix("D:").unwrap_or(text);
+    let digits = text.bytes().take_while(u8::is_ascii_digit).count();
+    if digits < 4 || digits % 2 != 0 || digits > 14 {
+        return None;
+    }
+    let field = |start: usize, default: u32| -> u32 {
+        match text.get(start..start + 2) {
+            Some(f) if start + 2 <= digits => f.parse().unwrap_or(default),
+            _ => default,
+        }
+    };
+    let year: i32 = text[..4].parse().ok()?;
+    let date = NaiveDate::from_ymd_opt(year, field(4, 1), field(6, 1))?
+        .and_hms_opt(field(8, 0), field(10, 0), field(12, 0))?;
+    let zone: Vec<&str> = text[digits..].split('\'').collect();
+    let offset = match zone[0].chars().next() {
+        Some(sign @ ('+' | '-')) => {
+            let hours: i32 = zone[0][1..].parse().ok()?;
+            let minutes = zone.get(1).filter(|m| !m.is_empty()).map_or(Ok(0), |m| m.parse());
+            let minutes: i32 = minutes.ok()?;
+            let seconds = (hours * 60 + minutes) * 60;
+            FixedOffset::east_opt(if sign == '-' { -seconds } else { seconds })?
+        }
+        _ => FixedOffset::east_opt(0)?,
+    };
+    date.and_local_timezone(offset).single()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pdf_dates() {
+        let date = |text| read_date(text).map(|d| d.to_rfc3339());
+        assert_eq!(date("D:20240501093000+02'00'").unwrap(), "2024-05-01T09:30:00+02:00");
+        assert_eq!(date("D:20240501093000-05'30").unwrap(), "2024-05-01T09:30:00-05:30");
+        assert_eq!(date("D:20240501093000Z").unwrap(), "2024-05-01T09:30:00+00:00");
+        assert_eq!(date("D:2024").unwrap(), "2024-01-01T00:00:00+00:00");
+        assert_eq!(date("yesterday"), None);
+        assert_eq!(date("D:20241301"), None);
+    }
+}
@@ -0,0 +1,77 @@
+use anyhow::Result;
+use chrono::{Datelike, Local, Timelike};
+use std::io::{Cursor, Write};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, DateTime, ZipWriter};
+
+/// write `entries` (name, data) as a ZIP archive, in order. entries are stored,
+/// not deflated: the images in them are compressed already. past 65535 entries
+/// or 4 GB the archive switches to ZIP64. each entry's data is freed once it's
+/// in the archive
+pub fn write_stored(
+    entries: impl IntoIterator<Item = (String, Vec<u8>)>,
+    out: &mut impl Write,
+) -> Result<()> {
+    let options = SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Stored)
+        .last_modified_time(dos_timestamp());
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, data) in entries {
+        zip.start_file(name, options.large_file(data.len() as u64 >= u32::MAX as u64))?;
+        zip.write_all(&data)?;
+    }
+    out.write_all(&zip.finish()?.into_inner())?;
+    Ok(())
+}
+
+/// the current local time, within what MS-DOS timestamps hold
+fn dos_timestamp() -> DateTime {
+    let now = Local::now();
+    DateTime::from_date_and_time(
+        now.year().clamp(1980, 2107) as u16,
+        now.month() as u8,
+        now.day() as u8,
+        now.hour() as u8,
+        now.minute() as u8,
+        now.second() as u8,
+    )
+    .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use zip::ZipArchive;
+
+    #[test]
+    fn stored_entries_in_order() {
+        let entries = vec![
+            ("a_0001.png".to_string(), b"first".to_vec()),
+            ("a_0002.png".to_string(), b"second page".to_vec()),
+        ];
+        let mut zip = Vec::new();
+        write_stored(entries, &mut zip).unwrap();
+        // the first name right after the first local header, as EPUB's mimetype needs
+        assert_eq!(&zip[30..40], b"a_0001.png");
+
+        let mut archive = ZipArchive::new(Cursor::new(zip)).unwrap();
+        assert_eq!(archive.len(), 2);
+        let mut second = archive.by_index(1).unwrap();
+        assert_eq!(second.name(), "a_0002.png");
+        assert_eq!(second.compression(), CompressionMethod::Stored);
+        let mut data = Vec::new();
+        second.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"second page");
+    }
+
+    #[test]
+    fn zip64_past_65535_entries() {
+        let entries = (0..70_000).map(|i| (format!("{}.txt", i), vec![b'x']));
+        let mut zip = Vec::new();
+        write_stored(entries, &mut zip).unwrap();
+        let archive = ZipArchive::new(Cursor::new(zip)).unwrap();
+        assert_eq!(archive.len(), 70_000);
+        assert_eq!(archive.name_for_index(69_999), Some("69999.txt"));
+    }
+}
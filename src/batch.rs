@@ -0,0 +1,352 @@
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use serde::de::{Deserializer, Error as _};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use tracing::{error, info};
+
+use crate::interrupt::{self, Interrupted};
+use crate::merge::{self, MergeOptions};
+use crate::parse::{self, Deflate, IccMode, ImageFormat, Orientation, PageSize, PngCompression};
+use crate::split::{self, SplitOptions};
+
+/// a job's result, and how long it ran in seconds
+type Outcome = (Result<()>, f64);
+
+/// a job's entry in the `--json` report
+#[derive(Serialize)]
+struct JobReport {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    input: String,
+    output: String,
+    ok: bool,
+    error: Option<String>,
+    seconds: f64,
+}
+
+/// one conversion from the job file
+enum Job {
+    Split {
+        input: PathBuf,
+        output_dir: PathBuf,
+        opts: SplitOptions,
+    },
+    Merge {
+        /// files, dirs, or glob patterns, expanded when the job runs
+        images: Vec<PathBuf>,
+        output: PathBuf,
+        opts: Box<MergeOptions>,
+    },
+}
+
+impl Job {
+    fn kind(&self) -> &'static str {
+        match self {
+            Job::Split { .. } => "split",
+            Job::Merge { .. } => "merge",
+        }
+    }
+
+    fn input(&self) -> String {
+        match self {
+            Job::Split { input, .. } => input.display().to_string(),
+            Job::Merge { images, .. } => images
+                .iter()
+                .map(|p| p.display().to_string())
+                .collect::<Vec<_>>()
+                .join(" "),
+        }
+    }
+
+    fn output(&self) -> &Path {
+        match self {
+            Job::Split { output_dir, .. } => output_dir,
+            Job::Merge { output, .. } => output,
+        }
+    }
+
+    fn run(&self) -> Result<()> {
+        match self {
+            Job::Split {
+                input,
+                output_dir,
+                opts,
+            } => split::split_pdf(input, output_dir, opts).map(drop),
+            Job::Merge {
+                images,
+                output,
+                opts,
+            } => {
+                let images = parse::expand_image_paths(images)?;
+                anyhow::ensure!(!images.is_empty(), "No input images provided");
+                merge::merge_images(&images, output, opts).map(drop)
+            }
+        }
+    }
+}
+
+/// run every `[[job]]` of a TOML job file, or `job:` item of a .yaml/.yml one,
+/// `parallel` at a time, all sharing the global thread pool, then report each
+/// job's outcome. the whole file is checked before anything runs; a failed job
+/// doesn't stop the others. fails if every job did, and returns false if only
+/// some did
+pub fn run_jobs(job_file: &Path, parallel: usize, json: bool, quiet: bool) -> Result<bool> {
+    let start = std::time::Instant::now();
+    let text = std::fs::read_to_string(job_file)
+        .with_context(|| format!("Cannot read {}", job_file.display()))?;
+    // paths in the file are relative to it
+    let base = job_file.parent().unwrap_or_else(|| Path::new("."));
+    let yaml = job_file
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("yaml") || ext.eq_ignore_ascii_case("yml"));
+    let jobs = load_jobs(&text, yaml, base)
+        .with_context(|| format!("Invalid job file {}", job_file.display()))?;
+    anyhow::ensure!(!jobs.is_empty(), "{} has no jobs", job_file.display());
+    if !quiet {
+        info!("Running {} job(s) from {}", jobs.len(), job_file.display());
+    }
+
+    // jobs run on plain threads: the pipelines block while their work runs on the
+    // pool, which would deadlock if the jobs themselves were pool tasks
+    let next = AtomicUsize::new(0);
+    let slots: Vec<Mutex<Option<Outcome>>> = jobs.iter().map(|_| Mutex::new(None)).collect();
+    std::thread::scope(|s| {
+        for _ in 0..parallel.min(jobs.len()) {
+            s.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                let Some(job) = jobs.get(i).filter(|_| !interrupt::requested()) else {
+                    return;
+                };
+                let job_start = std::time::Instant::now();
+                let result = job.run();
+                if !quiet {
+                    let status = if result.is_ok() { "done" } else { "failed" };
+                    info!("  [{}/{}] {} {}", i + 1, jobs.len(), job.kind(), status);
+                }
+                *slots[i].lock().unwrap() = Some((result, job_start.elapsed().as_secs_f64()));
+            });
+        }
+    });
+    let outcomes: Vec<Option<Outcome>> =
+        slots.into_iter().map(|slot| slot.into_inner().unwrap()).collect();
+    if interrupt::requested() {
+        // jobs left unstarted have no outcome, the ones stopped midway failed
+        let files: Vec<PathBuf> = jobs
+            .iter()
+            .zip(&outcomes)
+            .filter(|(_, outcome)| matches!(outcome, Some((Ok(()), _))))
+            .map(|(job, _)| job.output().to_path_buf())
+            .collect();
+        let (done, total) = (files.len(), jobs.len());
+        return Err(Interrupted { done, total, files }.into());
+    }
+    let results: Vec<Outcome> =
+        outcomes.into_iter().map(|outcome| outcome.expect("every job ran")).collect();
+
+    let failed = results.iter().filter(|(r, _)| r.is_err()).count();
+    if json {
+        let report: Vec<JobReport> = jobs
+            .iter()
+            .zip(&results)
+            .map(|(job, (result, seconds))| JobReport {
+                kind: job.kind(),
+                input: job.input(),
+                output: job.output().display().to_string(),
+                ok: result.is_ok(),
+                error: result.as_ref().err().map(|e| format!("{:#}", e)),
+                seconds: *seconds,
+            })
+            .collect();
+        println!("{}", serde_json::to_string(&report)?);
+    } else {
+        for (job, (result, seconds)) in jobs.iter().zip(&results) {
+            let status = if result.is_ok() { "ok" } else { "FAILED" };
+            println!(
+                "{:<6}  {} {} -> {}  ({:.2}s)",
+                status,
+                job.kind(),
+                job.input(),
+                job.output().display(),
+                seconds
+            );
+            if let Err(e) = result {
+                println!("        {:#}", e);
+            }
+        }
+    }
+    if !quiet {
+        info!(
+            "Done. {} of {} job(s) succeeded in {:.2}s",
+            jobs.len() - failed,
+            jobs.len(),
+            start.elapsed().as_secs_f64()
+        );
+    }
+    anyhow::ensure!(failed < jobs.len(), "all {} job(s) failed", jobs.len());
+    if failed > 0 {
+        error!("{} of {} job(s) failed", failed, jobs.len());
+    }
+    Ok(failed == 0)
+}
+
+/// a job file: one `[[job]]` table, or `job:` list item in YAML, per conversion.
+/// the jobs are read one by one, so an error can say which job it's in
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JobFile<T> {
+    #[serde(default = "Vec::new")]
+    job: Vec<T>,
+}
+
+/// a job as written in the file, its options named as on the command line
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum JobSpec {
+    Split(SplitJob),
+    Merge(MergeJob),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SplitJob {
+    input: PathBuf,
+    /// next to the input by default
+    output: Option<PathBuf>,
+    #[serde(default, deserialize_with = "choice")]
+    format: Option<ImageFormat>,
+    dpi: Option<u32>,
+    #[serde(default, deserialize_with = "choice")]
+    compress: Option<PngCompression>,
+    #[serde(default)]
+    gray: bool,
+    pages: Option<String>,
+    quality: Option<u8>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MergeJob {
+    images: Images,
+    output: PathBuf,
+    dpi: Option<u32>,
+    title: Option<String>,
+    author: Option<String>,
+    #[serde(default, deserialize_with = "choice")]
+    pagesize: Option<PageSize>,
+    #[serde(default, deserialize_with = "choice")]
+    orientation: Option<Orientation>,
+    #[serde(default)]
+    gray: bool,
+    #[serde(default, deserialize_with = "choice")]
+    icc: Option<IccMode>,
+    #[serde(default)]
+    strip_exif: bool,
+    #[serde(default)]
+    strip_metadata: bool,
+    #[serde(default, deserialize_with = "choice")]
+    deflate: Option<Deflate>,
+    #[serde(default)]
+    deskew: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged, expecting = "images must be a path or a list of paths")]
+enum Images {
+    One(PathBuf),
+    Many(Vec<PathBuf>),
+}
+
+/// a string naming one of the CLI's choices for the same option
+fn choice<'de, D: Deserializer<'de>, T: ValueEnum>(d: D) -> Result<Option<T>, D::Error> {
+    let name = String::deserialize(d)?;
+    T::from_str(&name, true).map(Some).map_err(|_| {
+        let names: Vec<String> = T::value_variants()
+            .iter()
+            .filter_map(|v| v.to_possible_value())
+            .map(|v| v.get_name().to_string())
+            .collect();
+        D::Error::custom(format!("unknown value '{}', expected one of {}", name, names.join(", ")))
+    })
+}
+
+/// `value` if it's within `min..=max`
+fn in_range<T: PartialOrd + std::fmt::Display>(key: &str, value: T, min: T, max: T) -> Result<T> {
+    if value < min || value > max {
+        bail!("{} must be {}-{}", key, min, max);
+    }
+    Ok(value)
+}
+
+/// the jobs of a TOML job file, or a YAML one if `yaml`, with paths relative to
+/// `base`
+fn load_jobs(text: &str, yaml: bool, base: &Path) -> Result<Vec<Job>> {
+    let specs: Vec<Result<JobSpec>> = if yaml {
+        let file: JobFile<serde_yaml::Value> = serde_yaml::from_str(text)?;
+        let specs = file.job.into_iter().map(serde_yaml::from_value);
+        specs.map(|spec| spec.map_err(Into::into)).collect()
+    } else {
+        let file: JobFile<toml::Value> = toml::from_str(text)?;
+        let specs = file.job.into_iter().map(toml::Value::try_into);
+        specs.map(|spec| spec.map_err(Into::into)).collect()
+    };
+    specs
+        .into_iter()
+        .enumerate()
+        .map(|(i, spec)| {
+            spec.and_then(|spec| load_job(spec, base))
+                .with_context(|| format!("job {}", i + 1))
+        })
+        .collect()
+}
+
+fn load_job(spec: JobSpec, base: &Path) -> Result<Job> {
+    Ok(match spec {
+        JobSpec::Split(job) => {
+            let input = base.join(job.input);
+            let output_dir = match job.output {
+                Some(dir) => base.join(dir),
+                None => input.parent().unwrap_or(base).to_path_buf(),
+            };
+            Job::Split {
+                input,
+                output_dir,
+                opts: SplitOptions {
+                    format: job.format.unwrap_or(ImageFormat::Png),
+                    dpi: in_range("dpi", job.dpi.unwrap_or(300), 72, 2400)?,
+                    compress: job.compress.unwrap_or_default(),
+                    gray: job.gray,
+                    pages: job.pages,
+                    quality: in_range("quality", job.quality.unwrap_or(75), 1, 100)?,
+                    quiet: true,
+                    ..Default::default()
+                },
+            }
+        }
+        JobSpec::Merge(job) => Job::Merge {
+            images: match job.images {
+                Images::One(path) => vec![base.join(path)],
+                Images::Many(paths) => paths.iter().map(|path| base.join(path)).collect(),
+            },
+            output: base.join(job.output),
+            opts: Box::new(MergeOptions {
+                quiet: true,
+                dpi: job.dpi.map(|dpi| in_range("dpi", dpi, 72, 2400)).transpose()?,
+                title: job.title,
+                author: job.author,
+                pagesize: job.pagesize,
+                orientation: job.orientation.unwrap_or_default(),
+                gray: job.gray,
+                icc: job.icc.unwrap_or_default(),
+                strip_exif: job.strip_exif,
+                strip_metadata: job.strip_metadata,
+                deflate: job.deflate.unwrap_or_default(),
+                deskew: job.deskew,
+                max_pixels: Some(200_000_000),
+                ..Default::default()
+            }),
+        },
+    })
+}
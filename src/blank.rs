@@ -0,0 +1,132 @@
+use anyhow::{ensure, Result};
+use lopdf::content::Operation;
+use lopdf::Object;
+use std::path::Path;
+use tracing::info;
+
+use crate::merge::{self, MergeOptions};
+use crate::parse::{Color, PaperTemplate};
+
+/// default distance between lines or dots: 5mm squares, 8mm rules
+const GRID_SPACING: f32 = 5.0 * 72.0 / 25.4;
+const LINE_SPACING: f32 = 8.0 * 72.0 / 25.4;
+
+pub struct BlankOptions {
+    pub template: PaperTemplate,
+    /// distance between lines or dots, in points; None for the template's default
+    pub spacing: Option<f32>,
+    /// space left empty around the pattern, in points
+    pub margin: f32,
+    pub color: Color,
+}
+
+/// write a PDF of `pages` empty pages, or of grid, dot or lined paper; page
+/// size, numbering and metadata come from `opts` as for merged images
+pub fn blank_pdf(
+    output: &Path,
+    pages: usize,
+    blank: &BlankOptions,
+    opts: &MergeOptions,
+) -> Result<()> {
+    let spacing = blank.spacing.unwrap_or(match blank.template {
+        PaperTemplate::Lined => LINE_SPACING,
+        _ => GRID_SPACING,
+    });
+    ensure!(spacing >= 1.0, "Spacing must be at least 1pt");
+    merge::write_drawn_pages(output, pages, |w, h| pattern(blank, spacing, w, h), opts)?;
+    if !opts.quiet {
+        info!("{} page(s) -> {}", pages, output.display());
+    }
+    Ok(())
+}
+
+/// drawing operations for the template on a `width` x `height` page; the
+/// pattern is centered in the area inside the margin
+fn pattern(blank: &BlankOptions, spacing: f32, width: f32, height: f32) -> Vec<Operation> {
+    let (area_w, area_h) = (width - 2.0 * blank.margin, height - 2.0 * blank.margin);
+    if blank.template == PaperTemplate::Blank || area_w < spacing || area_h < spacing {
+        return Vec::new();
+    }
+    // whole cells only
+    let (cols, rows) = ((area_w / spacing) as usize, (area_h / spacing) as usize);
+    let left = (width - cols as f32 * spacing) / 2.0;
+    let bottom = (height - rows as f32 * spacing) / 2.0;
+    let (right, top) = (width - left, height - bottom);
+    let xs = (0..=cols).map(|i| left + i as f32 * spacing);
+    let ys = (0..=rows).map(|i| bottom + i as f32 * spacing);
+
+    let real = |v: f32| Object::Real((v * 1000.0).round() / 1000.0);
+    let Color { r, g, b } = blank.color;
+    let mut ops = vec![
+        Operation::new("q", vec![]),
+        Operation::new("RG", [r, g, b].map(|c| real(c as f32 / 255.0)).to_vec()),
+    ];
+    let mut line = |x0: f32, y0: f32, x1: f32, y1: f32| {
+        ops.push(Operation::new("m", vec![real(x0), real(y0)]));
+        ops.push(Operation::new("l", vec![real(x1), real(y1)]));
+    };
+    let line_width = match blank.template {
+        PaperTemplate::Grid => {
+            for x in xs {
+                line(x, bottom, x, top);
+            }
+            for y in ys {
+                line(left, y, right, y);
+            }
+            0.3
+        }
+        PaperTemplate::Lined => {
+            // rules only between the top and bottom of the area, no border lines
+            for y in ys.skip(1).take(rows.saturating_sub(1)) {
+                line(left, y, right, y);
+            }
+            0.4
+        }
+        // zero-length lines with round caps are dots
+        PaperTemplate::Dot => {
+            for x in xs {
+                for y in ys.clone() {
+                    line(x, y, x, y);
+                }
+            }
+            (spacing / 10.0).clamp(0.8, 2.0)
+        }
+        PaperTemplate::Blank => unreachable!(),
+    };
+    ops.insert(1, Operation::new("w", vec![real(line_width)]));
+    if blank.template == PaperTemplate::Dot {
+        ops.insert(1, Operation::new("J", vec![1.into()]));
+    }
+    ops.push(Operation::new("S", vec![]));
+    ops.push(Operation::new("Q", vec![]));
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count(ops: &[Operation], operator: &str) -> usize {
+        ops.iter().filter(|op| op.operator == operator).count()
+    }
+
+    #[test]
+    fn patterns_fill_whole_cells() {
+        let mut blank = BlankOptions {
+            template: PaperTemplate::Grid,
+            spacing: None,
+            margin: 10.0,
+            color: Color { r: 200, g: 200, b: 200 },
+        };
+        // 100 x 60 inside the margin: 10 x 6 cells of 10pt
+        let grid = pattern(&blank, 10.0, 120.0, 80.0);
+        assert_eq!(count(&grid, "m"), 11 + 7);
+        assert_eq!(count(&grid, "S"), 1);
+        blank.template = PaperTemplate::Dot;
+        assert_eq!(count(&pattern(&blank, 10.0, 120.0, 80.0), "m"), 11 * 7);
+        blank.template = PaperTemplate::Lined;
+        assert_eq!(count(&pattern(&blank, 10.0, 120.0, 80.0), "m"), 5);
+        blank.template = PaperTemplate::Blank;
+        assert!(pattern(&blank, 10.0, 120.0, 80.0).is_empty());
+    }
+}
@@ -0,0 +1,301 @@
+use anyhow::{Context, Result};
+use mupdf::pdf::{PdfDocument, PdfObject};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::crypto;
+
+/// page keys that don't change how a page renders. unlike `ovid dedupe`,
+/// annotations count, as split draws them
+const IGNORED_KEYS: [&[u8]; 4] = [b"Parent", b"B", b"StructParents", b"Thumb"];
+
+/// page keys a page may take from the page tree above it
+const INHERITED_KEYS: [&str; 4] = ["Resources", "MediaBox", "CropBox", "Rotate"];
+
+/// catalog entries every page renders by: which layers are shown by default,
+/// whether fields are drawn from their values and with which resources, and
+/// the output intents colours are rendered for
+const CATALOG_INPUTS: [(&str, Option<&str>); 4] = [
+    ("OCProperties", Some("D")),
+    ("AcroForm", Some("NeedAppearances")),
+    ("AcroForm", Some("DR")),
+    ("OutputIntents", None),
+];
+
+/// what earlier splits rendered, to skip the pages that haven't changed since.
+/// the cache dir has an entry per page rendered, named for the digest of what
+/// the page draws, its number and the render settings, and holding the SHA-256
+/// of the image written for it. a page is skipped while its image is still
+/// there, unchanged
+pub struct RenderCache {
+    dir: PathBuf,
+    /// each selected page's digest, by 0-based index
+    pages: HashMap<i32, [u8; 16]>,
+    /// everything else the images depend on
+    settings: String,
+}
+
+impl RenderCache {
+    /// digest the pages at `page_indices` of `doc`, as the split rendering them
+    /// opened it, for a split with `settings` cached in `dir`
+    pub fn open(
+        dir: &Path,
+        doc: &PdfDocument,
+        page_indices: &[i32],
+        settings: String,
+    ) -> Result<Self> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Cannot create cache dir: {}", dir.display()))?;
+        let mut digest = Digest::default();
+        let pages = page_indices
+            .iter()
+            .map(|&i| Ok((i, digest.page(&doc.find_page(i)?)?)))
+            .collect::<Result<HashMap<_, _>, mupdf::Error>>()?;
+        let catalog = doc.catalog()?;
+        let mut inputs = Vec::new();
+        for (key, inner) in CATALOG_INPUTS {
+            let mut value = catalog.get_dict(key)?;
+            if let (Some(dict), Some(inner)) = (&value, inner) {
+                value = dict.get_dict(inner)?;
+            }
+            match value {
+                Some(value) => digest.encode(&value, &mut inputs)?,
+                None => inputs.push(b'-'),
+            }
+        }
+        Ok(RenderCache {
+            dir: dir.to_path_buf(),
+            pages,
+            settings: format!("{} catalog {}", settings, hex(&crypto::md5(&[&inputs]))),
+        })
+    }
+
+    fn entry(&self, index: i32) -> Option<PathBuf> {
+        let digest = self.pages.get(&index)?;
+        let key = [&digest[..], &index.to_le_bytes(), self.settings.as_bytes()].concat();
+        Some(self.dir.join(hex(&crypto::sha256(&key))))
+    }
+
+    /// whether `image` is what was written for page `index` as it is now
+    pub fn is_fresh(&self, index: i32, image: &Path) -> bool {
+        let Some(entry) = self.entry(index) else {
+            return false;
+        };
+        match (std::fs::read_to_string(entry), std::fs::read(image)) {
+            (Ok(recorded), Ok(data)) => recorded.trim() == hex(&crypto::sha256(&data)),
+            _ => false,
+        }
+    }
+
+    /// note that `data` was written as the image for page `index`
+    pub fn record(&self, index: i32, data: &[u8]) -> Result<()> {
+        let Some(entry) = self.entry(index) else {
+            return Ok(());
+        };
+        std::fs::write(&entry, hex(&crypto::sha256(data)))
+            .with_context(|| format!("Failed to write {}", entry.display()))
+    }
+}
+
+/// digests of what pages draw, as `dedupe::page_digest` makes them but from the
+/// objects MuPDF parsed: each object hashed by value, once
+#[derive(Default)]
+struct Digest {
+    /// object digests, by object number
+    memo: HashMap<i32, [u8; 16]>,
+    /// objects being hashed, to stop at a reference back into one
+    visiting: HashSet<i32>,
+}
+
+impl Digest {
+    /// a page's dictionary, less the ignored keys and with what it inherits
+    fn page(&mut self, page: &PdfObject) -> Result<[u8; 16], mupdf::Error> {
+        let mut entries = Vec::new();
+        for i in 0..page.dict_len()? as i32 {
+            let (Some(key), Some(value)) = (page.get_dict_key(i)?, page.get_dict_val(i)?) else {
+                continue;
+            };
+            let key = key.as_name()?.to_vec();
+            if !IGNORED_KEYS.contains(&key.as_slice()) {
+                entries.push((key, value));
+            }
+        }
+        for key in INHERITED_KEYS {
+            if entries.iter().all(|(name, _)| name != key.as_bytes()) {
+                if let Some(value) = page.get_dict_inheritable(key)? {
+                    entries.push((key.as_bytes().to_vec(), value));
+                }
+            }
+        }
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut bytes = Vec::new();
+        for (key, value) in entries {
+            encode_name(&mut bytes, &key);
+            self.encode(&value, &mut bytes)?;
+        }
+        Ok(crypto::md5(&[&bytes]))
+    }
+
+    /// the same serialization as dedupe's: a tag per type, lengths before
+    /// variable data, dictionary keys sorted, references replaced by their
+    /// target's digest
+    fn encode(&mut self, obj: &PdfObject, out: &mut Vec<u8>) -> Result<(), mupdf::Error> {
+        if obj.is_indirect()? {
+            let num = obj.as_indirect()?;
+            let digest = match self.memo.get(&num) {
+                Some(digest) => *digest,
+                // a reference back into an object still being hashed
+                None if !self.visiting.insert(num) => [0; 16],
+                None => {
+                    let mut bytes = Vec::new();
+                    match obj.resolve()? {
+                        Some(target) => self.encode(&target, &mut bytes)?,
+                        None => bytes.push(b'?'),
+                    }
+                    // streams are numbered objects, so their data is read here
+                    if obj.is_stream()? {
+                        let content = obj.read_raw_stream()?;
+                        bytes.push(b'S');
+                        bytes.extend_from_slice(&(content.len() as u64).to_le_bytes());
+                        bytes.extend_from_slice(&content);
+                    }
+                    self.visiting.remove(&num);
+                    let digest = crypto::md5(&[&bytes]);
+                    self.memo.insert(num, digest);
+                    digest
+                }
+            };
+            out.push(b'R');
+            out.extend_from_slice(&digest);
+        } else if obj.is_null()? {
+            out.push(b'n');
+        } else if obj.is_bool()? {
+            out.extend_from_slice(&[b'b', obj.as_bool()? as u8]);
+        } else if obj.is_int()? {
+            out.push(b'i');
+            out.extend_from_slice(&(obj.as_int()? as i64).to_le_bytes());
+        } else if obj.is_real()? {
+            out.push(b'r');
+            out.extend_from_slice(&obj.as_float()?.to_le_bytes());
+        } else if obj.is_name()? {
+            encode_name(out, obj.as_name()?);
+        } else if obj.is_string()? {
+            let bytes = obj.as_bytes()?;
+            out.push(b's');
+            out.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+            out.extend_from_slice(bytes);
+        } else if obj.is_array()? {
+            let len = obj.len()?;
+            out.push(b'[');
+            out.extend_from_slice(&(len as u64).to_le_bytes());
+            for i in 0..len as i32 {
+                match obj.get_array(i)? {
+                    Some(item) => self.encode(&item, out)?,
+                    None => out.push(b'n'),
+                }
+            }
+        } else if obj.is_dict()? {
+            let mut entries = Vec::new();
+            for i in 0..obj.dict_len()? as i32 {
+                if let (Some(key), Some(value)) = (obj.get_dict_key(i)?, obj.get_dict_val(i)?) {
+                    entries.push((key.as_name()?.to_vec(), value));
+                }
+            }
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            out.push(b'<');
+            out.extend_from_slice(&(entries.len() as u64).to_le_bytes());
+            for (key, value) in entries {
+                encode_name(out, &key);
+                self.encode(&value, out)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn encode_name(out: &mut Vec<u8>, name: &[u8]) {
+    out.push(b'/');
+    out.extend_from_slice(&(name.len() as u64).to_le_bytes());
+    out.extend_from_slice(name);
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::{dictionary, Document, Object, Stream};
+
+    /// a PDF with a page per content stream
+    fn write_pdf(path: &Path, contents: &[&[u8]]) {
+        let mut doc = Document::with_version("1.7");
+        let pages_id = doc.new_object_id();
+        let kids: Vec<Object> = contents
+            .iter()
+            .map(|content| {
+                let content_id = doc.add_object(Stream::new(dictionary! {}, content.to_vec()));
+                let page = dictionary! {
+                    "Type" => "Page",
+                    "Parent" => pages_id,
+                    "MediaBox" => vec![0.into(), 0.into(), 100.into(), 100.into()],
+                    "Contents" => content_id,
+                };
+                doc.add_object(page).into()
+            })
+            .collect();
+        let count = kids.len() as i64;
+        doc.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! { "Type" => "Pages", "Kids" => kids, "Count" => count }),
+        );
+        let catalog_id = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        doc.trailer.set("Root", catalog_id);
+        doc.save(path).unwrap();
+    }
+
+    #[test]
+    fn changed_pages_are_rendered_again() {
+        let dir = std::env::temp_dir().join("ovid_test_render_cache");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let (input, cache_dir) = (dir.join("doc.pdf"), dir.join("cache"));
+        let images = [dir.join("doc_1.png"), dir.join("doc_2.png")];
+        write_pdf(&input, &[b"0 0 10 10 re f", b"0 0 20 20 re f"]);
+
+        let open_pages = |pages: &[i32], settings: &str| {
+            let doc = PdfDocument::open(input.to_str().unwrap()).unwrap();
+            RenderCache::open(&cache_dir, &doc, pages, settings.into()).unwrap()
+        };
+        let open = |settings: &str| open_pages(&[0, 1], settings);
+        let cache = open("png 300");
+        for (i, image) in images.iter().enumerate() {
+            assert!(!cache.is_fresh(i as i32, image));
+            std::fs::write(image, [i as u8; 8]).unwrap();
+            cache.record(i as i32, &[i as u8; 8]).unwrap();
+        }
+
+        // the second page changes
+        write_pdf(&input, &[b"0 0 10 10 re f", b"0 0 30 30 re f"]);
+        let cache = open("png 300");
+        assert!(cache.is_fresh(0, &images[0]));
+        assert!(!cache.is_fresh(1, &images[1]));
+        // only the pages selected are digested
+        let second = open_pages(&[1], "png 300");
+        assert!(!second.is_fresh(0, &images[0]));
+        // the settings changed
+        assert!(!open("png 150").is_fresh(0, &images[0]));
+
+        // so did the catalog's, for every page
+        let mut doc = Document::load(&input).unwrap();
+        let form = dictionary! { "Fields" => vec![], "NeedAppearances" => true };
+        doc.catalog_mut().unwrap().set("AcroForm", form);
+        doc.save(&input).unwrap();
+        assert!(!open("png 300").is_fresh(0, &images[0]));
+
+        // or the image was edited
+        std::fs::write(&images[0], b"edited").unwrap();
+        assert!(!cache.is_fresh(0, &images[0]));
+    }
+}
@@ -0,0 +1,330 @@
+use anyhow::{Context, Result};
+use lopdf::{Document, Object, ObjectId};
+use rayon::prelude::*;
+use std::collections::HashSet;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+use crate::merge;
+use crate::outline::{self, Bookmark};
+use crate::parse::ByteSize;
+use crate::pdf;
+
+/// rough size of what every part carries besides its pages: header, catalog,
+/// page tree, xref table, trailer
+const PART_OVERHEAD: u64 = 1024;
+
+/// parts rendered at most while looking for the most pages under a size limit
+const MAX_TRIES: usize = 6;
+
+#[derive(Debug, Clone, Copy)]
+pub enum ChunkLimit {
+    /// pages per part
+    Pages(usize),
+    /// bytes per part; a single page over the limit still gets a part of its own
+    Size(u64),
+}
+
+/// cut a PDF into numbered parts (out.pdf -> out_001.pdf, out_002.pdf, ...) without
+/// re-rendering: each part is the document with all other pages dropped, so text,
+/// fonts, and links within the part come through unchanged
+pub fn chunk_pdf(input: &Path, output: &Path, limit: ChunkLimit, quiet: bool) -> Result<()> {
+    anyhow::ensure!(output != Path::new("-"), "chunk needs a file output, not stdout");
+    let start = std::time::Instant::now();
+    let mut doc = pdf::load(input)?;
+    pdf::inherit_page_attributes(&mut doc);
+    let page_ids: Vec<ObjectId> = doc.get_pages().into_values().collect();
+    anyhow::ensure!(!page_ids.is_empty(), "{} has no pages", input.display());
+    if let Some(dir) = output.parent().filter(|d| !d.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let write_part = |n: usize, bytes: &[u8]| {
+        let path = merge::part_path(output, n + 1);
+        pdf::write_output(&path, |out| Ok(out.write_all(bytes)?))
+    };
+    let parts: Vec<(Range<usize>, u64)> = match limit {
+        ChunkLimit::Pages(every) => {
+            let ranges: Vec<Range<usize>> = (0..page_ids.len())
+                .step_by(every)
+                .map(|first| first..(first + every).min(page_ids.len()))
+                .collect();
+            ranges
+                .into_par_iter()
+                .enumerate()
+                .map(|(n, range)| {
+                    let bytes = render(&doc, &page_ids[range.clone()])?;
+                    write_part(n, &bytes)?;
+                    Ok((range, bytes.len() as u64))
+                })
+                .collect::<Result<_>>()?
+        }
+        ChunkLimit::Size(max) => {
+            let mut parts = Vec::new();
+            let mut first = 0;
+            while first < page_ids.len() {
+                // the estimate is rough: measure real parts, narrowing down the
+                // most pages that fit between the largest part known to fit and
+                // the smallest known not to
+                let mut fitting: Option<(usize, Vec<u8>)> = None;
+                let mut over = page_ids.len() + 1;
+                let mut end = first + estimate_fit(&doc, &page_ids, first, max);
+                for _ in 0..MAX_TRIES {
+                    let bytes = render(&doc, &page_ids[first..end])?;
+                    let size = bytes.len() as u64;
+                    let pages = end - first;
+                    let guess = first + (pages as f64 * max as f64 / size as f64 * 0.97) as usize;
+                    if size <= max || pages == 1 {
+                        fitting = Some((end, bytes));
+                    }
+                    if size > max {
+                        over = end;
+                    }
+                    let most = fitting.as_ref().map_or(first, |(end, _)| *end);
+                    if over - most <= 1 {
+                        break;
+                    }
+                    end = guess.clamp(most + 1, over - 1);
+                }
+                let (end, bytes) = match fitting {
+                    Some(part) => part,
+                    None => (first + 1, render(&doc, &page_ids[first..first + 1])?),
+                };
+                if bytes.len() as u64 > max {
+                    warn!(
+                        "page {} alone is {}, over the {} limit",
+                        first + 1,
+                        ByteSize(bytes.len() as u64),
+                        ByteSize(max)
+                    );
+                }
+                write_part(parts.len(), &bytes)?;
+                parts.push((first..end, bytes.len() as u64));
+                first = end;
+            }
+            parts
+        }
+    };
+
+    if !quiet {
+        for (n, (range, size)) in parts.iter().enumerate() {
+            info!(
+                "  {}: pages {}-{} ({})",
+                merge::part_path(output, n + 1).display(),
+                range.start + 1,
+                range.end,
+                ByteSize(*size)
+            );
+        }
+        info!(
+            "Done. {} page(s) in {} part(s) saved in {:.2}s",
+            page_ids.len(),
+            parts.len(),
+            start.elapsed().as_secs_f64()
+        );
+    }
+    Ok(())
+}
+
+/// split a PDF into one file per bookmark at `level` or above, named after the
+/// bookmarks (01_Introduction.pdf, ...) in `output_dir`. pages before the first
+/// such bookmark get a part of their own; each part keeps the bookmarks inside it
+pub fn split_at_bookmarks(
+    input: &Path,
+    output_dir: &Path,
+    level: usize,
+    quiet: bool,
+) -> Result<()> {
+    let start = std::time::Instant::now();
+    let mut doc = pdf::load(input)?;
+    pdf::inherit_page_attributes(&mut doc);
+    let page_ids: Vec<ObjectId> = doc.get_pages().into_values().collect();
+    let bookmarks = outline::read_outline(&doc);
+    let mut cuts: Vec<(usize, &str)> = bookmarks
+        .iter()
+        .filter(|b| b.level <= level)
+        .filter_map(|b| Some((b.page? - 1, b.title.as_str())))
+        .collect();
+    anyhow::ensure!(
+        !cuts.is_empty(),
+        "{} has no bookmarks to split at (level {} or above)",
+        input.display(),
+        level
+    );
+    // parts follow page order even if the outline doesn't
+    cuts.sort_by_key(|&(page, _)| page);
+    if cuts[0].0 > 0 {
+        cuts.insert(0, (0, "front"));
+    }
+    let mut parts: Vec<(Range<usize>, &str)> = Vec::new();
+    for (i, &(first, title)) in cuts.iter().enumerate() {
+        let end = cuts.get(i + 1).map_or(page_ids.len(), |&(next, _)| next);
+        if end <= first {
+            warn!("bookmark \"{}\" has no pages of its own, skipped", title);
+            continue;
+        }
+        parts.push((first..end, title));
+    }
+    std::fs::create_dir_all(output_dir)
+        .with_context(|| format!("Cannot create output dir: {}", output_dir.display()))?;
+    if !quiet {
+        info!(
+            "Splitting {} at {} bookmark(s) -> {}",
+            input.display(),
+            parts.len(),
+            output_dir.display()
+        );
+    }
+
+    let width = parts.len().to_string().len().max(2);
+    let paths: Vec<PathBuf> = parts
+        .iter()
+        .enumerate()
+        .map(|(n, (_, title))| {
+            output_dir.join(format!("{:0width$}_{}.pdf", n + 1, file_title(title)))
+        })
+        .collect();
+    let sizes: Vec<u64> = parts
+        .par_iter()
+        .zip(&paths)
+        .map(|((range, _), path)| {
+            // the part's own bookmarks, moved up so the shallowest is top-level
+            let mut inside: Vec<Bookmark> = bookmarks
+                .iter()
+                .filter(|b| b.page.is_some_and(|p| range.contains(&(p - 1))))
+                .map(|b| Bookmark {
+                    page: b.page.map(|p| p - range.start),
+                    ..b.clone()
+                })
+                .collect();
+            let base = inside.iter().map(|b| b.level).min().unwrap_or(1) - 1;
+            let mut previous = 0;
+            for b in &mut inside {
+                b.level = (b.level - base).min(previous + 1);
+                previous = b.level;
+            }
+            let bytes = render_with_outline(&doc, &page_ids[range.clone()], &inside)?;
+            pdf::write_output(path, |out| Ok(out.write_all(&bytes)?))?;
+            Ok(bytes.len() as u64)
+        })
+        .collect::<Result<_>>()?;
+
+    if !quiet {
+        for (((range, _), path), size) in parts.iter().zip(&paths).zip(&sizes) {
+            info!(
+                "  {}: pages {}-{} ({})",
+                path.display(),
+                range.start + 1,
+                range.end,
+                ByteSize(*size)
+            );
+        }
+        info!(
+            "Done. {} part(s) saved in {:.2}s",
+            parts.len(),
+            start.elapsed().as_secs_f64()
+        );
+    }
+    Ok(())
+}
+
+/// a bookmark title made safe as a file name: letters, digits, '-' and '_', with
+/// anything else run together as '_'
+fn file_title(title: &str) -> String {
+    let mut name = String::new();
+    for c in title.chars() {
+        if c.is_alphanumeric() || c == '-' {
+            name.push(c);
+        } else if !name.ends_with('_') {
+            name.push('_');
+        }
+    }
+    let name: String = name.trim_matches('_').chars().take(60).collect();
+    let name = name.trim_end_matches('_');
+    if name.is_empty() {
+        "part".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+/// the document cut down to `pages`, serialized
+fn render(doc: &Document, pages: &[ObjectId]) -> Result<Vec<u8>> {
+    let mut part = doc.clone();
+    pdf::set_page_order(&mut part, pages)?;
+    let mut bytes = Vec::new();
+    part.save_to(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// like `render`, with the outline replaced by `bookmarks` (pages counted within
+/// the part)
+fn render_with_outline(
+    doc: &Document,
+    pages: &[ObjectId],
+    bookmarks: &[Bookmark],
+) -> Result<Vec<u8>> {
+    let mut part = doc.clone();
+    pdf::set_page_order(&mut part, pages)?;
+    outline::set_outline(&mut part, bookmarks, pages)?;
+    let mut bytes = Vec::new();
+    part.save_to(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// how many pages from `first` on should fit in `max` bytes, judging by the
+/// objects each page uses; objects shared between pages (fonts, images) count
+/// once. always at least one page
+fn estimate_fit(doc: &Document, page_ids: &[ObjectId], first: usize, max: u64) -> usize {
+    let all_pages: HashSet<ObjectId> = page_ids.iter().copied().collect();
+    let mut seen = HashSet::new();
+    let mut size = PART_OVERHEAD;
+    for (n, &page_id) in page_ids[first..].iter().enumerate() {
+        let mut added = 0;
+        let mut stack = vec![page_id];
+        while let Some(id) = stack.pop() {
+            if !seen.insert(id) {
+                continue;
+            }
+            let Ok(obj) = doc.get_object(id) else {
+                continue;
+            };
+            added += object_size(obj);
+            // other pages, reached through links or /Parent, aren't part of this one
+            collect_references(obj, &mut |target| {
+                if !all_pages.contains(&target) {
+                    stack.push(target);
+                }
+            });
+        }
+        if n > 0 && size + added > max {
+            return n;
+        }
+        size += added;
+    }
+    page_ids.len() - first
+}
+
+/// approximate serialized size of an object
+fn object_size(obj: &Object) -> u64 {
+    match obj {
+        Object::Stream(stream) => stream.content.len() as u64 + 100,
+        Object::Dictionary(dict) => 20 + 30 * dict.len() as u64,
+        Object::Array(items) => 20 + 12 * items.len() as u64,
+        Object::String(s, _) => 20 + s.len() as u64,
+        _ => 20,
+    }
+}
+
+fn collect_references(obj: &Object, found: &mut impl FnMut(ObjectId)) {
+    match obj {
+        Object::Reference(id) => found(*id),
+        Object::Array(items) => items.iter().for_each(|item| collect_references(item, found)),
+        Object::Dictionary(dict) => dict.iter().for_each(|(_, v)| collect_references(v, found)),
+        Object::Stream(stream) => {
+            stream.dict.iter().for_each(|(_, v)| collect_references(v, found))
+        }
+        _ => {}
+    }
+}
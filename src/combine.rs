@@ -0,0 +1,120 @@
+use anyhow::Result;
+use lopdf::{dictionary, Document, Object, ObjectId};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::pdf;
+
+/// concatenate PDFs without re-rendering: every input's objects are renumbered into
+/// one document and its pages hung under a new page tree, so text, fonts, and links
+/// come through unchanged. the first input's document info is kept, and form fields
+/// from all inputs are joined into one form
+pub fn combine_pdfs(inputs: &[PathBuf], output: &Path, quiet: bool) -> Result<()> {
+    if !quiet {
+        info!("Combining {} PDF(s) -> {}", inputs.len(), output.display());
+    }
+    let start = std::time::Instant::now();
+
+    let mut doc = combine(inputs)?;
+    pdf::save(&mut doc, output)?;
+
+    if !quiet {
+        info!(
+            "Done. {} pages saved in {:.2}s",
+            doc.get_pages().len(),
+            start.elapsed().as_secs_f64()
+        );
+    }
+    Ok(())
+}
+
+fn combine(inputs: &[PathBuf]) -> Result<Document> {
+    let mut combined = Document::with_version("1.4");
+    let mut kids: Vec<ObjectId> = Vec::new();
+    let mut info = None;
+    let mut acroform: Option<lopdf::Dictionary> = None;
+    let mut fields: Vec<Object> = Vec::new();
+
+    for path in inputs {
+        let mut doc = pdf::load(path)?;
+        // pages leave their old tree behind, so they need their own copies of
+        // anything they inherited from it
+        pdf::inherit_page_attributes(&mut doc);
+        doc.renumber_objects_with(combined.max_id + 1);
+        combined.max_id = combined.max_id.max(doc.max_id);
+        if version_number(&doc.version) > version_number(&combined.version) {
+            combined.version = doc.version.clone();
+        }
+
+        kids.extend(doc.get_pages().into_values());
+        if info.is_none() {
+            info = doc.trailer.get(b"Info").ok().cloned();
+        }
+
+        let catalog_id = doc.trailer.get(b"Root").and_then(Object::as_reference)?;
+        if let Some(form) = doc
+            .get_dictionary(catalog_id)?
+            .get(b"AcroForm")
+            .ok()
+            .and_then(|f| doc.dereference(f).ok())
+            .and_then(|(_, f)| f.as_dict().ok())
+        {
+            if let Ok(Object::Array(form_fields)) =
+                form.get(b"Fields").and_then(|f| doc.dereference(f)).map(|(_, f)| f)
+            {
+                fields.extend(form_fields.iter().cloned());
+            }
+            acroform.get_or_insert_with(|| form.clone());
+        }
+
+        // the old catalog and page tree nodes are replaced by the combined ones
+        doc.objects.remove(&catalog_id);
+        doc.objects.retain(|_, obj| {
+            obj.as_dict()
+                .and_then(|d| d.get(b"Type"))
+                .and_then(Object::as_name)
+                .map_or(true, |t| t != b"Pages")
+        });
+        combined.objects.extend(doc.objects);
+    }
+
+    let pages_id = combined.new_object_id();
+    for &kid in &kids {
+        if let Ok(page) = combined.get_dictionary_mut(kid) {
+            page.set("Parent", pages_id);
+        }
+    }
+    let count = kids.len() as i64;
+    combined.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => kids.into_iter().map(Object::Reference).collect::<Vec<_>>(),
+            "Count" => count,
+        }),
+    );
+
+    let mut catalog = dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    };
+    if let Some(mut form) = acroform {
+        form.set("Fields", fields);
+        catalog.set("AcroForm", form);
+    }
+    let catalog_id = combined.add_object(catalog);
+    combined.trailer.set("Root", catalog_id);
+    if let Some(info) = info {
+        combined.trailer.set("Info", info);
+    }
+
+    // drop outlines and other catalog-only objects nothing points to any more
+    combined.prune_objects();
+    combined.renumber_objects();
+    Ok(combined)
+}
+
+/// "1.7" -> 1.7, for picking the highest input version
+fn version_number(version: &str) -> f32 {
+    version.parse().unwrap_or(0.0)
+}
@@ -0,0 +1,411 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::parse::{parse_page_ranges, PngCompression};
+use crate::split::{self, encode_png};
+
+pub struct CompareOptions<'a> {
+    pub dpi: u32,
+    /// per-channel difference (0-255) below which pixels count as equal
+    pub fuzz: u8,
+    /// fraction of differing pixels (0-1) a page may have and still match
+    pub threshold: f64,
+    /// page selection like "1,3-5"
+    pub pages: Option<&'a str>,
+    /// write <name>_diff_0001.png for pages that differ
+    pub diff_dir: Option<&'a Path>,
+    pub json: bool,
+    pub quiet: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Status {
+    Identical,
+    /// differing pixels within the threshold
+    Similar,
+    Different,
+    SizeMismatch,
+    /// the page exists in only one document
+    Missing,
+}
+
+impl Status {
+    fn name(self) -> &'static str {
+        match self {
+            Status::Identical => "identical",
+            Status::Similar => "similar",
+            Status::Different => "different",
+            Status::SizeMismatch => "size-mismatch",
+            Status::Missing => "missing",
+        }
+    }
+
+    fn matches(self) -> bool {
+        matches!(self, Status::Identical | Status::Similar)
+    }
+}
+
+struct PageDiff {
+    /// 1-based
+    page: u32,
+    status: Status,
+    diff_pixels: usize,
+    total_pixels: usize,
+    ssim: f64,
+    diff_image: Option<PathBuf>,
+}
+
+impl PageDiff {
+    fn ratio(&self) -> f64 {
+        if self.total_pixels == 0 {
+            return 0.0;
+        }
+        self.diff_pixels as f64 / self.total_pixels as f64
+    }
+
+    fn to_json(&self) -> PageJson {
+        let measured = !matches!(self.status, Status::SizeMismatch | Status::Missing);
+        PageJson {
+            page: self.page,
+            status: self.status.name(),
+            diff_pixels: measured.then_some(self.diff_pixels),
+            diff_ratio: measured.then_some(self.ratio()),
+            ssim: measured.then_some(self.ssim),
+            diff_image: self.diff_image.as_ref().map(|p| p.display().to_string()),
+        }
+    }
+}
+
+/// `compare --json`'s summary
+#[derive(Serialize)]
+struct CompareJson {
+    first: String,
+    second: String,
+    first_pages: i32,
+    second_pages: i32,
+    dpi: u32,
+    #[serde(rename = "match")]
+    matched: bool,
+    pages: Vec<PageJson>,
+}
+
+/// a page's entry; the measures are null for pages that couldn't be compared
+#[derive(Serialize)]
+struct PageJson {
+    page: u32,
+    status: &'static str,
+    diff_pixels: Option<usize>,
+    diff_ratio: Option<f64>,
+    ssim: Option<f64>,
+    diff_image: Option<String>,
+}
+
+/// rendered page: packed RGB
+struct Render {
+    width: u32,
+    height: u32,
+    samples: Vec<u8>,
+}
+
+/// render both PDFs and compare them page by page; prints a summary (or JSON)
+/// and returns whether every page matched
+pub fn compare_pdfs(first: &Path, second: &Path, opts: &CompareOptions) -> Result<bool> {
+    let first_str = first.to_str().context("Invalid path")?.to_string();
+    let second_str = second.to_str().context("Invalid path")?.to_string();
+    let open = |path: &str| {
+        mupdf::Document::open(path).with_context(|| format!("Failed to open {}", path))
+    };
+    let (first_doc, second_doc) = (open(&first_str)?, open(&second_str)?);
+    let (first_pages, second_pages) = (first_doc.page_count()?, second_doc.page_count()?);
+    let num_pages = first_pages.max(second_pages);
+    let page_indices: Vec<i32> = match opts.pages {
+        Some(s) => parse_page_ranges(s, num_pages)?,
+        None => (0..num_pages).collect(),
+    };
+    if let Some(dir) = opts.diff_dir {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Cannot create output dir: {}", dir.display()))?;
+    }
+    let start = std::time::Instant::now();
+    if !opts.quiet {
+        info!(
+            "Comparing {} and {} ({} page{}) at {} DPI",
+            first.display(),
+            second.display(),
+            page_indices.len(),
+            if page_indices.len() == 1 { "" } else { "s" },
+            opts.dpi
+        );
+    }
+    let stem = first.file_stem().and_then(|s| s.to_str()).unwrap_or("page");
+
+    // both documents are parsed once, here, into display lists of the pages
+    // they share; the pool renders and compares them, in page order
+    let lists = |i| -> Result<Option<(mupdf::DisplayList, mupdf::DisplayList)>> {
+        if i >= first_pages || i >= second_pages {
+            return Ok(None);
+        }
+        let list = |doc: &mupdf::Document| doc.load_page(i)?.to_display_list(true);
+        Ok(Some((list(&first_doc)?, list(&second_doc)?)))
+    };
+    let mut diffs = Vec::with_capacity(page_indices.len());
+    split::lists_in_order(
+        &page_indices,
+        lists,
+        |i, lists| {
+            let page = i as u32 + 1;
+            let Some((a, b)) = lists else {
+                return Ok(PageDiff {
+                    page,
+                    status: Status::Missing,
+                    diff_pixels: 0,
+                    total_pixels: 0,
+                    ssim: 0.0,
+                    diff_image: None,
+                });
+            };
+            let (a, b) = (render(&a, opts.dpi)?, render(&b, opts.dpi)?);
+            let path = opts.diff_dir.map(|dir| dir.join(format!("{}_diff_{:04}.png", stem, page)));
+            compare_page(page, &a, &b, opts, path.as_deref())
+                .with_context(|| format!("Failed to compare page {}", page))
+        },
+        |done| {
+            for diff in done {
+                diffs.push(diff?);
+            }
+            Ok(())
+        },
+    )?;
+
+    let matched = first_pages == second_pages && diffs.iter().all(|d| d.status.matches());
+    if opts.json {
+        let summary = CompareJson {
+            first: first.display().to_string(),
+            second: second.display().to_string(),
+            first_pages,
+            second_pages,
+            dpi: opts.dpi,
+            matched,
+            pages: diffs.iter().map(PageDiff::to_json).collect(),
+        };
+        println!("{}", serde_json::to_string(&summary)?);
+    } else {
+        if first_pages != second_pages {
+            println!("page count: {} vs {}", first_pages, second_pages);
+        }
+        for diff in &diffs {
+            match diff.status {
+                Status::Identical => println!("page {}: identical", diff.page),
+                Status::SizeMismatch => println!("page {}: page sizes differ", diff.page),
+                Status::Missing => println!("page {}: missing in one document", diff.page),
+                Status::Similar | Status::Different => println!(
+                    "page {}: {} ({:.4}% of pixels differ, SSIM {:.4}){}",
+                    diff.page,
+                    diff.status.name(),
+                    diff.ratio() * 100.0,
+                    diff.ssim,
+                    diff.diff_image
+                        .as_ref()
+                        .map_or(String::new(), |p| format!(" -> {}", p.display()))
+                ),
+            }
+        }
+        println!("{}", if matched { "match" } else { "documents differ" });
+    }
+    if !opts.quiet {
+        info!("Done. Compared in {:.2}s", start.elapsed().as_secs_f64());
+    }
+    Ok(matched)
+}
+
+fn render(list: &mupdf::DisplayList, dpi: u32) -> Result<Render> {
+    let scale = dpi as f32 / 72.0;
+    let matrix = mupdf::Matrix::new_scale(scale, scale);
+    let pixmap = list.to_pixmap(&matrix, &mupdf::Colorspace::device_rgb(), false)?;
+    let (width, height) = (pixmap.width(), pixmap.height());
+    let samples = split::packed_samples(&pixmap, 3);
+    Ok(Render {
+        width,
+        height,
+        samples,
+    })
+}
+
+fn compare_page(
+    page: u32,
+    a: &Render,
+    b: &Render,
+    opts: &CompareOptions,
+    diff_path: Option<&Path>,
+) -> Result<PageDiff> {
+    if (a.width, a.height) != (b.width, b.height) {
+        return Ok(PageDiff {
+            page,
+            status: Status::SizeMismatch,
+            diff_pixels: 0,
+            total_pixels: 0,
+            ssim: 0.0,
+            diff_image: None,
+        });
+    }
+    let mask = diff_mask(&a.samples, &b.samples, opts.fuzz);
+    let diff_pixels = mask.iter().filter(|&&d| d).count();
+    let total_pixels = mask.len();
+    let ssim = ssim(&luma(&a.samples), &luma(&b.samples), a.width, a.height);
+    let status = if diff_pixels == 0 {
+        Status::Identical
+    } else if diff_pixels as f64 <= opts.threshold * total_pixels as f64 {
+        Status::Similar
+    } else {
+        Status::Different
+    };
+    let mut diff_image = None;
+    if let (Some(path), Status::Different) = (diff_path, status) {
+        let file = std::fs::File::create(path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        let image = highlight(&a.samples, &mask);
+        encode_png(&image, a.width, a.height, false, PngCompression::Fast, file)?;
+        diff_image = Some(path.to_path_buf());
+    }
+    Ok(PageDiff {
+        page,
+        status,
+        diff_pixels,
+        total_pixels,
+        ssim,
+        diff_image,
+    })
+}
+
+/// per pixel: does any channel differ by more than `fuzz`
+fn diff_mask(a: &[u8], b: &[u8], fuzz: u8) -> Vec<bool> {
+    a.chunks_exact(3)
+        .zip(b.chunks_exact(3))
+        .map(|(p, q)| p.iter().zip(q).any(|(x, y)| x.abs_diff(*y) > fuzz))
+        .collect()
+}
+
+/// the first page washed out, with differing pixels in red
+fn highlight(samples: &[u8], mask: &[bool]) -> Vec<u8> {
+    samples
+        .chunks_exact(3)
+        .zip(mask)
+        .flat_map(|(p, &differs)| {
+            if differs {
+                [255, 0, 0]
+            } else {
+                let y = (p[0] as u32 * 299 + p[1] as u32 * 587 + p[2] as u32 * 114) / 1000;
+                let v = (255 - (255 - y) / 4) as u8;
+                [v, v, v]
+            }
+        })
+        .collect()
+}
+
+fn luma(samples: &[u8]) -> Vec<f32> {
+    samples
+        .chunks_exact(3)
+        .map(|p| 0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32)
+        .collect()
+}
+
+/// mean structural similarity over 8x8 windows: 1.0 for identical images
+pub fn ssim(a: &[f32], b: &[f32], width: u32, height: u32) -> f64 {
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+    let (width, height) = (width as usize, height as usize);
+    let mut total = 0.0;
+    let mut windows = 0;
+    for wy in (0..height).step_by(8) {
+        for wx in (0..width).step_by(8) {
+            let (mut sa, mut sb, mut saa, mut sbb, mut sab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+            let mut n = 0.0;
+            for y in wy..(wy + 8).min(height) {
+                for x in wx..(wx + 8).min(width) {
+                    let (p, q) = (a[y * width + x] as f64, b[y * width + x] as f64);
+                    sa += p;
+                    sb += q;
+                    saa += p * p;
+                    sbb += q * q;
+                    sab += p * q;
+                    n += 1.0;
+                }
+            }
+            let (ma, mb) = (sa / n, sb / n);
+            let (va, vb) = (saa / n - ma * ma, sbb / n - mb * mb);
+            let cov = sab / n - ma * mb;
+            total += ((2.0 * ma * mb + C1) * (2.0 * cov + C2))
+                / ((ma * ma + mb * mb + C1) * (va + vb + C2));
+            windows += 1;
+        }
+    }
+    if windows == 0 {
+        1.0
+    } else {
+        total / windows as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opts(threshold: f64) -> CompareOptions<'static> {
+        CompareOptions {
+            dpi: 72,
+            fuzz: 4,
+            threshold,
+            pages: None,
+            diff_dir: None,
+            json: false,
+            quiet: true,
+        }
+    }
+
+    fn render(width: u32, height: u32, fill: impl Fn(u32, u32) -> [u8; 3]) -> Render {
+        let samples = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .flat_map(|(x, y)| fill(x, y))
+            .collect();
+        Render {
+            width,
+            height,
+            samples,
+        }
+    }
+
+    #[test]
+    fn compare_counts_pixels_beyond_fuzz() {
+        let a = render(16, 16, |x, y| [(x * 16) as u8, (y * 16) as u8, 100]);
+        let same = compare_page(1, &a, &a, &opts(0.0), None).unwrap();
+        assert_eq!(same.status, Status::Identical);
+        assert!((same.ssim - 1.0).abs() < 1e-9);
+
+        // within the fuzz everywhere, plus one 2x2 black square
+        let b = render(16, 16, |x, y| {
+            if (4..6).contains(&x) && (4..6).contains(&y) {
+                [0, 0, 0]
+            } else {
+                [(x * 16) as u8 + 3, (y * 16) as u8, 100]
+            }
+        });
+        let diff = compare_page(1, &a, &b, &opts(0.0), None).unwrap();
+        assert_eq!(diff.status, Status::Different);
+        assert_eq!(diff.diff_pixels, 4);
+        assert!(diff.ssim < 1.0);
+        let similar = compare_page(1, &a, &b, &opts(0.02), None).unwrap();
+        assert_eq!(similar.status, Status::Similar);
+
+        let small = render(8, 16, |_, _| [0, 0, 0]);
+        let mismatch = compare_page(1, &a, &small, &opts(0.0), None).unwrap();
+        assert_eq!(mismatch.status, Status::SizeMismatch);
+    }
+
+    #[test]
+    fn highlight_marks_differences_red() {
+        let image = highlight(&[0, 0, 0, 255, 255, 255], &[true, false]);
+        assert_eq!(image, vec![255, 0, 0, 255, 255, 255]);
+    }
+}
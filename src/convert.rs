@@ -0,0 +1,286 @@
+use anyhow::{Context, Result};
+use image::DynamicImage;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tracing::{error, info};
+
+use crate::deskew;
+use crate::parse::{ConvertFormat, PngCompression, Scale};
+use crate::split::{encode_jpg, encode_png};
+
+pub struct ConvertOptions {
+    pub format: ConvertFormat,
+    /// output dir (None = next to each input)
+    pub output_dir: Option<PathBuf>,
+    pub scale: Option<Scale>,
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+    pub gray: bool,
+    pub quality: u8,
+    pub compress: PngCompression,
+    /// straighten skewed scans before resizing
+    pub deskew: bool,
+    pub quiet: bool,
+}
+
+/// convert images to another format in parallel, one output file per input
+pub fn convert_images(inputs: &[PathBuf], opts: &ConvertOptions) -> Result<()> {
+    let jobs = output_paths(inputs, opts)?;
+    let total = jobs.len();
+    if let Some(dir) = &opts.output_dir {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Cannot create output dir: {}", dir.display()))?;
+    }
+    if !opts.quiet {
+        info!(
+            "Converting {} image{} to {}",
+            total,
+            if total == 1 { "" } else { "s" },
+            opts.format.extension().to_uppercase()
+        );
+    }
+    let start = std::time::Instant::now();
+    let done_count = AtomicUsize::new(0);
+
+    let errors: Vec<(&Path, anyhow::Error)> = jobs
+        .par_iter()
+        .filter_map(|(input, output)| {
+            let result = convert_image(input, output, opts);
+            if let (Ok(angle), false) = (&result, opts.quiet) {
+                let done = done_count.fetch_add(1, Ordering::Relaxed) + 1;
+                let note = if !opts.deskew {
+                    String::new()
+                } else if *angle == 0.0 {
+                    " (already straight)".to_string()
+                } else {
+                    format!(" (straightened by {:.2}°)", angle)
+                };
+                info!("  [{}/{}] {}{}", done, total, output.display(), note);
+            }
+            result.err().map(|e| (input.as_path(), e))
+        })
+        .collect();
+
+    if !errors.is_empty() {
+        let count = errors.len();
+        for (input, err) in &errors {
+            error!("{}: {:#}", input.display(), err);
+        }
+        let (input, err) = errors.into_iter().next().unwrap();
+        return Err(err.context(format!(
+            "Failed to convert {} ({} total error{})",
+            input.display(),
+            count,
+            if count == 1 { "" } else { "s" }
+        )));
+    }
+
+    if !opts.quiet {
+        info!("Done. {} images in {:.2}s", total, start.elapsed().as_secs_f64());
+    }
+    Ok(())
+}
+
+/// pair each input with its output path, refusing to overwrite an input or to
+/// write two inputs to the same file
+fn output_paths(inputs: &[PathBuf], opts: &ConvertOptions) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let mut seen: HashMap<PathBuf, &Path> = HashMap::new();
+    let mut jobs = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        let stem = input.file_stem().context("Invalid path")?;
+        let dir = match &opts.output_dir {
+            Some(dir) => dir.as_path(),
+            None => input.parent().unwrap_or(Path::new("")),
+        };
+        let output = dir.join(stem).with_extension(opts.format.extension());
+        anyhow::ensure!(
+            output != *input,
+            "{} is already {}; choose another --output dir",
+            input.display(),
+            opts.format.extension()
+        );
+        if let Some(other) = seen.insert(output.clone(), input) {
+            anyhow::bail!(
+                "{} and {} would both be written to {}",
+                other.display(),
+                input.display(),
+                output.display()
+            );
+        }
+        jobs.push((input.clone(), output));
+    }
+    Ok(jobs)
+}
+
+/// returns the skew corrected, in degrees (0 without `deskew`)
+fn convert_image(input: &Path, output: &Path, opts: &ConvertOptions) -> Result<f32> {
+    let mut img = image::ImageReader::open(input)
+        .with_context(|| format!("Failed to open {}", input.display()))?
+        .with_guessed_format()?
+        .decode()
+        .with_context(|| format!("Failed to decode {}", input.display()))?;
+
+    let mut angle = 0.0;
+    if opts.deskew {
+        (img, angle) = deskew::deskew(img);
+    }
+    if let Some((w, h)) = target_size(img.width(), img.height(), opts) {
+        img = img.resize_exact(w, h, image::imageops::FilterType::Lanczos3);
+    }
+    if opts.gray {
+        img = if img.color().has_alpha() {
+            DynamicImage::ImageLumaA8(img.to_luma_alpha8())
+        } else {
+            DynamicImage::ImageLuma8(img.to_luma8())
+        };
+    }
+
+    let file = std::fs::File::create(output)
+        .with_context(|| format!("Failed to create {}", output.display()))?;
+    let mut out = std::io::BufWriter::new(file);
+    let gray = !img.color().has_color();
+    let result = match opts.format {
+        ConvertFormat::Jpg => {
+            // JPEG has no alpha: composite onto white
+            let (data, w, h) = flatten_to_white(&img, gray);
+            encode_jpg(&data, w, h, gray, opts.quality, out)
+        }
+        // 8-bit without alpha takes the same encoder as split
+        ConvertFormat::Png if !img.color().has_alpha() && is_8bit(&img) => {
+            let (data, w, h) = if gray {
+                let gray = img.to_luma8();
+                let (w, h) = gray.dimensions();
+                (gray.into_raw(), w, h)
+            } else {
+                let rgb = img.to_rgb8();
+                let (w, h) = rgb.dimensions();
+                (rgb.into_raw(), w, h)
+            };
+            encode_png(&data, w, h, gray, opts.compress, out)
+        }
+        format => {
+            let format = match format {
+                ConvertFormat::Png => image::ImageFormat::Png,
+                ConvertFormat::Tiff => image::ImageFormat::Tiff,
+                ConvertFormat::Bmp => image::ImageFormat::Bmp,
+                _ => image::ImageFormat::Gif,
+            };
+            // the GIF encoder takes RGBA, the BMP encoder 8-bit channels only
+            let img = match format {
+                image::ImageFormat::Gif => DynamicImage::ImageRgba8(img.to_rgba8()),
+                image::ImageFormat::Bmp if img.color().has_alpha() => {
+                    DynamicImage::ImageRgba8(img.to_rgba8())
+                }
+                image::ImageFormat::Bmp if gray => DynamicImage::ImageLuma8(img.to_luma8()),
+                image::ImageFormat::Bmp => DynamicImage::ImageRgb8(img.to_rgb8()),
+                _ => img,
+            };
+            img.write_to(&mut out, format)
+                .map_err(anyhow::Error::from)
+                .and_then(|_| Ok(out.flush()?))
+        }
+    };
+    if result.is_err() {
+        let _ = std::fs::remove_file(output);
+    }
+    result
+        .map(|_| angle)
+        .with_context(|| format!("Failed to encode {}", output.display()))
+}
+
+/// output pixel size after --scale, then --max-width/--max-height (which only
+/// shrink, keeping the aspect ratio); None when unchanged
+fn target_size(width: u32, height: u32, opts: &ConvertOptions) -> Option<(u32, u32)> {
+    let mut factor = opts.scale.map_or(1.0, |Scale(s)| s as f64);
+    if let Some(max) = opts.max_width {
+        factor = factor.min(max as f64 / width as f64);
+    }
+    if let Some(max) = opts.max_height {
+        factor = factor.min(max as f64 / height as f64);
+    }
+    let w = ((width as f64 * factor).round() as u32).max(1);
+    let h = ((height as f64 * factor).round() as u32).max(1);
+    ((w, h) != (width, height)).then_some((w, h))
+}
+
+fn is_8bit(img: &DynamicImage) -> bool {
+    let color = img.color();
+    color.bytes_per_pixel() == color.channel_count()
+}
+
+/// 8-bit gray or RGB pixels with any alpha composited onto white
+fn flatten_to_white(img: &DynamicImage, gray: bool) -> (Vec<u8>, u32, u32) {
+    let rgba = img.to_rgba8();
+    let (w, h) = rgba.dimensions();
+    let channels = if gray { 1 } else { 3 };
+    let mut data = Vec::with_capacity((w * h) as usize * channels);
+    for px in rgba.pixels() {
+        let a = px[3] as u32;
+        let blend = |c: u8| ((c as u32 * a + 255 * (255 - a) + 127) / 255) as u8;
+        if gray {
+            data.push(blend(px[0]));
+        } else {
+            data.extend_from_slice(&[blend(px[0]), blend(px[1]), blend(px[2])]);
+        }
+    }
+    (data, w, h)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opts() -> ConvertOptions {
+        ConvertOptions {
+            format: ConvertFormat::Png,
+            output_dir: None,
+            scale: None,
+            max_width: None,
+            max_height: None,
+            gray: false,
+            quality: 75,
+            compress: PngCompression::Fast,
+            deskew: false,
+            quiet: true,
+        }
+    }
+
+    #[test]
+    fn target_size_scale_and_bounds() {
+        assert_eq!(target_size(400, 200, &opts()), None);
+        let half = ConvertOptions {
+            scale: Some(Scale(0.5)),
+            ..opts()
+        };
+        assert_eq!(target_size(400, 200, &half), Some((200, 100)));
+        let bounded = ConvertOptions {
+            max_width: Some(100),
+            max_height: Some(100),
+            ..opts()
+        };
+        assert_eq!(target_size(400, 200, &bounded), Some((100, 50)));
+        // bounds never enlarge
+        assert_eq!(target_size(50, 20, &bounded), None);
+    }
+
+    #[test]
+    fn output_paths_detect_clashes() {
+        let png = ConvertOptions {
+            format: ConvertFormat::Png,
+            ..opts()
+        };
+        assert!(output_paths(&[PathBuf::from("a/x.png")], &png).is_err());
+        let jpg = ConvertOptions {
+            format: ConvertFormat::Jpg,
+            output_dir: Some(PathBuf::from("out")),
+            ..opts()
+        };
+        let jobs = output_paths(&[PathBuf::from("a/x.png")], &jpg).unwrap();
+        assert_eq!(jobs[0].1, PathBuf::from("out/x.jpg"));
+        let clash = [PathBuf::from("a/x.png"), PathBuf::from("b/x.bmp")];
+        assert!(output_paths(&clash, &jpg).is_err());
+    }
+}
@@ -0,0 +1,120 @@
+use anyhow::Result;
+use lopdf::Object;
+use std::path::Path;
+use tracing::info;
+
+use crate::parse::{parse_page_ranges, BoxTarget, Margins, PageRect};
+use crate::pdf;
+
+/// the new box: given outright, or trimmed from the current visible area
+#[derive(Debug, Clone, Copy)]
+pub enum Crop {
+    Rect(PageRect),
+    Margins(Margins),
+}
+
+/// set the CropBox and/or TrimBox of pages; content is left untouched, so
+/// nothing is rasterized and the trimmed area can be restored later
+pub fn crop_pages(
+    input: &Path,
+    output: &Path,
+    crop: Crop,
+    target: BoxTarget,
+    pages: Option<&str>,
+    quiet: bool,
+) -> Result<()> {
+    let start = std::time::Instant::now();
+    let mut doc = pdf::load(input)?;
+    let page_ids: Vec<_> = doc.get_pages().into_values().collect();
+    let selected: Vec<usize> = match pages {
+        Some(s) => parse_page_ranges(s, page_ids.len() as i32)?
+            .into_iter()
+            .map(|p| p as usize)
+            .collect(),
+        None => (0..page_ids.len()).collect(),
+    };
+    if !quiet {
+        info!("Cropping {} page(s) -> {}", selected.len(), output.display());
+    }
+
+    // all computed from the original boxes, so a page listed twice is trimmed once
+    let mut updates = Vec::new();
+    for &i in &selected {
+        let page_id = page_ids[i];
+        let rect = match crop {
+            Crop::Rect(rect) => rect.0,
+            Crop::Margins(margins) => {
+                let rotate = pdf::page_attribute(&doc, page_id, b"Rotate")
+                    .and_then(|r| r.as_i64().ok())
+                    .unwrap_or(0);
+                trim(pdf::crop_box(&doc, page_id), margins, rotate)
+            }
+        };
+        // viewers clip every box to the MediaBox anyway
+        let [mx0, my0, mx1, my1] = pdf::media_box(&doc, page_id);
+        let media = [mx0.min(mx1), my0.min(my1), mx0.max(mx1), my0.max(my1)];
+        let rect = intersect(rect, media)
+            .ok_or_else(|| anyhow::anyhow!("Page {}: the new box leaves nothing visible", i + 1))?;
+        updates.push((page_id, rect));
+    }
+    for (page_id, rect) in updates {
+        let rect: Vec<Object> = rect.iter().map(|&v| Object::Real(v)).collect();
+        let page = doc.get_dictionary_mut(page_id)?;
+        if matches!(target, BoxTarget::Crop | BoxTarget::Both) {
+            page.set("CropBox", rect.clone());
+        }
+        if matches!(target, BoxTarget::Trim | BoxTarget::Both) {
+            page.set("TrimBox", rect);
+        }
+    }
+    pdf::save(&mut doc, output)?;
+
+    if !quiet {
+        info!("Done. PDF saved in {:.2}s", start.elapsed().as_secs_f64());
+    }
+    Ok(())
+}
+
+/// shrink a box by margins given for the page as shown, i.e. after /Rotate
+fn trim(rect: [f32; 4], margins: Margins, rotate: i64) -> [f32; 4] {
+    let shown = [margins.top, margins.right, margins.bottom, margins.left];
+    // /Rotate turns the page clockwise: at 90 the shown top is the box's left
+    let turn = (rotate.rem_euclid(360) / 90) as usize;
+    let side = |k: usize| shown[(k + turn) % 4];
+    [rect[0] + side(3), rect[1] + side(2), rect[2] - side(1), rect[3] - side(0)]
+}
+
+fn intersect(a: [f32; 4], b: [f32; 4]) -> Option<[f32; 4]> {
+    let rect = [a[0].max(b[0]), a[1].max(b[1]), a[2].min(b[2]), a[3].min(b[3])];
+    (rect[0] < rect[2] && rect[1] < rect[3]).then_some(rect)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trim_follows_rotation() {
+        let margins = Margins {
+            top: 1.0,
+            right: 2.0,
+            bottom: 3.0,
+            left: 4.0,
+        };
+        let rect = [0.0, 0.0, 100.0, 200.0];
+        assert_eq!(trim(rect, margins, 0), [4.0, 3.0, 98.0, 199.0]);
+        // shown top is the box's left edge, shown right its top
+        assert_eq!(trim(rect, margins, 90), [1.0, 4.0, 97.0, 198.0]);
+        assert_eq!(trim(rect, margins, 180), [2.0, 1.0, 96.0, 197.0]);
+        assert_eq!(trim(rect, margins, -90), [3.0, 2.0, 99.0, 196.0]);
+    }
+
+    #[test]
+    fn intersect_rejects_empty() {
+        assert_eq!(
+            intersect([-10.0, 0.0, 50.0, 50.0], [0.0, 0.0, 100.0, 100.0]),
+            Some([0.0, 0.0, 50.0, 50.0])
+        );
+        assert_eq!(intersect([0.0, 0.0, 10.0, 10.0], [20.0, 0.0, 30.0, 10.0]), None);
+    }
+}
@@ -0,0 +1,189 @@
+use aes::cipher::block_padding::{NoPadding, Pkcs7};
+use aes::cipher::{BlockDecryptMut, BlockEncrypt, BlockEncryptMut, KeyInit, KeyIvInit};
+use aes::{Aes128, Aes192, Aes256};
+use md5::{Digest, Md5};
+use rc4::{consts, Rc4, StreamCipher};
+use sha2::{Sha256, Sha384, Sha512};
+
+pub fn md5(parts: &[&[u8]]) -> [u8; 16] {
+    let mut hasher = Md5::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+/// RC4 with keys of up to 16 bytes, as PDF uses
+pub fn rc4(key: &[u8], data: &[u8]) -> Vec<u8> {
+    macro_rules! apply {
+        ($buf:ident; $($n:literal => $size:ty),*) => {
+            match key.len() {
+                $($n => Rc4::<$size>::new(key.into()).apply_keystream(&mut $buf),)*
+                n => panic!("unsupported RC4 key length {}", n),
+            }
+        };
+    }
+    let mut out = data.to_vec();
+    apply!(out;
+        1 => consts::U1, 2 => consts::U2, 3 => consts::U3, 4 => consts::U4,
+        5 => consts::U5, 6 => consts::U6, 7 => consts::U7, 8 => consts::U8,
+        9 => consts::U9, 10 => consts::U10, 11 => consts::U11, 12 => consts::U12,
+        13 => consts::U13, 14 => consts::U14, 15 => consts::U15, 16 => consts::U16
+    );
+    out
+}
+
+/// runs `$body` with `$cipher` bound to the AES type for the key's length
+macro_rules! with_aes {
+    ($key:expr, $cipher:ident => $body:expr) => {
+        match $key.len() {
+            16 => {
+                type $cipher = Aes128;
+                $body
+            }
+            24 => {
+                type $cipher = Aes192;
+                $body
+            }
+            32 => {
+                type $cipher = Aes256;
+                $body
+            }
+            n => panic!("unsupported AES key length {}", n),
+        }
+    };
+}
+
+/// AES-CBC encryption; `pad` adds PKCS#7 padding, otherwise `data` must be a
+/// whole number of blocks
+pub fn aes_cbc_encrypt(key: &[u8], iv: &[u8; 16], data: &[u8], pad: bool) -> Vec<u8> {
+    with_aes!(key, C => {
+        let encryptor = cbc::Encryptor::<C>::new(key.into(), iv.into());
+        if pad {
+            encryptor.encrypt_padded_vec_mut::<Pkcs7>(data)
+        } else {
+            encryptor.encrypt_padded_vec_mut::<NoPadding>(data)
+        }
+    })
+}
+
+/// AES-CBC decryption of whole blocks (a trailing partial block is dropped);
+/// padding is left for the caller
+pub fn aes_cbc_decrypt(key: &[u8], iv: &[u8; 16], data: &[u8]) -> Vec<u8> {
+    let whole = &data[..data.len() / 16 * 16];
+    with_aes!(key, C => cbc::Decryptor::<C>::new(key.into(), iv.into())
+        .decrypt_padded_vec_mut::<NoPadding>(whole)
+        .expect("whole blocks need no unpadding"))
+}
+
+/// one AES block with no chaining (ECB), for the R6 /Perms entry
+pub fn aes_encrypt_block(key: &[u8], block: &mut [u8; 16]) {
+    with_aes!(key, C => C::new(key.into()).encrypt_block(block.into()))
+}
+
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+pub fn sha384(data: &[u8]) -> [u8; 48] {
+    Sha384::digest(data).into()
+}
+
+pub fn sha512(data: &[u8]) -> [u8; 64] {
+    Sha512::digest(data).into()
+}
+
+/// the SHA-2 hashes, where the algorithm is picked at run time (signatures)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hash {
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl Hash {
+    pub fn digest(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Hash::Sha256 => sha256(data).to_vec(),
+            Hash::Sha384 => sha384(data).to_vec(),
+            Hash::Sha512 => sha512(data).to_vec(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn unhex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn aes_fips197_vectors() {
+        let plain: [u8; 16] = unhex("00112233445566778899aabbccddeeff").try_into().unwrap();
+        for (key, cipher) in [
+            ("000102030405060708090a0b0c0d0e0f", "69c4e0d86a7b0430d8cdb78070b4c55a"),
+            (
+                "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f",
+                "8ea2b7ca516745bfeafc49904b496089",
+            ),
+        ] {
+            let key = unhex(key);
+            let mut block = plain;
+            aes_encrypt_block(&key, &mut block);
+            assert_eq!(hex(&block), cipher);
+            // a single CBC block under a zero IV is the raw cipher
+            assert_eq!(aes_cbc_decrypt(&key, &[0; 16], &block), plain);
+        }
+    }
+
+    #[test]
+    fn aes_cbc_round_trip_with_padding() {
+        let key = [7u8; 32];
+        let iv = [3u8; 16];
+        for len in [0, 5, 16, 33] {
+            let data: Vec<u8> = (0..len as u8).collect();
+            let cipher = aes_cbc_encrypt(&key, &iv, &data, true);
+            assert_eq!(cipher.len(), (len / 16 + 1) * 16);
+            let plain = aes_cbc_decrypt(&key, &iv, &cipher);
+            assert_eq!(&plain[..len], &data[..]);
+        }
+    }
+
+    #[test]
+    fn sha2_vectors() {
+        assert_eq!(
+            hex(&sha256(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hex(&sha384(b"abc")),
+            "cb00753f45a35e8bb5a03d699ac65007272c32ab0eded1631a8b605a43ff5bed\
+             8086072ba1e7cc2358baeca134c825a7"
+        );
+        assert_eq!(
+            hex(&sha512(b"abc")),
+            "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+             2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f"
+        );
+        // two-block message
+        assert_eq!(
+            hex(&sha256(&[b'a'; 64])),
+            "ffe054fe7ae0cb6dc65c3af9b61d5209f439851db43d0ba5997337df154668eb"
+        );
+    }
+
+    #[test]
+    fn rc4_and_md5_vectors() {
+        assert_eq!(hex(&rc4(b"Key", b"Plaintext")), "bbf316e8d940af0ad3");
+        assert_eq!(hex(&md5(&[b"a", b"bc"])), "900150983cd24fb0d6963f7d28e17f72");
+    }
+}
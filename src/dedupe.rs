@@ -0,0 +1,352 @@
+use anyhow::{Context, Result};
+use image::GrayImage;
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use tracing::info;
+
+use crate::compare;
+use crate::crypto;
+use crate::pdf;
+use crate::split;
+
+/// rendered pages are compared as thumbnails of this size, squeezed to a square
+const THUMB: u32 = 128;
+
+/// average-hash bits two thumbnails may differ by before the full comparison is
+/// skipped as pointless
+const HASH_DISTANCE: u32 = 12;
+
+/// page keys that say where a page sits or what points at it, not what it shows
+const IGNORED_KEYS: [&[u8]; 5] = [b"Parent", b"Annots", b"B", b"StructParents", b"Thumb"];
+
+pub struct DedupeOptions {
+    /// also drop pages that look the same rendered, not just identical ones
+    pub similar: bool,
+    /// SSIM (0-1) two rendered pages need to count as the same
+    pub threshold: f64,
+    /// only compare each page with the one before it
+    pub adjacent: bool,
+    pub quiet: bool,
+}
+
+/// why a page was dropped
+struct Duplicate {
+    page: usize,
+    of: usize,
+    /// similarity when the pages only look alike; None for identical pages
+    ssim: Option<f64>,
+}
+
+/// a rendered page, reduced for comparison
+struct Thumbnail {
+    aspect: f32,
+    hash: u64,
+    luma: Vec<f32>,
+}
+
+/// drop repeated pages from a PDF, keeping the first of each. pages are the same
+/// when they draw the same content with the same resources; with `similar`, also
+/// when their renderings match closely, as with a page scanned twice
+pub fn dedupe_pdf(input: &Path, output: &Path, opts: &DedupeOptions) -> Result<()> {
+    let start = std::time::Instant::now();
+    let mut doc = pdf::load(input)?;
+    pdf::inherit_page_attributes(&mut doc);
+    let page_ids: Vec<ObjectId> = doc.get_pages().into_values().collect();
+    if !opts.quiet {
+        info!(
+            "Looking for duplicates among {} page(s) of {}",
+            page_ids.len(),
+            input.display()
+        );
+    }
+
+    let mut memo = HashMap::new();
+    let digests: Vec<[u8; 16]> = page_ids
+        .iter()
+        .map(|&id| page_digest(&doc, id, &IGNORED_KEYS, &mut memo))
+        .collect();
+    let thumbnails = if opts.similar {
+        Some(render_thumbnails(input, page_ids.len())?)
+    } else {
+        None
+    };
+
+    let mut kept: Vec<usize> = Vec::new();
+    let mut duplicates = Vec::new();
+    for i in 0..page_ids.len() {
+        let candidates = match opts.adjacent {
+            true => &kept[kept.len().saturating_sub(1)..],
+            false => &kept[..],
+        };
+        let found = candidates.iter().find_map(|&k| {
+            if digests[k] == digests[i] {
+                return Some((k, None));
+            }
+            let thumbs = thumbnails.as_ref()?;
+            let ssim = similarity(&thumbs[k], &thumbs[i])?;
+            (ssim >= opts.threshold).then_some((k, Some(ssim)))
+        });
+        match found {
+            Some((k, ssim)) => duplicates.push(Duplicate {
+                page: i + 1,
+                of: k + 1,
+                ssim,
+            }),
+            None => kept.push(i),
+        }
+    }
+
+    if !opts.quiet {
+        for dup in &duplicates {
+            match dup.ssim {
+                None => info!("  page {}: same as page {}", dup.page, dup.of),
+                Some(ssim) => info!(
+                    "  page {}: looks like page {} (SSIM {:.3})",
+                    dup.page, dup.of, ssim
+                ),
+            }
+        }
+    }
+    let kept_ids: Vec<ObjectId> = kept.iter().map(|&i| page_ids[i]).collect();
+    pdf::set_page_order(&mut doc, &kept_ids)?;
+    pdf::save(&mut doc, output)?;
+
+    if !opts.quiet {
+        info!(
+            "Done. {} duplicate page(s) dropped, {} kept, PDF saved in {:.2}s",
+            duplicates.len(),
+            kept.len(),
+            start.elapsed().as_secs_f64()
+        );
+    }
+    Ok(())
+}
+
+/// digest of what a page draws: its dictionary (less the `ignored` keys) and
+/// everything it references, hashed by value so copies of a resource under
+/// different object numbers still match
+pub(crate) fn page_digest(
+    doc: &Document,
+    page_id: ObjectId,
+    ignored: &[&[u8]],
+    memo: &mut HashMap<ObjectId, [u8; 16]>,
+) -> [u8; 16] {
+    let mut bytes = Vec::new();
+    if let Ok(page) = doc.get_dictionary(page_id) {
+        let mut entries: Vec<_> = page
+            .iter()
+            .filter(|(key, _)| !ignored.contains(&key.as_slice()))
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+        let mut visiting = HashSet::from([page_id]);
+        for (key, value) in entries {
+            encode_name(&mut bytes, key);
+            encode(doc, value, memo, &mut visiting, &mut bytes);
+        }
+    }
+    crypto::md5(&[&bytes])
+}
+
+/// an object's digest, through the memo
+fn object_digest(
+    doc: &Document,
+    id: ObjectId,
+    memo: &mut HashMap<ObjectId, [u8; 16]>,
+    visiting: &mut HashSet<ObjectId>,
+) -> [u8; 16] {
+    if let Some(digest) = memo.get(&id) {
+        return *digest;
+    }
+    // a reference back into an object still being hashed
+    if !visiting.insert(id) {
+        return [0; 16];
+    }
+    let mut bytes = Vec::new();
+    match doc.get_object(id) {
+        Ok(obj) => encode(doc, obj, memo, visiting, &mut bytes),
+        Err(_) => bytes.push(b'?'),
+    }
+    visiting.remove(&id);
+    let digest = crypto::md5(&[&bytes]);
+    memo.insert(id, digest);
+    digest
+}
+
+/// unambiguous serialization: a tag per type, lengths before variable data,
+/// dictionary keys sorted, references replaced by their target's digest
+fn encode(
+    doc: &Document,
+    obj: &Object,
+    memo: &mut HashMap<ObjectId, [u8; 16]>,
+    visiting: &mut HashSet<ObjectId>,
+    out: &mut Vec<u8>,
+) {
+    match obj {
+        Object::Null => out.push(b'n'),
+        Object::Boolean(b) => out.extend_from_slice(&[b'b', *b as u8]),
+        Object::Integer(n) => {
+            out.push(b'i');
+            out.extend_from_slice(&n.to_le_bytes());
+        }
+        Object::Real(n) => {
+            out.push(b'r');
+            out.extend_from_slice(&n.to_le_bytes());
+        }
+        Object::Name(name) => encode_name(out, name),
+        Object::String(bytes, _) => {
+            out.push(b's');
+            out.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+            out.extend_from_slice(bytes);
+        }
+        Object::Array(items) => {
+            out.push(b'[');
+            out.extend_from_slice(&(items.len() as u64).to_le_bytes());
+            for item in items {
+                encode(doc, item, memo, visiting, out);
+            }
+        }
+        Object::Dictionary(dict) => encode_dict(doc, dict, memo, visiting, out),
+        Object::Stream(stream) => {
+            encode_dict(doc, &stream.dict, memo, visiting, out);
+            out.push(b'S');
+            out.extend_from_slice(&(stream.content.len() as u64).to_le_bytes());
+            out.extend_from_slice(&stream.content);
+        }
+        Object::Reference(id) => {
+            out.push(b'R');
+            out.extend_from_slice(&object_digest(doc, *id, memo, visiting));
+        }
+    }
+}
+
+fn encode_dict(
+    doc: &Document,
+    dict: &Dictionary,
+    memo: &mut HashMap<ObjectId, [u8; 16]>,
+    visiting: &mut HashSet<ObjectId>,
+    out: &mut Vec<u8>,
+) {
+    let mut entries: Vec<_> = dict.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    out.push(b'<');
+    out.extend_from_slice(&(entries.len() as u64).to_le_bytes());
+    for (key, value) in entries {
+        encode_name(out, key);
+        encode(doc, value, memo, visiting, out);
+    }
+}
+
+fn encode_name(out: &mut Vec<u8>, name: &[u8]) {
+    out.push(b'/');
+    out.extend_from_slice(&(name.len() as u64).to_le_bytes());
+    out.extend_from_slice(name);
+}
+
+/// render every page small, from one open document
+fn render_thumbnails(input: &Path, pages: usize) -> Result<Vec<Thumbnail>> {
+    let path = input.to_str().context("Invalid path")?;
+    let doc = mupdf::Document::open(path)
+        .with_context(|| format!("Failed to open {}", input.display()))?;
+    let indices: Vec<i32> = (0..pages as i32).collect();
+    let mut thumbnails = Vec::with_capacity(indices.len());
+    split::pages_in_order(
+        &doc,
+        &indices,
+        |i, list| {
+            render_thumbnail(&list).with_context(|| format!("Failed to render page {}", i + 1))
+        },
+        |done| {
+            for thumbnail in done {
+                thumbnails.push(thumbnail?);
+            }
+            Ok(())
+        },
+    )?;
+    Ok(thumbnails)
+}
+
+fn render_thumbnail(list: &mupdf::DisplayList) -> Result<Thumbnail> {
+    let bounds = list.bounds();
+    let (width, height) = (bounds.width().max(1.0), bounds.height().max(1.0));
+    // twice the thumbnail size, so downsampling smooths out scan noise
+    let scale = 2.0 * THUMB as f32 / width.max(height);
+    let matrix = mupdf::Matrix::new_scale(scale, scale);
+    let pixmap = list.to_pixmap(&matrix, &mupdf::Colorspace::device_gray(), false)?;
+    let samples = split::packed_samples(&pixmap, 1);
+    let gray = GrayImage::from_raw(pixmap.width(), pixmap.height(), samples)
+        .context("Unexpected pixmap layout")?;
+    Ok(thumbnail(&gray, width / height))
+}
+
+fn thumbnail(gray: &GrayImage, aspect: f32) -> Thumbnail {
+    let small = image::imageops::resize(gray, THUMB, THUMB, image::imageops::FilterType::Triangle);
+    let luma: Vec<f32> = small.pixels().map(|p| p.0[0] as f32).collect();
+    // one bit per 16x16 block: brighter than the page on average or not
+    let block = (THUMB / 8) as usize;
+    let mean = luma.iter().sum::<f32>() / luma.len() as f32;
+    let mut hash = 0u64;
+    for by in 0..8 {
+        for bx in 0..8 {
+            let sum: f32 = (0..block)
+                .flat_map(|y| (0..block).map(move |x| (by * block + y, bx * block + x)))
+                .map(|(y, x)| luma[y * THUMB as usize + x])
+                .sum();
+            if sum / (block * block) as f32 > mean {
+                hash |= 1 << (by * 8 + bx);
+            }
+        }
+    }
+    Thumbnail { aspect, hash, luma }
+}
+
+/// SSIM of two thumbnails, or None when their shapes or rough layouts differ
+fn similarity(a: &Thumbnail, b: &Thumbnail) -> Option<f64> {
+    let same_shape = (a.aspect / b.aspect - 1.0).abs() <= 0.02;
+    if !same_shape || (a.hash ^ b.hash).count_ones() > HASH_DISTANCE {
+        return None;
+    }
+    Some(compare::ssim(&a.luma, &b.luma, THUMB, THUMB))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::{dictionary, Stream};
+
+    #[test]
+    fn copies_of_a_page_hash_alike() {
+        let mut doc = Document::with_version("1.5");
+        let mut page = |text: &str| {
+            let font = doc.add_object(dictionary! { "Type" => "Font", "BaseFont" => "Courier" });
+            let content = doc.add_object(Stream::new(dictionary! {}, text.as_bytes().to_vec()));
+            doc.add_object(dictionary! {
+                "Type" => "Page",
+                "Contents" => content,
+                "Resources" => dictionary! { "Font" => dictionary! { "F1" => font } },
+            })
+        };
+        let (a, b, c) = (page("(one) Tj"), page("(one) Tj"), page("(two) Tj"));
+        let mut memo = HashMap::new();
+        let mut digest = |id| page_digest(&doc, id, &IGNORED_KEYS, &mut memo);
+        let (a, b, c) = (digest(a), digest(b), digest(c));
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn similar_thumbnails_match() {
+        let lines = |shift: u32, noise: u8| {
+            GrayImage::from_fn(256, 256, |x, y| {
+                let ink = (y + shift) % 32 < 6 && x > 20 && x < 236;
+                image::Luma([if ink { noise } else { 255 - noise }])
+            })
+        };
+        let page = thumbnail(&lines(0, 0), 1.0);
+        let rescan = thumbnail(&lines(0, 12), 1.0);
+        let other = thumbnail(&lines(16, 0), 1.0);
+        assert!(similarity(&page, &rescan).unwrap() > 0.95);
+        assert!(similarity(&page, &other).is_none_or(|ssim| ssim < 0.5));
+        assert!(similarity(&page, &thumbnail(&lines(0, 0), 0.7)).is_none());
+    }
+}
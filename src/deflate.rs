@@ -0,0 +1,319 @@
+use anyhow::Result;
+use flate2::{Compress, Compression, FlushCompress, Status};
+#[cfg(not(target_arch = "wasm32"))]
+use rayon::prelude::*;
+
+use crate::parse::Deflate;
+
+/// bytes of input per strip. big images are cut into strips deflated in
+/// parallel, each ending on a byte boundary (a sync flush) so they join into
+/// one zlib stream, as pigz does; below this an image is one strip
+const STRIP_LEN: usize = 4 << 20;
+
+/// pixels `split_alpha` copies at a time: a fixed run the compiler unrolls and
+/// turns into vector shuffles
+const SPLIT_BLOCK: usize = 16;
+
+/// the zlib level for `deflate`: 1 is the quick strategy, a single pass with
+/// static codes; 6 is zlib's default. flate2 runs them on zlib-rs, or on the
+/// native zlib-ng with the `zlib-ng` feature
+pub fn compression(deflate: Deflate) -> Compression {
+    match deflate {
+        Deflate::Fast => Compression::fast(),
+        Deflate::Standard => Compression::default(),
+    }
+}
+
+/// zlib header for `deflate`: deflate, 32K window, no dictionary, and the
+/// level hint (fastest or default)
+fn zlib_header(deflate: Deflate) -> [u8; 2] {
+    match deflate {
+        Deflate::Fast => [0x78, 0x01],
+        Deflate::Standard => [0x78, 0x9C],
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+thread_local! {
+    /// set while a merge prepare worker runs; see `serial_strips`
+    static SERIAL: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+/// run `f` with strips deflated one after another on this thread. merge's
+/// prepare workers block on a condvar inside a rayon scope; a parallel map from
+/// one of them could steal another, blocked worker's task while waiting for its
+/// strips, and hold up the image that task waits on
+#[cfg(not(target_arch = "wasm32"))]
+pub fn serial_strips<R>(f: impl FnOnce() -> R) -> R {
+    struct Reset(bool);
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            SERIAL.with(|serial| serial.set(self.0));
+        }
+    }
+    let _reset = Reset(SERIAL.with(|serial| serial.replace(true)));
+    f()
+}
+
+/// one strip's deflate blocks, with the Adler-32 and length of its input
+struct Strip {
+    deflated: Vec<u8>,
+    adler: u32,
+    len: usize,
+}
+
+/// zlib-compress `data` for a FlateDecode stream
+pub fn compress(data: &[u8], deflate: Deflate) -> Result<Vec<u8>> {
+    let strips = map_strips(data, 1, |strip, last| deflate_strip(strip, last, deflate))?;
+    Ok(join(strips, deflate))
+}
+
+/// zlib-compress the color and alpha samples of interleaved `pixels` as two
+/// streams: each pixel is `pixel_len` bytes, color first, then alpha
+pub fn compress_split(
+    pixels: &[u8],
+    pixel_len: usize,
+    color_len: usize,
+    deflate: Deflate,
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    let strips = map_strips(pixels, pixel_len, |strip, last| {
+        let (mut color, mut alpha) = (Vec::new(), Vec::new());
+        split_alpha(strip, pixel_len, color_len, &mut color, &mut alpha);
+        let color = deflate_strip(&color, last, deflate)?;
+        Ok((color, deflate_strip(&alpha, last, deflate)?))
+    })?;
+    let (color, alpha) = strips.into_iter().unzip();
+    Ok((join(color, deflate), join(alpha, deflate)))
+}
+
+/// append the color and alpha samples of interleaved `pixels` to `color` and
+/// `alpha`: each pixel is `pixel_len` bytes, color first, then alpha. the
+/// layouts of 8- and 16-bit RGBA and gray with alpha are split by the block,
+/// others a pixel at a time
+pub fn split_alpha(
+    pixels: &[u8],
+    pixel_len: usize,
+    color_len: usize,
+    color: &mut Vec<u8>,
+    alpha: &mut Vec<u8>,
+) {
+    match (pixel_len, color_len) {
+        (4, 3) => split_blocks::<4, 3>(pixels, color, alpha),
+        (2, 1) => split_blocks::<2, 1>(pixels, color, alpha),
+        (8, 6) => split_blocks::<8, 6>(pixels, color, alpha),
+        (4, 2) => split_blocks::<4, 2>(pixels, color, alpha),
+        _ => {
+            for pixel in pixels.chunks_exact(pixel_len) {
+                color.extend_from_slice(&pixel[..color_len]);
+                alpha.extend_from_slice(&pixel[color_len..]);
+            }
+        }
+    }
+}
+
+/// `split_alpha` with the layout known at compile time, so each block of
+/// pixels is copied with constant offsets and no bounds checks
+fn split_blocks<const PIXEL: usize, const COLOR: usize>(
+    pixels: &[u8],
+    color: &mut Vec<u8>,
+    alpha: &mut Vec<u8>,
+) {
+    let alpha_len = PIXEL - COLOR;
+    let count = pixels.len() / PIXEL;
+    let (color_start, alpha_start) = (color.len(), alpha.len());
+    color.resize(color_start + count * COLOR, 0);
+    alpha.resize(alpha_start + count * alpha_len, 0);
+    let (color, alpha) = (&mut color[color_start..], &mut alpha[alpha_start..]);
+    let blocks = pixels
+        .chunks_exact(PIXEL * SPLIT_BLOCK)
+        .zip(color.chunks_exact_mut(COLOR * SPLIT_BLOCK))
+        .zip(alpha.chunks_exact_mut(alpha_len * SPLIT_BLOCK));
+    for ((pixels, color), alpha) in blocks {
+        for i in 0..SPLIT_BLOCK {
+            color[i * COLOR..][..COLOR].copy_from_slice(&pixels[i * PIXEL..][..COLOR]);
+            alpha[i * alpha_len..][..alpha_len]
+                .copy_from_slice(&pixels[i * PIXEL + COLOR..][..alpha_len]);
+        }
+    }
+    for i in count / SPLIT_BLOCK * SPLIT_BLOCK..count {
+        color[i * COLOR..][..COLOR].copy_from_slice(&pixels[i * PIXEL..][..COLOR]);
+        alpha[i * alpha_len..][..alpha_len]
+            .copy_from_slice(&pixels[i * PIXEL + COLOR..][..alpha_len]);
+    }
+}
+
+/// `f` over the strips of `data`, cut on multiples of `unit` bytes, with
+/// whether each is the last; in parallel when there's more than one, outside
+/// `serial_strips`
+fn map_strips<T: Send>(
+    data: &[u8],
+    unit: usize,
+    f: impl Fn(&[u8], bool) -> Result<T> + Sync,
+) -> Result<Vec<T>> {
+    let strip_len = (STRIP_LEN / unit).max(1) * unit;
+    let count = data.len().div_ceil(strip_len).max(1);
+    let strip = |n: usize| {
+        let start = n * strip_len;
+        let end = (start + strip_len).min(data.len());
+        f(&data[start..end], n + 1 == count)
+    };
+    #[cfg(not(target_arch = "wasm32"))]
+    if count > 1 && !SERIAL.with(|serial| serial.get()) {
+        return (0..count).into_par_iter().map(strip).collect();
+    }
+    (0..count).map(strip).collect()
+}
+
+/// deflate blocks for `data`: the final block if `last`, otherwise ending in a
+/// sync flush so the next strip's blocks can follow
+fn deflate_strip(data: &[u8], last: bool, deflate: Deflate) -> Result<Strip> {
+    let mut compress = Compress::new(compression(deflate), false);
+    let mut deflated = Vec::with_capacity(data.len() / 2 + 64);
+    let flush = if last {
+        FlushCompress::Finish
+    } else {
+        FlushCompress::Sync
+    };
+    loop {
+        let consumed = compress.total_in() as usize;
+        let status = compress.compress_vec(&data[consumed..], &mut deflated, flush)?;
+        let all_in = compress.total_in() as usize == data.len();
+        // done once the flush had room to spare, or the stream ended
+        let done = match last {
+            true => status == Status::StreamEnd,
+            false => all_in && deflated.len() < deflated.capacity(),
+        };
+        if done {
+            break;
+        }
+        deflated.reserve(deflated.capacity().max(4096));
+    }
+    Ok(Strip {
+        deflated,
+        adler: adler32(data),
+        len: data.len(),
+    })
+}
+
+/// the zlib stream of strips: the header, their blocks in order, and the
+/// Adler-32 of all their input
+fn join(strips: Vec<Strip>, deflate: Deflate) -> Vec<u8> {
+    let len = strips.iter().map(|strip| strip.deflated.len()).sum::<usize>();
+    let mut out = Vec::with_capacity(2 + len + 4);
+    out.extend_from_slice(&zlib_header(deflate));
+    let mut adler = 1;
+    for strip in strips {
+        out.extend_from_slice(&strip.deflated);
+        adler = adler32_combine(adler, strip.adler, strip.len);
+    }
+    out.extend_from_slice(&adler.to_be_bytes());
+    out
+}
+
+const ADLER_BASE: u32 = 65521;
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    // the most bytes before the sums can overflow a u32
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= ADLER_BASE;
+        b %= ADLER_BASE;
+    }
+    b << 16 | a
+}
+
+/// the Adler-32 of two inputs one after the other, from theirs and the length
+/// of the second, as zlib's adler32_combine
+fn adler32_combine(adler1: u32, adler2: u32, len2: usize) -> u32 {
+    let base = ADLER_BASE as u64;
+    let rem = len2 as u64 % base;
+    let (a1, b1) = ((adler1 & 0xffff) as u64, (adler1 >> 16) as u64);
+    let (a2, b2) = ((adler2 & 0xffff) as u64, (adler2 >> 16) as u64);
+    let a = (a1 + a2 + base - 1) % base;
+    let b = (rem * a1 % base + b1 + b2 + base - rem) % base;
+    (b << 16 | a) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn inflate(data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        // the decoder checks the Adler-32 at the end
+        flate2::read::ZlibDecoder::new(data).read_to_end(&mut out).unwrap();
+        out
+    }
+
+    /// noise that deflate can't shrink much, so strips fill their buffers
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_f491u32;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                (state >> 24) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn strips_join_into_one_stream() {
+        for len in [0, 10, STRIP_LEN, 2 * STRIP_LEN + 12345] {
+            let data = noise(len);
+            for deflate in [Deflate::Fast, Deflate::Standard] {
+                let compressed = compress(&data, deflate).unwrap();
+                assert_eq!(inflate(&compressed), data, "{} bytes, {:?}", len, deflate);
+            }
+        }
+    }
+
+    #[test]
+    fn color_and_alpha_apart() {
+        // RGBA pixels over two strips, the second a short one
+        let pixels = noise(STRIP_LEN + 4 * 1001);
+        let (color, alpha) = compress_split(&pixels, 4, 3, Deflate::Fast).unwrap();
+        let (color, alpha) = (inflate(&color), inflate(&alpha));
+        assert_eq!(color.len(), pixels.len() / 4 * 3);
+        let joined: Vec<u8> = color
+            .chunks(3)
+            .zip(&alpha)
+            .flat_map(|(rgb, &a)| [rgb[0], rgb[1], rgb[2], a])
+            .collect();
+        assert!(joined == pixels);
+    }
+
+    #[test]
+    fn alpha_split_by_block_and_by_pixel() {
+        // past a whole number of blocks, so the tail is split too
+        let pixels = noise(8 * (SPLIT_BLOCK * 3 + 5));
+        for (pixel_len, color_len) in [(4, 3), (2, 1), (8, 6), (4, 2), (3, 2)] {
+            let pixels = &pixels[..pixels.len() / pixel_len * pixel_len];
+            // appended after what's there
+            let (mut color, mut alpha) = (vec![7], vec![9]);
+            split_alpha(pixels, pixel_len, color_len, &mut color, &mut alpha);
+            let count = pixels.len() / pixel_len;
+            assert_eq!(color.len(), 1 + count * color_len);
+            assert_eq!((color[0], alpha[0]), (7, 9));
+            for (n, pixel) in pixels.chunks_exact(pixel_len).enumerate() {
+                let alpha_len = pixel_len - color_len;
+                assert_eq!(&color[1 + n * color_len..][..color_len], &pixel[..color_len]);
+                assert_eq!(&alpha[1 + n * alpha_len..][..alpha_len], &pixel[color_len..]);
+            }
+        }
+    }
+
+    #[test]
+    fn adler_combines() {
+        let data = noise(100_000);
+        let (head, tail) = data.split_at(31_337);
+        let combined = adler32_combine(adler32(head), adler32(tail), tail.len());
+        assert_eq!(combined, adler32(&data));
+    }
+}
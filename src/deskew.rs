@@ -0,0 +1,205 @@
+use image::{DynamicImage, GrayImage};
+
+/// largest skew looked for, in degrees either way
+const MAX_ANGLE: f32 = 10.0;
+
+/// detection works on a copy at most this wide; skew doesn't need the detail
+const DETECT_WIDTH: u32 = 1200;
+
+/// dark pixels sampled at most when scoring an angle
+const MAX_POINTS: usize = 200_000;
+
+/// straighten a scan: find the angle its text lines run at and rotate the image
+/// back by it, filling the uncovered corners with white. returns the image and the
+/// correction in degrees (positive: the scan was turned counter-clockwise); images
+/// with no clear lines come back unchanged with 0. output is 8-bit
+pub fn deskew(img: DynamicImage) -> (DynamicImage, f32) {
+    let angle = detect_skew(&img);
+    if angle == 0.0 {
+        return (img, 0.0);
+    }
+    (rotate(&img, angle), angle)
+}
+
+/// skew of the text lines in degrees, counter-clockwise positive, or 0 when there
+/// is none worth correcting. projects the dark pixels onto lines at each candidate
+/// angle: at the right one, rows of text and the gaps between them line up, so the
+/// projection is most uneven
+pub fn detect_skew(img: &DynamicImage) -> f32 {
+    let mut gray = img.to_luma8();
+    if gray.width() > DETECT_WIDTH {
+        let height = (gray.height() as u64 * DETECT_WIDTH as u64 / gray.width() as u64).max(1);
+        gray = image::imageops::thumbnail(&gray, DETECT_WIDTH, height as u32);
+    }
+    let points = dark_points(&gray);
+    // too empty or too dark to be a page of text
+    let pixels = gray.width() as usize * gray.height() as usize;
+    if points.len() < 50 || points.len() > pixels / 2 {
+        return 0.0;
+    }
+    let (width, height) = (gray.width() as f32, gray.height() as f32);
+    let score = |degrees: f32| projection_score(&points, degrees, width + height);
+
+    let coarse = (-20..=20).map(|step| step as f32 * MAX_ANGLE / 20.0);
+    let (best, _) = best_angle(coarse, score);
+    let fine = (-10..=10).map(|step| best + step as f32 * 0.05);
+    let (best, best_score) = best_angle(fine, score);
+    // only turn the image when that lines things up clearly better
+    if best.abs() < 0.05 || best_score < score(0.0) * 1.02 {
+        return 0.0;
+    }
+    (best * 100.0).round() / 100.0
+}
+
+fn best_angle(angles: impl Iterator<Item = f32>, score: impl Fn(f32) -> f64) -> (f32, f64) {
+    angles
+        .map(|angle| (angle, score(angle)))
+        .fold((0.0, f64::MIN), |best, next| if next.1 > best.1 { next } else { best })
+}
+
+/// coordinates of pixels darker than the Otsu threshold, every nth one if there
+/// are many
+fn dark_points(gray: &GrayImage) -> Vec<(f32, f32)> {
+    let threshold = otsu_threshold(gray);
+    let dark = gray.pixels().filter(|p| p.0[0] < threshold).count();
+    let stride = dark.div_ceil(MAX_POINTS).max(1);
+    gray.enumerate_pixels()
+        .filter(|(_, _, p)| p.0[0] < threshold)
+        .step_by(stride)
+        .map(|(x, y, _)| (x as f32, y as f32))
+        .collect()
+}
+
+/// the gray level that best separates ink from paper
+fn otsu_threshold(gray: &GrayImage) -> u8 {
+    let mut histogram = [0u64; 256];
+    for p in gray.pixels() {
+        histogram[p.0[0] as usize] += 1;
+    }
+    let total: u64 = histogram.iter().sum();
+    let sum: f64 = histogram.iter().enumerate().map(|(v, &n)| v as f64 * n as f64).sum();
+    let (mut below, mut below_sum) = (0u64, 0f64);
+    let (mut best, mut best_variance) = (128u8, 0f64);
+    for (value, &count) in histogram.iter().enumerate() {
+        below += count;
+        below_sum += value as f64 * count as f64;
+        let above = total - below;
+        if below == 0 || above == 0 {
+            continue;
+        }
+        let mean_below = below_sum / below as f64;
+        let mean_above = (sum - below_sum) / above as f64;
+        let variance = below as f64 * above as f64 * (mean_below - mean_above).powi(2);
+        if variance > best_variance {
+            best_variance = variance;
+            best = value as u8 + 1;
+        }
+    }
+    best
+}
+
+/// sum of squared counts of points per line at `degrees`; higher is more uneven
+fn projection_score(points: &[(f32, f32)], degrees: f32, extent: f32) -> f64 {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let offset = extent;
+    let mut bins = vec![0u32; (extent * 3.0) as usize + 2];
+    for &(x, y) in points {
+        // lines rising to the right by `degrees` (y points down) map to one bin
+        let line = (x * sin + y * cos + offset) as usize;
+        if let Some(bin) = bins.get_mut(line) {
+            *bin += 1;
+        }
+    }
+    bins.iter().map(|&n| (n as f64).powi(2)).sum()
+}
+
+/// turn an image clockwise by `degrees` about its center, keeping its size;
+/// bilinear, with white (and opaque) filling in where nothing maps
+pub fn rotate(img: &DynamicImage, degrees: f32) -> DynamicImage {
+    let (width, height) = (img.width(), img.height());
+    match img {
+        DynamicImage::ImageLuma8(_) | DynamicImage::ImageLuma16(_) => {
+            let pixels = rotate_samples(img.to_luma8().as_raw(), width, height, 1, degrees);
+            DynamicImage::ImageLuma8(GrayImage::from_raw(width, height, pixels).unwrap())
+        }
+        DynamicImage::ImageLumaA8(_) | DynamicImage::ImageLumaA16(_) => {
+            let pixels = rotate_samples(img.to_luma_alpha8().as_raw(), width, height, 2, degrees);
+            let rotated = image::GrayAlphaImage::from_raw(width, height, pixels).unwrap();
+            DynamicImage::ImageLumaA8(rotated)
+        }
+        _ if img.color().has_alpha() => {
+            let pixels = rotate_samples(img.to_rgba8().as_raw(), width, height, 4, degrees);
+            DynamicImage::ImageRgba8(image::RgbaImage::from_raw(width, height, pixels).unwrap())
+        }
+        _ => {
+            let pixels = rotate_samples(img.to_rgb8().as_raw(), width, height, 3, degrees);
+            DynamicImage::ImageRgb8(image::RgbImage::from_raw(width, height, pixels).unwrap())
+        }
+    }
+}
+
+fn rotate_samples(src: &[u8], width: u32, height: u32, channels: usize, degrees: f32) -> Vec<u8> {
+    let (w, h) = (width as usize, height as usize);
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let (cx, cy) = ((width as f32 - 1.0) / 2.0, (height as f32 - 1.0) / 2.0);
+    let mut out = vec![255u8; src.len()];
+    let sample = |x: usize, y: usize, c: usize| src[(y * w + x) * channels + c] as f32;
+    for v in 0..h {
+        for u in 0..w {
+            let (du, dv) = (u as f32 - cx, v as f32 - cy);
+            // the source point this output pixel shows
+            let x = cx + du * cos + dv * sin;
+            let y = cy - du * sin + dv * cos;
+            if x < 0.0 || y < 0.0 || x > (w - 1) as f32 || y > (h - 1) as f32 {
+                continue;
+            }
+            let (x0, y0) = (x as usize, y as usize);
+            let (x1, y1) = ((x0 + 1).min(w - 1), (y0 + 1).min(h - 1));
+            let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+            for c in 0..channels {
+                let top = sample(x0, y0, c) * (1.0 - fx) + sample(x1, y0, c) * fx;
+                let bottom = sample(x0, y1, c) * (1.0 - fx) + sample(x1, y1, c) * fx;
+                out[(v * w + u) * channels + c] = (top * (1.0 - fy) + bottom * fy).round() as u8;
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a white page with rows of black "text"
+    fn page() -> DynamicImage {
+        let mut gray = GrayImage::from_pixel(400, 300, image::Luma([255]));
+        for row in (40..260).step_by(20) {
+            for y in row..row + 6 {
+                for x in 40..360 {
+                    if x % 11 < 8 {
+                        gray.put_pixel(x, y, image::Luma([0]));
+                    }
+                }
+            }
+        }
+        DynamicImage::ImageLuma8(gray)
+    }
+
+    #[test]
+    fn straight_page_is_left_alone() {
+        assert_eq!(detect_skew(&page()), 0.0);
+        assert_eq!(detect_skew(&DynamicImage::new_luma8(50, 50)), 0.0);
+    }
+
+    #[test]
+    fn finds_and_undoes_a_turn() {
+        for turn in [2.5f32, -4.0] {
+            // turning clockwise by -turn is a counter-clockwise skew of turn
+            let skewed = rotate(&page(), -turn);
+            let angle = detect_skew(&skewed);
+            assert!((angle - turn).abs() < 0.2, "found {} for {}", angle, turn);
+            let (straight, _) = deskew(skewed);
+            assert!(detect_skew(&straight).abs() < 0.2);
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use anyhow::{ensure, Context, Result};
+use lopdf::{dictionary, Document, Object, Stream};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::io::Cursor;
+
+use crate::parse::PngCompression;
+use crate::split::{self, encode_jpg, encode_png};
+
+/// one self-test's outcome: None if it passed, else what went wrong
+struct Check {
+    name: &'static str,
+    error: Option<String>,
+}
+
+/// `doctor --json`'s report
+#[derive(Serialize)]
+struct DoctorJson<'a> {
+    /// library name to version
+    versions: Map<String, Value>,
+    build: &'static str,
+    platform: String,
+    threads: usize,
+    cpus: usize,
+    decoders: &'a [&'a str],
+    encoders: &'a [&'a str],
+    features: &'a [&'a str],
+    self_tests: Vec<CheckJson<'a>>,
+}
+
+#[derive(Serialize)]
+struct CheckJson<'a> {
+    name: &'static str,
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>,
+}
+
+/// print what ovid was built with and runs on, and run a tiny render and
+/// encode of each kind; returns false if any of them failed
+pub fn run_doctor(json: bool) -> Result<bool> {
+    let libraries = [
+        ("ovid", env!("CARGO_PKG_VERSION").to_string()),
+        ("MuPDF", mupdf_version()),
+        ("libjpeg-turbo", env!("OVID_LIBJPEG_TURBO_VERSION").to_string()),
+        ("turbojpeg", env!("OVID_TURBOJPEG_VERSION").to_string()),
+        ("image", env!("OVID_IMAGE_VERSION").to_string()),
+        ("lopdf", env!("OVID_LOPDF_VERSION").to_string()),
+    ];
+    let build = if cfg!(debug_assertions) { "debug" } else { "release" };
+    let platform = format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH);
+    let threads = rayon::current_num_threads();
+    let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let (decoders, encoders) = codecs();
+    let features = [
+        "mimalloc allocator",
+        "MuPDF with system libjpeg",
+        "turbojpeg via pkg-config",
+        "http(s) inputs (rustls)",
+    ];
+    let checks = [
+        check("PNG encode/decode", png_roundtrip),
+        check("JPEG encode/decode", jpeg_roundtrip),
+        check("PDF render", pdf_render),
+    ];
+    let passed = checks.iter().all(|c| c.error.is_none());
+
+    if json {
+        let report = DoctorJson {
+            versions: libraries
+                .iter()
+                .map(|(name, version)| (name.to_string(), version.as_str().into()))
+                .collect(),
+            build,
+            platform,
+            threads,
+            cpus,
+            decoders: &decoders,
+            encoders: &encoders,
+            features: &features,
+            self_tests: checks
+                .iter()
+                .map(|c| CheckJson {
+                    name: c.name,
+                    ok: c.error.is_none(),
+                    error: c.error.as_deref(),
+                })
+                .collect(),
+        };
+        println!("{}", serde_json::to_string(&report)?);
+        return Ok(passed);
+    }
+
+    for (name, version) in &libraries {
+        println!("{:<16}{}", format!("{}:", name), version);
+    }
+    println!("{:<16}{}, {}", "Build:", build, platform);
+    println!("{:<16}{} ({} CPUs, set with -j)", "Threads:", threads, cpus);
+    println!("{:<16}{}", "Decoders:", decoders.join(", "));
+    println!("{:<16}{}", "Encoders:", encoders.join(", "));
+    println!("{:<16}{}", "Features:", features.join(", "));
+    println!();
+    println!("Self-tests:");
+    for c in &checks {
+        match &c.error {
+            None => println!("  {:<22}ok", c.name),
+            Some(error) => println!("  {:<22}FAILED: {}", c.name, error),
+        }
+    }
+    Ok(passed)
+}
+
+/// the MuPDF the mupdf-sys crate builds doesn't report its version, so name
+/// the crate it came with
+fn mupdf_version() -> String {
+    match env!("OVID_MUPDF_SYS_VERSION") {
+        "unknown" => "unknown".to_string(),
+        version => format!("bundled with mupdf-sys {}", version),
+    }
+}
+
+/// image formats the image crate can read and write in this build
+fn codecs() -> (Vec<&'static str>, Vec<&'static str>) {
+    let (mut decoders, mut encoders) = (Vec::new(), Vec::new());
+    for format in image::ImageFormat::all() {
+        let name = format.extensions_str().first().copied().unwrap_or("?");
+        if format.reading_enabled() {
+            decoders.push(name);
+        }
+        if format.writing_enabled() {
+            encoders.push(name);
+        }
+    }
+    (decoders, encoders)
+}
+
+fn check(name: &'static str, test: fn() -> Result<()>) -> Check {
+    Check {
+        name,
+        error: test().err().map(|e| format!("{:#}", e)),
+    }
+}
+
+/// 8x8 RGB test card: a black left half and a white right half
+fn test_card() -> Vec<u8> {
+    (0..8 * 8)
+        .flat_map(|i| if i % 8 < 4 { [0u8; 3] } else { [255u8; 3] })
+        .collect()
+}
+
+/// true if the decoded card still has a dark left and a light right
+fn card_intact(img: &image::RgbImage) -> bool {
+    img.dimensions() == (8, 8) && img.get_pixel(1, 4)[0] < 64 && img.get_pixel(6, 4)[0] > 192
+}
+
+fn png_roundtrip() -> Result<()> {
+    let mut data = Vec::new();
+    encode_png(&test_card(), 8, 8, false, PngCompression::Fast, &mut data)?;
+    let img = image::load_from_memory_with_format(&data, image::ImageFormat::Png)?;
+    ensure!(card_intact(&img.to_rgb8()), "decoded image differs");
+    Ok(())
+}
+
+fn jpeg_roundtrip() -> Result<()> {
+    let mut data = Vec::new();
+    encode_jpg(&test_card(), 8, 8, false, 90, &mut data)?;
+    let img = image::load_from_memory_with_format(&data, image::ImageFormat::Jpeg)?;
+    ensure!(card_intact(&img.to_rgb8()), "decoded image differs");
+    Ok(())
+}
+
+/// render a one-page PDF that fills its left half black
+fn pdf_render() -> Result<()> {
+    let mut doc = Document::with_version("1.4");
+    let pages_id = doc.new_object_id();
+    let content = Stream::new(dictionary! {}, b"0 g 0 0 4 8 re f".to_vec());
+    let content_id = doc.add_object(content);
+    let page_id = doc.add_object(dictionary! {
+        "Type" => "Page",
+        "Parent" => pages_id,
+        "MediaBox" => vec![0.into(), 0.into(), 8.into(), 8.into()],
+        "Contents" => content_id,
+    });
+    doc.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => vec![Object::Reference(page_id)],
+            "Count" => 1,
+        }),
+    );
+    let catalog_id = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+    doc.trailer.set("Root", catalog_id);
+    let mut bytes = Vec::new();
+    doc.save_to(&mut Cursor::new(&mut bytes))?;
+
+    let pdf = mupdf::Document::from_bytes(&bytes, "application/pdf")
+        .context("MuPDF cannot open a PDF")?;
+    let page = pdf.load_page(0)?;
+    let pixmap = page.to_pixmap(
+        &mupdf::Matrix::IDENTITY,
+        &mupdf::Colorspace::device_rgb(),
+        false,
+        true,
+    )?;
+    ensure!(
+        (pixmap.width(), pixmap.height()) == (8, 8),
+        "rendered {}x{} instead of 8x8",
+        pixmap.width(),
+        pixmap.height()
+    );
+    let img = image::RgbImage::from_raw(8, 8, split::packed_samples(&pixmap, 3)).context("short render")?;
+    ensure!(card_intact(&img), "rendered page differs");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn png_self_test_passes() {
+        png_roundtrip().unwrap();
+        let (decoders, encoders) = codecs();
+        assert!(decoders.contains(&"png") && encoders.contains(&"png"));
+    }
+}
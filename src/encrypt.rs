@@ -0,0 +1,623 @@
+use anyhow::{Context, Result};
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, ObjectStream, StringFormat};
+use std::path::Path;
+use tracing::info;
+
+use crate::crypto::{self, aes_cbc_decrypt, aes_cbc_encrypt, md5, rc4};
+use crate::pdf::{self, Encryption};
+
+/// padding for passwords of the RC4 and AES-128 security handlers (R2-R4)
+const PAD: [u8; 32] = [
+    0x28, 0xBF, 0x4E, 0x5E, 0x4E, 0x75, 0x8A, 0x41, 0x64, 0x00, 0x4E, 0x56, 0xFF, 0xFA, 0x01, 0x08,
+    0x2E, 0x2E, 0x00, 0xB6, 0xD0, 0x68, 0x3E, 0x80, 0x2F, 0x0C, 0xA9, 0xFE, 0x64, 0x53, 0x69, 0x7A,
+];
+
+/// object streams of an encrypted PDF are kept under this type until decrypted
+const HIDDEN_OBJSTM: &str = "OvidEncryptedObjStm";
+
+/// what readers may do with an encrypted PDF without the owner password
+#[derive(Debug, Clone, Copy)]
+pub struct Permissions {
+    pub print: bool,
+    pub modify: bool,
+    pub copy: bool,
+    /// add annotations and fill in forms
+    pub annotate: bool,
+}
+
+impl Permissions {
+    /// the /P flags; bits 1-2 must be clear and 7-8, 13-32 set. extraction for
+    /// accessibility (bit 10) is always allowed
+    fn bits(&self) -> u32 {
+        let mut p: u32 = 0xFFFF_F0C0 | 1 << 9;
+        if self.print {
+            p |= 1 << 2 | 1 << 11;
+        }
+        if self.modify {
+            p |= 1 << 3 | 1 << 10;
+        }
+        if self.copy {
+            p |= 1 << 4;
+        }
+        if self.annotate {
+            p |= 1 << 5 | 1 << 8;
+        }
+        p
+    }
+
+    /// read back /P flags
+    fn from_bits(p: u32) -> Self {
+        Permissions {
+            print: p & 1 << 2 != 0,
+            modify: p & 1 << 3 != 0,
+            copy: p & 1 << 4 != 0,
+            annotate: p & 1 << 5 != 0,
+        }
+    }
+
+    /// names of what isn't allowed
+    fn denied(&self) -> Vec<&'static str> {
+        [
+            (self.print, "printing"),
+            (self.modify, "editing"),
+            (self.copy, "copying"),
+            (self.annotate, "annotating"),
+        ]
+        .into_iter()
+        .filter(|&(allowed, _)| !allowed)
+        .map(|(_, name)| name)
+        .collect()
+    }
+}
+
+pub struct EncryptOptions<'a> {
+    /// needed to open the document; empty lets anyone open it
+    pub user_password: &'a str,
+    /// lifts the permission restrictions
+    pub owner_password: &'a str,
+    pub permissions: Permissions,
+    pub quiet: bool,
+}
+
+/// encrypt a PDF with AES-256 (PDF 2.0 security handler, revision 6)
+pub fn encrypt_pdf(input: &Path, output: &Path, opts: &EncryptOptions) -> Result<()> {
+    let start = std::time::Instant::now();
+    let mut doc = pdf::load(input)?;
+    if !opts.quiet {
+        info!("Encrypting {} -> {}", input.display(), output.display());
+    }
+    encrypt_document(&mut doc, opts)?;
+    pdf::save(&mut doc, output)?;
+    if !opts.quiet {
+        info!("Done. PDF saved in {:.2}s", start.elapsed().as_secs_f64());
+    }
+    Ok(())
+}
+
+/// write a decrypted copy of a PDF, given its user or owner password
+pub fn decrypt_pdf(input: &Path, output: &Path, password: &str, quiet: bool) -> Result<()> {
+    let start = std::time::Instant::now();
+    let (mut doc, encryption) = pdf::load_with_encryption(input)?;
+    match encryption {
+        Encryption::None => anyhow::bail!("{} is not encrypted", input.display()),
+        // already decrypted on load
+        Encryption::EmptyPassword => {}
+        Encryption::Locked => decrypt_document(&mut doc, password)
+            .with_context(|| format!("Cannot decrypt {}", input.display()))?,
+    }
+    if !quiet {
+        info!("Decrypting {} -> {}", input.display(), output.display());
+    }
+    pdf::save(&mut doc, output)?;
+    if !quiet {
+        info!("Done. PDF saved in {:.2}s", start.elapsed().as_secs_f64());
+    }
+    Ok(())
+}
+
+/// write a copy of a PDF without its permission restrictions (no printing, copying,
+/// ...). owner-locked files open without a password; files that also have a user
+/// password need the owner password
+pub fn unlock_pdf(input: &Path, output: &Path, password: &str, quiet: bool) -> Result<()> {
+    let start = std::time::Instant::now();
+    let mut doc = load_encrypted(input)?;
+    anyhow::ensure!(
+        doc.is_encrypted(),
+        "{} has no restrictions to remove",
+        input.display()
+    );
+    let permissions = doc
+        .get_encrypted()
+        .ok()
+        .and_then(|dict| dict.get(b"P").and_then(Object::as_i64).ok())
+        .map(|p| Permissions::from_bits(p as u32));
+    if let Err(e) = decrypt_document(&mut doc, password) {
+        if password.is_empty() {
+            anyhow::bail!(
+                "{} needs a password to open; give its owner password with --password",
+                input.display()
+            );
+        }
+        return Err(e.context(format!("Cannot unlock {}", input.display())));
+    }
+    if !quiet {
+        info!("Unlocking {} -> {}", input.display(), output.display());
+    }
+    pdf::save(&mut doc, output)?;
+    if !quiet {
+        match permissions.map(|p| p.denied()) {
+            Some(denied) if !denied.is_empty() => {
+                info!("  lifted restrictions: {}", denied.join(", "))
+            }
+            _ => info!("  no permission restrictions were set, encryption removed"),
+        }
+        info!("Done. PDF saved in {:.2}s", start.elapsed().as_secs_f64());
+    }
+    Ok(())
+}
+
+/// load an encrypted PDF; its object streams can only be unpacked once decrypted,
+/// so they are kept as plain streams for `decrypt_document`
+pub fn load_encrypted(path: &Path) -> Result<Document> {
+    fn hide_object_streams(id: ObjectId, obj: &mut Object) -> Option<(ObjectId, Object)> {
+        if let Object::Stream(stream) = obj {
+            if stream.dict.type_is(b"ObjStm") {
+                stream.dict.set("Type", HIDDEN_OBJSTM);
+            }
+        }
+        Some((id, obj.clone()))
+    }
+    Document::load_filtered(path, hide_object_streams)
+        .with_context(|| format!("Failed to open PDF {}", path.display()))
+}
+
+/// decrypt every string and stream in place, given the user or owner password;
+/// the document is left as it was if the password is wrong
+pub fn decrypt_document(doc: &mut Document, password: &str) -> Result<()> {
+    let handler = Handler::authenticate(doc, password)?;
+    let encrypt_id = doc.trailer.get(b"Encrypt").and_then(Object::as_reference).ok();
+
+    for (&id, obj) in doc.objects.iter_mut() {
+        if Some(id) == encrypt_id {
+            continue;
+        }
+        if let Object::Stream(stream) = obj {
+            // cross-reference streams are never encrypted
+            if stream.dict.type_is(b"XRef") {
+                continue;
+            }
+            if handler.metadata || !stream.dict.type_is(b"Metadata") {
+                let content = handler.decrypt(id, handler.streams, &stream.content);
+                stream.set_content(content);
+            }
+        }
+        crypt_strings(obj, &|data| Ok(handler.decrypt(id, handler.strings, data)))?;
+    }
+
+    // objects inside object streams were encrypted with their stream
+    let hidden: Vec<ObjectId> = doc
+        .objects
+        .iter()
+        .filter(|(_, obj)| obj.type_name().is_ok_and(|t| t == HIDDEN_OBJSTM))
+        .map(|(&id, _)| id)
+        .collect();
+    for id in hidden {
+        let Some(Object::Stream(mut stream)) = doc.objects.remove(&id) else { continue };
+        stream.dict.set("Type", "ObjStm");
+        let unpacked = ObjectStream::new(&mut stream)
+            .with_context(|| format!("Corrupt object stream {} {} R", id.0, id.1))?;
+        for (id, obj) in unpacked.objects {
+            doc.objects.entry(id).or_insert(obj);
+        }
+    }
+
+    if let Some(id) = encrypt_id {
+        doc.objects.remove(&id);
+    }
+    doc.trailer.remove(b"Encrypt");
+    Ok(())
+}
+
+/// encrypt every string and stream with AES-256 and add the /Encrypt dictionary
+fn encrypt_document(doc: &mut Document, opts: &EncryptOptions) -> Result<()> {
+    let key: [u8; 32] = random_bytes()?;
+    let salts: [u8; 32] = random_bytes()?;
+    let (user_salts, owner_salts) = salts.split_at(16);
+    let user = utf8_password(opts.user_password);
+    let owner = utf8_password(opts.owner_password);
+
+    let mut u = hash_r6(user, &user_salts[..8], &[]).to_vec();
+    u.extend_from_slice(user_salts);
+    let ue = aes_cbc_encrypt(&hash_r6(user, &user_salts[8..], &[]), &[0; 16], &key, false);
+    let mut o = hash_r6(owner, &owner_salts[..8], &u).to_vec();
+    o.extend_from_slice(owner_salts);
+    let oe = aes_cbc_encrypt(&hash_r6(owner, &owner_salts[8..], &u), &[0; 16], &key, false);
+
+    let p = opts.permissions.bits();
+    let mut perms = [0u8; 16];
+    perms[..4].copy_from_slice(&p.to_le_bytes());
+    perms[4..8].fill(0xFF);
+    perms[8..12].copy_from_slice(b"Tadb");
+    perms[12..].copy_from_slice(&random_bytes::<4>()?);
+    crypto::aes_encrypt_block(&key, &mut perms);
+
+    let handler = Handler {
+        key: key.to_vec(),
+        revision: 6,
+        strings: Method::Aes,
+        streams: Method::Aes,
+        metadata: true,
+    };
+    for (&id, obj) in doc.objects.iter_mut() {
+        // neither is written out by lopdf
+        if obj.type_name().is_ok_and(|t| t == "XRef" || t == "ObjStm") {
+            continue;
+        }
+        if let Object::Stream(stream) = obj {
+            let content = handler.encrypt(id, handler.streams, &stream.content)?;
+            stream.set_content(content);
+        }
+        crypt_strings(obj, &|data| handler.encrypt(id, handler.strings, data))?;
+    }
+
+    let hex = |bytes: &[u8]| Object::String(bytes.to_vec(), StringFormat::Hexadecimal);
+    let encrypt_id = doc.add_object(dictionary! {
+        "Filter" => "Standard",
+        "V" => 5,
+        "R" => 6,
+        "Length" => 256,
+        "CF" => dictionary! {
+            "StdCF" => dictionary! {
+                "AuthEvent" => "DocOpen",
+                "CFM" => "AESV3",
+                "Length" => 32,
+            },
+        },
+        "StmF" => "StdCF",
+        "StrF" => "StdCF",
+        "O" => hex(&o),
+        "U" => hex(&u),
+        "OE" => hex(&oe),
+        "UE" => hex(&ue),
+        "P" => p as i32 as i64,
+        "Perms" => hex(&perms),
+        "EncryptMetadata" => true,
+    });
+    doc.trailer.set("Encrypt", encrypt_id);
+    if doc.trailer.get(b"ID").is_err() {
+        let id: [u8; 16] = random_bytes()?;
+        doc.trailer.set("ID", vec![hex(&id), hex(&id)]);
+    }
+    // AES-256 is PDF 2.0, or 1.7 with Adobe extension level 8
+    if doc.version.parse::<f32>().unwrap_or(0.0) < 2.0 {
+        doc.version = "1.7".to_string();
+        doc.catalog_mut()?.set(
+            "Extensions",
+            dictionary! {
+                "ADBE" => dictionary! {
+                    "BaseVersion" => Object::Name(b"1.7".to_vec()),
+                    "ExtensionLevel" => 8,
+                },
+            },
+        );
+    }
+    Ok(())
+}
+
+/// how strings or streams are encrypted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Method {
+    Identity,
+    Rc4,
+    Aes,
+}
+
+/// the standard security handler, with the file key recovered from a password
+struct Handler {
+    key: Vec<u8>,
+    revision: i64,
+    strings: Method,
+    streams: Method,
+    /// whether the XMP metadata stream is encrypted too
+    metadata: bool,
+}
+
+impl Handler {
+    fn authenticate(doc: &Document, password: &str) -> Result<Handler> {
+        let dict = doc.get_encrypted().context("Missing /Encrypt dictionary")?;
+        let filter = dict.get(b"Filter").and_then(Object::as_name).unwrap_or(b"");
+        anyhow::ensure!(
+            filter == b"Standard",
+            "Unsupported security handler /{}",
+            String::from_utf8_lossy(filter)
+        );
+        let version = dict.get(b"V").and_then(Object::as_i64).unwrap_or(0);
+        let revision = dict.get(b"R").and_then(Object::as_i64).unwrap_or(0);
+        let metadata = dict.get(b"EncryptMetadata").and_then(Object::as_bool).unwrap_or(true);
+        let bytes = |key: &[u8]| -> Result<&[u8]> {
+            dict.get(key)
+                .and_then(Object::as_str)
+                .with_context(|| format!("Missing /{} in /Encrypt", String::from_utf8_lossy(key)))
+        };
+        let o = bytes(b"O")?;
+        let u = bytes(b"U")?;
+
+        let key = match revision {
+            2..=4 => {
+                let n = if revision == 2 {
+                    5
+                } else {
+                    (dict.get(b"Length").and_then(Object::as_i64).unwrap_or(40) / 8).clamp(5, 16)
+                        as usize
+                };
+                let p = dict.get(b"P").and_then(Object::as_i64).context("Missing /P")? as u32;
+                let id0 = doc
+                    .trailer
+                    .get(b"ID")
+                    .and_then(Object::as_array)
+                    .ok()
+                    .and_then(|ids| ids.first())
+                    .and_then(|id| id.as_str().ok())
+                    .unwrap_or(b"");
+                let legacy = LegacyParams {
+                    revision,
+                    n,
+                    o: &o[..o.len().min(32)],
+                    p,
+                    id0,
+                    metadata,
+                };
+                let password = latin1_password(password);
+                legacy
+                    .user_key(&password, u)
+                    .or_else(|| legacy.user_key(&legacy.user_password_from_owner(&password), u))
+                    .context("Incorrect password")?
+            }
+            5 | 6 => {
+                anyhow::ensure!(o.len() >= 48 && u.len() >= 48, "Invalid /O or /U in /Encrypt");
+                let password = utf8_password(password);
+                let hash = |salt: &[u8], udata: &[u8]| {
+                    if revision == 5 {
+                        crypto::sha256(&[password, salt, udata].concat())
+                    } else {
+                        hash_r6(password, salt, udata)
+                    }
+                };
+                let (encrypted_key, intermediate) = if hash(&o[32..40], &u[..48]) == o[..32] {
+                    (bytes(b"OE")?, hash(&o[40..48], &u[..48]))
+                } else if hash(&u[32..40], &[]) == u[..32] {
+                    (bytes(b"UE")?, hash(&u[40..48], &[]))
+                } else {
+                    anyhow::bail!("Incorrect password");
+                };
+                anyhow::ensure!(encrypted_key.len() >= 32, "Invalid /OE or /UE in /Encrypt");
+                aes_cbc_decrypt(&intermediate, &[0; 16], &encrypted_key[..32])
+            }
+            _ => anyhow::bail!("Unsupported encryption revision {}", revision),
+        };
+
+        let method = |filter: &[u8]| -> Result<Method> {
+            if version < 4 {
+                return Ok(Method::Rc4);
+            }
+            let name = dict.get(filter).and_then(Object::as_name).unwrap_or(b"Identity");
+            if name == b"Identity" {
+                return Ok(Method::Identity);
+            }
+            let cfm = dict
+                .get(b"CF")
+                .and_then(Object::as_dict)
+                .and_then(|cf| cf.get(name))
+                .and_then(Object::as_dict)
+                .and_then(|f| f.get(b"CFM"))
+                .and_then(Object::as_name)
+                .unwrap_or(b"None");
+            match cfm {
+                b"None" => Ok(Method::Identity),
+                b"V2" => Ok(Method::Rc4),
+                b"AESV2" | b"AESV3" => Ok(Method::Aes),
+                other => {
+                    anyhow::bail!("Unsupported crypt filter /{}", String::from_utf8_lossy(other))
+                }
+            }
+        };
+        Ok(Handler {
+            key,
+            revision,
+            strings: method(b"StrF")?,
+            streams: method(b"StmF")?,
+            metadata,
+        })
+    }
+
+    /// key for one object: the file key itself for AES-256, else derived from the
+    /// object number
+    fn object_key(&self, id: ObjectId, method: Method) -> Vec<u8> {
+        if self.revision >= 5 {
+            return self.key.clone();
+        }
+        let salt: &[u8] = if method == Method::Aes { b"sAlT" } else { b"" };
+        let hash = md5(&[&self.key, &id.0.to_le_bytes()[..3], &id.1.to_le_bytes(), salt]);
+        hash[..(self.key.len() + 5).min(16)].to_vec()
+    }
+
+    fn decrypt(&self, id: ObjectId, method: Method, data: &[u8]) -> Vec<u8> {
+        match method {
+            Method::Identity => data.to_vec(),
+            Method::Rc4 => rc4(&self.object_key(id, method), data),
+            Method::Aes => {
+                // a 16 byte IV, then PKCS#7 padded blocks
+                if data.len() < 32 {
+                    return Vec::new();
+                }
+                let iv: [u8; 16] = data[..16].try_into().unwrap();
+                let mut plain = aes_cbc_decrypt(&self.object_key(id, method), &iv, &data[16..]);
+                let pad = *plain.last().unwrap() as usize;
+                if (1..=16).contains(&pad) {
+                    plain.truncate(plain.len() - pad);
+                }
+                plain
+            }
+        }
+    }
+
+    fn encrypt(&self, id: ObjectId, method: Method, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(match method {
+            Method::Identity => data.to_vec(),
+            Method::Rc4 => rc4(&self.object_key(id, method), data),
+            Method::Aes => {
+                let iv: [u8; 16] = random_bytes()?;
+                let mut out = iv.to_vec();
+                out.extend(aes_cbc_encrypt(&self.object_key(id, method), &iv, data, true));
+                out
+            }
+        })
+    }
+}
+
+/// the /Encrypt entries the RC4 and AES-128 handlers derive keys from
+struct LegacyParams<'a> {
+    revision: i64,
+    /// key length in bytes
+    n: usize,
+    o: &'a [u8],
+    p: u32,
+    id0: &'a [u8],
+    metadata: bool,
+}
+
+impl LegacyParams<'_> {
+    /// the file key, if `password` is the user password
+    fn user_key(&self, password: &[u8], u: &[u8]) -> Option<Vec<u8>> {
+        let unencrypted_metadata: &[u8] = if self.revision >= 4 && !self.metadata {
+            &[0xFF; 4]
+        } else {
+            &[]
+        };
+        let mut hash = md5(&[
+            &pad_password(password),
+            self.o,
+            &self.p.to_le_bytes(),
+            self.id0,
+            unencrypted_metadata,
+        ]);
+        if self.revision >= 3 {
+            for _ in 0..50 {
+                hash = md5(&[&hash[..self.n]]);
+            }
+        }
+        let key = hash[..self.n].to_vec();
+
+        // /U is a known value encrypted with the key
+        let (expected, check) = if self.revision == 2 {
+            (rc4(&key, &PAD), 32)
+        } else {
+            let mut data = rc4(&key, &md5(&[&PAD, self.id0]));
+            for i in 1..=19u8 {
+                let round_key: Vec<u8> = key.iter().map(|b| b ^ i).collect();
+                data = rc4(&round_key, &data);
+            }
+            (data, 16)
+        };
+        (u.len() >= check && u[..check] == expected[..check]).then_some(key)
+    }
+
+    /// recover the user password from /O with the owner password
+    fn user_password_from_owner(&self, owner: &[u8]) -> Vec<u8> {
+        let mut hash = md5(&[&pad_password(owner)]);
+        if self.revision >= 3 {
+            for _ in 0..50 {
+                hash = md5(&[&hash]);
+            }
+        }
+        let key = &hash[..self.n];
+        if self.revision == 2 {
+            return rc4(key, self.o);
+        }
+        let mut data = self.o.to_vec();
+        for i in (0..=19u8).rev() {
+            let round_key: Vec<u8> = key.iter().map(|b| b ^ i).collect();
+            data = rc4(&round_key, &data);
+        }
+        data
+    }
+}
+
+/// apply `f` to every string in an object, including those nested in dictionaries
+/// and arrays and in a stream's dictionary
+fn crypt_strings(obj: &mut Object, f: &dyn Fn(&[u8]) -> Result<Vec<u8>>) -> Result<()> {
+    match obj {
+        Object::String(data, _) => *data = f(data)?,
+        Object::Array(items) => {
+            for item in items {
+                crypt_strings(item, f)?;
+            }
+        }
+        Object::Dictionary(dict) => crypt_dict_strings(dict, f)?,
+        Object::Stream(stream) => crypt_dict_strings(&mut stream.dict, f)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+fn crypt_dict_strings(dict: &mut Dictionary, f: &dyn Fn(&[u8]) -> Result<Vec<u8>>) -> Result<()> {
+    for (_, value) in dict.iter_mut() {
+        crypt_strings(value, f)?;
+    }
+    Ok(())
+}
+
+/// AES-256 password hash (ISO 32000-2, algorithm 2.B)
+fn hash_r6(password: &[u8], salt: &[u8], udata: &[u8]) -> [u8; 32] {
+    let mut k: Vec<u8> = crypto::sha256(&[password, salt, udata].concat()).to_vec();
+    let mut round = 0;
+    loop {
+        round += 1;
+        let k1 = [password, &k, udata].concat().repeat(64);
+        let iv: [u8; 16] = k[16..32].try_into().unwrap();
+        let e = aes_cbc_encrypt(&k[..16], &iv, &k1, false);
+        // the first 16 bytes as a big-endian number, mod 3
+        let sum: u32 = e[..16].iter().map(|&b| b as u32).sum();
+        k = match sum % 3 {
+            0 => crypto::sha256(&e).to_vec(),
+            1 => crypto::sha384(&e).to_vec(),
+            _ => crypto::sha512(&e).to_vec(),
+        };
+        if round >= 64 && *e.last().unwrap() as usize <= round - 32 {
+            break;
+        }
+    }
+    k[..32].try_into().unwrap()
+}
+
+/// AES-256 passwords are UTF-8, at most 127 bytes
+fn utf8_password(password: &str) -> &[u8] {
+    let bytes = password.as_bytes();
+    &bytes[..bytes.len().min(127)]
+}
+
+/// older handlers take PDFDocEncoding passwords, which match Latin-1 for
+/// printable characters; anything else is passed as UTF-8
+fn latin1_password(password: &str) -> Vec<u8> {
+    if password.chars().all(|c| (c as u32) < 256) {
+        password.chars().map(|c| c as u8).collect()
+    } else {
+        password.as_bytes().to_vec()
+    }
+}
+
+/// a password cut or padded to 32 bytes
+fn pad_password(password: &[u8]) -> [u8; 32] {
+    let len = password.len().min(32);
+    let mut padded = PAD;
+    padded[..len].copy_from_slice(&password[..len]);
+    padded[len..].copy_from_slice(&PAD[..32 - len]);
+    padded
+}
+
+fn random_bytes<const N: usize>() -> Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    getrandom::getrandom(&mut bytes)
+        .map_err(|e| anyhow::anyhow!("Cannot get random bytes for encryption: {}", e))?;
+    Ok(bytes)
+}
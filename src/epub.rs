@@ -0,0 +1,385 @@
+use anyhow::{bail, Context, Result};
+use chrono::{Local, SecondsFormat, Utc};
+use std::fmt::Write as _;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::archive;
+use crate::interrupt::Interrupted;
+use crate::merge::{MergeOptions, MergeReport};
+use crate::meta::xml_escape;
+use crate::parse::{Direction, InputFormat};
+use crate::pdf;
+use crate::progress::{self, Event};
+use crate::stats::RunStats;
+
+/// true for outputs that should be written as EPUB rather than PDF
+pub fn is_epub(output: &Path) -> bool {
+    output
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("epub"))
+}
+
+/// an image ready to go into the book
+struct PageImage {
+    data: Vec<u8>,
+    extension: &'static str,
+    media_type: &'static str,
+    width: u32,
+    height: u32,
+}
+
+/// write the images as a fixed-layout EPUB 3, one image per page sized to the
+/// image, read in `opts.direction`. PNG, JPEG and GIF go in unchanged, TIFF
+/// and BMP as PNG. the whole book is assembled in memory
+pub fn write_epub(
+    images: &[PathBuf],
+    output: &Path,
+    opts: &MergeOptions,
+) -> Result<MergeReport> {
+    check_options(opts)?;
+    if !opts.quiet {
+        info!("Merging {} image(s) -> {}", images.len(), output.display());
+    }
+    let start = std::time::Instant::now();
+
+    let mut pages = Vec::with_capacity(images.len());
+    let mut skipped = 0;
+    let total = images.len();
+    progress::emit(&opts.progress, Event::Start { total });
+    for (i, path) in images.iter().enumerate() {
+        if opts.cancel.is_cancelled() {
+            return Err(Interrupted {
+                done: i,
+                total,
+                files: Vec::new(),
+            }
+            .into());
+        }
+        let (page, done) = (i + 1, i + 1);
+        progress::emit(&opts.progress, Event::PageStarted { page, total });
+        match load_image(path) {
+            Ok(image) => {
+                pages.push(image);
+                let file = Some(path.as_path());
+                progress::emit(&opts.progress, Event::PageDone { page, done, total, file });
+            }
+            Err(error) => {
+                let failed = Event::PageFailed {
+                    page,
+                    done,
+                    total,
+                    error: &error,
+                };
+                progress::emit(&opts.progress, failed);
+                if !opts.skip_errors {
+                    return Err(error);
+                }
+                let message = format!("skipping {}: {:#}", path.display(), error);
+                progress::warn(&opts.progress, Some(page), &message);
+                skipped += 1;
+            }
+        }
+    }
+    anyhow::ensure!(!pages.is_empty(), "No readable images to put in {}", output.display());
+
+    let title = opts.title.clone().unwrap_or_else(|| {
+        let stem = output.file_stem().unwrap_or_default();
+        stem.to_string_lossy().into_owned()
+    });
+    let mut entries: Vec<(String, Vec<u8>)> = vec![
+        // must come first, so readers can tell the format from the start of the file
+        ("mimetype".to_string(), b"application/epub+zip".to_vec()),
+        ("META-INF/container.xml".to_string(), CONTAINER.as_bytes().to_vec()),
+        ("OEBPS/content.opf".to_string(), package(&pages, &title, opts)?.into_bytes()),
+        ("OEBPS/nav.xhtml".to_string(), nav(pages.len(), &title, opts).into_bytes()),
+    ];
+    let count = pages.len();
+    for (i, page) in pages.into_iter().enumerate() {
+        let name = page_name(i);
+        let xhtml = page_xhtml(&name, &page, i + 1);
+        entries.push((format!("OEBPS/{}.xhtml", name), xhtml.into_bytes()));
+        entries.push((format!("OEBPS/images/{}.{}", name, page.extension), page.data));
+    }
+    pdf::write_output(output, |mut out| archive::write_stored(entries, &mut out))?;
+    let finish = Event::Finish {
+        saved: count,
+        failed: skipped,
+    };
+    progress::emit(&opts.progress, finish);
+
+    if !opts.quiet {
+        info!(
+            "Done. EPUB with {} page(s) saved in {:.2}s",
+            count,
+            start.elapsed().as_secs_f64()
+        );
+    }
+    Ok(MergeReport {
+        files: vec![output.to_path_buf()],
+        pages: count,
+        skipped,
+        stats: RunStats::default(),
+    })
+}
+
+/// refuse the merge options that only make sense for PDF pages
+fn check_options(opts: &MergeOptions) -> Result<()> {
+    let pdf_only = [
+        ("--pagesize", opts.pagesize.is_some()),
+        ("--scale", opts.scale.is_some()),
+        ("--watermark-text/--watermark-image", opts.watermark.is_some()),
+        ("--page-numbers", opts.page_numbers.is_some()),
+        ("--page-layout", opts.page_layout.is_some()),
+        ("--page-mode", opts.page_mode.is_some()),
+        ("--max-pages/--max-size", opts.max_pages.is_some() || opts.max_size.is_some()),
+        ("--gray", opts.gray),
+        ("--deskew", opts.deskew),
+        ("--keep-exif", opts.keep_exif),
+        ("--placeholder", opts.placeholder),
+    ];
+    for (name, set) in pdf_only {
+        if set {
+            bail!("{} only applies to PDF output, not EPUB", name);
+        }
+    }
+    Ok(())
+}
+
+/// read an image, converting formats EPUB readers needn't support to PNG
+fn load_image(path: &Path) -> Result<PageImage> {
+    let data = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let format = InputFormat::sniff(&data).context("Unsupported image format")?;
+    let reader = image::ImageReader::new(Cursor::new(&data)).with_guessed_format()?;
+    let (extension, media_type) = match format {
+        InputFormat::Png => ("png", "image/png"),
+        InputFormat::Jpeg => ("jpg", "image/jpeg"),
+        InputFormat::Gif => ("gif", "image/gif"),
+        InputFormat::Tiff | InputFormat::Bmp => {
+            let img = reader.decode().context("Failed to decode image")?;
+            let mut png = Vec::new();
+            img.write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+                .context("Failed to encode PNG")?;
+            return Ok(PageImage {
+                width: img.width(),
+                height: img.height(),
+                data: png,
+                extension: "png",
+                media_type: "image/png",
+            });
+        }
+    };
+    let (width, height) = reader
+        .into_dimensions()
+        .context("Failed to read image size")?;
+    Ok(PageImage {
+        data,
+        extension,
+        media_type,
+        width,
+        height,
+    })
+}
+
+/// base name of page `index`'s XHTML file and image: page_0001
+fn page_name(index: usize) -> String {
+    format!("page_{:04}", index + 1)
+}
+
+const CONTAINER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>
+"#;
+
+/// the package document: metadata, every file in the book, and the page order
+fn package(pages: &[PageImage], title: &str, opts: &MergeOptions) -> Result<String> {
+    let date = opts.date.unwrap_or_else(|| Local::now().fixed_offset());
+    let modified = date.with_timezone(&Utc).to_rfc3339_opts(SecondsFormat::Secs, true);
+    // facing pages are read first on the left, or first on the right
+    let (direction, first, second) = match opts.direction {
+        Direction::Ltr => ("ltr", "left", "right"),
+        Direction::Rtl => ("rtl", "right", "left"),
+    };
+    let mut opf = String::new();
+    writeln!(opf, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        opf,
+        r#"<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="id">"#
+    )?;
+    writeln!(opf, r#"  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">"#)?;
+    writeln!(opf, r#"    <dc:identifier id="id">urn:uuid:{}</dc:identifier>"#, uuid()?)?;
+    writeln!(opf, "    <dc:title>{}</dc:title>", xml_escape(title))?;
+    if let Some(author) = &opts.author {
+        writeln!(opf, "    <dc:creator>{}</dc:creator>", xml_escape(author))?;
+    }
+    writeln!(opf, "    <dc:language>und</dc:language>")?;
+    writeln!(opf, r#"    <meta property="dcterms:modified">{}</meta>"#, modified)?;
+    writeln!(opf, r#"    <meta property="rendition:layout">pre-paginated</meta>"#)?;
+    writeln!(opf, r#"    <meta property="rendition:spread">landscape</meta>"#)?;
+    writeln!(opf, r#"    <meta name="cover" content="img_0001"/>"#)?;
+    writeln!(opf, "  </metadata>")?;
+    writeln!(opf, "  <manifest>")?;
+    opf.push_str(concat!(
+        r#"    <item id="nav" href="nav.xhtml" media-type="application/xhtml+xml""#,
+        r#" properties="nav"/>"#,
+        "\n"
+    ));
+    for (i, page) in pages.iter().enumerate() {
+        let name = page_name(i);
+        writeln!(
+            opf,
+            r#"    <item id="p_{n}" href="{name}.xhtml" media-type="application/xhtml+xml"/>"#,
+            n = i + 1,
+            name = name,
+        )?;
+        let cover = if i == 0 { r#" properties="cover-image""# } else { "" };
+        writeln!(
+            opf,
+            r#"    <item id="img_{n:04}" href="images/{name}.{ext}" media-type="{media}"{cover}/>"#,
+            n = i + 1,
+            name = name,
+            ext = page.extension,
+            media = page.media_type,
+            cover = cover,
+        )?;
+    }
+    writeln!(opf, "  </manifest>")?;
+    writeln!(opf, r#"  <spine page-progression-direction="{}">"#, direction)?;
+    for i in 0..pages.len() {
+        // the cover stands alone and the pages after it pair up
+        let spread = match i {
+            0 => "rendition:page-spread-center".to_string(),
+            _ if i % 2 == 1 => format!("page-spread-{}", first),
+            _ => format!("page-spread-{}", second),
+        };
+        writeln!(opf, r#"    <itemref idref="p_{}" properties="{}"/>"#, i + 1, spread)?;
+    }
+    writeln!(opf, "  </spine>")?;
+    writeln!(opf, "</package>")?;
+    Ok(opf)
+}
+
+/// the navigation document: the outline if one was given, else the first page
+fn nav(count: usize, title: &str, opts: &MergeOptions) -> String {
+    let mut items = String::new();
+    let entries: Vec<(&str, usize)> = opts
+        .bookmarks
+        .iter()
+        .filter_map(|b| Some((b.title.as_str(), b.page?)))
+        .filter(|&(_, page)| (1..=count).contains(&page))
+        .collect();
+    let entries = if entries.is_empty() { vec![(title, 1)] } else { entries };
+    for (text, page) in entries {
+        let _ = writeln!(
+            items,
+            r#"      <li><a href="{}.xhtml">{}</a></li>"#,
+            page_name(page - 1),
+            xml_escape(text)
+        );
+    }
+    let dir = match opts.direction {
+        Direction::Ltr => "ltr",
+        Direction::Rtl => "rtl",
+    };
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops" dir="{dir}">
+<head><title>{title}</title></head>
+<body>
+  <nav epub:type="toc" id="toc">
+    <ol>
+{items}    </ol>
+  </nav>
+</body>
+</html>
+"#,
+        dir = dir,
+        title = xml_escape(title),
+        items = items
+    )
+}
+
+/// a page: just its image, filling a viewport of the image's size
+fn page_xhtml(name: &str, page: &PageImage, number: usize) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml">
+<head>
+<title>Page {number}</title>
+<meta name="viewport" content="width={w}, height={h}"/>
+<style>html, body {{ margin: 0; padding: 0; }} img {{ display: block; }}</style>
+</head>
+<body>
+<img src="images/{name}.{ext}" width="{w}" height="{h}" alt="Page {number}"/>
+</body>
+</html>
+"#,
+        number = number,
+        w = page.width,
+        h = page.height,
+        name = name,
+        ext = page.extension
+    )
+}
+
+/// a random (version 4) UUID for the book's identifier
+fn uuid() -> Result<String> {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes)
+        .map_err(|e| anyhow::anyhow!("Cannot get random bytes for the book id: {}", e))?;
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    Ok(format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page() -> PageImage {
+        PageImage {
+            data: Vec::new(),
+            extension: "jpg",
+            media_type: "image/jpeg",
+            width: 800,
+            height: 1200,
+        }
+    }
+
+    #[test]
+    fn right_to_left_spine() {
+        let opts = MergeOptions {
+            direction: Direction::Rtl,
+            ..Default::default()
+        };
+        let opf = package(&[page(), page(), page()], "Vol. 1 & 2", &opts).unwrap();
+        assert!(opf.contains(r#"<spine page-progression-direction="rtl">"#));
+        assert!(opf.contains("<dc:title>Vol. 1 &amp; 2</dc:title>"));
+        assert!(opf.contains(r#"idref="p_1" properties="rendition:page-spread-center""#));
+        // facing pages: the first on the right when read right to left
+        assert!(opf.contains(r#"idref="p_2" properties="page-spread-right""#));
+        assert!(opf.contains(r#"idref="p_3" properties="page-spread-left""#));
+        assert!(opf.contains(r#"href="images/page_0001.jpg" media-type="image/jpeg" "#));
+    }
+
+    #[test]
+    fn uuid_format() {
+        let id = uuid().unwrap();
+        assert_eq!(id.len(), 36);
+        assert_eq!(&id[14..15], "4");
+    }
+}
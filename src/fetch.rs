@@ -0,0 +1,161 @@
+use anyhow::{bail, ensure, Context, Result};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::parse::ByteSize;
+use crate::tmp;
+
+/// times a broken download is picked up again where it stopped
+const RETRIES: usize = 3;
+
+/// true for inputs given as http:// or https:// URLs
+pub fn is_url(input: &Path) -> bool {
+    let s = input.to_string_lossy();
+    s.starts_with("http://") || s.starts_with("https://")
+}
+
+/// run `f` with every URL among `inputs` downloaded to a temporary directory,
+/// removed afterwards, and replaced by the local copy; other inputs are passed
+/// through. downloads larger than `max_size` bytes are refused
+pub fn with_local_inputs<T>(
+    inputs: &[PathBuf],
+    max_size: u64,
+    quiet: bool,
+    f: impl FnOnce(Vec<PathBuf>) -> Result<T>,
+) -> Result<T> {
+    if !inputs.iter().any(|input| is_url(input)) {
+        return f(inputs.to_vec());
+    }
+    tmp::with_scratch_dir("fetch", |dir| {
+        let mut local = Vec::with_capacity(inputs.len());
+        for (n, input) in inputs.iter().enumerate() {
+            if !is_url(input) {
+                local.push(input.clone());
+                continue;
+            }
+            let url = input.to_string_lossy();
+            // a directory per download keeps the file's own name
+            let sub = dir.join((n + 1).to_string());
+            std::fs::create_dir(&sub)?;
+            let path = sub.join(file_name(&url));
+            let size = download(&url, &path, max_size)?;
+            if !quiet {
+                info!("Downloaded {} ({})", url, ByteSize(size));
+            }
+            local.push(path);
+        }
+        f(local)
+    })
+}
+
+/// a safe local name for what a URL points at: its last path segment
+fn file_name(url: &str) -> String {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let rest = path.split_once("://").map_or(path, |(_, rest)| rest);
+    let last = rest.split_once('/').map_or("", |(_, path)| path).rsplit('/').next();
+    let name: String = last
+        .unwrap_or_default()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || "._-".contains(c) { c } else { '_' })
+        .collect();
+    if name.trim_matches(['.', '_']).is_empty() {
+        "download".to_string()
+    } else {
+        name
+    }
+}
+
+/// download `url` to `dest`; a connection that drops is resumed with a Range
+/// request, or restarted if the server doesn't do ranges. returns the size
+fn download(url: &str, dest: &Path, max_size: u64) -> Result<u64> {
+    let agent = ureq::AgentBuilder::new()
+        .timeout_connect(Duration::from_secs(30))
+        .timeout_read(Duration::from_secs(60))
+        .build();
+    let mut file =
+        File::create(dest).with_context(|| format!("Cannot create {}", dest.display()))?;
+    let too_big = || format!("{} is larger than the {} download limit", url, ByteSize(max_size));
+    let mut have = 0u64;
+    let mut failures = 0;
+    loop {
+        let mut request = agent.get(url);
+        if have > 0 {
+            request = request.set("Range", &format!("bytes={}-", have));
+        }
+        let response = match request.call() {
+            Ok(response) => response,
+            Err(ureq::Error::Status(code, response)) => {
+                bail!("Failed to download {}: HTTP {} {}", url, code, response.status_text())
+            }
+            Err(e) if failures < RETRIES => {
+                failures += 1;
+                warn!("{}: {}, retrying", url, e);
+                std::thread::sleep(Duration::from_secs(failures as u64));
+                continue;
+            }
+            Err(e) => return Err(e).with_context(|| format!("Failed to download {}", url)),
+        };
+        if have > 0 && response.status() != 206 {
+            // the server sent the whole file again
+            have = 0;
+            file.set_len(0)?;
+            file.seek(SeekFrom::Start(0))?;
+        }
+        let length: Option<u64> = response.header("Content-Length").and_then(|v| v.parse().ok());
+        if let Some(length) = length {
+            ensure!(have + length <= max_size, "{}", too_big());
+        }
+        let expected = length.map(|length| have + length);
+
+        let mut reader = response.into_reader();
+        let mut buf = vec![0u8; 64 << 10];
+        let broken = loop {
+            match reader.read(&mut buf) {
+                Ok(0) => break None,
+                Ok(n) => {
+                    have += n as u64;
+                    ensure!(have <= max_size, "{}", too_big());
+                    file.write_all(&buf[..n])
+                        .with_context(|| format!("Failed to write {}", dest.display()))?;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => break Some(e.to_string()),
+            }
+        };
+        // a body that ends early without an error is broken too
+        let broken = broken.or_else(|| {
+            expected.filter(|&expected| have < expected).map(|expected| {
+                format!("connection closed after {} of {} bytes", have, expected)
+            })
+        });
+        match broken {
+            None => break,
+            Some(e) if failures < RETRIES => {
+                failures += 1;
+                warn!("{}: {}, resuming", url, e);
+            }
+            Some(e) => bail!("Failed to download {}: {}", url, e),
+        }
+    }
+    file.flush()?;
+    Ok(have)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn url_file_names() {
+        assert_eq!(file_name("https://example.com/scans/page%201.png?x=1"), "page_201.png");
+        assert_eq!(file_name("http://example.com/report.pdf#page=2"), "report.pdf");
+        assert_eq!(file_name("https://example.com/"), "download");
+        assert_eq!(file_name("https://example.com"), "download");
+        assert_eq!(file_name("https://example.com/.."), "download");
+        assert!(is_url(Path::new("https://example.com/a.pdf")));
+        assert!(!is_url(Path::new("scans/http.png")));
+    }
+}
@@ -0,0 +1,372 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::merge::{self, MergeOptions};
+use crate::parse::{ImageFormat, PageSize};
+use crate::progress::Progress;
+use crate::split::{self, SplitOptions};
+
+/// progress callback: pages done, pages in total, and the caller's pointer
+pub type ProgressFn = Option<unsafe extern "C" fn(usize, usize, *mut c_void)>;
+
+/// the caller's callback and pointer; calls are serialized through the mutex,
+/// so the callback needn't be thread-safe, only callable from any thread
+struct Callback {
+    f: unsafe extern "C" fn(usize, usize, *mut c_void),
+    user_data: *mut c_void,
+}
+
+// SAFETY: the pointer is only handed back to the callback, one call at a time
+unsafe impl Send for Callback {}
+
+fn progress(f: ProgressFn, user_data: *mut c_void) -> Option<Progress> {
+    let callback = Mutex::new(Callback { f: f?, user_data });
+    Some(Progress::new(move |done, total| {
+        let callback = callback.lock().unwrap_or_else(|e| e.into_inner());
+        // SAFETY: the caller promised a valid callback for the length of the run
+        unsafe { (callback.f)(done, total, callback.user_data) }
+    }))
+}
+
+/// render the pages of the PDF at `input` to images in `output_dir`, as
+/// `ovid split` does. `format` is "png" or "jpg" (NULL: png), `dpi` 0 means
+/// 300, `pages` a selection like "1,3-5" (NULL: all) and `quality` the JPEG
+/// quality (0: 75). returns 0, or -1 with a message in `*error` if `error`
+/// isn't NULL, to be freed with `ovid_string_free`
+///
+/// # Safety
+///
+/// strings must be NUL-terminated UTF-8 or NULL where allowed, `error` NULL or
+/// writable, and `progress` callable from any thread until the call returns
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn ovid_split(
+    input: *const c_char,
+    output_dir: *const c_char,
+    format: *const c_char,
+    dpi: u32,
+    pages: *const c_char,
+    quality: u8,
+    gray: bool,
+    progress_fn: ProgressFn,
+    user_data: *mut c_void,
+    error: *mut *mut c_char,
+) -> c_int {
+    run(error, || {
+        let input = PathBuf::from(string(input).context("input is NULL")?);
+        let output_dir = PathBuf::from(string(output_dir).context("output_dir is NULL")?);
+        let opts = SplitOptions {
+            format: match string(format) {
+                Some(s) => value(&s, "format")?,
+                None => ImageFormat::Png,
+            },
+            dpi: if dpi == 0 { 300 } else { dpi },
+            pages: string(pages),
+            quality: if quality == 0 { 75 } else { quality.min(100) },
+            gray,
+            quiet: true,
+            progress: progress(progress_fn, user_data),
+            ..Default::default()
+        };
+        anyhow::ensure!((72..=2400).contains(&opts.dpi), "dpi must be 72-2400");
+        split::split_pdf(&input, &output_dir, &opts).map(drop)
+    })
+}
+
+/// build a PDF (or, for an output ending in .epub, an EPUB) from `count`
+/// image paths, as `ovid merge` does. `pagesize` is "a4", "letter", "legal" or
+/// "a3" (NULL: pages sized to the images) and `dpi` 0 takes each image's own.
+/// returns 0, or -1 with a message in `*error` as for `ovid_split`
+///
+/// # Safety
+///
+/// `images` must point to `count` NUL-terminated UTF-8 strings, the other
+/// strings be NUL-terminated UTF-8 or NULL where allowed, `error` NULL or
+/// writable, and `progress` callable until the call returns
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn ovid_merge(
+    images: *const *const c_char,
+    count: usize,
+    output: *const c_char,
+    pagesize: *const c_char,
+    dpi: u32,
+    progress_fn: ProgressFn,
+    user_data: *mut c_void,
+    error: *mut *mut c_char,
+) -> c_int {
+    run(error, || {
+        anyhow::ensure!(!images.is_null() && count > 0, "No input images provided");
+        let images = std::slice::from_raw_parts(images, count)
+            .iter()
+            .map(|&path| string(path).map(PathBuf::from).context("image path is NULL"))
+            .collect::<Result<Vec<_>>>()?;
+        let output = PathBuf::from(string(output).context("output is NULL")?);
+        let opts = MergeOptions {
+            pagesize: string(pagesize)
+                .map(|s| value::<PageSize>(&s, "pagesize"))
+                .transpose()?,
+            dpi: (dpi > 0).then_some(dpi),
+            quiet: true,
+            max_pixels: Some(200_000_000),
+            progress: progress(progress_fn, user_data),
+            ..Default::default()
+        };
+        merge::merge_images(&images, &output, &opts).map(drop)
+    })
+}
+
+/// free an error string returned by ovid; NULL is ignored
+///
+/// # Safety
+///
+/// `s` must be NULL or a string from ovid not freed before
+#[no_mangle]
+pub unsafe extern "C" fn ovid_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// run `f`, turning an error or a panic into -1 and a message in `*error`
+unsafe fn run(error: *mut *mut c_char, f: impl FnOnce() -> Result<()>) -> c_int {
+    if !error.is_null() {
+        *error = std::ptr::null_mut();
+    }
+    let message = match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => return 0,
+        Ok(Err(e)) => format!("{:#}", e),
+        Err(panic) => match panic.downcast_ref::<&str>() {
+            Some(s) => format!("panic: {}", s),
+            None => match panic.downcast_ref::<String>() {
+                Some(s) => format!("panic: {}", s),
+                None => "panic".to_string(),
+            },
+        },
+    };
+    if !error.is_null() {
+        let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+        *error = message.into_raw();
+    }
+    -1
+}
+
+/// a C string argument, None for NULL
+unsafe fn string(s: *const c_char) -> Option<String> {
+    (!s.is_null()).then(|| CStr::from_ptr(s).to_string_lossy().into_owned())
+}
+
+fn value<T: ValueEnum>(s: &str, name: &str) -> Result<T> {
+    T::from_str(s, true).map_err(|_| anyhow::anyhow!("invalid {}: {}", name, s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    unsafe extern "C" fn count_pages(done: usize, total: usize, user_data: *mut c_void) {
+        let seen = &mut *(user_data as *mut Vec<(usize, usize)>);
+        seen.push((done, total));
+    }
+
+    #[test]
+    fn merge_from_c() {
+        let dir = std::env::temp_dir().join("ovid_test_ffi_merge");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let image = dir.join("page.png");
+        image::RgbImage::new(4, 4).save(&image).unwrap();
+        let image = CString::new(image.to_str().unwrap()).unwrap();
+        let images = [image.as_ptr(), image.as_ptr()];
+        let output = CString::new(dir.join("out.pdf").to_str().unwrap()).unwrap();
+        let a4 = CString::new("a4").unwrap();
+
+        let mut seen: Vec<(usize, usize)> = Vec::new();
+        let mut error = std::ptr::null_mut();
+        let status = unsafe {
+            ovid_merge(
+                images.as_ptr(),
+                images.len(),
+                output.as_ptr(),
+                a4.as_ptr(),
+                0,
+                Some(count_pages),
+                &mut seen as *mut _ as *mut c_void,
+                &mut error,
+            )
+        };
+        assert_eq!(status, 0);
+        assert!(error.is_null());
+        assert_eq!(seen, [(1, 2), (2, 2)]);
+
+        let bad = CString::new("a9").unwrap();
+        let status = unsafe {
+            ovid_merge(
+                images.as_ptr(),
+                images.len(),
+                output.as_ptr(),
+                bad.as_ptr(),
+                0,
+                None,
+                std::ptr::null_mut(),
+                &mut error,
+            )
+        };
+        assert_eq!(status, -1);
+        let message = unsafe { CStr::from_ptr(error) }.to_str().unwrap().to_string();
+        unsafe { ovid_string_free(error) };
+        assert_eq!(message, "invalid pagesize: a9");
+    }
+
+    #[test]
+    fn split_from_c() {
+        let dir = std::env::temp_dir().join("ovid_test_ffi_split");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let image = dir.join("page.png");
+        image::RgbImage::new(4, 4).save(&image).unwrap();
+        let pdf = dir.join("doc.pdf");
+        let opts = MergeOptions::default().quiet(true);
+        merge::merge_images(&[image.clone(), image], &pdf, &opts).unwrap();
+        let input = CString::new(pdf.to_str().unwrap()).unwrap();
+        let output = CString::new(dir.join("pages").to_str().unwrap()).unwrap();
+        let jpg = CString::new("jpg").unwrap();
+
+        let mut seen: Vec<(usize, usize)> = Vec::new();
+        let mut error = std::ptr::null_mut();
+        let status = unsafe {
+            ovid_split(
+                input.as_ptr(),
+                output.as_ptr(),
+                jpg.as_ptr(),
+                72,
+                std::ptr::null(),
+                0,
+                true,
+                Some(count_pages),
+                &mut seen as *mut _ as *mut c_void,
+                &mut error,
+            )
+        };
+        assert_eq!(status, 0);
+        assert!(error.is_null());
+        seen.sort();
+        assert_eq!(seen, [(1, 2), (2, 2)]);
+        assert!(dir.join("pages/doc_0001.jpg").exists() && dir.join("pages/doc_0002.jpg").exists());
+
+        let status = unsafe {
+            ovid_split(
+                input.as_ptr(),
+                output.as_ptr(),
+                std::ptr::null(),
+                10,
+                std::ptr::null(),
+                0,
+                false,
+                None,
+                std::ptr::null_mut(),
+                &mut error,
+            )
+        };
+        assert_eq!(status, -1);
+        let message = unsafe { CStr::from_ptr(error) }.to_str().unwrap().to_string();
+        unsafe { ovid_string_free(error) };
+        assert_eq!(message, "dpi must be 72-2400");
+    }
+
+    #[test]
+    fn panics_become_errors() {
+        let mut error = std::ptr::null_mut();
+        let status = unsafe { run(&mut error, || panic!("page {} broke", 3)) };
+        assert_eq!(status, -1);
+        let message = unsafe { CStr::from_ptr(error) }.to_str().unwrap().to_string();
+        unsafe { ovid_string_free(error) };
+        assert_eq!(message, "panic: page 3 broke");
+        // without anywhere to put the message
+        assert_eq!(unsafe { run(std::ptr::null_mut(), || panic!("quietly")) }, -1);
+    }
+
+    /// a C header's declarations as tokens, without comments, preprocessor
+    /// lines or the parameter names of function pointer types, which C doesn't
+    /// need and cbindgen leaves out
+    fn declarations(header: &str) -> Vec<String> {
+        let mut code = String::new();
+        let mut rest = header;
+        while let Some((before, comment)) = rest.split_once("/*") {
+            code.push_str(before);
+            rest = comment.split_once("*/").map_or("", |(_, after)| after);
+        }
+        code.push_str(rest);
+        let code: Vec<&str> = code.lines().filter(|line| !line.starts_with('#')).collect();
+        let mut declarations: Vec<String> = code
+            .join(" ")
+            .split(';')
+            .map(|statement| statement.rsplit(['{', '}']).next().unwrap())
+            .filter(|statement| statement.contains('('))
+            .map(|statement| {
+                let mut tokens = Vec::new();
+                let mut word = String::new();
+                for c in statement.chars() {
+                    if c.is_alphanumeric() || c == '_' {
+                        word.push(c);
+                        continue;
+                    }
+                    if !word.is_empty() {
+                        tokens.push(std::mem::take(&mut word));
+                    }
+                    if !c.is_whitespace() {
+                        tokens.push(c.to_string());
+                    }
+                }
+                if tokens[0] == "typedef" {
+                    // in the parameter list, a word after a type and before a
+                    // comma or the closing parenthesis is a name
+                    let params = tokens.iter().rposition(|t| t == "(").unwrap();
+                    let is_word = |t: &str| t.starts_with(|c: char| c.is_alphabetic());
+                    let named: Vec<bool> = (0..tokens.len())
+                        .map(|i| {
+                            i > params + 1
+                                && is_word(&tokens[i])
+                                && (is_word(&tokens[i - 1]) || tokens[i - 1] == "*")
+                                && matches!(tokens.get(i + 1).map(String::as_str), Some("," | ")"))
+                        })
+                        .collect();
+                    let mut named = named.into_iter();
+                    tokens.retain(|_| !named.next().unwrap());
+                }
+                tokens.join(" ")
+            })
+            .collect();
+        declarations.sort();
+        declarations
+    }
+
+    #[test]
+    fn header_matches_the_exports() {
+        let root = env!("CARGO_MANIFEST_DIR");
+        let config = cbindgen::Config {
+            language: cbindgen::Language::C,
+            usize_is_size_t: true,
+            no_includes: true,
+            ..Default::default()
+        };
+        let mut generated = Vec::new();
+        cbindgen::Builder::new()
+            .with_config(config)
+            .with_src(format!("{}/src/ffi.rs", root))
+            .rename_item("ProgressFn", "ovid_progress_fn")
+            .generate()
+            .unwrap()
+            .write(&mut generated);
+        let header = std::fs::read_to_string(format!("{}/include/ovid.h", root)).unwrap();
+        assert_eq!(
+            declarations(&header),
+            declarations(&String::from_utf8(generated).unwrap()),
+            "include/ovid.h differs from the functions src/ffi.rs exports"
+        );
+    }
+}
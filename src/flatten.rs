@@ -0,0 +1,327 @@
+use anyhow::Result;
+use lopdf::content::{Content, Operation};
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream, StringFormat};
+use std::path::Path;
+use tracing::{info, warn};
+
+use crate::pdf;
+use crate::watermark::{encode_win_ansi, standard_font, text_width_em, HELVETICA_WIDTHS};
+
+/// annotation flags that keep an annotation from being shown
+const HIDDEN: i64 = 1 << 1;
+const NO_VIEW: i64 = 1 << 5;
+/// text field flag for multi-line input
+const MULTILINE: i64 = 1 << 12;
+
+/// bake form fields and annotations into page content: each annotation's normal
+/// appearance is drawn where it sits and the annotation removed, then the form
+/// goes. links are kept, as they're navigation rather than content. text and
+/// choice fields without an up-to-date appearance get one drawn from their value
+pub fn flatten_pdf(input: &Path, output: &Path, quiet: bool) -> Result<()> {
+    let start = std::time::Instant::now();
+    let mut doc = pdf::load(input)?;
+    if !quiet {
+        info!("Flattening {} -> {}", input.display(), output.display());
+    }
+    let flattened = flatten_document(&mut doc, input)?;
+    pdf::save(&mut doc, output)?;
+
+    if !quiet {
+        info!(
+            "Done. {} annotation(s) flattened, PDF saved in {:.2}s",
+            flattened,
+            start.elapsed().as_secs_f64()
+        );
+    }
+    Ok(())
+}
+
+/// flatten a loaded document in place (see `flatten_pdf`); returns how many
+/// annotations were baked in. `input` names the file in warnings
+pub fn flatten_document(doc: &mut Document, input: &Path) -> Result<usize> {
+    let catalog_id = doc.trailer.get(b"Root").and_then(Object::as_reference)?;
+    let acroform = doc
+        .get_dictionary(catalog_id)?
+        .get(b"AcroForm")
+        .ok()
+        .and_then(|f| doc.dereference(f).ok())
+        .and_then(|(_, f)| f.as_dict().ok())
+        .cloned()
+        .unwrap_or_default();
+    if acroform.has(b"XFA") {
+        warn!("{}: XFA form data is dropped", input.display());
+    }
+    let need_appearances = acroform
+        .get(b"NeedAppearances")
+        .and_then(Object::as_bool)
+        .unwrap_or(false);
+
+    let mut flattened = 0;
+    for page_id in doc.get_pages().into_values() {
+        let annots = match doc.get_dictionary(page_id)?.get(b"Annots") {
+            Ok(annots) => match doc.dereference(annots) {
+                Ok((_, Object::Array(annots))) => annots.clone(),
+                _ => continue,
+            },
+            Err(_) => continue,
+        };
+        let mut kept = Vec::new();
+        let mut stamps = Vec::new();
+        for annot_ref in annots {
+            let Some(annot) = doc
+                .dereference(&annot_ref)
+                .ok()
+                .and_then(|(_, a)| a.as_dict().ok())
+                .cloned()
+            else {
+                continue;
+            };
+            let subtype = annot.get(b"Subtype").and_then(Object::as_name).unwrap_or(b"");
+            match subtype {
+                b"Link" => {
+                    kept.push(annot_ref);
+                    continue;
+                }
+                // shown only through its parent annotation
+                b"Popup" => continue,
+                _ => {}
+            }
+            let flags = annot.get(b"F").and_then(Object::as_i64).unwrap_or(0);
+            if flags & (HIDDEN | NO_VIEW) != 0 {
+                continue;
+            }
+            let widget = subtype == b"Widget";
+            let field_type = field_attr(doc, &annot, b"FT").and_then(|t| t.as_name().ok());
+            let text_field = matches!(field_type, Some(b"Tx" | b"Ch"));
+            let appearance = match normal_appearance(doc, &annot) {
+                Some(id) if !(widget && text_field && need_appearances) => Some(id),
+                _ if widget && text_field => text_appearance(doc, &annot, &acroform)?,
+                found => found,
+            };
+            let Some(rect) = rect(&annot) else { continue };
+            match appearance.and_then(|form| placement(doc, form, rect).map(|m| (form, m))) {
+                Some(stamp) => {
+                    stamps.push(stamp);
+                    flattened += 1;
+                }
+                // an empty field draws nothing; other annotations can't be baked in
+                None if !widget => kept.push(annot_ref),
+                None => {}
+            }
+        }
+        pdf::stamp_forms(doc, page_id, &stamps)?;
+        let page = doc.get_dictionary_mut(page_id)?;
+        if kept.is_empty() {
+            page.remove(b"Annots");
+        } else {
+            page.set("Annots", kept);
+        }
+    }
+    doc.get_dictionary_mut(catalog_id)?.remove(b"AcroForm");
+    // field dictionaries and replaced appearances are unreachable now
+    doc.prune_objects();
+    Ok(flattened)
+}
+
+/// a field attribute, inherited from parent fields
+pub fn field_attr<'a>(doc: &'a Document, annot: &'a Dictionary, key: &[u8]) -> Option<&'a Object> {
+    let mut node = annot;
+    // bounded walk in case of a cyclic field tree
+    for _ in 0..32 {
+        if let Ok(value) = node.get(key) {
+            return doc.dereference(value).ok().map(|(_, v)| v);
+        }
+        let parent = node.get(b"Parent").and_then(Object::as_reference).ok()?;
+        node = doc.get_dictionary(parent).ok()?;
+    }
+    None
+}
+
+/// annotation Rect, normalized
+fn rect(annot: &Dictionary) -> Option<[f32; 4]> {
+    let v: Vec<f32> = annot
+        .get(b"Rect")
+        .and_then(Object::as_array)
+        .ok()?
+        .iter()
+        .filter_map(pdf::number)
+        .collect();
+    let [x0, y0, x1, y1] = v[..] else { return None };
+    Some([x0.min(x1), y0.min(y1), x0.max(x1), y0.max(y1)])
+}
+
+/// the normal appearance stream, picking the state in /AS if there are several
+fn normal_appearance(doc: &Document, annot: &Dictionary) -> Option<ObjectId> {
+    let ap = doc.dereference(annot.get(b"AP").ok()?).ok()?.1.as_dict().ok()?;
+    let normal = ap.get(b"N").ok()?;
+    match doc.dereference(normal).ok()? {
+        (Some(id), Object::Stream(_)) => Some(id),
+        (_, Object::Dictionary(states)) => {
+            let state = annot.get(b"AS").and_then(Object::as_name).ok()?;
+            let id = states.get(state).and_then(Object::as_reference).ok()?;
+            doc.get_object(id).and_then(Object::as_stream).ok()?;
+            Some(id)
+        }
+        _ => None,
+    }
+}
+
+/// matrix drawing appearance `form_id` into `rect`: its BBox, transformed by its
+/// Matrix, is scaled and moved onto the rectangle (PDF 32000 12.5.5)
+fn placement(doc: &Document, form_id: ObjectId, rect: [f32; 4]) -> Option<[f32; 6]> {
+    let form = doc.get_object(form_id).and_then(Object::as_stream).ok()?;
+    let numbers = |key: &[u8]| -> Option<Vec<f32>> {
+        let arr = form.dict.get(key).and_then(Object::as_array).ok()?;
+        Some(arr.iter().filter_map(pdf::number).collect())
+    };
+    let bbox = numbers(b"BBox").filter(|b| b.len() == 4)?;
+    let m = numbers(b"Matrix")
+        .filter(|m| m.len() == 6)
+        .unwrap_or(vec![1.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+    let corners = [(bbox[0], bbox[1]), (bbox[2], bbox[1]), (bbox[0], bbox[3]), (bbox[2], bbox[3])];
+    let points = corners.map(|(x, y)| (m[0] * x + m[2] * y + m[4], m[1] * x + m[3] * y + m[5]));
+    let x0 = points.iter().map(|p| p.0).fold(f32::INFINITY, f32::min);
+    let y0 = points.iter().map(|p| p.1).fold(f32::INFINITY, f32::min);
+    let x1 = points.iter().map(|p| p.0).fold(f32::NEG_INFINITY, f32::max);
+    let y1 = points.iter().map(|p| p.1).fold(f32::NEG_INFINITY, f32::max);
+    if x1 - x0 <= 0.0 || y1 - y0 <= 0.0 {
+        return None;
+    }
+    let sx = (rect[2] - rect[0]) / (x1 - x0);
+    let sy = (rect[3] - rect[1]) / (y1 - y0);
+    Some([sx, 0.0, 0.0, sy, rect[0] - x0 * sx, rect[1] - y0 * sy])
+}
+
+/// draw a text or choice field's value with its default appearance (font, size,
+/// color); widths are Helvetica's, close enough for alignment in common fonts.
+/// None if the field is empty
+pub fn text_appearance(
+    doc: &mut Document,
+    annot: &Dictionary,
+    acroform: &Dictionary,
+) -> Result<Option<ObjectId>> {
+    let value = match field_attr(doc, annot, b"V") {
+        Some(Object::String(bytes, _)) => pdf::decode_text(bytes),
+        // multiple selection: the first
+        Some(Object::Array(items)) => match items.first() {
+            Some(Object::String(bytes, _)) => pdf::decode_text(bytes),
+            _ => String::new(),
+        },
+        _ => String::new(),
+    };
+    let Some(rect) = rect(annot).filter(|_| !value.is_empty()) else {
+        return Ok(None);
+    };
+    let (w, h) = (rect[2] - rect[0], rect[3] - rect[1]);
+    let da = field_attr(doc, annot, b"DA")
+        .or_else(|| acroform.get(b"DA").ok())
+        .and_then(|da| da.as_str().ok())
+        .unwrap_or(b"/Helv 0 Tf 0 g");
+    let mut da_ops = Content::decode(da).map(|c| c.operations).unwrap_or_default();
+    if !da_ops.iter().any(|op| op.operator == "Tf") {
+        da_ops.push(Operation::new("Tf", vec![Object::Name(b"Helv".to_vec()), 0.into()]));
+    }
+    let multiline = field_attr(doc, annot, b"Ff")
+        .and_then(|f| f.as_i64().ok())
+        .is_some_and(|f| f & MULTILINE != 0);
+    let quadding = field_attr(doc, annot, b"Q")
+        .or_else(|| acroform.get(b"Q").ok())
+        .and_then(|q| q.as_i64().ok())
+        .unwrap_or(0);
+
+    // auto size (0) fits a line in the field
+    let mut font_name = b"Helv".to_vec();
+    let mut size = 0.0;
+    for op in &mut da_ops {
+        if op.operator == "Tf" && op.operands.len() == 2 {
+            font_name = op.operands[0].as_name().map(<[u8]>::to_vec).unwrap_or(font_name);
+            size = pdf::number(&op.operands[1]).unwrap_or(0.0);
+            if size <= 0.0 {
+                size = (h - 4.0).clamp(4.0, 12.0);
+            }
+            op.operands[1] = Object::Real(size);
+        }
+    }
+    let font = acroform
+        .get(b"DR")
+        .and_then(|dr| doc.dereference(dr))
+        .and_then(|(_, dr)| dr.as_dict())
+        .and_then(|dr| dr.get(b"Font"))
+        .and_then(|fonts| doc.dereference(fonts))
+        .and_then(|(_, fonts)| fonts.as_dict())
+        .and_then(|fonts| fonts.get(&font_name))
+        .cloned()
+        .unwrap_or_else(|_| standard_font(b"Helvetica").into());
+
+    let lines: Vec<&str> = if multiline {
+        value.lines().collect()
+    } else {
+        vec![value.lines().next().unwrap_or("")]
+    };
+    let leading = size * 1.15;
+    let mut ops = vec![
+        Operation::new("BMC", vec![Object::Name(b"Tx".to_vec())]),
+        Operation::new("q", vec![]),
+        // clip to the field, inside a small padding
+        Operation::new("re", [1.0, 1.0, w - 2.0, h - 2.0].map(Object::Real).to_vec()),
+        Operation::new("W", vec![]),
+        Operation::new("n", vec![]),
+        Operation::new("BT", vec![]),
+    ];
+    ops.extend(da_ops);
+    for (i, line) in lines.iter().enumerate() {
+        let encoded = encode_win_ansi(line);
+        let width = text_width_em(&HELVETICA_WIDTHS, &encoded) * size;
+        let x = match quadding {
+            1 => (w - width) / 2.0,
+            2 => w - 2.0 - width,
+            _ => 2.0,
+        };
+        let y = if multiline {
+            h - 2.0 - size - i as f32 * leading
+        } else {
+            // cap height roughly centered
+            (h - size * 0.7) / 2.0
+        };
+        let tm = [1.0, 0.0, 0.0, 1.0, x, y].map(Object::Real).to_vec();
+        ops.push(Operation::new("Tm", tm));
+        ops.push(Operation::new("Tj", vec![Object::String(encoded, StringFormat::Literal)]));
+    }
+    ops.extend([
+        Operation::new("ET", vec![]),
+        Operation::new("Q", vec![]),
+        Operation::new("EMC", vec![]),
+    ]);
+    let form = Stream::new(
+        dictionary! {
+            "Type" => "XObject",
+            "Subtype" => "Form",
+            "BBox" => [0.0, 0.0, w, h].map(Object::Real).to_vec(),
+            "Resources" => dictionary! {
+                "Font" => Dictionary::from_iter([(font_name, font)]),
+            },
+        },
+        Content { operations: ops }.encode()?,
+    );
+    Ok(Some(doc.add_object(form)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn placement_maps_bbox_onto_rect() {
+        let mut doc = Document::with_version("1.5");
+        let form = doc.add_object(Stream::new(
+            dictionary! {
+                "BBox" => vec![0.into(), 0.into(), 50.into(), 10.into()],
+                // turned a quarter: the box becomes 10 wide, 50 high
+                "Matrix" => vec![0.into(), 1.into(), (-1).into(), 0.into(), 0.into(), 0.into()],
+            },
+            Vec::new(),
+        ));
+        let m = placement(&doc, form, [100.0, 200.0, 120.0, 300.0]).unwrap();
+        assert_eq!(m, [2.0, 0.0, 0.0, 2.0, 120.0, 200.0]);
+    }
+}
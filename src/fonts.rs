@@ -0,0 +1,373 @@
+use anyhow::{bail, Result};
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashSet};
+use std::path::Path;
+use tracing::info;
+
+use crate::info;
+use crate::pdf;
+
+/// a font as the document defines it, and the pages it's available to
+struct Font {
+    /// None for a font dictionary written inline in resources
+    id: Option<ObjectId>,
+    name: String,
+    kind: String,
+    encoding: String,
+    embedded: bool,
+    subset: bool,
+    pages: BTreeSet<u32>,
+}
+
+/// list the fonts a PDF uses, as a table or JSON: type, encoding, whether each
+/// is embedded (and only as a subset) and on which pages. with `by_page`, list
+/// each page's fonts instead. with `check`, print nothing and fail if any font
+/// isn't embedded
+pub fn print_fonts(
+    input: &Path,
+    json: bool,
+    by_page: bool,
+    check: bool,
+    quiet: bool,
+) -> Result<()> {
+    let doc = pdf::load(input)?;
+    let fonts = collect_fonts(&doc);
+
+    if check {
+        let missing: Vec<String> = fonts
+            .iter()
+            .filter(|font| !font.embedded)
+            .map(|font| format!("{} (pages {})", font.name, page_ranges(&font.pages)))
+            .collect();
+        if !missing.is_empty() {
+            bail!(
+                "{} font(s) not embedded in {}: {}",
+                missing.len(),
+                input.display(),
+                missing.join(", ")
+            );
+        }
+        if !quiet {
+            info!("all {} font(s) embedded", fonts.len());
+        }
+        return Ok(());
+    }
+
+    if by_page {
+        let pages: Vec<(u32, Vec<&Font>)> = doc
+            .get_pages()
+            .into_keys()
+            .map(|page| (page, fonts.iter().filter(|f| f.pages.contains(&page)).collect()))
+            .collect();
+        if json {
+            let all: Vec<PageFonts> = pages
+                .iter()
+                .map(|(page, fonts)| PageFonts {
+                    page: *page,
+                    fonts: fonts.iter().map(|f| f.to_json(false)).collect(),
+                })
+                .collect();
+            println!("{}", serde_json::to_string(&all)?);
+        } else {
+            for (i, (page, fonts)) in pages.iter().enumerate() {
+                if i > 0 {
+                    println!();
+                }
+                println!("Page {}:", page);
+                print_table(fonts, false);
+            }
+        }
+    } else if json {
+        let all: Vec<FontJson> = fonts.iter().map(|f| f.to_json(true)).collect();
+        println!("{}", serde_json::to_string(&all)?);
+    } else {
+        print_table(&fonts.iter().collect::<Vec<_>>(), true);
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct PageFonts<'a> {
+    page: u32,
+    fonts: Vec<FontJson<'a>>,
+}
+
+#[derive(Serialize)]
+struct FontJson<'a> {
+    name: &'a str,
+    #[serde(rename = "type")]
+    kind: &'a str,
+    encoding: &'a str,
+    embedded: bool,
+    subset: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pages: Option<&'a BTreeSet<u32>>,
+}
+
+impl Font {
+    /// the font for `--json`, with the pages it's on if `pages`
+    fn to_json(&self, pages: bool) -> FontJson<'_> {
+        FontJson {
+            name: &self.name,
+            kind: &self.kind,
+            encoding: &self.encoding,
+            embedded: self.embedded,
+            subset: self.subset,
+            pages: pages.then_some(&self.pages),
+        }
+    }
+}
+
+fn print_table(fonts: &[&Font], pages: bool) {
+    if fonts.is_empty() {
+        println!("no fonts");
+        return;
+    }
+    let yes_no = |b: bool| if b { "yes" } else { "no" };
+    let mut header = vec!["name", "type", "encoding", "embedded", "subset"];
+    if pages {
+        header.push("pages");
+    }
+    let rows: Vec<Vec<String>> = fonts
+        .iter()
+        .map(|f| {
+            let mut row = vec![
+                f.name.clone(),
+                f.kind.clone(),
+                f.encoding.clone(),
+                yes_no(f.embedded).to_string(),
+                yes_no(f.subset).to_string(),
+            ];
+            if pages {
+                row.push(page_ranges(&f.pages));
+            }
+            row
+        })
+        .collect();
+    info::print_table(&header, &rows);
+}
+
+/// every font in the pages' resources, including those of the forms and
+/// annotation appearances drawn on them, in order of first use
+fn collect_fonts(doc: &Document) -> Vec<Font> {
+    let mut fonts: Vec<Font> = Vec::new();
+    for (page, page_id) in doc.get_pages() {
+        let mut found = Vec::new();
+        let mut seen = HashSet::new();
+        if let Some(resources) = pdf::page_attribute(doc, page_id, b"Resources") {
+            resource_fonts(doc, resources, &mut seen, &mut found, 0);
+        }
+        let annots = doc
+            .get_dictionary(page_id)
+            .and_then(|p| p.get(b"Annots"))
+            .and_then(|a| doc.dereference(a))
+            .and_then(|(_, a)| a.as_array());
+        for annot in annots.into_iter().flatten() {
+            let appearance = doc
+                .dereference(annot)
+                .and_then(|(_, a)| a.as_dict())
+                .and_then(|a| a.get(b"AP"))
+                .and_then(|ap| doc.dereference(ap))
+                .and_then(|(_, ap)| ap.as_dict())
+                .and_then(|ap| ap.get(b"N"));
+            if let Ok(appearance) = appearance {
+                xobject_fonts(doc, appearance, &mut seen, &mut found, 0);
+            }
+        }
+        for (id, dict) in found {
+            match fonts.iter_mut().find(|f| id.is_some() && f.id == id) {
+                Some(font) => {
+                    font.pages.insert(page);
+                }
+                None => {
+                    let mut font = describe(doc, id, dict);
+                    font.pages.insert(page);
+                    fonts.push(font);
+                }
+            }
+        }
+    }
+    fonts
+}
+
+/// fonts in a resources dictionary and in the form XObjects it holds
+fn resource_fonts<'a>(
+    doc: &'a Document,
+    resources: &'a Object,
+    seen: &mut HashSet<ObjectId>,
+    found: &mut Vec<(Option<ObjectId>, &'a Dictionary)>,
+    depth: usize,
+) {
+    let Ok((_, Object::Dictionary(resources))) = doc.dereference(resources) else {
+        return;
+    };
+    let entries = |key: &[u8]| {
+        resources
+            .get(key)
+            .and_then(|d| doc.dereference(d))
+            .and_then(|(_, d)| d.as_dict())
+            .into_iter()
+            .flat_map(|d| d.iter().map(|(_, v)| v))
+    };
+    for font in entries(b"Font") {
+        if let Ok((id, Object::Dictionary(dict))) = doc.dereference(font) {
+            if id.is_none_or(|id| seen.insert(id)) {
+                found.push((id, dict));
+            }
+        }
+    }
+    for xobject in entries(b"XObject") {
+        xobject_fonts(doc, xobject, seen, found, depth);
+    }
+}
+
+/// fonts of a form XObject, or of each state of an appearance dictionary
+fn xobject_fonts<'a>(
+    doc: &'a Document,
+    xobject: &'a Object,
+    seen: &mut HashSet<ObjectId>,
+    found: &mut Vec<(Option<ObjectId>, &'a Dictionary)>,
+    depth: usize,
+) {
+    // forms can draw forms; guard against loops and absurd nesting
+    if depth > 16 {
+        return;
+    }
+    if let Object::Reference(id) = xobject {
+        if !seen.insert(*id) {
+            return;
+        }
+    }
+    match doc.dereference(xobject) {
+        Ok((_, Object::Stream(form)))
+            if form.dict.get(b"Subtype").and_then(Object::as_name).ok() == Some(b"Form") =>
+        {
+            if let Ok(resources) = form.dict.get(b"Resources") {
+                resource_fonts(doc, resources, seen, found, depth + 1);
+            }
+        }
+        // an appearance with on and off states
+        Ok((_, Object::Dictionary(states))) => {
+            for (_, state) in states.iter() {
+                xobject_fonts(doc, state, seen, found, depth + 1);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn describe(doc: &Document, id: Option<ObjectId>, font: &Dictionary) -> Font {
+    let name_of = |dict: &Dictionary, key: &[u8]| {
+        dict.get(key)
+            .and_then(Object::as_name)
+            .ok()
+            .map(|n| String::from_utf8_lossy(n).into_owned())
+    };
+    let subtype = name_of(font, b"Subtype").unwrap_or_else(|| "unknown".to_string());
+    // composite fonts keep their glyphs and metrics in a descendant CIDFont
+    let descendant = font
+        .get(b"DescendantFonts")
+        .and_then(|d| doc.dereference(d))
+        .and_then(|(_, d)| d.as_array())
+        .ok()
+        .and_then(|d| d.first())
+        .and_then(|d| doc.dereference(d).ok())
+        .and_then(|(_, d)| d.as_dict().ok());
+    let kind = match descendant.and_then(|d| name_of(d, b"Subtype")) {
+        Some(cid) => format!("{} ({})", subtype, cid),
+        None => subtype.clone(),
+    };
+    let name = name_of(font, b"BaseFont").unwrap_or_else(|| "[none]".to_string());
+    let descriptor = descendant
+        .unwrap_or(font)
+        .get(b"FontDescriptor")
+        .and_then(|d| doc.dereference(d))
+        .and_then(|(_, d)| d.as_dict())
+        .ok();
+    // type 3 glyphs are content streams in the font itself
+    let embedded = subtype == "Type3"
+        || descriptor.is_some_and(|d| {
+            [b"FontFile".as_slice(), b"FontFile2", b"FontFile3"].iter().any(|key| d.has(key))
+        });
+    Font {
+        id,
+        subset: is_subset(&name),
+        encoding: encoding(doc, font),
+        name,
+        kind,
+        embedded,
+        pages: BTreeSet::new(),
+    }
+}
+
+/// a predefined encoding or CMap by name; a dictionary is its base encoding with
+/// differences, and no entry leaves the font's own
+fn encoding(doc: &Document, font: &Dictionary) -> String {
+    let Ok(encoding) = font.get(b"Encoding") else {
+        return "built-in".to_string();
+    };
+    let name = |obj: Option<&Object>| {
+        obj.and_then(|n| n.as_name().ok()).map(|n| String::from_utf8_lossy(n).into_owned())
+    };
+    match doc.dereference(encoding) {
+        Ok((_, Object::Name(n))) => String::from_utf8_lossy(n).into_owned(),
+        Ok((_, Object::Dictionary(dict))) => {
+            let base = name(dict.get(b"BaseEncoding").ok());
+            let base = base.unwrap_or_else(|| "built-in".to_string());
+            if dict.has(b"Differences") {
+                format!("{} (modified)", base)
+            } else {
+                base
+            }
+        }
+        // an embedded CMap
+        Ok((_, Object::Stream(cmap))) => {
+            name(cmap.dict.get(b"CMapName").ok()).unwrap_or_else(|| "embedded CMap".to_string())
+        }
+        _ => "unknown".to_string(),
+    }
+}
+
+/// subset fonts are named with a tag of six capitals and a plus: ABCDEF+Font
+fn is_subset(name: &str) -> bool {
+    let bytes = name.as_bytes();
+    bytes.len() > 7 && bytes[6] == b'+' && bytes[..6].iter().all(u8::is_ascii_uppercase)
+}
+
+/// "1-3,5" for pages 1, 2, 3 and 5
+fn page_ranges(pages: &BTreeSet<u32>) -> String {
+    let mut runs: Vec<(u32, u32)> = Vec::new();
+    for &page in pages {
+        match runs.last_mut() {
+            Some((_, last)) if *last + 1 == page => *last = page,
+            _ => runs.push((page, page)),
+        }
+    }
+    runs.iter()
+        .map(|&(first, last)| {
+            if first == last {
+                first.to_string()
+            } else {
+                format!("{}-{}", first, last)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subset_names_and_page_ranges() {
+        assert!(is_subset("ABCDEF+Helvetica"));
+        assert!(!is_subset("Helvetica"));
+        assert!(!is_subset("AbCDEF+Helvetica"));
+        assert!(!is_subset("ABCDEF+"));
+        let pages = BTreeSet::from([1, 2, 3, 5, 7, 8]);
+        assert_eq!(page_ranges(&pages), "1-3,5,7-8");
+        assert_eq!(page_ranges(&BTreeSet::new()), "");
+    }
+}
@@ -0,0 +1,657 @@
+use anyhow::{bail, Context, Result};
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use rayon::prelude::*;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::path::Path;
+use tracing::{info, warn};
+
+use crate::flatten;
+use crate::merge;
+use crate::pdf;
+use crate::writer;
+
+/// field flags (PDF 32000 12.7.4)
+const READ_ONLY: i64 = 1;
+const RADIO: i64 = 1 << 15;
+const PUSH_BUTTON: i64 = 1 << 16;
+const EDIT: i64 = 1 << 18;
+const MULTI_SELECT: i64 = 1 << 21;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FieldKind {
+    Text,
+    Checkbox,
+    Radio,
+    Choice,
+    Button,
+    Signature,
+}
+
+impl FieldKind {
+    fn name(self) -> &'static str {
+        match self {
+            FieldKind::Text => "text",
+            FieldKind::Checkbox => "checkbox",
+            FieldKind::Radio => "radio",
+            FieldKind::Choice => "choice",
+            FieldKind::Button => "button",
+            FieldKind::Signature => "signature",
+        }
+    }
+}
+
+/// a terminal field: one value, shown by one or more widget annotations
+#[derive(Debug)]
+struct Field {
+    /// fully qualified name, parent names joined with dots
+    name: String,
+    id: ObjectId,
+    kind: FieldKind,
+    flags: i64,
+    /// the field's widgets; usually the field dictionary itself
+    widgets: Vec<ObjectId>,
+    /// allowed values: on states for buttons, (export, shown) pairs for choices
+    options: Vec<(String, String)>,
+}
+
+/// what a field is set to, checked against the field before anything is written
+enum Setting {
+    Text(String),
+    /// a button's on state, None for off
+    Button(Option<String>),
+    Choice(Vec<String>),
+}
+
+#[derive(Serialize)]
+struct FieldJson<'a> {
+    name: &'a str,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    value: Option<String>,
+    /// export values
+    options: Vec<&'a str>,
+    read_only: bool,
+}
+
+/// print the fields of a PDF's form: name, type, value, and the values it accepts
+pub fn list_fields(input: &Path, json: bool) -> Result<()> {
+    let doc = pdf::load(input)?;
+    let fields = collect_fields(&doc);
+    if json {
+        let list: Vec<FieldJson> = fields
+            .iter()
+            .map(|field| FieldJson {
+                name: &field.name,
+                kind: field.kind.name(),
+                value: field_value(&doc, field),
+                options: field.options.iter().map(|(export, _)| export.as_str()).collect(),
+                read_only: field.flags & READ_ONLY != 0,
+            })
+            .collect();
+        println!("{}", serde_json::to_string(&list)?);
+        return Ok(());
+    }
+    if fields.is_empty() {
+        println!("{} has no form fields", input.display());
+        return Ok(());
+    }
+    let width = fields.iter().map(|f| f.name.chars().count()).max().unwrap_or(0);
+    for field in &fields {
+        let mut line = format!("{:<width$}  {:<9}  ", field.name, field.kind.name());
+        line.push_str(&field_value(&doc, field).unwrap_or_default());
+        if !field.options.is_empty() {
+            let options: Vec<&str> = field.options.iter().map(|(e, _)| e.as_str()).collect();
+            line.push_str(&format!("  [{}]", options.join(", ")));
+        }
+        if field.flags & READ_ONLY != 0 {
+            line.push_str("  (read-only)");
+        }
+        println!("{}", line.trim_end());
+    }
+    Ok(())
+}
+
+/// fill a PDF's form from `data`: a JSON object of field names to values (or an
+/// array of such objects), or CSV with a header row of field names. each record
+/// makes one PDF; several are numbered like chunk parts (out_001.pdf, ...).
+/// every record is checked against the form before anything is written
+pub fn fill_form(
+    input: &Path,
+    data: &Path,
+    output: &Path,
+    flatten: bool,
+    quiet: bool,
+) -> Result<()> {
+    let start = std::time::Instant::now();
+    let records = load_data(data)?;
+    anyhow::ensure!(!records.is_empty(), "{} has no records", data.display());
+    anyhow::ensure!(
+        records.len() == 1 || output != Path::new("-"),
+        "{} records make several PDFs, which need a file output, not stdout",
+        records.len()
+    );
+    let mut doc = pdf::load(input)?;
+    let fields = collect_fields(&doc);
+    anyhow::ensure!(!fields.is_empty(), "{} has no form fields", input.display());
+    let settings: Vec<Vec<(&Field, Setting)>> = records
+        .iter()
+        .enumerate()
+        .map(|(n, record)| {
+            resolve_record(&fields, record).with_context(|| {
+                if records.len() == 1 {
+                    format!("Invalid data in {}", data.display())
+                } else {
+                    format!("Invalid data in {}, record {}", data.display(), n + 1)
+                }
+            })
+        })
+        .collect::<Result<_>>()?;
+    if !quiet {
+        info!(
+            "Filling {} from {} ({} record(s)) -> {}",
+            input.display(),
+            data.display(),
+            records.len(),
+            output.display()
+        );
+    }
+
+    // an XFA form would be shown in place of the fields in viewers that support it
+    if take_xfa(&mut doc)? {
+        warn!("{}: XFA form data is dropped", input.display());
+    }
+    let outputs: Vec<_> = if records.len() == 1 {
+        vec![output.to_path_buf()]
+    } else {
+        (1..=records.len()).map(|n| merge::part_path(output, n)).collect()
+    };
+    settings
+        .par_iter()
+        .zip(&outputs)
+        .map(|(record, path)| {
+            let mut filled = doc.clone();
+            for (field, setting) in record {
+                apply(&mut filled, field, setting)?;
+            }
+            if flatten {
+                flatten::flatten_document(&mut filled, input)?;
+            } else {
+                set_need_appearances(&mut filled)?;
+            }
+            pdf::save(&mut filled, path)
+        })
+        .collect::<Result<()>>()?;
+
+    if !quiet {
+        if outputs.len() > 1 {
+            for path in &outputs {
+                info!("  {}", path.display());
+            }
+        }
+        info!(
+            "Done. {} PDF(s) filled in {:.2}s",
+            outputs.len(),
+            start.elapsed().as_secs_f64()
+        );
+    }
+    Ok(())
+}
+
+/// the form's terminal fields, in field tree order
+fn collect_fields(doc: &Document) -> Vec<Field> {
+    let Some(acroform) = acroform(doc) else {
+        return Vec::new();
+    };
+    let roots = match acroform.get(b"Fields").map(|f| doc.dereference(f)) {
+        Ok(Ok((_, Object::Array(roots)))) => roots.clone(),
+        _ => return Vec::new(),
+    };
+    let mut fields = Vec::new();
+    let mut seen = HashSet::new();
+    // (field, parent name); reversed so fields pop in order
+    let mut stack: Vec<(ObjectId, String)> = roots
+        .iter()
+        .rev()
+        .filter_map(|f| f.as_reference().ok())
+        .map(|id| (id, String::new()))
+        .collect();
+    while let Some((id, parent)) = stack.pop() {
+        if !seen.insert(id) {
+            continue;
+        }
+        let Ok(dict) = doc.get_dictionary(id) else {
+            continue;
+        };
+        let partial = dict.get(b"T").and_then(Object::as_str).map(pdf::decode_text);
+        let name = match (parent.is_empty(), partial) {
+            (_, Err(_)) => parent,
+            (true, Ok(partial)) => partial,
+            (false, Ok(partial)) => format!("{}.{}", parent, partial),
+        };
+        let kids: Vec<ObjectId> = match dict.get(b"Kids").map(|k| doc.dereference(k)) {
+            Ok(Ok((_, Object::Array(kids)))) => {
+                kids.iter().filter_map(|k| k.as_reference().ok()).collect()
+            }
+            _ => Vec::new(),
+        };
+        // kids with names are fields of their own; kids without are widgets
+        let (child_fields, widgets): (Vec<ObjectId>, Vec<ObjectId>) = kids
+            .into_iter()
+            .partition(|&kid| doc.get_dictionary(kid).is_ok_and(|k| k.has(b"T")));
+        if !child_fields.is_empty() {
+            stack.extend(child_fields.into_iter().rev().map(|kid| (kid, name.clone())));
+            continue;
+        }
+        let widgets = if widgets.is_empty() { vec![id] } else { widgets };
+        let flags = flatten::field_attr(doc, dict, b"Ff")
+            .and_then(|f| f.as_i64().ok())
+            .unwrap_or(0);
+        let kind = match flatten::field_attr(doc, dict, b"FT").and_then(|t| t.as_name().ok()) {
+            Some(b"Tx") => FieldKind::Text,
+            Some(b"Ch") => FieldKind::Choice,
+            Some(b"Sig") => FieldKind::Signature,
+            Some(b"Btn") if flags & PUSH_BUTTON != 0 => FieldKind::Button,
+            Some(b"Btn") if flags & RADIO != 0 => FieldKind::Radio,
+            Some(b"Btn") => FieldKind::Checkbox,
+            _ => continue,
+        };
+        let options = match kind {
+            FieldKind::Checkbox | FieldKind::Radio => {
+                let mut states: Vec<String> = Vec::new();
+                for state in widgets.iter().flat_map(|&w| on_states(doc, w)) {
+                    if !states.contains(&state) {
+                        states.push(state);
+                    }
+                }
+                states.into_iter().map(|s| (s.clone(), s)).collect()
+            }
+            FieldKind::Choice => choice_options(doc, dict),
+            _ => Vec::new(),
+        };
+        fields.push(Field {
+            name,
+            id,
+            kind,
+            flags,
+            widgets,
+            options,
+        });
+    }
+    fields
+}
+
+fn acroform(doc: &Document) -> Option<&Dictionary> {
+    let form = doc.catalog().ok()?.get(b"AcroForm").ok()?;
+    doc.dereference(form).ok()?.1.as_dict().ok()
+}
+
+fn acroform_mut(doc: &mut Document) -> Result<Option<&mut Dictionary>> {
+    let catalog_id = doc.trailer.get(b"Root").and_then(Object::as_reference)?;
+    match doc.get_dictionary(catalog_id)?.get(b"AcroForm") {
+        Ok(&Object::Reference(id)) => Ok(doc.get_dictionary_mut(id).ok()),
+        Ok(Object::Dictionary(_)) => Ok(doc
+            .get_dictionary_mut(catalog_id)?
+            .get_mut(b"AcroForm")
+            .and_then(Object::as_dict_mut)
+            .ok()),
+        _ => Ok(None),
+    }
+}
+
+/// drop the form's XFA data, if it has any
+fn take_xfa(doc: &mut Document) -> Result<bool> {
+    Ok(acroform_mut(doc)?.is_some_and(|form| form.remove(b"XFA").is_some()))
+}
+
+/// ask viewers to redraw fields with their own fonts; ours are approximations
+fn set_need_appearances(doc: &mut Document) -> Result<()> {
+    if let Some(form) = acroform_mut(doc)? {
+        form.set("NeedAppearances", true);
+    }
+    Ok(())
+}
+
+/// names of a button widget's on appearance states
+fn on_states(doc: &Document, widget: ObjectId) -> Vec<String> {
+    let states = doc
+        .get_dictionary(widget)
+        .and_then(|w| w.get(b"AP"))
+        .and_then(|ap| doc.dereference(ap))
+        .and_then(|(_, ap)| ap.as_dict())
+        .and_then(|ap| ap.get(b"N"))
+        .and_then(|n| doc.dereference(n))
+        .and_then(|(_, n)| n.as_dict());
+    match states {
+        Ok(states) => states
+            .iter()
+            .filter(|(name, _)| name.as_slice() != b"Off")
+            .map(|(name, _)| String::from_utf8_lossy(name).into_owned())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// a choice field's Opt entries as (export value, shown text)
+fn choice_options(doc: &Document, field: &Dictionary) -> Vec<(String, String)> {
+    let text = |obj: &Object| match doc.dereference(obj) {
+        Ok((_, Object::String(bytes, _))) => Some(pdf::decode_text(bytes)),
+        _ => None,
+    };
+    let options = match field.get(b"Opt").map(|o| doc.dereference(o)) {
+        Ok(Ok((_, Object::Array(options)))) => options,
+        _ => return Vec::new(),
+    };
+    options
+        .iter()
+        .filter_map(|option| match doc.dereference(option).ok()?.1 {
+            Object::Array(pair) if pair.len() == 2 => Some((text(&pair[0])?, text(&pair[1])?)),
+            other => text(other).map(|t| (t.clone(), t)),
+        })
+        .collect()
+}
+
+/// the field's current value as text; selections of several choices are joined
+/// with commas
+fn field_value(doc: &Document, field: &Field) -> Option<String> {
+    let dict = doc.get_dictionary(field.id).ok()?;
+    match flatten::field_attr(doc, dict, b"V")? {
+        Object::String(bytes, _) => Some(pdf::decode_text(bytes)),
+        Object::Name(name) => Some(String::from_utf8_lossy(name).into_owned()),
+        Object::Array(items) => Some(
+            items
+                .iter()
+                .filter_map(|item| item.as_str().ok().map(pdf::decode_text))
+                .collect::<Vec<_>>()
+                .join(", "),
+        ),
+        _ => None,
+    }
+}
+
+/// field values from a .json or .csv file, one list of (name, value) per record
+fn load_data(path: &Path) -> Result<Vec<Vec<(String, Value)>>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Cannot read {}", path.display()))?;
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    let records = match extension.as_str() {
+        "json" => from_json(&text),
+        "csv" => from_csv(&text),
+        _ => bail!("{}: expected a .json or .csv file", path.display()),
+    };
+    records.with_context(|| format!("Invalid data file {}", path.display()))
+}
+
+fn from_json(text: &str) -> Result<Vec<Vec<(String, Value)>>> {
+    let items = match serde_json::from_str(text)? {
+        Value::Array(items) => items,
+        object @ Value::Object(_) => vec![object],
+        _ => bail!("expected an object of field values, or an array of them"),
+    };
+    items
+        .into_iter()
+        .enumerate()
+        .map(|(n, item)| {
+            let Value::Object(entries) = item else {
+                bail!("record {}: expected an object", n + 1);
+            };
+            let mut record = Vec::new();
+            flatten_names(entries, "", &mut record);
+            Ok(record)
+        })
+        .collect()
+}
+
+/// nested objects name fields by their parents: {"a": {"b": 1}} sets "a.b"
+fn flatten_names(entries: Map<String, Value>, prefix: &str, record: &mut Vec<(String, Value)>) {
+    for (key, value) in entries {
+        let name = if prefix.is_empty() { key } else { format!("{}.{}", prefix, key) };
+        match value {
+            Value::Object(entries) => flatten_names(entries, &name, record),
+            value => record.push((name, value)),
+        }
+    }
+}
+
+fn from_csv(text: &str) -> Result<Vec<Vec<(String, Value)>>> {
+    let mut rows = parse_csv(text)?.into_iter();
+    let Some((_, header)) = rows.next() else {
+        return Ok(Vec::new());
+    };
+    if let Some(blank) = header.iter().position(|name| name.trim().is_empty()) {
+        bail!("line 1: column {} has no field name", blank + 1);
+    }
+    rows.map(|(line, row)| {
+        anyhow::ensure!(
+            row.len() == header.len(),
+            "line {}: expected {} value(s), found {}",
+            line,
+            header.len(),
+            row.len()
+        );
+        let names = header.iter().map(|name| name.trim().to_string());
+        Ok(names.zip(row.into_iter().map(Value::String)).collect())
+    })
+    .collect()
+}
+
+/// RFC 4180 CSV: comma-separated, quoted values may hold commas, quotes (doubled),
+/// and line breaks. blank lines are skipped. rows come with their line number
+fn parse_csv(text: &str) -> Result<Vec<(usize, Vec<String>)>> {
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut value = String::new();
+    let (mut line, mut row_line) = (1, 1);
+    let mut chars = text.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted => {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    value.push('"');
+                } else {
+                    quoted = false;
+                }
+            }
+            '"' if value.is_empty() => quoted = true,
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' if quoted => {
+                value.push('\n');
+                line += 1;
+            }
+            ',' if !quoted => row.push(std::mem::take(&mut value)),
+            '\n' if !quoted => {
+                row.push(std::mem::take(&mut value));
+                if row.len() > 1 || !row[0].is_empty() {
+                    rows.push((row_line, std::mem::take(&mut row)));
+                }
+                row.clear();
+                line += 1;
+                row_line = line;
+            }
+            c => value.push(c),
+        }
+    }
+    anyhow::ensure!(!quoted, "line {}: unterminated quoted value", row_line);
+    row.push(value);
+    if row.len() > 1 || !row[0].is_empty() {
+        rows.push((row_line, row));
+    }
+    Ok(rows)
+}
+
+/// match a record's values to fields, checking each against what the field takes
+fn resolve_record<'a>(
+    fields: &'a [Field],
+    record: &[(String, Value)],
+) -> Result<Vec<(&'a Field, Setting)>> {
+    let mut settings = Vec::new();
+    for (name, value) in record {
+        let mut matched = fields.iter().filter(|f| &f.name == name).peekable();
+        if matched.peek().is_none() {
+            bail!("the form has no field named '{}'", name);
+        }
+        for field in matched {
+            let setting = resolve(field, value).with_context(|| format!("field '{}'", name))?;
+            settings.push((field, setting));
+        }
+    }
+    Ok(settings)
+}
+
+fn resolve(field: &Field, value: &Value) -> Result<Setting> {
+    let text = match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Null => Some(String::new()),
+        _ => None,
+    };
+    let is_option = |s: &str| field.options.iter().any(|(export, _)| export == s);
+    match field.kind {
+        FieldKind::Text => match (value, text) {
+            (Value::Bool(b), _) => Ok(Setting::Text(b.to_string())),
+            (_, Some(text)) => Ok(Setting::Text(text)),
+            _ => bail!("expected text"),
+        },
+        FieldKind::Checkbox => {
+            let on = field.options.first().cloned().map_or("Yes".to_string(), |(s, _)| s);
+            let checked = match (value, text.as_deref().map(str::trim)) {
+                (&Value::Bool(b), _) => b,
+                (_, Some(s)) if s == on => true,
+                (_, Some(s)) => match s.to_ascii_lowercase().as_str() {
+                    "true" | "yes" | "on" | "1" | "x" => true,
+                    "false" | "no" | "off" | "0" | "" => false,
+                    _ => bail!("expected true or false, got '{}'", s),
+                },
+                _ => bail!("expected true or false"),
+            };
+            Ok(Setting::Button(checked.then_some(on)))
+        }
+        FieldKind::Radio => match text.as_deref() {
+            Some("" | "Off") => Ok(Setting::Button(None)),
+            Some(s) if is_option(s) => Ok(Setting::Button(Some(s.to_string()))),
+            _ => {
+                let options: Vec<&str> = field.options.iter().map(|(e, _)| e.as_str()).collect();
+                bail!("expected one of {}", options.join(", "))
+            }
+        },
+        FieldKind::Choice => {
+            let values: Vec<String> = match value {
+                Value::Array(items) if field.flags & MULTI_SELECT != 0 => items
+                    .iter()
+                    .map(|item| match item {
+                        Value::String(s) => Ok(s.clone()),
+                        _ => bail!("expected a list of text values"),
+                    })
+                    .collect::<Result<_>>()?,
+                Value::Array(_) => bail!("takes a single value"),
+                _ => text.into_iter().filter(|s| !s.is_empty()).collect(),
+            };
+            // a value may name an option by what it shows; the export value is stored
+            let values = values
+                .into_iter()
+                .map(|v| {
+                    let option = field.options.iter().find(|(e, shown)| *e == v || *shown == v);
+                    match option {
+                        Some((export, _)) => Ok(export.clone()),
+                        None if field.options.is_empty() || field.flags & EDIT != 0 => Ok(v),
+                        None => {
+                            let shown: Vec<&str> =
+                                field.options.iter().map(|(_, s)| s.as_str()).collect();
+                            bail!("'{}' is not one of {}", v, shown.join(", "))
+                        }
+                    }
+                })
+                .collect::<Result<_>>()?;
+            Ok(Setting::Choice(values))
+        }
+        FieldKind::Button => bail!("push buttons take no value"),
+        FieldKind::Signature => bail!("signature fields can't be filled; use `ovid sign`"),
+    }
+}
+
+/// write a setting into the field and its widgets' appearances
+fn apply(doc: &mut Document, field: &Field, setting: &Setting) -> Result<()> {
+    let value = match setting {
+        Setting::Text(text) => writer::text_string(text),
+        Setting::Button(state) => {
+            Object::Name(state.as_deref().unwrap_or("Off").as_bytes().to_vec())
+        }
+        Setting::Choice(values) if values.len() <= 1 => {
+            writer::text_string(values.first().map_or("", String::as_str))
+        }
+        Setting::Choice(values) => {
+            Object::Array(values.iter().map(|v| writer::text_string(v)).collect())
+        }
+    };
+    let dict = doc.get_dictionary_mut(field.id)?;
+    dict.set("V", value);
+    // selected option indices would disagree with the new value
+    dict.remove(b"I");
+
+    let acroform = acroform(doc).cloned().unwrap_or_default();
+    for &widget_id in &field.widgets {
+        match setting {
+            Setting::Button(state) => {
+                // each widget of a radio group shows on only for its own state
+                let own = state.as_ref().filter(|s| on_states(doc, widget_id).contains(s));
+                let state = own.map_or("Off", |s| s.as_str()).as_bytes().to_vec();
+                doc.get_dictionary_mut(widget_id)?.set("AS", Object::Name(state));
+            }
+            Setting::Text(_) | Setting::Choice(_) => {
+                let annot = doc.get_dictionary(widget_id)?.clone();
+                let appearance = flatten::text_appearance(doc, &annot, &acroform)?;
+                let widget = doc.get_dictionary_mut(widget_id)?;
+                match appearance {
+                    Some(form) => widget.set("AP", lopdf::dictionary! { "N" => form }),
+                    None => {
+                        widget.remove(b"AP");
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_quotes_and_line_breaks() {
+        let text = "name,note\r\n\"Lovelace, Ada\",\"said \"\"hi\"\"\r\nthen left\"\n\n3,\n";
+        let rows = parse_csv(text).unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                (1, vec!["name".to_string(), "note".to_string()]),
+                (2, vec!["Lovelace, Ada".to_string(), "said \"hi\"\nthen left".to_string()]),
+                (5, vec!["3".to_string(), String::new()]),
+            ]
+        );
+        assert!(parse_csv("a,\"b\n").is_err());
+        let records = from_csv("a,b\n1\n").unwrap_err();
+        assert_eq!(records.to_string(), "line 2: expected 2 value(s), found 1");
+    }
+
+    #[test]
+    fn json_records_nest_names() {
+        let records = from_json(r#"{"name": "Ada", "address": {"city": "London"}}"#).unwrap();
+        assert_eq!(
+            records,
+            vec![vec![
+                ("name".to_string(), Value::from("Ada")),
+                ("address.city".to_string(), Value::from("London")),
+            ]]
+        );
+        assert_eq!(from_json(r#"[{"a": 1}, {"a": 2}]"#).unwrap().len(), 2);
+        assert!(from_json("[1]").is_err());
+    }
+}
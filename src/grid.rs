@@ -0,0 +1,254 @@
+use anyhow::{bail, Context, Result};
+use image::{imageops, Rgb, RgbImage};
+use rayon::prelude::*;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::merge;
+use crate::parse::{Caption, Color, ImageFormat, PixelSize, PngCompression};
+use crate::split::{encode_jpg, encode_png};
+
+pub struct GridOptions {
+    pub cols: u32,
+    /// rows per sheet; None puts every image on one sheet
+    pub rows: Option<u32>,
+    /// room for each image, in pixels; images are scaled to fit
+    pub cell: PixelSize,
+    /// space between and around cells, in pixels
+    pub gap: u32,
+    pub caption: Caption,
+    pub background: Color,
+    /// JPEG quality (1-100)
+    pub quality: u8,
+    pub quiet: bool,
+}
+
+/// compose images into contact sheets: a grid of thumbnails, each captioned
+/// with its file name or number, written as PNG or JPEG by `output`'s extension.
+/// more images than fit on one sheet go on numbered sheets (out_001.png, ...)
+pub fn grid_images(inputs: &[PathBuf], output: &Path, opts: &GridOptions) -> Result<()> {
+    let ext = output.extension().map(|e| e.to_string_lossy().to_lowercase());
+    let format = match ext.as_deref() {
+        Some("png") => ImageFormat::Png,
+        Some("jpg" | "jpeg") => ImageFormat::Jpg,
+        _ => bail!("Output must be a .png or .jpg file, got {}", output.display()),
+    };
+    let start = std::time::Instant::now();
+    let per_sheet = match opts.rows {
+        Some(rows) => (opts.cols * rows) as usize,
+        None => inputs.len(),
+    };
+    let sheets: Vec<&[PathBuf]> = inputs.chunks(per_sheet.max(1)).collect();
+
+    for (n, images) in sheets.iter().enumerate() {
+        let first = n * per_sheet;
+        let thumbs: Vec<RgbImage> = images
+            .par_iter()
+            .map(|path| thumbnail(path, opts.cell))
+            .collect::<Result<_>>()?;
+        let captions: Vec<String> = images
+            .iter()
+            .enumerate()
+            .map(|(i, path)| match opts.caption {
+                Caption::Name => path.file_name().unwrap_or_default().to_string_lossy().into(),
+                Caption::Number => (first + i + 1).to_string(),
+                Caption::None => String::new(),
+            })
+            .collect();
+        let sheet = compose(&thumbs, &captions, opts);
+
+        let path = match sheets.len() {
+            1 => output.to_path_buf(),
+            _ => merge::part_path(output, n + 1),
+        };
+        let file = File::create(&path)
+            .with_context(|| format!("Cannot create output file: {}", path.display()))?;
+        let (width, height) = sheet.dimensions();
+        match format {
+            ImageFormat::Png => {
+                encode_png(sheet.as_raw(), width, height, false, PngCompression::default(), file)?
+            }
+            ImageFormat::Jpg => {
+                encode_jpg(sheet.as_raw(), width, height, false, opts.quality, file)?
+            }
+        }
+        if !opts.quiet {
+            info!("  {} ({} image(s), {}x{})", path.display(), images.len(), width, height);
+        }
+    }
+
+    if !opts.quiet {
+        info!(
+            "Done. {} images on {} sheet(s) in {:.2}s",
+            inputs.len(),
+            sheets.len(),
+            start.elapsed().as_secs_f64()
+        );
+    }
+    Ok(())
+}
+
+/// an image scaled to fit the cell, keeping its aspect ratio
+fn thumbnail(path: &Path, cell: PixelSize) -> Result<RgbImage> {
+    let img = image::ImageReader::open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?
+        .with_guessed_format()?
+        .decode()
+        .with_context(|| format!("Failed to decode {}", path.display()))?;
+    Ok(img.resize(cell.width, cell.height, imageops::FilterType::Triangle).to_rgb8())
+}
+
+/// caption text size: a multiple of the 5x7 font, bigger for big cells
+fn caption_scale(cell: PixelSize) -> u32 {
+    (cell.width / 160).clamp(1, 4)
+}
+
+/// lay thumbnails out row by row, each centered in its cell with its caption
+/// centered underneath
+fn compose(thumbs: &[RgbImage], captions: &[String], opts: &GridOptions) -> RgbImage {
+    let (cell, gap) = (opts.cell, opts.gap);
+    let scale = caption_scale(cell);
+    let band = if opts.caption == Caption::None { 0 } else { GLYPH_HEIGHT * scale + 4 * scale };
+    let cols = opts.cols.min(thumbs.len() as u32).max(1);
+    let rows = (thumbs.len() as u32).div_ceil(opts.cols).max(1);
+    let width = gap + cols * (cell.width + gap);
+    let height = gap + rows * (cell.height + band + gap);
+
+    let Color { r, g, b } = opts.background;
+    let mut sheet = RgbImage::from_pixel(width, height, Rgb([r, g, b]));
+    // dark text on light backgrounds, light on dark
+    let luma = (r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000;
+    let ink = if luma > 128 { Rgb([32, 32, 32]) } else { Rgb([224, 224, 224]) };
+    for (i, (thumb, caption)) in thumbs.iter().zip(captions).enumerate() {
+        let (col, row) = (i as u32 % opts.cols, i as u32 / opts.cols);
+        let x = gap + col * (cell.width + gap);
+        let y = gap + row * (cell.height + band + gap);
+        let dx = (cell.width - thumb.width()) / 2;
+        let dy = (cell.height - thumb.height()) / 2;
+        imageops::overlay(&mut sheet, thumb, (x + dx) as i64, (y + dy) as i64);
+        if band > 0 {
+            let text = fit_text(caption, cell.width / (GLYPH_ADVANCE * scale));
+            let chars = text.chars().count() as u32;
+            let text_width = (chars * GLYPH_ADVANCE).saturating_sub(1) * scale;
+            let tx = x + cell.width.saturating_sub(text_width) / 2;
+            draw_text(&mut sheet, tx, y + cell.height + 2 * scale, &text, scale, ink);
+        }
+    }
+    sheet
+}
+
+/// `text` cut down to `max` characters, with ".." marking the cut
+fn fit_text(text: &str, max: u32) -> String {
+    let max = max as usize;
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+    let kept: String = text.chars().take(max.saturating_sub(2)).collect();
+    format!("{}..", kept).chars().take(max).collect()
+}
+
+const GLYPH_HEIGHT: u32 = 7;
+/// glyph width plus one column of spacing
+const GLYPH_ADVANCE: u32 = 6;
+
+/// draw text in the 5x7 font with its top left at (x, y), each font pixel
+/// `scale` pixels square; characters outside printable ASCII show as '?'
+fn draw_text(img: &mut RgbImage, x: u32, y: u32, text: &str, scale: u32, ink: Rgb<u8>) {
+    for (i, c) in text.chars().enumerate() {
+        let index = match c {
+            ' '..='~' => c as usize - 32,
+            _ => '?' as usize - 32,
+        };
+        let left = x + i as u32 * GLYPH_ADVANCE * scale;
+        for (col, bits) in FONT[index].iter().enumerate() {
+            for row in 0..GLYPH_HEIGHT {
+                if bits & (1 << row) == 0 {
+                    continue;
+                }
+                for py in 0..scale {
+                    for px in 0..scale {
+                        let (fx, fy) = (left + col as u32 * scale + px, y + row * scale + py);
+                        if fx < img.width() && fy < img.height() {
+                            img.put_pixel(fx, fy, ink);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// 5x7 glyphs for ' ' to '~', one byte per column, least significant bit at the top
+#[rustfmt::skip]
+const FONT: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], [0x00, 0x00, 0x5f, 0x00, 0x00], [0x00, 0x07, 0x00, 0x07, 0x00],
+    [0x14, 0x7f, 0x14, 0x7f, 0x14], [0x24, 0x2a, 0x7f, 0x2a, 0x12], [0x23, 0x13, 0x08, 0x64, 0x62],
+    [0x36, 0x49, 0x55, 0x22, 0x50], [0x00, 0x05, 0x03, 0x00, 0x00], [0x00, 0x1c, 0x22, 0x41, 0x00],
+    [0x00, 0x41, 0x22, 0x1c, 0x00], [0x08, 0x2a, 0x1c, 0x2a, 0x08], [0x08, 0x08, 0x3e, 0x08, 0x08],
+    [0x00, 0x50, 0x30, 0x00, 0x00], [0x08, 0x08, 0x08, 0x08, 0x08], [0x00, 0x60, 0x60, 0x00, 0x00],
+    [0x20, 0x10, 0x08, 0x04, 0x02], [0x3e, 0x51, 0x49, 0x45, 0x3e], [0x00, 0x42, 0x7f, 0x40, 0x00],
+    [0x42, 0x61, 0x51, 0x49, 0x46], [0x21, 0x41, 0x45, 0x4b, 0x31], [0x18, 0x14, 0x12, 0x7f, 0x10],
+    [0x27, 0x45, 0x45, 0x45, 0x39], [0x3c, 0x4a, 0x49, 0x49, 0x30], [0x01, 0x71, 0x09, 0x05, 0x03],
+    [0x36, 0x49, 0x49, 0x49, 0x36], [0x06, 0x49, 0x49, 0x29, 0x1e], [0x00, 0x36, 0x36, 0x00, 0x00],
+    [0x00, 0x56, 0x36, 0x00, 0x00], [0x08, 0x14, 0x22, 0x41, 0x00], [0x14, 0x14, 0x14, 0x14, 0x14],
+    [0x00, 0x41, 0x22, 0x14, 0x08], [0x02, 0x01, 0x51, 0x09, 0x06], [0x32, 0x49, 0x79, 0x41, 0x3e],
+    [0x7e, 0x11, 0x11, 0x11, 0x7e], [0x7f, 0x49, 0x49, 0x49, 0x36], [0x3e, 0x41, 0x41, 0x41, 0x22],
+    [0x7f, 0x41, 0x41, 0x22, 0x1c], [0x7f, 0x49, 0x49, 0x49, 0x41], [0x7f, 0x09, 0x09, 0x09, 0x01],
+    [0x3e, 0x41, 0x49, 0x49, 0x7a], [0x7f, 0x08, 0x08, 0x08, 0x7f], [0x00, 0x41, 0x7f, 0x41, 0x00],
+    [0x20, 0x40, 0x41, 0x3f, 0x01], [0x7f, 0x08, 0x14, 0x22, 0x41], [0x7f, 0x40, 0x40, 0x40, 0x40],
+    [0x7f, 0x02, 0x0c, 0x02, 0x7f], [0x7f, 0x04, 0x08, 0x10, 0x7f], [0x3e, 0x41, 0x41, 0x41, 0x3e],
+    [0x7f, 0x09, 0x09, 0x09, 0x06], [0x3e, 0x41, 0x51, 0x21, 0x5e], [0x7f, 0x09, 0x19, 0x29, 0x46],
+    [0x46, 0x49, 0x49, 0x49, 0x31], [0x01, 0x01, 0x7f, 0x01, 0x01], [0x3f, 0x40, 0x40, 0x40, 0x3f],
+    [0x1f, 0x20, 0x40, 0x20, 0x1f], [0x3f, 0x40, 0x38, 0x40, 0x3f], [0x63, 0x14, 0x08, 0x14, 0x63],
+    [0x07, 0x08, 0x70, 0x08, 0x07], [0x61, 0x51, 0x49, 0x45, 0x43], [0x00, 0x7f, 0x41, 0x41, 0x00],
+    [0x02, 0x04, 0x08, 0x10, 0x20], [0x00, 0x41, 0x41, 0x7f, 0x00], [0x04, 0x02, 0x01, 0x02, 0x04],
+    [0x40, 0x40, 0x40, 0x40, 0x40], [0x00, 0x01, 0x02, 0x04, 0x00], [0x20, 0x54, 0x54, 0x54, 0x78],
+    [0x7f, 0x48, 0x44, 0x44, 0x38], [0x38, 0x44, 0x44, 0x44, 0x20], [0x38, 0x44, 0x44, 0x48, 0x7f],
+    [0x38, 0x54, 0x54, 0x54, 0x18], [0x08, 0x7e, 0x09, 0x01, 0x02], [0x0c, 0x52, 0x52, 0x52, 0x3e],
+    [0x7f, 0x08, 0x04, 0x04, 0x78], [0x00, 0x44, 0x7d, 0x40, 0x00], [0x20, 0x40, 0x44, 0x3d, 0x00],
+    [0x7f, 0x10, 0x28, 0x44, 0x00], [0x00, 0x41, 0x7f, 0x40, 0x00], [0x7c, 0x04, 0x18, 0x04, 0x78],
+    [0x7c, 0x08, 0x04, 0x04, 0x78], [0x38, 0x44, 0x44, 0x44, 0x38], [0x7c, 0x14, 0x14, 0x14, 0x08],
+    [0x08, 0x14, 0x14, 0x18, 0x7c], [0x7c, 0x08, 0x04, 0x04, 0x08], [0x48, 0x54, 0x54, 0x54, 0x20],
+    [0x04, 0x3f, 0x44, 0x40, 0x20], [0x3c, 0x40, 0x40, 0x20, 0x7c], [0x1c, 0x20, 0x40, 0x20, 0x1c],
+    [0x3c, 0x40, 0x30, 0x40, 0x3c], [0x44, 0x28, 0x10, 0x28, 0x44], [0x0c, 0x50, 0x50, 0x50, 0x3c],
+    [0x44, 0x64, 0x54, 0x4c, 0x44], [0x00, 0x08, 0x36, 0x41, 0x00], [0x00, 0x00, 0x7f, 0x00, 0x00],
+    [0x00, 0x41, 0x36, 0x08, 0x00], [0x08, 0x04, 0x08, 0x10, 0x08],
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn captions_fit_their_cell() {
+        assert_eq!(fit_text("scan_0001.tif", 20), "scan_0001.tif");
+        assert_eq!(fit_text("scan_0001.tif", 8), "scan_0..");
+        assert_eq!(fit_text("scan_0001.tif", 1), ".");
+    }
+
+    #[test]
+    fn sheet_layout() {
+        let opts = GridOptions {
+            cols: 3,
+            rows: None,
+            cell: PixelSize { width: 40, height: 30 },
+            gap: 4,
+            caption: Caption::Number,
+            background: Color { r: 255, g: 255, b: 255 },
+            quality: 75,
+            quiet: true,
+        };
+        let thumbs = vec![RgbImage::from_pixel(40, 20, Rgb([255, 0, 0])); 4];
+        let captions: Vec<String> = (1..=4).map(|n| n.to_string()).collect();
+        let sheet = compose(&thumbs, &captions, &opts);
+        // 3 columns, 2 rows of 30px cells with an 11px caption band
+        assert_eq!(sheet.dimensions(), (4 + 3 * 44, 4 + 2 * (30 + 11 + 4)));
+        // the wide image is centered vertically in its cell
+        assert_eq!(sheet.get_pixel(4, 4 + 5), &Rgb([255, 0, 0]));
+        assert_eq!(sheet.get_pixel(4, 4 + 4), &Rgb([255, 255, 255]));
+        // some ink under the first image
+        let band = (4..44).flat_map(|x| (36..45).map(move |y| (x, y)));
+        assert!(band.into_iter().any(|(x, y)| sheet.get_pixel(x, y) == &Rgb([32, 32, 32])));
+    }
+}
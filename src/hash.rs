@@ -0,0 +1,188 @@
+use anyhow::{Context, Result};
+use image::imageops::{self, FilterType};
+use image::{GrayImage, ImageReader};
+use serde::Serialize;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use crate::parse::parse_page_ranges;
+use crate::split;
+
+/// perceptual hashes of one page or image
+struct PageHash {
+    page: usize,
+    phash: u64,
+    dhash: u64,
+}
+
+/// an entry of the printed array, the hashes in hex
+#[derive(Serialize)]
+struct HashJson {
+    file: String,
+    page: usize,
+    phash: String,
+    dhash: String,
+}
+
+/// print a JSON array with the pHash and dHash of every page of the input PDFs
+/// (rendered in gray at `dpi`) and of input images, one entry per page. hashes
+/// are 64 bits as hex, compared by Hamming distance: a few bits apart for the
+/// same page rescanned or recompressed
+pub fn print_hashes(inputs: &[impl AsRef<Path>], pages: Option<&str>, dpi: u32) -> Result<()> {
+    let mut entries = Vec::new();
+    for input in inputs {
+        let input = input.as_ref();
+        let mut head = Vec::new();
+        File::open(input)
+            .and_then(|file| file.take(1024).read_to_end(&mut head))
+            .with_context(|| format!("Failed to read {}", input.display()))?;
+        // the header may follow a little junk, as viewers allow
+        let hashes = if head.windows(5).any(|w| w == b"%PDF-") {
+            hash_pdf(input, pages, dpi)?
+        } else {
+            let img = ImageReader::open(input)
+                .and_then(ImageReader::with_guessed_format)
+                .map_err(image::ImageError::from)
+                .and_then(ImageReader::decode)
+                .with_context(|| format!("Failed to decode {}", input.display()))?;
+            vec![hash_image(1, &img.to_luma8())]
+        };
+        entries.extend(hashes.into_iter().map(|hash| HashJson {
+            file: input.display().to_string(),
+            page: hash.page,
+            phash: format!("{:016x}", hash.phash),
+            dhash: format!("{:016x}", hash.dhash),
+        }));
+    }
+    println!("{}", serde_json::to_string(&entries)?);
+    Ok(())
+}
+
+/// render the selected pages small, in parallel from one parse of the
+/// document, and hash them
+fn hash_pdf(input: &Path, pages: Option<&str>, dpi: u32) -> Result<Vec<PageHash>> {
+    let input_str = input.to_str().context("Invalid path")?;
+    let doc = mupdf::Document::open(input_str)
+        .with_context(|| format!("Failed to open {}", input.display()))?;
+    let num_pages = doc.page_count()?;
+    let indices: Vec<i32> = match pages {
+        Some(s) => parse_page_ranges(s, num_pages)?,
+        None => (0..num_pages).collect(),
+    };
+    let mut hashes = Vec::with_capacity(indices.len());
+    split::pages_in_order(
+        &doc,
+        &indices,
+        |i, list| {
+            render_gray(&list, dpi)
+                .map(|gray| hash_image(i as usize + 1, &gray))
+                .with_context(|| format!("{}: page {}", input.display(), i + 1))
+        },
+        |done| {
+            for hash in done {
+                hashes.push(hash?);
+            }
+            Ok(())
+        },
+    )?;
+    Ok(hashes)
+}
+
+fn render_gray(list: &mupdf::DisplayList, dpi: u32) -> Result<GrayImage> {
+    let scale = dpi as f32 / 72.0;
+    let matrix = mupdf::Matrix::new_scale(scale, scale);
+    let pixmap = list.to_pixmap(&matrix, &mupdf::Colorspace::device_gray(), false)?;
+    let samples = split::packed_samples(&pixmap, 1);
+    GrayImage::from_raw(pixmap.width(), pixmap.height(), samples).context("Unexpected pixmap layout")
+}
+
+fn hash_image(page: usize, gray: &GrayImage) -> PageHash {
+    PageHash {
+        page,
+        phash: phash(gray),
+        dhash: dhash(gray),
+    }
+}
+
+/// difference hash: one bit per neighbouring pair in a 9x8 thumbnail, set
+/// where the right pixel is brighter. bits run row by row, first bit highest
+fn dhash(gray: &GrayImage) -> u64 {
+    let small = imageops::resize(gray, 9, 8, FilterType::Triangle);
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            let brighter = small.get_pixel(x + 1, y)[0] > small.get_pixel(x, y)[0];
+            hash = hash << 1 | brighter as u64;
+        }
+    }
+    hash
+}
+
+/// DCT hash: the 8x8 lowest frequencies of a 32x32 thumbnail's cosine
+/// transform, one bit each, set where above their median
+fn phash(gray: &GrayImage) -> u64 {
+    const N: usize = 32;
+    let small = imageops::resize(gray, N as u32, N as u32, FilterType::Triangle);
+    let pixels: Vec<f64> = small.pixels().map(|p| p[0] as f64).collect();
+    // cos[k][x] for the 8 frequencies kept
+    let cos: Vec<[f64; N]> = (0..8)
+        .map(|k| {
+            std::array::from_fn(|x| {
+                ((2 * x + 1) as f64 * k as f64 * std::f64::consts::PI / (2 * N) as f64).cos()
+            })
+        })
+        .collect();
+    // rows first, then columns of the row results
+    let rows: Vec<[f64; 8]> = pixels
+        .chunks(N)
+        .map(|row| std::array::from_fn(|u| (0..N).map(|x| row[x] * cos[u][x]).sum()))
+        .collect();
+    let coefficients: Vec<f64> = (0..8)
+        .flat_map(|v| (0..8).map(move |u| (v, u)))
+        .map(|(v, u)| (0..N).map(|y| rows[y][u] * cos[v][y]).sum())
+        .collect();
+    let mut sorted = coefficients.clone();
+    sorted.sort_by(f64::total_cmp);
+    let median = (sorted[31] + sorted[32]) / 2.0;
+    coefficients
+        .iter()
+        .fold(0u64, |hash, &c| hash << 1 | (c > median) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a smooth "photo" of waves with the given periods
+    fn photo(px: f32, py: f32, brightness: f32) -> GrayImage {
+        GrayImage::from_fn(200, 260, |x, y| {
+            let (x, y) = (x as f32, y as f32);
+            let wave =
+                ((x + y) / px).sin() + ((x - 2.0 * y) / py).cos() + (x * y / 4000.0).sin();
+            image::Luma([(brightness + 35.0 * wave) as u8])
+        })
+    }
+
+    #[test]
+    fn near_copies_hash_close() {
+        let distance = |a: u64, b: u64| (a ^ b).count_ones();
+        let original = hash_image(1, &photo(17.0, 23.0, 128.0));
+        // a smaller, brighter copy, as from a second scan at another DPI
+        let small = imageops::resize(&photo(17.0, 23.0, 140.0), 90, 117, FilterType::Triangle);
+        let copy = hash_image(1, &small);
+        let other = hash_image(1, &photo(31.0, 11.0, 128.0));
+        assert!(distance(original.phash, copy.phash) <= 4);
+        assert!(distance(original.dhash, copy.dhash) <= 4);
+        assert!(distance(original.phash, other.phash) > 12);
+        assert!(distance(original.dhash, other.dhash) > 12);
+    }
+
+    #[test]
+    fn dhash_of_a_gradient() {
+        let rising = GrayImage::from_fn(90, 80, |x, _| image::Luma([(x * 2) as u8]));
+        assert_eq!(dhash(&rising), u64::MAX);
+        let falling = GrayImage::from_fn(90, 80, |x, _| image::Luma([255 - (x * 2) as u8]));
+        assert_eq!(dhash(&falling), 0);
+    }
+}
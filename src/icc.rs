@@ -0,0 +1,208 @@
+use std::sync::OnceLock;
+
+/// sRGB primaries adapted to the D50 profile connection space (Bradford)
+const SRGB_RED: [f64; 3] = [0.4360747, 0.2225045, 0.0139322];
+const SRGB_GREEN: [f64; 3] = [0.3850649, 0.7168786, 0.0971045];
+const SRGB_BLUE: [f64; 3] = [0.1430804, 0.0606169, 0.7141733];
+const D50: [f64; 3] = [0.9642, 1.0, 0.8249];
+
+/// entries in the tone curve table
+const TRC_POINTS: usize = 1024;
+
+/// compact ICC v2 display profile for sRGB, built once on first use
+pub fn srgb_profile() -> &'static [u8] {
+    static PROFILE: OnceLock<Vec<u8>> = OnceLock::new();
+    PROFILE.get_or_init(build_srgb_profile)
+}
+
+fn build_srgb_profile() -> Vec<u8> {
+    rgb_profile("sRGB", curve_tag())
+}
+
+/// compact ICC v2 display profile for gray with the sRGB tone curve, built once
+/// on first use
+pub fn srgb_gray_profile() -> &'static [u8] {
+    static PROFILE: OnceLock<Vec<u8>> = OnceLock::new();
+    PROFILE.get_or_init(|| gray_profile("sGray", curve_tag()))
+}
+
+/// ICC v2 display profile decoding samples with a power of `gamma` (2.2 for
+/// most displays): sRGB primaries if `channels` is 3, gray if 1
+pub fn gamma_profile(gamma: f64, channels: u8) -> Vec<u8> {
+    let description = format!("Gamma {:.2}", gamma);
+    match channels {
+        1 => gray_profile(&description, gamma_tag(gamma)),
+        _ => rgb_profile(&description, gamma_tag(gamma)),
+    }
+}
+
+fn rgb_profile(description: &str, trc: Vec<u8>) -> Vec<u8> {
+    build_profile(
+        b"RGB ",
+        &[
+            (b"desc", description_tag(description)),
+            (b"cprt", text_tag("No copyright, use freely")),
+            (b"wtpt", xyz_tag(D50)),
+            (b"rXYZ", xyz_tag(SRGB_RED)),
+            (b"gXYZ", xyz_tag(SRGB_GREEN)),
+            (b"bXYZ", xyz_tag(SRGB_BLUE)),
+            (b"rTRC", trc.clone()),
+            (b"gTRC", trc.clone()),
+            (b"bTRC", trc),
+        ],
+    )
+}
+
+fn gray_profile(description: &str, trc: Vec<u8>) -> Vec<u8> {
+    build_profile(
+        b"GRAY",
+        &[
+            (b"desc", description_tag(description)),
+            (b"cprt", text_tag("No copyright, use freely")),
+            (b"wtpt", xyz_tag(D50)),
+            (b"kTRC", trc),
+        ],
+    )
+}
+
+/// a display profile for the `color_space` ("RGB " or "GRAY") with `tags`
+fn build_profile(color_space: &[u8; 4], tags: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+    // tag data follows the header and tag table, each element 4-byte aligned
+    let mut table = (tags.len() as u32).to_be_bytes().to_vec();
+    let mut body = Vec::new();
+    let data_start = 128 + 4 + 12 * tags.len();
+    for (sig, data) in tags {
+        table.extend_from_slice(*sig);
+        table.extend_from_slice(&((data_start + body.len()) as u32).to_be_bytes());
+        table.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        body.extend_from_slice(data);
+        body.resize(body.len().next_multiple_of(4), 0);
+    }
+
+    let size = (128 + table.len() + body.len()) as u32;
+    let mut profile = Vec::with_capacity(size as usize);
+    profile.extend_from_slice(&size.to_be_bytes());
+    profile.extend_from_slice(&[0; 4]); // preferred CMM
+    profile.extend_from_slice(&[2, 0x10, 0, 0]); // version 2.1
+    profile.extend_from_slice(b"mntr");
+    profile.extend_from_slice(color_space);
+    profile.extend_from_slice(b"XYZ ");
+    profile.extend_from_slice(&[0; 12]); // creation date
+    profile.extend_from_slice(b"acsp");
+    profile.extend_from_slice(&[0; 24]); // platform, flags, manufacturer, model, attributes
+    profile.extend_from_slice(&[0; 4]); // perceptual intent
+    for v in D50 {
+        profile.extend_from_slice(&s15_fixed16(v));
+    }
+    profile.resize(128, 0); // creator, id, reserved
+    profile.extend_from_slice(&table);
+    profile.extend_from_slice(&body);
+    profile
+}
+
+fn s15_fixed16(v: f64) -> [u8; 4] {
+    ((v * 65536.0).round() as i32).to_be_bytes()
+}
+
+fn xyz_tag(xyz: [f64; 3]) -> Vec<u8> {
+    let mut tag = b"XYZ \0\0\0\0".to_vec();
+    for v in xyz {
+        tag.extend_from_slice(&s15_fixed16(v));
+    }
+    tag
+}
+
+fn text_tag(text: &str) -> Vec<u8> {
+    let mut tag = b"text\0\0\0\0".to_vec();
+    tag.extend_from_slice(text.as_bytes());
+    tag.push(0);
+    tag
+}
+
+fn description_tag(text: &str) -> Vec<u8> {
+    let mut tag = b"desc\0\0\0\0".to_vec();
+    tag.extend_from_slice(&(text.len() as u32 + 1).to_be_bytes());
+    tag.extend_from_slice(text.as_bytes());
+    tag.push(0);
+    // empty Unicode and ScriptCode descriptions
+    tag.extend_from_slice(&[0; 8]);
+    tag.extend_from_slice(&[0; 3]);
+    tag.extend_from_slice(&[0; 67]);
+    tag
+}
+
+/// sRGB transfer function sampled as a lookup table
+fn curve_tag() -> Vec<u8> {
+    let mut tag = b"curv\0\0\0\0".to_vec();
+    tag.extend_from_slice(&(TRC_POINTS as u32).to_be_bytes());
+    for i in 0..TRC_POINTS {
+        let v = i as f64 / (TRC_POINTS - 1) as f64;
+        let linear = if v <= 0.04045 {
+            v / 12.92
+        } else {
+            ((v + 0.055) / 1.055).powf(2.4)
+        };
+        tag.extend_from_slice(&((linear * 65535.0).round() as u16).to_be_bytes());
+    }
+    tag
+}
+
+/// a pure power-law curve: one u8Fixed8Number exponent
+fn gamma_tag(gamma: f64) -> Vec<u8> {
+    let mut tag = b"curv\0\0\0\0".to_vec();
+    tag.extend_from_slice(&1u32.to_be_bytes());
+    let fixed = (gamma * 256.0).round().clamp(1.0, u16::MAX as f64) as u16;
+    tag.extend_from_slice(&fixed.to_be_bytes());
+    tag
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u32_at(data: &[u8], pos: usize) -> u32 {
+        u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn srgb_profile_header() {
+        let profile = srgb_profile();
+        assert_eq!(u32_at(profile, 0) as usize, profile.len());
+        assert_eq!(&profile[12..24], b"mntrRGB XYZ ");
+        assert_eq!(&profile[36..40], b"acsp");
+        assert_eq!(u32_at(profile, 128), 9);
+    }
+
+    #[test]
+    fn srgb_profile_tags_in_bounds() {
+        let gamma = gamma_profile(2.2, 1);
+        for profile in [srgb_profile(), srgb_gray_profile(), &gamma] {
+            for i in 0..u32_at(profile, 128) as usize {
+                let entry = 132 + i * 12;
+                let (offset, size) = (u32_at(profile, entry + 4), u32_at(profile, entry + 8));
+                assert_eq!(offset % 4, 0);
+                assert!((offset + size) as usize <= profile.len());
+            }
+        }
+    }
+
+    #[test]
+    fn gamma_profiles() {
+        let rgb = gamma_profile(2.2, 3);
+        assert_eq!(u32_at(&rgb, 0) as usize, rgb.len());
+        assert_eq!(&rgb[12..24], b"mntrRGB XYZ ");
+        let gray = gamma_profile(1.8, 1);
+        assert_eq!(&gray[12..24], b"mntrGRAYXYZ ");
+        assert_eq!(u32_at(&gray, 128), 4);
+        // the curve is the last tag: one entry, 1.8 in 8.8 fixed point, padded
+        assert_eq!(&gray[gray.len() - 8..], &[0, 0, 0, 1, 0x01, 0xCD, 0, 0][..]);
+    }
+
+    #[test]
+    fn srgb_curve_endpoints() {
+        let curve = curve_tag();
+        let first = u16::from_be_bytes([curve[12], curve[13]]);
+        let last = u16::from_be_bytes([curve[curve.len() - 2], curve[curve.len() - 1]]);
+        assert_eq!((first, last), (0, 65535));
+    }
+}
@@ -0,0 +1,249 @@
+use anyhow::Result;
+use lopdf::{Document, Object, ObjectId};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+
+use crate::info;
+use crate::optimize;
+use crate::parse::ByteSize;
+use crate::pdf;
+
+/// an image XObject, and where it's drawn if anywhere we can follow
+struct ImageUse {
+    page: Option<u32>,
+    id: ObjectId,
+    width: i64,
+    height: i64,
+    filter: String,
+    color: String,
+    bits: i64,
+    bytes: u64,
+    /// largest size drawn on the page, in points
+    drawn: Option<(f32, f32)>,
+}
+
+impl ImageUse {
+    /// pixels per inch across and down, as drawn
+    fn dpi(&self) -> Option<(f64, f64)> {
+        let (w, h) = self.drawn.filter(|&(w, h)| w > 0.0 && h > 0.0)?;
+        let dpi = |pixels: i64, points: f32| (pixels as f64 * 72.0 / points as f64).round();
+        Some((dpi(self.width, w), dpi(self.height, h)))
+    }
+
+    fn to_json(&self) -> ImageJson<'_> {
+        let round = |v: f32| (v as f64 * 100.0).round() / 100.0;
+        let dpi = self.dpi();
+        ImageJson {
+            page: self.page,
+            object: self.id.0,
+            width: self.width,
+            height: self.height,
+            filter: &self.filter,
+            color_space: &self.color,
+            bits: self.bits,
+            bytes: self.bytes,
+            drawn_width: self.drawn.map(|(w, _)| round(w)),
+            drawn_height: self.drawn.map(|(_, h)| round(h)),
+            dpi_x: dpi.map(|(x, _)| x as i64),
+            dpi_y: dpi.map(|(_, y)| y as i64),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ImageJson<'a> {
+    page: Option<u32>,
+    object: u32,
+    width: i64,
+    height: i64,
+    filter: &'a str,
+    color_space: &'a str,
+    bits: i64,
+    bytes: u64,
+    drawn_width: Option<f64>,
+    drawn_height: Option<f64>,
+    /// whole pixels per inch
+    dpi_x: Option<i64>,
+    dpi_y: Option<i64>,
+}
+
+/// list the images a PDF's pages draw, page by page, with their pixel size,
+/// compression, color space, depth and the resolution they're drawn at; as a
+/// table or JSON. images no page draws (that we can follow) come last, without
+/// a page
+pub fn print_images(input: &Path, json: bool) -> Result<()> {
+    let doc = pdf::load(input)?;
+    let uses = collect_images(&doc);
+
+    if json {
+        let all: Vec<ImageJson> = uses.iter().map(ImageUse::to_json).collect();
+        println!("{}", serde_json::to_string(&all)?);
+        return Ok(());
+    }
+    if uses.is_empty() {
+        println!("no images");
+        return Ok(());
+    }
+    let header = [
+        "page", "object", "pixels", "filter", "color", "bits", "size", "drawn (pt)", "dpi",
+    ];
+    let rows: Vec<Vec<String>> = uses
+        .iter()
+        .map(|image| {
+            let dash = || "-".to_string();
+            let drawn = image.drawn.map_or_else(dash, |(w, h)| format!("{:.0}x{:.0}", w, h));
+            let dpi = image.dpi().map_or_else(dash, |(x, y)| {
+                if x == y {
+                    x.to_string()
+                } else {
+                    format!("{}x{}", x, y)
+                }
+            });
+            vec![
+                image.page.map_or_else(dash, |page| page.to_string()),
+                image.id.0.to_string(),
+                format!("{}x{}", image.width, image.height),
+                image.filter.clone(),
+                image.color.clone(),
+                image.bits.to_string(),
+                ByteSize(image.bytes).to_string(),
+                drawn,
+                dpi,
+            ]
+        })
+        .collect();
+    info::print_table(&header, &rows);
+
+    // each image counted once however often it's drawn
+    let mut seen = HashSet::new();
+    let (count, bytes) = uses
+        .iter()
+        .filter(|image| seen.insert(image.id))
+        .fold((0, 0), |(count, bytes), image| (count + 1, bytes + image.bytes));
+    // the sharpness that matters is the lower of the two
+    let range = uses
+        .iter()
+        .filter_map(ImageUse::dpi)
+        .map(|(x, y)| x.min(y))
+        .fold(None, |range: Option<(f64, f64)>, dpi| match range {
+            Some((low, high)) => Some((low.min(dpi), high.max(dpi))),
+            None => Some((dpi, dpi)),
+        });
+    let range = match range {
+        Some((low, high)) if low == high => format!(", drawn at {} dpi", low),
+        Some((low, high)) => format!(", drawn at {}-{} dpi", low, high),
+        None => String::new(),
+    };
+    println!();
+    println!("{} image(s), {}{}", count, ByteSize(bytes), range);
+    Ok(())
+}
+
+/// images drawn on each page in turn, then those no page draws
+fn collect_images(doc: &Document) -> Vec<ImageUse> {
+    let mut uses = Vec::new();
+    let mut drawn = HashSet::new();
+    for (page, page_id) in doc.get_pages() {
+        // ids are in the order the file defines the images, which is stable
+        let placements: BTreeMap<ObjectId, (f32, f32)> =
+            optimize::page_image_placements(doc, page_id).into_iter().collect();
+        for (id, size) in placements {
+            let Ok(obj) = doc.get_object(id) else { continue };
+            drawn.insert(id);
+            uses.extend(describe(doc, Some(page), id, obj, Some(size)));
+        }
+    }
+
+    // masks are part of the image they belong to, not images of their own
+    let mut masks = HashSet::new();
+    for obj in doc.objects.values() {
+        let Ok(stream) = obj.as_stream() else { continue };
+        for key in [b"SMask".as_slice(), b"Mask"] {
+            if let Ok(id) = stream.dict.get(key).and_then(Object::as_reference) {
+                masks.insert(id);
+            }
+        }
+    }
+    for (&id, obj) in &doc.objects {
+        let Ok(stream) = obj.as_stream() else { continue };
+        if stream.dict.get(b"Subtype").and_then(Object::as_name).ok() == Some(b"Image")
+            && !drawn.contains(&id)
+            && !masks.contains(&id)
+        {
+            uses.extend(describe(doc, None, id, obj, None));
+        }
+    }
+    uses
+}
+
+fn describe(
+    doc: &Document,
+    page: Option<u32>,
+    id: ObjectId,
+    obj: &Object,
+    drawn: Option<(f32, f32)>,
+) -> Option<ImageUse> {
+    let stream = obj.as_stream().ok()?;
+    let dict = &stream.dict;
+    let int = |key: &[u8]| {
+        dict.get(key)
+            .and_then(|v| doc.dereference(v))
+            .and_then(|(_, v)| v.as_i64())
+            .unwrap_or(0)
+    };
+    let mask = dict.get(b"ImageMask").and_then(Object::as_bool).unwrap_or(false);
+    let color = match dict.get(b"ColorSpace") {
+        _ if mask => "stencil mask".to_string(),
+        Ok(space) => color_space(doc, space, 0),
+        // JPEG 2000 carries its own
+        Err(_) => "embedded".to_string(),
+    };
+    Some(ImageUse {
+        page,
+        id,
+        width: int(b"Width"),
+        height: int(b"Height"),
+        filter: info::image_filter(doc, obj),
+        color,
+        bits: if mask { 1 } else { int(b"BitsPerComponent") },
+        bytes: stream.content.len() as u64,
+        drawn,
+    })
+}
+
+/// a color space's family, with what an ICC profile or palette is based on:
+/// "DeviceRGB", "ICCBased RGB", "Indexed DeviceRGB"
+fn color_space(doc: &Document, space: &Object, depth: usize) -> String {
+    let name = |obj: &Object| {
+        obj.as_name().map_or("unknown".to_string(), |n| String::from_utf8_lossy(n).into_owned())
+    };
+    let Ok((_, space)) = doc.dereference(space) else {
+        return "unknown".to_string();
+    };
+    let Object::Array(parts) = space else {
+        return name(space);
+    };
+    let Some(family) = parts.first().map(name) else {
+        return "unknown".to_string();
+    };
+    match (family.as_str(), parts.get(1)) {
+        ("ICCBased", Some(profile)) => {
+            let components = doc
+                .dereference(profile)
+                .and_then(|(_, p)| p.as_stream())
+                .and_then(|p| p.dict.get(b"N"))
+                .and_then(Object::as_i64);
+            match components {
+                Ok(1) => "ICCBased Gray".to_string(),
+                Ok(3) => "ICCBased RGB".to_string(),
+                Ok(4) => "ICCBased CMYK".to_string(),
+                _ => family,
+            }
+        }
+        ("Indexed", Some(base)) if depth < 4 => {
+            format!("Indexed {}", color_space(doc, base, depth + 1))
+        }
+        _ => family,
+    }
+}
@@ -0,0 +1,555 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use lopdf::{Document, Object};
+use rayon::prelude::*;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::parse::{
+    parse_jpeg_header, parse_png_header, parse_tiff_resolution, ByteSize, InputFormat, PageSize,
+};
+use crate::pdf::{self, Encryption};
+
+/// print a summary of each input PDF or image, as text or one JSON document
+pub fn print_info(inputs: &[impl AsRef<Path>], json: bool) -> Result<()> {
+    let reports = inputs
+        .iter()
+        .map(|path| inspect(path.as_ref()))
+        .collect::<Result<Vec<_>>>()?;
+    if json {
+        match <[Report; 1]>::try_from(reports) {
+            Ok([report]) => println!("{}", serde_json::to_string(&report.to_json())?),
+            Err(reports) => {
+                let all: Vec<ReportJson> = reports.iter().map(Report::to_json).collect();
+                println!("{}", serde_json::to_string(&all)?);
+            }
+        }
+        return Ok(());
+    }
+    for (i, report) in reports.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        report.print_text();
+    }
+    Ok(())
+}
+
+/// print the page count of each input PDF: just the number for one file, the
+/// number and the path for several; as JSON, an object per file
+pub fn print_page_counts(inputs: &[impl AsRef<Path> + Sync], json: bool) -> Result<()> {
+    let counts = inputs
+        .par_iter()
+        .map(|path| pdf::page_count(path.as_ref()))
+        .collect::<Result<Vec<_>>>()?;
+    if json {
+        let all: Vec<PageCount> = inputs
+            .iter()
+            .zip(&counts)
+            .map(|(path, &pages)| PageCount {
+                file: path.as_ref().display().to_string(),
+                pages,
+            })
+            .collect();
+        match &all[..] {
+            [one] => println!("{}", serde_json::to_string(one)?),
+            _ => println!("{}", serde_json::to_string(&all)?),
+        }
+        return Ok(());
+    }
+    match counts[..] {
+        [pages] => println!("{}", pages),
+        _ => {
+            let width = counts.iter().max().map_or(1, |n| n.to_string().len());
+            for (path, pages) in inputs.iter().zip(&counts) {
+                println!("{:>width$}  {}", pages, path.as_ref().display());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct PageCount {
+    file: String,
+    pages: usize,
+}
+
+enum Report {
+    Pdf(PdfReport),
+    Image(ImageReport),
+}
+
+/// a report as `--json` prints it
+#[derive(Serialize)]
+#[serde(untagged)]
+enum ReportJson<'a> {
+    Pdf(PdfJson<'a>),
+    Image(ImageJson<'a>),
+}
+
+impl Report {
+    fn to_json(&self) -> ReportJson<'_> {
+        match self {
+            Report::Pdf(r) => ReportJson::Pdf(r.to_json()),
+            Report::Image(r) => ReportJson::Image(r.to_json()),
+        }
+    }
+
+    fn print_text(&self) {
+        match self {
+            Report::Pdf(r) => r.print_text(),
+            Report::Image(r) => r.print_text(),
+        }
+    }
+}
+
+fn inspect(path: &Path) -> Result<Report> {
+    let data = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    // the header may follow a little junk, as viewers allow
+    if data.windows(5).take(1024).any(|w| w == b"%PDF-") {
+        drop(data);
+        return inspect_pdf(path).map(Report::Pdf);
+    }
+    inspect_image(path, &data).map(Report::Image)
+}
+
+/// run of consecutive pages sharing a size
+struct SizeRun {
+    width: f32,
+    height: f32,
+    first: usize,
+    last: usize,
+}
+
+struct PdfReport {
+    file: String,
+    file_size: u64,
+    version: String,
+    pages: usize,
+    encryption: Encryption,
+    /// Info dict entries, in display order
+    metadata: Vec<(&'static str, Option<String>)>,
+    sizes: Vec<SizeRun>,
+    image_count: usize,
+    image_bytes: u64,
+    /// image count per compression filter
+    image_filters: BTreeMap<String, usize>,
+}
+
+fn inspect_pdf(path: &Path) -> Result<PdfReport> {
+    let (doc, encryption) = pdf::load_with_encryption(path)?;
+
+    let mut sizes: Vec<SizeRun> = Vec::new();
+    for (i, page_id) in doc.get_pages().into_values().enumerate() {
+        let [x0, y0, x1, y1] = pdf::media_box(&doc, page_id);
+        let rotate = pdf::page_attribute(&doc, page_id, b"Rotate")
+            .and_then(|r| r.as_i64().ok())
+            .unwrap_or(0);
+        // report the size as displayed
+        let (mut w, mut h) = ((x1 - x0).abs(), (y1 - y0).abs());
+        if rotate.rem_euclid(180) == 90 {
+            std::mem::swap(&mut w, &mut h);
+        }
+        match sizes.last_mut() {
+            Some(run) if run.width == w && run.height == h => run.last = i + 1,
+            _ => sizes.push(SizeRun {
+                width: w,
+                height: h,
+                first: i + 1,
+                last: i + 1,
+            }),
+        }
+    }
+
+    let mut image_count = 0;
+    let mut image_bytes = 0;
+    let mut image_filters = BTreeMap::new();
+    for obj in doc.objects.values() {
+        let Ok(stream) = obj.as_stream() else { continue };
+        if stream.dict.get(b"Subtype").and_then(Object::as_name).ok() != Some(b"Image") {
+            continue;
+        }
+        image_count += 1;
+        image_bytes += stream.content.len() as u64;
+        *image_filters.entry(image_filter(&doc, obj)).or_insert(0) += 1;
+    }
+
+    // metadata strings stay encrypted without the password
+    let metadata = ["Title", "Author", "Subject", "Creator", "Producer", "CreationDate", "ModDate"]
+        .map(|key| {
+            let value = match encryption {
+                Encryption::Locked => None,
+                _ => pdf::info_string(&doc, key.as_bytes()),
+            };
+            (key, value)
+        })
+        .to_vec();
+
+    Ok(PdfReport {
+        file: path.display().to_string(),
+        file_size: std::fs::metadata(path).map(|m| m.len()).unwrap_or(0),
+        version: doc.version.clone(),
+        pages: sizes.last().map_or(0, |run| run.last),
+        encryption,
+        metadata,
+        sizes,
+        image_count,
+        image_bytes,
+        image_filters,
+    })
+}
+
+/// the filter that decodes to pixels (the last of a filter chain), or "none"
+pub fn image_filter(doc: &Document, obj: &Object) -> String {
+    let filter = obj
+        .as_stream()
+        .ok()
+        .and_then(|s| s.dict.get(b"Filter").ok())
+        .and_then(|f| doc.dereference(f).ok())
+        .map(|(_, f)| f);
+    let name = match filter {
+        Some(Object::Name(name)) => Some(name.as_slice()),
+        Some(Object::Array(names)) => names.last().and_then(|n| n.as_name().ok()),
+        _ => None,
+    };
+    name.map_or("none".to_string(), |n| String::from_utf8_lossy(n).into_owned())
+}
+
+/// standard paper name for a page size in points, either orientation
+fn paper_name(width: f32, height: f32) -> Option<String> {
+    PageSize::value_variants().iter().find_map(|size| {
+        let (w, h) = size.dimensions_pt();
+        let close = |a: f32, b: f32| (a - b).abs() < 1.0;
+        ((close(w, width) && close(h, height)) || (close(w, height) && close(h, width)))
+            .then(|| size.to_possible_value().unwrap().get_name().to_string())
+    })
+}
+
+fn encryption_label(encryption: Encryption) -> &'static str {
+    match encryption {
+        Encryption::None => "no",
+        Encryption::EmptyPassword => "yes (no user password)",
+        Encryption::Locked => "yes (password required)",
+    }
+}
+
+#[derive(Serialize)]
+struct PdfJson<'a> {
+    file: &'a str,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    file_size: u64,
+    version: &'a str,
+    pages: usize,
+    encrypted: bool,
+    password_required: bool,
+    /// Info dict entries under snake_case keys, in display order
+    metadata: Map<String, Value>,
+    page_sizes: Vec<SizeJson>,
+    images: ImagesJson<'a>,
+}
+
+#[derive(Serialize)]
+struct SizeJson {
+    width: f32,
+    height: f32,
+    paper: Option<String>,
+    first_page: usize,
+    last_page: usize,
+}
+
+#[derive(Serialize)]
+struct ImagesJson<'a> {
+    count: usize,
+    bytes: u64,
+    filters: &'a BTreeMap<String, usize>,
+}
+
+impl PdfReport {
+    fn to_json(&self) -> PdfJson<'_> {
+        PdfJson {
+            file: &self.file,
+            kind: "pdf",
+            file_size: self.file_size,
+            version: &self.version,
+            pages: self.pages,
+            encrypted: self.encryption != Encryption::None,
+            password_required: self.encryption == Encryption::Locked,
+            metadata: self
+                .metadata
+                .iter()
+                .map(|(key, value)| (snake_case(key), value.clone().into()))
+                .collect(),
+            page_sizes: self
+                .sizes
+                .iter()
+                .map(|run| SizeJson {
+                    width: run.width,
+                    height: run.height,
+                    paper: paper_name(run.width, run.height),
+                    first_page: run.first,
+                    last_page: run.last,
+                })
+                .collect(),
+            images: ImagesJson {
+                count: self.image_count,
+                bytes: self.image_bytes,
+                filters: &self.image_filters,
+            },
+        }
+    }
+
+    fn print_text(&self) {
+        println!("{:<14}{}", "File:", self.file);
+        println!("{:<14}PDF {}", "Format:", self.version);
+        println!("{:<14}{}", "File size:", ByteSize(self.file_size));
+        println!("{:<14}{}", "Pages:", self.pages);
+        println!("{:<14}{}", "Encrypted:", encryption_label(self.encryption));
+        for (key, value) in &self.metadata {
+            if let Some(value) = value {
+                println!("{:<14}{}", format!("{}:", key), value);
+            }
+        }
+        for (i, run) in self.sizes.iter().enumerate() {
+            let label = if i == 0 { "Page size:" } else { "" };
+            let paper = paper_name(run.width, run.height)
+                .map_or(String::new(), |name| format!(", {}", name));
+            let pages = if run.first == run.last {
+                format!("page {}", run.first)
+            } else {
+                format!("pages {}-{}", run.first, run.last)
+            };
+            println!(
+                "{:<14}{} x {} pt{} ({})",
+                label,
+                trim_float(run.width),
+                trim_float(run.height),
+                paper,
+                pages
+            );
+        }
+        let filters = self
+            .image_filters
+            .iter()
+            .map(|(filter, count)| format!("{} {}", count, filter))
+            .collect::<Vec<_>>()
+            .join(", ");
+        if self.image_count > 0 {
+            println!(
+                "{:<14}{} ({}), {}",
+                "Images:",
+                self.image_count,
+                filters,
+                ByteSize(self.image_bytes)
+            );
+        } else {
+            println!("{:<14}0", "Images:");
+        }
+    }
+}
+
+struct ImageReport {
+    file: String,
+    file_size: u64,
+    format: InputFormat,
+    width: u32,
+    height: u32,
+    dpi: Option<(u32, u32)>,
+    color: String,
+    bit_depth: u8,
+    icc_profile: bool,
+    interlaced: bool,
+}
+
+fn inspect_image(path: &Path, data: &[u8]) -> Result<ImageReport> {
+    let format = InputFormat::sniff(data)
+        .with_context(|| format!("Not a PDF or supported image: {}", path.display()))?;
+    let base = |width, height, dpi, color: &str, bit_depth, icc_profile, interlaced| ImageReport {
+        file: path.display().to_string(),
+        file_size: data.len() as u64,
+        format,
+        width,
+        height,
+        dpi,
+        color: color.to_string(),
+        bit_depth,
+        icc_profile,
+        interlaced,
+    };
+    match format {
+        InputFormat::Jpeg => {
+            let info = parse_jpeg_header(data)
+                .with_context(|| format!("Failed to parse JPEG header: {}", path.display()))?;
+            let color = match info.components {
+                1 => "gray",
+                3 => "rgb",
+                4 => "cmyk",
+                _ => "unknown",
+            };
+            let icc = info.icc_profile.is_some();
+            Ok(base(info.width, info.height, info.dpi, color, 8, icc, false))
+        }
+        InputFormat::Png => {
+            let info = parse_png_header(data)
+                .with_context(|| format!("Failed to parse PNG header: {}", path.display()))?;
+            let color = match info.color_type {
+                0 => "gray",
+                2 => "rgb",
+                3 => "palette",
+                4 => "gray+alpha",
+                6 => "rgba",
+                _ => "unknown",
+            };
+            let icc = info.icc_profile.is_some();
+            let interlaced = info.interlace != 0;
+            Ok(base(info.width, info.height, info.dpi, color, info.bit_depth, icc, interlaced))
+        }
+        InputFormat::Tiff | InputFormat::Bmp | InputFormat::Gif => {
+            use image::ImageDecoder;
+
+            let mut decoder = image::ImageReader::new(std::io::Cursor::new(data))
+                .with_guessed_format()?
+                .into_decoder()
+                .with_context(|| format!("Failed to read image header: {}", path.display()))?;
+            let (width, height) = decoder.dimensions();
+            let color_type = decoder.original_color_type();
+            let channels = color_type.channel_count().max(1);
+            let bit_depth = (color_type.bits_per_pixel() / channels as u16) as u8;
+            let color = color_name(color_type);
+            let icc = decoder.icc_profile().ok().flatten().is_some();
+            let dpi = match format {
+                InputFormat::Tiff => parse_tiff_resolution(data),
+                _ => None,
+            };
+            Ok(base(width, height, dpi, color, bit_depth, icc, false))
+        }
+    }
+}
+
+fn color_name(color_type: image::ExtendedColorType) -> &'static str {
+    use image::ExtendedColorType as C;
+    match color_type {
+        C::L1 | C::L2 | C::L4 | C::L8 | C::L16 => "gray",
+        C::La1 | C::La2 | C::La4 | C::La8 | C::La16 => "gray+alpha",
+        C::Rgb1 | C::Rgb2 | C::Rgb4 | C::Rgb8 | C::Rgb16 | C::Rgb32F => "rgb",
+        C::Rgba1 | C::Rgba2 | C::Rgba4 | C::Rgba8 | C::Rgba16 | C::Rgba32F => "rgba",
+        C::Bgr8 => "rgb",
+        C::Bgra8 => "rgba",
+        C::Cmyk8 | C::Cmyk16 => "cmyk",
+        _ => "unknown",
+    }
+}
+
+#[derive(Serialize)]
+struct ImageJson<'a> {
+    file: &'a str,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    file_size: u64,
+    format: &'static str,
+    width: u32,
+    height: u32,
+    /// `[x, y]`
+    dpi: Option<(u32, u32)>,
+    color: &'a str,
+    bit_depth: u8,
+    icc_profile: bool,
+    interlaced: bool,
+}
+
+impl ImageReport {
+    fn to_json(&self) -> ImageJson<'_> {
+        ImageJson {
+            file: &self.file,
+            kind: "image",
+            file_size: self.file_size,
+            format: self.format.name(),
+            width: self.width,
+            height: self.height,
+            dpi: self.dpi,
+            color: &self.color,
+            bit_depth: self.bit_depth,
+            icc_profile: self.icc_profile,
+            interlaced: self.interlaced,
+        }
+    }
+
+    fn print_text(&self) {
+        println!("{:<14}{}", "File:", self.file);
+        println!("{:<14}{}", "Format:", self.format.name());
+        println!("{:<14}{}", "File size:", ByteSize(self.file_size));
+        println!("{:<14}{} x {} px", "Dimensions:", self.width, self.height);
+        if let Some((x, y)) = self.dpi {
+            println!("{:<14}{} x {}", "DPI:", x, y);
+        }
+        println!("{:<14}{}, {}-bit", "Color:", self.color, self.bit_depth);
+        println!("{:<14}{}", "ICC profile:", if self.icc_profile { "yes" } else { "no" });
+        if self.interlaced {
+            println!("{:<14}yes", "Interlaced:");
+        }
+    }
+}
+
+/// rows under a header, each column as wide as its widest cell
+pub fn print_table(header: &[&str], rows: &[Vec<String>]) {
+    let widths: Vec<usize> = header
+        .iter()
+        .enumerate()
+        .map(|(c, name)| {
+            let cells = rows.iter().map(|r| r[c].chars().count());
+            cells.fold(name.len(), usize::max)
+        })
+        .collect();
+    let line = |cells: Vec<&str>| {
+        let padded: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, &width)| format!("{:<width$}", cell))
+            .collect();
+        println!("{}", padded.join("  ").trim_end());
+    };
+    line(header.to_vec());
+    for row in rows {
+        line(row.iter().map(String::as_str).collect());
+    }
+}
+
+/// "CreationDate" -> "creation_date"
+fn snake_case(key: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in key.chars().enumerate() {
+        if c.is_ascii_uppercase() && i > 0 {
+            out.push('_');
+        }
+        out.push(c.to_ascii_lowercase());
+    }
+    out
+}
+
+/// "612" rather than "612.0", at most two decimals otherwise
+fn trim_float(v: f32) -> String {
+    let s = format!("{:.2}", v);
+    s.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paper_names() {
+        assert_eq!(paper_name(612.0, 792.0).as_deref(), Some("letter"));
+        assert_eq!(paper_name(842.0, 595.0).as_deref(), Some("a4"));
+        assert_eq!(paper_name(100.0, 100.0), None);
+    }
+
+    #[test]
+    fn helpers() {
+        assert_eq!(snake_case("CreationDate"), "creation_date");
+        assert_eq!(trim_float(612.0), "612");
+        assert_eq!(trim_float(595.276), "595.28");
+    }
+}
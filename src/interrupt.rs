@@ -0,0 +1,76 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// exit status of a run stopped by SIGINT or SIGTERM, what a shell reports for
+/// a process killed by SIGINT
+pub const EXIT_CODE: i32 = 130;
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// a handle to stop a split or merge from another thread: set it as the run's
+/// `cancel` option and call [`CancelToken::cancel`]. no new pages are started,
+/// pages in flight finish or are dropped, and the run returns [`Interrupted`].
+/// clones share the same cancellation
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// true once cancelled, or once the whole process was asked to stop with
+    /// [`request`]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed) || requested()
+    }
+}
+
+/// the error of a split, merge or batch that was cancelled, with how far it
+/// got. what it had left half-written is removed first; `files` are the whole
+/// ones it keeps: a split's saved images, a batch's finished outputs, and
+/// nothing from a merge
+#[derive(Debug)]
+pub struct Interrupted {
+    /// pages, images or jobs finished
+    pub done: usize,
+    pub total: usize,
+    pub files: Vec<PathBuf>,
+}
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "interrupted ({} of {} done)", self.done, self.total)
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+/// cancel every run in the process, whatever its token
+pub fn request() {
+    REQUESTED.store(true, Ordering::SeqCst);
+}
+
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::Relaxed)
+}
+
+/// stop on the first SIGINT or SIGTERM (Ctrl-C on Windows) with [`request`],
+/// and exit at once, cleanup or not, on the second
+#[cfg(feature = "native")]
+pub fn install() -> anyhow::Result<()> {
+    use anyhow::Context;
+    ctrlc::set_handler(|| {
+        if REQUESTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(EXIT_CODE);
+        }
+        tracing::warn!("interrupted, stopping (again to quit at once)");
+    })
+    .context("Failed to install the signal handler")
+}
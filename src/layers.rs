@@ -0,0 +1,394 @@
+use anyhow::{bail, Context, Result};
+use lopdf::content::{Content, Operation};
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use tracing::info;
+
+use crate::info;
+use crate::pdf;
+
+/// an optional content group, as the default configuration shows it
+#[derive(Serialize)]
+struct Layer {
+    #[serde(skip)]
+    id: ObjectId,
+    name: String,
+    on: bool,
+    locked: bool,
+}
+
+/// what to do with the layers picked out by name
+pub struct LayerChanges<'a> {
+    pub on: &'a [String],
+    pub off: &'a [String],
+    /// layers to delete, with everything drawn in them
+    pub remove: &'a [String],
+}
+
+/// print a PDF's layers (optional content groups), whether each shows by default
+/// and whether viewers let it be switched, as a table or JSON
+pub fn list_layers(input: &Path, json: bool) -> Result<()> {
+    let doc = pdf::load(input)?;
+    let layers = read_layers(&doc);
+    if json {
+        println!("{}", serde_json::to_string(&layers)?);
+    } else if layers.is_empty() {
+        println!("no layers");
+    } else {
+        let rows: Vec<Vec<String>> = layers
+            .iter()
+            .map(|layer| {
+                let yes_no = if layer.locked { "yes" } else { "no" };
+                let state = if layer.on { "on" } else { "off" };
+                vec![layer.name.clone(), state.to_string(), yes_no.to_string()]
+            })
+            .collect();
+        info::print_table(&["name", "default", "locked"], &rows);
+    }
+    Ok(())
+}
+
+/// save a copy of the PDF with layers switched on or off by default, which is
+/// what viewers and renderers show, or removed along with their content. a name
+/// picks every layer called that
+pub fn set_layers(
+    input: &Path,
+    changes: &LayerChanges,
+    output: &Path,
+    quiet: bool,
+) -> Result<()> {
+    let mut doc = pdf::load(input)?;
+    let layers = read_layers(&doc);
+    if layers.is_empty() {
+        bail!("{} has no layers", input.display());
+    }
+    let pick = |names: &[String]| -> Result<HashSet<ObjectId>> {
+        let mut ids = HashSet::new();
+        for name in names {
+            let found: Vec<ObjectId> =
+                layers.iter().filter(|l| l.name == *name).map(|l| l.id).collect();
+            if found.is_empty() {
+                let known: Vec<&str> = layers.iter().map(|l| l.name.as_str()).collect();
+                bail!("No layer named '{}' (layers: {})", name, known.join(", "));
+            }
+            ids.extend(found);
+        }
+        Ok(ids)
+    };
+    let (on, off, remove) = (pick(changes.on)?, pick(changes.off)?, pick(changes.remove)?);
+    let mut twice = on.intersection(&off).chain(on.intersection(&remove));
+    if let Some(id) = twice.next().or_else(|| off.intersection(&remove).next()) {
+        let name = &layers.iter().find(|l| l.id == *id).unwrap().name;
+        bail!("Layer '{}' is given more than one state", name);
+    }
+
+    let states: Vec<(ObjectId, bool)> = layers
+        .iter()
+        .filter(|layer| !remove.contains(&layer.id))
+        .map(|layer| {
+            let shown = on.contains(&layer.id) || (layer.on && !off.contains(&layer.id));
+            (layer.id, shown)
+        })
+        .collect();
+    let mut removed_ops = 0;
+    if !remove.is_empty() {
+        removed_ops = remove_content(&mut doc, &remove)?;
+    }
+    write_config(&mut doc, &states, &remove)?;
+    doc.prune_objects();
+    pdf::save(&mut doc, output)?;
+
+    if !quiet {
+        let mut done = Vec::new();
+        if !on.is_empty() {
+            done.push(format!("{} on", on.len()));
+        }
+        if !off.is_empty() {
+            done.push(format!("{} off", off.len()));
+        }
+        if !remove.is_empty() {
+            done.push(format!("{} removed ({} drawing operations)", remove.len(), removed_ops));
+        }
+        info!("layers: {} -> {}", done.join(", "), output.display());
+    }
+    Ok(())
+}
+
+fn oc_properties(doc: &Document) -> Option<&Dictionary> {
+    let props = doc.catalog().ok()?.get(b"OCProperties").ok()?;
+    doc.dereference(props).ok()?.1.as_dict().ok()
+}
+
+/// references in an array entry of a dictionary, resolving either
+fn id_list(doc: &Document, dict: &Dictionary, key: &[u8]) -> Vec<ObjectId> {
+    let Ok((_, Object::Array(items))) = dict.get(key).and_then(|a| doc.dereference(a)) else {
+        return Vec::new();
+    };
+    items.iter().filter_map(|item| item.as_reference().ok()).collect()
+}
+
+fn read_layers(doc: &Document) -> Vec<Layer> {
+    let Some(props) = oc_properties(doc) else {
+        return Vec::new();
+    };
+    let config = props
+        .get(b"D")
+        .and_then(|d| doc.dereference(d))
+        .and_then(|(_, d)| d.as_dict())
+        .ok();
+    let base_off = config
+        .and_then(|d| d.get(b"BaseState").and_then(Object::as_name).ok())
+        .is_some_and(|base| base == b"OFF");
+    let listed = |key: &[u8]| config.map_or_else(HashSet::new, |d| {
+        id_list(doc, d, key).into_iter().collect::<HashSet<_>>()
+    });
+    let (on, off, locked) = (listed(b"ON"), listed(b"OFF"), listed(b"Locked"));
+    id_list(doc, props, b"OCGs")
+        .into_iter()
+        .map(|id| {
+            let name = doc
+                .get_dictionary(id)
+                .and_then(|ocg| ocg.get(b"Name"))
+                .and_then(|n| doc.dereference(n))
+                .and_then(|(_, n)| n.as_str())
+                .map(pdf::decode_text)
+                .unwrap_or_default();
+            Layer {
+                id,
+                name,
+                on: if base_off { on.contains(&id) } else { !off.contains(&id) },
+                locked: locked.contains(&id),
+            }
+        })
+        .collect()
+}
+
+/// rewrite the default configuration with every remaining layer's state spelled
+/// out, and take removed layers out of every configuration
+fn write_config(
+    doc: &mut Document,
+    states: &[(ObjectId, bool)],
+    remove: &HashSet<ObjectId>,
+) -> Result<()> {
+    let catalog_id = doc.trailer.get(b"Root").and_then(Object::as_reference)?;
+    let props_ref = doc.get_dictionary(catalog_id)?.get(b"OCProperties").cloned()?;
+    if states.is_empty() {
+        doc.get_dictionary_mut(catalog_id)?.remove(b"OCProperties");
+        return Ok(());
+    }
+    let mut props = doc.dereference(&props_ref)?.1.as_dict()?.clone();
+    let refs = |on: bool| -> Vec<Object> {
+        states.iter().filter(|s| s.1 == on).map(|s| Object::Reference(s.0)).collect()
+    };
+    props.set("OCGs", states.iter().map(|s| Object::Reference(s.0)).collect::<Vec<_>>());
+
+    let mut default = match props.get(b"D").map(|d| doc.dereference(d)) {
+        Ok(Ok((_, Object::Dictionary(d)))) => d.clone(),
+        _ => Dictionary::new(),
+    };
+    default.remove(b"BaseState");
+    default.set("ON", refs(true));
+    default.set("OFF", refs(false));
+    let mut default = Object::Dictionary(default);
+    drop_ids(&mut default, remove);
+    props.set("D", default);
+    if let Ok(configs) = props.get_mut(b"Configs") {
+        // alternate configurations are usually inline; referenced ones are left
+        drop_ids(configs, remove);
+    }
+
+    if let Object::Reference(id) = props_ref {
+        doc.objects.insert(id, Object::Dictionary(props));
+    } else {
+        doc.get_dictionary_mut(catalog_id)?.set("OCProperties", props);
+    }
+    Ok(())
+}
+
+/// remove references to `ids` from a configuration's arrays, nested ones (Order,
+/// radio button groups) included
+fn drop_ids(obj: &mut Object, ids: &HashSet<ObjectId>) {
+    match obj {
+        Object::Array(items) => {
+            items.retain(|item| item.as_reference().map_or(true, |id| !ids.contains(&id)));
+            items.iter_mut().for_each(|item| drop_ids(item, ids));
+        }
+        Object::Dictionary(dict) => {
+            for (_, value) in dict.iter_mut() {
+                drop_ids(value, ids);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// delete what removed layers draw: marked content tagged with them on pages and
+/// in forms, XObjects and annotations that belong to them. returns how many
+/// content operations were dropped
+fn remove_content(doc: &mut Document, remove: &HashSet<ObjectId>) -> Result<usize> {
+    // membership dictionaries hide with their layers when nothing else shows them
+    let hidden = |doc: &Document, oc: &Object| -> bool {
+        let Ok((id, Object::Dictionary(dict))) = doc.dereference(oc) else {
+            return false;
+        };
+        if id.is_some_and(|id| remove.contains(&id)) {
+            return true;
+        }
+        if dict.get(b"Type").and_then(Object::as_name).ok() != Some(b"OCMD") {
+            return false;
+        }
+        // one group, or an array of them
+        let members = match dict.get(b"OCGs").and_then(|m| doc.dereference(m)) {
+            Ok((Some(id), Object::Dictionary(_))) => vec![id],
+            _ => id_list(doc, dict, b"OCGs"),
+        };
+        let policy = dict.get(b"P").and_then(Object::as_name).unwrap_or(b"AnyOn");
+        match policy {
+            b"AnyOn" => !members.is_empty() && members.iter().all(|id| remove.contains(id)),
+            b"AllOn" => members.iter().any(|id| remove.contains(id)),
+            _ => false,
+        }
+    };
+
+    let mut dropped = 0;
+    let page_ids: Vec<ObjectId> = doc.get_pages().into_values().collect();
+    for &page_id in &page_ids {
+        let resources = pdf::page_attribute(doc, page_id, b"Resources").cloned();
+        let Ok(content) = doc.get_and_decode_page_content(page_id) else {
+            continue;
+        };
+        let (kept, n) = filter_content(doc, content, resources.as_ref(), &hidden);
+        if n > 0 {
+            dropped += n;
+            let mut stream = Stream::new(Dictionary::new(), kept.encode()?);
+            stream.compress()?;
+            let stream_id = doc.add_object(stream);
+            doc.get_dictionary_mut(page_id)?.set("Contents", stream_id);
+        }
+
+        // annotations that belong to a removed layer
+        let annots = match doc.get_dictionary(page_id)?.get(b"Annots") {
+            Ok(annots) => match doc.dereference(annots) {
+                Ok((_, Object::Array(annots))) => annots.clone(),
+                _ => continue,
+            },
+            Err(_) => continue,
+        };
+        let kept: Vec<Object> = annots
+            .iter()
+            .filter(|annot| {
+                let oc = doc
+                    .dereference(annot)
+                    .ok()
+                    .and_then(|(_, a)| a.as_dict().ok())
+                    .and_then(|a| a.get(b"OC").ok());
+                !oc.is_some_and(|oc| hidden(doc, oc))
+            })
+            .cloned()
+            .collect();
+        if kept.len() < annots.len() {
+            doc.get_dictionary_mut(page_id)?.set("Annots", kept);
+        }
+    }
+
+    // forms with marked content of their own
+    let forms: Vec<ObjectId> = doc
+        .objects
+        .iter()
+        .filter(|(_, obj)| {
+            obj.as_stream().is_ok_and(|s| {
+                s.dict.get(b"Subtype").and_then(Object::as_name).ok() == Some(b"Form")
+            })
+        })
+        .map(|(&id, _)| id)
+        .collect();
+    for form_id in forms {
+        let stream = doc.get_object(form_id).and_then(Object::as_stream)?;
+        let resources = match stream.dict.get(b"Resources") {
+            Ok(resources) => Some(doc.dereference(resources)?.1.clone()),
+            Err(_) => None,
+        };
+        let Ok(content) = stream.get_plain_content().map(|c| Content::decode(&c)) else {
+            continue;
+        };
+        let Ok(content) = content else { continue };
+        let (kept, n) = filter_content(doc, content, resources.as_ref(), &hidden);
+        if n > 0 {
+            dropped += n;
+            let encoded = kept.encode().context("Failed to write form content")?;
+            let stream = doc.get_object_mut(form_id).and_then(Object::as_stream_mut)?;
+            stream.set_plain_content(encoded);
+            stream.compress()?;
+        }
+    }
+    Ok(dropped)
+}
+
+/// content without marked sections tagged with hidden optional content, and
+/// without XObjects that are themselves hidden; returns how many operations went
+fn filter_content(
+    doc: &Document,
+    content: Content,
+    resources: Option<&Object>,
+    hidden: &impl Fn(&Document, &Object) -> bool,
+) -> (Content, usize) {
+    let category = |key: &[u8]| {
+        resources
+            .and_then(|r| doc.dereference(r).ok())
+            .and_then(|(_, r)| r.as_dict().ok())
+            .and_then(|r| r.get(key).ok())
+            .and_then(|c| doc.dereference(c).ok())
+            .and_then(|(_, c)| c.as_dict().ok())
+    };
+    let (properties, xobjects) = (category(b"Properties"), category(b"XObject"));
+    // (is an XObject, resource name) -> hidden
+    let mut cache: HashMap<(bool, Vec<u8>), bool> = HashMap::new();
+    let mut hidden_name = |xobject: bool, name: &[u8]| -> bool {
+        *cache.entry((xobject, name.to_vec())).or_insert_with(|| {
+            let dict = if xobject { xobjects } else { properties };
+            let Some(target) = dict.and_then(|d| d.get(name).ok()) else {
+                return false;
+            };
+            if !xobject {
+                return hidden(doc, target);
+            }
+            // an XObject is hidden by its /OC entry
+            doc.dereference(target)
+                .ok()
+                .and_then(|(_, x)| x.as_stream().ok())
+                .and_then(|x| x.dict.get(b"OC").ok())
+                .is_some_and(|oc| hidden(doc, oc))
+        })
+    };
+
+    let total = content.operations.len();
+    let mut kept: Vec<Operation> = Vec::with_capacity(total);
+    // open marked sections, and how deep inside a hidden one we are
+    let mut skipping = 0usize;
+    for op in content.operations {
+        let name = op.operands.first().and_then(|o| o.as_name().ok());
+        let tag = op.operands.get(1).and_then(|p| p.as_name().ok());
+        match op.operator.as_str() {
+            "BMC" | "BDC" if skipping > 0 => {
+                skipping += 1;
+                continue;
+            }
+            "BDC" if name == Some(b"OC") && tag.is_some_and(|tag| hidden_name(false, tag)) => {
+                skipping = 1;
+                continue;
+            }
+            "EMC" if skipping > 0 => {
+                skipping -= 1;
+                continue;
+            }
+            "Do" if name.is_some_and(|n| hidden_name(true, n)) => continue,
+            _ if skipping > 0 => continue,
+            _ => {}
+        }
+        kept.push(op);
+    }
+    let dropped = total - kept.len();
+    (Content { operations: kept }, dropped)
+}
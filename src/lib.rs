@@ -0,0 +1,134 @@
+//! ovid's conversion pipelines, for use without the command-line tool:
+//! [`split::split_pdf`] renders PDF pages to images, [`merge::merge_images`]
+//! builds a PDF or EPUB from images, and [`parse`] has the values their options
+//! take. [`task`] runs them in the background, as futures a host can await or
+//! cancel with an [`interrupt::CancelToken`]. the other public modules back the
+//! rest of the `ovid` subcommands and may change between releases
+//!
+//! rendering needs MuPDF and libjpeg-turbo, which come with the default
+//! `native` feature. without it the rendering modules are left out and the
+//! merge builds on its own, for wasm32 too; the `wasm` feature adds JavaScript
+//! bindings for it in the `wasm` module
+
+pub mod interrupt;
+pub mod merge;
+pub mod parse;
+pub mod progress;
+pub mod stats;
+#[cfg(feature = "native")]
+pub mod split;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod task;
+
+#[doc(hidden)]
+pub mod annots;
+mod archive;
+#[cfg(feature = "native")]
+#[doc(hidden)]
+pub mod batch;
+#[doc(hidden)]
+pub mod blank;
+#[cfg(feature = "native")]
+mod cache;
+#[doc(hidden)]
+pub mod chunk;
+#[doc(hidden)]
+pub mod combine;
+#[cfg(feature = "native")]
+#[doc(hidden)]
+pub mod compare;
+#[cfg(feature = "native")]
+#[doc(hidden)]
+pub mod convert;
+#[doc(hidden)]
+pub mod crop;
+mod crypto;
+#[cfg(feature = "native")]
+#[doc(hidden)]
+pub mod dedupe;
+mod deflate;
+mod deskew;
+#[cfg(feature = "native")]
+#[doc(hidden)]
+pub mod doctor;
+#[doc(hidden)]
+pub mod encrypt;
+mod epub;
+#[cfg(feature = "native")]
+#[doc(hidden)]
+pub mod fetch;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[doc(hidden)]
+pub mod flatten;
+#[doc(hidden)]
+pub mod fonts;
+#[doc(hidden)]
+pub mod form;
+#[cfg(feature = "native")]
+#[doc(hidden)]
+pub mod grid;
+#[cfg(feature = "native")]
+#[doc(hidden)]
+pub mod hash;
+mod icc;
+#[doc(hidden)]
+pub mod images;
+#[doc(hidden)]
+pub mod info;
+#[doc(hidden)]
+pub mod layers;
+#[doc(hidden)]
+pub mod links;
+#[cfg(feature = "native")]
+#[doc(hidden)]
+pub mod logging;
+#[doc(hidden)]
+pub mod meta;
+#[doc(hidden)]
+pub mod nup;
+#[doc(hidden)]
+pub mod optimize;
+#[doc(hidden)]
+pub mod outline;
+#[doc(hidden)]
+pub mod overlay;
+#[doc(hidden)]
+pub mod pages;
+mod pdf;
+mod pkcs;
+#[doc(hidden)]
+pub mod poster;
+#[cfg(feature = "native")]
+#[doc(hidden)]
+pub mod rasterize;
+#[cfg(feature = "native")]
+#[doc(hidden)]
+pub mod redact;
+#[cfg(feature = "native")]
+#[doc(hidden)]
+pub mod repair;
+#[doc(hidden)]
+pub mod rotate;
+#[cfg(feature = "native")]
+#[doc(hidden)]
+pub mod serve;
+#[doc(hidden)]
+pub mod sign;
+#[doc(hidden)]
+pub mod stamp;
+#[cfg(feature = "native")]
+#[doc(hidden)]
+pub mod text;
+#[cfg(feature = "native")]
+mod tmp;
+#[doc(hidden)]
+pub mod validate;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "native")]
+#[doc(hidden)]
+pub mod watch;
+#[doc(hidden)]
+pub mod watermark;
+mod writer;
@@ -0,0 +1,203 @@
+use anyhow::{Context, Result};
+use lopdf::{Dictionary, Document, Object, ObjectId, StringFormat};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use tracing::info;
+
+use crate::outline;
+use crate::parse::UriRewrite;
+use crate::pdf;
+
+/// print a PDF's links as a JSON array, one link per line, or write it to
+/// `output`: where each sits and what it opens, a web address, a page of the
+/// document or another file
+pub fn export_links(input: &Path, output: Option<&Path>) -> Result<()> {
+    let doc = pdf::load(input)?;
+    let page_numbers: HashMap<ObjectId, u32> =
+        doc.get_pages().into_iter().map(|(n, id)| (id, n)).collect();
+    let lines: Vec<String> = link_annotations(&doc)
+        .into_iter()
+        .map(|(page, _, link)| to_json(&doc, page, link, &page_numbers))
+        .map(|link| format!("  {}", serde_json::to_string(&link).expect("links are plain objects")))
+        .collect();
+    let text = if lines.is_empty() {
+        "[]\n".to_string()
+    } else {
+        format!("[\n{}\n]\n", lines.join(",\n"))
+    };
+    match output {
+        Some(path) if path != Path::new("-") => std::fs::write(path, text)
+            .with_context(|| format!("Failed to save {}", path.display()))?,
+        _ => print!("{}", text),
+    }
+    Ok(())
+}
+
+/// save a copy of the PDF with its web links rewritten: a URI starting with a
+/// rule's `from` gets `to` in its place, the rules applying one after another
+pub fn rewrite_links(
+    input: &Path,
+    rules: &[UriRewrite],
+    output: &Path,
+    quiet: bool,
+) -> Result<()> {
+    let mut doc = pdf::load(input)?;
+    // actions are often shared objects; each is rewritten once
+    let mut actions = Vec::new();
+    let mut seen = HashSet::new();
+    for (_, id, link) in link_annotations(&doc) {
+        match link.get(b"A") {
+            Ok(Object::Reference(action)) if seen.insert(*action) => {
+                actions.push(Action::Object(*action))
+            }
+            Ok(Object::Dictionary(_)) => actions.extend(id.map(Action::InAnnotation)),
+            _ => {}
+        }
+    }
+
+    let mut rewritten = 0;
+    for action in actions {
+        let dict = match action {
+            Action::Object(id) => doc.get_dictionary_mut(id).ok(),
+            Action::InAnnotation(id) => doc
+                .get_dictionary_mut(id)
+                .and_then(|annot| annot.get_mut(b"A"))
+                .and_then(Object::as_dict_mut)
+                .ok(),
+        };
+        let Some(dict) = dict else {
+            continue;
+        };
+        if dict.get(b"S").and_then(Object::as_name).ok() != Some(b"URI") {
+            continue;
+        }
+        let Ok(Object::String(uri, _)) = dict.get(b"URI") else {
+            continue;
+        };
+        let uri = String::from_utf8_lossy(uri).into_owned();
+        let replaced = rules.iter().fold(uri.clone(), |uri, rule| {
+            match uri.strip_prefix(rule.from.as_str()) {
+                Some(rest) => format!("{}{}", rule.to, rest),
+                None => uri,
+            }
+        });
+        if replaced != uri {
+            dict.set("URI", Object::String(replaced.into_bytes(), StringFormat::Literal));
+            rewritten += 1;
+        }
+    }
+    pdf::save(&mut doc, output)?;
+    if !quiet {
+        info!("{} link(s) rewritten -> {}", rewritten, output.display());
+    }
+    Ok(())
+}
+
+/// where a link's action dictionary lives
+enum Action {
+    Object(ObjectId),
+    /// inline in the annotation with this id
+    InAnnotation(ObjectId),
+}
+
+/// (page number, object id if indirect, dictionary) of every link annotation
+fn link_annotations(doc: &Document) -> Vec<(u32, Option<ObjectId>, &Dictionary)> {
+    let mut found = Vec::new();
+    for (page, page_id) in doc.get_pages() {
+        let Ok(Ok((_, Object::Array(annots)))) = doc
+            .get_dictionary(page_id)
+            .and_then(|p| p.get(b"Annots"))
+            .map(|a| doc.dereference(a))
+        else {
+            continue;
+        };
+        for annot in annots {
+            let Ok((id, Object::Dictionary(dict))) = doc.dereference(annot) else {
+                continue;
+            };
+            if dict.get(b"Subtype").and_then(Object::as_name).ok() == Some(b"Link") {
+                found.push((page, id, dict));
+            }
+        }
+    }
+    found
+}
+
+/// a link as `links` exports it
+#[derive(Serialize)]
+struct LinkJson {
+    page: u32,
+    rect: Option<Vec<f64>>,
+    action: Option<String>,
+    uri: Option<String>,
+    dest_page: Option<u32>,
+    file: Option<String>,
+}
+
+fn to_json(
+    doc: &Document,
+    page: u32,
+    link: &Dictionary,
+    page_numbers: &HashMap<ObjectId, u32>,
+) -> LinkJson {
+    let rect = doc
+        .dereference(link.get(b"Rect").unwrap_or(&Object::Null))
+        .ok()
+        .and_then(|(_, rect)| rect.as_array().ok())
+        .map(|rect| rect.iter().filter_map(pdf::number).map(round).collect());
+
+    let action = link
+        .get(b"A")
+        .ok()
+        .and_then(|a| doc.dereference(a).ok())
+        .and_then(|(_, a)| a.as_dict().ok());
+    // a bare /Dest is the same as a GoTo action
+    let (kind, dest) = match (link.get(b"Dest"), action) {
+        (Ok(dest), _) => (Some("GoTo".to_string()), Some(dest)),
+        (_, Some(action)) => {
+            let kind = action.get(b"S").and_then(Object::as_name).ok();
+            let kind = kind.map(|k| String::from_utf8_lossy(k).into_owned());
+            (kind, action.get(b"D").ok())
+        }
+        _ => (None, None),
+    };
+    let uri = action
+        .filter(|_| kind.as_deref() == Some("URI"))
+        .and_then(|a| a.get(b"URI").ok())
+        .and_then(|uri| doc.dereference(uri).ok())
+        .and_then(|(_, uri)| uri.as_str().ok())
+        .map(|uri| String::from_utf8_lossy(uri).into_owned());
+    // pages in other files can't be looked up, only internal ones
+    let dest_page = dest
+        .filter(|_| kind.as_deref() == Some("GoTo"))
+        .and_then(|dest| outline::destination_page(doc, dest, 0))
+        .and_then(|id| page_numbers.get(&id).copied());
+    let file = action
+        .and_then(|a| a.get(b"F").ok())
+        .and_then(|f| file_name(doc, f));
+    LinkJson {
+        page,
+        rect,
+        action: kind,
+        uri,
+        dest_page,
+        file,
+    }
+}
+
+/// the name in a file specification, a plain string or a dictionary
+fn file_name(doc: &Document, spec: &Object) -> Option<String> {
+    match doc.dereference(spec).ok()?.1 {
+        Object::String(name, _) => Some(pdf::decode_text(name)),
+        Object::Dictionary(spec) => {
+            let name = spec.get(b"UF").or_else(|_| spec.get(b"F")).ok()?;
+            Some(pdf::decode_text(doc.dereference(name).ok()?.1.as_str().ok()?))
+        }
+        _ => None,
+    }
+}
+
+fn round(n: f32) -> f64 {
+    (n as f64 * 100.0).round() / 100.0
+}
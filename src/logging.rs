@@ -0,0 +1,149 @@
+use anyhow::{Context, Result};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::path::Path;
+use std::sync::Mutex;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, FormattedFields};
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::parse::LogLevel;
+
+/// route ovid's log events: to stderr as plain lines, as it has always printed
+/// them, or as JSON lines with `json`, and with `file` also there with
+/// timestamps, thread IDs and spans. the file gets everything at `level` while
+/// stderr stays at info, so a debug log of a long run doesn't flood the terminal
+pub fn init(level: LogLevel, file: Option<&Path>, json: bool) -> Result<()> {
+    let level = match level {
+        LogLevel::Error => LevelFilter::ERROR,
+        LogLevel::Warn => LevelFilter::WARN,
+        LogLevel::Info => LevelFilter::INFO,
+        LogLevel::Debug => LevelFilter::DEBUG,
+        LogLevel::Trace => LevelFilter::TRACE,
+    };
+    let file_layer = match file {
+        Some(path) => {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("Failed to open log file {}", path.display()))?;
+            let layer = tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_thread_ids(true)
+                .with_writer(Mutex::new(file))
+                .with_filter(level);
+            Some(layer)
+        }
+        None => None,
+    };
+    let stderr_level = match file_layer {
+        Some(_) => level.min(LevelFilter::INFO),
+        None => level,
+    };
+    let plain = (!json).then(|| {
+        let layer = tracing_subscriber::fmt::layer().with_writer(std::io::stderr);
+        layer.event_format(Plain).with_filter(stderr_level)
+    });
+    let json = json.then(|| {
+        let layer = tracing_subscriber::fmt::layer().with_writer(std::io::stderr);
+        layer.event_format(JsonLines).with_filter(stderr_level)
+    });
+    tracing_subscriber::registry()
+        .with(plain)
+        .with(json)
+        .with(file_layer)
+        .try_init()
+        .context("Failed to set up logging")
+}
+
+/// info events as bare messages, warnings and errors prefixed like compiler
+/// output, and debug and trace events with the spans they happened in
+struct Plain;
+
+impl<S, N> FormatEvent<S, N> for Plain
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let level = *event.metadata().level();
+        match level {
+            Level::ERROR => write!(writer, "error: ")?,
+            Level::WARN => write!(writer, "warning: ")?,
+            Level::INFO => {}
+            Level::DEBUG => write!(writer, "debug: ")?,
+            Level::TRACE => write!(writer, "trace: ")?,
+        }
+        if level > Level::INFO {
+            for span in ctx.event_scope().into_iter().flat_map(|scope| scope.from_root()) {
+                write!(writer, "{}", span.name())?;
+                if let Some(fields) = span.extensions().get::<FormattedFields<N>>() {
+                    if !fields.is_empty() {
+                        write!(writer, "{{{}}}", fields)?;
+                    }
+                }
+                write!(writer, ": ")?;
+            }
+        }
+        ctx.field_format().format_fields(writer.by_ref(), event)?;
+        writeln!(writer)
+    }
+}
+
+/// events as `{"event":"log","level":"warn","message":...}` lines, to go with
+/// the progress events of `--progress json`
+struct JsonLines;
+
+impl<S, N> FormatEvent<S, N> for JsonLines
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        _ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let mut obj = Map::new();
+        obj.insert("event".into(), "log".into());
+        obj.insert("level".into(), event.metadata().level().as_str().to_lowercase().into());
+        event.record(&mut JsonFields(&mut obj));
+        writeln!(writer, "{}", Value::Object(obj))
+    }
+}
+
+/// copies an event's fields, the message included, into a JSON object
+struct JsonFields<'a>(&'a mut Map<String, Value>);
+
+impl Visit for JsonFields<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.insert(field.name().into(), format!("{:?}", value).into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().into(), value.into());
+    }
+}
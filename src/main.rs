@@ -1,15 +1,24 @@
 #[global_allocator]
 static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 
-mod merge;
-mod parse;
-mod split;
-
 use anyhow::{Context, Result};
 use clap::{CommandFactory, Parser, Subcommand};
 use std::path::{Path, PathBuf};
 
-use parse::{ImageFormat, Orientation, PageSize, PngCompression};
+use ovid::{
+    annots, batch, blank, chunk, combine, compare, convert, crop, dedupe, doctor, encrypt, fetch,
+    flatten, fonts, form, grid, hash, images, info, interrupt, layers, links, logging, merge, meta,
+    nup, optimize, outline, overlay, pages, parse, poster, progress, rasterize, redact, repair,
+    rotate, serve, sign, split, stamp, stats, text, validate, watch, watermark,
+};
+
+use parse::{
+    BoxTarget, ByteSize, Caption, Color, ConvertFormat, Deflate, Direction, IccMode, ImageFormat,
+    Length, LogLevel, Margins, MetaField, Orientation, OutlineFormat, PageLayout, PageMode,
+    PageMove, PageRect, PageSize, PaperTemplate, PdfaLevel, PixelSize, PngCompression, Position,
+    ProgressFormat, RedactRegion, Rotation, Scale, StampFont, StatsFormat,
+};
+use progress::{Event, Progress};
 
 #[derive(Parser)]
 #[command(name = "ovid", version, about = "Lightning-fast PDF / Image converter")]
@@ -22,18 +31,41 @@ struct Cli {
     #[arg(short, long, global = true)]
     quiet: bool,
 
+    /// how much to log; debug adds per-page details and worker IDs, trace timings
+    #[arg(long, global = true, default_value = "info")]
+    log_level: LogLevel,
+
+    /// more detail: -v for each page's size, color space and compression, -vv
+    /// for how long its render, encode and write took too (as --log-level debug
+    /// and trace)
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// also append the log, with timestamps and threads, to this file (stderr
+    /// then stays at info)
+    #[arg(long, global = true)]
+    log_file: Option<PathBuf>,
+
+    /// progress on stderr as log lines, or as JSON events, one per line, for
+    /// split and merge
+    #[arg(long, global = true, default_value = "human")]
+    progress: ProgressFormat,
+
     #[command(subcommand)]
     command: Commands,
 }
 
+// parsed once per run, so the size of the merge variant doesn't matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand)]
 enum Commands {
     /// convert PDF pages to images (PNG or JPG)
     Split {
-        /// input PDF file
+        /// input PDF file or http(s):// URL
         input: PathBuf,
 
-        /// output dir (default next to input file), or "-" for stdout (single page only)
+        /// output dir (default next to input file, or the current dir for a URL), or "-"
+        /// for stdout (single page only)
         #[arg(short, long)]
         output: Option<PathBuf>,
 
@@ -45,7 +77,7 @@ enum Commands {
         #[arg(short, long, default_value_t = 300, value_parser = clap::value_parser!(u32).range(72..=2400))]
         dpi: u32,
 
-        /// PNG compression: fast (speed) or small (filesize)
+        /// PNG compression: fast (speed), small (filesize) or max (archival, slowest)
         #[arg(short, long, default_value = "fast")]
         compress: PngCompression,
 
@@ -60,16 +92,108 @@ enum Commands {
         /// JPEG quality (1-100)
         #[arg(long, default_value_t = 75, value_parser = clap::value_parser!(u8).range(1..=100))]
         quality: u8,
+
+        /// warn about pages that fail to render and save the rest (exit status 2)
+        #[arg(long)]
+        skip_errors: bool,
+
+        /// refuse to download an input URL larger than this
+        #[arg(long, value_name = "SIZE", default_value = "512MB")]
+        max_download: ByteSize,
+
+        /// cap the pages in flight so their estimated memory stays under BUDGET,
+        /// trading speed for a bounded footprint (default 512MB)
+        #[arg(long, value_name = "BUDGET", num_args = 0..=1, default_missing_value = "512MB")]
+        low_memory: Option<ByteSize>,
+
+        /// record the pages rendered in DIR, and skip those unchanged since the last
+        /// run whose images are still there
+        #[arg(long, value_name = "DIR")]
+        cache: Option<PathBuf>,
+
+        /// render a dynamic XFA form's stored pages, a placeholder asking for another
+        /// viewer, instead of failing
+        #[arg(long)]
+        force_static: bool,
+
+        /// print the pages, image names and sizes without rendering or writing anything
+        #[arg(long)]
+        dry_run: bool,
+
+        /// at the end, report each page's time per stage and compression, the
+        /// slowest pages, throughput and peak memory on stderr, as text or json
+        #[arg(long, value_name = "FORMAT", num_args = 0..=1, default_missing_value = "text")]
+        stats: Option<StatsFormat>,
+    },
+    /// turn a PDF into one of page images, dropping text, fonts, scripts and layers
+    Rasterize {
+        /// input PDF file
+        input: PathBuf,
+
+        /// output PDF path, "-" for stdout
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// rendering DPI (72-1200)
+        #[arg(short, long, default_value_t = 150, value_parser = clap::value_parser!(u32).range(72..=1200))]
+        dpi: u32,
+
+        /// render in grayscale
+        #[arg(long)]
+        gray: bool,
+
+        /// JPEG-compress pages at this quality (1-100) instead of lossless
+        #[arg(long, value_parser = clap::value_parser!(u8).range(1..=100))]
+        quality: Option<u8>,
+    },
+    /// black out regions of pages for good, rasterizing the pages they're on
+    Redact {
+        /// input PDF file
+        input: PathBuf,
+
+        /// output PDF path, "-" for stdout
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// area to black out, "PAGES:x0,y0,x1,y1" in PDF coordinates (origin
+        /// bottom left), e.g. "2:72,700,300,720" or "1-3:0,0,612,36"; repeatable
+        #[arg(long)]
+        region: Vec<RedactRegion>,
+
+        /// JSON file of regions: [{"page": 2, "rect": [x0, y0, x1, y1]}, ...]
+        #[arg(long)]
+        regions: Option<PathBuf>,
+
+        /// rendering DPI of redacted pages (72-1200)
+        #[arg(short, long, default_value_t = 300, value_parser = clap::value_parser!(u32).range(72..=1200))]
+        dpi: u32,
+
+        /// JPEG-compress redacted pages at this quality (1-100) instead of lossless
+        #[arg(long, value_parser = clap::value_parser!(u8).range(1..=100))]
+        quality: Option<u8>,
     },
     /// combine images into a single PDF
     Merge {
-        /// input image files or dirs (png, jpg, tiff, bmp, gif)
+        /// input image files, dirs or http(s):// URLs (png, jpg, tiff, bmp, gif)
+        #[arg(required_unless_present = "interleave")]
         images: Vec<PathBuf>,
 
-        /// output PDF path, "-" for stdout
+        /// collate two sources page by page (e.g. duplex scans: fronts/ backs/)
+        #[arg(long, num_args = 2, value_names = ["FIRST", "SECOND"], conflicts_with = "images")]
+        interleave: Option<Vec<PathBuf>>,
+
+        /// reverse the second --interleave source (back stack scanned last page first)
+        #[arg(long, requires = "interleave")]
+        reverse_second: bool,
+
+        /// output PDF path, "-" for stdout; a .epub path writes a fixed-layout EPUB
         #[arg(short, long, default_value = "output.pdf")]
         output: PathBuf,
 
+        /// reading direction: rtl lays facing pages out right to left (manga)
+        #[arg(long, default_value = "ltr")]
+        direction: Direction,
+
         /// DPI for page sizing (default: from image metadata, or 300)
         #[arg(short, long, value_parser = clap::value_parser!(u32).range(72..=2400))]
         dpi: Option<u32>,
@@ -89,86 +213,2002 @@ enum Commands {
         /// page orientation: auto (from image aspect ratio), portrait, landscape
         #[arg(long, default_value_t = Orientation::Auto)]
         orientation: Orientation,
+
+        /// image position with --pagesize: top-left, top, top-right, left, center, right,
+        /// bottom-left, bottom, bottom-right, or "x,y" in points from the top-left corner
+        #[arg(long, default_value = "center")]
+        position: Position,
+
+        /// image scale (e.g. "100%" for actual size) instead of fitting to --pagesize
+        #[arg(long)]
+        scale: Option<Scale>,
+
+        /// composite transparency over a background color (default white) instead of a soft mask;
+        /// flattened pages are always 8 bits per component
+        #[arg(
+            long,
+            value_name = "COLOR",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "#ffffff"
+        )]
+        flatten_alpha: Option<Color>,
+
+        /// convert all pages to grayscale (re-encodes color JPEGs)
+        #[arg(long)]
+        gray: bool,
+
+        /// text stamped over every page (e.g. "DRAFT")
+        #[arg(long, conflicts_with = "watermark_image")]
+        watermark_text: Option<String>,
+
+        /// image stamped over every page at its natural size
+        #[arg(long)]
+        watermark_image: Option<PathBuf>,
+
+        /// watermark opacity (0.0-1.0)
+        #[arg(long, default_value_t = 0.3)]
+        watermark_opacity: f32,
+
+        /// watermark rotation in degrees, counter-clockwise
+        #[arg(long, default_value_t = 45.0, allow_negative_numbers = true)]
+        watermark_rotation: f32,
+
+        /// watermark position (same values as --position)
+        #[arg(long, default_value = "center")]
+        watermark_position: Position,
+
+        /// watermark text color
+        #[arg(long, default_value = "gray")]
+        watermark_color: Color,
+
+        /// print page numbers, with {n} and {total} placeholders in the optional format
+        #[arg(
+            long,
+            value_name = "FORMAT",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "Page {n} of {total}"
+        )]
+        page_numbers: Option<String>,
+
+        /// page number position (same values as --position)
+        #[arg(long, default_value = "bottom")]
+        page_numbers_position: Position,
+
+        /// page arrangement when the PDF is opened
+        #[arg(long)]
+        page_layout: Option<PageLayout>,
+
+        /// side panel shown when the PDF is opened
+        #[arg(long)]
+        page_mode: Option<PageMode>,
+
+        /// open with the viewer window fit to the first page
+        #[arg(long)]
+        fit_window: bool,
+
+        /// split output into numbered files (output_001.pdf, ...) of at most N pages
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+        max_pages: Option<u64>,
+
+        /// split output into numbered files of about this size at most (e.g. 25MB)
+        #[arg(long, value_name = "SIZE")]
+        max_size: Option<ByteSize>,
+
+        /// refuse to download an input URL larger than this
+        #[arg(long, value_name = "SIZE", default_value = "512MB")]
+        max_download: ByteSize,
+
+        /// embedded ICC profiles: keep, strip, or replace with sRGB
+        #[arg(long, default_value = "keep")]
+        icc: IccMode,
+
+        /// warn about corrupt or unreadable images and keep going instead of aborting
+        /// (exit status 2 if any were skipped)
+        #[arg(long)]
+        skip_errors: bool,
+
+        /// with --skip-errors, insert a captioned blank page for each skipped image
+        #[arg(long, requires = "skip_errors")]
+        placeholder: bool,
+
+        /// refuse to decode images larger than this many pixels (0 = no limit)
+        #[arg(long, value_name = "N", default_value_t = 200_000_000)]
+        max_pixels: u64,
+
+        /// creation/modification date to record, RFC 3339 (default: now, local time)
+        #[arg(long, value_name = "DATE")]
+        date: Option<chrono::DateTime<chrono::FixedOffset>>,
+
+        /// record each image's EXIF capture date and camera in its page's PieceInfo
+        #[arg(long, conflicts_with = "strip_exif")]
+        keep_exif: bool,
+
+        /// with --keep-exif, also record the GPS position
+        #[arg(long, requires = "keep_exif")]
+        exif_gps: bool,
+
+        /// remove EXIF and XMP metadata from JPEGs embedded unchanged
+        #[arg(long)]
+        strip_exif: bool,
+
+        /// remove all metadata from JPEGs embedded unchanged: EXIF thumbnails,
+        /// XMP, MPF and other APPn segments (ICC profiles too, unless --icc keep)
+        #[arg(long)]
+        strip_metadata: bool,
+
+        /// deflate level for decoded images: fast (speed) or standard (filesize)
+        #[arg(long, default_value = "fast")]
+        deflate: Deflate,
+
+        /// straighten skewed scans (decodes and re-compresses every image)
+        #[arg(long)]
+        deskew: bool,
+
+        /// put each frame of an animated PNG on a page of its own (default: its
+        /// first frame only)
+        #[arg(long)]
+        apng_frames: bool,
+
+        /// add bookmarks from a .json or .toml outline file (see `ovid bookmarks`)
+        #[arg(long, value_name = "FILE")]
+        bookmarks: Option<PathBuf>,
+
+        /// cap the images in flight so their estimated memory stays under BUDGET,
+        /// trading speed for a bounded footprint (default 512MB)
+        #[arg(long, value_name = "BUDGET", num_args = 0..=1, default_missing_value = "512MB")]
+        low_memory: Option<ByteSize>,
+
+        /// print the images in order, their page sizes and the files to write, without
+        /// writing anything
+        #[arg(long)]
+        dry_run: bool,
+
+        /// at the end, report each page's time per stage and compression, the
+        /// slowest pages, throughput and peak memory on stderr, as text or json
+        #[arg(long, value_name = "FORMAT", num_args = 0..=1, default_missing_value = "text")]
+        stats: Option<StatsFormat>,
     },
-    /// generate shell completions
-    Completions {
-        /// shell to generate completions for
-        shell: clap_complete::Shell,
+    /// concatenate PDFs into one, keeping text, fonts, and links (no rasterization)
+    Combine {
+        /// input PDF files, in order
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// output PDF path, "-" for stdout
+        #[arg(short, long, default_value = "output.pdf")]
+        output: PathBuf,
     },
-}
+    /// cut a PDF into smaller PDFs of N pages or a size limit, without re-rendering
+    Chunk {
+        /// input PDF file
+        input: PathBuf,
 
-fn main() -> Result<()> {
-    let cli = Cli::parse();
+        /// name for the parts, numbered out_001.pdf, out_002.pdf, ... (default next
+        /// to the input, named after it)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
 
-    if let Some(threads) = cli.threads {
-        rayon::ThreadPoolBuilder::new()
-            .num_threads(threads)
-            .build_global()
-            .context("Failed to configure thread pool")?;
-    }
+        /// pages per part
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+        #[arg(required_unless_present = "max_size", conflicts_with = "max_size")]
+        every: Option<u64>,
 
-    let quiet = cli.quiet;
+        /// largest size of a part (e.g. 20MB); a page over the limit gets its own part
+        #[arg(long, value_name = "SIZE")]
+        max_size: Option<ByteSize>,
+    },
+    /// drop repeated pages from a PDF, keeping the first of each
+    Dedupe {
+        /// input PDF file
+        input: PathBuf,
 
-    match cli.command {
-        Commands::Split {
-            input,
-            output,
-            format,
-            dpi,
-            compress,
-            gray,
-            pages,
-            quality,
-        } => {
-            let output_dir = output.unwrap_or_else(|| {
-                input
-                    .parent()
-                    .unwrap_or_else(|| Path::new("."))
-                    .to_path_buf()
-            });
-            split::split_pdf(
-                &input,
-                &output_dir,
-                format,
-                dpi,
-                compress,
-                gray,
-                pages.as_deref(),
-                quality,
-                quiet,
-            )?;
-        }
-        Commands::Merge {
-            images,
-            output,
-            dpi,
-            title,
-            author,
-            pagesize,
-            orientation,
-        } => {
-            let images = parse::expand_image_paths(&images)?;
-            anyhow::ensure!(!images.is_empty(), "No input images provided");
-            merge::merge_images(
-                &images,
-                &output,
-                dpi,
-                quiet,
-                title.as_deref(),
-                author.as_deref(),
-                pagesize,
-                orientation,
-            )?;
-        }
-        Commands::Completions { shell } => {
-            clap_complete::generate(
-                shell,
-                &mut Cli::command(),
-                "ovid",
-                &mut std::io::stdout(),
-            );
-        }
-    }
+        /// output PDF path, "-" for stdout
+        #[arg(short, long)]
+        output: PathBuf,
 
-    Ok(())
+        /// also drop pages that look the same rendered (a page scanned twice), not
+        /// just identical ones
+        #[arg(long)]
+        similar: bool,
+
+        /// with --similar, how alike (SSIM, 0.0-1.0) pages must look to count as
+        /// the same
+        #[arg(long, default_value_t = 0.95, requires = "similar")]
+        threshold: f64,
+
+        /// only compare each page with the one before it
+        #[arg(long)]
+        adjacent: bool,
+    },
+    /// split a PDF into one file per bookmark, named after the bookmark titles
+    SplitBookmarks {
+        /// input PDF file
+        input: PathBuf,
+
+        /// output dir (default next to input file)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// deepest bookmark level to split at (1: top-level entries only)
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+        level: u64,
+    },
+    /// extract, delete, and reorder PDF pages without re-rendering
+    Pages {
+        /// input PDF file
+        input: PathBuf,
+
+        /// output PDF path, "-" for stdout
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// pages to keep, in output order (e.g. "1,5-9" or "3,1,2")
+        #[arg(long)]
+        keep: Option<String>,
+
+        /// pages to delete (e.g. "2,4-6")
+        #[arg(long)]
+        delete: Option<String>,
+
+        /// move the page at position FROM to position TO, after --keep/--delete
+        /// (repeatable, applied in order)
+        #[arg(long = "move", value_name = "FROM:TO")]
+        moves: Vec<PageMove>,
+    },
+    /// rotate PDF pages losslessly (sets /Rotate, nothing is re-encoded)
+    Rotate {
+        /// input PDF file
+        input: PathBuf,
+
+        /// output PDF path, "-" for stdout
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// clockwise degrees to turn by: 90, 180, 270, or negative for counterclockwise
+        #[arg(short, long, allow_hyphen_values = true, default_value = "90")]
+        angle: Rotation,
+
+        /// page selection (e.g. "1", "1,3-5,10"), default all pages
+        #[arg(short, long)]
+        pages: Option<String>,
+    },
+    /// set the visible area of pages (CropBox/TrimBox) without touching their content
+    Crop {
+        /// input PDF file
+        input: PathBuf,
+
+        /// output PDF path, "-" for stdout
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// new box "x0,y0,x1,y1" in PDF coordinates (origin bottom left); units pt, mm, cm, in
+        #[arg(long = "box", value_name = "X0,Y0,X1,Y1", allow_hyphen_values = true)]
+        #[arg(conflicts_with = "margins", required_unless_present = "margins")]
+        rect: Option<PageRect>,
+
+        /// trim from the page as shown: "10mm", "V,H", or "TOP,RIGHT,BOTTOM,LEFT"; negative grows
+        #[arg(short, long, allow_hyphen_values = true)]
+        margins: Option<Margins>,
+
+        /// which box to set
+        #[arg(long, default_value = "crop")]
+        target: BoxTarget,
+
+        /// page selection (e.g. "1", "1,3-5,10"), default all pages
+        #[arg(short, long)]
+        pages: Option<String>,
+    },
+    /// place several pages of a PDF on each sheet (2-up handouts, 4-up proofs)
+    Nup {
+        /// input PDF file
+        input: PathBuf,
+
+        /// output PDF path, "-" for stdout
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// pages per sheet (2-64)
+        #[arg(short = 'n', long, default_value_t = 2, value_parser = clap::value_parser!(u8).range(2..=64))]
+        per_sheet: u8,
+
+        /// sheet size (default: the first page's size)
+        #[arg(long)]
+        page_size: Option<PageSize>,
+
+        /// sheet orientation; auto picks whichever shows pages largest
+        #[arg(long, default_value = "auto")]
+        orientation: Orientation,
+
+        /// space around the sheet edge, in points
+        #[arg(long, default_value_t = 0.0)]
+        margin: f32,
+
+        /// space between pages, in points
+        #[arg(long, default_value_t = 0.0)]
+        gap: f32,
+
+        /// draw a thin border around each page
+        #[arg(long)]
+        border: bool,
+    },
+    /// print each page large across several sheets, with overlap and crop marks
+    Poster {
+        /// input PDF file
+        input: PathBuf,
+
+        /// output PDF path, "-" for stdout
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// sheet size to print on
+        #[arg(long, default_value = "a4")]
+        page_size: PageSize,
+
+        /// sheet orientation; auto picks whichever needs fewer sheets
+        #[arg(long, default_value = "auto")]
+        orientation: Orientation,
+
+        /// poster size relative to the page, e.g. 200%
+        #[arg(long, default_value = "100%")]
+        scale: Scale,
+
+        /// how much neighboring sheets share, for lining them up (pt, mm, cm, in)
+        #[arg(long, default_value = "10mm")]
+        overlap: Length,
+
+        /// unprintable edge of each sheet, where marks and labels go
+        #[arg(long, default_value = "10mm")]
+        margin: Length,
+
+        /// leave out crop marks and sheet labels
+        #[arg(long)]
+        no_marks: bool,
+    },
+    /// create a PDF of blank pages, or of grid, dot or lined paper
+    Blank {
+        /// output PDF path, "-" for stdout
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// number of pages
+        #[arg(short = 'n', long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..=100_000))]
+        pages: u32,
+
+        /// page size (default: letter)
+        #[arg(long)]
+        page_size: Option<PageSize>,
+
+        /// page orientation
+        #[arg(long, default_value = "auto")]
+        orientation: Orientation,
+
+        /// what to print on the pages
+        #[arg(long, value_enum, default_value_t)]
+        template: PaperTemplate,
+
+        /// distance between lines or dots (default: 5mm, 8mm for lined)
+        #[arg(long)]
+        spacing: Option<Length>,
+
+        /// space left clear around the pattern (pt, mm, cm, in)
+        #[arg(long, default_value = "10mm")]
+        margin: Length,
+
+        /// line and dot color
+        #[arg(long, default_value = "#c0c0c0")]
+        color: Color,
+    },
+    /// bake form field values and annotations into page content
+    Flatten {
+        /// input PDF file
+        input: PathBuf,
+
+        /// output PDF path, "-" for stdout
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// list a PDF's form fields, or fill them from JSON or CSV data
+    Form {
+        /// input PDF file
+        input: PathBuf,
+
+        /// field values: a .json object of field names to values (or an array of
+        /// them), or a .csv with a header row of field names; one PDF per record
+        #[arg(short, long, value_name = "FILE", requires = "output")]
+        data: Option<PathBuf>,
+
+        /// output PDF path, "-" for stdout; several records are numbered
+        /// (out_001.pdf, out_002.pdf, ...)
+        #[arg(short, long, requires = "data")]
+        output: Option<PathBuf>,
+
+        /// bake the filled-in values into the page content
+        #[arg(long, requires = "data")]
+        flatten: bool,
+
+        /// list the fields as JSON
+        #[arg(long, conflicts_with = "data")]
+        json: bool,
+    },
+    /// recover a damaged PDF: rebuild its xref, drop unreadable pages and broken
+    /// objects, and write a clean file
+    Repair {
+        /// input PDF file
+        input: PathBuf,
+
+        /// output PDF path, "-" for stdout
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// shrink an existing PDF: downsample and recompress images, merge duplicates,
+    /// drop unused resources, and write with object streams
+    Optimize {
+        /// input PDF file
+        input: PathBuf,
+
+        /// output PDF path, "-" for stdout
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// downsample images shown sharper than this DPI (0 keeps full resolution)
+        #[arg(short, long, default_value_t = 150)]
+        dpi: u32,
+
+        /// JPEG quality for re-encoded images (1-100)
+        #[arg(long, default_value_t = 75, value_parser = clap::value_parser!(u8).range(1..=100))]
+        quality: u8,
+
+        /// keep lossless images lossless instead of re-encoding photos as JPEG
+        #[arg(long)]
+        no_jpeg: bool,
+    },
+    /// convert images between formats (PNG, JPG, TIFF, BMP, GIF)
+    Convert {
+        /// input image files or dirs
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// output format
+        #[arg(short, long)]
+        format: ConvertFormat,
+
+        /// output dir (default next to each input file)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// resize by a percentage (e.g. 50%)
+        #[arg(long)]
+        scale: Option<Scale>,
+
+        /// shrink to at most this width in pixels, keeping the aspect ratio
+        #[arg(long, value_name = "PX")]
+        max_width: Option<u32>,
+
+        /// shrink to at most this height in pixels, keeping the aspect ratio
+        #[arg(long, value_name = "PX")]
+        max_height: Option<u32>,
+
+        /// convert to grayscale
+        #[arg(long)]
+        gray: bool,
+
+        /// JPEG quality (1-100)
+        #[arg(long, default_value_t = 75, value_parser = clap::value_parser!(u8).range(1..=100))]
+        quality: u8,
+
+        /// PNG compression: fast (speed), small (filesize) or max (archival, slowest)
+        #[arg(short, long, default_value = "fast")]
+        compress: PngCompression,
+    },
+    /// lay images out in captioned contact sheets, saved as PNG or JPEG
+    Grid {
+        /// input image files or dirs
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// output image, .png or .jpg; several sheets are numbered (sheet_001.png)
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// images per row
+        #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u32).range(1..=100))]
+        cols: u32,
+
+        /// rows per sheet, starting a new sheet when full (default: one sheet)
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..=100))]
+        rows: Option<u32>,
+
+        /// room for each image in pixels, "256" or "320x240"
+        #[arg(long, default_value = "256")]
+        cell_size: PixelSize,
+
+        /// space between images, in pixels
+        #[arg(long, default_value_t = 8)]
+        gap: u32,
+
+        /// text under each image
+        #[arg(long, value_enum, default_value_t)]
+        caption: Caption,
+
+        /// sheet color
+        #[arg(long, default_value = "white")]
+        background: Color,
+
+        /// JPEG quality (1-100)
+        #[arg(long, default_value_t = 85, value_parser = clap::value_parser!(u8).range(1..=100))]
+        quality: u8,
+    },
+    /// straighten skewed scans, writing the corrected images to a dir
+    Deskew {
+        /// input image files or dirs
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// output dir
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// output format
+        #[arg(short, long, default_value = "png")]
+        format: ConvertFormat,
+
+        /// JPEG quality (1-100)
+        #[arg(long, default_value_t = 75, value_parser = clap::value_parser!(u8).range(1..=100))]
+        quality: u8,
+
+        /// PNG compression: fast (speed), small (filesize) or max (archival, slowest)
+        #[arg(short, long, default_value = "fast")]
+        compress: PngCompression,
+    },
+    /// stamp text or an image onto pages of an existing PDF
+    Watermark {
+        /// input PDF file
+        input: PathBuf,
+
+        /// output PDF path, "-" for stdout
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// text to stamp (e.g. "CONFIDENTIAL")
+        #[arg(long, conflicts_with = "image", required_unless_present = "image")]
+        text: Option<String>,
+
+        /// image to stamp at its natural size
+        #[arg(long)]
+        image: Option<PathBuf>,
+
+        /// opacity (0.0-1.0)
+        #[arg(long, default_value_t = 0.3)]
+        opacity: f32,
+
+        /// rotation in degrees, counter-clockwise
+        #[arg(long, default_value_t = 45.0, allow_negative_numbers = true)]
+        rotation: f32,
+
+        /// position on the page (same values as merge --position)
+        #[arg(long, default_value = "center")]
+        position: Position,
+
+        /// text color
+        #[arg(long, default_value = "gray")]
+        color: Color,
+
+        /// page selection (e.g. "1", "1,3-5,10"), default all pages
+        #[arg(short, long)]
+        pages: Option<String>,
+    },
+    /// Bates-number the pages of one or more PDFs with a prefix and zero-padded counter
+    Stamp {
+        /// input PDF files, numbered in the order given
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// directory for the stamped files, which keep their names
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// text before the number, e.g. "ABC"
+        #[arg(long, default_value = "")]
+        prefix: String,
+
+        /// number of the first page
+        #[arg(long, default_value_t = 1)]
+        start: u64,
+
+        /// counter width, zero-padded (1-12)
+        #[arg(long, default_value_t = 6, value_parser = clap::value_parser!(u8).range(1..=12))]
+        digits: u8,
+
+        /// position on the page (same values as merge --position)
+        #[arg(long, default_value = "bottom-right")]
+        position: Position,
+
+        /// distance from the page edge in points, for named positions
+        #[arg(long, default_value_t = 18.0)]
+        margin: f32,
+
+        /// typeface
+        #[arg(long, default_value = "helvetica")]
+        font: StampFont,
+
+        /// text size in points
+        #[arg(long, default_value_t = 10.0)]
+        font_size: f32,
+
+        /// text color
+        #[arg(long, default_value = "black")]
+        color: Color,
+
+        /// print the assigned ranges as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// print a PDF's bookmarks as JSON or TOML, or replace them from such a file
+    Bookmarks {
+        /// input PDF file
+        input: PathBuf,
+
+        /// replace the bookmarks with those in this .json or .toml file (an empty
+        /// outline removes them)
+        #[arg(long, value_name = "FILE", requires = "output")]
+        apply: Option<PathBuf>,
+
+        /// with --apply, the output PDF ("-" for stdout); otherwise a file for the
+        /// outline (default stdout)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// outline format (default from the output's extension, else json)
+        #[arg(short, long, conflicts_with = "apply")]
+        format: Option<OutlineFormat>,
+    },
+    /// export a PDF's comments and markup (notes, highlights, shapes) as JSON
+    Annots {
+        /// input PDF file
+        input: PathBuf,
+
+        /// file for the JSON (default stdout)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// also save a copy of the PDF without them here (links and form fields
+        /// stay)
+        #[arg(long, value_name = "PDF")]
+        strip: Option<PathBuf>,
+    },
+    /// list a PDF's layers, or switch them on or off or remove them
+    Layers {
+        /// input PDF file
+        input: PathBuf,
+
+        /// show this layer (repeatable)
+        #[arg(long, value_name = "NAME", requires = "output")]
+        on: Vec<String>,
+
+        /// hide this layer (repeatable)
+        #[arg(long, value_name = "NAME", requires = "output")]
+        off: Vec<String>,
+
+        /// delete this layer and everything drawn in it (repeatable)
+        #[arg(long, value_name = "NAME", requires = "output")]
+        remove: Vec<String>,
+
+        /// output PDF path, "-" for stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// print JSON instead of a table
+        #[arg(long, conflicts_with = "output")]
+        json: bool,
+    },
+    /// list the images a PDF draws: pixel size, compression, color and DPI
+    Images {
+        /// input PDF file
+        input: PathBuf,
+
+        /// print JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// list the fonts a PDF uses: type, encoding, embedding and pages
+    Fonts {
+        /// input PDF file
+        input: PathBuf,
+
+        /// print JSON instead of a table
+        #[arg(long)]
+        json: bool,
+
+        /// list each page's fonts
+        #[arg(long)]
+        by_page: bool,
+
+        /// print nothing, and fail if any font isn't embedded
+        #[arg(long, conflicts_with_all = ["json", "by_page"])]
+        check: bool,
+    },
+    /// list a PDF's links as JSON, or rewrite their web addresses
+    Links {
+        /// input PDF file
+        input: PathBuf,
+
+        /// rewrite URIs starting with FROM to start with TO instead, e.g.
+        /// http://=https:// (repeatable, applied in order)
+        #[arg(long, value_name = "FROM=TO", requires = "output")]
+        replace: Vec<parse::UriRewrite>,
+
+        /// with --replace, the output PDF ("-" for stdout); otherwise a file for
+        /// the JSON (default stdout)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// composite the pages of another PDF (letterhead, grid, template) onto each page
+    Overlay {
+        /// input PDF file
+        input: PathBuf,
+
+        /// PDF whose pages are drawn onto the input's pages
+        overlay: PathBuf,
+
+        /// output PDF path, "-" for stdout
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// draw the overlay under the page content, as a background
+        #[arg(long)]
+        under: bool,
+
+        /// match pages one to one (the overlay's last page repeats), instead of
+        /// using its first page everywhere
+        #[arg(long)]
+        multi: bool,
+
+        /// page selection (e.g. "1", "1,3-5,10"), default all pages
+        #[arg(short, long)]
+        pages: Option<String>,
+    },
+    /// password-protect a PDF with AES-256 encryption and permission flags
+    Encrypt {
+        /// input PDF file
+        input: PathBuf,
+
+        /// output PDF path, "-" for stdout
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// password needed to open the PDF (empty: anyone can open it, but the
+        /// permission flags still apply)
+        #[arg(long, default_value = "")]
+        user_password: String,
+
+        /// password that lifts the permission flags (default: the user password)
+        #[arg(long)]
+        owner_password: Option<String>,
+
+        /// disallow printing
+        #[arg(long)]
+        no_print: bool,
+
+        /// disallow editing and assembling pages
+        #[arg(long)]
+        no_modify: bool,
+
+        /// disallow copying text and images
+        #[arg(long)]
+        no_copy: bool,
+
+        /// disallow adding annotations and filling in forms
+        #[arg(long)]
+        no_annotate: bool,
+    },
+    /// remove encryption from a PDF, given its user or owner password
+    Decrypt {
+        /// input PDF file
+        input: PathBuf,
+
+        /// output PDF path, "-" for stdout
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// user or owner password
+        #[arg(long, default_value = "")]
+        password: String,
+    },
+    /// remove permission restrictions (printing, copying, ...) from a PDF
+    Unlock {
+        /// input PDF file
+        input: PathBuf,
+
+        /// output PDF path, "-" for stdout
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// owner password; only needed if the PDF also has a password to open it
+        #[arg(long, default_value = "")]
+        password: String,
+    },
+    /// digitally sign a PDF with a PKCS#12 key, or verify its signatures
+    Sign {
+        /// input PDF file
+        input: PathBuf,
+
+        /// PKCS#12 (.p12/.pfx) file with the RSA key and certificate to sign with
+        #[arg(long, required_unless_present = "verify", conflicts_with = "verify")]
+        key: Option<PathBuf>,
+
+        /// password of the key file
+        #[arg(long, default_value = "")]
+        password: String,
+
+        /// write the signed PDF here instead of appending the signature to the input
+        #[arg(short, long, conflicts_with = "verify")]
+        output: Option<PathBuf>,
+
+        /// show the signature in this box "x0,y0,x1,y1" (units pt, mm, cm, in); default invisible
+        #[arg(long = "box", value_name = "X0,Y0,X1,Y1", conflicts_with = "verify")]
+        rect: Option<PageRect>,
+
+        /// page for a visible signature
+        #[arg(long, default_value_t = 1, requires = "rect")]
+        #[arg(value_parser = clap::value_parser!(u32).range(1..))]
+        page: u32,
+
+        /// signature field name (default: Signature1, Signature2, ...)
+        #[arg(long, conflicts_with = "verify")]
+        field: Option<String>,
+
+        /// reason for signing, e.g. "Approved"
+        #[arg(long, conflicts_with = "verify")]
+        reason: Option<String>,
+
+        /// where the document was signed
+        #[arg(long, conflicts_with = "verify")]
+        location: Option<String>,
+
+        /// check the signatures in the PDF instead; exits 1 if any doesn't hold
+        #[arg(long)]
+        verify: bool,
+
+        /// print machine-readable JSON (with --verify)
+        #[arg(long, requires = "verify")]
+        json: bool,
+    },
+    /// print or edit a PDF's metadata (document info and XMP)
+    Meta {
+        /// input PDF file
+        input: PathBuf,
+
+        /// set a field, e.g. title="Q3 Report"; an empty value removes it (repeatable)
+        #[arg(long, value_name = "KEY=VALUE")]
+        set: Vec<MetaField>,
+
+        /// write the edited PDF here instead of appending the update to the input
+        #[arg(short, long, requires = "set")]
+        output: Option<PathBuf>,
+
+        /// print machine-readable JSON
+        #[arg(long, conflicts_with = "set")]
+        json: bool,
+    },
+    /// extract text from a PDF
+    Text {
+        /// input PDF file
+        input: PathBuf,
+
+        /// output file (default stdout), or dir with --per-page (default next to input)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// write one file per page (<name>_0001.txt)
+        #[arg(long)]
+        per_page: bool,
+
+        /// print JSON with every word's bounding box (points, origin top left)
+        #[arg(long)]
+        json: bool,
+
+        /// page selection (e.g. "1", "1,3-5,10")
+        #[arg(short, long)]
+        pages: Option<String>,
+    },
+    /// render two PDFs and compare them page by page (exit status 1 if they differ)
+    Compare {
+        /// first PDF file
+        first: PathBuf,
+
+        /// second PDF file
+        second: PathBuf,
+
+        /// rendering DPI (36-600)
+        #[arg(short, long, default_value_t = 72, value_parser = clap::value_parser!(u32).range(36..=600))]
+        dpi: u32,
+
+        /// per-channel difference (0-255) still treated as equal
+        #[arg(long, default_value_t = 0)]
+        fuzz: u8,
+
+        /// fraction of differing pixels (0.0-1.0) a page may have and still match
+        #[arg(long, default_value_t = 0.0)]
+        threshold: f64,
+
+        /// write images of differing pages, changes in red, into this dir
+        #[arg(long)]
+        diff_dir: Option<PathBuf>,
+
+        /// page selection (e.g. "1", "1,3-5,10")
+        #[arg(short, long)]
+        pages: Option<String>,
+
+        /// print machine-readable JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// show page count, sizes, images, encryption and metadata of PDFs or images
+    Info {
+        /// input PDF or image files
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// print machine-readable JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// print the number of pages in each PDF
+    Pagecount {
+        /// input PDF files
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// print machine-readable JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// print perceptual hashes (pHash, dHash) of each page as JSON, for finding
+    /// duplicates and near matches
+    Hash {
+        /// input PDF or image files
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// page selection for PDFs (e.g. "1", "1,3-5,10")
+        #[arg(short, long)]
+        pages: Option<String>,
+
+        /// DPI pages are rendered at before hashing (18-300)
+        #[arg(short, long, default_value_t = 72, value_parser = clap::value_parser!(u32).range(18..=300))]
+        dpi: u32,
+    },
+    /// watch a hot folder: split PDFs and merge image folders dropped into it
+    Watch {
+        /// folder to watch; finished inputs move to processed/ or failed/ inside it
+        dir: PathBuf,
+
+        /// output dir (default: output/ inside the watched folder)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// seconds between scans
+        #[arg(long, default_value_t = 2, value_parser = clap::value_parser!(u64).range(1..))]
+        interval: u64,
+
+        /// seconds an input must stay unchanged before it's converted
+        #[arg(long, default_value_t = 5)]
+        settle: u64,
+
+        /// convert what's in the folder now and exit, without waiting for it to settle
+        #[arg(long)]
+        once: bool,
+
+        /// image format for split PDFs
+        #[arg(short, long, default_value = "png")]
+        format: ImageFormat,
+
+        /// rendering DPI for split PDFs (72-2400)
+        #[arg(short, long, default_value_t = 300, value_parser = clap::value_parser!(u32).range(72..=2400))]
+        dpi: u32,
+
+        /// PNG compression: fast (speed), small (filesize) or max (archival, slowest)
+        #[arg(short, long, default_value = "fast")]
+        compress: PngCompression,
+
+        /// JPEG quality (1-100)
+        #[arg(long, default_value_t = 75, value_parser = clap::value_parser!(u8).range(1..=100))]
+        quality: u8,
+
+        /// render split pages and merged PDFs in grayscale
+        #[arg(long)]
+        gray: bool,
+
+        /// page size for merged PDFs (scales images to fit)
+        #[arg(long)]
+        pagesize: Option<PageSize>,
+
+        /// page orientation for merged PDFs: auto, portrait, landscape
+        #[arg(long, default_value_t = Orientation::Auto)]
+        orientation: Orientation,
+    },
+    /// check PDF structure and, optionally, PDF/A rules (exit status 1 if any file fails)
+    Validate {
+        /// input PDF files
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// also check the rules of this PDF/A level: 1b or 2b
+        #[arg(long)]
+        pdfa: Option<PdfaLevel>,
+
+        /// print machine-readable JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// run the split and merge jobs listed in a TOML or YAML job file
+    Batch {
+        /// job file with one [[job]] table per conversion (a job: list in .yaml/.yml)
+        job_file: PathBuf,
+
+        /// jobs run at the same time (each one is parallel already)
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+        parallel: u16,
+
+        /// print machine-readable JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// run an HTTP service converting uploads with split and merge
+    Serve {
+        /// address to listen on, e.g. 0.0.0.0:8080 for all interfaces
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        bind: String,
+
+        /// largest accepted upload (e.g. 50MB)
+        #[arg(long, default_value = "100MB")]
+        max_upload: ByteSize,
+
+        /// most pages a single request may render or create
+        #[arg(long, default_value_t = 500)]
+        max_pages: usize,
+
+        /// highest DPI /split renders at (72-2400)
+        #[arg(long, default_value_t = 300, value_parser = clap::value_parser!(u32).range(72..=2400))]
+        max_dpi: u32,
+
+        /// requests converted at once; others are answered 503
+        #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u16).range(1..))]
+        workers: u16,
+
+        /// seconds to wait on a stalled client
+        #[arg(long, default_value_t = 30)]
+        timeout: u64,
+    },
+    /// print library versions, threads and codecs, and run a tiny render and encode
+    /// self-test (exit status 1 if one fails)
+    Doctor {
+        /// print machine-readable JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// generate shell completions
+    Completions {
+        /// shell to generate completions for
+        shell: clap_complete::Shell,
+    },
+    /// write roff man pages for ovid and each subcommand (ovid.1, ovid-split.1, ...)
+    Manpages {
+        /// output dir, created if missing
+        #[arg(default_value = ".")]
+        dir: PathBuf,
+    },
+}
+
+/// exit status when some pages, images or jobs failed and were skipped but the
+/// rest was done; 0 is success and 1 (an error out of main) total failure
+const EXIT_PARTIAL: i32 = 2;
+/// exit status for arguments clap rejects
+const EXIT_USAGE: i32 = 3;
+/// exit status after SIGINT or SIGTERM stopped a split, merge or batch
+const EXIT_INTERRUPTED: i32 = interrupt::EXIT_CODE;
+
+fn main() -> Result<()> {
+    let cli = Cli::try_parse().unwrap_or_else(|e| {
+        // --help and --version come through here too, on stdout
+        let _ = e.print();
+        std::process::exit(if e.use_stderr() { EXIT_USAGE } else { 0 });
+    });
+    let json_progress = cli.progress == ProgressFormat::Json;
+    let level = match cli.verbose {
+        0 => cli.log_level,
+        1 => cli.log_level.max(LogLevel::Debug),
+        _ => LogLevel::Trace,
+    };
+    logging::init(level, cli.log_file.as_deref(), json_progress)?;
+    // the long runs stop cleanly on a signal; anything else just dies as before
+    let long_run = matches!(
+        cli.command,
+        Commands::Split { .. } | Commands::Merge { .. } | Commands::Batch { .. }
+    );
+    if long_run {
+        interrupt::install()?;
+    }
+    let result = run(cli);
+    if let Some(e) = result.as_ref().err().filter(|e| e.is::<interrupt::Interrupted>()) {
+        tracing::error!("{:#}", e);
+        std::process::exit(EXIT_INTERRUPTED);
+    }
+    if let (Err(e), true) = (&result, json_progress) {
+        // the failure too as a JSON line, not std's "Error: ..."
+        tracing::error!("{:#}", e);
+        std::process::exit(1);
+    }
+    result
+}
+
+fn run(cli: Cli) -> Result<()> {
+    if let Some(threads) = cli.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .context("Failed to configure thread pool")?;
+    }
+
+    // JSON progress replaces the human progress lines
+    let quiet = cli.quiet || cli.progress == ProgressFormat::Json;
+    // warnings are already there as log events
+    let progress = (cli.progress == ProgressFormat::Json).then(|| {
+        Progress::events(|event| {
+            if !matches!(event, Event::Warning { .. }) {
+                eprintln!("{}", event.to_json())
+            }
+        })
+    });
+
+    match cli.command {
+        Commands::Split {
+            input,
+            output,
+            format,
+            dpi,
+            compress,
+            gray,
+            pages,
+            quality,
+            skip_errors,
+            max_download,
+            low_memory,
+            cache,
+            force_static,
+            dry_run,
+            stats,
+        } => {
+            let output_dir = output.unwrap_or_else(|| match fetch::is_url(&input) {
+                true => PathBuf::from("."),
+                false => input.parent().unwrap_or_else(|| Path::new(".")).to_path_buf(),
+            });
+            let inputs = std::slice::from_ref(&input);
+            let report = fetch::with_local_inputs(inputs, max_download.0, quiet, |inputs| {
+                let opts = split::SplitOptions::default()
+                    .format(format)
+                    .dpi(dpi)
+                    .compress(compress)
+                    .gray(gray)
+                    .pages(pages)
+                    .quality(quality)
+                    .quiet(quiet)
+                    .progress(progress)
+                    .skip_errors(skip_errors)
+                    .memory_budget(low_memory.map(|ByteSize(bytes)| bytes))
+                    .cache(cache)
+                    .force_static(force_static);
+                if dry_run {
+                    return split::print_split_plan(&inputs[0], &output_dir, &opts).map(|()| None);
+                }
+                split::split_pdf(&inputs[0], &output_dir, &opts).map(Some)
+            })?;
+            if let (Some(format), Some(report)) = (stats, &report) {
+                print_stats(&report.stats, format);
+            }
+            if report.is_some_and(|report| !report.failed.is_empty()) {
+                std::process::exit(EXIT_PARTIAL);
+            }
+        }
+        Commands::Rasterize {
+            input,
+            output,
+            dpi,
+            gray,
+            quality,
+        } => {
+            let opts = rasterize::RasterizeOptions {
+                dpi,
+                gray,
+                jpeg_quality: quality,
+            };
+            let merge_opts = merge::MergeOptions::default().gray(gray).quiet(quiet);
+            rasterize::rasterize_pdf(&input, &output, &opts, &merge_opts)?;
+        }
+        Commands::Redact {
+            input,
+            output,
+            mut region,
+            regions,
+            dpi,
+            quality,
+        } => {
+            if let Some(path) = regions {
+                region.extend(redact::read_regions(&path)?);
+            }
+            anyhow::ensure!(!region.is_empty(), "Nothing to redact: give --region or --regions");
+            let opts = redact::RedactOptions {
+                dpi,
+                jpeg_quality: quality,
+                quiet,
+            };
+            redact::redact_pdf(&input, &output, &region, &opts)?;
+        }
+        Commands::Merge {
+            images,
+            interleave,
+            reverse_second,
+            output,
+            direction,
+            dpi,
+            title,
+            author,
+            pagesize,
+            orientation,
+            position,
+            scale,
+            flatten_alpha,
+            gray,
+            watermark_text,
+            watermark_image,
+            watermark_opacity,
+            watermark_rotation,
+            watermark_position,
+            watermark_color,
+            page_numbers,
+            page_numbers_position,
+            page_layout,
+            page_mode,
+            fit_window,
+            max_pages,
+            max_size,
+            max_download,
+            icc,
+            skip_errors,
+            placeholder,
+            max_pixels,
+            date,
+            keep_exif,
+            exif_gps,
+            strip_exif,
+            strip_metadata,
+            deflate,
+            deskew,
+            apng_frames,
+            bookmarks,
+            low_memory,
+            dry_run,
+            stats,
+        } => {
+            anyhow::ensure!(
+                (0.0..=1.0).contains(&watermark_opacity),
+                "Watermark opacity must be between 0.0 and 1.0"
+            );
+            let watermark_content = match (watermark_text, watermark_image) {
+                (Some(text), _) => Some(watermark::WatermarkContent::Text(text)),
+                (None, Some(path)) => Some(watermark::WatermarkContent::Image(path)),
+                (None, None) => None,
+            };
+            let watermark = watermark_content.map(|content| watermark::Watermark {
+                content,
+                opacity: watermark_opacity,
+                rotation: watermark_rotation,
+                position: watermark_position,
+                color: watermark_color,
+            });
+            let page_numbers = page_numbers.map(|format| watermark::PageNumbers {
+                format,
+                position: page_numbers_position,
+            });
+            let bookmarks = match bookmarks {
+                Some(path) => outline::load_outline_file(&path)?,
+                None => Vec::new(),
+            };
+            let opts = merge::MergeOptions::default()
+                .dpi(dpi)
+                .quiet(quiet)
+                .progress(progress)
+                .title(title)
+                .author(author)
+                .pagesize(pagesize)
+                .orientation(orientation)
+                .flatten_alpha(flatten_alpha)
+                .gray(gray)
+                .position(position)
+                .scale(scale)
+                .watermark(watermark)
+                .page_numbers(page_numbers)
+                .page_layout(page_layout)
+                .page_mode(page_mode)
+                .fit_window(fit_window)
+                .direction(direction)
+                .max_pages(max_pages.map(|n| n as usize))
+                .max_size(max_size.map(|ByteSize(bytes)| bytes))
+                .icc(icc)
+                .skip_errors(skip_errors)
+                .placeholder(placeholder)
+                .max_pixels((max_pixels > 0).then_some(max_pixels))
+                .date(date)
+                .keep_exif(keep_exif)
+                .exif_gps(exif_gps)
+                .strip_exif(strip_exif)
+                .strip_metadata(strip_metadata)
+                .deflate(deflate)
+                .deskew(deskew)
+                .apng_frames(apng_frames)
+                .bookmarks(bookmarks)
+                .memory_budget(low_memory.map(|ByteSize(bytes)| bytes));
+            // URLs are downloaded first; directories and globs are expanded after
+            let report = fetch::with_local_inputs(&images, max_download.0, quiet, |images| {
+                let images = match interleave.as_deref() {
+                    Some([first, second]) => parse::interleave_paths(
+                        parse::expand_image_paths(std::slice::from_ref(first))?,
+                        parse::expand_image_paths(std::slice::from_ref(second))?,
+                        reverse_second,
+                    )?,
+                    _ => parse::expand_image_paths(&images)?,
+                };
+                anyhow::ensure!(!images.is_empty(), "No input images provided");
+                if dry_run {
+                    return merge::print_merge_plan(&images, &output, &opts)
+                        .map(|()| merge::MergeReport::default());
+                }
+                merge::merge_images(&images, &output, &opts)
+            })?;
+            if let (Some(format), false) = (stats, dry_run) {
+                print_stats(&report.stats, format);
+            }
+            if report.skipped > 0 {
+                std::process::exit(EXIT_PARTIAL);
+            }
+        }
+        Commands::Combine { inputs, output } => {
+            combine::combine_pdfs(&inputs, &output, quiet)?;
+        }
+        Commands::Chunk {
+            input,
+            output,
+            every,
+            max_size,
+        } => {
+            let limit = match (every, max_size) {
+                (Some(every), _) => chunk::ChunkLimit::Pages(every as usize),
+                (None, Some(ByteSize(bytes))) => chunk::ChunkLimit::Size(bytes),
+                (None, None) => unreachable!("clap requires --every or --max-size"),
+            };
+            let output = output.unwrap_or_else(|| input.clone());
+            chunk::chunk_pdf(&input, &output, limit, quiet)?;
+        }
+        Commands::SplitBookmarks {
+            input,
+            output,
+            level,
+        } => {
+            let output_dir = output.unwrap_or_else(|| {
+                input
+                    .parent()
+                    .unwrap_or_else(|| Path::new("."))
+                    .to_path_buf()
+            });
+            chunk::split_at_bookmarks(&input, &output_dir, level as usize, quiet)?;
+        }
+        Commands::Dedupe {
+            input,
+            output,
+            similar,
+            threshold,
+            adjacent,
+        } => {
+            anyhow::ensure!(
+                (0.0..=1.0).contains(&threshold),
+                "--threshold must be between 0.0 and 1.0"
+            );
+            let opts = dedupe::DedupeOptions {
+                similar,
+                threshold,
+                adjacent,
+                quiet,
+            };
+            dedupe::dedupe_pdf(&input, &output, &opts)?;
+        }
+        Commands::Pages {
+            input,
+            output,
+            keep,
+            delete,
+            moves,
+        } => {
+            let edits = pages::PageEdits {
+                keep: keep.as_deref(),
+                delete: delete.as_deref(),
+                moves: &moves,
+            };
+            pages::edit_pages(&input, &output, &edits, quiet)?;
+        }
+        Commands::Rotate {
+            input,
+            output,
+            angle,
+            pages,
+        } => {
+            rotate::rotate_pages(&input, &output, angle, pages.as_deref(), quiet)?;
+        }
+        Commands::Crop {
+            input,
+            output,
+            rect,
+            margins,
+            target,
+            pages,
+        } => {
+            let crop = match (rect, margins) {
+                (Some(rect), _) => crop::Crop::Rect(rect),
+                (None, Some(margins)) => crop::Crop::Margins(margins),
+                (None, None) => unreachable!("clap requires --box or --margins"),
+            };
+            crop::crop_pages(&input, &output, crop, target, pages.as_deref(), quiet)?;
+        }
+        Commands::Nup {
+            input,
+            output,
+            per_sheet,
+            page_size,
+            orientation,
+            margin,
+            gap,
+            border,
+        } => {
+            anyhow::ensure!(
+                margin >= 0.0 && gap >= 0.0,
+                "Margin and gap can't be negative"
+            );
+            let opts = nup::NupOptions {
+                per_sheet: per_sheet as usize,
+                page_size,
+                orientation,
+                margin,
+                gap,
+                border,
+                quiet,
+            };
+            nup::nup_pdf(&input, &output, &opts)?;
+        }
+        Commands::Poster {
+            input,
+            output,
+            page_size,
+            orientation,
+            scale,
+            overlap,
+            margin,
+            no_marks,
+        } => {
+            let opts = poster::PosterOptions {
+                page_size,
+                orientation,
+                scale: scale.0,
+                overlap: overlap.0,
+                margin: margin.0,
+                marks: !no_marks,
+                quiet,
+            };
+            poster::poster_pdf(&input, &output, &opts)?;
+        }
+        Commands::Blank {
+            output,
+            pages,
+            page_size,
+            orientation,
+            template,
+            spacing,
+            margin,
+            color,
+        } => {
+            let opts = merge::MergeOptions::default()
+                .pagesize(page_size)
+                .orientation(orientation)
+                .quiet(quiet);
+            let blank = blank::BlankOptions {
+                template,
+                spacing: spacing.map(|s| s.0),
+                margin: margin.0,
+                color,
+            };
+            blank::blank_pdf(&output, pages as usize, &blank, &opts)?;
+        }
+        Commands::Flatten { input, output } => {
+            flatten::flatten_pdf(&input, &output, quiet)?;
+        }
+        Commands::Form {
+            input,
+            data,
+            output,
+            flatten,
+            json,
+        } => match (data, output) {
+            (Some(data), Some(output)) => {
+                form::fill_form(&input, &data, &output, flatten, quiet)?;
+            }
+            _ => form::list_fields(&input, json)?,
+        },
+        Commands::Repair { input, output } => {
+            repair::repair_pdf(&input, &output, quiet)?;
+        }
+        Commands::Optimize {
+            input,
+            output,
+            dpi,
+            quality,
+            no_jpeg,
+        } => {
+            let opts = optimize::OptimizeOptions {
+                max_dpi: (dpi > 0).then_some(dpi),
+                quality,
+                jpeg: !no_jpeg,
+                quiet,
+            };
+            optimize::optimize_pdf(&input, &output, &opts)?;
+        }
+        Commands::Convert {
+            inputs,
+            format,
+            output,
+            scale,
+            max_width,
+            max_height,
+            gray,
+            quality,
+            compress,
+        } => {
+            let inputs = parse::expand_image_paths(&inputs)?;
+            let opts = convert::ConvertOptions {
+                format,
+                output_dir: output,
+                scale,
+                max_width,
+                max_height,
+                gray,
+                quality,
+                compress,
+                deskew: false,
+                quiet,
+            };
+            convert::convert_images(&inputs, &opts)?;
+        }
+        Commands::Grid {
+            inputs,
+            output,
+            cols,
+            rows,
+            cell_size,
+            gap,
+            caption,
+            background,
+            quality,
+        } => {
+            let inputs = parse::expand_image_paths(&inputs)?;
+            anyhow::ensure!(!inputs.is_empty(), "No input images provided");
+            let opts = grid::GridOptions {
+                cols,
+                rows,
+                cell: cell_size,
+                gap,
+                caption,
+                background,
+                quality,
+                quiet,
+            };
+            grid::grid_images(&inputs, &output, &opts)?;
+        }
+        Commands::Deskew {
+            inputs,
+            output,
+            format,
+            quality,
+            compress,
+        } => {
+            let inputs = parse::expand_image_paths(&inputs)?;
+            let opts = convert::ConvertOptions {
+                format,
+                output_dir: Some(output),
+                scale: None,
+                max_width: None,
+                max_height: None,
+                gray: false,
+                quality,
+                compress,
+                deskew: true,
+                quiet,
+            };
+            convert::convert_images(&inputs, &opts)?;
+        }
+        Commands::Watermark {
+            input,
+            output,
+            text,
+            image,
+            opacity,
+            rotation,
+            position,
+            color,
+            pages,
+        } => {
+            anyhow::ensure!(
+                (0.0..=1.0).contains(&opacity),
+                "Watermark opacity must be between 0.0 and 1.0"
+            );
+            let content = match (text, image) {
+                (Some(text), _) => watermark::WatermarkContent::Text(text),
+                (None, Some(path)) => watermark::WatermarkContent::Image(path),
+                (None, None) => unreachable!("clap requires --text or --image"),
+            };
+            let mark = watermark::Watermark {
+                content,
+                opacity,
+                rotation,
+                position,
+                color,
+            };
+            watermark::watermark_pdf(&input, &output, &mark, pages.as_deref(), quiet)?;
+        }
+        Commands::Stamp {
+            inputs,
+            output,
+            prefix,
+            start,
+            digits,
+            position,
+            margin,
+            font,
+            font_size,
+            color,
+            json,
+        } => {
+            anyhow::ensure!(font_size > 0.0, "Font size must be positive");
+            anyhow::ensure!(margin >= 0.0, "Margin can't be negative");
+            let opts = stamp::BatesOptions {
+                prefix: &prefix,
+                start,
+                digits: digits as usize,
+                position,
+                margin,
+                font,
+                size: font_size,
+                color,
+                json,
+                quiet,
+            };
+            stamp::bates_stamp(&inputs, &output, &opts)?;
+        }
+        Commands::Bookmarks {
+            input,
+            apply,
+            output,
+            format,
+        } => match (apply, output) {
+            (Some(outline_file), Some(output)) => {
+                outline::apply_bookmarks(&input, &outline_file, &output, quiet)?;
+            }
+            (_, output) => outline::export_bookmarks(&input, output.as_deref(), format)?,
+        },
+        Commands::Annots {
+            input,
+            output,
+            strip,
+        } => {
+            annots::export_annotations(&input, output.as_deref(), strip.as_deref(), quiet)?;
+        }
+        Commands::Layers {
+            input,
+            on,
+            off,
+            remove,
+            output,
+            json,
+        } => match output {
+            Some(output) => {
+                anyhow::ensure!(
+                    !(on.is_empty() && off.is_empty() && remove.is_empty()),
+                    "--output needs --on, --off or --remove"
+                );
+                let changes = layers::LayerChanges {
+                    on: &on,
+                    off: &off,
+                    remove: &remove,
+                };
+                layers::set_layers(&input, &changes, &output, quiet)?;
+            }
+            None => layers::list_layers(&input, json)?,
+        },
+        Commands::Images { input, json } => images::print_images(&input, json)?,
+        Commands::Fonts {
+            input,
+            json,
+            by_page,
+            check,
+        } => fonts::print_fonts(&input, json, by_page, check, quiet)?,
+        Commands::Links {
+            input,
+            replace,
+            output,
+        } => match output {
+            Some(output) if !replace.is_empty() => {
+                links::rewrite_links(&input, &replace, &output, quiet)?;
+            }
+            output => links::export_links(&input, output.as_deref())?,
+        },
+        Commands::Overlay {
+            input,
+            overlay,
+            output,
+            under,
+            multi,
+            pages,
+        } => {
+            let opts = overlay::OverlayOptions {
+                under,
+                multi,
+                pages: pages.as_deref(),
+                quiet,
+            };
+            overlay::overlay_pdf(&input, &overlay, &output, &opts)?;
+        }
+        Commands::Encrypt {
+            input,
+            output,
+            user_password,
+            owner_password,
+            no_print,
+            no_modify,
+            no_copy,
+            no_annotate,
+        } => {
+            let owner_password = owner_password.unwrap_or_else(|| user_password.clone());
+            anyhow::ensure!(
+                !owner_password.is_empty(),
+                "Give a --user-password or an --owner-password"
+            );
+            let opts = encrypt::EncryptOptions {
+                user_password: &user_password,
+                owner_password: &owner_password,
+                permissions: encrypt::Permissions {
+                    print: !no_print,
+                    modify: !no_modify,
+                    copy: !no_copy,
+                    annotate: !no_annotate,
+                },
+                quiet,
+            };
+            encrypt::encrypt_pdf(&input, &output, &opts)?;
+        }
+        Commands::Decrypt {
+            input,
+            output,
+            password,
+        } => {
+            encrypt::decrypt_pdf(&input, &output, &password, quiet)?;
+        }
+        Commands::Unlock {
+            input,
+            output,
+            password,
+        } => {
+            encrypt::unlock_pdf(&input, &output, &password, quiet)?;
+        }
+        Commands::Sign {
+            input,
+            key,
+            password,
+            output,
+            rect,
+            page,
+            field,
+            reason,
+            location,
+            verify,
+            json,
+        } => {
+            if verify {
+                // like compare: 1 when a signature doesn't hold, or there is none
+                if !sign::verify_pdf(&input, json)? {
+                    std::process::exit(1);
+                }
+            } else {
+                let key = key.expect("clap requires --key unless --verify");
+                let opts = sign::SignOptions {
+                    key: &key,
+                    password: &password,
+                    visible: rect.map(|rect| (page, rect)),
+                    field: field.as_deref(),
+                    reason: reason.as_deref(),
+                    location: location.as_deref(),
+                    quiet,
+                };
+                sign::sign_pdf(&input, output.as_deref(), &opts)?;
+            }
+        }
+        Commands::Meta {
+            input,
+            set,
+            output,
+            json,
+        } => {
+            if set.is_empty() {
+                meta::print_meta(&input, json)?;
+            } else {
+                meta::edit_meta(&input, output.as_deref(), &set, quiet)?;
+            }
+        }
+        Commands::Text {
+            input,
+            output,
+            per_page,
+            json,
+            pages,
+        } => {
+            let output = output.unwrap_or_else(|| {
+                if per_page {
+                    input.parent().unwrap_or_else(|| Path::new(".")).to_path_buf()
+                } else {
+                    PathBuf::from("-")
+                }
+            });
+            let opts = text::TextOptions {
+                pages: pages.as_deref(),
+                per_page,
+                json,
+                quiet,
+            };
+            text::extract_text(&input, &output, &opts)?;
+        }
+        Commands::Compare {
+            first,
+            second,
+            dpi,
+            fuzz,
+            threshold,
+            diff_dir,
+            pages,
+            json,
+        } => {
+            anyhow::ensure!(
+                (0.0..=1.0).contains(&threshold),
+                "Threshold must be between 0.0 and 1.0"
+            );
+            let opts = compare::CompareOptions {
+                dpi,
+                fuzz,
+                threshold,
+                pages: pages.as_deref(),
+                diff_dir: diff_dir.as_deref(),
+                json,
+                quiet,
+            };
+            // like cmp: 1 when the documents differ, 2 when they could not be compared
+            match compare::compare_pdfs(&first, &second, &opts) {
+                Ok(true) => {}
+                Ok(false) => std::process::exit(1),
+                Err(e) => {
+                    tracing::error!("{:?}", e);
+                    std::process::exit(2);
+                }
+            }
+        }
+        Commands::Info { inputs, json } => {
+            info::print_info(&inputs, json)?;
+        }
+        Commands::Pagecount { inputs, json } => {
+            info::print_page_counts(&inputs, json)?;
+        }
+        Commands::Hash { inputs, pages, dpi } => {
+            hash::print_hashes(&inputs, pages.as_deref(), dpi)?;
+        }
+        Commands::Watch {
+            dir,
+            output,
+            interval,
+            settle,
+            once,
+            format,
+            dpi,
+            compress,
+            quality,
+            gray,
+            pagesize,
+            orientation,
+        } => {
+            let output = output.unwrap_or_else(|| dir.join("output"));
+            let opts = watch::WatchOptions {
+                output: &output,
+                interval: std::time::Duration::from_secs(interval),
+                settle: std::time::Duration::from_secs(settle),
+                once,
+                format,
+                dpi,
+                compress,
+                quality,
+                gray,
+                pagesize,
+                orientation,
+                quiet,
+            };
+            watch::watch(&dir, &opts)?;
+        }
+        Commands::Doctor { json } => {
+            if !doctor::run_doctor(json)? {
+                std::process::exit(1);
+            }
+        }
+        Commands::Validate { inputs, pdfa, json } => {
+            if !validate::validate_pdfs(&inputs, pdfa, json)? {
+                std::process::exit(1);
+            }
+        }
+        Commands::Batch {
+            job_file,
+            parallel,
+            json,
+        } => {
+            if !batch::run_jobs(&job_file, parallel as usize, json, quiet)? {
+                std::process::exit(EXIT_PARTIAL);
+            }
+        }
+        Commands::Serve {
+            bind,
+            max_upload,
+            max_pages,
+            max_dpi,
+            workers,
+            timeout,
+        } => {
+            let opts = serve::ServeOptions {
+                max_upload: max_upload.0,
+                max_pages,
+                max_dpi,
+                workers: workers as usize,
+                timeout: std::time::Duration::from_secs(timeout),
+                quiet,
+            };
+            serve::serve(&bind, opts)?;
+        }
+        Commands::Completions { shell } => {
+            clap_complete::generate(
+                shell,
+                &mut Cli::command(),
+                "ovid",
+                &mut std::io::stdout(),
+            );
+        }
+        Commands::Manpages { dir } => {
+            std::fs::create_dir_all(&dir)
+                .with_context(|| format!("Cannot create output dir: {}", dir.display()))?;
+            clap_mangen::generate_to(Cli::command(), &dir)
+                .with_context(|| format!("Failed to write man pages to {}", dir.display()))?;
+            if !quiet {
+                tracing::info!("Man pages written to {}", dir.display());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// print a run's `--stats` report on stderr
+fn print_stats(stats: &stats::RunStats, format: StatsFormat) {
+    match format {
+        StatsFormat::Text => eprint!("{}", stats.to_text()),
+        StatsFormat::Json => eprintln!("{}", stats.to_json()),
+    }
 }
@@ -1,9 +1,303 @@
 use anyhow::{Context, Result};
-use rayon::prelude::*;
-use std::io::Write;
+use std::cell::RefCell;
+use std::io::{BufRead, Seek, Write};
+use std::ops::Range;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
+// the parallel prepare_in_order; wasm32 prepares images one by one
+#[cfg(not(target_arch = "wasm32"))]
+use std::{
+    collections::BTreeMap,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    sync::{mpsc, Condvar, Mutex},
+};
+use tracing::{info, trace};
 
-use crate::parse::{parse_jpeg_header, parse_png_header, Orientation, PageSize, PngInfo};
+use chrono::{DateTime, FixedOffset, Local};
+use lopdf::content::{Content, Operation};
+use lopdf::{dictionary, Document, Object, ObjectId};
+
+use crate::deflate;
+use crate::deskew;
+use crate::epub;
+use crate::icc;
+use crate::interrupt::{CancelToken, Interrupted};
+use crate::outline::{self, Bookmark};
+use crate::parse::{
+    apng_frame_count, find_exif, gif_has_transparency, is_image_extension,
+    jpeg_ranges_without_app1, jpeg_ranges_without_metadata, parse_exif, parse_jpeg_header,
+    parse_png_header, parse_tiff_ccitt, parse_tiff_resolution, CcittInfo, Color, Deflate,
+    Direction, ExifInfo, IccMode, InputFormat, Orientation, PageLayout, PageMode, PageSize,
+    PngInfo, Position, Scale,
+};
+use crate::pdf;
+use crate::progress::{self, Event, Progress};
+#[cfg(feature = "native")]
+use crate::split;
+use crate::stats::{PageStats, RunStats};
+use crate::watermark::{self, PageNumbers, Watermark, WatermarkContent};
+use crate::writer::{self, PdfWriter};
+
+/// settings for a merge run, applied to every page
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct MergeOptions {
+    /// DPI for page sizing (overrides image metadata)
+    pub dpi: Option<u32>,
+    /// don't print progress to stderr
+    pub quiet: bool,
+    /// called as each image is added
+    pub progress: Option<Progress>,
+    /// stops the merge when cancelled
+    pub cancel: CancelToken,
+    /// document title metadata
+    pub title: Option<String>,
+    /// document author metadata
+    pub author: Option<String>,
+    /// fixed page size to fit images on, instead of pages sized to the images
+    pub pagesize: Option<PageSize>,
+    /// page orientation with `pagesize`
+    pub orientation: Orientation,
+    /// composite transparent images over this color instead of emitting an SMask
+    pub flatten_alpha: Option<Color>,
+    /// convert every page to single-channel DeviceGray
+    pub gray: bool,
+    /// image placement on fixed-size pages
+    pub position: Position,
+    /// fixed image scale instead of fit-to-page
+    pub scale: Option<Scale>,
+    /// stamp drawn over every page
+    pub watermark: Option<Watermark>,
+    /// printed page numbers
+    pub page_numbers: Option<PageNumbers>,
+    /// initial page arrangement in viewers
+    pub page_layout: Option<PageLayout>,
+    /// initial side panel in viewers
+    pub page_mode: Option<PageMode>,
+    /// open with the window sized to, and zoomed to fit, the first page
+    pub fit_window: bool,
+    /// reading order; right to left puts facing pages right to left
+    pub direction: Direction,
+    /// start a new numbered output file after this many pages
+    pub max_pages: Option<usize>,
+    /// start a new numbered output file before exceeding this many bytes (approximate)
+    pub max_size: Option<u64>,
+    /// handling of embedded ICC profiles
+    pub icc: IccMode,
+    /// warn about unreadable images and carry on instead of aborting
+    pub skip_errors: bool,
+    /// with `skip_errors`, keep a captioned blank page in place of each skipped image
+    pub placeholder: bool,
+    /// refuse to decode images with more pixels than this (None: no limit)
+    pub max_pixels: Option<u64>,
+    /// CreationDate/ModDate to record instead of the current local time
+    pub date: Option<DateTime<FixedOffset>>,
+    /// copy capture date and camera from each image's EXIF into its page's PieceInfo
+    pub keep_exif: bool,
+    /// with `keep_exif`, also copy the GPS position
+    pub exif_gps: bool,
+    /// remove EXIF/XMP segments from JPEGs embedded as-is
+    pub strip_exif: bool,
+    /// remove every APPn segment a decoder doesn't need from JPEGs embedded
+    /// as-is, the ICC profile too unless `icc` keeps profiles
+    pub strip_metadata: bool,
+    /// deflate level for decoded images, their soft masks and ICC profiles
+    pub deflate: Deflate,
+    /// straighten skewed scans; every image is decoded and re-compressed
+    pub deskew: bool,
+    /// a page for each frame of an animated PNG read from a file; otherwise
+    /// only its first frame (the image viewers without animation show) is
+    /// merged, with a warning
+    pub apng_frames: bool,
+    /// outline to add, pages counted in the merged output
+    pub bookmarks: Vec<Bookmark>,
+    /// bytes the images being decoded and written may take together, as
+    /// estimated from their headers; fewer are decoded at once to stay under
+    /// it (None: a few per worker)
+    pub memory_budget: Option<u64>,
+}
+
+/// one setter per field, to build the options up from the defaults:
+/// `MergeOptions::default().pagesize(PageSize::A4).gray(true)`. new fields
+/// get a setter and a default, so callers setting only what they need keep
+/// compiling
+impl MergeOptions {
+    pub fn dpi(mut self, dpi: impl Into<Option<u32>>) -> Self {
+        self.dpi = dpi.into();
+        self
+    }
+
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    pub fn progress(mut self, progress: impl Into<Option<Progress>>) -> Self {
+        self.progress = progress.into();
+        self
+    }
+
+    pub fn cancel(mut self, cancel: CancelToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    pub fn title(mut self, title: impl Into<Option<String>>) -> Self {
+        self.title = title.into();
+        self
+    }
+
+    pub fn author(mut self, author: impl Into<Option<String>>) -> Self {
+        self.author = author.into();
+        self
+    }
+
+    pub fn pagesize(mut self, pagesize: impl Into<Option<PageSize>>) -> Self {
+        self.pagesize = pagesize.into();
+        self
+    }
+
+    pub fn orientation(mut self, orientation: Orientation) -> Self {
+        self.orientation = orientation;
+        self
+    }
+
+    pub fn flatten_alpha(mut self, flatten_alpha: impl Into<Option<Color>>) -> Self {
+        self.flatten_alpha = flatten_alpha.into();
+        self
+    }
+
+    pub fn gray(mut self, gray: bool) -> Self {
+        self.gray = gray;
+        self
+    }
+
+    pub fn position(mut self, position: Position) -> Self {
+        self.position = position;
+        self
+    }
+
+    pub fn scale(mut self, scale: impl Into<Option<Scale>>) -> Self {
+        self.scale = scale.into();
+        self
+    }
+
+    pub fn watermark(mut self, watermark: impl Into<Option<Watermark>>) -> Self {
+        self.watermark = watermark.into();
+        self
+    }
+
+    pub fn page_numbers(mut self, page_numbers: impl Into<Option<PageNumbers>>) -> Self {
+        self.page_numbers = page_numbers.into();
+        self
+    }
+
+    pub fn page_layout(mut self, page_layout: impl Into<Option<PageLayout>>) -> Self {
+        self.page_layout = page_layout.into();
+        self
+    }
+
+    pub fn page_mode(mut self, page_mode: impl Into<Option<PageMode>>) -> Self {
+        self.page_mode = page_mode.into();
+        self
+    }
+
+    pub fn fit_window(mut self, fit_window: bool) -> Self {
+        self.fit_window = fit_window;
+        self
+    }
+
+    pub fn direction(mut self, direction: Direction) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    pub fn max_pages(mut self, max_pages: impl Into<Option<usize>>) -> Self {
+        self.max_pages = max_pages.into();
+        self
+    }
+
+    pub fn max_size(mut self, max_size: impl Into<Option<u64>>) -> Self {
+        self.max_size = max_size.into();
+        self
+    }
+
+    pub fn icc(mut self, icc: IccMode) -> Self {
+        self.icc = icc;
+        self
+    }
+
+    pub fn skip_errors(mut self, skip_errors: bool) -> Self {
+        self.skip_errors = skip_errors;
+        self
+    }
+
+    pub fn placeholder(mut self, placeholder: bool) -> Self {
+        self.placeholder = placeholder;
+        self
+    }
+
+    pub fn max_pixels(mut self, max_pixels: impl Into<Option<u64>>) -> Self {
+        self.max_pixels = max_pixels.into();
+        self
+    }
+
+    pub fn date(mut self, date: impl Into<Option<DateTime<FixedOffset>>>) -> Self {
+        self.date = date.into();
+        self
+    }
+
+    pub fn keep_exif(mut self, keep_exif: bool) -> Self {
+        self.keep_exif = keep_exif;
+        self
+    }
+
+    pub fn exif_gps(mut self, exif_gps: bool) -> Self {
+        self.exif_gps = exif_gps;
+        self
+    }
+
+    pub fn strip_exif(mut self, strip_exif: bool) -> Self {
+        self.strip_exif = strip_exif;
+        self
+    }
+
+    pub fn strip_metadata(mut self, strip_metadata: bool) -> Self {
+        self.strip_metadata = strip_metadata;
+        self
+    }
+
+    pub fn deflate(mut self, deflate: Deflate) -> Self {
+        self.deflate = deflate;
+        self
+    }
+
+    pub fn deskew(mut self, deskew: bool) -> Self {
+        self.deskew = deskew;
+        self
+    }
+
+    pub fn apng_frames(mut self, apng_frames: bool) -> Self {
+        self.apng_frames = apng_frames;
+        self
+    }
+
+    pub fn bookmarks(mut self, bookmarks: Vec<Bookmark>) -> Self {
+        self.bookmarks = bookmarks;
+        self
+    }
+
+    pub fn memory_budget(mut self, memory_budget: impl Into<Option<u64>>) -> Self {
+        self.memory_budget = memory_budget.into();
+        self
+    }
+}
+
+/// PieceInfo key for data written by ovid
+const PIECE_INFO_KEY: &str = "ovid";
+
+/// JPEG quality used when color JPEGs have to be re-encoded as grayscale
+const GRAY_JPEG_QUALITY: u8 = 90;
 
 /// pre-processed image data ready for PDF insertion
 enum PreparedImage {
@@ -13,33 +307,415 @@ enum PreparedImage {
         components: u8,
         /// true if CMYK values need inversion
         invert_cmyk: bool,
-        data: Vec<u8>,
-        dpi: Option<u32>,
+        data: ImageBytes,
+        /// the byte ranges of `data` that go into the PDF, None for all of it
+        parts: Option<Vec<Range<usize>>>,
+        dpi: Option<(u32, u32)>,
         icc_profile: Option<Vec<u8>>,
     },
     PngPassthrough {
         info: PngInfo,
+        /// soft mask for palettes with partially transparent entries
+        alpha_compressed: Option<Vec<u8>>,
+    },
+    /// Group 3/4 fax TIFF strip, embedded without re-encoding
+    Ccitt {
+        info: CcittInfo,
+        dpi: Option<(u32, u32)>,
     },
     /// decoded pixel data compressed with deflate
     Compressed {
         width: u32,
         height: u32,
         color_channels: u8,
+        /// 8, or 16 for high-depth sources (applies to the alpha channel too)
+        bits_per_component: u8,
         color_compressed: Vec<u8>,
         alpha_compressed: Option<Vec<u8>>,
-        dpi: Option<u32>,
+        dpi: Option<(u32, u32)>,
         icc_profile: Option<Vec<u8>>,
     },
 }
 
-fn prepare_image(path: &Path) -> Result<PreparedImage> {
-    let data = std::fs::read(path)
-        .with_context(|| format!("Failed to read {}", path.display()))?;
+impl PreparedImage {
+    /// bytes of image data the XObject will carry
+    fn encoded_len(&self) -> usize {
+        let icc_len = |icc: &Option<Vec<u8>>| icc.as_ref().map_or(0, Vec::len);
+        match self {
+            PreparedImage::Jpeg {
+                data,
+                parts,
+                icc_profile,
+                ..
+            } => {
+                let len = match parts {
+                    Some(parts) => parts.iter().map(|part| part.len()).sum(),
+                    None => data.len(),
+                };
+                len + icc_len(icc_profile)
+            }
+            PreparedImage::PngPassthrough {
+                info,
+                alpha_compressed,
+            } => {
+                info.idat_data.len()
+                    + info.plte_data.len()
+                    + alpha_compressed.as_ref().map_or(0, Vec::len)
+                    + icc_len(&info.icc_profile)
+            }
+            PreparedImage::Ccitt { info, .. } => info.data.len(),
+            PreparedImage::Compressed {
+                color_compressed,
+                alpha_compressed,
+                icc_profile,
+                ..
+            } => {
+                color_compressed.len()
+                    + alpha_compressed.as_ref().map_or(0, Vec::len)
+                    + icc_len(icc_profile)
+            }
+        }
+    }
+
+    /// pixel size, color channels, bits per sample and how it's encoded
+    fn layout(&self) -> (u32, u32, u8, u8, &'static str) {
+        match self {
+            PreparedImage::Jpeg {
+                width,
+                height,
+                components,
+                ..
+            } => (*width, *height, *components, 8, "JPEG passthrough"),
+            PreparedImage::PngPassthrough { info, .. } => {
+                let channels = if info.color_type == 2 { 3 } else { 1 };
+                (info.width, info.height, channels, info.bit_depth, "PNG passthrough")
+            }
+            PreparedImage::Ccitt { info, .. } => (info.width, info.height, 1, 1, "CCITT fax"),
+            PreparedImage::Compressed {
+                width,
+                height,
+                color_channels,
+                bits_per_component,
+                ..
+            } => (*width, *height, *color_channels, *bits_per_component, "deflate"),
+        }
+    }
+
+    /// bytes of the image's pixels, uncompressed
+    fn raw_len(&self) -> u64 {
+        let (width, height, channels, bits, _) = self.layout();
+        width as u64 * height as u64 * channels as u64 * bits as u64 / 8
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    /// size, color space, encoding and compression, for the log:
+    /// "2480x3508 RGB 8-bit JPEG passthrough, 24.9 MB -> 1.2 MB (20.7:1)"
+    fn describe(&self) -> String {
+        use crate::parse::ByteSize;
+
+        let (width, height, channels, bits, encoding) = self.layout();
+        let color = match (self, channels) {
+            (PreparedImage::PngPassthrough { info, .. }, _) if info.color_type == 3 => "indexed",
+            (_, 1) => "gray",
+            (_, 4) => "CMYK",
+            _ => "RGB",
+        };
+        let raw = self.raw_len();
+        let encoded = self.encoded_len() as u64;
+        format!(
+            "{}x{} {} {}-bit {}, {} -> {} ({:.1}:1)",
+            width,
+            height,
+            color,
+            bits,
+            encoding,
+            ByteSize(raw),
+            ByteSize(encoded),
+            raw as f64 / encoded.max(1) as f64
+        )
+    }
+}
+
+/// a prepared image and the source metadata that goes with its page
+struct PreparedPage {
+    image: PreparedImage,
+    exif: Option<ExifInfo>,
+    /// time spent reading the file, and decoding and compressing the image
+    read: Duration,
+    prepare: Duration,
+}
+
+/// an input image's bytes, mapped from disk where that's possible, so a JPEG
+/// passed through goes from the page cache into the PDF without a copy on the
+/// heap
+enum ImageBytes {
+    Owned(Vec<u8>),
+    #[cfg(feature = "native")]
+    Mapped(memmap2::Mmap),
+}
+
+impl std::ops::Deref for ImageBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            ImageBytes::Owned(data) => data,
+            #[cfg(feature = "native")]
+            ImageBytes::Mapped(map) => map,
+        }
+    }
+}
+
+impl From<Vec<u8>> for ImageBytes {
+    fn from(data: Vec<u8>) -> Self {
+        ImageBytes::Owned(data)
+    }
+}
 
+/// files smaller than this are read: mapping them costs more than the copy
+#[cfg(feature = "native")]
+const MAP_MIN_LEN: u64 = 64 * 1024;
+
+/// map `path` if it's a regular file of some size, or read it
+fn read_image_file(path: &Path) -> Result<ImageBytes> {
+    let failed = || format!("Failed to read {}", path.display());
+    #[cfg(feature = "native")]
+    {
+        let file = std::fs::File::open(path).with_context(failed)?;
+        let meta = file.metadata().with_context(failed)?;
+        if meta.is_file() && meta.len() >= MAP_MIN_LEN {
+            // SAFETY: inputs aren't expected to change during a merge; one
+            // truncated underneath it faults, as it would any reader that maps
+            if let Ok(map) = unsafe { memmap2::Mmap::map(&file) } {
+                return Ok(ImageBytes::Mapped(map));
+            }
+        }
+    }
+    Ok(ImageBytes::Owned(std::fs::read(path).with_context(failed)?))
+}
+
+/// scratch buffers a worker thread keeps between the images it prepares
+const SCRATCH_KEEP: usize = 4;
+
+thread_local! {
+    static SCRATCH: RefCell<Vec<Vec<u8>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// a byte buffer from the worker thread's pool, back in it when dropped, so the
+/// decode buffers and rows of one image are reused by the next rather than
+/// allocated again
+struct Scratch(Vec<u8>);
+
+impl Scratch {
+    /// an empty buffer with room for `capacity` bytes: the smallest pooled one
+    /// that fits, or else the biggest, grown
+    fn with_capacity(capacity: usize) -> Self {
+        let buf = SCRATCH.with(|pool| {
+            let mut pool = pool.borrow_mut();
+            let by_size = pool.iter().enumerate().map(|(i, buf)| (buf.capacity(), i));
+            let fits = by_size.clone().filter(|&(len, _)| len >= capacity).min();
+            let (_, i) = fits.or_else(|| by_size.max())?;
+            Some(pool.swap_remove(i))
+        });
+        let mut buf = buf.unwrap_or_default();
+        buf.clear();
+        buf.reserve(capacity);
+        Scratch(buf)
+    }
+
+    /// `len` zeroed bytes
+    fn zeroed(len: usize) -> Self {
+        let mut scratch = Scratch::with_capacity(len);
+        scratch.resize(len, 0);
+        scratch
+    }
+}
+
+/// a buffer allocated elsewhere, such as a decoder's, pooled once dropped
+impl From<Vec<u8>> for Scratch {
+    fn from(buf: Vec<u8>) -> Self {
+        Scratch(buf)
+    }
+}
+
+impl std::ops::Deref for Scratch {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.0
+    }
+}
+
+impl std::ops::DerefMut for Scratch {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.0
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let buf = std::mem::take(&mut self.0);
+        // try_with: the thread may be on its way out
+        let _ = SCRATCH.try_with(|pool| {
+            let mut pool = pool.borrow_mut();
+            if pool.len() < SCRATCH_KEEP {
+                pool.push(buf);
+            }
+        });
+    }
+}
+
+fn prepare_image(path: &Path, opts: &MergeOptions) -> Result<PreparedPage> {
+    let started = std::time::Instant::now();
+    let data = read_image_file(path)?;
+    let read = started.elapsed();
+    let mut prepared = prepare_image_data(data, path, opts);
+    let prepare = started.elapsed() - read;
+    trace!(
+        read_ms = read.as_millis() as u64,
+        prepare_ms = prepare.as_millis() as u64,
+        "timings"
+    );
+    if let Ok(page) = &mut prepared {
+        (page.read, page.prepare) = (read, prepare);
+    }
+    prepared
+}
+
+/// prepare image bytes already read; `path` names the image in messages
+fn prepare_image_data(data: ImageBytes, path: &Path, opts: &MergeOptions) -> Result<PreparedPage> {
+    let exif = if opts.keep_exif {
+        find_exif(&data).and_then(parse_exif)
+    } else {
+        None
+    };
+    let mut image = load_image(data, path, opts)?;
+    apply_icc_mode(&mut image, opts.icc);
+    Ok(PreparedPage {
+        image,
+        exif,
+        read: Duration::ZERO,
+        prepare: Duration::ZERO,
+    })
+}
+
+/// prepare frame `frame` (0-based) of the animated PNG at `path`, for
+/// `apng_frames`
+fn prepare_frame(path: &Path, frame: u32, opts: &MergeOptions) -> Result<PreparedPage> {
+    let started = std::time::Instant::now();
+    let data = read_image_file(path)?;
+    let read = started.elapsed();
+    let exif = if opts.keep_exif {
+        find_exif(&data).and_then(parse_exif)
+    } else {
+        None
+    };
+    let mut image = decode_apng_frame(&data, frame, path, opts)?;
+    apply_icc_mode(&mut image, opts.icc);
+    Ok(PreparedPage {
+        image,
+        exif,
+        read,
+        prepare: started.elapsed() - read,
+    })
+}
+
+/// keep, drop, or replace the image's embedded profile according to `--icc`
+fn apply_icc_mode(img: &mut PreparedImage, mode: IccMode) {
+    let (profile, channels) = match img {
+        PreparedImage::Jpeg {
+            icc_profile,
+            components,
+            ..
+        } => (icc_profile, *components),
+        // a palette's profile describes its RGB entries
+        PreparedImage::PngPassthrough { info, .. } => {
+            let channels = if info.color_type == 0 { 1 } else { 3 };
+            (&mut info.icc_profile, channels)
+        }
+        PreparedImage::Compressed {
+            icc_profile,
+            color_channels,
+            ..
+        } => (icc_profile, *color_channels),
+        PreparedImage::Ccitt { .. } => return,
+    };
+    match mode {
+        IccMode::Keep => {}
+        IccMode::Strip => *profile = None,
+        IccMode::Srgb => {
+            if profile.is_some() {
+                *profile = (channels == 3).then(|| icc::srgb_profile().to_vec());
+            }
+        }
+    }
+}
+
+/// a PNG without an embedded profile that declares its encoding with an sRGB
+/// chunk, or with a gAMA chunk, gets a profile built for it, so readers that
+/// manage color show it as other viewers do rather than as DeviceRGB. sRGB
+/// wins over gAMA, as the PNG spec has it
+fn tag_png_profile(info: &mut PngInfo) {
+    if info.icc_profile.is_some() {
+        return;
+    }
+    // a palette's profile describes its RGB entries
+    let channels = if matches!(info.color_type, 0 | 4) { 1 } else { 3 };
+    info.icc_profile = match (info.srgb, info.gamma) {
+        (true, _) if channels == 1 => Some(icc::srgb_gray_profile().to_vec()),
+        (true, _) => Some(icc::srgb_profile().to_vec()),
+        // gAMA holds the encoding gamma, a profile the decoding one
+        (false, Some(gamma)) => Some(icc::gamma_profile(100_000.0 / gamma as f64, channels)),
+        (false, None) => None,
+    };
+}
+
+fn load_image(data: ImageBytes, path: &Path, opts: &MergeOptions) -> Result<PreparedImage> {
     anyhow::ensure!(data.len() >= 4, "File too small: {}", path.display());
 
+    // content decides the decoder, a mislabeled file only gets a warning
+    let format = InputFormat::sniff(&data)
+        .with_context(|| format!("Unrecognized image format: {}", path.display()))?;
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+        if is_image_extension(ext) && !format.matches_extension(ext) {
+            let message = format!(
+                "{} contains {} data, not {}",
+                path.display(),
+                format.name(),
+                ext.to_uppercase()
+            );
+            progress::warn(&opts.progress, None, &message);
+        }
+    }
+    // every decoder below reads the first frame of an animated PNG
+    if let Some(frames) = apng_frame_count(&data).filter(|&frames| frames > 1) {
+        let message = format!(
+            "{} is animated: merging the first of its {} frames (--apng-frames for a page each)",
+            path.display(),
+            frames
+        );
+        progress::warn(&opts.progress, None, &message);
+    }
+
+    // straightening needs the pixels, whatever the format
+    if opts.deskew {
+        let (dpi, icc_profile) = match format {
+            InputFormat::Jpeg => parse_jpeg_header(&data)
+                .map(|info| (info.dpi, info.icc_profile))
+                .unwrap_or_default(),
+            InputFormat::Png => parse_png_header(&data)
+                .map(|mut info| {
+                    tag_png_profile(&mut info);
+                    (info.dpi, info.icc_profile)
+                })
+                .unwrap_or_default(),
+            _ => (parse_tiff_resolution(&data), None),
+        };
+        return decode_generic_image(&data, path, dpi, icc_profile, opts);
+    }
+
     // JPEG: passthrough
-    if data[0] == 0xFF && data[1] == 0xD8 {
+    if format == InputFormat::Jpeg {
         let jpeg_info = parse_jpeg_header(&data)
             .with_context(|| format!("Failed to parse JPEG header: {}", path.display()))?;
         anyhow::ensure!(
@@ -48,6 +724,10 @@ fn prepare_image(path: &Path) -> Result<PreparedImage> {
             jpeg_info.components,
             path.display()
         );
+        if opts.gray && jpeg_info.components != 1 {
+            ensure_pixel_limit(jpeg_info.width, jpeg_info.height, path, opts)?;
+            return reencode_jpeg_gray(&data, path, jpeg_info.dpi);
+        }
         // determine CMYK inversion
         // with transform=2 (YCCK), or when no Adobe marker
         let invert_cmyk = jpeg_info.components == 4
@@ -61,22 +741,41 @@ fn prepare_image(path: &Path) -> Result<PreparedImage> {
             height: jpeg_info.height,
             components: jpeg_info.components,
             invert_cmyk,
+            // metadata left out as the data is written, with no copy
+            parts: if opts.strip_metadata {
+                let keep_icc = matches!(opts.icc, IccMode::Keep);
+                Some(jpeg_ranges_without_metadata(&data, keep_icc))
+            } else {
+                opts.strip_exif.then(|| jpeg_ranges_without_app1(&data))
+            },
             data,
             dpi: jpeg_info.dpi,
             icc_profile: jpeg_info.icc_profile,
         });
     }
 
-    // PNG: passthrough for opaque non-interlaced without tRNS, decode otherwise
-    if data.len() >= 8 && data[..8] == [137, 80, 78, 71, 13, 10, 26, 10] {
-        let info = parse_png_header(&data)
+    // PNG: passthrough (tRNS becomes a color-key mask), decode alpha channels
+    if format == InputFormat::Png {
+        let mut info = parse_png_header(&data)
             .with_context(|| format!("Failed to parse PNG header: {}", path.display()))?;
+        tag_png_profile(&mut info);
 
-        // interlaced or tRNS PNGs cannot use IDAT passthrough, so full decode required
-        let needs_full_decode = info.interlace != 0 || info.has_trns;
+        // flattening tRNS transparency and converting RGB to gray need the pixels
+        // (palettes are converted in place)
+        let needs_full_decode = (info.trns.is_some() && opts.flatten_alpha.is_some())
+            || (opts.gray && info.color_type == 2);
 
         if needs_full_decode {
-            return decode_generic_image(&data, path, info.dpi, info.icc_profile);
+            return decode_generic_image(&data, path, info.dpi, info.icc_profile, opts);
+        }
+        // passthrough copies IDAT as is; everything else decodes rows
+        if info.interlace != 0 || matches!(info.color_type, 4 | 6) || info.trns.is_some() {
+            ensure_pixel_limit(info.width, info.height, path, opts)?;
+        }
+
+        // interlaced: re-pack rows at native bit depth instead of expanding to 8-bit
+        if info.interlace != 0 && matches!(info.color_type, 0 | 2 | 3) {
+            deinterlace_png(&data, &mut info, path, opts.deflate)?;
         }
 
         match info.color_type {
@@ -88,10 +787,19 @@ fn prepare_image(path: &Path) -> Result<PreparedImage> {
                         path.display()
                     );
                 }
-                return Ok(PreparedImage::PngPassthrough { info });
+                let alpha_compressed = match &info.trns {
+                    Some(trns) if info.color_type == 3 && !is_binary_alpha(trns) => {
+                        Some(palette_smask(&data, &info, trns, path, opts.deflate)?)
+                    }
+                    _ => None,
+                };
+                return Ok(PreparedImage::PngPassthrough {
+                    info,
+                    alpha_compressed,
+                });
             }
             4 | 6 => {
-                return decode_alpha_png(&data, &info, path);
+                return decode_alpha_png(&data, &info, path, opts);
             }
             _ => anyhow::bail!(
                 "Unsupported PNG color type {} in {}",
@@ -102,13 +810,91 @@ fn prepare_image(path: &Path) -> Result<PreparedImage> {
     }
 
     // generic image formats (TIFF, BMP, GIF, etc.) decode via image crate
-    decode_generic_image(&data, path, None, None)
+    let dpi = parse_tiff_resolution(&data);
+
+    // fax TIFF: passthrough, decoding would expand 1-bit data to 8 bits
+    if let Some(info) = parse_tiff_ccitt(&data) {
+        return Ok(PreparedImage::Ccitt { info, dpi });
+    }
+
+    // CMYK TIFF: keep the separations, the image crate would convert to RGB
+    if format == InputFormat::Tiff {
+        if let Some(img) = decode_cmyk_tiff(&data, path, dpi, opts)? {
+            return Ok(img);
+        }
+    }
+
+    decode_generic_image(&data, path, dpi, None, opts)
 }
 
-/// decode a PNG with alpha channel, split color+alpha, compress separately
-fn decode_alpha_png(data: &[u8], info: &PngInfo, path: &Path) -> Result<PreparedImage> {
+/// decode a CMYK(A) TIFF to DeviceCMYK samples, or None if the TIFF is not CMYK
+/// or needs an RGB/gray conversion (`--gray`, or flattening its alpha)
+fn decode_cmyk_tiff(
+    data: &[u8],
+    path: &Path,
+    dpi: Option<(u32, u32)>,
+    opts: &MergeOptions,
+) -> Result<Option<PreparedImage>> {
+    use tiff::decoder::{Decoder, DecodingResult};
+    use tiff::tags::Tag;
+    use tiff::ColorType;
+
+    if opts.gray {
+        return Ok(None);
+    }
+    let Ok(mut decoder) = Decoder::new(std::io::Cursor::new(data)) else {
+        return Ok(None);
+    };
+    let (has_alpha, bits) = match decoder.colortype() {
+        Ok(ColorType::CMYK(bits @ (8 | 16))) => (false, bits),
+        Ok(ColorType::CMYKA(bits @ (8 | 16))) => (true, bits),
+        _ => return Ok(None),
+    };
+    if has_alpha && opts.flatten_alpha.is_some() {
+        return Ok(None);
+    }
+    let (width, height) = decoder.dimensions()?;
+    ensure_pixel_limit(width, height, path, opts)?;
+    let icc_profile = decoder.get_tag_u8_vec(Tag::IccProfile).ok();
+
+    // PDF wants 16-bit samples big-endian
+    let pixels = match decoder
+        .read_image()
+        .with_context(|| format!("Failed to decode CMYK TIFF: {}", path.display()))?
+    {
+        DecodingResult::U8(v) => v,
+        DecodingResult::U16(v) => v.iter().flat_map(|s| s.to_be_bytes()).collect(),
+        _ => anyhow::bail!("Unexpected CMYK sample format in {}", path.display()),
+    };
+
+    let channels = if has_alpha { 5 } else { 4 };
+    let bytes_per_sample = bits as usize / 8;
+    let (color_compressed, alpha_compressed) = if has_alpha {
+        let pixel_bytes = channels * bytes_per_sample;
+        let color_bytes = 4 * bytes_per_sample;
+        let (color, alpha) =
+            deflate::compress_split(&pixels, pixel_bytes, color_bytes, opts.deflate)?;
+        (color, Some(alpha))
+    } else {
+        (deflate::compress(&pixels, opts.deflate)?, None)
+    };
+
+    Ok(Some(PreparedImage::Compressed {
+        width,
+        height,
+        color_channels: 4,
+        bits_per_component: bits,
+        color_compressed,
+        alpha_compressed,
+        dpi,
+        icc_profile,
+    }))
+}
+
+/// replace an interlaced PNG's IDAT data with sequential, unfiltered rows at the same
+/// bit depth and color type, so it can take the passthrough path
+fn deinterlace_png(data: &[u8], info: &mut PngInfo, path: &Path, level: Deflate) -> Result<()> {
     use flate2::write::ZlibEncoder;
-    use flate2::Compression;
 
     let decoder = png::Decoder::new(std::io::Cursor::new(data));
     let mut reader = decoder
@@ -117,543 +903,1915 @@ fn decode_alpha_png(data: &[u8], info: &PngInfo, path: &Path) -> Result<Prepared
     let buf_size = reader
         .output_buffer_size()
         .context("PNG output buffer size unknown")?;
-    let mut buf = vec![0u8; buf_size];
+    let mut buf = Scratch::zeroed(buf_size);
     let output_info = reader
         .next_frame(&mut buf)
         .with_context(|| format!("Failed to read PNG frame: {}", path.display()))?;
-    let pixels = &buf[..output_info.buffer_size()];
-
-    let color_channels: usize = if info.color_type == 4 { 1 } else { 3 };
-    let total_channels = color_channels + 1;
-    let pixel_count = (info.width as usize) * (info.height as usize);
-
-    // fused split + compress stream directly into zlib encoders
-    let mut color_enc = ZlibEncoder::new(
-        Vec::with_capacity(pixel_count * color_channels / 2),
-        Compression::fast(),
-    );
-    let mut alpha_enc = ZlibEncoder::new(
-        Vec::with_capacity(pixel_count / 2),
-        Compression::fast(),
-    );
 
-    // process row-by-row for better cache locality
-    let row_pixels = info.width as usize;
-    let row_bytes = row_pixels * total_channels;
-    for row in 0..info.height as usize {
-        let row_start = row * row_bytes;
-        let row_slice = &pixels[row_start..row_start + row_bytes];
-        let mut color_row = Vec::with_capacity(row_pixels * color_channels);
-        let mut alpha_row = Vec::with_capacity(row_pixels);
-        for px in 0..row_pixels {
-            let base = px * total_channels;
-            color_row.extend_from_slice(&row_slice[base..base + color_channels]);
-            alpha_row.push(row_slice[base + color_channels]);
-        }
-        color_enc.write_all(&color_row)?;
-        alpha_enc.write_all(&alpha_row)?;
+    let capacity = info.idat_data.len();
+    let mut enc = ZlibEncoder::new(Vec::with_capacity(capacity), deflate::compression(level));
+    for row in buf[..output_info.buffer_size()].chunks_exact(output_info.line_size) {
+        // filter type 0 (None) keeps the data valid for the PNG predictor
+        enc.write_all(&[0])?;
+        enc.write_all(row)?;
     }
+    info.idat_data = enc.finish()?;
+    info.interlace = 0;
+    Ok(())
+}
 
-    let color_compressed = color_enc.finish()?;
-    let alpha_compressed = alpha_enc.finish()?;
-
-    Ok(PreparedImage::Compressed {
-        width: info.width,
-        height: info.height,
-        color_channels: color_channels as u8,
-        color_compressed,
-        alpha_compressed: Some(alpha_compressed),
-        dpi: info.dpi,
-        icc_profile: info.icc_profile.clone(),
-    })
+/// true if palette alpha values are all fully transparent or fully opaque,
+/// so a color-key mask can express them
+fn is_binary_alpha(trns: &[u8]) -> bool {
+    trns.iter().all(|&a| a == 0 || a == 255)
 }
 
-/// decode any image format via image crate and compress for PDF embedding
-fn decode_generic_image(
+/// 8-bit soft mask for a palette PNG with partially transparent entries; only the
+/// indices are decoded, the color data still passes through
+fn palette_smask(
     data: &[u8],
+    info: &PngInfo,
+    trns: &[u8],
     path: &Path,
-    dpi: Option<u32>,
-    icc_profile: Option<Vec<u8>>,
-) -> Result<PreparedImage> {
+    level: Deflate,
+) -> Result<Vec<u8>> {
     use flate2::write::ZlibEncoder;
-    use flate2::Compression;
 
-    use image::GenericImageView;
-    let img = image::load_from_memory(data)
-        .with_context(|| format!("Failed to decode image: {}", path.display()))?;
-    let (width, height) = img.dimensions();
+    let decoder = png::Decoder::new(std::io::Cursor::new(data));
+    let mut reader = decoder
+        .read_info()
+        .with_context(|| format!("Failed to decode PNG: {}", path.display()))?;
+    let buf_size = reader
+        .output_buffer_size()
+        .context("PNG output buffer size unknown")?;
+    let mut buf = Scratch::zeroed(buf_size);
+    let output_info = reader
+        .next_frame(&mut buf)
+        .with_context(|| format!("Failed to read PNG frame: {}", path.display()))?;
 
-    let has_alpha = img.color().has_alpha();
-    if has_alpha {
-        let rgba = img.into_rgba8();
-        let pixels = rgba.as_raw();
-        let pixel_count = (width as usize) * (height as usize);
-
-        let mut color_enc = ZlibEncoder::new(
-            Vec::with_capacity(pixel_count * 3 / 2),
-            Compression::fast(),
-        );
-        let mut alpha_enc = ZlibEncoder::new(
-            Vec::with_capacity(pixel_count / 2),
-            Compression::fast(),
-        );
+    let bits = info.bit_depth as usize;
+    let per_byte = 8 / bits;
+    let index_mask = ((1u16 << bits) - 1) as u8;
+    let width = info.width as usize;
+    let mut enc = ZlibEncoder::new(
+        Vec::with_capacity(width * info.height as usize / 4),
+        deflate::compression(level),
+    );
+    let mut alpha_row = Scratch::with_capacity(width);
+    for row in buf[..output_info.buffer_size()].chunks_exact(output_info.line_size) {
+        alpha_row.clear();
+        for x in 0..width {
+            // indices are packed MSB-first below 8 bits
+            let shift = 8 - bits * (x % per_byte + 1);
+            let index = (row[x / per_byte] >> shift) & index_mask;
+            alpha_row.push(trns.get(index as usize).copied().unwrap_or(255));
+        }
+        enc.write_all(&alpha_row)?;
+    }
+    Ok(enc.finish()?)
+}
 
-        for chunk in pixels.chunks_exact(4) {
-            color_enc.write_all(&chunk[..3])?;
-            alpha_enc.write_all(&chunk[3..4])?;
+/// /Mask color-key array for a passthrough PNG's tRNS chunk, if it has one
+fn png_color_key_mask(info: &PngInfo) -> Option<Object> {
+    let trns = info.trns.as_ref()?;
+    let sample = |i: usize| -> Option<i64> {
+        let bytes = trns.get(i * 2..i * 2 + 2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]) as i64)
+    };
+    let ranges: Vec<i64> = match info.color_type {
+        // a single gray or RGB color is transparent
+        0 => {
+            let v = sample(0)?;
+            vec![v, v]
+        }
+        2 => {
+            let rgb = (0..3).map(sample).collect::<Option<Vec<_>>>()?;
+            rgb.iter().flat_map(|&v| [v, v]).collect()
+        }
+        // transparent palette indices, as index ranges
+        3 => {
+            let mut ranges: Vec<i64> = Vec::new();
+            for (i, _) in trns.iter().enumerate().filter(|(_, &a)| a == 0) {
+                let i = i as i64;
+                match ranges.last_mut() {
+                    Some(end) if *end == i - 1 => *end = i,
+                    _ => ranges.extend([i, i]),
+                }
+            }
+            ranges
         }
+        _ => return None,
+    };
+    (!ranges.is_empty()).then(|| Object::Array(ranges.into_iter().map(Object::Integer).collect()))
+}
 
-        Ok(PreparedImage::Compressed {
+/// fail before decoding an image whose pixel count is over `--max-pixels`, since
+/// a corrupt or hostile header could otherwise demand gigabytes of buffers
+fn ensure_pixel_limit(width: u32, height: u32, path: &Path, opts: &MergeOptions) -> Result<()> {
+    let pixels = width as u64 * height as u64;
+    if let Some(max) = opts.max_pixels {
+        anyhow::ensure!(
+            pixels <= max,
+            "{} is {}x{} ({} pixels), over the --max-pixels limit of {}",
+            path.display(),
             width,
             height,
-            color_channels: 3,
-            color_compressed: color_enc.finish()?,
-            alpha_compressed: Some(alpha_enc.finish()?),
-            dpi,
-            icc_profile,
-        })
-    } else if img.color().channel_count() == 1 {
-        let gray = img.into_luma8();
-        let pixels = gray.as_raw();
+            pixels,
+            max
+        );
+    }
+    Ok(())
+}
+
+/// decode a color JPEG and re-encode it as single-channel grayscale
+fn reencode_jpeg_gray(data: &[u8], path: &Path, dpi: Option<(u32, u32)>) -> Result<PreparedImage> {
+    let img = image::load_from_memory_with_format(data, image::ImageFormat::Jpeg)
+        .with_context(|| format!("Failed to decode JPEG: {}", path.display()))?;
+    let gray = img.into_luma8();
+    let mut out = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new_with_quality(&mut out, GRAY_JPEG_QUALITY)
+        .encode_image(&gray)
+        .with_context(|| format!("Failed to re-encode JPEG: {}", path.display()))?;
+    Ok(PreparedImage::Jpeg {
+        width: gray.width(),
+        height: gray.height(),
+        components: 1,
+        invert_cmyk: false,
+        data: out.into(),
+        parts: None,
+        dpi,
+        // source profile describes the color data, not the derived luma
+        icc_profile: None,
+    })
+}
+
+/// convert packed RGB pixels to luma (Rec. 709 weights, matching the image crate)
+fn rgb_to_luma(rgb: &[u8], out: &mut Vec<u8>) {
+    for px in rgb.chunks_exact(3) {
+        let l = (2126 * px[0] as u32 + 7152 * px[1] as u32 + 722 * px[2] as u32) / 10000;
+        out.push(l as u8);
+    }
+}
+
+/// 16-bit counterpart of `rgb_to_luma` for big-endian sample pairs
+fn rgb16_to_luma16(rgb: &[u8], out: &mut Vec<u8>) {
+    for px in rgb.chunks_exact(6) {
+        let c = |i: usize| u16::from_be_bytes([px[i], px[i + 1]]) as u64;
+        let l = (2126 * c(0) + 7152 * c(2) + 722 * c(4)) / 10000;
+        out.extend_from_slice(&(l as u16).to_be_bytes());
+    }
+}
+
+/// number of color channels a color+alpha image has after flattening over `bg`
+///
+/// gray images stay gray over a neutral background but become RGB over a tinted one
+fn flattened_channels(color_channels: usize, bg: Color) -> usize {
+    if color_channels == 1 && bg.is_gray() {
+        1
+    } else {
+        3
+    }
+}
+
+/// composite interleaved color+alpha pixels over a solid background color
+fn flatten_pixels(pixels: &[u8], color_channels: usize, bg: Color, out: &mut Vec<u8>) {
+    let blend = |c: u8, b: u8, a: u8| -> u8 {
+        let a = a as u32;
+        ((c as u32 * a + b as u32 * (255 - a) + 127) / 255) as u8
+    };
+    let bg_rgb = [bg.r, bg.g, bg.b];
+    let out_channels = flattened_channels(color_channels, bg);
+    for px in pixels.chunks_exact(color_channels + 1) {
+        let a = px[color_channels];
+        for ch in 0..out_channels {
+            let c = if color_channels == 1 { px[0] } else { px[ch] };
+            out.push(blend(c, bg_rgb[ch], a));
+        }
+    }
+}
+
+/// decode a PNG with alpha channel, split color+alpha, compress separately
+fn decode_alpha_png(
+    data: &[u8],
+    info: &PngInfo,
+    path: &Path,
+    opts: &MergeOptions,
+) -> Result<PreparedImage> {
+    use flate2::write::ZlibEncoder;
+
+    let decoder = png::Decoder::new(std::io::Cursor::new(data));
+    let mut reader = decoder
+        .read_info()
+        .with_context(|| format!("Failed to decode PNG: {}", path.display()))?;
+    let buf_size = reader
+        .output_buffer_size()
+        .context("PNG output buffer size unknown")?;
+    let mut buf = Scratch::zeroed(buf_size);
+    let output_info = reader
+        .next_frame(&mut buf)
+        .with_context(|| format!("Failed to read PNG frame: {}", path.display()))?;
+    let pixels = &buf[..output_info.buffer_size()];
 
+    let color_channels: usize = if info.color_type == 4 { 1 } else { 3 };
+    let total_channels = color_channels + 1;
+    let pixel_count = (info.width as usize) * (info.height as usize);
+    // 16-bit samples are big-endian pairs, which PDF expects as-is
+    let bytes_per_sample: usize = if info.bit_depth == 16 { 2 } else { 1 };
+    // converting color to gray invalidates the source profile
+    let to_gray = opts.gray && color_channels == 3;
+    let icc_profile = if to_gray { None } else { info.icc_profile.clone() };
+
+    if let Some(bg) = opts.flatten_alpha {
+        let flat_channels = flattened_channels(color_channels, bg);
+        let out_channels = if opts.gray { 1 } else { flat_channels };
         let mut enc = ZlibEncoder::new(
-            Vec::with_capacity(pixels.len() / 2),
-            Compression::fast(),
+            Vec::with_capacity(pixel_count * out_channels / 2),
+            deflate::compression(opts.deflate),
         );
-        enc.write_all(pixels)?;
-
-        Ok(PreparedImage::Compressed {
-            width,
-            height,
-            color_channels: 1,
+        let row_bytes = info.width as usize * total_channels * bytes_per_sample;
+        let mut flat_row = Scratch::with_capacity(info.width as usize * flat_channels);
+        let mut gray_row = Scratch::with_capacity(info.width as usize);
+        let mut row8 = Scratch::with_capacity(info.width as usize * total_channels);
+        for row in pixels.chunks_exact(row_bytes) {
+            // flattening is a compatibility mode and always produces 8-bit output
+            let row = if bytes_per_sample == 2 {
+                row8.clear();
+                row8.extend(row.iter().step_by(2));
+                &row8[..]
+            } else {
+                row
+            };
+            flat_row.clear();
+            flatten_pixels(row, color_channels, bg, &mut flat_row);
+            if out_channels == flat_channels {
+                enc.write_all(&flat_row)?;
+            } else {
+                gray_row.clear();
+                rgb_to_luma(&flat_row, &mut gray_row);
+                enc.write_all(&gray_row)?;
+            }
+        }
+        return Ok(PreparedImage::Compressed {
+            width: info.width,
+            height: info.height,
+            color_channels: out_channels as u8,
+            bits_per_component: 8,
             color_compressed: enc.finish()?,
             alpha_compressed: None,
-            dpi,
-            icc_profile,
-        })
+            dpi: info.dpi,
+            icc_profile: if out_channels == flat_channels { icc_profile } else { None },
+        });
+    }
+    let out_channels = if to_gray { 1 } else { color_channels };
+
+    // fused split + compress stream directly into zlib encoders
+    let mut color_enc = ZlibEncoder::new(
+        Vec::with_capacity(pixel_count * out_channels / 2),
+        deflate::compression(opts.deflate),
+    );
+    let mut alpha_enc = ZlibEncoder::new(
+        Vec::with_capacity(pixel_count / 2),
+        deflate::compression(opts.deflate),
+    );
+    let mut gray_row = Scratch::with_capacity(info.width as usize * bytes_per_sample);
+
+    // process row-by-row for better cache locality
+    let row_pixels = info.width as usize;
+    let px_bytes = total_channels * bytes_per_sample;
+    let color_bytes = color_channels * bytes_per_sample;
+    let row_bytes = row_pixels * px_bytes;
+    let mut color_row = Scratch::with_capacity(row_pixels * color_bytes);
+    let mut alpha_row = Scratch::with_capacity(row_pixels * bytes_per_sample);
+    for row in 0..info.height as usize {
+        let row_start = row * row_bytes;
+        let row_slice = &pixels[row_start..row_start + row_bytes];
+        color_row.clear();
+        alpha_row.clear();
+        deflate::split_alpha(row_slice, px_bytes, color_bytes, &mut color_row, &mut alpha_row);
+        if to_gray {
+            gray_row.clear();
+            if bytes_per_sample == 2 {
+                rgb16_to_luma16(&color_row, &mut gray_row);
+            } else {
+                rgb_to_luma(&color_row, &mut gray_row);
+            }
+            color_enc.write_all(&gray_row)?;
+        } else {
+            color_enc.write_all(&color_row)?;
+        }
+        alpha_enc.write_all(&alpha_row)?;
+    }
+
+    let color_compressed = color_enc.finish()?;
+    let alpha_compressed = alpha_enc.finish()?;
+
+    Ok(PreparedImage::Compressed {
+        width: info.width,
+        height: info.height,
+        color_channels: out_channels as u8,
+        bits_per_component: 8 * bytes_per_sample as u8,
+        color_compressed,
+        alpha_compressed: Some(alpha_compressed),
+        dpi: info.dpi,
+        icc_profile,
+    })
+}
+
+/// decoded samples as bytes; 16-bit sources become big-endian pairs (PDF byte order)
+fn image_samples(img: image::DynamicImage, channels: usize, sixteen: bool) -> Scratch {
+    if sixteen {
+        let raw: Vec<u16> = match channels {
+            1 => img.into_luma16().into_raw(),
+            2 => img.into_luma_alpha16().into_raw(),
+            3 => img.into_rgb16().into_raw(),
+            _ => img.into_rgba16().into_raw(),
+        };
+        let mut samples = Scratch::with_capacity(raw.len() * 2);
+        samples.extend(raw.iter().flat_map(|v| v.to_be_bytes()));
+        samples
     } else {
-        let rgb = img.into_rgb8();
-        let pixels = rgb.as_raw();
+        let raw = match channels {
+            1 => img.into_luma8().into_raw(),
+            2 => img.into_luma_alpha8().into_raw(),
+            3 => img.into_rgb8().into_raw(),
+            _ => img.into_rgba8().into_raw(),
+        };
+        raw.into()
+    }
+}
 
-        let mut enc = ZlibEncoder::new(
-            Vec::with_capacity(pixels.len() / 2),
-            Compression::fast(),
-        );
-        enc.write_all(pixels)?;
+/// decode any image format via image crate and compress for PDF embedding
+fn decode_generic_image(
+    data: &[u8],
+    path: &Path,
+    dpi: Option<(u32, u32)>,
+    icc_profile: Option<Vec<u8>>,
+    opts: &MergeOptions,
+) -> Result<PreparedImage> {
+    use image::{DynamicImage, ImageReader};
+
+    let reader = || ImageReader::new(std::io::Cursor::new(data)).with_guessed_format();
+    let (width, height) = reader()?
+        .into_dimensions()
+        .with_context(|| format!("Failed to decode image: {}", path.display()))?;
+    ensure_pixel_limit(width, height, path, opts)?;
+    // --max-pixels replaces the decoder's own allocation cap
+    let mut reader = reader()?;
+    reader.no_limits();
+    let mut img = reader
+        .decode()
+        .with_context(|| format!("Failed to decode image: {}", path.display()))?;
+    // a GIF decodes to RGBA either way: transparent-index pixels get an SMask,
+    // and a GIF without a transparent index none
+    if InputFormat::sniff(data) == Some(InputFormat::Gif) && !gif_has_transparency(data) {
+        img = DynamicImage::ImageRgb8(img.into_rgb8());
+    }
+    prepare_decoded(img, dpi, icc_profile, opts)
+}
+
+/// decode frame `frame` of an animated PNG as a viewer shows it, drawn over
+/// the frames before. each frame is composited anew from the first, which is
+/// fine for the short animations of stickers and the like
+fn decode_apng_frame(
+    data: &[u8],
+    frame: u32,
+    path: &Path,
+    opts: &MergeOptions,
+) -> Result<PreparedImage> {
+    use image::codecs::png::PngDecoder;
+    use image::{AnimationDecoder, DynamicImage};
+
+    let mut info = parse_png_header(data)
+        .with_context(|| format!("Failed to parse PNG header: {}", path.display()))?;
+    tag_png_profile(&mut info);
+    ensure_pixel_limit(info.width, info.height, path, opts)?;
+    let failed = || format!("Failed to decode frame {} of {}", frame + 1, path.display());
+    let mut frames = PngDecoder::new(std::io::Cursor::new(data))
+        .and_then(|decoder| decoder.apng())
+        .with_context(failed)?
+        .into_frames();
+    let pixels = frames
+        .nth(frame as usize)
+        .with_context(failed)?
+        .with_context(failed)?
+        .into_buffer();
+    // frames come out RGBA: gray ones go back to gray, for their profile
+    let img = match info.color_type {
+        0 | 4 => DynamicImage::ImageLumaA8(DynamicImage::ImageRgba8(pixels).into_luma_alpha8()),
+        _ => DynamicImage::ImageRgba8(pixels),
+    };
+    prepare_decoded(img, info.dpi, info.icc_profile, opts)
+}
+
+/// compress decoded pixels for PDF embedding, straightened, grayed and
+/// flattened as `opts` asks
+fn prepare_decoded(
+    mut img: image::DynamicImage,
+    dpi: Option<(u32, u32)>,
+    icc_profile: Option<Vec<u8>>,
+    opts: &MergeOptions,
+) -> Result<PreparedImage> {
+    use image::{ColorType, DynamicImage};
+
+    let (width, height) = (img.width(), img.height());
+    if opts.deskew {
+        img = deskew::deskew(img).0;
+    }
 
-        Ok(PreparedImage::Compressed {
+    let has_alpha = img.color().has_alpha();
+    // keep high-depth sources at 16 bits rather than truncating to 8
+    let sixteen = !matches!(
+        img.color(),
+        ColorType::L8 | ColorType::La8 | ColorType::Rgb8 | ColorType::Rgba8
+    );
+    let (img, icc_profile) = if opts.gray && img.color().channel_count() >= 3 {
+        // converting color to gray invalidates the source profile
+        let gray = match (has_alpha, sixteen) {
+            (true, true) => DynamicImage::ImageLumaA16(img.into_luma_alpha16()),
+            (true, false) => DynamicImage::ImageLumaA8(img.into_luma_alpha8()),
+            (false, true) => DynamicImage::ImageLuma16(img.into_luma16()),
+            (false, false) => DynamicImage::ImageLuma8(img.into_luma8()),
+        };
+        (gray, None)
+    } else {
+        (img, icc_profile)
+    };
+
+    if let (true, Some(bg)) = (has_alpha, opts.flatten_alpha) {
+        // flattening is a compatibility mode and always produces 8-bit output
+        let gray = img.color().channel_count() == 2;
+        let (pixels, color_channels) = if gray {
+            (Scratch::from(img.into_luma_alpha8().into_raw()), 1)
+        } else {
+            (Scratch::from(img.into_rgba8().into_raw()), 3)
+        };
+        let mut out_channels = flattened_channels(color_channels, bg);
+        let mut flat = Scratch::with_capacity(pixels.len() / (color_channels + 1) * out_channels);
+        flatten_pixels(&pixels, color_channels, bg, &mut flat);
+        drop(pixels);
+        if opts.gray && out_channels == 3 {
+            // tinted background turned gray input back into RGB
+            let mut luma = Scratch::with_capacity(flat.len() / 3);
+            rgb_to_luma(&flat, &mut luma);
+            flat = luma;
+            out_channels = 1;
+        }
+
+        return Ok(PreparedImage::Compressed {
             width,
             height,
-            color_channels: 3,
-            color_compressed: enc.finish()?,
+            color_channels: out_channels as u8,
+            bits_per_component: 8,
+            color_compressed: deflate::compress(&flat, opts.deflate)?,
             alpha_compressed: None,
             dpi,
             icc_profile,
-        })
+        });
     }
+
+    let channels = img.color().channel_count() as usize;
+    let color_channels = if has_alpha { channels - 1 } else { channels };
+    let bytes_per_sample = if sixteen { 2 } else { 1 };
+    let pixels = image_samples(img, channels, sixteen);
+
+    let (color_compressed, alpha_compressed) = if has_alpha {
+        let pixel_bytes = channels * bytes_per_sample;
+        let color_bytes = color_channels * bytes_per_sample;
+        let (color, alpha) =
+            deflate::compress_split(&pixels, pixel_bytes, color_bytes, opts.deflate)?;
+        (color, Some(alpha))
+    } else {
+        (deflate::compress(&pixels, opts.deflate)?, None)
+    };
+
+    Ok(PreparedImage::Compressed {
+        width,
+        height,
+        color_channels: color_channels as u8,
+        bits_per_component: 8 * bytes_per_sample as u8,
+        color_compressed,
+        alpha_compressed,
+        dpi,
+        icc_profile,
+    })
 }
 
+/// what a merge wrote
+#[derive(Debug, Clone, Default)]
+pub struct MergeReport {
+    /// files written: the output, or its numbered parts when `max_pages` or
+    /// `max_size` split it; empty for stdout
+    pub files: Vec<PathBuf>,
+    /// pages in the output, placeholders included
+    pub pages: usize,
+    /// unreadable images left out with `skip_errors`
+    pub skipped: usize,
+    /// time and size of each image; none for an EPUB
+    pub stats: RunStats,
+}
+
+/// build a PDF with a page per image (per frame of animated PNGs with
+/// `apng_frames`), in order, streamed to `output` ("-" for stdout) as the
+/// images are prepared in parallel; an output ending in .epub
+/// gets a fixed-layout EPUB instead. cancelled, it stops and removes the
+/// unfinished output
 pub fn merge_images(
     images: &[PathBuf],
     output: &Path,
-    cli_dpi: Option<u32>,
-    quiet: bool,
-    title: Option<&str>,
-    author: Option<&str>,
-    pagesize: Option<PageSize>,
-    orientation: Orientation,
-) -> Result<()> {
-    use lopdf::content::{Content, Operation};
-    use lopdf::{dictionary, Document, Object, Stream};
-
-    if !quiet {
-        eprintln!("Merging {} image(s) -> {}", images.len(), output.display());
+    opts: &MergeOptions,
+) -> Result<MergeReport> {
+    if epub::is_epub(output) {
+        return epub::write_epub(images, output, opts);
+    }
+    if !opts.quiet {
+        info!("Merging {} image(s) -> {}", images.len(), output.display());
     }
     let start = std::time::Instant::now();
-
-    // phase 1 - parallel image processing (file I/O + decode + compress)
-    let prepared: Vec<Result<PreparedImage>> = images
-        .par_iter()
-        .map(|path| prepare_image(path))
+    // (image, frame) of each page, and the image each is named after
+    let pages: Vec<(usize, Option<u32>)> = images
+        .iter()
+        .enumerate()
+        .flat_map(|(i, path)| match frame_count(path, opts) {
+            1 => vec![(i, None)],
+            frames => (0..frames).map(|frame| (i, Some(frame))).collect(),
+        })
         .collect();
+    let names: Vec<PathBuf> = pages.iter().map(|&(i, _)| images[i].clone()).collect();
+    let prepare = |page: usize| match pages[page] {
+        (i, None) => prepare_image(&images[i], opts),
+        (i, Some(frame)) => prepare_frame(&images[i], frame, opts),
+    };
+    let estimate = |page: usize| estimate_file_memory(&names[page]);
 
-    // phase 2 - sequential PDF assembly
-    let mut doc = Document::with_version("1.5");
-    let pages_id = doc.new_object_id();
-    let mut page_ids: Vec<Object> = Vec::with_capacity(images.len());
-
-    /// helper - build an ICCBased color space object from profile data
-    fn make_icc_color_space(
-        doc: &mut Document,
-        icc_data: &[u8],
-        num_components: u8,
-    ) -> Object {
-        let icc_stream = Stream::new(
-            dictionary! {
-                "N" => num_components as i64,
-                "Filter" => Object::Name(b"FlateDecode".to_vec()),
-            },
-            {
-                use flate2::write::ZlibEncoder;
-                use flate2::Compression;
-                let mut enc = ZlibEncoder::new(Vec::new(), Compression::fast());
-                enc.write_all(icc_data).unwrap();
-                enc.finish().unwrap()
+    // objects are streamed straight to the output, so only the images in
+    // flight are ever held in memory
+    let to_stdout = output == Path::new("-");
+    let chunked = opts.max_pages.is_some() || opts.max_size.is_some();
+    let mut page_stats = Vec::new();
+    anyhow::ensure!(
+        !chunked || opts.bookmarks.is_empty(),
+        "--bookmarks can't be combined with --max-pages/--max-size"
+    );
+    outline::check_pages(&opts.bookmarks, pages.len())?;
+    let (files, skipped) = if to_stdout {
+        anyhow::ensure!(!chunked, "--max-pages/--max-size need a file output, not stdout");
+        let stdout = std::io::stdout();
+        let mut out = Some(std::io::BufWriter::new(stdout.lock()));
+        write_merged_pdf(
+            |_| out.take().context("stdout output cannot be split"),
+            &names,
+            &prepare,
+            &estimate,
+            opts,
+            &mut page_stats,
+        )
+        .map(|(_, skipped)| (Vec::new(), skipped))?
+    } else {
+        let mut created: Vec<PathBuf> = Vec::new();
+        let result = write_merged_pdf(
+            |n| {
+                let path = if chunked {
+                    part_path(output, n + 1)
+                } else {
+                    output.to_path_buf()
+                };
+                let file = std::fs::File::create(&path)
+                    .with_context(|| format!("Failed to save {}", path.display()))?;
+                created.push(path);
+                Ok(std::io::BufWriter::new(file))
             },
+            &names,
+            &prepare,
+            &estimate,
+            opts,
+            &mut page_stats,
         );
-        let icc_id = doc.add_object(icc_stream);
-        Object::Array(vec![
-            Object::Name(b"ICCBased".to_vec()),
-            icc_id.into(),
-        ])
+        match result {
+            // limits never hit: keep the plain output name
+            Ok((1, skipped)) if chunked => {
+                std::fs::rename(&created[0], output)
+                    .with_context(|| format!("Failed to save {}", output.display()))?;
+                (vec![output.to_path_buf()], skipped)
+            }
+            Ok((_, skipped)) => (created, skipped),
+            Err(e) => {
+                // don't leave truncated PDFs behind
+                for path in &created {
+                    let _ = std::fs::remove_file(path);
+                }
+                return Err(e);
+            }
+        }
+    };
+
+    if !opts.quiet {
+        let elapsed = start.elapsed();
+        if files.len() > 1 {
+            info!("Done. {} PDFs saved in {:.2}s", files.len(), elapsed.as_secs_f64());
+        } else {
+            info!("Done. PDF saved in {:.2}s", elapsed.as_secs_f64());
+        }
     }
+    Ok(MergeReport {
+        files,
+        pages: pages.len() - if opts.placeholder { 0 } else { skipped },
+        skipped,
+        stats: RunStats::new(page_stats, start.elapsed()),
+    })
+}
 
-    for (i, result) in prepared.into_iter().enumerate() {
-        let img = result?;
-        let path = &images[i];
+/// pages an image makes: with `apng_frames`, a page per frame of an animated
+/// PNG, otherwise one
+fn frame_count(path: &Path, opts: &MergeOptions) -> u32 {
+    if !opts.apng_frames {
+        return 1;
+    }
+    let Ok(data) = read_image_file(path) else {
+        return 1;
+    };
+    // a frame takes a 38-byte fcTL chunk at least, so more is a corrupt count
+    apng_frame_count(&data)
+        .filter(|&frames| frames > 0 && frames as usize <= data.len() / 38)
+        .unwrap_or(1)
+}
 
-        let (img_width, img_height, img_dpi, image_id) = match img {
-            PreparedImage::Jpeg {
-                width,
-                height,
-                components,
-                invert_cmyk,
-                data,
-                dpi: img_dpi,
-                icc_profile,
-            } => {
-                let color_space = match (&icc_profile, components) {
-                    (Some(icc), n) => make_icc_color_space(&mut doc, icc, n),
-                    (None, 1) => Object::Name(b"DeviceGray".to_vec()),
-                    (None, 3) => Object::Name(b"DeviceRGB".to_vec()),
-                    (None, 4) => Object::Name(b"DeviceCMYK".to_vec()),
-                    _ => unreachable!(),
-                };
-                let decode = if invert_cmyk {
-                    Some(Object::Array(vec![
-                        1.into(), 0.into(),
-                        1.into(), 0.into(),
-                        1.into(), 0.into(),
-                        1.into(), 0.into(),
-                    ]))
-                } else {
-                    None
-                };
-                let mut dict = dictionary! {
-                    "Type" => Object::Name(b"XObject".to_vec()),
-                    "Subtype" => Object::Name(b"Image".to_vec()),
-                    "Width" => width as i64,
-                    "Height" => height as i64,
-                    "ColorSpace" => color_space,
-                    "BitsPerComponent" => 8,
-                    "Filter" => Object::Name(b"DCTDecode".to_vec()),
-                    "Length" => data.len() as i64,
-                };
-                if let Some(d) = decode {
-                    dict.set("Decode", d);
-                }
-                (width, height, img_dpi, doc.add_object(Stream::new(dict, data)))
-            }
-            PreparedImage::PngPassthrough { info } => {
-                let img_dpi = info.dpi;
-                let icc_profile = info.icc_profile.clone();
-                let id = match info.color_type {
-                    0 | 2 => {
-                        let channels: u8 = if info.color_type == 0 { 1 } else { 3 };
-                        let color_space = match &icc_profile {
-                            Some(icc) => make_icc_color_space(&mut doc, icc, channels),
-                            None if info.color_type == 0 => {
-                                Object::Name(b"DeviceGray".to_vec())
-                            }
-                            None => Object::Name(b"DeviceRGB".to_vec()),
-                        };
-                        let decode_parms = dictionary! {
-                            "Predictor" => 15,
-                            "Colors" => channels as i64,
-                            "BitsPerComponent" => info.bit_depth as i64,
-                            "Columns" => info.width as i64,
-                        };
-                        doc.add_object(Stream::new(
-                            dictionary! {
-                                "Type" => Object::Name(b"XObject".to_vec()),
-                                "Subtype" => Object::Name(b"Image".to_vec()),
-                                "Width" => info.width as i64,
-                                "Height" => info.height as i64,
-                                "ColorSpace" => color_space,
-                                "BitsPerComponent" => info.bit_depth as i64,
-                                "Filter" => Object::Name(b"FlateDecode".to_vec()),
-                                "DecodeParms" => Object::Dictionary(decode_parms),
-                                "Length" => info.idat_data.len() as i64,
-                            },
-                            info.idat_data,
-                        ))
-                    }
-                    3 => {
-                        let num_entries = info.plte_data.len() / 3;
-                        let base_cs: Object = match &icc_profile {
-                            Some(icc) => make_icc_color_space(&mut doc, icc, 3),
-                            None => Object::Name(b"DeviceRGB".to_vec()),
-                        };
-                        let color_space = Object::Array(vec![
-                            Object::Name(b"Indexed".to_vec()),
-                            base_cs,
-                            Object::Integer((num_entries - 1) as i64),
-                            Object::String(
-                                info.plte_data,
-                                lopdf::StringFormat::Hexadecimal,
-                            ),
-                        ]);
-                        let decode_parms = dictionary! {
-                            "Predictor" => 15,
-                            "Colors" => 1_i64,
-                            "BitsPerComponent" => info.bit_depth as i64,
-                            "Columns" => info.width as i64,
-                        };
-                        doc.add_object(Stream::new(
-                            dictionary! {
-                                "Type" => Object::Name(b"XObject".to_vec()),
-                                "Subtype" => Object::Name(b"Image".to_vec()),
-                                "Width" => info.width as i64,
-                                "Height" => info.height as i64,
-                                "ColorSpace" => color_space,
-                                "BitsPerComponent" => info.bit_depth as i64,
-                                "Filter" => Object::Name(b"FlateDecode".to_vec()),
-                                "DecodeParms" => Object::Dictionary(decode_parms),
-                                "Length" => info.idat_data.len() as i64,
-                            },
-                            info.idat_data,
-                        ))
-                    }
-                    _ => unreachable!(),
-                };
-                (info.width, info.height, img_dpi, id)
+/// print what `merge_images` would do, without writing anything: each image
+/// with its size in pixels and the size of its page, and the files to be
+/// written. only the image headers are read, so an image that fails to decode
+/// can still get through; one that can't be read at all fails the plan, unless
+/// `skip_errors`
+pub fn print_merge_plan(images: &[PathBuf], output: &Path, opts: &MergeOptions) -> Result<()> {
+    let is_epub = epub::is_epub(output);
+    let to_stdout = output == Path::new("-");
+    let target = if to_stdout { "stdout".into() } else { output.display().to_string() };
+    println!("Merge {} image(s) -> {}", images.len(), target);
+
+    let digits = images.len().to_string().len();
+    let mut pages = 0;
+    let mut unreadable = 0;
+    for (i, path) in images.iter().enumerate() {
+        let probe = probe_image(path);
+        unreadable += probe.is_err() as usize;
+        let (page, kept) = match probe {
+            Ok(ImageHeader { width, height, .. }) if is_epub => {
+                (format!("{}x{} px", width, height), true)
             }
-            PreparedImage::Compressed {
-                width,
-                height,
-                color_channels,
-                color_compressed,
-                alpha_compressed,
-                dpi: img_dpi,
-                icc_profile,
-            } => {
-                let color_space = match &icc_profile {
-                    Some(icc) => make_icc_color_space(&mut doc, icc, color_channels),
-                    None if color_channels == 1 => {
-                        Object::Name(b"DeviceGray".to_vec())
+            Ok(ImageHeader { width, height, dpi }) => {
+                let (pw, ph, ..) = place_image(width, height, dpi, opts);
+                let page = format!("{}x{} px, {:.1}x{:.1} pt page", width, height, pw, ph);
+                match frame_count(path, opts) {
+                    1 => (page, true),
+                    frames => {
+                        pages += frames as usize - 1;
+                        (format!("{} for each of {} frames", page, frames), true)
                     }
-                    None => Object::Name(b"DeviceRGB".to_vec()),
-                };
-                let image_stream = if let Some(alpha_data) = alpha_compressed {
-                    let smask_stream = Stream::new(
-                        dictionary! {
-                            "Type" => Object::Name(b"XObject".to_vec()),
-                            "Subtype" => Object::Name(b"Image".to_vec()),
-                            "Width" => width as i64,
-                            "Height" => height as i64,
-                            "ColorSpace" => Object::Name(b"DeviceGray".to_vec()),
-                            "BitsPerComponent" => 8,
-                            "Filter" => Object::Name(b"FlateDecode".to_vec()),
-                            "Length" => alpha_data.len() as i64,
-                        },
-                        alpha_data,
-                    );
-                    let smask_id = doc.add_object(smask_stream);
-                    Stream::new(
-                        dictionary! {
-                            "Type" => Object::Name(b"XObject".to_vec()),
-                            "Subtype" => Object::Name(b"Image".to_vec()),
-                            "Width" => width as i64,
-                            "Height" => height as i64,
-                            "ColorSpace" => color_space,
-                            "BitsPerComponent" => 8,
-                            "Filter" => Object::Name(b"FlateDecode".to_vec()),
-                            "SMask" => smask_id,
-                            "Length" => color_compressed.len() as i64,
-                        },
-                        color_compressed,
-                    )
-                } else {
-                    Stream::new(
-                        dictionary! {
-                            "Type" => Object::Name(b"XObject".to_vec()),
-                            "Subtype" => Object::Name(b"Image".to_vec()),
-                            "Width" => width as i64,
-                            "Height" => height as i64,
-                            "ColorSpace" => color_space,
-                            "BitsPerComponent" => 8,
-                            "Filter" => Object::Name(b"FlateDecode".to_vec()),
-                            "Length" => color_compressed.len() as i64,
-                        },
-                        color_compressed,
-                    )
-                };
-                (width, height, img_dpi, doc.add_object(image_stream))
+                }
             }
+            Err(e) if opts.skip_errors && opts.placeholder && !is_epub => {
+                let (pw, ph) = fixed_page_size(opts);
+                (format!("{:.1}x{:.1} pt placeholder page: {:#}", pw, ph, e), true)
+            }
+            Err(e) if opts.skip_errors => (format!("skipped: {:#}", e), false),
+            Err(e) => (format!("fails: {:#}", e), false),
         };
+        pages += kept as usize;
+        println!("  {:>digits$}  {}  {}", i + 1, path.display(), page);
+    }
 
-        let effective_dpi = cli_dpi.or(img_dpi).unwrap_or(300);
-        let (page_w_pts, page_h_pts, img_w_pts, img_h_pts, x_off, y_off) =
-            if let Some(ps) = pagesize {
-                let (pw, ph) = ps.dimensions_pt();
-                let img_w = img_width as f32 * 72.0 / effective_dpi as f32;
-                let img_h = img_height as f32 * 72.0 / effective_dpi as f32;
-                let (pw, ph) = match orientation {
-                    Orientation::Auto => {
-                        if img_w > img_h {
-                            (pw.max(ph), pw.min(ph))
-                        } else {
-                            (pw.min(ph), pw.max(ph))
-                        }
-                    }
-                    Orientation::Portrait => (pw.min(ph), pw.max(ph)),
-                    Orientation::Landscape => (pw.max(ph), pw.min(ph)),
-                };
-                let scale = (pw / img_w).min(ph / img_h);
-                let w = img_w * scale;
-                let h = img_h * scale;
-                (pw, ph, w, h, (pw - w) / 2.0, (ph - h) / 2.0)
-            } else {
-                let w = img_width as f32 * 72.0 / effective_dpi as f32;
-                let h = img_height as f32 * 72.0 / effective_dpi as f32;
-                (w, h, w, h, 0.0, 0.0)
-            };
+    match (opts.max_pages, opts.max_size) {
+        _ if to_stdout => println!("Would write {} page(s) to stdout", pages),
+        (Some(max), _) if !is_epub && pages > max => {
+            println!("Would write {} page(s) in {} parts:", pages, pages.div_ceil(max));
+            for (n, first) in (1..=pages).step_by(max).enumerate() {
+                let last = (first + max - 1).min(pages);
+                let part = part_path(output, n + 1);
+                match first == last {
+                    true => println!("  {}  page {}", part.display(), first),
+                    false => println!("  {}  pages {}-{}", part.display(), first, last),
+                }
+            }
+            if opts.max_size.is_some() {
+                println!("  (or more, as --max-size fills them)");
+            }
+        }
+        (_, Some(_)) if !is_epub => {
+            let parts = "or parts as --max-size fills them";
+            println!("Would write {} page(s) to {}, {}", pages, target, parts);
+        }
+        _ => println!("Would write {} page(s) to {}", pages, target),
+    }
+    anyhow::ensure!(
+        unreadable == 0 || opts.skip_errors,
+        "{} of {} image(s) can't be read",
+        unreadable,
+        images.len()
+    );
+    Ok(())
+}
 
-        // content stream
-        let content = Content {
-            operations: vec![
-                Operation::new("q", vec![]),
-                Operation::new(
-                    "cm",
-                    vec![
-                        Object::Real(img_w_pts),
-                        Object::Integer(0),
-                        Object::Integer(0),
-                        Object::Real(img_h_pts),
-                        Object::Real(x_off),
-                        Object::Real(y_off),
-                    ],
-                ),
-                Operation::new("Do", vec![Object::Name(b"Im0".to_vec())]),
-                Operation::new("Q", vec![]),
-            ],
-        };
-        let content_id = doc.add_object(Stream::new(
-            dictionary! {},
-            content
-                .encode()
-                .context("Failed to encode content stream")?,
-        ));
+/// an image's size and resolution, from its header alone
+struct ImageHeader {
+    width: u32,
+    height: u32,
+    dpi: Option<(u32, u32)>,
+}
 
-        let resources_id = doc.add_object(dictionary! {
-            "XObject" => dictionary! {
-                "Im0" => image_id,
-            },
+fn probe_image(path: &Path) -> Result<ImageHeader> {
+    let data = std::fs::read(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let format = InputFormat::sniff(&data)
+        .with_context(|| format!("Unrecognized image format: {}", path.display()))?;
+    let dpi = match format {
+        InputFormat::Jpeg => parse_jpeg_header(&data).ok().and_then(|info| info.dpi),
+        InputFormat::Png => parse_png_header(&data).ok().and_then(|info| info.dpi),
+        InputFormat::Tiff => parse_tiff_resolution(&data),
+        InputFormat::Bmp | InputFormat::Gif => None,
+    };
+    let (width, height) = image_size(std::io::Cursor::new(&data), format)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(ImageHeader { width, height, dpi })
+}
+
+/// an image's size in pixels, from the header at the start of `reader`
+fn image_size<R: BufRead + Seek>(reader: R, format: InputFormat) -> Result<(u32, u32)> {
+    // the tiff crate reads the size of any TIFF, fax and CMYK ones too
+    match format {
+        InputFormat::Tiff => Ok(tiff::decoder::Decoder::new(reader)?.dimensions()?),
+        _ => Ok(image::ImageReader::new(reader).with_guessed_format()?.into_dimensions()?),
+    }
+}
+
+/// about how much memory preparing an image takes, for a memory budget: the
+/// file, its pixels decoded as 8-bit RGBA, and their compressed copy, at worst
+/// as big. an image whose size can't be read counts as its file alone
+fn memory_estimate(file_len: u64, size: Option<(u32, u32)>) -> u64 {
+    let pixels = size.map_or(0, |(width, height)| width as u64 * height as u64);
+    file_len + pixels * 4 * 2
+}
+
+/// `memory_estimate` for the image file at `path`, reading only its header
+fn estimate_file_memory(path: &Path) -> u64 {
+    let Ok(file) = std::fs::File::open(path) else {
+        return 0;
+    };
+    let len = file.metadata().map_or(0, |meta| meta.len());
+    let mut reader = std::io::BufReader::new(file);
+    let format = reader.fill_buf().ok().and_then(InputFormat::sniff);
+    let size = format.and_then(|format| image_size(reader, format).ok());
+    memory_estimate(len, size)
+}
+
+/// `memory_estimate` for an encoded image in memory
+fn estimate_data_memory(data: &[u8]) -> u64 {
+    let size = InputFormat::sniff(data)
+        .and_then(|format| image_size(std::io::Cursor::new(data), format).ok());
+    memory_estimate(data.len() as u64, size)
+}
+
+/// build a PDF in memory from encoded images, a page each, in order, for
+/// callers without files to read or write, like the wasm32 build. the images
+/// are named "image N" in messages; chunked output isn't supported
+pub fn merge_image_data(images: &[Vec<u8>], opts: &MergeOptions) -> Result<Vec<u8>> {
+    anyhow::ensure!(
+        opts.max_pages.is_none() && opts.max_size.is_none(),
+        "--max-pages/--max-size need a file output"
+    );
+    outline::check_pages(&opts.bookmarks, images.len())?;
+    let names: Vec<PathBuf> = (1..=images.len())
+        .map(|n| PathBuf::from(format!("image {}", n)))
+        .collect();
+    let mut pdf = Vec::new();
+    let mut out = Some(&mut pdf);
+    write_merged_pdf(
+        |_| out.take().context("in-memory output cannot be split"),
+        &names,
+        &|i| prepare_image_data(images[i].clone().into(), &names[i], opts),
+        &|i| estimate_data_memory(&images[i]),
+        opts,
+        &mut Vec::new(),
+    )?;
+    Ok(pdf)
+}
+
+/// numbered output path for chunked merges: out.pdf -> out_001.pdf
+pub fn part_path(output: &Path, n: usize) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match output.extension() {
+        Some(ext) => format!("{}_{:03}.{}", stem, n, ext.to_string_lossy()),
+        None => format!("{}_{:03}", stem, n),
+    };
+    output.with_file_name(name)
+}
+
+/// helper - build an ICCBased color space object from profile data
+fn make_icc_color_space<W: Write>(
+    pdf: &mut PdfWriter<W>,
+    icc_data: &[u8],
+    num_components: u8,
+    level: Deflate,
+) -> Result<Object> {
+    let compressed = deflate::compress(icc_data, level)?;
+    let icc_id = pdf.add_stream(
+        dictionary! {
+            "N" => num_components as i64,
+            "Filter" => Object::Name(b"FlateDecode".to_vec()),
+        },
+        &compressed,
+    )?;
+    Ok(Object::Array(vec![
+        Object::Name(b"ICCBased".to_vec()),
+        icc_id.into(),
+    ]))
+}
+
+/// prepares the image at an input index, from a file or from memory
+type PrepareFn<'a> = dyn Fn(usize) -> Result<PreparedPage> + Sync + 'a;
+
+/// the memory preparing the image at an input index takes, roughly
+type EstimateFn<'a> = dyn Fn(usize) -> u64 + Sync + 'a;
+
+/// how far the consumer of `prepare_in_order` has got, and the estimated
+/// bytes of the images being prepared or waiting to be consumed
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Default)]
+struct InFlight {
+    done: usize,
+    bytes: u64,
+}
+
+/// prepare images in parallel and hand each result to `consume` in input order
+///
+/// workers claim images one at a time and never run more than a small window
+/// ahead of the consumer, so memory held by finished-but-unwritten images stays
+/// bounded regardless of input count. with a `budget`, an image also waits
+/// until its estimate fits next to those in flight. stops early if `consume`
+/// fails.
+#[cfg(not(target_arch = "wasm32"))]
+fn prepare_in_order(
+    count: usize,
+    prepare: &PrepareFn,
+    budget: Option<(u64, &EstimateFn)>,
+    mut consume: impl FnMut(usize, Result<PreparedPage>) -> Result<()>,
+) -> Result<()> {
+    let workers = rayon::current_num_threads();
+    let window = workers * 2;
+    let next = AtomicUsize::new(0);
+    let cancelled = AtomicBool::new(false);
+    // workers wait on it to stay in the window and the budget
+    let consumed = (Mutex::new(InFlight::default()), Condvar::new());
+    let (tx, rx) = mpsc::channel::<(usize, u64, Result<PreparedPage>)>();
+
+    std::thread::scope(|s| {
+        let (next, cancelled, consumed) = (&next, &cancelled, &consumed);
+        s.spawn(move || {
+            rayon::scope(|rs| {
+                for _ in 0..workers {
+                    let tx = tx.clone();
+                    rs.spawn(move |_| loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        if i >= count {
+                            break;
+                        }
+                        let bytes = budget.map_or(0, |(_, estimate)| estimate(i));
+                        {
+                            let (lock, cvar) = consumed;
+                            let mut state = lock.lock().unwrap();
+                            // the image to be consumed next always goes ahead,
+                            // however big, or nothing else could
+                            let waits = |state: &InFlight| {
+                                let over = budget.is_some_and(|(limit, _)| {
+                                    i != state.done && state.bytes + bytes > limit
+                                });
+                                i >= state.done + window || over
+                            };
+                            while waits(&state) && !cancelled.load(Ordering::Relaxed) {
+                                state = cvar.wait(state).unwrap();
+                            }
+                            state.bytes += bytes;
+                        }
+                        if cancelled.load(Ordering::Relaxed) {
+                            break;
+                        }
+                        let _image = tracing::debug_span!("image", image = i + 1).entered();
+                        let started = std::time::Instant::now();
+                        let result = deflate::serial_strips(|| prepare(i));
+                        let (worker, ms) =
+                            (rayon::current_thread_index(), started.elapsed().as_millis() as u64);
+                        match &result {
+                            Ok(page) => {
+                                tracing::debug!(worker, ms, "prepared {}", page.image.describe())
+                            }
+                            Err(_) => tracing::debug!(worker, ms, "failed"),
+                        }
+                        if tx.send((i, bytes, result)).is_err() {
+                            break;
+                        }
+                    });
+                }
+            });
         });
 
-        let page_id = doc.add_object(dictionary! {
-            "Type" => Object::Name(b"Page".to_vec()),
-            "Parent" => pages_id,
-            "MediaBox" => vec![0.into(), 0.into(), Object::Real(page_w_pts), Object::Real(page_h_pts)],
-            "Contents" => content_id,
-            "Resources" => resources_id,
+        // reorder results; at most `window` of them are ever pending here
+        let mut pending = BTreeMap::new();
+        let mut expected = 0;
+        let mut outcome = Ok(());
+        for (i, bytes, result) in rx {
+            pending.insert(i, (bytes, result));
+            while let Some((bytes, result)) = pending.remove(&expected) {
+                outcome = consume(expected, result);
+                expected += 1;
+                let (lock, cvar) = consumed;
+                let mut state = lock.lock().unwrap();
+                state.done = expected;
+                state.bytes -= bytes;
+                cvar.notify_all();
+                drop(state);
+                if outcome.is_err() {
+                    break;
+                }
+            }
+            if outcome.is_err() {
+                // flag under the lock so a worker can't miss the wakeup
+                let (lock, cvar) = consumed;
+                let _guard = lock.lock().unwrap();
+                cancelled.store(true, Ordering::Relaxed);
+                cvar.notify_all();
+                break;
+            }
+        }
+        outcome
+    })
+}
+
+/// wasm32 has no threads to spare, so each image is prepared as it's consumed
+#[cfg(target_arch = "wasm32")]
+fn prepare_in_order(
+    count: usize,
+    prepare: &PrepareFn,
+    _budget: Option<(u64, &EstimateFn)>,
+    mut consume: impl FnMut(usize, Result<PreparedPage>) -> Result<()>,
+) -> Result<()> {
+    (0..count).try_for_each(|i| consume(i, prepare(i)))
+}
+
+/// rough per-page cost beyond the image data (page, content, resources, xref entries)
+const PAGE_OVERHEAD: u64 = 2048;
+
+/// write the merged PDF, starting a new output from `open_part` (called with the
+/// 0-based part number) whenever `--max-pages` or `--max-size` would be exceeded;
+/// returns the number of parts written and of images skipped, and adds each
+/// image's timings and sizes to `stats`
+fn write_merged_pdf<W: Write>(
+    mut open_part: impl FnMut(usize) -> Result<W>,
+    images: &[PathBuf],
+    prepare: &PrepareFn,
+    estimate: &EstimateFn,
+    opts: &MergeOptions,
+    stats: &mut Vec<PageStats>,
+) -> Result<(usize, usize)> {
+    let mut part: Option<OutputPart<W>> = None;
+    let mut parts = 0;
+    let mut skipped = 0;
+    let total = images.len();
+    progress::emit(&opts.progress, Event::Start { total });
+    let prepare = |i| {
+        progress::emit(&opts.progress, Event::PageStarted { page: i + 1, total });
+        prepare(i)
+    };
+
+    // images are decoded on the rayon pool while this thread writes them out
+    // in input order, so assembly of page 1 overlaps decoding of later pages
+    let budget = opts.memory_budget.map(|limit| (limit, estimate));
+    prepare_in_order(total, &prepare, budget, |i, result| {
+        if opts.cancel.is_cancelled() {
+            return Err(Interrupted {
+                done: i,
+                total,
+                files: Vec::new(),
+            }
+            .into());
+        }
+        let failed = |error| Event::PageFailed {
+            page: i + 1,
+            done: i + 1,
+            total,
+            error,
+        };
+        let img = match result {
+            Ok(img) => Some(img),
+            Err(e) if opts.skip_errors => {
+                let message = format!("skipping {}: {:#}", images[i].display(), e);
+                progress::warn(&opts.progress, Some(i + 1), &message);
+                skipped += 1;
+                progress::emit(&opts.progress, failed(&e));
+                if !opts.placeholder {
+                    return Ok(());
+                }
+                None
+            }
+            Err(e) => {
+                progress::emit(&opts.progress, failed(&e));
+                return Err(e);
+            }
+        };
+        let placeholder = img.is_none();
+        let encoded_len = img.as_ref().map_or(0, |page| page.image.encoded_len()) as u64;
+        let timings = img.as_ref().map(|page| (page.read, page.prepare, page.image.raw_len()));
+        // a part always takes at least one page, even if that page alone is over the limit
+        let full = part.as_ref().is_none_or(|p| {
+            let over_pages = opts.max_pages.is_some_and(|max| p.page_ids.len() >= max);
+            let over_size = opts.max_size.is_some_and(|max| {
+                p.pdf.bytes_written() + encoded_len + PAGE_OVERHEAD > max
+            });
+            over_pages || over_size
         });
-        page_ids.push(page_id.into());
+        if full {
+            if let Some(done) = part.take() {
+                done.finish(opts)?;
+            }
+            part = Some(OutputPart::begin(open_part(parts)?, images.len(), opts)?);
+            parts += 1;
+        }
+        let current = part.as_mut().expect("output part was just opened");
+        let started = std::time::Instant::now();
+        let written = current.pdf.bytes_written();
+        let page_id = match img {
+            Some(img) => write_image_page(
+                &mut current.pdf,
+                img,
+                current.pages_id,
+                opts,
+                &current.overlays,
+                i + 1,
+            )?,
+            None => write_placeholder_page(
+                &mut current.pdf,
+                &images[i],
+                current.pages_id,
+                opts,
+                &current.overlays,
+                i + 1,
+            )?,
+        };
+        current.page_ids.push(page_id.into());
+        let write = started.elapsed();
+        trace!(image = i + 1, write_ms = write.as_millis() as u64, "written");
+        let bytes = current.pdf.bytes_written() - written;
+        progress::emit(&opts.progress, Event::BytesWritten { page: i + 1, bytes });
+        if let Some((read, prepare, raw_bytes)) = timings {
+            stats.push(PageStats {
+                page: i + 1,
+                stages: [("read", read), ("prepare", prepare), ("write", write)],
+                raw_bytes,
+                bytes,
+            });
+        }
 
-        if !quiet {
-            eprintln!("  [{}/{}] {}", i + 1, images.len(), path.display());
+        if !opts.quiet {
+            info!("  [{}/{}] {}", i + 1, images.len(), images[i].display());
+        }
+        if !placeholder {
+            let done = Event::PageDone {
+                page: i + 1,
+                done: i + 1,
+                total,
+                file: Some(&images[i]),
+            };
+            progress::emit(&opts.progress, done);
         }
+        Ok(())
+    })?;
+
+    if skipped > 0 {
+        let message = format!("skipped {} of {} image(s)", skipped, images.len());
+        progress::warn(&opts.progress, None, &message);
     }
+    let part = match part {
+        Some(part) => part,
+        None => {
+            anyhow::ensure!(images.is_empty() || skipped == 0, "No readable images to merge");
+            parts += 1;
+            OutputPart::begin(open_part(0)?, images.len(), opts)?
+        }
+    };
+    part.finish(opts)?;
+    let saved = total - skipped;
+    progress::emit(&opts.progress, Event::Finish { saved, failed: skipped });
+    Ok((parts, skipped))
+}
 
-    // build pages tree
-    let count = page_ids.len() as i64;
-    doc.objects.insert(
-        pages_id,
-        Object::Dictionary(dictionary! {
-            "Type" => Object::Name(b"Pages".to_vec()),
-            "Kids" => page_ids,
-            "Count" => count,
-        }),
-    );
+/// one output PDF being assembled
+struct OutputPart<W: Write> {
+    pdf: PdfWriter<W>,
+    pages_id: ObjectId,
+    page_ids: Vec<Object>,
+    overlays: PageOverlays,
+}
 
-    // catalog
-    let catalog_id = doc.add_object(dictionary! {
-        "Type" => Object::Name(b"Catalog".to_vec()),
-        "Pages" => pages_id,
-    });
-    doc.trailer.set("Root", catalog_id);
+impl<W: Write> OutputPart<W> {
+    /// write the header and the objects shared by every page
+    fn begin(out: W, total: usize, opts: &MergeOptions) -> Result<Self> {
+        let mut pdf = PdfWriter::new(out, "1.5")?;
+        let pages_id = pdf.reserve_id();
+        let overlays = PageOverlays {
+            watermark: match &opts.watermark {
+                Some(mark) => Some(write_watermark_objects(&mut pdf, mark, opts)?),
+                None => None,
+            },
+            page_number_font: match opts.page_numbers {
+                Some(_) => Some(pdf.add_object(PageNumbers::font())?),
+                None => None,
+            },
+            placeholder_font: if opts.skip_errors && opts.placeholder {
+                Some(pdf.add_object(watermark::placeholder_font())?)
+            } else {
+                None
+            },
+            total,
+        };
+        Ok(OutputPart {
+            pdf,
+            pages_id,
+            page_ids: Vec::new(),
+            overlays,
+        })
+    }
 
-    // PDF metadata
-    {
-        let mut info_dict = lopdf::Dictionary::new();
-        info_dict.set(
-            "Producer",
-            Object::String(
-                format!("ovid {}", env!("CARGO_PKG_VERSION")).into_bytes(),
-                lopdf::StringFormat::Literal,
-            ),
-        );
-        // PDF date format: D:YYYYMMDDHHmmSS+HH'mm'
-        let now = std::time::SystemTime::now();
-        if let Ok(dur) = now.duration_since(std::time::UNIX_EPOCH) {
-            let secs = dur.as_secs();
-            // simple UTC breakdown without external crate
-            let days = secs / 86400;
-            let time_of_day = secs % 86400;
-            let hours = time_of_day / 3600;
-            let minutes = (time_of_day % 3600) / 60;
-            let seconds = time_of_day % 60;
-            // date from days since epoch (civil calendar algorithm)
-            let z = days + 719468;
-            let era = z / 146097;
-            let doe = z - era * 146097;
-            let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
-            let y = yoe + era * 400;
-            let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
-            let mp = (5 * doy + 2) / 153;
-            let d = doy - (153 * mp + 2) / 5 + 1;
-            let m = if mp < 10 { mp + 3 } else { mp - 9 };
-            let y = if m <= 2 { y + 1 } else { y };
-            let date_str = format!(
-                "D:{:04}{:02}{:02}{:02}{:02}{:02}Z",
-                y, m, d, hours, minutes, seconds
-            );
-            info_dict.set(
-                "CreationDate",
-                Object::String(date_str.into_bytes(), lopdf::StringFormat::Literal),
-            );
+    /// write the page tree, catalog, metadata, and xref
+    fn finish(self, opts: &MergeOptions) -> Result<W> {
+        let OutputPart {
+            mut pdf,
+            pages_id,
+            page_ids,
+            ..
+        } = self;
+
+        // build pages tree
+        let count = page_ids.len() as i64;
+        let first_page = page_ids.first().cloned();
+        let kids = page_ids.clone();
+        pdf.write_object(
+            pages_id,
+            &Object::Dictionary(dictionary! {
+                "Type" => Object::Name(b"Pages".to_vec()),
+                "Kids" => page_ids,
+                "Count" => count,
+            }),
+        )?;
+
+        // catalog
+        let mut catalog = dictionary! {
+            "Type" => Object::Name(b"Catalog".to_vec()),
+            "Pages" => pages_id,
+        };
+        if let Some(layout) = opts.page_layout {
+            catalog.set("PageLayout", Object::Name(layout.pdf_name().as_bytes().to_vec()));
         }
-        if let Some(t) = title {
+        if let Some(mode) = opts.page_mode {
+            catalog.set("PageMode", Object::Name(mode.pdf_name().as_bytes().to_vec()));
+        }
+        if !opts.bookmarks.is_empty() {
+            let pages: Vec<ObjectId> = kids.iter().filter_map(|p| p.as_reference().ok()).collect();
+            // skipped images leave fewer pages than the outline was checked against
+            outline::check_pages(&opts.bookmarks, pages.len())?;
+            let (root_id, objects) =
+                outline::outline_objects(&opts.bookmarks, &pages, || pdf.reserve_id());
+            for (id, dict) in objects {
+                pdf.write_object(id, &Object::Dictionary(dict))?;
+            }
+            catalog.set("Outlines", root_id);
+        }
+        let mut preferences = lopdf::Dictionary::new();
+        if opts.fit_window {
+            preferences.set("FitWindow", true);
+            if let Some(first_page) = first_page {
+                catalog.set("OpenAction", vec![first_page, Object::Name(b"Fit".to_vec())]);
+            }
+        }
+        if opts.direction == Direction::Rtl {
+            preferences.set("Direction", Object::Name(b"R2L".to_vec()));
+        }
+        if !preferences.is_empty() {
+            catalog.set("ViewerPreferences", preferences);
+        }
+        let catalog_id = pdf.add_object(catalog)?;
+
+        // PDF metadata
+        let info_id = {
+            let mut info_dict = lopdf::Dictionary::new();
             info_dict.set(
-                "Title",
-                Object::String(t.as_bytes().to_vec(), lopdf::StringFormat::Literal),
+                "Producer",
+                Object::String(
+                    format!("ovid {}", env!("CARGO_PKG_VERSION")).into_bytes(),
+                    lopdf::StringFormat::Literal,
+                ),
             );
+            let date = opts.date.unwrap_or_else(|| Local::now().fixed_offset());
+            let date = Object::String(pdf_date(date).into_bytes(), lopdf::StringFormat::Literal);
+            info_dict.set("CreationDate", date.clone());
+            info_dict.set("ModDate", date);
+            if let Some(t) = &opts.title {
+                info_dict.set("Title", writer::text_string(t));
+            }
+            if let Some(a) = &opts.author {
+                info_dict.set("Author", writer::text_string(a));
+            }
+            pdf.add_object(Object::Dictionary(info_dict))?
+        };
+
+        pdf.finish(catalog_id, Some(info_id))
+    }
+}
+
+/// PDF date string with the UTC offset spelled out: D:YYYYMMDDHHmmSS+HH'mm'
+pub fn pdf_date(date: DateTime<FixedOffset>) -> String {
+    let offset = date.offset().local_minus_utc() / 60;
+    let sign = if offset < 0 { '-' } else { '+' };
+    format!(
+        "D:{}{}{:02}'{:02}'",
+        date.format("%Y%m%d%H%M%S"),
+        sign,
+        offset.abs() / 60,
+        offset.abs() % 60
+    )
+}
+
+/// objects drawn over every page, written once and shared
+struct PageOverlays {
+    watermark: Option<WatermarkObjects>,
+    page_number_font: Option<ObjectId>,
+    placeholder_font: Option<ObjectId>,
+    /// page count for "of M" numbering
+    total: usize,
+}
+
+/// watermark objects written once and shared by every page
+struct WatermarkObjects {
+    mark: Watermark,
+    gs_id: ObjectId,
+    resource: WatermarkResource,
+    /// natural size in points of an image watermark
+    image_size: Option<(f32, f32)>,
+}
+
+enum WatermarkResource {
+    Font(ObjectId),
+    Image(ObjectId),
+}
+
+fn write_watermark_objects<W: Write>(
+    pdf: &mut PdfWriter<W>,
+    mark: &Watermark,
+    opts: &MergeOptions,
+) -> Result<WatermarkObjects> {
+    let mut mark = mark.clone();
+    if opts.gray {
+        let c = mark.color;
+        let mut luma = Vec::with_capacity(1);
+        rgb_to_luma(&[c.r, c.g, c.b], &mut luma);
+        mark.color = Color { r: luma[0], g: luma[0], b: luma[0] };
+    }
+    let gs_id = pdf.add_object(mark.ext_gstate())?;
+    let (resource, image_size) = match &mark.content {
+        WatermarkContent::Text(_) => {
+            let font_id = pdf.add_object(Watermark::font())?;
+            (WatermarkResource::Font(font_id), None)
         }
-        if let Some(a) = author {
-            info_dict.set(
-                "Author",
-                Object::String(a.as_bytes().to_vec(), lopdf::StringFormat::Literal),
+        WatermarkContent::Image(path) => {
+            let img = prepare_image(path, opts)
+                .with_context(|| format!("Failed to load watermark image {}", path.display()))?;
+            let xobject = write_image_xobject(pdf, img.image, opts)?;
+            let (dpi_x, dpi_y) = xobject.dpi.unwrap_or((300, 300));
+            let size = (
+                xobject.width as f32 * 72.0 / dpi_x as f32,
+                xobject.height as f32 * 72.0 / dpi_y as f32,
             );
+            (WatermarkResource::Image(xobject.id), Some(size))
         }
-        let info_id = doc.add_object(Object::Dictionary(info_dict));
-        doc.trailer.set("Info", info_id);
-    }
+    };
+    Ok(WatermarkObjects {
+        mark,
+        gs_id,
+        resource,
+        image_size,
+    })
+}
 
-    // write output
-    let to_stdout = output == Path::new("-");
-    if to_stdout {
-        let stdout = std::io::stdout();
-        let mut out = std::io::BufWriter::new(stdout.lock());
-        doc.save_to(&mut out)
-            .context("Failed to write PDF to stdout")?;
+/// add an image file to an existing document as an image XObject, encoded the
+/// same way merge encodes pages; returns its id and natural size in points
+pub fn import_image_xobject(doc: &mut Document, path: &Path) -> Result<(ObjectId, (f32, f32))> {
+    let opts = MergeOptions::default();
+    let img = prepare_image(path, &opts)?;
+    // written to a scratch PDF in memory, then copied over with fresh numbers
+    let mut pdf = PdfWriter::new(Vec::new(), "1.5")?;
+    let xobject = write_image_xobject(&mut pdf, img.image, &opts)?;
+    let pages_id = pdf.add_object(dictionary! {
+        "Type" => "Pages",
+        "Kids" => Vec::<Object>::new(),
+        "Count" => 0,
+    })?;
+    let root_id = pdf.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+        "Image" => xobject.id,
+    })?;
+    let mut scratch = Document::load_mem(&pdf.finish(root_id, None)?)?;
+    scratch.renumber_objects_with(doc.max_id + 1);
+    let root_id = scratch.trailer.get(b"Root").and_then(Object::as_reference)?;
+    let catalog = scratch.get_dictionary(root_id)?;
+    let image_id = catalog.get(b"Image").and_then(Object::as_reference)?;
+    let pages_id = catalog.get(b"Pages").and_then(Object::as_reference)?;
+    scratch.objects.remove(&root_id);
+    scratch.objects.remove(&pages_id);
+    doc.max_id = doc.max_id.max(scratch.max_id);
+    doc.objects.extend(scratch.objects);
+
+    let (dpi_x, dpi_y) = xobject.dpi.unwrap_or((300, 300));
+    let size = (
+        xobject.width as f32 * 72.0 / dpi_x as f32,
+        xobject.height as f32 * 72.0 / dpi_y as f32,
+    );
+    Ok((image_id, size))
+}
+
+/// write one prepared image as an XObject plus the page that displays it
+fn write_image_page<W: Write>(
+    pdf: &mut PdfWriter<W>,
+    prepared: PreparedPage,
+    pages_id: ObjectId,
+    opts: &MergeOptions,
+    overlays: &PageOverlays,
+    page_number: usize,
+) -> Result<ObjectId> {
+    let ImageXObject {
+        id: image_id,
+        width: img_width,
+        height: img_height,
+        dpi: img_dpi,
+    } = write_image_xobject(pdf, prepared.image, opts)?;
+    let (page_w_pts, page_h_pts, img_w_pts, img_h_pts, x_off, y_off) =
+        place_image(img_width, img_height, img_dpi, opts);
+
+    // content stream
+    let content = Content {
+        operations: vec![
+            Operation::new("q", vec![]),
+            Operation::new(
+                "cm",
+                vec![
+                    Object::Real(img_w_pts),
+                    Object::Integer(0),
+                    Object::Integer(0),
+                    Object::Real(img_h_pts),
+                    Object::Real(x_off),
+                    Object::Real(y_off),
+                ],
+            ),
+            Operation::new("Do", vec![Object::Name(b"Im0".to_vec())]),
+            Operation::new("Q", vec![]),
+        ],
+    };
+    let resources = dictionary! {
+        "XObject" => dictionary! {
+            "Im0" => image_id,
+        },
+    };
+    let page = PageContent {
+        content,
+        resources,
+        fonts: lopdf::Dictionary::new(),
+        width: page_w_pts,
+        height: page_h_pts,
+        exif: prepared.exif,
+    };
+    add_page(pdf, page, pages_id, opts, overlays, page_number)
+}
+
+/// the page an image of `width` x `height` pixels gets, and where on it the
+/// image goes: (page width, page height, image width, image height, x, y), in
+/// points
+fn place_image(
+    width: u32,
+    height: u32,
+    img_dpi: Option<(u32, u32)>,
+    opts: &MergeOptions,
+) -> (f32, f32, f32, f32, f32, f32) {
+    // axes are scaled independently so non-square pixels (e.g. 204x98 fax) keep their aspect
+    let (dpi_x, dpi_y) = opts.dpi.map(|d| (d, d)).or(img_dpi).unwrap_or((300, 300));
+    let img_w = width as f32 * 72.0 / dpi_x as f32;
+    let img_h = height as f32 * 72.0 / dpi_y as f32;
+    if let Some(ps) = opts.pagesize {
+        let (pw, ph) = ps.dimensions_pt();
+        let (pw, ph) = match opts.orientation {
+            Orientation::Auto => {
+                if img_w > img_h {
+                    (pw.max(ph), pw.min(ph))
+                } else {
+                    (pw.min(ph), pw.max(ph))
+                }
+            }
+            Orientation::Portrait => (pw.min(ph), pw.max(ph)),
+            Orientation::Landscape => (pw.max(ph), pw.min(ph)),
+        };
+        let scale = match opts.scale {
+            Some(Scale(s)) => s,
+            None => (pw / img_w).min(ph / img_h),
+        };
+        let w = img_w * scale;
+        let h = img_h * scale;
+        let (x, y) = opts.position.place(pw, ph, w, h);
+        (pw, ph, w, h, x, y)
     } else {
-        doc.save(output)
-            .with_context(|| format!("Failed to save {}", output.display()))?;
+        let scale = opts.scale.map_or(1.0, |Scale(s)| s);
+        let w = img_w * scale;
+        let h = img_h * scale;
+        (w, h, w, h, 0.0, 0.0)
     }
+}
 
-    if !quiet {
-        let elapsed = start.elapsed();
-        eprintln!("Done. PDF saved in {:.2}s", elapsed.as_secs_f64());
+/// stand-in page for an image that could not be read (`--skip-errors --placeholder`)
+fn write_placeholder_page<W: Write>(
+    pdf: &mut PdfWriter<W>,
+    path: &Path,
+    pages_id: ObjectId,
+    opts: &MergeOptions,
+    overlays: &PageOverlays,
+    page_number: usize,
+) -> Result<ObjectId> {
+    let (width, height) = fixed_page_size(opts);
+    let name = path.file_name().unwrap_or(path.as_os_str()).to_string_lossy();
+    let caption = format!("Unreadable image: {}", name);
+    let mut fonts = lopdf::Dictionary::new();
+    if let Some(font_id) = overlays.placeholder_font {
+        fonts.set(watermark::PLACEHOLDER_FONT_NAME, font_id);
     }
-    Ok(())
+    let page = PageContent {
+        content: Content {
+            operations: watermark::placeholder_operations(&caption, width, height),
+        },
+        resources: lopdf::Dictionary::new(),
+        fonts,
+        width,
+        height,
+        exif: None,
+    };
+    add_page(pdf, page, pages_id, opts, overlays, page_number)
+}
+
+/// size of pages that have no image to take it from: --pagesize (default
+/// letter), portrait unless asked otherwise
+fn fixed_page_size(opts: &MergeOptions) -> (f32, f32) {
+    let (pw, ph) = opts.pagesize.unwrap_or(PageSize::Letter).dimensions_pt();
+    match opts.orientation {
+        Orientation::Landscape => (pw.max(ph), pw.min(ph)),
+        Orientation::Auto | Orientation::Portrait => (pw.min(ph), pw.max(ph)),
+    }
+}
+
+/// write a PDF of `count` pages with no images, each drawn by `draw` for its
+/// width and height, sized and finished (watermark, page numbers, metadata) as
+/// merge does
+pub fn write_drawn_pages(
+    output: &Path,
+    count: usize,
+    draw: impl Fn(f32, f32) -> Vec<Operation>,
+    opts: &MergeOptions,
+) -> Result<()> {
+    let (width, height) = fixed_page_size(opts);
+    pdf::write_output(output, |out| {
+        let mut part = OutputPart::begin(out, count, opts)?;
+        for n in 1..=count {
+            let page = PageContent {
+                content: Content {
+                    operations: draw(width, height),
+                },
+                resources: lopdf::Dictionary::new(),
+                fonts: lopdf::Dictionary::new(),
+                width,
+                height,
+                exif: None,
+            };
+            let page_id = add_page(&mut part.pdf, page, part.pages_id, opts, &part.overlays, n)?;
+            part.page_ids.push(page_id.into());
+        }
+        part.finish(opts)?;
+        Ok(())
+    })
+}
+
+/// a rendered page compressed for embedding, see `prepare_raster`
+pub struct PreparedRaster(PreparedImage);
+
+/// compress 8-bit gray or RGB samples rendered at `dpi` into a page image:
+/// JPEG at `jpeg_quality`, or lossless deflate without it
+#[cfg(feature = "native")]
+pub fn prepare_raster(
+    samples: &[u8],
+    width: u32,
+    height: u32,
+    gray: bool,
+    dpi: u32,
+    jpeg_quality: Option<u8>,
+) -> Result<PreparedRaster> {
+    let channels: u8 = if gray { 1 } else { 3 };
+    let image = match jpeg_quality {
+        Some(quality) => {
+            let mut data = Vec::new();
+            split::encode_jpg(samples, width, height, gray, quality, &mut data)?;
+            PreparedImage::Jpeg {
+                width,
+                height,
+                components: channels,
+                invert_cmyk: false,
+                data: data.into(),
+                parts: None,
+                dpi: Some((dpi, dpi)),
+                icc_profile: None,
+            }
+        }
+        None => PreparedImage::Compressed {
+            width,
+            height,
+            color_channels: channels,
+            bits_per_component: 8,
+            color_compressed: deflate::compress(samples, Deflate::Fast)?,
+            alpha_compressed: None,
+            dpi: Some((dpi, dpi)),
+            icc_profile: None,
+        },
+    };
+    Ok(PreparedRaster(image))
+}
+
+/// write a PDF with one page per raster, in order, sized by the DPI they were
+/// rendered at unless `opts` sets a page size; `count` is the number of pages
+/// `pages` will yield
+pub fn write_raster_pages(
+    output: &Path,
+    count: usize,
+    pages: impl Iterator<Item = Result<PreparedRaster>>,
+    opts: &MergeOptions,
+) -> Result<()> {
+    pdf::write_output(output, |out| {
+        let mut part = OutputPart::begin(out, count, opts)?;
+        for (i, raster) in pages.enumerate() {
+            let page = PreparedPage {
+                image: raster?.0,
+                exif: None,
+                read: Duration::ZERO,
+                prepare: Duration::ZERO,
+            };
+            let page_id =
+                write_image_page(&mut part.pdf, page, part.pages_id, opts, &part.overlays, i + 1)?;
+            part.page_ids.push(page_id.into());
+            if !opts.quiet {
+                info!("  [{}/{}]", i + 1, count);
+            }
+        }
+        part.finish(opts)?;
+        Ok(())
+    })
+}
+
+/// page content and resources before overlays are added
+struct PageContent {
+    content: Content,
+    resources: lopdf::Dictionary,
+    fonts: lopdf::Dictionary,
+    width: f32,
+    height: f32,
+    /// source metadata for the page's PieceInfo (`--keep-exif`)
+    exif: Option<ExifInfo>,
+}
+
+/// draw the watermark and page number over `page` and write the page object
+fn add_page<W: Write>(
+    pdf: &mut PdfWriter<W>,
+    page: PageContent,
+    pages_id: ObjectId,
+    opts: &MergeOptions,
+    overlays: &PageOverlays,
+    page_number: usize,
+) -> Result<ObjectId> {
+    let PageContent {
+        mut content,
+        mut resources,
+        mut fonts,
+        width: page_w_pts,
+        height: page_h_pts,
+        exif,
+    } = page;
+
+    // overlays drawn last so they sit on top of the image
+    if let Some(wm) = &overlays.watermark {
+        content.operations.extend(wm.mark.operations(page_w_pts, page_h_pts, wm.image_size));
+        resources.set("ExtGState", dictionary! { watermark::GS_NAME => wm.gs_id });
+        match wm.resource {
+            WatermarkResource::Font(id) => fonts.set(watermark::FONT_NAME, id),
+            WatermarkResource::Image(id) => match resources.get_mut(b"XObject") {
+                Ok(Object::Dictionary(xobjects)) => xobjects.set(watermark::IMAGE_NAME, id),
+                _ => resources.set("XObject", dictionary! { watermark::IMAGE_NAME => id }),
+            },
+        }
+    }
+    if let (Some(numbers), Some(font_id)) = (&opts.page_numbers, overlays.page_number_font) {
+        content.operations.extend(numbers.operations(
+            page_number,
+            overlays.total,
+            page_w_pts,
+            page_h_pts,
+        ));
+        fonts.set(watermark::PAGE_NUMBER_FONT_NAME, font_id);
+    }
+    if !fonts.is_empty() {
+        resources.set("Font", fonts);
+    }
+
+    let content_id = pdf.add_stream(
+        dictionary! {},
+        &content
+            .encode()
+            .context("Failed to encode content stream")?,
+    )?;
+
+    let resources_id = pdf.add_object(resources)?;
+
+    let mut page_dict = dictionary! {
+        "Type" => Object::Name(b"Page".to_vec()),
+        "Parent" => pages_id,
+        "MediaBox" => vec![0.into(), 0.into(), Object::Real(page_w_pts), Object::Real(page_h_pts)],
+        "Contents" => content_id,
+        "Resources" => resources_id,
+    };
+    if let Some(exif) = exif {
+        // PieceInfo data must carry a LastModified matching the page's
+        let modified = pdf_date(opts.date.unwrap_or_else(|| Local::now().fixed_offset()));
+        let modified = Object::String(modified.into_bytes(), lopdf::StringFormat::Literal);
+        page_dict.set(
+            "PieceInfo",
+            dictionary! {
+                PIECE_INFO_KEY => dictionary! {
+                    "LastModified" => modified.clone(),
+                    "Private" => exif_dictionary(&exif, opts.exif_gps),
+                },
+            },
+        );
+        page_dict.set("LastModified", modified);
+    }
+    let page_id = pdf.add_object(page_dict)?;
+    Ok(page_id)
+}
+
+/// EXIF fields as a PieceInfo /Private dictionary
+fn exif_dictionary(exif: &ExifInfo, gps: bool) -> lopdf::Dictionary {
+    let mut dict = lopdf::Dictionary::new();
+    if let Some(date) = &exif.date {
+        dict.set("DateTimeOriginal", writer::text_string(date));
+    }
+    if let Some(make) = &exif.make {
+        dict.set("Make", writer::text_string(make));
+    }
+    if let Some(model) = &exif.model {
+        dict.set("Model", writer::text_string(model));
+    }
+    if let (true, Some((lat, lon))) = (gps, exif.gps) {
+        dict.set("GPSLatitude", Object::Real(lat as f32));
+        dict.set("GPSLongitude", Object::Real(lon as f32));
+    }
+    dict
+}
+
+/// image XObject written to the output, with the source pixel size and DPI
+struct ImageXObject {
+    id: ObjectId,
+    width: u32,
+    height: u32,
+    dpi: Option<(u32, u32)>,
+}
+
+/// write a prepared image as an image XObject (`opts.gray` converts palettes to
+/// gray)
+fn write_image_xobject<W: Write>(
+    pdf: &mut PdfWriter<W>,
+    img: PreparedImage,
+    opts: &MergeOptions,
+) -> Result<ImageXObject> {
+    let xobject = match img {
+        PreparedImage::Jpeg {
+            width,
+            height,
+            components,
+            invert_cmyk,
+            data,
+            parts,
+            dpi: img_dpi,
+            icc_profile,
+        } => {
+            let color_space = match (&icc_profile, components) {
+                (Some(icc), n) => make_icc_color_space(pdf, icc, n, opts.deflate)?,
+                (None, 1) => Object::Name(b"DeviceGray".to_vec()),
+                (None, 3) => Object::Name(b"DeviceRGB".to_vec()),
+                (None, 4) => Object::Name(b"DeviceCMYK".to_vec()),
+                _ => unreachable!(),
+            };
+            let decode = if invert_cmyk {
+                Some(Object::Array(vec![
+                    1.into(), 0.into(),
+                    1.into(), 0.into(),
+                    1.into(), 0.into(),
+                    1.into(), 0.into(),
+                ]))
+            } else {
+                None
+            };
+            let mut dict = dictionary! {
+                "Type" => Object::Name(b"XObject".to_vec()),
+                "Subtype" => Object::Name(b"Image".to_vec()),
+                "Width" => width as i64,
+                "Height" => height as i64,
+                "ColorSpace" => color_space,
+                "BitsPerComponent" => 8,
+                "Filter" => Object::Name(b"DCTDecode".to_vec()),
+            };
+            if let Some(d) = decode {
+                dict.set("Decode", d);
+            }
+            let parts: Vec<&[u8]> = match parts {
+                Some(parts) => parts.into_iter().map(|part| &data[part]).collect(),
+                None => vec![&data],
+            };
+            let id = pdf.reserve_id();
+            pdf.write_stream_parts(id, dict, &parts)?;
+            (width, height, img_dpi, id)
+        }
+        PreparedImage::Ccitt { info, dpi } => {
+            let mut dict = dictionary! {
+                "Type" => Object::Name(b"XObject".to_vec()),
+                "Subtype" => Object::Name(b"Image".to_vec()),
+                "Width" => info.width as i64,
+                "Height" => info.height as i64,
+                "ColorSpace" => Object::Name(b"DeviceGray".to_vec()),
+                "BitsPerComponent" => 1,
+                "Filter" => Object::Name(b"CCITTFaxDecode".to_vec()),
+                "DecodeParms" => dictionary! {
+                    "K" => info.k as i64,
+                    "Columns" => info.width as i64,
+                    "Rows" => info.height as i64,
+                    "EncodedByteAlign" => info.byte_align,
+                },
+            };
+            if info.invert {
+                dict.set("Decode", vec![1.into(), 0.into()]);
+            }
+            let id = pdf.add_stream(dict, &info.data)?;
+            (info.width, info.height, dpi, id)
+        }
+        PreparedImage::PngPassthrough {
+            info,
+            alpha_compressed,
+        } => {
+            let img_dpi = info.dpi;
+            let icc_profile = info.icc_profile.clone();
+            let mask = png_color_key_mask(&info);
+            let (color_space, colors) = match info.color_type {
+                0 | 2 => {
+                    let channels: u8 = if info.color_type == 0 { 1 } else { 3 };
+                    let color_space = match &icc_profile {
+                        Some(icc) => make_icc_color_space(pdf, icc, channels, opts.deflate)?,
+                        None if info.color_type == 0 => {
+                            Object::Name(b"DeviceGray".to_vec())
+                        }
+                        None => Object::Name(b"DeviceRGB".to_vec()),
+                    };
+                    (color_space, channels)
+                }
+                3 => {
+                    let num_entries = info.plte_data.len() / 3;
+                    // in gray mode the palette itself is converted, keeping IDAT passthrough
+                    let (base_cs, palette): (Object, Vec<u8>) = if opts.gray {
+                        let mut gray = Vec::with_capacity(num_entries);
+                        rgb_to_luma(&info.plte_data, &mut gray);
+                        (Object::Name(b"DeviceGray".to_vec()), gray)
+                    } else {
+                        let base_cs = match &icc_profile {
+                            Some(icc) => make_icc_color_space(pdf, icc, 3, opts.deflate)?,
+                            None => Object::Name(b"DeviceRGB".to_vec()),
+                        };
+                        (base_cs, info.plte_data)
+                    };
+                    let color_space = Object::Array(vec![
+                        Object::Name(b"Indexed".to_vec()),
+                        base_cs,
+                        Object::Integer((num_entries - 1) as i64),
+                        Object::String(palette, lopdf::StringFormat::Hexadecimal),
+                    ]);
+                    (color_space, 1)
+                }
+                _ => unreachable!(),
+            };
+            let decode_parms = dictionary! {
+                "Predictor" => 15,
+                "Colors" => colors as i64,
+                "BitsPerComponent" => info.bit_depth as i64,
+                "Columns" => info.width as i64,
+            };
+            let mut dict = dictionary! {
+                "Type" => Object::Name(b"XObject".to_vec()),
+                "Subtype" => Object::Name(b"Image".to_vec()),
+                "Width" => info.width as i64,
+                "Height" => info.height as i64,
+                "ColorSpace" => color_space,
+                "BitsPerComponent" => info.bit_depth as i64,
+                "Filter" => Object::Name(b"FlateDecode".to_vec()),
+                "DecodeParms" => Object::Dictionary(decode_parms),
+            };
+            // tRNS: partial palette alpha needs a soft mask, anything else is a color key
+            if let Some(alpha_data) = alpha_compressed {
+                let smask_id = pdf.add_stream(
+                    dictionary! {
+                        "Type" => Object::Name(b"XObject".to_vec()),
+                        "Subtype" => Object::Name(b"Image".to_vec()),
+                        "Width" => info.width as i64,
+                        "Height" => info.height as i64,
+                        "ColorSpace" => Object::Name(b"DeviceGray".to_vec()),
+                        "BitsPerComponent" => 8,
+                        "Filter" => Object::Name(b"FlateDecode".to_vec()),
+                    },
+                    &alpha_data,
+                )?;
+                dict.set("SMask", smask_id);
+            } else if let Some(mask) = mask {
+                dict.set("Mask", mask);
+            }
+            let id = pdf.add_stream(dict, &info.idat_data)?;
+            (info.width, info.height, img_dpi, id)
+        }
+        PreparedImage::Compressed {
+            width,
+            height,
+            color_channels,
+            bits_per_component,
+            color_compressed,
+            alpha_compressed,
+            dpi: img_dpi,
+            icc_profile,
+        } => {
+            let color_space = match &icc_profile {
+                Some(icc) => make_icc_color_space(pdf, icc, color_channels, opts.deflate)?,
+                None if color_channels == 1 => {
+                    Object::Name(b"DeviceGray".to_vec())
+                }
+                None if color_channels == 4 => Object::Name(b"DeviceCMYK".to_vec()),
+                None => Object::Name(b"DeviceRGB".to_vec()),
+            };
+            let image_id = if let Some(alpha_data) = alpha_compressed {
+                let smask_id = pdf.add_stream(
+                    dictionary! {
+                        "Type" => Object::Name(b"XObject".to_vec()),
+                        "Subtype" => Object::Name(b"Image".to_vec()),
+                        "Width" => width as i64,
+                        "Height" => height as i64,
+                        "ColorSpace" => Object::Name(b"DeviceGray".to_vec()),
+                        "BitsPerComponent" => bits_per_component as i64,
+                        "Filter" => Object::Name(b"FlateDecode".to_vec()),
+                    },
+                    &alpha_data,
+                )?;
+                pdf.add_stream(
+                    dictionary! {
+                        "Type" => Object::Name(b"XObject".to_vec()),
+                        "Subtype" => Object::Name(b"Image".to_vec()),
+                        "Width" => width as i64,
+                        "Height" => height as i64,
+                        "ColorSpace" => color_space,
+                        "BitsPerComponent" => bits_per_component as i64,
+                        "Filter" => Object::Name(b"FlateDecode".to_vec()),
+                        "SMask" => smask_id,
+                    },
+                    &color_compressed,
+                )?
+            } else {
+                pdf.add_stream(
+                    dictionary! {
+                        "Type" => Object::Name(b"XObject".to_vec()),
+                        "Subtype" => Object::Name(b"Image".to_vec()),
+                        "Width" => width as i64,
+                        "Height" => height as i64,
+                        "ColorSpace" => color_space,
+                        "BitsPerComponent" => bits_per_component as i64,
+                        "Filter" => Object::Name(b"FlateDecode".to_vec()),
+                    },
+                    &color_compressed,
+                )?
+            };
+            (width, height, img_dpi, image_id)
+        }
+    };
+    let (width, height, dpi, id) = xobject;
+    Ok(ImageXObject {
+        id,
+        width,
+        height,
+        dpi,
+    })
 }
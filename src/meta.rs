@@ -0,0 +1,451 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, FixedOffset, Local};
+use lopdf::xref::XrefType;
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream, StringFormat};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::io::Write;
+use std::path::Path;
+use tracing::{info, warn};
+
+use crate::crypto;
+use crate::merge;
+use crate::parse::{MetaField, INFO_KEYS};
+use crate::pdf;
+use crate::writer;
+
+/// XMP properties mirroring the standard document info keys
+const XMP_PROPERTIES: [(&str, &str); 8] = [
+    ("Title", "dc:title"),
+    ("Author", "dc:creator"),
+    ("Subject", "dc:description"),
+    ("Keywords", "pdf:Keywords"),
+    ("Creator", "xmp:CreatorTool"),
+    ("Producer", "pdf:Producer"),
+    ("CreationDate", "xmp:CreateDate"),
+    ("ModDate", "xmp:ModifyDate"),
+];
+
+/// print a PDF's document info fields and the matching XMP properties
+pub fn print_meta(input: &Path, json: bool) -> Result<()> {
+    let doc = pdf::load(input)?;
+    let info = info_fields(&doc);
+    let xmp = xmp_stream(&doc).map(|(_, xmp)| {
+        XMP_PROPERTIES
+            .iter()
+            .filter_map(|&(_, prop)| xmp_get(&xmp, prop).map(|value| (prop, value)))
+            .collect::<Vec<_>>()
+    });
+
+    if json {
+        let report = MetaJson {
+            file: input.display().to_string(),
+            info: fields(&info),
+            xmp: xmp.as_deref().map(fields),
+        };
+        println!("{}", serde_json::to_string(&report)?);
+        return Ok(());
+    }
+
+    println!("{:<14}{}", "File:", input.display());
+    for (key, value) in &info {
+        println!("{:<14}{}", format!("{}:", key), value);
+    }
+    match xmp {
+        Some(props) => {
+            println!("XMP:");
+            for (prop, value) in props {
+                println!("  {:<18}{}", prop, value);
+            }
+        }
+        None => println!("{:<14}none", "XMP:"),
+    }
+    Ok(())
+}
+
+/// `meta --json`'s report; `xmp` is null when the file has no XMP stream
+#[derive(Serialize)]
+struct MetaJson {
+    file: String,
+    info: Map<String, Value>,
+    xmp: Option<Map<String, Value>>,
+}
+
+/// (key, value) pairs as an object, in order
+fn fields<K: ToString>(pairs: &[(K, String)]) -> Map<String, Value> {
+    pairs.iter().map(|(key, value)| (key.to_string(), value.as_str().into())).collect()
+}
+
+/// set or remove metadata fields with an incremental update: the changed info
+/// dictionary (and XMP stream, if the file has one) are appended, so the original
+/// bytes stay as they are. ModDate is set to now unless given
+pub fn edit_meta(
+    input: &Path,
+    output: Option<&Path>,
+    fields: &[MetaField],
+    quiet: bool,
+) -> Result<()> {
+    let start = std::time::Instant::now();
+    let original =
+        std::fs::read(input).with_context(|| format!("Failed to read {}", input.display()))?;
+    let doc = Document::load_mem(&original)
+        .with_context(|| format!("Failed to open PDF {}", input.display()))?;
+    // new strings would have to be encrypted too
+    anyhow::ensure!(
+        !doc.is_encrypted(),
+        "{} is encrypted; decrypt it before editing metadata",
+        input.display()
+    );
+    let target = output.unwrap_or(input);
+    if !quiet {
+        info!("Updating metadata of {} -> {}", input.display(), target.display());
+    }
+
+    let info_ref = doc.trailer.get(b"Info").ok();
+    let mut info = info_ref
+        .and_then(|info| doc.dereference(info).ok())
+        .and_then(|(_, info)| info.as_dict().ok())
+        .cloned()
+        .unwrap_or_default();
+    let mut xmp_edits: Vec<(&str, Option<String>)> = Vec::new();
+    let mut edits: Vec<(&str, &str)> = fields
+        .iter()
+        .map(|f| (f.key.as_str(), f.value.as_str()))
+        .collect();
+    let now = Local::now().fixed_offset().to_rfc3339();
+    if !edits.iter().any(|(key, _)| *key == "ModDate") {
+        edits.push(("ModDate", &now));
+    }
+    for (key, value) in edits {
+        let xmp_prop = XMP_PROPERTIES.iter().find(|(k, _)| *k == key).map(|(_, p)| *p);
+        if value.is_empty() {
+            info.remove(key.as_bytes());
+            xmp_edits.extend(xmp_prop.map(|prop| (prop, None)));
+            continue;
+        }
+        if key.ends_with("Date") && INFO_KEYS.contains(&key) {
+            let date = parse_date(key, value)?;
+            let pdf_date = merge::pdf_date(date).into_bytes();
+            info.set(key, Object::String(pdf_date, StringFormat::Literal));
+            xmp_edits.extend(xmp_prop.map(|prop| (prop, Some(date.to_rfc3339()))));
+        } else {
+            info.set(key, writer::text_string(value));
+            xmp_edits.extend(xmp_prop.map(|prop| (prop, Some(value.to_string()))));
+        }
+    }
+
+    let mut next_num = doc
+        .trailer
+        .get(b"Size")
+        .and_then(Object::as_i64)
+        .map_or(0, |size| size as u32)
+        .max(doc.max_id + 1);
+    let info_id = match info_ref.and_then(|info| info.as_reference().ok()) {
+        Some(id) => id,
+        None => {
+            next_num += 1;
+            (next_num - 1, 0)
+        }
+    };
+    let mut objects: Vec<(ObjectId, Object)> = vec![(info_id, Object::Dictionary(info))];
+    if let Some((xmp_id, xmp)) = xmp_stream(&doc) {
+        match update_xmp(&xmp, &xmp_edits) {
+            Some(updated) => {
+                // XMP stays uncompressed so it can be found without parsing the PDF
+                let mut dict = doc.get_object(xmp_id)?.as_stream()?.dict.clone();
+                dict.remove(b"Filter");
+                dict.remove(b"DecodeParms");
+                objects.push((xmp_id, Object::Stream(Stream::new(dict, updated.into_bytes()))));
+            }
+            None => warn!(
+                "{}: XMP metadata has no rdf:RDF element, left unchanged",
+                input.display()
+            ),
+        }
+    }
+
+    let mut trailer = Dictionary::new();
+    trailer.set("Size", next_num as i64);
+    trailer.set("Root", doc.trailer.get(b"Root")?.clone());
+    trailer.set("Info", info_id);
+    // the second ID changes with every revision of the file
+    if let Ok(Object::Array(ids)) = doc.trailer.get(b"ID") {
+        if let Some(first) = ids.first() {
+            let tail = &original[original.len().saturating_sub(1024)..];
+            let revision = crypto::md5(&[tail, now.as_bytes()]);
+            let revision = Object::String(revision.to_vec(), StringFormat::Hexadecimal);
+            trailer.set("ID", vec![first.clone(), revision]);
+        }
+    }
+    let xref_stream = matches!(
+        doc.reference_table.cross_reference_type,
+        XrefType::CrossReferenceStream
+    );
+    let update =
+        writer::write_update(&original, doc.xref_start as u64, xref_stream, &objects, trailer)?;
+
+    if target == input {
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(input)
+            .and_then(|mut file| file.write_all(&update))
+            .with_context(|| format!("Failed to update {}", input.display()))?;
+    } else {
+        pdf::write_output(target, |out| {
+            out.write_all(&original)?;
+            out.write_all(&update)?;
+            Ok(())
+        })?;
+    }
+
+    if !quiet {
+        info!("Done. PDF saved in {:.2}s", start.elapsed().as_secs_f64());
+    }
+    Ok(())
+}
+
+/// text fields of the info dictionary: standard keys first, then custom ones
+fn info_fields(doc: &Document) -> Vec<(String, String)> {
+    let Some(info) = doc
+        .trailer
+        .get(b"Info")
+        .ok()
+        .and_then(|info| doc.dereference(info).ok())
+        .and_then(|(_, info)| info.as_dict().ok())
+    else {
+        return Vec::new();
+    };
+    let text = |value: &Object| match doc.dereference(value).ok()?.1 {
+        Object::String(bytes, _) => Some(pdf::decode_text(bytes)),
+        Object::Name(name) => Some(String::from_utf8_lossy(name).into_owned()),
+        _ => None,
+    };
+    let mut fields: Vec<(String, String)> = INFO_KEYS
+        .iter()
+        .filter_map(|key| {
+            let value = info.get(key.as_bytes()).ok().and_then(text)?;
+            Some((key.to_string(), value))
+        })
+        .collect();
+    for (key, value) in info.iter() {
+        let key = String::from_utf8_lossy(key);
+        if INFO_KEYS.contains(&key.as_ref()) {
+            continue;
+        }
+        if let Some(value) = text(value) {
+            fields.push((key.into_owned(), value));
+        }
+    }
+    fields
+}
+
+/// the catalog's XMP metadata stream, decoded
+fn xmp_stream(doc: &Document) -> Option<(ObjectId, String)> {
+    let id = doc.catalog().ok()?.get(b"Metadata").ok()?.as_reference().ok()?;
+    let stream = doc.get_object(id).ok()?.as_stream().ok()?;
+    let data = if stream.dict.has(b"Filter") {
+        stream.decompressed_content().ok()?
+    } else {
+        stream.content.clone()
+    };
+    Some((id, String::from_utf8_lossy(&data).into_owned()))
+}
+
+fn parse_date(key: &str, value: &str) -> Result<DateTime<FixedOffset>> {
+    DateTime::parse_from_rfc3339(value).with_context(|| {
+        format!(
+            "Invalid {} '{}' (expected RFC 3339, e.g. 2024-05-01T09:30:00+02:00)",
+            key, value
+        )
+    })
+}
+
+/// namespace URIs for the prefixes of `XMP_PROPERTIES`
+fn xmp_namespace(prefix: &str) -> &'static str {
+    match prefix {
+        "dc" => "http://purl.org/dc/elements/1.1/",
+        "pdf" => "http://ns.adobe.com/pdf/1.3/",
+        _ => "http://ns.adobe.com/xap/1.0/",
+    }
+}
+
+/// apply property edits (None removes) to an XMP packet: old values are removed
+/// wherever they are, new ones go into an added rdf:Description. None if the
+/// packet has no rdf:RDF element to add to
+fn update_xmp(xmp: &str, edits: &[(&str, Option<String>)]) -> Option<String> {
+    let mut xmp = xmp.to_string();
+    let mut added = String::new();
+    let mut prefixes: Vec<&str> = Vec::new();
+    for (prop, value) in edits {
+        xmp = remove_xmp_property(&xmp, prop);
+        // older packets use the xap prefix for the xmp namespace
+        if let Some(name) = prop.strip_prefix("xmp:") {
+            xmp = remove_xmp_property(&xmp, &format!("xap:{}", name));
+        }
+        let Some(value) = value else { continue };
+        let value = xml_escape(value);
+        added.push_str(&match *prop {
+            "dc:title" | "dc:description" => format!(
+                "<{0}><rdf:Alt><rdf:li xml:lang=\"x-default\">{1}</rdf:li></rdf:Alt></{0}>",
+                prop, value
+            ),
+            "dc:creator" => {
+                format!("<{0}><rdf:Seq><rdf:li>{1}</rdf:li></rdf:Seq></{0}>", prop, value)
+            }
+            _ => format!("<{0}>{1}</{0}>", prop, value),
+        });
+        let prefix = prop.split(':').next().unwrap();
+        if !prefixes.contains(&prefix) {
+            prefixes.push(prefix);
+        }
+    }
+    if !added.is_empty() {
+        let end = xmp.rfind("</rdf:RDF>")?;
+        let namespaces: String = prefixes
+            .iter()
+            .map(|p| format!(" xmlns:{}=\"{}\"", p, xmp_namespace(p)))
+            .collect();
+        let description =
+            format!("<rdf:Description rdf:about=\"\"{}>{}</rdf:Description>\n", namespaces, added);
+        xmp.insert_str(end, &description);
+    }
+    Some(xmp)
+}
+
+/// byte range of a property written as an element, `<prop>...</prop>` or `<prop/>`
+fn find_xmp_element(xmp: &str, prop: &str) -> Option<(usize, usize)> {
+    let open = format!("<{}", prop);
+    let mut from = 0;
+    while let Some(pos) = xmp[from..].find(&open) {
+        let start = from + pos;
+        let after = start + open.len();
+        from = after;
+        // not a longer name that starts the same
+        if !xmp[after..].starts_with(|c: char| c == '>' || c == '/' || c.is_whitespace()) {
+            continue;
+        }
+        let tag_end = after + xmp[after..].find('>')?;
+        if xmp[..tag_end].ends_with('/') {
+            return Some((start, tag_end + 1));
+        }
+        let close = format!("</{}>", prop);
+        let end = tag_end + xmp[tag_end..].find(&close)? + close.len();
+        return Some((start, end));
+    }
+    None
+}
+
+/// byte range of a property written as an attribute, ` prop="..."`, and of its value
+fn find_xmp_attribute(xmp: &str, prop: &str) -> Option<((usize, usize), (usize, usize))> {
+    for quote in ['"', '\''] {
+        let pattern = format!("{}={}", prop, quote);
+        let mut from = 0;
+        while let Some(pos) = xmp[from..].find(&pattern) {
+            let start = from + pos;
+            from = start + pattern.len();
+            if !xmp[..start].ends_with(|c: char| c.is_whitespace()) {
+                continue;
+            }
+            let value_start = start + pattern.len();
+            let value_end = value_start + xmp[value_start..].find(quote)?;
+            let ws = xmp[..start].trim_end().len();
+            return Some(((ws, value_end + 1), (value_start, value_end)));
+        }
+    }
+    None
+}
+
+fn remove_xmp_property(xmp: &str, prop: &str) -> String {
+    let mut xmp = xmp.to_string();
+    while let Some((start, end)) = find_xmp_element(&xmp, prop) {
+        xmp.replace_range(start..end, "");
+    }
+    while let Some(((start, end), _)) = find_xmp_attribute(&xmp, prop) {
+        xmp.replace_range(start..end, "");
+    }
+    xmp
+}
+
+/// text of an XMP property; the items of an rdf:Seq or rdf:Bag are joined with "; "
+fn xmp_get(xmp: &str, prop: &str) -> Option<String> {
+    let mut names = vec![prop.to_string()];
+    if let Some(name) = prop.strip_prefix("xmp:") {
+        names.push(format!("xap:{}", name));
+    }
+    for name in &names {
+        if let Some((start, end)) = find_xmp_element(xmp, name) {
+            let element = &xmp[start..end];
+            let texts: Vec<String> = element
+                .split('<')
+                .filter_map(|part| part.split_once('>').map(|(_, text)| text.trim()))
+                .filter(|text| !text.is_empty())
+                .map(xml_unescape)
+                .collect();
+            if !texts.is_empty() {
+                return Some(texts.join("; "));
+            }
+        }
+        if let Some((_, (start, end))) = find_xmp_attribute(xmp, name) {
+            return Some(xml_unescape(&xmp[start..end]));
+        }
+    }
+    None
+}
+
+pub fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn xml_unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XMP: &str = r#"<?xpacket begin="" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+<rdf:Description rdf:about="" xmlns:pdf="http://ns.adobe.com/pdf/1.3/" pdf:Producer="Old &amp; Co">
+<dc:title xmlns:dc="http://purl.org/dc/elements/1.1/"><rdf:Alt><rdf:li xml:lang="x-default">Draft</rdf:li></rdf:Alt></dc:title>
+<dc:titleExtra>keep</dc:titleExtra>
+<xap:ModifyDate>2020-01-01T00:00:00Z</xap:ModifyDate>
+</rdf:Description>
+</rdf:RDF></x:xmpmeta>
+<?xpacket end="w"?>"#;
+
+    #[test]
+    fn xmp_reads_elements_and_attributes() {
+        assert_eq!(xmp_get(XMP, "dc:title").as_deref(), Some("Draft"));
+        assert_eq!(xmp_get(XMP, "pdf:Producer").as_deref(), Some("Old & Co"));
+        assert_eq!(xmp_get(XMP, "xmp:ModifyDate").as_deref(), Some("2020-01-01T00:00:00Z"));
+        assert_eq!(xmp_get(XMP, "dc:creator"), None);
+    }
+
+    #[test]
+    fn xmp_update_replaces_and_removes() {
+        let edits = [
+            ("dc:title", Some("Q3 <Report>".to_string())),
+            ("pdf:Producer", None),
+            ("xmp:ModifyDate", Some("2024-05-01T09:30:00+02:00".to_string())),
+        ];
+        let updated = update_xmp(XMP, &edits).unwrap();
+        assert_eq!(xmp_get(&updated, "dc:title").as_deref(), Some("Q3 <Report>"));
+        assert_eq!(xmp_get(&updated, "pdf:Producer"), None);
+        assert_eq!(
+            xmp_get(&updated, "xmp:ModifyDate").as_deref(),
+            Some("2024-05-01T09:30:00+02:00")
+        );
+        assert!(!updated.contains("xap:ModifyDate"));
+        assert!(updated.contains("<dc:titleExtra>keep</dc:titleExtra>"));
+        assert!(updated.contains("xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\""));
+        assert!(update_xmp("<x:xmpmeta/>", &edits).is_none());
+    }
+}
@@ -0,0 +1,215 @@
+use anyhow::Result;
+use lopdf::{dictionary, Dictionary, Object, ObjectId, Stream};
+use std::path::Path;
+use tracing::info;
+
+use crate::parse::{Orientation, PageSize};
+use crate::pdf;
+
+pub struct NupOptions {
+    /// source pages per sheet
+    pub per_sheet: usize,
+    /// sheet size; default fits the first page
+    pub page_size: Option<PageSize>,
+    pub orientation: Orientation,
+    /// space around the sheet edge, in points
+    pub margin: f32,
+    /// space between cells, in points
+    pub gap: f32,
+    /// outline each placed page
+    pub border: bool,
+    pub quiet: bool,
+}
+
+/// sheet size and grid picked for the source pages
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Layout {
+    width: f32,
+    height: f32,
+    cols: usize,
+    rows: usize,
+}
+
+/// place several pages of a PDF on each sheet, left to right, top to bottom.
+/// pages are referenced as form XObjects, so nothing is rasterized
+pub fn nup_pdf(input: &Path, output: &Path, opts: &NupOptions) -> Result<()> {
+    let start = std::time::Instant::now();
+    let mut doc = pdf::load(input)?;
+    pdf::inherit_page_attributes(&mut doc);
+    let page_ids: Vec<ObjectId> = doc.get_pages().into_values().collect();
+    anyhow::ensure!(!page_ids.is_empty(), "{} has no pages", input.display());
+
+    let mut forms = Vec::with_capacity(page_ids.len());
+    for &page_id in &page_ids {
+        forms.push(pdf::page_form(&mut doc, page_id)?);
+    }
+    let (_, first_w, first_h) = forms[0];
+    let sheet = opts.page_size.map(PageSize::dimensions_pt);
+    let layout = choose_layout(opts, sheet.unwrap_or((first_w, first_h)), (first_w, first_h));
+    let sheets = forms.len().div_ceil(opts.per_sheet);
+    if !opts.quiet {
+        info!(
+            "Placing {} page(s) {}-up ({}x{}) on {} sheet(s) -> {}",
+            forms.len(),
+            opts.per_sheet,
+            layout.cols,
+            layout.rows,
+            sheets,
+            output.display()
+        );
+    }
+
+    let pages_id = doc.catalog()?.get(b"Pages").and_then(Object::as_reference)?;
+    let (cell_w, cell_h) = cell_size(opts, layout.width, layout.height, layout.cols, layout.rows);
+    anyhow::ensure!(
+        cell_w > 0.0 && cell_h > 0.0,
+        "Margin and gap leave no room for pages"
+    );
+    let mut sheet_ids = Vec::with_capacity(sheets);
+    for chunk in forms.chunks(opts.per_sheet) {
+        let mut xobjects = Dictionary::new();
+        let mut content = String::new();
+        for (slot, &(form_id, w, h)) in chunk.iter().enumerate() {
+            let (col, row) = (slot % layout.cols, slot / layout.cols);
+            let cell_x = opts.margin + col as f32 * (cell_w + opts.gap);
+            let cell_top = layout.height - opts.margin - row as f32 * (cell_h + opts.gap);
+            // fit and center in the cell
+            let scale = (cell_w / w).min(cell_h / h);
+            let x = cell_x + (cell_w - w * scale) / 2.0;
+            let y = cell_top - cell_h + (cell_h - h * scale) / 2.0;
+            let name = format!("P{}", slot);
+            content.push_str(&format!(
+                "q {} 0 0 {} {} {} cm /{} Do Q\n",
+                fmt(scale),
+                fmt(scale),
+                fmt(x),
+                fmt(y),
+                name
+            ));
+            if opts.border {
+                content.push_str(&format!(
+                    "q 0.5 w 0 G {} {} {} {} re S Q\n",
+                    fmt(x),
+                    fmt(y),
+                    fmt(w * scale),
+                    fmt(h * scale)
+                ));
+            }
+            xobjects.set(name, form_id);
+        }
+        let mut stream = Stream::new(Dictionary::new(), content.into_bytes());
+        stream.compress()?;
+        let content_id = doc.add_object(stream);
+        let sheet_id = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => [0.0, 0.0, layout.width, layout.height].map(Object::Real).to_vec(),
+            "Resources" => dictionary! { "XObject" => xobjects },
+            "Contents" => content_id,
+        });
+        sheet_ids.push(sheet_id);
+    }
+    // the source pages live on only as forms
+    pdf::set_page_order(&mut doc, &sheet_ids)?;
+    pdf::save(&mut doc, output)?;
+
+    if !opts.quiet {
+        info!("Done. PDF saved in {:.2}s", start.elapsed().as_secs_f64());
+    }
+    Ok(())
+}
+
+/// columns x rows for n pages, as square as possible with more columns
+fn grid(n: usize) -> (usize, usize) {
+    let rows = (1..=n)
+        .take_while(|r| r * r <= n)
+        .filter(|r| n.is_multiple_of(*r))
+        .last()
+        .unwrap_or(1);
+    (n / rows, rows)
+}
+
+/// sheet orientation and grid direction that show a `page` sized page largest
+fn choose_layout(opts: &NupOptions, sheet: (f32, f32), page: (f32, f32)) -> Layout {
+    let (short, long) = (sheet.0.min(sheet.1), sheet.0.max(sheet.1));
+    let sheets: Vec<(f32, f32)> = match opts.orientation {
+        Orientation::Portrait => vec![(short, long)],
+        Orientation::Landscape => vec![(long, short)],
+        // n-up of a page usually wants the other orientation: 2-up portrait
+        // pages go side by side on a landscape sheet
+        Orientation::Auto => vec![(long, short), (short, long)],
+    };
+    let (cols, rows) = grid(opts.per_sheet);
+    let mut best: Option<(f32, Layout)> = None;
+    for &(width, height) in &sheets {
+        for (cols, rows) in [(cols, rows), (rows, cols)] {
+            let (cell_w, cell_h) = cell_size(opts, width, height, cols, rows);
+            let scale = (cell_w / page.0).min(cell_h / page.1);
+            if best.is_none_or(|(s, _)| scale > s + 1e-3) {
+                let layout = Layout {
+                    width,
+                    height,
+                    cols,
+                    rows,
+                };
+                best = Some((scale, layout));
+            }
+        }
+    }
+    best.unwrap().1
+}
+
+/// room for each page on a `width` x `height` sheet
+fn cell_size(opts: &NupOptions, width: f32, height: f32, cols: usize, rows: usize) -> (f32, f32) {
+    let room = |length: f32, n: usize| {
+        (length - 2.0 * opts.margin - (n - 1) as f32 * opts.gap) / n as f32
+    };
+    (room(width, cols), room(height, rows))
+}
+
+/// compact number for content streams
+fn fmt(v: f32) -> String {
+    let s = format!("{:.3}", v);
+    s.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opts(per_sheet: usize, orientation: Orientation) -> NupOptions {
+        NupOptions {
+            per_sheet,
+            page_size: None,
+            orientation,
+            margin: 0.0,
+            gap: 0.0,
+            border: false,
+            quiet: true,
+        }
+    }
+
+    #[test]
+    fn grid_is_near_square() {
+        assert_eq!(grid(2), (2, 1));
+        assert_eq!(grid(4), (2, 2));
+        assert_eq!(grid(6), (3, 2));
+        assert_eq!(grid(8), (4, 2));
+        assert_eq!(grid(9), (3, 3));
+        assert_eq!(grid(3), (3, 1));
+    }
+
+    #[test]
+    fn layout_turns_sheet_for_best_fit() {
+        let a4 = (595.0, 842.0);
+        // portrait pages 2-up: side by side on landscape
+        let layout = choose_layout(&opts(2, Orientation::Auto), a4, a4);
+        assert_eq!((layout.width, layout.cols, layout.rows), (842.0, 2, 1));
+        // 4-up stays portrait
+        let layout = choose_layout(&opts(4, Orientation::Auto), a4, a4);
+        assert_eq!((layout.width, layout.cols, layout.rows), (595.0, 2, 2));
+        // landscape pages on a forced portrait sheet are stacked
+        let layout = choose_layout(&opts(2, Orientation::Portrait), a4, (842.0, 595.0));
+        assert_eq!((layout.width, layout.cols, layout.rows), (595.0, 1, 2));
+    }
+}
@@ -0,0 +1,602 @@
+use anyhow::Result;
+use lopdf::content::Content;
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream};
+use rayon::prelude::*;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::Path;
+use tracing::info;
+
+use crate::parse::ByteSize;
+use crate::pdf;
+
+pub struct OptimizeOptions {
+    /// downsample images shown above this resolution (None keeps every pixel)
+    pub max_dpi: Option<u32>,
+    /// JPEG quality for re-encoded images
+    pub quality: u8,
+    /// re-encode photographic Flate images as JPEG
+    pub jpeg: bool,
+    pub quiet: bool,
+}
+
+/// shrink an existing PDF: downsample and recompress images, merge duplicate
+/// streams, drop unused page resources, and write with object streams
+pub fn optimize_pdf(input: &Path, output: &Path, opts: &OptimizeOptions) -> Result<()> {
+    let start = std::time::Instant::now();
+    let before = std::fs::metadata(input).map(|m| m.len()).unwrap_or(0);
+    if !opts.quiet {
+        info!("Optimizing {} -> {}", input.display(), output.display());
+    }
+    let mut doc = pdf::load(input)?;
+
+    let images = recompress_images(&mut doc, opts);
+    let duplicates = merge_duplicate_streams(&mut doc);
+    let resources = remove_unused_resources(&mut doc);
+    doc.prune_objects();
+    doc.renumber_objects();
+    // content streams and other plain streams get Flate
+    doc.compress();
+    pdf::save_compact(&doc, output)?;
+
+    if !opts.quiet {
+        info!(
+            "{} image(s) recompressed, {} duplicate stream(s) merged, \
+             {} unused resource(s) removed",
+            images, duplicates, resources
+        );
+        if output != Path::new("-") {
+            let after = std::fs::metadata(output).map(|m| m.len()).unwrap_or(0);
+            let saved = 100.0 * (1.0 - after as f64 / before.max(1) as f64);
+            info!("{} -> {} ({:.1}% smaller)", ByteSize(before), ByteSize(after), saved);
+        }
+        info!("Done. PDF saved in {:.2}s", start.elapsed().as_secs_f64());
+    }
+    Ok(())
+}
+
+/// 2D affine matrix [a b c d e f] as used by `cm`
+type Matrix = [f32; 6];
+
+const IDENTITY: Matrix = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+
+/// m1 applied first, then m2
+fn multiply(m1: &Matrix, m2: &Matrix) -> Matrix {
+    [
+        m1[0] * m2[0] + m1[1] * m2[2],
+        m1[0] * m2[1] + m1[1] * m2[3],
+        m1[2] * m2[0] + m1[3] * m2[2],
+        m1[2] * m2[1] + m1[3] * m2[3],
+        m1[4] * m2[0] + m1[5] * m2[2] + m2[4],
+        m1[4] * m2[1] + m1[5] * m2[3] + m2[5],
+    ]
+}
+
+fn matrix_operands(operands: &[Object]) -> Option<Matrix> {
+    let v: Vec<f32> = operands.iter().filter_map(pdf::number).collect();
+    (v.len() == 6).then(|| [v[0], v[1], v[2], v[3], v[4], v[5]])
+}
+
+/// largest size, in points, each image XObject is drawn at anywhere in the document;
+/// images drawn in ways we can't follow are left out (and never downsampled)
+fn image_placements(doc: &Document) -> HashMap<ObjectId, (f32, f32)> {
+    let mut placements: HashMap<ObjectId, (f32, f32)> = HashMap::new();
+    for page_id in doc.get_pages().into_values() {
+        for (id, (w, h)) in page_image_placements(doc, page_id) {
+            let size = placements.entry(id).or_insert((0.0, 0.0));
+            *size = (size.0.max(w), size.1.max(h));
+        }
+    }
+    placements
+}
+
+/// largest size, in points, each image XObject is drawn at on one page, directly
+/// or through forms
+pub fn page_image_placements(doc: &Document, page_id: ObjectId) -> HashMap<ObjectId, (f32, f32)> {
+    let mut placements = HashMap::new();
+    if let Ok(content) = doc.get_and_decode_page_content(page_id) {
+        let resources = pdf::page_attribute(doc, page_id, b"Resources");
+        walk_content(doc, &content, resources, IDENTITY, 0, &mut placements);
+    }
+    placements
+}
+
+fn walk_content(
+    doc: &Document,
+    content: &Content,
+    resources: Option<&Object>,
+    base: Matrix,
+    depth: usize,
+    placements: &mut HashMap<ObjectId, (f32, f32)>,
+) {
+    let xobjects = resources
+        .and_then(|r| r.as_dict().ok())
+        .and_then(|r| r.get(b"XObject").ok())
+        .and_then(|x| doc.dereference(x).ok())
+        .and_then(|(_, x)| x.as_dict().ok());
+    let mut ctm = base;
+    let mut stack = Vec::new();
+    for op in &content.operations {
+        match op.operator.as_str() {
+            "q" => stack.push(ctm),
+            "Q" => ctm = stack.pop().unwrap_or(base),
+            "cm" => {
+                if let Some(m) = matrix_operands(&op.operands) {
+                    ctm = multiply(&m, &ctm);
+                }
+            }
+            "Do" => {
+                let Some(id) = op
+                    .operands
+                    .first()
+                    .and_then(|n| n.as_name().ok())
+                    .and_then(|n| xobjects?.get(n).ok())
+                    .and_then(|x| x.as_reference().ok())
+                else {
+                    continue;
+                };
+                let Ok(stream) = doc.get_object(id).and_then(Object::as_stream) else { continue };
+                match stream.dict.get(b"Subtype").and_then(Object::as_name) {
+                    Ok(b"Image") => {
+                        // the unit square scaled by the CTM
+                        let w = ctm[0].hypot(ctm[1]);
+                        let h = ctm[2].hypot(ctm[3]);
+                        let size = placements.entry(id).or_insert((0.0, 0.0));
+                        *size = (size.0.max(w), size.1.max(h));
+                    }
+                    Ok(b"Form") if depth < 8 => {
+                        let Ok(form) = stream.get_plain_content().map(|c| Content::decode(&c))
+                        else {
+                            continue;
+                        };
+                        let Ok(form) = form else { continue };
+                        let matrix = stream
+                            .dict
+                            .get(b"Matrix")
+                            .and_then(Object::as_array)
+                            .ok()
+                            .and_then(|m| matrix_operands(m))
+                            .unwrap_or(IDENTITY);
+                        let form_resources = stream
+                            .dict
+                            .get(b"Resources")
+                            .ok()
+                            .and_then(|r| doc.dereference(r).ok())
+                            .map(|(_, r)| r)
+                            .or(resources);
+                        let base = multiply(&matrix, &ctm);
+                        walk_content(doc, &form, form_resources, base, depth + 1, placements);
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// an image stream and what the rewrite may do with it
+struct ImageJob<'a> {
+    id: ObjectId,
+    stream: &'a Stream,
+    components: usize,
+    /// pixel size to downsample to, if smaller than the image
+    target: Option<(u32, u32)>,
+}
+
+/// downsample and re-encode images in parallel; returns how many shrank
+fn recompress_images(doc: &mut Document, opts: &OptimizeOptions) -> usize {
+    let placements = image_placements(doc);
+    // soft masks and stencils keep their exact pixels
+    let mut masks = HashSet::new();
+    for obj in doc.objects.values() {
+        let Ok(stream) = obj.as_stream() else { continue };
+        for key in [b"SMask".as_slice(), b"Mask"] {
+            if let Ok(id) = stream.dict.get(key).and_then(Object::as_reference) {
+                masks.insert(id);
+            }
+        }
+    }
+
+    let jobs: Vec<ImageJob> = doc
+        .objects
+        .iter()
+        .filter(|(id, _)| !masks.contains(*id))
+        .filter_map(|(&id, obj)| {
+            let stream = obj.as_stream().ok()?;
+            let dict = &stream.dict;
+            if dict.get(b"Subtype").and_then(Object::as_name).ok() != Some(b"Image")
+                || dict.get(b"BitsPerComponent").and_then(Object::as_i64).ok() != Some(8)
+                || dict.has(b"Decode")
+                // color-key masks need exact colors
+                || dict.get(b"Mask").is_ok_and(|m| m.as_array().is_ok())
+            {
+                return None;
+            }
+            let components = color_components(doc, dict.get(b"ColorSpace").ok()?)?;
+            let width = dict.get(b"Width").and_then(Object::as_i64).ok()? as u32;
+            let height = dict.get(b"Height").and_then(Object::as_i64).ok()? as u32;
+            let target = opts.max_dpi.zip(placements.get(&id)).and_then(|(dpi, &size)| {
+                downsampled_size(width, height, size, dpi)
+            });
+            Some(ImageJob {
+                id,
+                stream,
+                components,
+                target,
+            })
+        })
+        .collect();
+
+    let results: Vec<(ObjectId, Stream)> = jobs
+        .par_iter()
+        .filter_map(|job| Some((job.id, recompress_image(job, opts)?)))
+        .collect();
+    let count = results.len();
+    for (id, stream) in results {
+        doc.objects.insert(id, Object::Stream(stream));
+    }
+    count
+}
+
+/// channels of a gray or RGB color space, None for anything else (CMYK,
+/// indexed, separations), which is left alone
+fn color_components(doc: &Document, color_space: &Object) -> Option<usize> {
+    let (_, color_space) = doc.dereference(color_space).ok()?;
+    match color_space {
+        Object::Name(name) if name == b"DeviceGray" => Some(1),
+        Object::Name(name) if name == b"DeviceRGB" => Some(3),
+        Object::Array(items) if items.first()?.as_name().ok()? == b"ICCBased" => {
+            let (_, profile) = doc.dereference(items.get(1)?).ok()?;
+            match profile.as_stream().ok()?.dict.get(b"N").and_then(Object::as_i64).ok()? {
+                1 => Some(1),
+                3 => Some(3),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// pixel size bringing an image drawn at `size` points down to `max_dpi`, if it is
+/// noticeably sharper than that
+fn downsampled_size(width: u32, height: u32, size: (f32, f32), max_dpi: u32) -> Option<(u32, u32)> {
+    let (w_pt, h_pt) = size;
+    if w_pt <= 0.0 || h_pt <= 0.0 {
+        return None;
+    }
+    let dpi = (width as f32 * 72.0 / w_pt).min(height as f32 * 72.0 / h_pt);
+    // a little headroom so near-target images aren't resampled for nothing
+    if dpi <= max_dpi as f32 * 1.2 {
+        return None;
+    }
+    let factor = max_dpi as f32 / dpi;
+    let w = ((width as f32 * factor).round() as u32).max(1);
+    let h = ((height as f32 * factor).round() as u32).max(1);
+    Some((w, h))
+}
+
+/// the single filter of a stream, if it has exactly one
+fn single_filter(dict: &Dictionary) -> Option<&[u8]> {
+    match dict.get(b"Filter").ok()? {
+        Object::Name(name) => Some(name),
+        Object::Array(names) if names.len() == 1 => names[0].as_name().ok(),
+        _ => None,
+    }
+}
+
+/// re-encode one image, returning the replacement stream only if it is smaller
+fn recompress_image(job: &ImageJob, opts: &OptimizeOptions) -> Option<Stream> {
+    let dict = &job.stream.dict;
+    let filter = single_filter(dict)?;
+    let is_jpeg = filter == b"DCTDecode";
+    if !is_jpeg && filter != b"FlateDecode" {
+        return None;
+    }
+    // JPEGs only gain from downsampling; Flate images also from becoming JPEGs
+    if job.target.is_none() && (is_jpeg || !opts.jpeg) {
+        return None;
+    }
+
+    let mut img = if is_jpeg {
+        let img = image::load_from_memory_with_format(&job.stream.content, image::ImageFormat::Jpeg)
+            .ok()?;
+        if img.color().channel_count() as usize != job.components {
+            return None;
+        }
+        img
+    } else {
+        decode_flate_image(job)?
+    };
+    if let Some((w, h)) = job.target {
+        img = img.resize_exact(w, h, image::imageops::FilterType::Triangle);
+    }
+
+    let use_jpeg = is_jpeg || (opts.jpeg && is_photographic(&img));
+    if !use_jpeg && job.target.is_none() {
+        return None;
+    }
+    let (filter, data) = if use_jpeg {
+        let mut out = Vec::new();
+        let mut encoder =
+            image::codecs::jpeg::JpegEncoder::new_with_quality(&mut out, opts.quality);
+        match job.components {
+            1 => encoder.encode_image(&img.to_luma8()).ok()?,
+            _ => encoder.encode_image(&img.to_rgb8()).ok()?,
+        }
+        ("DCTDecode", out)
+    } else {
+        let raw = match job.components {
+            1 => img.to_luma8().into_raw(),
+            _ => img.to_rgb8().into_raw(),
+        };
+        ("FlateDecode", zlib(&raw)?)
+    };
+    if data.len() >= job.stream.content.len() {
+        return None;
+    }
+
+    let mut dict = dict.clone();
+    dict.set("Width", img.width() as i64);
+    dict.set("Height", img.height() as i64);
+    dict.set("Filter", filter);
+    dict.remove(b"DecodeParms");
+    Some(Stream::new(dict, data))
+}
+
+/// inflate an 8-bit Flate image, undoing PNG row predictors
+fn decode_flate_image(job: &ImageJob) -> Option<image::DynamicImage> {
+    use std::io::Read;
+
+    let dict = &job.stream.dict;
+    let width = dict.get(b"Width").and_then(Object::as_i64).ok()? as u32;
+    let height = dict.get(b"Height").and_then(Object::as_i64).ok()? as u32;
+    let mut data = Vec::new();
+    flate2::read::ZlibDecoder::new(job.stream.content.as_slice())
+        .read_to_end(&mut data)
+        .ok()?;
+
+    let predictor = dict
+        .get(b"DecodeParms")
+        .and_then(Object::as_dict)
+        .and_then(|p| p.get(b"Predictor"))
+        .and_then(Object::as_i64)
+        .unwrap_or(1);
+    let row_len = width as usize * job.components;
+    let data = match predictor {
+        1 => data,
+        10..=15 => unpredict_png(&data, row_len, job.components)?,
+        // TIFF predictor: rare enough to leave alone
+        _ => return None,
+    };
+    if data.len() < row_len * height as usize {
+        return None;
+    }
+    let data = data[..row_len * height as usize].to_vec();
+    match job.components {
+        1 => image::GrayImage::from_raw(width, height, data).map(image::DynamicImage::ImageLuma8),
+        _ => image::RgbImage::from_raw(width, height, data).map(image::DynamicImage::ImageRgb8),
+    }
+}
+
+/// reverse PNG row filters: each row is a filter type byte followed by `row_len`
+/// bytes, `bpp` bytes per pixel
+fn unpredict_png(data: &[u8], row_len: usize, bpp: usize) -> Option<Vec<u8>> {
+    let mut out: Vec<u8> = Vec::with_capacity(data.len());
+    for (r, row) in data.chunks(row_len + 1).enumerate() {
+        let (&kind, row) = row.split_first()?;
+        if row.len() < row_len {
+            break;
+        }
+        let start = out.len();
+        for i in 0..row_len {
+            let left = if i >= bpp { out[start + i - bpp] } else { 0 };
+            let up = if r > 0 { out[start + i - row_len] } else { 0 };
+            let up_left = if r > 0 && i >= bpp { out[start + i - bpp - row_len] } else { 0 };
+            let predicted = match kind {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((left as u16 + up as u16) / 2) as u8,
+                4 => paeth(left, up, up_left),
+                _ => return None,
+            };
+            out.push(row[i].wrapping_add(predicted));
+        }
+    }
+    Some(out)
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// photos have many distinct colors; scans of text, charts, and screenshots have
+/// few and would pick up visible JPEG artifacts
+fn is_photographic(img: &image::DynamicImage) -> bool {
+    const MIN_COLORS: usize = 1024;
+    let rgb = img.to_rgb8();
+    let pixels = rgb.as_raw().chunks_exact(3);
+    // sample at most ~64k pixels
+    let step = (pixels.len() / 65536).max(1);
+    let mut colors = HashSet::new();
+    for px in pixels.step_by(step) {
+        colors.insert([px[0], px[1], px[2]]);
+        if colors.len() >= MIN_COLORS {
+            return true;
+        }
+    }
+    false
+}
+
+fn zlib(data: &[u8]) -> Option<Vec<u8>> {
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(data).ok()?;
+    encoder.finish().ok()
+}
+
+/// point references to byte-identical streams (fonts, images, and content embedded
+/// once per page) at a single copy; returns how many copies were dropped
+fn merge_duplicate_streams(doc: &mut Document) -> usize {
+    use std::hash::{Hash, Hasher};
+
+    let mut by_hash: HashMap<(u64, usize), Vec<ObjectId>> = HashMap::new();
+    for (&id, obj) in &doc.objects {
+        let Ok(stream) = obj.as_stream() else { continue };
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        stream.content.hash(&mut hasher);
+        by_hash.entry((hasher.finish(), stream.content.len())).or_default().push(id);
+    }
+
+    let mut replace: BTreeMap<ObjectId, ObjectId> = BTreeMap::new();
+    for ids in by_hash.values().filter(|ids| ids.len() > 1) {
+        let mut originals: Vec<ObjectId> = Vec::new();
+        for &id in ids {
+            let stream = doc.objects[&id].as_stream().unwrap();
+            let same = originals.iter().find(|&&orig| {
+                let orig = doc.objects[&orig].as_stream().unwrap();
+                orig.dict == stream.dict && orig.content == stream.content
+            });
+            match same {
+                Some(&orig) => {
+                    replace.insert(id, orig);
+                }
+                None => originals.push(id),
+            }
+        }
+    }
+    if !replace.is_empty() {
+        doc.traverse_objects(|obj| {
+            if let Object::Reference(id) = obj {
+                if let Some(&orig) = replace.get(id) {
+                    *id = orig;
+                }
+            }
+        });
+    }
+    replace.len()
+}
+
+/// drop XObject and Font entries that no page draws from its resources; returns
+/// how many were removed. shared resource dictionaries keep everything any of
+/// their pages uses, and undecodable content disables pruning for its resources
+fn remove_unused_resources(doc: &mut Document) -> usize {
+    /// resources dict: its object id, or the page id for one inlined in the page
+    type ResourcesKey = ObjectId;
+
+    let mut used: BTreeMap<ResourcesKey, Option<BTreeSet<Vec<u8>>>> = BTreeMap::new();
+    for page_id in doc.get_pages().into_values() {
+        let key = match doc
+            .get_dictionary(page_id)
+            .and_then(|page| page.get(b"Resources"))
+        {
+            Ok(Object::Reference(id)) => *id,
+            Ok(Object::Dictionary(_)) => page_id,
+            // inherited or missing: leave alone
+            _ => continue,
+        };
+        let names = doc.get_and_decode_page_content(page_id).ok().map(|content| {
+            content
+                .operations
+                .iter()
+                .filter(|op| op.operator == "Do" || op.operator == "Tf")
+                .filter_map(|op| op.operands.first()?.as_name().ok().map(<[u8]>::to_vec))
+                .collect::<BTreeSet<_>>()
+        });
+        // forms without their own resources draw from the page's
+        let names = names.filter(|names| {
+            let xobjects = pdf::page_attribute(doc, page_id, b"Resources")
+                .and_then(|r| r.as_dict().ok())
+                .and_then(|r| r.get(b"XObject").ok())
+                .and_then(|x| doc.dereference(x).ok())
+                .and_then(|(_, x)| x.as_dict().ok());
+            !names.iter().any(|name| {
+                xobjects
+                    .and_then(|x| x.get(name).ok())
+                    .and_then(|x| doc.dereference(x).ok())
+                    .and_then(|(_, x)| x.as_stream().ok())
+                    .is_some_and(|x| {
+                        x.dict.get(b"Subtype").and_then(Object::as_name).ok() == Some(b"Form")
+                            && !x.dict.has(b"Resources")
+                    })
+            })
+        });
+        let entry = used.entry(key).or_insert_with(|| Some(BTreeSet::new()));
+        match (entry.as_mut(), names) {
+            (Some(all), Some(names)) => all.extend(names),
+            _ => *entry = None,
+        }
+    }
+
+    let mut removed = 0;
+    for (key, names) in used {
+        let Some(names) = names else { continue };
+        let resources = match doc.get_object_mut(key) {
+            Ok(Object::Dictionary(dict)) if dict.has(b"Resources") => {
+                // a page with inline resources
+                match dict.get_mut(b"Resources") {
+                    Ok(Object::Dictionary(resources)) => resources,
+                    _ => continue,
+                }
+            }
+            Ok(Object::Dictionary(dict)) => dict,
+            _ => continue,
+        };
+        for category in [b"XObject".as_slice(), b"Font"] {
+            // categories held by reference may be shared further; leave them
+            let Ok(Object::Dictionary(entries)) = resources.get_mut(category) else { continue };
+            let unused: Vec<Vec<u8>> = entries
+                .iter()
+                .map(|(name, _)| name.clone())
+                .filter(|name| !names.contains(name))
+                .collect();
+            for name in unused {
+                entries.remove(&name);
+                removed += 1;
+            }
+        }
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn png_predictors_undone() {
+        // two rows of two RGB pixels: Sub on the first row, Up on the second
+        let data = [1, 10, 20, 30, 1, 1, 1, 2, 10, 10, 10, 0, 0, 0];
+        assert_eq!(
+            unpredict_png(&data, 6, 3).unwrap(),
+            vec![10, 20, 30, 11, 21, 31, 20, 30, 40, 11, 21, 31]
+        );
+    }
+
+    #[test]
+    fn downsample_only_when_sharper() {
+        // 3000 px across a 720 pt (10 in) placement is 300 dpi
+        assert_eq!(downsampled_size(3000, 1500, (720.0, 360.0), 150), Some((1500, 750)));
+        assert_eq!(downsampled_size(3000, 1500, (720.0, 360.0), 300), None);
+        assert_eq!(downsampled_size(3000, 1500, (0.0, 360.0), 150), None);
+    }
+
+    #[test]
+    fn matrix_product() {
+        let scale = [2.0, 0.0, 0.0, 3.0, 0.0, 0.0];
+        let translate = [1.0, 0.0, 0.0, 1.0, 10.0, 20.0];
+        assert_eq!(multiply(&scale, &translate), [2.0, 0.0, 0.0, 3.0, 10.0, 20.0]);
+        assert_eq!(multiply(&translate, &scale), [2.0, 0.0, 0.0, 3.0, 20.0, 60.0]);
+    }
+}
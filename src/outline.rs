@@ -0,0 +1,459 @@
+use anyhow::{bail, Context, Result};
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use tracing::info;
+
+use crate::parse::OutlineFormat;
+use crate::pdf;
+use crate::writer;
+
+/// deepest nesting read from a PDF, in case of a cyclic outline
+const MAX_LEVEL: usize = 64;
+
+/// one outline entry; the tree is a flat list where each entry nests under the
+/// closest entry before it with a lower level
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Bookmark {
+    pub title: String,
+    /// page it opens, from 1; None for entries without a destination
+    pub page: Option<usize>,
+    /// 1 for top-level entries
+    #[serde(default = "top_level")]
+    pub level: usize,
+}
+
+fn top_level() -> usize {
+    1
+}
+
+/// print a PDF's bookmarks, or write them to `output`, as JSON or TOML
+pub fn export_bookmarks(
+    input: &Path,
+    output: Option<&Path>,
+    format: Option<OutlineFormat>,
+) -> Result<()> {
+    let doc = pdf::load(input)?;
+    let bookmarks = read_outline(&doc);
+    let format = format
+        .or_else(|| output.and_then(OutlineFormat::from_path))
+        .unwrap_or(OutlineFormat::Json);
+    let text = match format {
+        OutlineFormat::Json => to_json(&bookmarks),
+        OutlineFormat::Toml => to_toml(&bookmarks),
+    };
+    match output {
+        Some(path) if path != Path::new("-") => std::fs::write(path, text)
+            .with_context(|| format!("Failed to save {}", path.display())),
+        _ => {
+            print!("{}", text);
+            Ok(())
+        }
+    }
+}
+
+/// replace a PDF's bookmarks with those from an outline file; an empty file
+/// removes them
+pub fn apply_bookmarks(input: &Path, outline: &Path, output: &Path, quiet: bool) -> Result<()> {
+    let start = std::time::Instant::now();
+    let bookmarks = load_outline_file(outline)?;
+    let mut doc = pdf::load(input)?;
+    let page_ids: Vec<ObjectId> = doc.get_pages().into_values().collect();
+    check_pages(&bookmarks, page_ids.len())?;
+    if !quiet {
+        info!(
+            "Applying {} bookmark(s) from {} -> {}",
+            bookmarks.len(),
+            outline.display(),
+            output.display()
+        );
+    }
+
+    set_outline(&mut doc, &bookmarks, &page_ids)?;
+    pdf::save(&mut doc, output)?;
+
+    if !quiet {
+        info!("Done. PDF saved in {:.2}s", start.elapsed().as_secs_f64());
+    }
+    Ok(())
+}
+
+/// replace a document's outline with `bookmarks`, whose pages index `page_ids`;
+/// none removes it
+pub fn set_outline(
+    doc: &mut Document,
+    bookmarks: &[Bookmark],
+    page_ids: &[ObjectId],
+) -> Result<()> {
+    let catalog_id = doc.trailer.get(b"Root").and_then(Object::as_reference)?;
+    if bookmarks.is_empty() {
+        doc.get_dictionary_mut(catalog_id)?.remove(b"Outlines");
+    } else {
+        let (root_id, objects) = outline_objects(bookmarks, page_ids, || doc.new_object_id());
+        for (id, dict) in objects {
+            doc.objects.insert(id, Object::Dictionary(dict));
+        }
+        doc.get_dictionary_mut(catalog_id)?.set("Outlines", root_id);
+    }
+    // the old outline items are unreachable now
+    doc.prune_objects();
+    Ok(())
+}
+
+/// read bookmarks from a .json or .toml outline file
+pub fn load_outline_file(path: &Path) -> Result<Vec<Bookmark>> {
+    let format = OutlineFormat::from_path(path).with_context(|| {
+        format!("{}: outline files must end in .json or .toml", path.display())
+    })?;
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Cannot read {}", path.display()))?;
+    let bookmarks = match format {
+        OutlineFormat::Json => from_json(&text),
+        OutlineFormat::Toml => from_toml(&text),
+    };
+    bookmarks
+        .and_then(|bookmarks| check_levels(&bookmarks).map(|_| bookmarks))
+        .with_context(|| format!("Invalid outline file {}", path.display()))
+}
+
+/// fail if a bookmark points past the last of `pages` pages
+pub fn check_pages(bookmarks: &[Bookmark], pages: usize) -> Result<()> {
+    if let Some(b) = bookmarks.iter().find(|b| b.page.is_some_and(|p| p > pages)) {
+        bail!(
+            "Bookmark \"{}\" points to page {}, but there are only {} page(s)",
+            b.title,
+            b.page.unwrap_or(0),
+            pages
+        );
+    }
+    Ok(())
+}
+
+/// the first entry is top-level, and no entry is more than one level below the
+/// one before it
+fn check_levels(bookmarks: &[Bookmark]) -> Result<()> {
+    let mut previous = 0;
+    for b in bookmarks {
+        if b.level > previous + 1 {
+            bail!(
+                "\"{}\" is at level {}, but the entry before it is at level {}",
+                b.title,
+                b.level,
+                previous
+            );
+        }
+        previous = b.level;
+    }
+    Ok(())
+}
+
+fn from_json(text: &str) -> Result<Vec<Bookmark>> {
+    let serde_json::Value::Array(items) = serde_json::from_str(text)? else {
+        bail!("expected an array of bookmarks");
+    };
+    items
+        .into_iter()
+        .enumerate()
+        .map(|(i, item)| checked(i + 1, serde_json::from_value(item)))
+        .collect()
+}
+
+/// an outline file's `[[bookmark]]` tables
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TomlOutline<T> {
+    #[serde(default = "Vec::new")]
+    bookmark: Vec<T>,
+}
+
+fn from_toml(text: &str) -> Result<Vec<Bookmark>> {
+    let outline: TomlOutline<toml::Value> = toml::from_str(text)?;
+    outline
+        .bookmark
+        .into_iter()
+        .enumerate()
+        .map(|(i, item)| checked(i + 1, item.try_into()))
+        .collect()
+}
+
+/// the `n`th entry of an outline file, its page and level checked
+fn checked<E>(n: usize, bookmark: Result<Bookmark, E>) -> Result<Bookmark>
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    let b = bookmark.with_context(|| format!("bookmark {}", n))?;
+    if b.page == Some(0) {
+        bail!("bookmark {}: page must be a number from 1", n);
+    }
+    if b.level == 0 {
+        bail!("bookmark {}: level must be a number from 1", n);
+    }
+    Ok(b)
+}
+
+/// a JSON array with one bookmark per line, easy to edit by hand
+fn to_json(bookmarks: &[Bookmark]) -> String {
+    if bookmarks.is_empty() {
+        return "[]\n".to_string();
+    }
+    let lines: Vec<String> = bookmarks
+        .iter()
+        .map(|b| format!("  {}", serde_json::to_string(b).expect("bookmarks are plain objects")))
+        .collect();
+    format!("[\n{}\n]\n", lines.join(",\n"))
+}
+
+fn to_toml(bookmarks: &[Bookmark]) -> String {
+    let outline = TomlOutline {
+        bookmark: bookmarks.iter().collect(),
+    };
+    toml::to_string(&outline).expect("bookmarks are plain tables")
+}
+
+/// a document's outline as a flat list, in reading order
+pub fn read_outline(doc: &Document) -> Vec<Bookmark> {
+    let page_numbers: HashMap<ObjectId, usize> = doc
+        .get_pages()
+        .into_iter()
+        .map(|(n, id)| (id, n as usize))
+        .collect();
+    let first = doc
+        .catalog()
+        .ok()
+        .and_then(|catalog| catalog.get(b"Outlines").ok())
+        .and_then(|outlines| doc.dereference(outlines).ok())
+        .and_then(|(_, outlines)| outlines.as_dict().ok())
+        .and_then(|outlines| outlines.get(b"First").and_then(Object::as_reference).ok());
+    let mut bookmarks = Vec::new();
+    if let Some(first) = first {
+        let mut seen = HashSet::new();
+        read_items(doc, first, 1, &page_numbers, &mut seen, &mut bookmarks);
+    }
+    bookmarks
+}
+
+/// an item and its following siblings, each followed by its children
+fn read_items(
+    doc: &Document,
+    first: ObjectId,
+    level: usize,
+    page_numbers: &HashMap<ObjectId, usize>,
+    seen: &mut HashSet<ObjectId>,
+    out: &mut Vec<Bookmark>,
+) {
+    let mut next = Some(first);
+    while let Some(id) = next.filter(|&id| seen.insert(id)) {
+        let Ok(item) = doc.get_dictionary(id) else {
+            return;
+        };
+        let title = item
+            .get(b"Title")
+            .ok()
+            .and_then(|t| doc.dereference(t).ok())
+            .and_then(|(_, t)| t.as_str().ok())
+            .map(pdf::decode_text)
+            .unwrap_or_default();
+        let target = match item.get(b"Dest") {
+            Ok(dest) => Some(dest),
+            Err(_) => item
+                .get(b"A")
+                .ok()
+                .and_then(|a| doc.dereference(a).ok())
+                .and_then(|(_, a)| a.as_dict().ok())
+                .filter(|a| a.get(b"S").and_then(Object::as_name).is_ok_and(|s| s == b"GoTo"))
+                .and_then(|a| a.get(b"D").ok()),
+        };
+        let page = target
+            .and_then(|dest| destination_page(doc, dest, 0))
+            .and_then(|page_id| page_numbers.get(&page_id).copied());
+        out.push(Bookmark { title, page, level });
+        if level < MAX_LEVEL {
+            if let Ok(child) = item.get(b"First").and_then(Object::as_reference) {
+                read_items(doc, child, level + 1, page_numbers, seen, out);
+            }
+        }
+        next = item.get(b"Next").and_then(Object::as_reference).ok();
+    }
+}
+
+/// the page an explicit destination, or a named one, points to
+pub fn destination_page(doc: &Document, dest: &Object, depth: usize) -> Option<ObjectId> {
+    if depth > 8 {
+        return None;
+    }
+    let (_, dest) = doc.dereference(dest).ok()?;
+    match dest {
+        Object::Array(items) => items.first()?.as_reference().ok(),
+        Object::Dictionary(dict) => destination_page(doc, dict.get(b"D").ok()?, depth + 1),
+        // PDF 1.1 style: a name in the catalog's /Dests dictionary
+        Object::Name(name) => {
+            let dests = doc.catalog().ok()?.get(b"Dests").ok()?;
+            let (_, dests) = doc.dereference(dests).ok()?;
+            destination_page(doc, dests.as_dict().ok()?.get(name).ok()?, depth + 1)
+        }
+        // a string in the /Names /Dests name tree
+        Object::String(name, _) => {
+            let names = doc.catalog().ok()?.get(b"Names").ok()?;
+            let (_, names) = doc.dereference(names).ok()?;
+            let tree = names.as_dict().ok()?.get(b"Dests").ok()?;
+            let target = name_tree_lookup(doc, tree, name, 0)?;
+            destination_page(doc, target, depth + 1)
+        }
+        _ => None,
+    }
+}
+
+fn name_tree_lookup<'a>(
+    doc: &'a Document,
+    node: &'a Object,
+    key: &[u8],
+    depth: usize,
+) -> Option<&'a Object> {
+    if depth > 32 {
+        return None;
+    }
+    let node = doc.dereference(node).ok()?.1.as_dict().ok()?;
+    if let Ok(Object::Array(names)) = node.get(b"Names") {
+        let found = names
+            .chunks_exact(2)
+            .find(|pair| pair[0].as_str().is_ok_and(|name| name == key));
+        if let Some(pair) = found {
+            return Some(&pair[1]);
+        }
+    }
+    let kids = node.get(b"Kids").and_then(Object::as_array).ok()?;
+    kids.iter()
+        .find_map(|kid| name_tree_lookup(doc, kid, key, depth + 1))
+}
+
+/// outline dictionaries for `bookmarks`, with `new_id` handing out object ids;
+/// returns the outline root's id and every dictionary to write. entries start
+/// out expanded, and open their page fitted to the window
+pub fn outline_objects(
+    bookmarks: &[Bookmark],
+    page_ids: &[ObjectId],
+    mut new_id: impl FnMut() -> ObjectId,
+) -> (ObjectId, Vec<(ObjectId, Dictionary)>) {
+    let root_id = new_id();
+    let ids: Vec<ObjectId> = bookmarks.iter().map(|_| new_id()).collect();
+    // children of the root (None) and of each entry, in order
+    let mut children: HashMap<Option<usize>, Vec<usize>> = HashMap::new();
+    let mut parents = Vec::with_capacity(bookmarks.len());
+    let mut stack: Vec<usize> = Vec::new();
+    for (i, b) in bookmarks.iter().enumerate() {
+        while stack.last().is_some_and(|&open| bookmarks[open].level >= b.level) {
+            stack.pop();
+        }
+        let parent = stack.last().copied();
+        children.entry(parent).or_default().push(i);
+        parents.push(parent);
+        stack.push(i);
+    }
+    // everything nested below an entry, all of it visible
+    let mut descendants = vec![0i64; bookmarks.len()];
+    for i in (0..bookmarks.len()).rev() {
+        if let Some(parent) = parents[i] {
+            descendants[parent] += 1 + descendants[i];
+        }
+    }
+
+    let link = |dict: &mut Dictionary, kids: Option<&Vec<usize>>| {
+        if let Some(kids) = kids {
+            dict.set("First", ids[kids[0]]);
+            dict.set("Last", ids[kids[kids.len() - 1]]);
+        }
+    };
+    let mut objects = Vec::with_capacity(bookmarks.len() + 1);
+    let mut root = dictionary! {
+        "Type" => "Outlines",
+        "Count" => bookmarks.len() as i64,
+    };
+    link(&mut root, children.get(&None));
+    objects.push((root_id, root));
+    for (i, b) in bookmarks.iter().enumerate() {
+        let siblings = &children[&parents[i]];
+        let position = siblings.iter().position(|&s| s == i).unwrap();
+        let mut item = dictionary! {
+            "Title" => writer::text_string(&b.title),
+            "Parent" => parents[i].map_or(root_id, |p| ids[p]),
+        };
+        if position > 0 {
+            item.set("Prev", ids[siblings[position - 1]]);
+        }
+        if let Some(&next) = siblings.get(position + 1) {
+            item.set("Next", ids[next]);
+        }
+        link(&mut item, children.get(&Some(i)));
+        if descendants[i] > 0 {
+            item.set("Count", descendants[i]);
+        }
+        if let Some(page) = b.page {
+            item.set("Dest", vec![page_ids[page - 1].into(), "Fit".into()]);
+        }
+        objects.push((ids[i], item));
+    }
+    (root_id, objects)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bookmark(title: &str, page: Option<usize>, level: usize) -> Bookmark {
+        Bookmark {
+            title: title.to_string(),
+            page,
+            level,
+        }
+    }
+
+    #[test]
+    fn file_formats_round_trip() {
+        let bookmarks = vec![
+            bookmark("Préface \"one\"", Some(1), 1),
+            bookmark("Part 1", None, 1),
+            bookmark("Chapter 1", Some(3), 2),
+        ];
+        assert_eq!(from_json(&to_json(&bookmarks)).unwrap(), bookmarks);
+        assert_eq!(from_toml(&to_toml(&bookmarks)).unwrap(), bookmarks);
+        assert!(from_json("[]").unwrap().is_empty());
+        let err = from_json(r#"[{"title": "a", "page": -1}]"#).unwrap_err();
+        assert_eq!(err.to_string(), "bookmark 1");
+        assert!(check_levels(&[bookmark("a", None, 2)]).is_err());
+        let err = from_toml("[[bookmark]]\ntitle = \"a\"\npages = 2\n").unwrap_err();
+        assert_eq!(err.to_string(), "bookmark 1");
+        assert!(format!("{:#}", err).contains("unknown field `pages`"), "{:#}", err);
+    }
+
+    #[test]
+    fn outline_tree_links() {
+        let bookmarks = vec![
+            bookmark("A", Some(1), 1),
+            bookmark("A.1", Some(1), 2),
+            bookmark("A.1.a", Some(2), 3),
+            bookmark("A.2", Some(2), 2),
+            bookmark("B", None, 1),
+        ];
+        let pages = [(10, 0), (11, 0)];
+        let mut next = 0;
+        let (root, objects) = outline_objects(&bookmarks, &pages, || {
+            next += 1;
+            (next, 0)
+        });
+        let get = |id: u32| &objects.iter().find(|(oid, _)| *oid == (id, 0)).unwrap().1;
+        let reference =
+            |dict: &Dictionary, key: &[u8]| dict.get(key).unwrap().as_reference().unwrap();
+        assert_eq!(root, (1, 0));
+        assert_eq!(reference(get(1), b"First"), (2, 0));
+        assert_eq!(reference(get(1), b"Last"), (6, 0));
+        assert_eq!(get(2).get(b"Count").unwrap().as_i64().unwrap(), 3);
+        assert_eq!(reference(get(3), b"Next"), (5, 0));
+        assert_eq!(reference(get(4), b"Parent"), (3, 0));
+        assert_eq!(reference(get(6), b"Prev"), (2, 0));
+        assert!(!get(6).has(b"Dest"));
+        let dest = get(4).get(b"Dest").unwrap().as_array().unwrap();
+        assert_eq!(dest[0].as_reference().unwrap(), (11, 0));
+    }
+}
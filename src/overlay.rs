@@ -0,0 +1,126 @@
+use anyhow::Result;
+use lopdf::ObjectId;
+use std::collections::HashMap;
+use std::path::Path;
+use tracing::info;
+
+use crate::parse::parse_page_ranges;
+use crate::pdf;
+
+pub struct OverlayOptions<'a> {
+    /// draw the overlay beneath the page content, as a background
+    pub under: bool,
+    /// page n gets overlay page n (the last one repeats), instead of every page
+    /// getting the first
+    pub multi: bool,
+    /// page selection, default all pages
+    pub pages: Option<&'a str>,
+    pub quiet: bool,
+}
+
+/// composite pages of `overlay` (letterhead, grid, form template) onto the pages of
+/// `input`, over or under their content. overlay pages are copied in as form
+/// XObjects and scaled to fit each page, centered, so nothing is rasterized
+pub fn overlay_pdf(
+    input: &Path,
+    overlay: &Path,
+    output: &Path,
+    opts: &OverlayOptions,
+) -> Result<()> {
+    let start = std::time::Instant::now();
+    let mut doc = pdf::load(input)?;
+    let page_ids: Vec<ObjectId> = doc.get_pages().into_values().collect();
+    let mut selected: Vec<usize> = match opts.pages {
+        Some(s) => parse_page_ranges(s, page_ids.len() as i32)?
+            .into_iter()
+            .map(|p| p as usize)
+            .collect(),
+        None => (0..page_ids.len()).collect(),
+    };
+    selected.sort_unstable();
+    selected.dedup();
+
+    // the overlay's objects move into the document; its catalog and page tree are
+    // left unreferenced and pruned on the way out
+    let mut stamp = pdf::load(overlay)?;
+    pdf::inherit_page_attributes(&mut stamp);
+    stamp.renumber_objects_with(doc.max_id + 1);
+    doc.max_id = doc.max_id.max(stamp.max_id);
+    if stamp.version.parse::<f32>().unwrap_or(0.0) > doc.version.parse().unwrap_or(0.0) {
+        doc.version = stamp.version.clone();
+    }
+    let stamp_pages: Vec<ObjectId> = stamp.get_pages().into_values().collect();
+    anyhow::ensure!(!stamp_pages.is_empty(), "{} has no pages", overlay.display());
+    doc.objects.extend(stamp.objects);
+    if !opts.quiet {
+        info!(
+            "{} {} onto {} of {} page(s) -> {}",
+            if opts.under { "Underlaying" } else { "Overlaying" },
+            overlay.display(),
+            selected.len(),
+            page_ids.len(),
+            output.display()
+        );
+    }
+
+    let mut forms: HashMap<usize, (ObjectId, f32, f32)> = HashMap::new();
+    for &i in &selected {
+        let n = if opts.multi { i.min(stamp_pages.len() - 1) } else { 0 };
+        let (form_id, form_w, form_h) = match forms.get(&n) {
+            Some(&form) => form,
+            None => {
+                let form = pdf::page_form(&mut doc, stamp_pages[n])?;
+                forms.insert(n, form);
+                form
+            }
+        };
+        let page_id = page_ids[i];
+        let (m, w, h) = pdf::display_transform(&doc, page_id);
+        let placement = multiply(&fit(form_w, form_h, w, h), &m);
+        if opts.under {
+            pdf::underlay_forms(&mut doc, page_id, &[(form_id, placement)])?;
+        } else {
+            pdf::stamp_forms(&mut doc, page_id, &[(form_id, placement)])?;
+        }
+    }
+    doc.prune_objects();
+    pdf::save(&mut doc, output)?;
+
+    if !opts.quiet {
+        info!("Done. PDF saved in {:.2}s", start.elapsed().as_secs_f64());
+    }
+    Ok(())
+}
+
+/// matrix scaling a `w` x `h` box to fit `page_w` x `page_h`, centered
+fn fit(w: f32, h: f32, page_w: f32, page_h: f32) -> [f32; 6] {
+    let scale = (page_w / w).min(page_h / h);
+    let (x, y) = ((page_w - w * scale) / 2.0, (page_h - h * scale) / 2.0);
+    [scale, 0.0, 0.0, scale, x, y]
+}
+
+/// m1 applied first, then m2
+fn multiply(m1: &[f32; 6], m2: &[f32; 6]) -> [f32; 6] {
+    [
+        m1[0] * m2[0] + m1[1] * m2[2],
+        m1[0] * m2[1] + m1[1] * m2[3],
+        m1[2] * m2[0] + m1[3] * m2[2],
+        m1[2] * m2[1] + m1[3] * m2[3],
+        m1[4] * m2[0] + m1[5] * m2[2] + m2[4],
+        m1[4] * m2[1] + m1[5] * m2[3] + m2[5],
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fit_centers_the_overlay() {
+        assert_eq!(fit(100.0, 100.0, 100.0, 100.0), [1.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+        // a square overlay on a tall page: full width, centered vertically
+        assert_eq!(fit(100.0, 100.0, 200.0, 400.0), [2.0, 0.0, 0.0, 2.0, 0.0, 100.0]);
+        let turned = multiply(&fit(50.0, 50.0, 100.0, 100.0), &[0.0, 1.0, -1.0, 0.0, 100.0, 0.0]);
+        assert_eq!(turned, [0.0, 2.0, -2.0, 0.0, 100.0, 0.0]);
+    }
+}
@@ -0,0 +1,130 @@
+use anyhow::Result;
+use lopdf::ObjectId;
+use std::path::Path;
+use tracing::info;
+
+use crate::parse::{parse_page_ranges, PageMove};
+use crate::pdf;
+
+/// page edits, applied in order: keep, then delete (both in original page
+/// numbers), then moves (in positions after the previous moves)
+pub struct PageEdits<'a> {
+    pub keep: Option<&'a str>,
+    pub delete: Option<&'a str>,
+    pub moves: &'a [PageMove],
+}
+
+/// write a PDF with pages extracted, deleted, or reordered; only the page tree is
+/// rewritten, content streams are copied as they are
+pub fn edit_pages(input: &Path, output: &Path, edits: &PageEdits, quiet: bool) -> Result<()> {
+    let start = std::time::Instant::now();
+    let mut doc = pdf::load(input)?;
+    let pages: Vec<ObjectId> = doc.get_pages().into_values().collect();
+    let order = page_order(pages.len(), edits)?;
+    if !quiet {
+        info!(
+            "Writing {} of {} page(s) -> {}",
+            order.len(),
+            pages.len(),
+            output.display()
+        );
+    }
+
+    let kept: Vec<ObjectId> = order.iter().map(|&i| pages[i]).collect();
+    pdf::set_page_order(&mut doc, &kept)?;
+    pdf::save(&mut doc, output)?;
+
+    if !quiet {
+        info!("Done. PDF saved in {:.2}s", start.elapsed().as_secs_f64());
+    }
+    Ok(())
+}
+
+/// 0-indexed original pages in output order
+fn page_order(num_pages: usize, edits: &PageEdits) -> Result<Vec<usize>> {
+    let mut order: Vec<usize> = match edits.keep {
+        Some(keep) => {
+            let mut seen = vec![false; num_pages];
+            let mut order = Vec::new();
+            for p in parse_page_ranges(keep, num_pages as i32)? {
+                let p = p as usize;
+                // a page object can only sit in the page tree once
+                anyhow::ensure!(!seen[p], "Page {} is kept more than once", p + 1);
+                seen[p] = true;
+                order.push(p);
+            }
+            order
+        }
+        None => (0..num_pages).collect(),
+    };
+    if let Some(delete) = edits.delete {
+        let delete = parse_page_ranges(delete, num_pages as i32)?;
+        order.retain(|&p| !delete.contains(&(p as i32)));
+    }
+    anyhow::ensure!(!order.is_empty(), "No pages left to write");
+
+    for m in edits.moves {
+        anyhow::ensure!(
+            m.from <= order.len() && m.to <= order.len(),
+            "Page move {}:{} out of bounds (output has {} pages)",
+            m.from,
+            m.to,
+            order.len()
+        );
+        let page = order.remove(m.from - 1);
+        order.insert(m.to - 1, page);
+    }
+    Ok(order)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(
+        num_pages: usize,
+        keep: Option<&str>,
+        delete: Option<&str>,
+        moves: &[&str],
+    ) -> Vec<usize> {
+        let moves: Vec<PageMove> = moves.iter().map(|m| m.parse().unwrap()).collect();
+        let edits = PageEdits {
+            keep,
+            delete,
+            moves: &moves,
+        };
+        page_order(num_pages, &edits).unwrap()
+    }
+
+    #[test]
+    fn keep_delete_move() {
+        assert_eq!(order(10, Some("1,5-9"), None, &[]), vec![0, 4, 5, 6, 7, 8]);
+        assert_eq!(order(4, Some("3,1,2"), None, &[]), vec![2, 0, 1]);
+        assert_eq!(order(5, None, Some("2,4"), &[]), vec![0, 2, 4]);
+        assert_eq!(order(10, Some("1,5-9"), None, &["3:1"]), vec![5, 0, 4, 6, 7, 8]);
+        assert_eq!(order(3, None, None, &["1:3", "1:2"]), vec![2, 1, 0]);
+    }
+
+    #[test]
+    fn invalid_edits() {
+        let no_moves = PageEdits {
+            keep: Some("1,2,1"),
+            delete: None,
+            moves: &[],
+        };
+        assert!(page_order(3, &no_moves).is_err());
+        let all_deleted = PageEdits {
+            keep: None,
+            delete: Some("1-3"),
+            moves: &[],
+        };
+        assert!(page_order(3, &all_deleted).is_err());
+        let moves = ["4:1".parse().unwrap()];
+        let out_of_bounds = PageEdits {
+            keep: None,
+            delete: None,
+            moves: &moves,
+        };
+        assert!(page_order(3, &out_of_bounds).is_err());
+    }
+}
@@ -1,6 +1,7 @@
 use anyhow::{Context, Result};
 use clap::ValueEnum;
-use std::path::PathBuf;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Clone, Copy, ValueEnum)]
 pub enum ImageFormat {
@@ -8,6 +9,30 @@ pub enum ImageFormat {
     Jpg,
 }
 
+/// output format for `convert`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ConvertFormat {
+    Png,
+    #[value(alias = "jpeg")]
+    Jpg,
+    #[value(alias = "tif")]
+    Tiff,
+    Bmp,
+    Gif,
+}
+
+impl ConvertFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ConvertFormat::Png => "png",
+            ConvertFormat::Jpg => "jpg",
+            ConvertFormat::Tiff => "tiff",
+            ConvertFormat::Bmp => "bmp",
+            ConvertFormat::Gif => "gif",
+        }
+    }
+}
+
 /// PNG compression level
 #[derive(Debug, Clone, Copy, Default, ValueEnum)]
 pub enum PngCompression {
@@ -16,6 +41,9 @@ pub enum PngCompression {
     Fast,
     /// smaller files, slower encoding
     Small,
+    /// smallest files, several times slower: an oxipng pass searching filters
+    /// at its highest deflate level
+    Max,
 }
 
 #[derive(Debug, Clone, Copy, ValueEnum)]
@@ -55,6 +83,638 @@ impl std::fmt::Display for Orientation {
     }
 }
 
+/// how hard merge deflates the images it decodes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Deflate {
+    /// the quick level (zlib-ng with the `zlib-ng` feature): fastest, larger files
+    #[default]
+    Fast,
+    /// zlib's default level: smaller files, several times slower
+    Standard,
+}
+
+/// what to do with ICC profiles embedded in input images
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+pub enum IccMode {
+    /// embed them as ICCBased color spaces
+    #[default]
+    Keep,
+    /// drop them, using device color spaces
+    Strip,
+    /// replace RGB profiles with sRGB (others are dropped)
+    Srgb,
+}
+
+/// what `blank` prints on its pages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum PaperTemplate {
+    /// nothing
+    #[default]
+    Blank,
+    /// squares
+    Grid,
+    /// dots where grid lines would cross
+    Dot,
+    /// horizontal rules
+    Lined,
+}
+
+/// how a viewer arranges pages when the PDF is opened
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum PageLayout {
+    /// one page at a time
+    Single,
+    /// one continuous column
+    Continuous,
+    /// two pages side by side, odd pages on the left
+    TwoUp,
+    /// two continuous columns, odd pages on the left
+    TwoUpContinuous,
+}
+
+impl PageLayout {
+    /// value for the catalog's /PageLayout entry
+    pub fn pdf_name(self) -> &'static str {
+        match self {
+            PageLayout::Single => "SinglePage",
+            PageLayout::Continuous => "OneColumn",
+            PageLayout::TwoUp => "TwoPageLeft",
+            PageLayout::TwoUpContinuous => "TwoColumnLeft",
+        }
+    }
+}
+
+/// which panel a viewer shows when the PDF is opened
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum PageMode {
+    /// no side panel
+    None,
+    /// bookmarks panel
+    Outlines,
+    /// page thumbnails panel
+    Thumbs,
+    /// full-screen presentation
+    FullScreen,
+}
+
+impl PageMode {
+    /// value for the catalog's /PageMode entry
+    pub fn pdf_name(self) -> &'static str {
+        match self {
+            PageMode::None => "UseNone",
+            PageMode::Outlines => "UseOutlines",
+            PageMode::Thumbs => "UseThumbs",
+            PageMode::FullScreen => "FullScreen",
+        }
+    }
+}
+
+/// order pages are read in, and so which side facing pages go on
+#[derive(Debug, Clone, Copy, Default, PartialEq, ValueEnum)]
+pub enum Direction {
+    /// left to right
+    #[default]
+    Ltr,
+    /// right to left (manga, Arabic, Hebrew)
+    Rtl,
+}
+
+/// how progress is shown on stderr
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ProgressFormat {
+    /// log lines for people
+    #[default]
+    Human,
+    /// one JSON event per line, for programs: start, page_done, page_failed,
+    /// finish, and log for warnings and errors
+    Json,
+}
+
+/// how `--stats` prints its report on stderr
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum StatsFormat {
+    /// totals, the slowest pages and a line per page
+    #[default]
+    Text,
+    /// the same as one JSON object
+    Json,
+}
+
+/// how much `--log-level` logs, least to most
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    /// progress and summaries, as printed without --log-level
+    #[default]
+    Info,
+    /// each page's size, color space and compression, and the worker on it (-v)
+    Debug,
+    /// timings of each page's stages too: render, encode and write (-vv)
+    Trace,
+}
+
+/// where the image goes on a fixed-size page
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Position {
+    /// fraction of the free space left of / above the image (0.5, 0.5 = centered)
+    Anchor { x: f32, y: f32 },
+    /// offset in points from the top-left corner of the page
+    Offset { x: f32, y: f32 },
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Position::Anchor { x: 0.5, y: 0.5 }
+    }
+}
+
+impl Position {
+    /// bottom-left corner of a `w` x `h` image on a `page_w` x `page_h` page, in PDF coordinates
+    pub fn place(self, page_w: f32, page_h: f32, w: f32, h: f32) -> (f32, f32) {
+        match self {
+            Position::Anchor { x, y } => ((page_w - w) * x, (page_h - h) * (1.0 - y)),
+            Position::Offset { x, y } => (x, page_h - y - h),
+        }
+    }
+}
+
+impl std::str::FromStr for Position {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let anchor = match s.to_ascii_lowercase().as_str() {
+            "top-left" => Some((0.0, 0.0)),
+            "top" => Some((0.5, 0.0)),
+            "top-right" => Some((1.0, 0.0)),
+            "left" => Some((0.0, 0.5)),
+            "center" => Some((0.5, 0.5)),
+            "right" => Some((1.0, 0.5)),
+            "bottom-left" => Some((0.0, 1.0)),
+            "bottom" => Some((0.5, 1.0)),
+            "bottom-right" => Some((1.0, 1.0)),
+            _ => None,
+        };
+        if let Some((x, y)) = anchor {
+            return Ok(Position::Anchor { x, y });
+        }
+        let err = || format!("invalid position '{}' (expected e.g. top-left, center, or x,y)", s);
+        let (x, y) = s.split_once(',').ok_or_else(err)?;
+        let x: f32 = x.trim().parse().map_err(|_| err())?;
+        let y: f32 = y.trim().parse().map_err(|_| err())?;
+        Ok(Position::Offset { x, y })
+    }
+}
+
+/// scale factor given as a percentage ("50%" or "50")
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Scale(pub f32);
+
+impl std::str::FromStr for Scale {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let percent: f32 = s
+            .trim()
+            .trim_end_matches('%')
+            .parse()
+            .map_err(|_| format!("invalid scale '{}' (expected e.g. 100%)", s))?;
+        if !(percent > 0.0 && percent.is_finite()) {
+            return Err(format!("scale must be positive, got '{}'", s));
+        }
+        Ok(Scale(percent / 100.0))
+    }
+}
+
+/// byte count given with an optional binary unit ("50MB", "512k", "1048576")
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteSize(pub u64);
+
+impl std::str::FromStr for ByteSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let err = || format!("invalid size '{}' (expected e.g. 50MB)", s);
+        let trimmed = s.trim();
+        let split = trimmed
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(trimmed.len());
+        let (number, unit) = trimmed.split_at(split);
+        let number: f64 = number.parse().map_err(|_| err())?;
+        let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+            "" | "b" => 1,
+            "k" | "kb" | "kib" => 1 << 10,
+            "m" | "mb" | "mib" => 1 << 20,
+            "g" | "gb" | "gib" => 1 << 30,
+            _ => return Err(err()),
+        };
+        let bytes = (number * multiplier as f64) as u64;
+        if bytes == 0 {
+            return Err(format!("size must be positive, got '{}'", s));
+        }
+        Ok(ByteSize(bytes))
+    }
+}
+
+impl std::fmt::Display for ByteSize {
+    /// human-readable size with binary units, e.g. "1.5 MB"
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        const UNITS: [&str; 4] = ["bytes", "KB", "MB", "GB"];
+        let mut size = self.0 as f64;
+        let mut unit = 0;
+        while size >= 1024.0 && unit < UNITS.len() - 1 {
+            size /= 1024.0;
+            unit += 1;
+        }
+        if unit == 0 {
+            write!(f, "{} {}", self.0, UNITS[0])
+        } else {
+            write!(f, "{:.1} {}", size, UNITS[unit])
+        }
+    }
+}
+
+/// RGB color given as "#rrggbb", "#rgb", or a basic color name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub fn is_gray(self) -> bool {
+        self.r == self.g && self.g == self.b
+    }
+}
+
+impl std::str::FromStr for Color {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let named = match s.to_ascii_lowercase().as_str() {
+            "white" => Some((255, 255, 255)),
+            "black" => Some((0, 0, 0)),
+            "red" => Some((255, 0, 0)),
+            "green" => Some((0, 128, 0)),
+            "blue" => Some((0, 0, 255)),
+            "gray" | "grey" => Some((128, 128, 128)),
+            _ => None,
+        };
+        if let Some((r, g, b)) = named {
+            return Ok(Color { r, g, b });
+        }
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        let digits: Vec<u8> = hex
+            .chars()
+            .map(|c| c.to_digit(16).map(|d| d as u8))
+            .collect::<Option<_>>()
+            .ok_or_else(|| format!("invalid color '{}' (expected #rrggbb)", s))?;
+        match digits.as_slice() {
+            [r, g, b] => Ok(Color { r: r * 17, g: g * 17, b: b * 17 }),
+            [r1, r0, g1, g0, b1, b0] => Ok(Color {
+                r: r1 * 16 + r0,
+                g: g1 * 16 + g0,
+                b: b1 * 16 + b0,
+            }),
+            _ => Err(format!("invalid color '{}' (expected #rrggbb)", s)),
+        }
+    }
+}
+
+/// clockwise page rotation in degrees, a multiple of 90 ("90", "-90", "180")
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rotation(pub i64);
+
+impl std::str::FromStr for Rotation {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let degrees: i64 = s
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid rotation '{}' (expected 90, 180, or 270)", s))?;
+        if degrees % 90 != 0 {
+            return Err(format!("rotation must be a multiple of 90, got '{}'", s));
+        }
+        Ok(Rotation(degrees))
+    }
+}
+
+/// page move given as "FROM:TO", both 1-based page positions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageMove {
+    pub from: usize,
+    pub to: usize,
+}
+
+impl std::str::FromStr for PageMove {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let err = || format!("invalid page move '{}' (expected FROM:TO, e.g. 3:1)", s);
+        let (from, to) = s.split_once(':').ok_or_else(err)?;
+        let from: usize = from.trim().parse().map_err(|_| err())?;
+        let to: usize = to.trim().parse().map_err(|_| err())?;
+        if from == 0 || to == 0 {
+            return Err(format!("page numbers start at 1, got '{}'", s));
+        }
+        Ok(PageMove { from, to })
+    }
+}
+
+/// standard document info keys, matched case-insensitively in `--set`
+pub const INFO_KEYS: [&str; 8] = [
+    "Title",
+    "Author",
+    "Subject",
+    "Keywords",
+    "Creator",
+    "Producer",
+    "CreationDate",
+    "ModDate",
+];
+
+/// metadata edit given as "KEY=VALUE"; an empty value removes the field
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetaField {
+    pub key: String,
+    pub value: String,
+}
+
+impl std::str::FromStr for MetaField {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (key, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("invalid field '{}' (expected KEY=VALUE)", s))?;
+        let key = key.trim();
+        // the key becomes a PDF name
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("invalid metadata key '{}'", key));
+        }
+        let key = INFO_KEYS
+            .iter()
+            .find(|k| k.eq_ignore_ascii_case(key))
+            .map_or(key, |k| *k);
+        Ok(MetaField {
+            key: key.to_string(),
+            value: value.to_string(),
+        })
+    }
+}
+
+/// link rewrite given as "FROM=TO": URIs starting with FROM get TO instead
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UriRewrite {
+    pub from: String,
+    pub to: String,
+}
+
+impl std::str::FromStr for UriRewrite {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (from, to) = s
+            .split_once('=')
+            .ok_or_else(|| format!("invalid rewrite '{}' (expected FROM=TO)", s))?;
+        if from.is_empty() {
+            return Err(format!("invalid rewrite '{}' (nothing to replace)", s));
+        }
+        Ok(UriRewrite {
+            from: from.to_string(),
+            to: to.to_string(),
+        })
+    }
+}
+
+/// length in points, or with a unit: "12pt", "10mm", "1.5cm", "0.5in"
+fn parse_length(s: &str) -> Option<f32> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| c.is_ascii_alphabetic())
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: f32 = number.trim().parse().ok()?;
+    let scale = match unit.to_ascii_lowercase().as_str() {
+        "" | "pt" => 1.0,
+        "mm" => 72.0 / 25.4,
+        "cm" => 72.0 / 2.54,
+        "in" => 72.0,
+        _ => return None,
+    };
+    let length = number * scale;
+    length.is_finite().then_some(length)
+}
+
+/// a distance that can't be negative, in points or with a unit: "20", "6mm"
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Length(pub f32);
+
+impl std::str::FromStr for Length {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match parse_length(s) {
+            Some(length) if length >= 0.0 => Ok(Length(length)),
+            Some(_) => Err(format!("length can't be negative, got '{}'", s)),
+            None => Err(format!("invalid length '{}' (expected e.g. 20, 6mm or 0.25in)", s)),
+        }
+    }
+}
+
+/// page box corners "x0,y0,x1,y1" in PDF coordinates (origin bottom left),
+/// normalized so x0 < x1 and y0 < y1
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PageRect(pub [f32; 4]);
+
+impl std::str::FromStr for PageRect {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let err = || format!("invalid box '{}' (expected x0,y0,x1,y1, e.g. 0,0,595,842)", s);
+        let v: Vec<f32> = s.split(',').map(parse_length).collect::<Option<_>>().ok_or_else(err)?;
+        let [x0, y0, x1, y1] = v[..] else {
+            return Err(err());
+        };
+        if x0 == x1 || y0 == y1 {
+            return Err(format!("box '{}' is empty", s));
+        }
+        Ok(PageRect([x0.min(x1), y0.min(y1), x0.max(x1), y0.max(y1)]))
+    }
+}
+
+/// an area to black out, "PAGES:x0,y0,x1,y1": a page selection like "1,3-5"
+/// and a box in PDF coordinates as for `PageRect`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RedactRegion {
+    pub pages: String,
+    pub rect: PageRect,
+}
+
+impl std::str::FromStr for RedactRegion {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let Some((pages, rect)) = s.split_once(':') else {
+            return Err(format!("invalid region '{}' (expected e.g. 2:72,700,300,720)", s));
+        };
+        if pages.trim().is_empty() {
+            return Err(format!("region '{}' has no pages", s));
+        }
+        Ok(RedactRegion {
+            pages: pages.trim().to_string(),
+            rect: rect.parse()?,
+        })
+    }
+}
+
+/// amounts to trim from each side of a page as it's shown, CSS-style: "10",
+/// "10,20" (top/bottom, left/right), or "10,20,10,20" (top, right, bottom, left).
+/// negative values grow the page
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Margins {
+    pub top: f32,
+    pub right: f32,
+    pub bottom: f32,
+    pub left: f32,
+}
+
+impl std::str::FromStr for Margins {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let err = || format!("invalid margins '{}' (expected e.g. 10mm or 10,20,10,20)", s);
+        let v: Vec<f32> = s.split(',').map(parse_length).collect::<Option<_>>().ok_or_else(err)?;
+        let (top, right, bottom, left) = match v[..] {
+            [all] => (all, all, all, all),
+            [vertical, horizontal] => (vertical, horizontal, vertical, horizontal),
+            [top, right, bottom, left] => (top, right, bottom, left),
+            _ => return Err(err()),
+        };
+        Ok(Margins {
+            top,
+            right,
+            bottom,
+            left,
+        })
+    }
+}
+
+/// pixel size "256" (square) or "320x240"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PixelSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl std::str::FromStr for PixelSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let err = || format!("invalid size '{}' (expected e.g. 256 or 320x240)", s);
+        let dim = |v: &str| v.trim().parse::<u32>().ok().filter(|&v| (1..=10_000).contains(&v));
+        let (width, height) = match s.split_once(['x', 'X']) {
+            Some((w, h)) => (dim(w).ok_or_else(err)?, dim(h).ok_or_else(err)?),
+            None => {
+                let side = dim(s).ok_or_else(err)?;
+                (side, side)
+            }
+        };
+        Ok(PixelSize { width, height })
+    }
+}
+
+/// what `grid` writes under each image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Caption {
+    /// file name
+    #[default]
+    Name,
+    /// position in the input list, from 1
+    Number,
+    /// no captions
+    None,
+}
+
+/// which page boxes `crop` sets
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BoxTarget {
+    /// CropBox: the area viewers show and print
+    Crop,
+    /// TrimBox: the finished page size after trimming
+    Trim,
+    /// both CropBox and TrimBox
+    Both,
+}
+
+/// standard typefaces for stamped text; none of them needs embedding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum StampFont {
+    Helvetica,
+    HelveticaBold,
+    /// monospaced, so numbers line up from page to page
+    Courier,
+}
+
+impl StampFont {
+    pub fn base_font(self) -> &'static [u8] {
+        match self {
+            StampFont::Helvetica => b"Helvetica",
+            StampFont::HelveticaBold => b"Helvetica-Bold",
+            StampFont::Courier => b"Courier",
+        }
+    }
+}
+
+/// file format for bookmarks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutlineFormat {
+    Json,
+    Toml,
+}
+
+impl OutlineFormat {
+    /// format named by a file's extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "json" => Some(OutlineFormat::Json),
+            "toml" => Some(OutlineFormat::Toml),
+            _ => None,
+        }
+    }
+}
+
+/// PDF/A conformance level checked by `validate`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PdfaLevel {
+    /// PDF/A-1b (ISO 19005-1): PDF 1.4, no transparency or object streams
+    #[value(name = "1b")]
+    A1b,
+    /// PDF/A-2b (ISO 19005-2): PDF 1.7 features allowed
+    #[value(name = "2b")]
+    A2b,
+}
+
+impl PdfaLevel {
+    /// the pdfaid:part the XMP metadata has to declare
+    pub fn part(self) -> u8 {
+        match self {
+            PdfaLevel::A1b => 1,
+            PdfaLevel::A2b => 2,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PdfaLevel::A1b => "PDF/A-1b",
+            PdfaLevel::A2b => "PDF/A-2b",
+        }
+    }
+}
+
 /// parse page range string like "1,3-5,10" into 0-indexed page indices
 pub fn parse_page_ranges(s: &str, num_pages: i32) -> Result<Vec<i32>> {
     let mut pages = Vec::new();
@@ -97,9 +757,79 @@ fn is_glob_pattern(s: &str) -> bool {
     s.contains('*') || s.contains('?') || s.contains('[')
 }
 
-/// expand dirs and glob patterns in input list into sorted image files
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "tiff", "tif", "bmp", "gif"];
+
+/// true if the extension (any case) is one merge picks up from directories
+pub fn is_image_extension(ext: &str) -> bool {
+    IMAGE_EXTENSIONS.contains(&ext.to_lowercase().as_str())
+}
+
+/// input image format, identified by content rather than file name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    Png,
+    Jpeg,
+    Tiff,
+    Bmp,
+    Gif,
+}
+
+impl InputFormat {
+    /// identify an image from its magic bytes
+    pub fn sniff(data: &[u8]) -> Option<Self> {
+        if data.starts_with(&[137, 80, 78, 71, 13, 10, 26, 10]) {
+            Some(InputFormat::Png)
+        } else if data.starts_with(&[0xFF, 0xD8]) {
+            Some(InputFormat::Jpeg)
+        } else if data.starts_with(b"II*\0") || data.starts_with(b"MM\0*") {
+            Some(InputFormat::Tiff)
+        } else if data.starts_with(b"BM") {
+            Some(InputFormat::Bmp)
+        } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+            Some(InputFormat::Gif)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            InputFormat::Png => "PNG",
+            InputFormat::Jpeg => "JPEG",
+            InputFormat::Tiff => "TIFF",
+            InputFormat::Bmp => "BMP",
+            InputFormat::Gif => "GIF",
+        }
+    }
+
+    /// whether a file extension (any case) conventionally names this format
+    pub fn matches_extension(self, ext: &str) -> bool {
+        let ext = ext.to_lowercase();
+        match self {
+            InputFormat::Png => ext == "png",
+            InputFormat::Jpeg => ext == "jpg" || ext == "jpeg",
+            InputFormat::Tiff => ext == "tif" || ext == "tiff",
+            InputFormat::Bmp => ext == "bmp",
+            InputFormat::Gif => ext == "gif",
+        }
+    }
+}
+
+/// true if the file starts like a supported image, whatever its name
+fn sniff_image_file(path: &std::path::Path) -> bool {
+    use std::io::Read;
+
+    let mut header = [0u8; 8];
+    let Ok(mut file) = std::fs::File::open(path) else {
+        return false;
+    };
+    let n = file.read(&mut header).unwrap_or(0);
+    InputFormat::sniff(&header[..n]).is_some()
+}
+
+/// expand dirs and glob patterns in input list into sorted image files;
+/// directory entries with other extensions are kept if their content is an image
 pub fn expand_image_paths(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
-    const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "tiff", "tif", "bmp", "gif"];
     let mut result = Vec::new();
     for path in paths {
         let path_str = path.to_string_lossy();
@@ -125,7 +855,8 @@ pub fn expand_image_paths(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
                 .filter(|p| {
                     p.extension()
                         .and_then(|e| e.to_str())
-                        .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+                        .is_some_and(is_image_extension)
+                        || (p.is_file() && sniff_image_file(p))
                 })
                 .collect();
             entries.sort();
@@ -142,19 +873,101 @@ pub fn expand_image_paths(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
     Ok(result)
 }
 
+/// collate two page sequences (e.g. duplex fronts and backs) into f1, b1, f2, b2, ...;
+/// `reverse_second` handles back stacks scanned last-page-first
+pub fn interleave_paths(
+    first: Vec<PathBuf>,
+    mut second: Vec<PathBuf>,
+    reverse_second: bool,
+) -> Result<Vec<PathBuf>> {
+    anyhow::ensure!(
+        first.len() == second.len(),
+        "Cannot interleave {} front page(s) with {} back page(s)",
+        first.len(),
+        second.len()
+    );
+    if reverse_second {
+        second.reverse();
+    }
+    Ok(first
+        .into_iter()
+        .zip(second)
+        .flat_map(|(a, b)| [a, b])
+        .collect())
+}
+
 pub struct JpegInfo {
     pub width: u32,
     pub height: u32,
     pub components: u8,
     /// APP14 Adobe color transform: None = no Adobe marker, Some(0) = CMYK, Some(2) = YCCK
     pub adobe_color_transform: Option<u8>,
-    /// DPI from JFIF APP0 marker (if present and units==1 for DPI)
-    pub dpi: Option<u32>,
+    /// horizontal and vertical DPI from JFIF APP0 marker (if present with units),
+    /// else from the EXIF resolution tags in APP1
+    pub dpi: Option<(u32, u32)>,
     /// ICC profile data reassembled from APP2 markers
     pub icc_profile: Option<Vec<u8>>,
 }
 
-/// parse JPEG file's SOF, APP0, APP2, and APP14 markers
+/// (marker, body start, body end) of each JPEG marker segment before the scan data
+fn jpeg_segments(data: &[u8]) -> Vec<(u8, usize, usize)> {
+    let mut segments = Vec::new();
+    let mut pos = 2;
+    while pos + 4 <= data.len() && data[pos] == 0xFF {
+        let marker = data[pos + 1];
+        if marker == 0xFF {
+            pos += 1;
+            continue;
+        }
+        if marker == 0x00 || (0xD0..=0xD9).contains(&marker) {
+            pos += 2;
+            continue;
+        }
+        let len = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        if marker == 0xDA || len < 2 || pos + 2 + len > data.len() {
+            break;
+        }
+        segments.push((marker, pos + 4, pos + 2 + len));
+        pos += 2 + len;
+    }
+    segments
+}
+
+/// byte ranges of a JPEG around its APP1 segments (EXIF and XMP metadata): the
+/// JPEG without them, to be written out piece by piece rather than copied
+pub fn jpeg_ranges_without_app1(data: &[u8]) -> Vec<Range<usize>> {
+    jpeg_ranges_without(data, |marker, _| marker == 0xE1)
+}
+
+/// byte ranges of a JPEG around the APPn segments a decoder can do without:
+/// all but the JFIF header, the Adobe color transform and, with `keep_icc`, the
+/// ICC profile. EXIF and JFXX thumbnails, XMP, MPF and maker notes all go
+pub fn jpeg_ranges_without_metadata(data: &[u8], keep_icc: bool) -> Vec<Range<usize>> {
+    jpeg_ranges_without(data, |marker, body| match marker {
+        0xE0 => !body.starts_with(b"JFIF\0"),
+        0xE2 => !(keep_icc && body.starts_with(b"ICC_PROFILE\0")),
+        0xEE => !body.starts_with(b"Adobe"),
+        0xE1..=0xEF => true,
+        _ => false,
+    })
+}
+
+/// byte ranges of a JPEG around the segments `drop` picks by marker and body
+fn jpeg_ranges_without(data: &[u8], drop: impl Fn(u8, &[u8]) -> bool) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut from = 0;
+    for (marker, start, end) in jpeg_segments(data) {
+        if drop(marker, &data[start..end]) {
+            // segment header (FF E1 + length) sits just before the body
+            ranges.push(from..start - 4);
+            from = end;
+        }
+    }
+    ranges.push(from..data.len());
+    ranges
+}
+
+/// parse JPEG file's SOF, APP0, APP1, APP2, and APP14 markers
 pub fn parse_jpeg_header(data: &[u8]) -> Result<JpegInfo> {
     anyhow::ensure!(
         data.len() >= 2 && data[0] == 0xFF && data[1] == 0xD8,
@@ -163,7 +976,8 @@ pub fn parse_jpeg_header(data: &[u8]) -> Result<JpegInfo> {
     let mut pos = 2;
     let mut sof: Option<(u32, u32, u8)> = None;
     let mut adobe_color_transform: Option<u8> = None;
-    let mut dpi: Option<u32> = None;
+    let mut dpi: Option<(u32, u32)> = None;
+    let mut exif_dpi: Option<(u32, u32)> = None;
     let mut icc_chunks: Vec<(u8, u8, Vec<u8>)> = Vec::new(); // (seq, total, data)
 
     while pos + 4 < data.len() {
@@ -205,15 +1019,24 @@ pub fn parse_jpeg_header(data: &[u8]) -> Result<JpegInfo> {
                 let units = seg[7];
                 let x_density = u16::from_be_bytes([seg[8], seg[9]]) as u32;
                 let y_density = u16::from_be_bytes([seg[10], seg[11]]) as u32;
-                if units == 1 && x_density == y_density && x_density > 0 {
-                    dpi = Some(x_density);
-                } else if units == 2 && x_density == y_density && x_density > 0 {
+                if units == 1 && x_density > 0 && y_density > 0 {
+                    dpi = Some((x_density, y_density));
+                } else if units == 2 && x_density > 0 && y_density > 0 {
                     // dots per cm -> DPI
-                    dpi = Some((x_density as f64 * 2.54) as u32);
+                    let to_dpi = |d: u32| (d as f64 * 2.54).round() as u32;
+                    dpi = Some((to_dpi(x_density), to_dpi(y_density)));
                 }
             }
         }
 
+        // APP1 (EXIF) - DPI, all most camera JPEGs have
+        if marker == 0xE1 && len >= 8 {
+            let seg = &data[pos + 4..pos + 2 + len];
+            if seg.starts_with(b"Exif\0\0") && exif_dpi.is_none() {
+                exif_dpi = parse_tiff_resolution(&seg[6..]);
+            }
+        }
+
         // APP2 - ICC profile chunks (tag: "ICC_PROFILE\0")
         if marker == 0xE2 && len >= 16 {
             let seg = &data[pos + 4..pos + 2 + len];
@@ -254,7 +1077,7 @@ pub fn parse_jpeg_header(data: &[u8]) -> Result<JpegInfo> {
         height,
         components,
         adobe_color_transform,
-        dpi,
+        dpi: dpi.or(exif_dpi),
         icc_profile,
     })
 }
@@ -267,11 +1090,34 @@ pub struct PngInfo {
     pub interlace: u8,
     pub idat_data: Vec<u8>,
     pub plte_data: Vec<u8>,
-    pub has_trns: bool,
-    /// DPI from pHYs chunk (if units == 1, meters -> DPI)
-    pub dpi: Option<u32>,
+    /// raw tRNS chunk: gray/RGB color key or per-palette-entry alpha
+    pub trns: Option<Vec<u8>>,
+    /// horizontal and vertical DPI from pHYs chunk (if units == 1, meters -> DPI)
+    pub dpi: Option<(u32, u32)>,
     /// ICC profile from iCCP chunk (decompressed)
     pub icc_profile: Option<Vec<u8>>,
+    /// sRGB chunk present: the samples are sRGB
+    pub srgb: bool,
+    /// file gamma from gAMA chunk, times 100000 (45455 for 1/2.2)
+    pub gamma: Option<u32>,
+}
+
+/// frames of an animated PNG, from the acTL chunk before its image data; None
+/// for a still PNG
+pub fn apng_frame_count(data: &[u8]) -> Option<u32> {
+    let mut pos = 8;
+    while pos + 12 <= data.len() {
+        let len = u32::from_be_bytes(data[pos..pos + 4].try_into().ok()?) as usize;
+        match &data[pos + 4..pos + 8] {
+            b"acTL" => {
+                let frames = data.get(pos + 8..pos + 12)?;
+                return Some(u32::from_be_bytes(frames.try_into().ok()?));
+            }
+            b"IDAT" | b"IEND" => return None,
+            _ => pos += 12 + len,
+        }
+    }
+    None
 }
 
 /// parse a PNG file to extract IHDR info and concatenated IDAT chunk data
@@ -290,9 +1136,11 @@ pub fn parse_png_header(data: &[u8]) -> Result<PngInfo> {
     let mut interlace = 0u8;
     let mut idat_data = Vec::new();
     let mut plte_data = Vec::new();
-    let mut has_trns = false;
-    let mut dpi: Option<u32> = None;
+    let mut trns: Option<Vec<u8>> = None;
+    let mut dpi: Option<(u32, u32)> = None;
     let mut icc_profile: Option<Vec<u8>> = None;
+    let mut srgb = false;
+    let mut gamma: Option<u32> = None;
     let mut got_ihdr = false;
 
     while pos + 8 <= data.len() {
@@ -315,15 +1163,16 @@ pub fn parse_png_header(data: &[u8]) -> Result<PngInfo> {
         } else if chunk_type == b"PLTE" {
             plte_data.extend_from_slice(&data[chunk_data_start..chunk_data_start + chunk_len]);
         } else if chunk_type == b"tRNS" {
-            has_trns = true;
+            trns = Some(data[chunk_data_start..chunk_data_start + chunk_len].to_vec());
         } else if chunk_type == b"pHYs" && chunk_len >= 9 {
             let d = &data[chunk_data_start..];
             let x_ppu = u32::from_be_bytes([d[0], d[1], d[2], d[3]]);
             let y_ppu = u32::from_be_bytes([d[4], d[5], d[6], d[7]]);
             let unit = d[8];
-            if unit == 1 && x_ppu == y_ppu && x_ppu > 0 {
+            if unit == 1 && x_ppu > 0 && y_ppu > 0 {
                 // unit 1 = meter, convert to DPI
-                dpi = Some((x_ppu as f64 / 39.3701).round() as u32);
+                let to_dpi = |ppu: u32| (ppu as f64 / 39.3701).round() as u32;
+                dpi = Some((to_dpi(x_ppu), to_dpi(y_ppu)));
             }
         } else if chunk_type == b"iCCP" && chunk_len > 2 {
             let d = &data[chunk_data_start..chunk_data_start + chunk_len];
@@ -337,6 +1186,11 @@ pub fn parse_png_header(data: &[u8]) -> Result<PngInfo> {
                     }
                 }
             }
+        } else if chunk_type == b"sRGB" {
+            srgb = true;
+        } else if chunk_type == b"gAMA" && chunk_len >= 4 {
+            let d = &data[chunk_data_start..];
+            gamma = Some(u32::from_be_bytes([d[0], d[1], d[2], d[3]])).filter(|&g| g > 0);
         } else if chunk_type == b"IDAT" {
             idat_data.extend_from_slice(&data[chunk_data_start..chunk_data_start + chunk_len]);
         } else if chunk_type == b"IEND" {
@@ -357,9 +1211,305 @@ pub fn parse_png_header(data: &[u8]) -> Result<PngInfo> {
         interlace,
         idat_data,
         plte_data,
-        has_trns,
+        trns,
         dpi,
         icc_profile,
+        srgb,
+        gamma,
+    })
+}
+
+/// an image file directory of a TIFF file (or EXIF block), for reading individual tags
+struct TiffIfd<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+    /// byte offset of the first 12-byte entry
+    entries: usize,
+    count: usize,
+}
+
+impl<'a> TiffIfd<'a> {
+    fn parse(data: &'a [u8]) -> Option<Self> {
+        let big_endian = match data.get(..4)? {
+            [b'I', b'I', 42, 0] => false,
+            [b'M', b'M', 0, 42] => true,
+            _ => return None,
+        };
+        let mut ifd = TiffIfd {
+            data,
+            big_endian,
+            entries: 0,
+            count: 0,
+        };
+        let offset = ifd.u32_at(4)? as usize;
+        ifd.count = ifd.u16_at(offset)? as usize;
+        ifd.entries = offset + 2;
+        Some(ifd)
+    }
+
+    /// IFD that a LONG/IFD pointer tag (e.g. the EXIF or GPS sub-IFD) refers to
+    fn sub_ifd(&self, tag: u16) -> Option<Self> {
+        let (_, _, entry) = self.find(tag)?;
+        let offset = self.u32_at(entry + 8)? as usize;
+        Some(TiffIfd {
+            data: self.data,
+            big_endian: self.big_endian,
+            entries: offset + 2,
+            count: self.u16_at(offset)? as usize,
+        })
+    }
+
+    fn u16_at(&self, pos: usize) -> Option<u32> {
+        let b: [u8; 2] = self.data.get(pos..pos + 2)?.try_into().ok()?;
+        Some(if self.big_endian { u16::from_be_bytes(b) } else { u16::from_le_bytes(b) } as u32)
+    }
+
+    fn u32_at(&self, pos: usize) -> Option<u32> {
+        let b: [u8; 4] = self.data.get(pos..pos + 4)?.try_into().ok()?;
+        Some(if self.big_endian { u32::from_be_bytes(b) } else { u32::from_le_bytes(b) })
+    }
+
+    /// (type, count, entry offset) of a tag
+    fn find(&self, tag: u16) -> Option<(u32, usize, usize)> {
+        for i in 0..self.count {
+            let entry = self.entries + i * 12;
+            if self.u16_at(entry)? == tag as u32 {
+                return Some((self.u16_at(entry + 2)?, self.u32_at(entry + 4)? as usize, entry));
+            }
+        }
+        None
+    }
+
+    /// SHORT or LONG values of a tag, read inline or from the value offset
+    fn values(&self, tag: u16) -> Option<Vec<u32>> {
+        let (typ, count, entry) = self.find(tag)?;
+        let size = match typ {
+            3 => 2,
+            4 => 4,
+            _ => return None,
+        };
+        let start = if count * size <= 4 {
+            entry + 8
+        } else {
+            self.u32_at(entry + 8)? as usize
+        };
+        (0..count)
+            .map(|i| match size {
+                2 => self.u16_at(start + i * 2),
+                _ => self.u32_at(start + i * 4),
+            })
+            .collect()
+    }
+
+    /// first SHORT or LONG value of a tag
+    fn value(&self, tag: u16) -> Option<u32> {
+        self.values(tag)?.first().copied()
+    }
+
+    /// RATIONAL value of a tag (always stored out of line)
+    fn rational(&self, tag: u16) -> Option<f64> {
+        self.rationals(tag)?.first().copied()
+    }
+
+    /// all RATIONAL values of a tag
+    fn rationals(&self, tag: u16) -> Option<Vec<f64>> {
+        let (typ, count, entry) = self.find(tag)?;
+        if typ != 5 {
+            return None;
+        }
+        let offset = self.u32_at(entry + 8)? as usize;
+        (0..count)
+            .map(|i| {
+                let pos = offset + i * 8;
+                let (num, den) = (self.u32_at(pos)?, self.u32_at(pos + 4)?);
+                (den > 0).then(|| num as f64 / den as f64)
+            })
+            .collect()
+    }
+
+    /// ASCII value of a tag, without the NUL terminator and padding
+    fn ascii(&self, tag: u16) -> Option<String> {
+        let (typ, count, entry) = self.find(tag)?;
+        if typ != 2 {
+            return None;
+        }
+        let start = if count <= 4 {
+            entry + 8
+        } else {
+            self.u32_at(entry + 8)? as usize
+        };
+        let bytes = self.data.get(start..start + count)?;
+        let text = String::from_utf8_lossy(bytes);
+        let text = text.trim_end_matches('\0').trim();
+        (!text.is_empty()).then(|| text.to_string())
+    }
+}
+
+/// capture metadata read from an image's EXIF block
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExifInfo {
+    /// DateTimeOriginal (or DateTime), "YYYY:MM:DD HH:MM:SS"
+    pub date: Option<String>,
+    pub make: Option<String>,
+    pub model: Option<String>,
+    /// latitude and longitude in signed decimal degrees
+    pub gps: Option<(f64, f64)>,
+}
+
+/// TIFF-structured EXIF block of a JPEG (APP1), PNG (eXIf), or TIFF (the file itself)
+pub fn find_exif(data: &[u8]) -> Option<&[u8]> {
+    match InputFormat::sniff(data)? {
+        InputFormat::Jpeg => jpeg_segments(data)
+            .into_iter()
+            .map(|(marker, start, end)| (marker, &data[start..end]))
+            .find(|(marker, seg)| *marker == 0xE1 && seg.starts_with(b"Exif\0\0"))
+            .map(|(_, seg)| &seg[6..]),
+        InputFormat::Png => {
+            let mut pos = 8;
+            while pos + 12 <= data.len() {
+                let len = u32::from_be_bytes(data[pos..pos + 4].try_into().ok()?) as usize;
+                let body = data.get(pos + 8..pos + 8 + len)?;
+                match &data[pos + 4..pos + 8] {
+                    b"eXIf" => return Some(body),
+                    b"IDAT" | b"IEND" => return None,
+                    _ => pos += 12 + len,
+                }
+            }
+            None
+        }
+        InputFormat::Tiff => Some(data),
+        InputFormat::Bmp | InputFormat::Gif => None,
+    }
+}
+
+/// read capture date, camera, and GPS position from a TIFF-structured EXIF block
+pub fn parse_exif(exif: &[u8]) -> Option<ExifInfo> {
+    let ifd0 = TiffIfd::parse(exif)?;
+    let exif_ifd = ifd0.sub_ifd(0x8769);
+    let gps = ifd0.sub_ifd(0x8825).and_then(|gps| {
+        // degrees, minutes, seconds with an N/S or E/W reference tag
+        let degrees = |tag: u16, negative: &str| -> Option<f64> {
+            let dms = gps.rationals(tag)?;
+            let minutes = dms.get(1).unwrap_or(&0.0) + dms.get(2).unwrap_or(&0.0) / 60.0;
+            let value = dms.first()? + minutes / 60.0;
+            let sign = if gps.ascii(tag - 1).as_deref() == Some(negative) { -1.0 } else { 1.0 };
+            Some(sign * value)
+        };
+        Some((degrees(2, "S")?, degrees(4, "W")?))
+    });
+    let info = ExifInfo {
+        date: exif_ifd
+            .and_then(|ifd| ifd.ascii(0x9003))
+            .or_else(|| ifd0.ascii(0x0132)),
+        make: ifd0.ascii(0x010F),
+        model: ifd0.ascii(0x0110),
+        gps,
+    };
+    (info != ExifInfo::default()).then_some(info)
+}
+
+/// read horizontal and vertical DPI from the first IFD of a TIFF file
+/// (XResolution, YResolution, ResolutionUnit tags)
+pub fn parse_tiff_resolution(data: &[u8]) -> Option<(u32, u32)> {
+    let ifd = TiffIfd::parse(data)?;
+    let (x_res, y_res) = (ifd.rational(282)?, ifd.rational(283)?);
+    // unit 1 = no absolute unit (aspect ratio only), 2 = inch (default), 3 = centimeter
+    let scale = match ifd.value(296).unwrap_or(2) {
+        2 => 1.0,
+        3 => 2.54,
+        _ => return None,
+    };
+    let (x, y) = ((x_res * scale).round() as u32, (y_res * scale).round() as u32);
+    (x > 0 && y > 0).then_some((x, y))
+}
+
+/// whether the first image of a GIF has a transparent color index, from the
+/// Graphic Control Extension before it
+pub fn gif_has_transparency(data: &[u8]) -> bool {
+    // header and logical screen descriptor, then the global color table
+    let Some(&flags) = data.get(10) else {
+        return false;
+    };
+    let mut pos = 13;
+    if flags & 0x80 != 0 {
+        pos += 3 << ((flags & 7) + 1);
+    }
+    let mut transparent = false;
+    // extensions up to the first image descriptor
+    while let (Some(0x21), Some(&label)) = (data.get(pos), data.get(pos + 1)) {
+        pos += 2;
+        if label == 0xF9 {
+            // block size 4, then packed fields: bit 0 is the transparency flag
+            transparent = data.get(pos + 1).is_some_and(|&packed| packed & 1 != 0);
+        }
+        // data sub-blocks until a zero-length one
+        while let Some(&len) = data.get(pos) {
+            pos += 1 + len as usize;
+            if len == 0 {
+                break;
+            }
+        }
+    }
+    data.get(pos) == Some(&0x2C) && transparent
+}
+
+/// bilevel TIFF image whose strip can be embedded as-is with CCITTFaxDecode
+pub struct CcittInfo {
+    pub width: u32,
+    pub height: u32,
+    /// CCITTFaxDecode /K: -1 = Group 4, 0 = Group 3 1-D, 1 = Group 3 2-D
+    pub k: i32,
+    /// Group 3 lines padded to byte boundaries (T4Options fill bits)
+    pub byte_align: bool,
+    /// BlackIsZero photometric, so decoded samples need inverting
+    pub invert: bool,
+    /// encoded strip, MSB-first
+    pub data: Vec<u8>,
+}
+
+/// detect a single-strip, first-page Group 3/4 fax TIFF and extract its encoded data;
+/// None means the image needs a full decode instead
+pub fn parse_tiff_ccitt(data: &[u8]) -> Option<CcittInfo> {
+    let ifd = TiffIfd::parse(data)?;
+    let width = ifd.value(256)?;
+    let height = ifd.value(257)?;
+    let t4_options = ifd.value(292).unwrap_or(0);
+    let k = match ifd.value(259)? {
+        3 if t4_options & 1 != 0 => 1,
+        3 => 0,
+        4 => -1,
+        _ => return None,
+    };
+    if ifd.value(258).unwrap_or(1) != 1 || ifd.value(277).unwrap_or(1) != 1 {
+        return None;
+    }
+    let invert = match ifd.value(262).unwrap_or(0) {
+        0 => false,
+        1 => true,
+        _ => return None,
+    };
+    // separately coded strips can't be concatenated into one stream
+    let offsets = ifd.values(273)?;
+    let counts = ifd.values(279)?;
+    let ([offset], [count]) = (offsets.as_slice(), counts.as_slice()) else {
+        return None;
+    };
+    let mut strip = data
+        .get(*offset as usize..(*offset as usize).checked_add(*count as usize)?)?
+        .to_vec();
+    // FillOrder 2 = LSB-first bit order, PDF expects MSB-first
+    if ifd.value(266) == Some(2) {
+        for byte in &mut strip {
+            *byte = byte.reverse_bits();
+        }
+    }
+    Some(CcittInfo {
+        width,
+        height,
+        k,
+        byte_align: k >= 0 && t4_options & 4 != 0,
+        invert,
+        data: strip,
     })
 }
 
@@ -592,7 +1742,29 @@ mod tests {
         }
         buf.extend_from_slice(&[0xFF, 0xD9]);
         let info = parse_jpeg_header(&buf).unwrap();
-        assert_eq!(info.dpi, Some(300));
+        assert_eq!(info.dpi, Some((300, 300)));
+    }
+
+    #[test]
+    fn jpeg_header_with_non_square_jfif_dpi() {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&[0xFF, 0xD8]);
+        // APP0 JFIF with fax resolution 204x98 DPI
+        buf.extend_from_slice(&[0xFF, 0xE0]);
+        let mut app0 = Vec::new();
+        app0.extend_from_slice(b"JFIF\0");
+        app0.extend_from_slice(&[1, 1]);
+        app0.push(1);
+        app0.extend_from_slice(&204u16.to_be_bytes());
+        app0.extend_from_slice(&98u16.to_be_bytes());
+        app0.extend_from_slice(&[0, 0]);
+        buf.extend_from_slice(&((app0.len() + 2) as u16).to_be_bytes());
+        buf.extend_from_slice(&app0);
+        // SOF (1 component)
+        buf.extend_from_slice(&[0xFF, 0xC0, 0, 11, 8, 0, 16, 0, 16, 1, 1, 0x11, 0]);
+        buf.extend_from_slice(&[0xFF, 0xD9]);
+        let info = parse_jpeg_header(&buf).unwrap();
+        assert_eq!(info.dpi, Some((204, 98)));
     }
 
     #[test]
@@ -736,7 +1908,7 @@ mod tests {
         assert_eq!(info.color_type, 2);
         assert_eq!(info.bit_depth, 8);
         assert_eq!(info.interlace, 0);
-        assert!(!info.has_trns);
+        assert!(info.trns.is_none());
         assert!(!info.idat_data.is_empty());
         assert!(info.plte_data.is_empty());
     }
@@ -790,6 +1962,128 @@ mod tests {
         assert_eq!(info.height, 3000);
     }
 
+    /// little-endian TIFF with the given (tag, type, value) entries in IFD0;
+    /// `extra` follows the IFD at the offset returned by `tiff_extra_offset`
+    fn tiff_with_entries(entries: &[(u16, u16, u32)], extra: &[u8]) -> Vec<u8> {
+        let mut buf = b"II".to_vec();
+        buf.extend_from_slice(&42u16.to_le_bytes());
+        buf.extend_from_slice(&8u32.to_le_bytes());
+        buf.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        for &(tag, typ, value) in entries {
+            buf.extend_from_slice(&tag.to_le_bytes());
+            buf.extend_from_slice(&typ.to_le_bytes());
+            buf.extend_from_slice(&1u32.to_le_bytes());
+            buf.extend_from_slice(&value.to_le_bytes());
+        }
+        buf.extend_from_slice(&0u32.to_le_bytes());
+        assert_eq!(buf.len() as u32, tiff_extra_offset(entries.len()));
+        buf.extend_from_slice(extra);
+        buf
+    }
+
+    fn tiff_extra_offset(entries: usize) -> u32 {
+        8 + 2 + 12 * entries as u32 + 4
+    }
+
+    /// little-endian TIFF with only resolution tags in IFD0
+    fn tiff_with_resolution(x: (u32, u32), y: (u32, u32), unit: u16) -> Vec<u8> {
+        let values = tiff_extra_offset(3);
+        let mut rationals = Vec::new();
+        for (num, den) in [x, y] {
+            rationals.extend_from_slice(&num.to_le_bytes());
+            rationals.extend_from_slice(&den.to_le_bytes());
+        }
+        tiff_with_entries(
+            &[(282, 5, values), (283, 5, values + 8), (296, 3, unit as u32)],
+            &rationals,
+        )
+    }
+
+    fn tiff_ccitt(compression: u32, fill_order: u32, photometric: u32, strip: &[u8]) -> Vec<u8> {
+        let strip_offset = tiff_extra_offset(9);
+        tiff_with_entries(
+            &[
+                (256, 3, 8),
+                (257, 3, 8),
+                (258, 3, 1),
+                (259, 3, compression),
+                (262, 3, photometric),
+                (266, 3, fill_order),
+                (273, 4, strip_offset),
+                (277, 3, 1),
+                (279, 4, strip.len() as u32),
+            ],
+            strip,
+        )
+    }
+
+    #[test]
+    fn tiff_ccitt_group4() {
+        let tiff = tiff_ccitt(4, 1, 0, &[0xFF, 0x00, 0x10, 0x01]);
+        let info = parse_tiff_ccitt(&tiff).unwrap();
+        assert_eq!((info.width, info.height, info.k), (8, 8, -1));
+        assert!(!info.invert && !info.byte_align);
+        assert_eq!(info.data, vec![0xFF, 0x00, 0x10, 0x01]);
+    }
+
+    #[test]
+    fn tiff_ccitt_lsb_fill_order_and_black_is_zero() {
+        let tiff = tiff_ccitt(3, 2, 1, &[0x01, 0x80]);
+        let info = parse_tiff_ccitt(&tiff).unwrap();
+        assert_eq!(info.k, 0);
+        assert!(info.invert);
+        assert_eq!(info.data, vec![0x80, 0x01]);
+    }
+
+    #[test]
+    fn tiff_ccitt_rejects_other_compression() {
+        // LZW
+        assert!(parse_tiff_ccitt(&tiff_ccitt(5, 1, 0, &[0])).is_none());
+        assert!(parse_tiff_ccitt(b"not a tiff").is_none());
+    }
+
+    #[test]
+    fn tiff_resolution_per_axis() {
+        let tiff = tiff_with_resolution((204, 1), (196, 2), 2);
+        assert_eq!(parse_tiff_resolution(&tiff), Some((204, 98)));
+    }
+
+    #[test]
+    fn jpeg_header_with_exif_dpi() {
+        // a JPEG with the given APPn segments before its SOF
+        let with_apps = |apps: &[(u8, &[u8])]| {
+            let jpeg = make_minimal_jpeg(64, 48, 3);
+            let mut buf = jpeg[..2].to_vec();
+            for &(marker, body) in apps {
+                buf.extend_from_slice(&[0xFF, marker]);
+                buf.extend_from_slice(&((body.len() + 2) as u16).to_be_bytes());
+                buf.extend_from_slice(body);
+            }
+            buf.extend_from_slice(&jpeg[2..]);
+            parse_jpeg_header(&buf).unwrap().dpi
+        };
+        let exif = [&b"Exif\0\0"[..], &tiff_with_resolution((180, 1), (180, 1), 2)].concat();
+        assert_eq!(with_apps(&[(0xE1, &exif)]), Some((180, 180)));
+
+        // JFIF density wins, unless it is only an aspect ratio (units 0)
+        let jfif = |units: u8| [&b"JFIF\0\x01\x01"[..], &[units, 0, 96, 0, 96, 0, 0]].concat();
+        assert_eq!(with_apps(&[(0xE0, &jfif(1)), (0xE1, &exif)]), Some((96, 96)));
+        assert_eq!(with_apps(&[(0xE0, &jfif(0)), (0xE1, &exif)]), Some((180, 180)));
+    }
+
+    #[test]
+    fn tiff_resolution_centimeters() {
+        let tiff = tiff_with_resolution((118, 1), (118, 1), 3);
+        assert_eq!(parse_tiff_resolution(&tiff), Some((300, 300)));
+    }
+
+    #[test]
+    fn tiff_resolution_without_unit_is_ignored() {
+        let tiff = tiff_with_resolution((72, 1), (72, 1), 1);
+        assert_eq!(parse_tiff_resolution(&tiff), None);
+        assert_eq!(parse_tiff_resolution(b"not a tiff"), None);
+    }
+
     #[test]
     fn png_header_err_not_png() {
         assert!(parse_png_header(&[0xFF, 0xD8, 0xFF, 0xE0, 0, 0, 0, 0]).is_err());
@@ -894,7 +2188,7 @@ mod tests {
         std::fs::write(dir.join("a.jpg"), b"fake").unwrap();
         std::fs::write(dir.join("b.tiff"), b"fake").unwrap();
         std::fs::write(dir.join("notes.txt"), b"not an image").unwrap();
-        let result = expand_image_paths(&[dir.clone()]).unwrap();
+        let result = expand_image_paths(std::slice::from_ref(&dir)).unwrap();
         assert_eq!(result.len(), 3);
         assert!(
             result[0].file_name().unwrap().to_str().unwrap()
@@ -950,6 +2244,303 @@ mod tests {
         assert_eq!(result.len(), 7);
     }
 
+    #[test]
+    fn expand_paths_sniffs_unknown_extensions() {
+        let dir = std::env::temp_dir().join("ovid_test_expand_sniff");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("download"), [0xFF, 0xD8, 0xFF, 0xE0]).unwrap();
+        std::fs::write(dir.join("scan.jpg_large"), b"GIF89a").unwrap();
+        std::fs::write(dir.join("notes.txt"), b"not an image").unwrap();
+        let result = expand_image_paths(&[dir]).unwrap();
+        let names: Vec<_> = result.iter().map(|p| p.file_name().unwrap()).collect();
+        assert_eq!(names, ["download", "scan.jpg_large"]);
+    }
+
+    #[test]
+    fn sniff_input_formats() {
+        let png = [137, 80, 78, 71, 13, 10, 26, 10, 0];
+        assert_eq!(InputFormat::sniff(&png), Some(InputFormat::Png));
+        assert_eq!(InputFormat::sniff(&[0xFF, 0xD8, 0xFF, 0xDB]), Some(InputFormat::Jpeg));
+        assert_eq!(InputFormat::sniff(b"MM\0*\0\0\0\x08"), Some(InputFormat::Tiff));
+        assert_eq!(InputFormat::sniff(b"BM\0\0"), Some(InputFormat::Bmp));
+        assert_eq!(InputFormat::sniff(b"GIF87a"), Some(InputFormat::Gif));
+        assert_eq!(InputFormat::sniff(b"RIFF\0\0\0\0WEBP"), None);
+        assert!(InputFormat::Jpeg.matches_extension("JPEG"));
+        assert!(!InputFormat::Jpeg.matches_extension("png"));
+    }
+
+    /// little-endian EXIF block: camera in IFD0, capture date in the EXIF IFD,
+    /// 48°30'N 2°15'36"W in the GPS IFD
+    fn exif_block() -> Vec<u8> {
+        let mut buf = b"II*\0".to_vec();
+        buf.extend_from_slice(&8u32.to_le_bytes());
+        let ifd = |buf: &mut Vec<u8>, entries: &[(u16, u16, u32, [u8; 4])]| {
+            buf.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+            for &(tag, typ, count, value) in entries {
+                buf.extend_from_slice(&tag.to_le_bytes());
+                buf.extend_from_slice(&typ.to_le_bytes());
+                buf.extend_from_slice(&count.to_le_bytes());
+                buf.extend_from_slice(&value);
+            }
+            buf.extend_from_slice(&0u32.to_le_bytes());
+        };
+        // IFD0 at 8, "Canon" at 62, EXIF IFD at 68 (date at 86), GPS IFD at 106 (rationals at 160)
+        ifd(&mut buf, &[
+            (0x010F, 2, 6, 62u32.to_le_bytes()),
+            (0x0110, 2, 3, *b"X1\0\0"),
+            (0x8769, 4, 1, 68u32.to_le_bytes()),
+            (0x8825, 4, 1, 106u32.to_le_bytes()),
+        ]);
+        buf.extend_from_slice(b"Canon\0");
+        ifd(&mut buf, &[(0x9003, 2, 20, 86u32.to_le_bytes())]);
+        buf.extend_from_slice(b"2021:06:01 12:30:45\0");
+        ifd(&mut buf, &[
+            (1, 2, 2, *b"N\0\0\0"),
+            (2, 5, 3, 160u32.to_le_bytes()),
+            (3, 2, 2, *b"W\0\0\0"),
+            (4, 5, 3, 184u32.to_le_bytes()),
+        ]);
+        for (num, den) in [(48u32, 1u32), (30, 1), (0, 1), (2, 1), (15, 1), (36, 1)] {
+            buf.extend_from_slice(&num.to_le_bytes());
+            buf.extend_from_slice(&den.to_le_bytes());
+        }
+        assert_eq!(buf.len(), 208);
+        buf
+    }
+
+    #[test]
+    fn exif_fields() {
+        let info = parse_exif(&exif_block()).unwrap();
+        assert_eq!(info.date.as_deref(), Some("2021:06:01 12:30:45"));
+        assert_eq!(info.make.as_deref(), Some("Canon"));
+        assert_eq!(info.model.as_deref(), Some("X1"));
+        let (lat, lon) = info.gps.unwrap();
+        assert!((lat - 48.5).abs() < 1e-9);
+        assert!((lon + 2.26).abs() < 1e-9);
+    }
+
+    #[test]
+    fn exif_found_in_jpeg_and_stripped() {
+        let exif = exif_block();
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        jpeg.extend_from_slice(&(exif.len() as u16 + 8).to_be_bytes());
+        jpeg.extend_from_slice(b"Exif\0\0");
+        jpeg.extend_from_slice(&exif);
+        let rest = [0xFF, 0xDB, 0x00, 0x03, 0x00, 0xFF, 0xDA, 0x00, 0x02, 0x12];
+        jpeg.extend_from_slice(&rest);
+        assert_eq!(find_exif(&jpeg), Some(&exif[..]));
+
+        let ranges = jpeg_ranges_without_app1(&jpeg);
+        assert_eq!(ranges, [0..2, jpeg.len() - rest.len()..jpeg.len()]);
+        let stripped: Vec<u8> = ranges.into_iter().flat_map(|r| jpeg[r].to_vec()).collect();
+        assert_eq!(stripped[..2], [0xFF, 0xD8]);
+        assert_eq!(stripped[2..], rest);
+        assert_eq!(find_exif(&stripped), None);
+    }
+
+    #[test]
+    fn metadata_stripped_from_jpeg() {
+        let segment = |marker: u8, body: &[u8]| {
+            let mut seg = vec![0xFF, marker];
+            seg.extend_from_slice(&(body.len() as u16 + 2).to_be_bytes());
+            seg.extend_from_slice(body);
+            seg
+        };
+        let jfif = segment(0xE0, b"JFIF\0\x01\x02\0\0\x01\0\x01\0\0");
+        let icc = segment(0xE2, b"ICC_PROFILE\0\x01\x01data");
+        let adobe = segment(0xEE, b"Adobe\0\x64\0\0\0\0\x01");
+        let rest = [0xFF, 0xDB, 0x00, 0x03, 0x00, 0xFF, 0xDA, 0x00, 0x02, 0x12];
+        let mut jpeg = vec![0xFF, 0xD8];
+        for seg in [
+            &jfif,
+            &segment(0xE0, b"JFXX\0\x13thumbnail"),
+            &segment(0xE1, b"Exif\0\0II*\0"),
+            &icc,
+            &segment(0xE2, b"MPF\0II*\0"),
+            &adobe,
+            &segment(0xED, b"Photoshop 3.0\0"),
+        ] {
+            jpeg.extend_from_slice(seg);
+        }
+        jpeg.extend_from_slice(&rest);
+
+        let stripped = |keep_icc| -> Vec<u8> {
+            let ranges = jpeg_ranges_without_metadata(&jpeg, keep_icc);
+            ranges.into_iter().flat_map(|r| jpeg[r].to_vec()).collect()
+        };
+        let expected = |with_icc: bool| {
+            let icc: &[u8] = if with_icc { &icc } else { &[] };
+            [&[0xFF, 0xD8][..], &jfif, icc, &adobe, &rest].concat()
+        };
+        assert_eq!(stripped(true), expected(true));
+        assert_eq!(stripped(false), expected(false));
+    }
+
+    #[test]
+    fn byte_size_display() {
+        assert_eq!(ByteSize(512).to_string(), "512 bytes");
+        assert_eq!(ByteSize(1536).to_string(), "1.5 KB");
+        assert_eq!(ByteSize(25 << 20).to_string(), "25.0 MB");
+    }
+
+    #[test]
+    fn interleave_alternates_sources() {
+        let fronts: Vec<PathBuf> = ["f1", "f2", "f3"].iter().map(PathBuf::from).collect();
+        let backs: Vec<PathBuf> = ["b1", "b2", "b3"].iter().map(PathBuf::from).collect();
+        let result = interleave_paths(fronts.clone(), backs.clone(), false).unwrap();
+        let names: Vec<&str> = result.iter().map(|p| p.to_str().unwrap()).collect();
+        assert_eq!(names, ["f1", "b1", "f2", "b2", "f3", "b3"]);
+
+        let result = interleave_paths(fronts, backs, true).unwrap();
+        let names: Vec<&str> = result.iter().map(|p| p.to_str().unwrap()).collect();
+        assert_eq!(names, ["f1", "b3", "f2", "b2", "f3", "b1"]);
+    }
+
+    #[test]
+    fn interleave_err_uneven() {
+        let fronts = vec![PathBuf::from("f1"), PathBuf::from("f2")];
+        assert!(interleave_paths(fronts, vec![PathBuf::from("b1")], false).is_err());
+    }
+
+    #[test]
+    fn color_hex() {
+        assert_eq!("#ff8000".parse::<Color>().unwrap(), Color { r: 255, g: 128, b: 0 });
+        assert_eq!("00FF7f".parse::<Color>().unwrap(), Color { r: 0, g: 255, b: 127 });
+    }
+
+    #[test]
+    fn color_short_hex_and_names() {
+        assert_eq!("#fff".parse::<Color>().unwrap(), Color { r: 255, g: 255, b: 255 });
+        assert_eq!("Black".parse::<Color>().unwrap(), Color { r: 0, g: 0, b: 0 });
+    }
+
+    #[test]
+    fn position_names_and_offsets() {
+        assert_eq!("center".parse::<Position>().unwrap(), Position::default());
+        assert_eq!(
+            "Top-Left".parse::<Position>().unwrap(),
+            Position::Anchor { x: 0.0, y: 0.0 }
+        );
+        assert_eq!(
+            "36, 72".parse::<Position>().unwrap(),
+            Position::Offset { x: 36.0, y: 72.0 }
+        );
+        assert!("middle".parse::<Position>().is_err());
+        assert!("1,x".parse::<Position>().is_err());
+    }
+
+    #[test]
+    fn position_place() {
+        let top: Position = "top".parse().unwrap();
+        assert_eq!(top.place(600.0, 800.0, 200.0, 100.0), (200.0, 700.0));
+        let offset: Position = "10,20".parse().unwrap();
+        assert_eq!(offset.place(600.0, 800.0, 200.0, 100.0), (10.0, 680.0));
+    }
+
+    #[test]
+    fn scale_percent() {
+        assert_eq!("50%".parse::<Scale>().unwrap(), Scale(0.5));
+        assert_eq!("100".parse::<Scale>().unwrap(), Scale(1.0));
+        assert!("0%".parse::<Scale>().is_err());
+        assert!("big".parse::<Scale>().is_err());
+    }
+
+    #[test]
+    fn rotation_parse() {
+        assert_eq!("90".parse::<Rotation>().unwrap(), Rotation(90));
+        assert_eq!("-90".parse::<Rotation>().unwrap(), Rotation(-90));
+        assert!("45".parse::<Rotation>().is_err());
+        assert!("left".parse::<Rotation>().is_err());
+    }
+
+    #[test]
+    fn page_move_parse() {
+        assert_eq!("3:1".parse::<PageMove>().unwrap(), PageMove { from: 3, to: 1 });
+        assert!("3".parse::<PageMove>().is_err());
+        assert!("0:2".parse::<PageMove>().is_err());
+        assert!("a:b".parse::<PageMove>().is_err());
+    }
+
+    #[test]
+    fn meta_field_parse() {
+        let field: MetaField = "title=Q3 Report".parse().unwrap();
+        assert_eq!(field, MetaField { key: "Title".into(), value: "Q3 Report".into() });
+        let custom: MetaField = "Department=a=b".parse().unwrap();
+        assert_eq!((custom.key.as_str(), custom.value.as_str()), ("Department", "a=b"));
+        assert_eq!("moddate=".parse::<MetaField>().unwrap().key, "ModDate");
+        assert!("title".parse::<MetaField>().is_err());
+        assert!("my key=x".parse::<MetaField>().is_err());
+    }
+
+    #[test]
+    fn length_units() {
+        assert_eq!("20".parse::<Length>().unwrap(), Length(20.0));
+        assert_eq!("0.5in".parse::<Length>().unwrap(), Length(36.0));
+        assert!(("10mm".parse::<Length>().unwrap().0 - 28.35).abs() < 0.01);
+        assert!("-5".parse::<Length>().is_err());
+        assert!("5 furlongs".parse::<Length>().is_err());
+    }
+
+    #[test]
+    fn pixel_size_parse() {
+        assert_eq!("256".parse::<PixelSize>().unwrap(), PixelSize { width: 256, height: 256 });
+        assert_eq!("320x240".parse::<PixelSize>().unwrap(), PixelSize { width: 320, height: 240 });
+        assert!("0x10".parse::<PixelSize>().is_err());
+        assert!("10x".parse::<PixelSize>().is_err());
+    }
+
+    #[test]
+    fn uri_rewrite_parse() {
+        let rule: UriRewrite = "http://=https://".parse().unwrap();
+        assert_eq!(rule, UriRewrite { from: "http://".into(), to: "https://".into() });
+        assert_eq!("https://old.example/=".parse::<UriRewrite>().unwrap().to, "");
+        assert!("https://".parse::<UriRewrite>().is_err());
+        assert!("=https://".parse::<UriRewrite>().is_err());
+    }
+
+    #[test]
+    fn page_rect_and_margins_units() {
+        let rect: PageRect = "100,50,0,0".parse().unwrap();
+        assert_eq!(rect, PageRect([0.0, 0.0, 100.0, 50.0]));
+        let inch: PageRect = "0,0,1in,2in".parse().unwrap();
+        assert_eq!(inch, PageRect([0.0, 0.0, 72.0, 144.0]));
+        assert!("0,0,10".parse::<PageRect>().is_err());
+        assert!("0,0,0,10".parse::<PageRect>().is_err());
+
+        let region: RedactRegion = "1,3-4:0,0,1in,20".parse().unwrap();
+        assert_eq!(region.pages, "1,3-4");
+        assert_eq!(region.rect, PageRect([0.0, 0.0, 72.0, 20.0]));
+        assert!("0,0,10,10".parse::<RedactRegion>().is_err());
+        assert!(":0,0,10,10".parse::<RedactRegion>().is_err());
+
+        let m: Margins = "10mm".parse().unwrap();
+        assert!((m.top - 28.3465).abs() < 1e-3 && m.left == m.top);
+        let m: Margins = "5,-2".parse().unwrap();
+        assert_eq!((m.top, m.right, m.bottom, m.left), (5.0, -2.0, 5.0, -2.0));
+        let m: Margins = "1,2,3,4".parse().unwrap();
+        assert_eq!((m.top, m.right, m.bottom, m.left), (1.0, 2.0, 3.0, 4.0));
+        assert!("1,2,3".parse::<Margins>().is_err());
+        assert!("10 furlongs".parse::<Margins>().is_err());
+    }
+
+    #[test]
+    fn byte_size_units() {
+        assert_eq!("50MB".parse::<ByteSize>().unwrap(), ByteSize(50 << 20));
+        assert_eq!("512k".parse::<ByteSize>().unwrap(), ByteSize(512 << 10));
+        assert_eq!("1.5 GB".parse::<ByteSize>().unwrap(), ByteSize(3 << 29));
+        assert_eq!("1000".parse::<ByteSize>().unwrap(), ByteSize(1000));
+        assert!("0".parse::<ByteSize>().is_err());
+        assert!("10 parsecs".parse::<ByteSize>().is_err());
+    }
+
+    #[test]
+    fn color_err_invalid() {
+        assert!("#12345".parse::<Color>().is_err());
+        assert!("#gggggg".parse::<Color>().is_err());
+        assert!("".parse::<Color>().is_err());
+    }
+
     #[test]
     fn page_size_dimensions() {
         let (w, h) = PageSize::A4.dimensions_pt();
@@ -0,0 +1,500 @@
+use anyhow::{Context, Result};
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream};
+use std::collections::BTreeSet;
+use std::io::Write;
+use std::path::Path;
+
+use crate::encrypt;
+use crate::writer;
+
+/// how an opened PDF was protected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encryption {
+    None,
+    /// encrypted with an empty user password (owner-locked), decrypted on load
+    EmptyPassword,
+    /// needs a user password; strings and streams are still encrypted
+    Locked,
+}
+
+/// open an existing PDF for inspection, reporting how it was encrypted; documents
+/// encrypted with an empty user password (common for "owner-locked" files) are
+/// decrypted in place
+pub fn load_with_encryption(path: &Path) -> Result<(Document, Encryption)> {
+    let doc = Document::load(path)
+        .with_context(|| format!("Failed to open PDF {}", path.display()))?;
+    if !doc.is_encrypted() {
+        return Ok((doc, Encryption::None));
+    }
+    // read again so encrypted object streams survive until decryption
+    let mut doc = encrypt::load_encrypted(path)?;
+    let encryption = if encrypt::decrypt_document(&mut doc, "").is_ok() {
+        Encryption::EmptyPassword
+    } else {
+        Encryption::Locked
+    };
+    Ok((doc, encryption))
+}
+
+/// a `Document::load_filtered` filter that keeps the objects but not the stream
+/// data (content, images, fonts)
+fn drop_streams(id: ObjectId, obj: &mut Object) -> Option<(ObjectId, Object)> {
+    // object streams hold other objects, which may include the page tree
+    if matches!(obj, Object::Stream(stream) if !stream.dict.type_is(b"ObjStm")) {
+        *obj = Object::Null;
+    }
+    Some((id, obj.clone()))
+}
+
+/// count a PDF's pages without keeping any stream data in memory. page trees
+/// aren't encrypted, so password-protected files can be counted too, unless
+/// their pages are packed in object streams
+pub fn page_count(path: &Path) -> Result<usize> {
+    let doc = Document::load_filtered(path, drop_streams)
+        .with_context(|| format!("Failed to open PDF {}", path.display()))?;
+    let pages = doc.get_pages().len();
+    if pages > 0 || !doc.is_encrypted() {
+        return Ok(pages);
+    }
+    let (doc, encryption) = load_with_encryption(path)?;
+    let pages = doc.get_pages().len();
+    anyhow::ensure!(
+        pages > 0 || encryption != Encryption::Locked,
+        "{} is password-protected and its page tree is encrypted",
+        path.display()
+    );
+    Ok(pages)
+}
+
+/// what a PDF's XFA form leaves for a renderer that can't lay XFA out
+#[cfg(feature = "native")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Xfa {
+    /// no XFA form
+    None,
+    /// static XFA: the fields are on the pages as AcroForm widgets too
+    Static,
+    /// dynamic XFA: the pages hold only a placeholder asking for a viewer that
+    /// can lay the form out
+    Dynamic,
+}
+
+/// find the XFA form of a PDF MuPDF has open, from its catalog. the form is
+/// dynamic when the catalog asks for it to be laid out (NeedsRendering), or it
+/// has no AcroForm fields to fall back on
+#[cfg(feature = "native")]
+pub fn xfa_form(doc: &mupdf::pdf::PdfDocument) -> Result<Xfa> {
+    let catalog = doc.catalog()?;
+    let Some(form) = catalog.get_dict("AcroForm")? else {
+        return Ok(Xfa::None);
+    };
+    if !form.is_dict()? || form.get_dict("XFA")?.is_none() {
+        return Ok(Xfa::None);
+    }
+    let needs_rendering = match catalog.get_dict("NeedsRendering")? {
+        Some(flag) => flag.is_bool()? && flag.as_bool()?,
+        None => false,
+    };
+    let fields = match form.get_dict("Fields")? {
+        Some(fields) if fields.is_array()? => fields.len()?,
+        _ => 0,
+    };
+    Ok(if needs_rendering || fields == 0 {
+        Xfa::Dynamic
+    } else {
+        Xfa::Static
+    })
+}
+
+/// open a PDF for rewriting; fails if it needs a password
+pub fn load(path: &Path) -> Result<Document> {
+    let (doc, encryption) = load_with_encryption(path)?;
+    anyhow::ensure!(
+        encryption != Encryption::Locked,
+        "{} is password-protected; remove it with `ovid unlock --password`",
+        path.display()
+    );
+    Ok(doc)
+}
+
+/// save a rewritten PDF to a file, or stdout for "-"; a failed write doesn't leave a
+/// truncated file behind
+pub fn save(doc: &mut Document, output: &Path) -> Result<()> {
+    write_output(output, |mut out| Ok(doc.save_to(&mut out)?))
+}
+
+/// like `save`, but packing objects into compressed object streams (PDF 1.5)
+pub fn save_compact(doc: &Document, output: &Path) -> Result<()> {
+    write_output(output, |out| writer::write_compact(doc, out).map(drop))
+}
+
+/// write to a file, or stdout for "-"; a partly written file is removed on error
+pub fn write_output(
+    output: &Path,
+    write: impl FnOnce(&mut dyn Write) -> Result<()>,
+) -> Result<()> {
+    if output == Path::new("-") {
+        let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+        write(&mut out).context("Failed to write PDF to stdout")?;
+        return out.flush().context("Failed to write PDF to stdout");
+    }
+    let result = std::fs::File::create(output).map_err(anyhow::Error::from).and_then(|file| {
+        let mut out = std::io::BufWriter::new(file);
+        write(&mut out)?;
+        out.flush()?;
+        Ok(())
+    });
+    if result.is_err() {
+        let _ = std::fs::remove_file(output);
+    }
+    result.with_context(|| format!("Failed to save {}", output.display()))
+}
+
+/// page attributes a page may inherit from its ancestors in the page tree
+const INHERITABLE: [&[u8]; 4] = [b"Resources", b"MediaBox", b"CropBox", b"Rotate"];
+
+/// copy inherited attributes onto each page, so pages can be moved to another page
+/// tree without losing their resources or size
+pub fn inherit_page_attributes(doc: &mut Document) {
+    let mut updates = Vec::new();
+    for page_id in doc.get_pages().into_values() {
+        let Ok(page) = doc.get_dictionary(page_id) else { continue };
+        for key in INHERITABLE {
+            if page.has(key) {
+                continue;
+            }
+            let mut node = page;
+            // bounded walk in case of a cyclic page tree
+            for _ in 0..64 {
+                let Some(parent) = node
+                    .get(b"Parent")
+                    .and_then(Object::as_reference)
+                    .ok()
+                    .and_then(|id| doc.get_dictionary(id).ok())
+                else {
+                    break;
+                };
+                if let Ok(value) = parent.get(key) {
+                    updates.push((page_id, key, value.clone()));
+                    break;
+                }
+                node = parent;
+            }
+        }
+    }
+    for (page_id, key, value) in updates {
+        if let Ok(page) = doc.get_dictionary_mut(page_id) {
+            page.set(key, value);
+        }
+    }
+}
+
+/// replace the page tree with a flat one holding `pages` in order; pages left out
+/// are dropped along with anything only they used, and references to them from
+/// outlines or links become null
+pub fn set_page_order(doc: &mut Document, pages: &[ObjectId]) -> Result<()> {
+    inherit_page_attributes(doc);
+    let kept: BTreeSet<ObjectId> = pages.iter().copied().collect();
+    let removed: BTreeSet<ObjectId> = doc
+        .get_pages()
+        .into_values()
+        .filter(|id| !kept.contains(id))
+        .collect();
+    if !removed.is_empty() {
+        doc.traverse_objects(|obj| {
+            if matches!(obj, Object::Reference(id) if removed.contains(id)) {
+                *obj = Object::Null;
+            }
+        });
+    }
+
+    let root_id = doc.catalog()?.get(b"Pages").and_then(Object::as_reference)?;
+    for &page_id in pages {
+        doc.get_dictionary_mut(page_id)?.set("Parent", root_id);
+    }
+    let root = doc.get_dictionary_mut(root_id)?;
+    root.set("Kids", pages.iter().map(|&id| Object::Reference(id)).collect::<Vec<_>>());
+    root.set("Count", pages.len() as i64);
+    // attributes now live on every page
+    for key in INHERITABLE {
+        root.remove(key);
+    }
+    // old intermediate nodes and dropped pages are unreachable now
+    doc.prune_objects();
+    Ok(())
+}
+
+/// page attribute, following /Parent for the inheritable ones (MediaBox, CropBox,
+/// Resources, Rotate)
+pub fn page_attribute<'a>(doc: &'a Document, page_id: ObjectId, key: &[u8]) -> Option<&'a Object> {
+    let mut node = doc.get_dictionary(page_id).ok()?;
+    // bounded walk in case of a cyclic page tree
+    for _ in 0..64 {
+        if let Ok(value) = node.get(key) {
+            return doc.dereference(value).ok().map(|(_, obj)| obj);
+        }
+        let parent = node.get(b"Parent").and_then(Object::as_reference).ok()?;
+        node = doc.get_dictionary(parent).ok()?;
+    }
+    None
+}
+
+/// numeric value of an integer or real object
+pub fn number(obj: &Object) -> Option<f32> {
+    match obj {
+        Object::Integer(i) => Some(*i as f32),
+        Object::Real(r) => Some(*r),
+        _ => None,
+    }
+}
+
+/// page MediaBox as [x0, y0, x1, y1], US Letter if missing
+pub fn media_box(doc: &Document, page_id: ObjectId) -> [f32; 4] {
+    page_box(doc, page_id, b"MediaBox").unwrap_or([0.0, 0.0, 612.0, 792.0])
+}
+
+fn page_box(doc: &Document, page_id: ObjectId, key: &[u8]) -> Option<[f32; 4]> {
+    page_attribute(doc, page_id, key)
+        .and_then(|obj| obj.as_array().ok())
+        .and_then(|arr| {
+            let v: Vec<f32> = arr
+                .iter()
+                .filter_map(|o| doc.dereference(o).ok().and_then(|(_, o)| number(o)))
+                .collect();
+            (v.len() == 4).then(|| [v[0], v[1], v[2], v[3]])
+        })
+}
+
+/// the visible area of a page, CropBox (else MediaBox), normalized to x0 < x1, y0 < y1
+pub fn crop_box(doc: &Document, page_id: ObjectId) -> [f32; 4] {
+    let [x0, y0, x1, y1] =
+        page_box(doc, page_id, b"CropBox").unwrap_or_else(|| media_box(doc, page_id));
+    [x0.min(x1), y0.min(y1), x0.max(x1), y0.max(y1)]
+}
+
+/// the visible area of a page as a viewer shows it (CropBox, turned by /Rotate):
+/// returns the matrix mapping that upright view into page space, and its width
+/// and height, so stamps can be drawn upright on any page
+pub fn display_transform(doc: &Document, page_id: ObjectId) -> ([f32; 6], f32, f32) {
+    let [left, bottom, right, top] = crop_box(doc, page_id);
+    let (w, h) = (right - left, top - bottom);
+    let rotate = page_attribute(doc, page_id, b"Rotate")
+        .and_then(|r| r.as_i64().ok())
+        .unwrap_or(0)
+        .rem_euclid(360);
+    // /Rotate turns the page clockwise for display
+    match rotate {
+        90 => ([0.0, 1.0, -1.0, 0.0, left + w, bottom], h, w),
+        180 => ([-1.0, 0.0, 0.0, -1.0, left + w, bottom + h], w, h),
+        270 => ([0.0, -1.0, 1.0, 0.0, left, bottom + h], h, w),
+        _ => ([1.0, 0.0, 0.0, 1.0, left, bottom], w, h),
+    }
+}
+
+/// draw form XObject `form_id` over a page's existing content, which is wrapped
+/// in q/Q so its graphics state can't leak into the stamp. the page gets its own
+/// copy of its resources, so pages sharing them are left alone
+pub fn stamp_form(doc: &mut Document, page_id: ObjectId, form_id: ObjectId) -> Result<()> {
+    stamp_forms(doc, page_id, &[(form_id, [1.0, 0.0, 0.0, 1.0, 0.0, 0.0])])
+}
+
+/// like `stamp_form`, for several forms each placed with its own matrix
+pub fn stamp_forms(
+    doc: &mut Document,
+    page_id: ObjectId,
+    forms: &[(ObjectId, [f32; 6])],
+) -> Result<()> {
+    place_forms(doc, page_id, forms, false)
+}
+
+/// like `stamp_forms`, but drawing the forms first, under the page's content
+pub fn underlay_forms(
+    doc: &mut Document,
+    page_id: ObjectId,
+    forms: &[(ObjectId, [f32; 6])],
+) -> Result<()> {
+    place_forms(doc, page_id, forms, true)
+}
+
+fn place_forms(
+    doc: &mut Document,
+    page_id: ObjectId,
+    forms: &[(ObjectId, [f32; 6])],
+    under: bool,
+) -> Result<()> {
+    if forms.is_empty() {
+        return Ok(());
+    }
+    let mut resources = page_attribute(doc, page_id, b"Resources")
+        .and_then(|r| r.as_dict().ok())
+        .cloned()
+        .unwrap_or_default();
+    let mut xobjects = resources
+        .get(b"XObject")
+        .and_then(|x| doc.dereference(x))
+        .and_then(|(_, x)| x.as_dict())
+        .cloned()
+        .unwrap_or_default();
+    let mut stamp = String::new();
+    let mut names = (0..).map(|i| format!("Ov{}", i));
+    for &(form_id, m) in forms {
+        let name = names.find(|name| !xobjects.has(name.as_bytes())).unwrap();
+        xobjects.set(name.as_bytes(), form_id);
+        if m == [1.0, 0.0, 0.0, 1.0, 0.0, 0.0] {
+            stamp.push_str(&format!("q /{} Do Q\n", name));
+        } else {
+            let m = m.map(|v| format!("{}", v)).join(" ");
+            stamp.push_str(&format!("q {} cm /{} Do Q\n", m, name));
+        }
+    }
+    resources.set("XObject", xobjects);
+
+    let page = doc.get_dictionary(page_id)?;
+    let mut contents = match page.get(b"Contents") {
+        Ok(Object::Reference(id)) => match doc.get_object(*id) {
+            Ok(Object::Array(parts)) => parts.clone(),
+            _ => vec![Object::Reference(*id)],
+        },
+        Ok(Object::Array(parts)) => parts.clone(),
+        _ => Vec::new(),
+    };
+    let (open, close) = if under {
+        (stamp + "q\n", "Q\n".to_string())
+    } else {
+        ("q\n".to_string(), "Q\n".to_string() + &stamp)
+    };
+    let open = doc.add_object(Stream::new(Dictionary::new(), open.into_bytes()));
+    let close = doc.add_object(Stream::new(Dictionary::new(), close.into_bytes()));
+    contents.insert(0, Object::Reference(open));
+    contents.push(Object::Reference(close));
+
+    let page = doc.get_dictionary_mut(page_id)?;
+    page.set("Resources", resources);
+    page.set("Contents", contents);
+    Ok(())
+}
+
+/// a page as a form XObject drawing it upright (after CropBox and /Rotate) in
+/// [0, w] x [0, h], for placing pages on other pages; returns the form and w, h.
+/// annotations aren't part of it
+pub fn page_form(doc: &mut Document, page_id: ObjectId) -> Result<(ObjectId, f32, f32)> {
+    let (m, w, h) = display_transform(doc, page_id);
+    let mut content = Vec::new();
+    for id in doc.get_page_contents(page_id) {
+        if let Ok(stream) = doc.get_object(id).and_then(Object::as_stream) {
+            let data = stream.decompressed_content();
+            content.extend(data.unwrap_or_else(|_| stream.content.clone()));
+            content.push(b'\n');
+        }
+    }
+    // the inverse of the display transform takes page space to the upright view
+    let det = m[0] * m[3] - m[1] * m[2];
+    let (a, b, c, d) = (m[3] / det, -m[1] / det, -m[2] / det, m[0] / det);
+    let matrix = [a, b, c, d, -(m[4] * a + m[5] * c), -(m[4] * b + m[5] * d)];
+    let mut dict = Dictionary::new();
+    dict.set("Type", "XObject");
+    dict.set("Subtype", "Form");
+    dict.set("BBox", crop_box(doc, page_id).map(Object::Real).to_vec());
+    dict.set("Matrix", matrix.map(Object::Real).to_vec());
+    let resources = page_attribute(doc, page_id, b"Resources").cloned();
+    dict.set("Resources", resources.unwrap_or_else(|| Dictionary::new().into()));
+    if let Ok(group) = doc.get_dictionary(page_id)?.get(b"Group") {
+        dict.set("Group", group.clone());
+    }
+    let mut form = Stream::new(dict, content);
+    form.compress()?;
+    Ok((doc.add_object(form), w, h))
+}
+
+/// decode a PDF text string: UTF-16BE with a byte order mark, else PDFDocEncoding
+/// (treated as Latin-1, which it matches for printable characters)
+pub fn decode_text(bytes: &[u8]) -> String {
+    if let Some(utf16) = bytes.strip_prefix(&[0xFE, 0xFF]) {
+        let units: Vec<u16> = utf16
+            .chunks_exact(2)
+            .map(|c| u16::from_be_bytes([c[0], c[1]]))
+            .collect();
+        return String::from_utf16_lossy(&units);
+    }
+    if let Some(utf8) = bytes.strip_prefix(&[0xEF, 0xBB, 0xBF]) {
+        return String::from_utf8_lossy(utf8).into_owned();
+    }
+    bytes.iter().map(|&b| b as char).collect()
+}
+
+/// document Info dictionary entry as text
+pub fn info_string(doc: &Document, key: &[u8]) -> Option<String> {
+    let info = doc.trailer.get(b"Info").ok()?;
+    let (_, info) = doc.dereference(info).ok()?;
+    let value = info.as_dict().ok()?.get(key).ok()?;
+    let (_, value) = doc.dereference(value).ok()?;
+    value.as_str().ok().map(decode_text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::dictionary;
+
+    #[test]
+    fn decode_text_encodings() {
+        assert_eq!(decode_text(b"plain"), "plain");
+        assert_eq!(decode_text(&[0xFE, 0xFF, 0x00, 0xE9, 0x67, 0x71]), "é東");
+        assert_eq!(decode_text(&[0x43, 0x61, 0x66, 0xE9]), "Café");
+    }
+
+    #[test]
+    #[cfg(feature = "native")]
+    fn xfa_forms_static_or_dynamic() {
+        let xfa_kind = |doc: &Document| {
+            let mut bytes = Vec::new();
+            doc.clone().save_to(&mut bytes).unwrap();
+            xfa_form(&mupdf::pdf::PdfDocument::from_bytes(&bytes).unwrap()).unwrap()
+        };
+        let mut doc = Document::with_version("1.7");
+        let pages = dictionary! { "Type" => "Pages", "Kids" => Vec::<Object>::new(), "Count" => 0 };
+        let pages_id = doc.add_object(pages);
+        let catalog_id = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        doc.trailer.set("Root", catalog_id);
+        assert_eq!(xfa_kind(&doc), Xfa::None);
+
+        let field_id = doc.add_object(dictionary! {
+            "FT" => "Tx",
+            "T" => Object::string_literal("name"),
+        });
+        let form_id = doc.add_object(dictionary! {
+            "Fields" => vec![field_id.into()],
+            "XFA" => vec![Object::string_literal("template"), Object::Null],
+        });
+        let catalog = doc.get_dictionary_mut(catalog_id).unwrap();
+        catalog.set("AcroForm", form_id);
+        assert_eq!(xfa_kind(&doc), Xfa::Static);
+
+        let catalog = doc.get_dictionary_mut(catalog_id).unwrap();
+        catalog.set("NeedsRendering", true);
+        assert_eq!(xfa_kind(&doc), Xfa::Dynamic);
+        // no fields to fall back on
+        let catalog = doc.get_dictionary_mut(catalog_id).unwrap();
+        catalog.remove(b"NeedsRendering");
+        let form = doc.get_dictionary_mut(form_id).unwrap();
+        form.set("Fields", Vec::<Object>::new());
+        assert_eq!(xfa_kind(&doc), Xfa::Dynamic);
+    }
+
+    #[test]
+    fn display_transform_follows_rotate() {
+        let mut doc = Document::with_version("1.5");
+        let page_id = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "MediaBox" => vec![0.into(), 0.into(), 200.into(), 100.into()],
+            "CropBox" => vec![10.into(), 10.into(), 110.into(), 60.into()],
+            "Rotate" => 90,
+        });
+        let (m, w, h) = display_transform(&doc, page_id);
+        assert_eq!((w, h), (50.0, 100.0));
+        // the shown bottom-left corner is the crop box's bottom-right
+        let map = |u: f32, v: f32| (m[0] * u + m[2] * v + m[4], m[1] * u + m[3] * v + m[5]);
+        assert_eq!(map(0.0, 0.0), (110.0, 10.0));
+        assert_eq!(map(w, h), (10.0, 60.0));
+    }
+}
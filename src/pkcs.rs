@@ -0,0 +1,509 @@
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use cms::cert::{CertificateChoices, IssuerAndSerialNumber};
+use cms::content_info::{CmsVersion, ContentInfo};
+use cms::encrypted_data::EncryptedData;
+use cms::signed_data::{
+    CertificateSet, EncapsulatedContentInfo, SignedData, SignerIdentifier, SignerInfo, SignerInfos,
+};
+use der::asn1::{Any, ObjectIdentifier, OctetString, SetOfVec, UtcTime};
+use der::referenced::OwnedToRef;
+use der::{Decode, Encode, SliceReader};
+use hmac::{Hmac, Mac};
+use pkcs12::cert_type::CertBag;
+use pkcs12::digest_info::DigestInfo;
+use pkcs12::kdf::{derive_key_utf8, Pkcs12KeyType};
+use pkcs12::pbe_params::{EncryptedPrivateKeyInfo, Pkcs12PbeParams};
+use pkcs12::pfx::Pfx;
+use pkcs12::safe_bag::SafeContents;
+use rsa::pkcs1v15::{Pkcs1v15Sign, SigningKey};
+use rsa::pkcs8::{DecodePrivateKey, PrivateKeyInfo};
+use rsa::rand_core::OsRng;
+use rsa::signature::{RandomizedSigner, SignatureEncoding};
+use rsa::traits::PublicKeyParts;
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use sha1::Sha1;
+use sha2::{Sha256, Sha384, Sha512};
+use x509_cert::attr::Attribute;
+use x509_cert::name::Name;
+use x509_cert::spki::AlgorithmIdentifierOwned;
+use x509_cert::time::Time;
+use x509_cert::Certificate;
+
+use crate::crypto::{self, Hash};
+
+const fn oid(dotted: &str) -> ObjectIdentifier {
+    ObjectIdentifier::new_unwrap(dotted)
+}
+
+const DATA: ObjectIdentifier = oid("1.2.840.113549.1.7.1");
+const SIGNED_DATA: ObjectIdentifier = oid("1.2.840.113549.1.7.2");
+const ENCRYPTED_DATA: ObjectIdentifier = oid("1.2.840.113549.1.7.6");
+const PBES2: ObjectIdentifier = oid("1.2.840.113549.1.5.13");
+const RSA_ENCRYPTION: ObjectIdentifier = oid("1.2.840.113549.1.1.1");
+const SHA_WITH_RSA: [ObjectIdentifier; 3] = [
+    oid("1.2.840.113549.1.1.11"),
+    oid("1.2.840.113549.1.1.12"),
+    oid("1.2.840.113549.1.1.13"),
+];
+const SHA1: ObjectIdentifier = oid("1.3.14.3.2.26");
+const CONTENT_TYPE: ObjectIdentifier = oid("1.2.840.113549.1.9.3");
+const MESSAGE_DIGEST: ObjectIdentifier = oid("1.2.840.113549.1.9.4");
+const SIGNING_TIME: ObjectIdentifier = oid("1.2.840.113549.1.9.5");
+const COMMON_NAME: ObjectIdentifier = oid("2.5.4.3");
+
+fn hash_oid(hash: Hash) -> ObjectIdentifier {
+    match hash {
+        Hash::Sha256 => oid("2.16.840.1.101.3.4.2.1"),
+        Hash::Sha384 => oid("2.16.840.1.101.3.4.2.2"),
+        Hash::Sha512 => oid("2.16.840.1.101.3.4.2.3"),
+    }
+}
+
+fn hash_from_oid(oid: &ObjectIdentifier) -> Option<Hash> {
+    [Hash::Sha256, Hash::Sha384, Hash::Sha512]
+        .into_iter()
+        .find(|&hash| hash_oid(hash) == *oid)
+}
+
+/// an AlgorithmIdentifier with NULL parameters, as RSA and digests in CMS carry
+fn algorithm(oid: ObjectIdentifier) -> AlgorithmIdentifierOwned {
+    AlgorithmIdentifierOwned {
+        oid,
+        parameters: Some(Any::null()),
+    }
+}
+
+/// an RSA private key and its certificate chain, from a PKCS#12 (.p12/.pfx) file
+pub struct Identity {
+    key: RsaPrivateKey,
+    /// the signer's certificate first, then the rest of the chain
+    certs: Vec<Certificate>,
+    /// common name of the signer's certificate
+    pub name: String,
+}
+
+impl Identity {
+    fn key_len(&self) -> usize {
+        self.key.size()
+    }
+
+    /// PKCS#1 v1.5 SHA-256 signature over `data`, blinded against timing attacks
+    fn sign(&self, data: &[u8]) -> Vec<u8> {
+        SigningKey::<Sha256>::new(self.key.clone())
+            .sign_with_rng(&mut OsRng, data)
+            .to_vec()
+    }
+}
+
+/// the RSA public key of a certificate; None for other key types
+fn public_key(cert: &Certificate) -> Option<RsaPublicKey> {
+    RsaPublicKey::try_from(cert.tbs_certificate.subject_public_key_info.owned_to_ref()).ok()
+}
+
+/// CN of an X.509 name
+fn common_name(name: &Name) -> Option<String> {
+    name.0
+        .iter()
+        .flat_map(|rdn| rdn.0.iter())
+        .find(|attr| attr.oid == COMMON_NAME)
+        .map(|attr| String::from_utf8_lossy(attr.value.value()).into_owned())
+}
+
+/// read the private key and certificates of a PKCS#12 file, encrypted with
+/// PBES2/AES as current OpenSSL and Windows write, or with the legacy SHA-1
+/// 3DES and RC2 schemes of older exports
+pub fn load_pkcs12(data: &[u8], password: &str) -> Result<Identity> {
+    let pfx = Pfx::from_der(data).context("Not a PKCS#12 file")?;
+    anyhow::ensure!(
+        pfx.auth_safe.content_type == DATA,
+        "Public-key protected PKCS#12 files aren't supported"
+    );
+    let auth_safe = pfx.auth_safe.content.decode_as::<OctetString>()?;
+    if let Some(mac) = &pfx.mac_data {
+        verify_mac(mac, auth_safe.as_bytes(), password)?;
+    }
+
+    let mut keys = Vec::new();
+    let mut certs = Vec::new();
+    for info in Vec::<ContentInfo>::from_der(auth_safe.as_bytes())? {
+        let safe_contents = if info.content_type == DATA {
+            info.content.decode_as::<OctetString>()?.into_bytes()
+        } else if info.content_type == ENCRYPTED_DATA {
+            let encrypted = info.content.decode_as::<EncryptedData>()?.enc_content_info;
+            let data = encrypted.encrypted_content.context("Empty encrypted content")?;
+            decrypt(&encrypted.content_enc_alg, data.as_bytes(), password)?
+        } else {
+            bail!("Unsupported PKCS#12 content type {}", info.content_type);
+        };
+        read_bags(&safe_contents, password, &mut keys, &mut certs)?;
+    }
+
+    let key = keys.into_iter().next().context("No private key found")?;
+    let signer = certs
+        .iter()
+        .position(|cert| public_key(cert).is_some_and(|public| public == key.to_public_key()))
+        .context("No certificate matches the private key")?;
+    let cert = certs.remove(signer);
+    let name = common_name(&cert.tbs_certificate.subject).unwrap_or_else(|| "unknown".into());
+    certs.insert(0, cert);
+    Ok(Identity { key, certs, name })
+}
+
+fn read_bags(
+    safe_contents: &[u8],
+    password: &str,
+    keys: &mut Vec<RsaPrivateKey>,
+    certs: &mut Vec<Certificate>,
+) -> Result<()> {
+    for bag in SafeContents::from_der(safe_contents)? {
+        // the value is kept with its [0] wrapper
+        let value = Any::from_der(&bag.bag_value)?;
+        match bag.bag_id {
+            pkcs12::PKCS_12_KEY_BAG_OID => keys.push(parse_private_key(value.value())?),
+            pkcs12::PKCS_12_PKCS8_KEY_BAG_OID => {
+                let encrypted = EncryptedPrivateKeyInfo::from_der(value.value())?;
+                let data = encrypted.encrypted_data.as_bytes();
+                let plain = decrypt(&encrypted.encryption_algorithm, data, password)?;
+                keys.push(parse_private_key(&plain)?);
+            }
+            pkcs12::PKCS_12_CERT_BAG_OID => {
+                let bag = CertBag::from_der(value.value())?;
+                if bag.cert_id == pkcs12::PKCS_12_X509_CERT_OID {
+                    certs.push(Certificate::from_der(bag.cert_value.as_bytes())?);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn parse_private_key(pkcs8: &[u8]) -> Result<RsaPrivateKey> {
+    let algorithm = PrivateKeyInfo::try_from(pkcs8)?.algorithm.oid;
+    anyhow::ensure!(
+        algorithm == RSA_ENCRYPTION,
+        "Only RSA keys are supported (the key is {})",
+        algorithm
+    );
+    Ok(RsaPrivateKey::from_pkcs8_der(pkcs8)?)
+}
+
+fn wrong_password<E>(_: E) -> anyhow::Error {
+    anyhow::anyhow!("Wrong password for the PKCS#12 file")
+}
+
+/// PBES2 (PBKDF2 with HMAC-SHA2, AES-CBC) or a legacy PKCS#12 PBE scheme
+fn decrypt(algorithm: &AlgorithmIdentifierOwned, data: &[u8], password: &str) -> Result<Vec<u8>> {
+    if algorithm.oid == PBES2 {
+        let der = algorithm.to_der()?;
+        let scheme = pkcs5::EncryptionScheme::from_der(&der)?;
+        return scheme.decrypt(password, data).map_err(wrong_password);
+    }
+    let params: Pkcs12PbeParams = algorithm
+        .parameters
+        .as_ref()
+        .with_context(|| format!("Unsupported PKCS#12 encryption {}", algorithm.oid))?
+        .decode_as()?;
+    legacy_decrypt(algorithm.oid, &params, data, password)
+}
+
+/// the SHA-1 based PBE schemes of RFC 7292 appendix C: 3DES, and RC2 with a 128
+/// or 40 bit key, as older OpenSSL and Windows exports use
+fn legacy_decrypt(
+    scheme: ObjectIdentifier,
+    params: &Pkcs12PbeParams,
+    data: &[u8],
+    password: &str,
+) -> Result<Vec<u8>> {
+    use cbc::cipher::block_padding::Pkcs7;
+    use cbc::cipher::{BlockDecryptMut, InnerIvInit, KeyIvInit};
+
+    let derive = |kind, len| {
+        let (salt, rounds) = (params.salt.as_bytes(), params.iterations);
+        derive_key_utf8::<Sha1>(password, salt, kind, rounds, len)
+    };
+    let key_len = match scheme {
+        pkcs12::PKCS_12_PBE_WITH_SHAAND3_KEY_TRIPLE_DES_CBC => 24,
+        pkcs12::PKCS_12_PBE_WITH_SHAAND2_KEY_TRIPLE_DES_CBC => 16,
+        pkcs12::PKCS_12_PBE_WITH_SHAAND128_BIT_RC2_CBC => 16,
+        pkcs12::PKCS_12_PBEWITH_SHAAND40_BIT_RC2_CBC => 5,
+        other => bail!("Unsupported PKCS#12 encryption {}", other),
+    };
+    let key = derive(Pkcs12KeyType::EncryptionKey, key_len)?;
+    let iv = derive(Pkcs12KeyType::Iv, 8)?;
+    let plain = match key_len {
+        24 => cbc::Decryptor::<des::TdesEde3>::new_from_slices(&key, &iv)?
+            .decrypt_padded_vec_mut::<Pkcs7>(data),
+        16 if scheme == pkcs12::PKCS_12_PBE_WITH_SHAAND2_KEY_TRIPLE_DES_CBC => {
+            cbc::Decryptor::<des::TdesEde2>::new_from_slices(&key, &iv)?
+                .decrypt_padded_vec_mut::<Pkcs7>(data)
+        }
+        _ => {
+            let rc2 = rc2::Rc2::new_with_eff_key_len(&key, key_len * 8);
+            cbc::Decryptor::inner_iv_slice_init(rc2, &iv)?.decrypt_padded_vec_mut::<Pkcs7>(data)
+        }
+    };
+    plain.map_err(wrong_password)
+}
+
+/// check the file's integrity MAC, which also tells a wrong password apart
+fn verify_mac(mac: &pkcs12::mac_data::MacData, content: &[u8], password: &str) -> Result<()> {
+    let (salt, rounds) = (mac.mac_salt.as_bytes(), mac.iterations);
+    macro_rules! check {
+        ($hash:ty) => {{
+            let len = <$hash as sha2::Digest>::output_size();
+            let key = derive_key_utf8::<$hash>(password, salt, Pkcs12KeyType::Mac, rounds, len)?;
+            let mut hmac = <Hmac<$hash> as Mac>::new_from_slice(&key)?;
+            hmac.update(content);
+            hmac.verify_slice(mac.mac.digest.as_bytes()).map_err(wrong_password)
+        }};
+    }
+    let oid = mac.mac.algorithm.oid;
+    match hash_from_oid(&oid) {
+        _ if oid == SHA1 => check!(Sha1),
+        Some(Hash::Sha256) => check!(Sha256),
+        Some(Hash::Sha384) => check!(Sha384),
+        Some(Hash::Sha512) => check!(Sha512),
+        None => bail!("Unsupported PKCS#12 MAC {}", oid),
+    }
+}
+
+/// a detached CMS SignedData (RFC 5652) over `data`, as adbe.pkcs7.detached
+/// signatures carry: SHA-256, RSA, signing time and the whole certificate chain
+pub fn sign_detached(identity: &Identity, data: &[u8], time: DateTime<Utc>) -> Result<Vec<u8>> {
+    signed_data(identity, &crypto::sha256(data), time, |attrs| identity.sign(attrs))
+}
+
+/// size of the SignedData `sign_detached` makes, known before the data is
+pub fn signature_size(identity: &Identity, time: DateTime<Utc>) -> Result<usize> {
+    let blank = signed_data(identity, &[0; 32], time, |_| vec![0; identity.key_len()])?;
+    Ok(blank.len())
+}
+
+fn signed_data(
+    identity: &Identity,
+    digest: &[u8],
+    time: DateTime<Utc>,
+    sign: impl Fn(&[u8]) -> Vec<u8>,
+) -> Result<Vec<u8>> {
+    let cert = &identity.certs[0];
+    let seconds = u64::try_from(time.timestamp()).context("Signing time before 1970")?;
+    let utc_time = UtcTime::from_unix_duration(std::time::Duration::from_secs(seconds))?;
+    let attribute = |oid, value: Any| -> Result<Attribute> {
+        Ok(Attribute {
+            oid,
+            values: SetOfVec::try_from(vec![value])?,
+        })
+    };
+    let attrs = SetOfVec::try_from(vec![
+        attribute(CONTENT_TYPE, Any::encode_from(&DATA)?)?,
+        attribute(SIGNING_TIME, Any::encode_from(&utc_time)?)?,
+        attribute(MESSAGE_DIGEST, Any::encode_from(&OctetString::new(digest)?)?)?,
+    ])?;
+    // signed as a SET, stored as [0] IMPLICIT
+    let signature = sign(&attrs.to_der()?);
+    let signer_info = SignerInfo {
+        version: CmsVersion::V1,
+        sid: SignerIdentifier::IssuerAndSerialNumber(IssuerAndSerialNumber {
+            issuer: cert.tbs_certificate.issuer.clone(),
+            serial_number: cert.tbs_certificate.serial_number.clone(),
+        }),
+        digest_alg: algorithm(hash_oid(Hash::Sha256)),
+        signed_attrs: Some(attrs),
+        signature_algorithm: algorithm(RSA_ENCRYPTION),
+        signature: OctetString::new(signature)?,
+        unsigned_attrs: None,
+    };
+    let certs = identity.certs.iter().cloned().map(CertificateChoices::Certificate);
+    let signed_data = SignedData {
+        version: CmsVersion::V1,
+        digest_algorithms: SetOfVec::try_from(vec![algorithm(hash_oid(Hash::Sha256))])?,
+        encap_content_info: EncapsulatedContentInfo {
+            econtent_type: DATA,
+            econtent: None,
+        },
+        certificates: Some(CertificateSet(SetOfVec::try_from(certs.collect::<Vec<_>>())?)),
+        crls: None,
+        signer_infos: SignerInfos(SetOfVec::try_from(vec![signer_info])?),
+    };
+    let info = ContentInfo {
+        content_type: SIGNED_DATA,
+        content: Any::encode_from(&signed_data)?,
+    };
+    Ok(info.to_der()?)
+}
+
+/// who signed, as the signature says
+#[derive(Debug)]
+pub struct Signer {
+    pub name: String,
+    pub signing_time: Option<String>,
+}
+
+/// check a detached CMS signature over `data`: the digest, and the RSA signature
+/// against the signer's certificate. the certificate itself isn't checked against
+/// any trusted root; errors say why the signature doesn't hold
+pub fn verify_detached(cms: &[u8], data: &[u8]) -> Result<Signer> {
+    // /Contents is zero-padded past the end of the SignedData
+    let info = ContentInfo::decode(&mut SliceReader::new(cms)?)?;
+    anyhow::ensure!(info.content_type == SIGNED_DATA, "Not a CMS signature");
+    let signed = info.content.decode_as::<SignedData>()?;
+    anyhow::ensure!(
+        signed.encap_content_info.econtent.is_none(),
+        "Signature isn't detached from the document"
+    );
+    let certs: Vec<&Certificate> = signed
+        .certificates
+        .iter()
+        .flat_map(|set| set.0.iter())
+        .filter_map(|choice| match choice {
+            CertificateChoices::Certificate(cert) => Some(cert),
+            _ => None,
+        })
+        .collect();
+    let signer = signed.signer_infos.0.iter().next().context("Signature has no signer")?;
+    let hash = hash_from_oid(&signer.digest_alg.oid)
+        .with_context(|| format!("Unsupported digest algorithm {}", signer.digest_alg.oid))?;
+    let algorithm = signer.signature_algorithm.oid;
+    anyhow::ensure!(
+        algorithm == RSA_ENCRYPTION || SHA_WITH_RSA.contains(&algorithm),
+        "Unsupported signature algorithm {}",
+        algorithm
+    );
+
+    let digest = hash.digest(data);
+    let mut signing_time = None;
+    let signed_bytes = match &signer.signed_attrs {
+        Some(attrs) => {
+            let mut message_digest = None;
+            for attr in attrs.iter() {
+                let Some(value) = attr.values.iter().next() else {
+                    continue;
+                };
+                if attr.oid == MESSAGE_DIGEST {
+                    message_digest = Some(value.decode_as::<OctetString>()?);
+                } else if attr.oid == SIGNING_TIME {
+                    let time = value.to_der().and_then(|der| Time::from_der(&der));
+                    signing_time = time.ok().map(format_time);
+                }
+            }
+            let message_digest = message_digest.context("Signature has no message digest")?;
+            anyhow::ensure!(
+                message_digest.as_bytes() == digest,
+                "Document was changed after signing"
+            );
+            attrs.to_der()?
+        }
+        None => data.to_vec(),
+    };
+
+    // by issuer and serial number; any certificate for a subject key identifier
+    let candidates: Vec<&Certificate> = match &signer.sid {
+        SignerIdentifier::IssuerAndSerialNumber(id) => certs
+            .iter()
+            .copied()
+            .filter(|c| {
+                c.tbs_certificate.issuer == id.issuer
+                    && c.tbs_certificate.serial_number == id.serial_number
+            })
+            .collect(),
+        SignerIdentifier::SubjectKeyIdentifier(_) => certs.clone(),
+    };
+    anyhow::ensure!(!candidates.is_empty(), "Signer's certificate isn't in the signature");
+    let signature = signer.signature.as_bytes();
+    let cert = candidates
+        .into_iter()
+        .find(|cert| {
+            public_key(cert).is_some_and(|key| rsa_verify(&key, hash, &signed_bytes, signature))
+        })
+        .context("Signature doesn't match the signer's certificate")?;
+    Ok(Signer {
+        name: common_name(&cert.tbs_certificate.subject).unwrap_or_else(|| "unknown".into()),
+        signing_time,
+    })
+}
+
+/// PKCS#1 v1.5 verification; the rsa crate rejects a signature value that isn't
+/// below the modulus
+fn rsa_verify(key: &RsaPublicKey, hash: Hash, signed: &[u8], signature: &[u8]) -> bool {
+    let digest = hash.digest(signed);
+    // some signers leave out the NULL algorithm parameters
+    [Some(Any::null()), None].into_iter().any(|parameters| {
+        let info = DigestInfo {
+            algorithm: AlgorithmIdentifierOwned {
+                oid: hash_oid(hash),
+                parameters,
+            },
+            digest: OctetString::new(digest.as_slice()).expect("digest fits"),
+        };
+        let Ok(encoded) = info.to_der() else {
+            return false;
+        };
+        let scheme = Pkcs1v15Sign {
+            hash_len: Some(digest.len()),
+            prefix: encoded[..encoded.len() - digest.len()].into(),
+        };
+        key.verify(scheme, &digest, signature).is_ok()
+    })
+}
+
+/// UTCTime or GeneralizedTime as "2024-05-01 12:00:00 UTC"
+fn format_time(time: Time) -> String {
+    let seconds = time.to_unix_duration().as_secs() as i64;
+    DateTime::from_timestamp(seconds, 0)
+        .map(|t| t.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+        .unwrap_or_else(|| time.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const P12: &[u8] = include_bytes!("../tests/fixtures/signer.p12");
+
+    #[test]
+    fn pkcs12_needs_the_right_password() {
+        let identity = load_pkcs12(P12, "ovid-test").unwrap();
+        assert_eq!(identity.name, "Ovid Test Signer");
+        assert_eq!(identity.key_len(), 256);
+        let err = load_pkcs12(P12, "wrong").err().unwrap();
+        assert!(err.to_string().contains("Wrong password"), "{}", err);
+    }
+
+    #[test]
+    fn legacy_pkcs12_files_load() {
+        // openssl pkcs12 -export -legacy: RC2-40 certificates, a 3DES key, SHA-1 MAC
+        let legacy = include_bytes!("../tests/fixtures/signer-legacy.p12");
+        let identity = load_pkcs12(legacy, "ovid-test").unwrap();
+        assert_eq!(identity.name, "Ovid Test Signer");
+        let err = load_pkcs12(legacy, "wrong").err().unwrap();
+        assert!(err.to_string().contains("Wrong password"), "{}", err);
+    }
+
+    #[test]
+    fn detached_signature_round_trip() {
+        let identity = load_pkcs12(P12, "ovid-test").unwrap();
+        let time = DateTime::parse_from_rfc3339("2024-05-01T12:00:00Z").unwrap().to_utc();
+        let cms = sign_detached(&identity, b"signed bytes", time).unwrap();
+        assert_eq!(cms.len(), signature_size(&identity, time).unwrap());
+
+        let signer = verify_detached(&cms, b"signed bytes").unwrap();
+        assert_eq!(signer.name, "Ovid Test Signer");
+        assert_eq!(signer.signing_time.as_deref(), Some("2024-05-01 12:00:00 UTC"));
+        let err = verify_detached(&cms, b"signed bytez").unwrap_err();
+        assert!(err.to_string().contains("changed after signing"), "{}", err);
+    }
+
+    #[test]
+    fn signature_values_outside_the_modulus_are_rejected() {
+        let identity = load_pkcs12(P12, "ovid-test").unwrap();
+        let key = identity.key.to_public_key();
+        let signature = identity.sign(b"data");
+        assert!(rsa_verify(&key, Hash::Sha256, b"data", &signature));
+        // s + n is the same value mod n, but not a valid signature
+        let shifted = (rsa::BigUint::from_bytes_be(&signature) + key.n()).to_bytes_be();
+        assert!(!rsa_verify(&key, Hash::Sha256, b"data", &shifted));
+        assert!(!rsa_verify(&key, Hash::Sha256, b"data", &key.n().to_bytes_be()));
+    }
+}
@@ -0,0 +1,261 @@
+use anyhow::{ensure, Result};
+use lopdf::{dictionary, Object, ObjectId, Stream};
+use std::path::Path;
+use tracing::info;
+
+use crate::parse::{Orientation, PageSize};
+use crate::pdf;
+
+pub struct PosterOptions {
+    /// sheet size to print on
+    pub page_size: PageSize,
+    pub orientation: Orientation,
+    /// size of the poster relative to the page
+    pub scale: f32,
+    /// how far each sheet repeats its neighbor's edge, in points
+    pub overlap: f32,
+    /// unprintable border of each sheet, in points; marks and labels go here
+    pub margin: f32,
+    /// draw crop and overlap marks and label each sheet
+    pub marks: bool,
+    pub quiet: bool,
+}
+
+/// how one page is cut up: sheet size and the grid of sheets
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Tiling {
+    width: f32,
+    height: f32,
+    cols: usize,
+    rows: usize,
+}
+
+/// print each page of a PDF as a poster across several sheets: the page is
+/// scaled, then cut into overlapping tiles, one per sheet, left to right and top
+/// to bottom. tiles show the page as a form XObject, so everything stays vector
+pub fn poster_pdf(input: &Path, output: &Path, opts: &PosterOptions) -> Result<()> {
+    let start = std::time::Instant::now();
+    let mut doc = pdf::load(input)?;
+    pdf::inherit_page_attributes(&mut doc);
+    let page_ids: Vec<ObjectId> = doc.get_pages().into_values().collect();
+    ensure!(!page_ids.is_empty(), "{} has no pages", input.display());
+    let (sheet_w, sheet_h) = opts.page_size.dimensions_pt();
+    ensure!(
+        sheet_w.min(sheet_h) - 2.0 * opts.margin > opts.overlap,
+        "Margin and overlap leave no room on the sheet"
+    );
+
+    let font_id = doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Helvetica",
+    });
+    let pages_id = doc.catalog()?.get(b"Pages").and_then(Object::as_reference)?;
+    let mut sheet_ids = Vec::new();
+    for (n, &page_id) in page_ids.iter().enumerate() {
+        let (form_id, w, h) = pdf::page_form(&mut doc, page_id)?;
+        let (w, h) = (w * opts.scale, h * opts.scale);
+        let tiling = choose_tiling(opts, (w, h));
+        let printable = (tiling.width - 2.0 * opts.margin, tiling.height - 2.0 * opts.margin);
+        let step = (printable.0 - opts.overlap, printable.1 - opts.overlap);
+        // the area the sheets cover together, with the page centered on it
+        let covered = (
+            tiling.cols as f32 * step.0 + opts.overlap,
+            tiling.rows as f32 * step.1 + opts.overlap,
+        );
+        let offset = ((covered.0 - w) / 2.0, (covered.1 - h) / 2.0);
+
+        for row in 0..tiling.rows {
+            for col in 0..tiling.cols {
+                // bottom left of this tile in the covered area
+                let x0 = col as f32 * step.0;
+                let y0 = covered.1 - printable.1 - row as f32 * step.1;
+                let (tx, ty) = (offset.0 - x0 + opts.margin, offset.1 - y0 + opts.margin);
+                let mut content = format!(
+                    "q {} {} {} {} re W n\nq {} 0 0 {} {} {} cm /P Do Q\nQ\n",
+                    fmt(opts.margin),
+                    fmt(opts.margin),
+                    fmt(printable.0),
+                    fmt(printable.1),
+                    fmt(opts.scale),
+                    fmt(opts.scale),
+                    fmt(tx),
+                    fmt(ty),
+                );
+                if opts.marks && opts.margin >= 6.0 {
+                    content.push_str(&marks(opts, tiling, col, row));
+                    let label = format!(
+                        "page {}: row {} of {}, column {} of {}",
+                        n + 1,
+                        row + 1,
+                        tiling.rows,
+                        col + 1,
+                        tiling.cols
+                    );
+                    let size = (opts.margin / 3.0).min(8.0);
+                    content.push_str(&format!(
+                        "BT /Helv {} Tf {} {} Td ({}) Tj ET\n",
+                        fmt(size),
+                        fmt(opts.margin + 6.0),
+                        fmt((opts.margin - size) / 2.0),
+                        label
+                    ));
+                }
+                let mut stream = Stream::new(dictionary! {}, content.into_bytes());
+                stream.compress()?;
+                let content_id = doc.add_object(stream);
+                let media_box = [0.0, 0.0, tiling.width, tiling.height];
+                sheet_ids.push(doc.add_object(dictionary! {
+                    "Type" => "Page",
+                    "Parent" => pages_id,
+                    "MediaBox" => media_box.map(Object::Real).to_vec(),
+                    "Resources" => dictionary! {
+                        "XObject" => dictionary! { "P" => form_id },
+                        "Font" => dictionary! { "Helv" => font_id },
+                    },
+                    "Contents" => content_id,
+                }));
+            }
+        }
+        if !opts.quiet {
+            info!(
+                "Page {}: {} x {} sheet(s) ({})",
+                n + 1,
+                tiling.cols,
+                tiling.rows,
+                if tiling.width > tiling.height { "landscape" } else { "portrait" }
+            );
+        }
+    }
+    pdf::set_page_order(&mut doc, &sheet_ids)?;
+    pdf::save(&mut doc, output)?;
+
+    if !opts.quiet {
+        info!(
+            "Done. {} sheet(s) saved in {:.2}s",
+            sheet_ids.len(),
+            start.elapsed().as_secs_f64()
+        );
+    }
+    Ok(())
+}
+
+/// sheets needed to cover `length` with `printable` per sheet, each sharing
+/// `overlap` with the next
+fn tiles(length: f32, printable: f32, overlap: f32) -> usize {
+    if length <= printable {
+        return 1;
+    }
+    ((length - overlap) / (printable - overlap)).ceil() as usize
+}
+
+/// the sheet orientation needing fewest sheets for a page of `size`; portrait
+/// on a tie
+fn choose_tiling(opts: &PosterOptions, size: (f32, f32)) -> Tiling {
+    let (w, h) = opts.page_size.dimensions_pt();
+    let (short, long) = (w.min(h), w.max(h));
+    let sheets = match opts.orientation {
+        Orientation::Portrait => vec![(short, long)],
+        Orientation::Landscape => vec![(long, short)],
+        Orientation::Auto => vec![(short, long), (long, short)],
+    };
+    sheets
+        .into_iter()
+        .map(|(width, height)| Tiling {
+            width,
+            height,
+            cols: tiles(size.0, width - 2.0 * opts.margin, opts.overlap),
+            rows: tiles(size.1, height - 2.0 * opts.margin, opts.overlap),
+        })
+        .min_by_key(|t| t.cols * t.rows)
+        .unwrap()
+}
+
+/// crop marks at the corners of the printable area, and ticks in the margin
+/// where the neighboring sheets' edges fall
+fn marks(opts: &PosterOptions, tiling: Tiling, col: usize, row: usize) -> String {
+    let m = opts.margin;
+    let (right, top) = (tiling.width - m, tiling.height - m);
+    let (gap, length) = (2.0, (m - 4.0).min(18.0));
+    let mut lines = Vec::new();
+    for (x, dx) in [(m, -1.0), (right, 1.0)] {
+        for (y, dy) in [(m, -1.0), (top, 1.0)] {
+            lines.push((x + dx * gap, y, x + dx * (gap + length), y));
+            lines.push((x, y + dy * gap, x, y + dy * (gap + length)));
+        }
+    }
+    let tick = length / 2.0;
+    let mut xs = Vec::new();
+    if col > 0 {
+        xs.push(m + opts.overlap);
+    }
+    if col + 1 < tiling.cols {
+        xs.push(right - opts.overlap);
+    }
+    for x in xs {
+        lines.push((x, m - gap, x, m - gap - tick));
+        lines.push((x, top + gap, x, top + gap + tick));
+    }
+    let mut ys = Vec::new();
+    if row > 0 {
+        ys.push(top - opts.overlap);
+    }
+    if row + 1 < tiling.rows {
+        ys.push(m + opts.overlap);
+    }
+    for y in ys {
+        lines.push((m - gap, y, m - gap - tick, y));
+        lines.push((right + gap, y, right + gap + tick, y));
+    }
+    let mut out = String::from("q 0.25 w 0 G\n");
+    for (x0, y0, x1, y1) in lines {
+        out.push_str(&format!("{} {} m {} {} l S\n", fmt(x0), fmt(y0), fmt(x1), fmt(y1)));
+    }
+    out.push_str("Q\n");
+    out
+}
+
+/// compact number for content streams
+fn fmt(v: f32) -> String {
+    let s = format!("{:.3}", v);
+    s.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opts(orientation: Orientation) -> PosterOptions {
+        PosterOptions {
+            page_size: PageSize::A4,
+            orientation,
+            scale: 1.0,
+            overlap: 20.0,
+            margin: 20.0,
+            marks: true,
+            quiet: true,
+        }
+    }
+
+    #[test]
+    fn tile_counts() {
+        assert_eq!(tiles(500.0, 555.0, 20.0), 1);
+        assert_eq!(tiles(1090.0, 555.0, 20.0), 2);
+        assert_eq!(tiles(1091.0, 555.0, 20.0), 3);
+    }
+
+    #[test]
+    fn fewest_sheets() {
+        // A0 is 2384 x 3370 pt: 5 x 5 portrait A4 sheets, or 4 x 7 landscape
+        let a0 = (2383.94, 3370.39);
+        let tiling = choose_tiling(&opts(Orientation::Auto), a0);
+        assert_eq!((tiling.cols, tiling.rows), (5, 5));
+        assert!(tiling.width < tiling.height);
+        let tiling = choose_tiling(&opts(Orientation::Landscape), a0);
+        assert_eq!((tiling.cols, tiling.rows), (4, 7));
+        // a wide banner goes on landscape sheets
+        let tiling = choose_tiling(&opts(Orientation::Auto), (2400.0, 500.0));
+        assert_eq!((tiling.cols, tiling.rows), (4, 1));
+        assert!(tiling.width > tiling.height);
+    }
+}
@@ -0,0 +1,163 @@
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+/// something a split or merge reports as it goes. `page` is 1-based: the page
+/// of the PDF being split, or the image's place in the merge. page events come
+/// from the worker threads, in no particular order
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum Event<'a> {
+    /// the run knows how many pages it has to do
+    Start { total: usize },
+    /// a worker picked up a page to render, or an image to decode
+    PageStarted { page: usize, total: usize },
+    /// a page was saved, from or to `file`: the image written by a split, or
+    /// read by a merge. `done` counts pages finished, saved or failed
+    PageDone {
+        page: usize,
+        done: usize,
+        total: usize,
+        #[serde(skip_serializing_if = "Option::is_none", serialize_with = "display")]
+        file: Option<&'a Path>,
+    },
+    /// a page failed, and the run skips it or stops
+    PageFailed {
+        page: usize,
+        done: usize,
+        total: usize,
+        #[serde(serialize_with = "alternate")]
+        error: &'a anyhow::Error,
+    },
+    /// `bytes` went out for `page`: a split's image file, or a merge's page
+    /// and its image in the PDF
+    BytesWritten { page: usize, bytes: u64 },
+    /// something the run logged as a warning, about `page` if it's known
+    Warning {
+        page: Option<usize>,
+        message: &'a str,
+    },
+    /// the run is over: `saved` pages saved and `failed` skipped
+    Finish { saved: usize, failed: usize },
+}
+
+impl Event<'_> {
+    /// the event as one line of JSON, `{"event":"page_done","page":12,...}`
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("events are plain objects")
+    }
+}
+
+/// a path as it displays, whether or not it's UTF-8
+fn display<S: Serializer>(file: &Option<&Path>, s: S) -> Result<S::Ok, S::Error> {
+    match file {
+        Some(file) => s.collect_str(&file.display()),
+        None => s.serialize_none(),
+    }
+}
+
+/// an error with its causes, `{:#}`
+fn alternate<S: Serializer>(error: &&anyhow::Error, s: S) -> Result<S::Ok, S::Error> {
+    s.collect_str(&format_args!("{:#}", error))
+}
+
+/// a callback told how a run is going. pages may finish on several threads, so
+/// calls can come from any of them
+#[derive(Clone)]
+pub struct Progress(Arc<dyn Fn(&Event) + Send + Sync>);
+
+impl Progress {
+    /// called as (pages done, pages in total) after each page, saved or failed
+    pub fn new(f: impl Fn(usize, usize) + Send + Sync + 'static) -> Self {
+        Progress::events(move |event| match *event {
+            Event::PageDone { done, total, .. } | Event::PageFailed { done, total, .. } => {
+                f(done, total)
+            }
+            _ => {}
+        })
+    }
+
+    /// called with every event
+    pub fn events(f: impl Fn(&Event) + Send + Sync + 'static) -> Self {
+        Progress(Arc::new(f))
+    }
+
+    pub fn emit(&self, event: Event) {
+        (self.0)(&event)
+    }
+}
+
+/// emit `event` to the run's callback, if it has one
+pub(crate) fn emit(progress: &Option<Progress>, event: Event) {
+    if let Some(progress) = progress {
+        progress.emit(event);
+    }
+}
+
+/// log a warning, and pass it to the run's callback
+pub(crate) fn warn(progress: &Option<Progress>, page: Option<usize>, message: &str) {
+    tracing::warn!("{}", message);
+    emit(progress, Event::Warning { page, message });
+}
+
+impl fmt::Debug for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Progress(..)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn events_as_json_lines() {
+        let file = Path::new("out/a_0012.png");
+        let done = Event::PageDone {
+            page: 12,
+            done: 5,
+            total: 300,
+            file: Some(file),
+        };
+        assert_eq!(
+            done.to_json(),
+            r#"{"event":"page_done","page":12,"done":5,"total":300,"file":"out/a_0012.png"}"#
+        );
+        let error = anyhow::anyhow!("bad stream").context("Failed to render");
+        let failed = Event::PageFailed {
+            page: 3,
+            done: 6,
+            total: 300,
+            error: &error,
+        };
+        assert!(failed.to_json().ends_with(r#""error":"Failed to render: bad stream"}"#));
+    }
+
+    #[test]
+    fn counts_only_see_pages() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let progress = Progress::new({
+            let seen = seen.clone();
+            move |done, total| seen.lock().unwrap().push((done, total))
+        });
+        let error = anyhow::anyhow!("unreadable");
+        progress.emit(Event::Start { total: 2 });
+        progress.emit(Event::PageDone {
+            page: 2,
+            done: 1,
+            total: 2,
+            file: None,
+        });
+        progress.emit(Event::PageFailed {
+            page: 1,
+            done: 2,
+            total: 2,
+            error: &error,
+        });
+        progress.emit(Event::Finish { saved: 1, failed: 1 });
+        assert_eq!(*seen.lock().unwrap(), [(1, 2), (2, 2)]);
+    }
+}
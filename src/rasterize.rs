@@ -0,0 +1,82 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+use tracing::{debug, info};
+
+use crate::merge::{self, MergeOptions, PreparedRaster};
+use crate::split;
+
+pub struct RasterizeOptions {
+    pub dpi: u32,
+    pub gray: bool,
+    /// JPEG-compress pages at this quality instead of lossless deflate
+    pub jpeg_quality: Option<u8>,
+}
+
+/// render every page at `dpi` and rebuild the PDF from the renders, leaving
+/// only pictures of the pages: no text, fonts, scripts, layers or forms.
+/// pages keep their size unless `merge_opts` sets one
+pub fn rasterize_pdf(
+    input: &Path,
+    output: &Path,
+    opts: &RasterizeOptions,
+    merge_opts: &MergeOptions,
+) -> Result<()> {
+    let input_str = input.to_str().context("Invalid path")?;
+    let doc = mupdf::Document::open(input_str)
+        .with_context(|| format!("Failed to open {}", input.display()))?;
+    let num_pages = doc.page_count()?;
+    anyhow::ensure!(num_pages > 0, "{} has no pages", input.display());
+    if !merge_opts.quiet {
+        info!(
+            "Rasterizing {} ({} page(s)) at {} DPI -> {}",
+            input.display(),
+            num_pages,
+            opts.dpi,
+            output.display()
+        );
+    }
+    let start = std::time::Instant::now();
+
+    // rendered on the pool from one parse of the document, and written out in
+    // order as they come in
+    let indices: Vec<i32> = (0..num_pages).collect();
+    split::pages_in_order(
+        &doc,
+        &indices,
+        |i, list| render_page(&list, i, opts),
+        |pages| merge::write_raster_pages(output, indices.len(), pages, merge_opts),
+    )?;
+
+    if !merge_opts.quiet {
+        info!("Done. PDF saved in {:.2}s", start.elapsed().as_secs_f64());
+    }
+    Ok(())
+}
+
+fn render_page(
+    list: &mupdf::DisplayList,
+    index: i32,
+    opts: &RasterizeOptions,
+) -> Result<PreparedRaster> {
+    let started = std::time::Instant::now();
+    let scale = opts.dpi as f32 / 72.0;
+    let matrix = mupdf::Matrix::new_scale(scale, scale);
+    let colorspace = if opts.gray {
+        mupdf::Colorspace::device_gray()
+    } else {
+        mupdf::Colorspace::device_rgb()
+    };
+    let pixmap = list
+        .to_pixmap(&matrix, &colorspace, false)
+        .with_context(|| format!("Failed to render page {}", index + 1))?;
+    let (width, height) = (pixmap.width(), pixmap.height());
+    let samples = split::packed_samples(&pixmap, if opts.gray { 1 } else { 3 });
+    let raster =
+        merge::prepare_raster(&samples, width, height, opts.gray, opts.dpi, opts.jpeg_quality);
+    debug!(
+        worker = rayon::current_thread_index(),
+        ms = started.elapsed().as_millis() as u64,
+        "rendered"
+    );
+    raster
+}
@@ -0,0 +1,469 @@
+use anyhow::{bail, Context, Result};
+use lopdf::{dictionary, Document, Object, ObjectId, Stream};
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::num::NonZeroU32;
+use std::path::Path;
+use tracing::{info, warn};
+
+use crate::parse::{parse_page_ranges, PageRect, RedactRegion};
+use crate::pdf;
+use crate::split::{self, encode_jpg};
+
+pub struct RedactOptions {
+    /// resolution redacted pages are rendered at
+    pub dpi: u32,
+    /// JPEG-compress redacted pages at this quality instead of lossless deflate
+    pub jpeg_quality: Option<u8>,
+    pub quiet: bool,
+}
+
+/// a page to redact: where its visible area sits in page space, and the boxes
+/// to black out
+struct PageJob {
+    number: u32,
+    page_id: ObjectId,
+    /// maps the upright view of the page into page space
+    view: [f32; 6],
+    width: f32,
+    height: f32,
+    rects: Vec<PageRect>,
+}
+
+/// a redacted page's render
+struct Raster {
+    width: u32,
+    height: u32,
+    samples: Vec<u8>,
+}
+
+/// black out regions of pages for real: each page with a region is rendered,
+/// the regions painted black, and the page replaced by that image, so the text
+/// and graphics under them are gone rather than covered. other pages are kept
+/// as they are
+pub fn redact_pdf(
+    input: &Path,
+    output: &Path,
+    regions: &[RedactRegion],
+    opts: &RedactOptions,
+) -> Result<()> {
+    let mut doc = pdf::load(input)?;
+    pdf::inherit_page_attributes(&mut doc);
+    let pages = doc.get_pages();
+    let mut by_page: BTreeMap<u32, Vec<PageRect>> = BTreeMap::new();
+    for region in regions {
+        for index in parse_page_ranges(&region.pages, pages.len() as i32)? {
+            by_page.entry(index as u32 + 1).or_default().push(region.rect);
+        }
+    }
+    let jobs: Vec<PageJob> = by_page
+        .into_iter()
+        .map(|(number, rects)| {
+            let page_id = pages[&number];
+            let (view, width, height) = pdf::display_transform(&doc, page_id);
+            PageJob {
+                number,
+                page_id,
+                view,
+                width,
+                height,
+                rects,
+            }
+        })
+        .collect();
+
+    let input_str = input.to_str().context("Invalid path")?;
+    let render_doc = mupdf::Document::open(input_str)
+        .with_context(|| format!("Failed to open {}", input.display()))?;
+    let indices: Vec<i32> = jobs.iter().map(|job| job.number as i32 - 1).collect();
+    let mut rasters = Vec::with_capacity(jobs.len());
+    split::pages_in_order(
+        &render_doc,
+        &indices,
+        |i, list| {
+            // jobs are in page order, one per page
+            let at = jobs.binary_search_by_key(&(i as u32 + 1), |job| job.number);
+            let job = &jobs[at.expect("a job per page")];
+            render_redacted(&list, job, opts.dpi)
+        },
+        |done| {
+            for raster in done {
+                rasters.push(raster?);
+            }
+            Ok(())
+        },
+    )?;
+
+    let redacted: BTreeSet<ObjectId> = jobs.iter().map(|job| job.page_id).collect();
+    for job in &jobs {
+        remove_page_fields(&mut doc, job.page_id);
+    }
+    prune_struct_tree(&mut doc, &redacted);
+    for (job, raster) in jobs.iter().zip(rasters) {
+        replace_page(&mut doc, job, raster, opts)?;
+    }
+    // the old content, and anything only it used, goes with it
+    doc.prune_objects();
+    pdf::save(&mut doc, output)?;
+
+    if !opts.quiet {
+        let count: usize = jobs.iter().map(|job| job.rects.len()).sum();
+        let numbers: Vec<String> = jobs.iter().map(|job| job.number.to_string()).collect();
+        info!(
+            "Redacted {} region(s) on page(s) {} -> {}",
+            count,
+            numbers.join(","),
+            output.display()
+        );
+    }
+    Ok(())
+}
+
+/// an entry of a regions file
+#[derive(Deserialize)]
+struct RegionSpec {
+    page: PageSpec,
+    rect: Corners,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged, expecting = "page must be a number from 1 or a page selection")]
+enum PageSpec {
+    Number(NonZeroU32),
+    Selection(String),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged, expecting = "rect must be [x0, y0, x1, y1]")]
+enum Corners {
+    Rect([f32; 4]),
+}
+
+/// regions from a JSON file: [{"page": 2, "rect": [x0, y0, x1, y1]}, ...], where
+/// a page can also be a selection like "1,3-5"
+pub fn read_regions(path: &Path) -> Result<Vec<RedactRegion>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let value = serde_json::from_str(&text).with_context(|| path.display().to_string())?;
+    let serde_json::Value::Array(items) = value else {
+        bail!("{}: expected an array of regions", path.display());
+    };
+    items
+        .into_iter()
+        .enumerate()
+        .map(|(i, item)| {
+            let n = i + 1;
+            let spec: RegionSpec = serde_json::from_value(item)
+                .map_err(|e| anyhow::anyhow!("region {}: {}", n, e))?;
+            let pages = match spec.page {
+                PageSpec::Number(page) => page.to_string(),
+                PageSpec::Selection(pages) if !pages.trim().is_empty() => pages,
+                PageSpec::Selection(_) => {
+                    bail!("region {}: page must be a number from 1 or a page selection", n)
+                }
+            };
+            let Corners::Rect([x0, y0, x1, y1]) = spec.rect;
+            if x0 == x1 || y0 == y1 {
+                bail!("region {}: rect is empty", n);
+            }
+            let rect = PageRect([x0.min(x1), y0.min(y1), x0.max(x1), y0.max(y1)]);
+            Ok(RedactRegion { pages, rect })
+        })
+        .collect()
+}
+
+fn render_redacted(list: &mupdf::DisplayList, job: &PageJob, dpi: u32) -> Result<Raster> {
+    let scale = dpi as f32 / 72.0;
+    let matrix = mupdf::Matrix::new_scale(scale, scale);
+    let pixmap = list
+        .to_pixmap(&matrix, &mupdf::Colorspace::device_rgb(), false)
+        .with_context(|| format!("Failed to render page {}", job.number))?;
+    let (width, height) = (pixmap.width(), pixmap.height());
+    let mut samples = split::packed_samples(&pixmap, 3);
+    for rect in &job.rects {
+        let Some([x0, y0, x1, y1]) = pixel_rect(job, rect, width, height) else {
+            warn!("region {:?} is outside page {}", rect.0, job.number);
+            continue;
+        };
+        for y in y0..y1 {
+            let start = (y as usize * width as usize + x0 as usize) * 3;
+            let end = (y as usize * width as usize + x1 as usize) * 3;
+            samples[start..end].fill(0);
+        }
+    }
+    Ok(Raster {
+        width,
+        height,
+        samples,
+    })
+}
+
+/// a box in page space as pixels of the page's render (origin top left,
+/// exclusive end), rounded outward so nothing at its edges survives; None if it
+/// misses the visible area
+fn pixel_rect(job: &PageJob, rect: &PageRect, width: u32, height: u32) -> Option<[u32; 4]> {
+    let [a, b, c, d, e, f] = job.view;
+    let (sx, sy) = (width as f32 / job.width, height as f32 / job.height);
+    let [x0, y0, x1, y1] = rect.0;
+    let (mut left, mut top, mut right, mut bottom) = (f32::MAX, f32::MAX, f32::MIN, f32::MIN);
+    for (x, y) in [(x0, y0), (x0, y1), (x1, y0), (x1, y1)] {
+        // the view matrix is a rotation and a shift, so its transpose undoes it
+        let u = a * (x - e) + b * (y - f);
+        let v = c * (x - e) + d * (y - f);
+        let (px, py) = (u * sx, (job.height - v) * sy);
+        (left, top) = (left.min(px), top.min(py));
+        (right, bottom) = (right.max(px), bottom.max(py));
+    }
+    let clamp = |v: f32, max: u32| v.clamp(0.0, max as f32) as u32;
+    let (left, right) = (clamp(left.floor(), width), clamp(right.ceil(), width));
+    let (top, bottom) = (clamp(top.floor(), height), clamp(bottom.ceil(), height));
+    (left < right && top < bottom).then_some([left, top, right, bottom])
+}
+
+/// make the page just its render: the image is drawn over the whole page, which
+/// loses its rotation and page boxes, and its annotations and thumbnail, which
+/// could still show what was redacted, are dropped
+fn replace_page(
+    doc: &mut Document,
+    job: &PageJob,
+    raster: Raster,
+    opts: &RedactOptions,
+) -> Result<()> {
+    let mut image = dictionary! {
+        "Type" => "XObject",
+        "Subtype" => "Image",
+        "Width" => raster.width as i64,
+        "Height" => raster.height as i64,
+        "ColorSpace" => "DeviceRGB",
+        "BitsPerComponent" => 8,
+    };
+    let image = match opts.jpeg_quality {
+        Some(quality) => {
+            let mut data = Vec::new();
+            encode_jpg(&raster.samples, raster.width, raster.height, false, quality, &mut data)?;
+            image.set("Filter", "DCTDecode");
+            Stream::new(image, data)
+        }
+        None => {
+            let mut stream = Stream::new(image, raster.samples);
+            stream.compress()?;
+            stream
+        }
+    };
+    let image_id = doc.add_object(image);
+    let content = format!("q {} 0 0 {} 0 0 cm /Im0 Do Q\n", job.width, job.height);
+    let mut content = Stream::new(dictionary! {}, content.into_bytes());
+    content.compress()?;
+    let content_id = doc.add_object(content);
+
+    let page = doc.get_dictionary_mut(job.page_id)?;
+    for key in [
+        b"CropBox".as_slice(),
+        b"BleedBox",
+        b"TrimBox",
+        b"ArtBox",
+        b"Rotate",
+        b"Annots",
+        b"Thumb",
+        b"PieceInfo",
+        b"Group",
+        b"StructParents",
+    ] {
+        page.remove(key);
+    }
+    let media_box = [0.0, 0.0, job.width, job.height];
+    page.set("MediaBox", media_box.map(Object::Real).to_vec());
+    page.set("Resources", dictionary! { "XObject" => dictionary! { "Im0" => image_id } });
+    page.set("Contents", content_id);
+    Ok(())
+}
+
+/// take the page's form fields out of /AcroForm: their /V holds the text the
+/// widgets showed. a field keeps its other widgets, on pages not redacted
+fn remove_page_fields(doc: &mut Document, page_id: ObjectId) {
+    let widgets: Vec<ObjectId> = doc
+        .get_dictionary(page_id)
+        .and_then(|page| page.get(b"Annots"))
+        .and_then(|annots| doc.dereference(annots))
+        .and_then(|(_, annots)| annots.as_array())
+        .map(|annots| annots.iter().filter_map(|a| a.as_reference().ok()).collect())
+        .unwrap_or_default();
+    for widget in widgets {
+        detach_field(doc, widget, 0);
+    }
+}
+
+/// remove a field or widget from its parent's /Kids, or from /Fields and /CO at
+/// the top, and the parent with it once it has no kids left
+fn detach_field(doc: &mut Document, id: ObjectId, depth: usize) {
+    let parent = doc
+        .get_dictionary(id)
+        .and_then(|field| field.get(b"Parent"))
+        .and_then(Object::as_reference)
+        .ok();
+    let not_id = |kid: &Object| kid.as_reference().ok() != Some(id);
+    match parent {
+        Some(parent) => {
+            let kids = doc
+                .get_dictionary_mut(parent)
+                .and_then(|field| field.get_mut(b"Kids"))
+                .and_then(Object::as_array_mut);
+            let Ok(kids) = kids else {
+                return;
+            };
+            kids.retain(not_id);
+            if kids.is_empty() && depth < 32 {
+                detach_field(doc, parent, depth + 1);
+            }
+        }
+        None => {
+            for key in [b"Fields".as_slice(), b"CO"] {
+                if let Some(list) = form_array_mut(doc, key) {
+                    list.retain(not_id);
+                }
+            }
+        }
+    }
+}
+
+/// an array of the document's /AcroForm, direct or not
+fn form_array_mut<'a>(doc: &'a mut Document, key: &[u8]) -> Option<&'a mut Vec<Object>> {
+    let form = doc.catalog().ok()?.get(b"AcroForm").ok()?;
+    let form_id = form.as_reference().ok();
+    let list_id = doc
+        .dereference(form)
+        .ok()
+        .and_then(|(_, form)| form.as_dict().ok()?.get(key).ok()?.as_reference().ok());
+    if let Some(list_id) = list_id {
+        return doc.get_object_mut(list_id).ok()?.as_array_mut().ok();
+    }
+    let form = match form_id {
+        Some(form_id) => doc.get_dictionary_mut(form_id).ok()?,
+        None => doc.catalog_mut().ok()?.get_mut(b"AcroForm").ok()?.as_dict_mut().ok()?,
+    };
+    form.get_mut(key).ok()?.as_array_mut().ok()
+}
+
+/// drop the structure elements of redacted pages from the tagged-PDF structure
+/// tree, with their /ActualText and /Alt. what else points at them (the parent
+/// tree, the ID tree) is left pointing at null objects
+fn prune_struct_tree(doc: &mut Document, pages: &BTreeSet<ObjectId>) {
+    let Ok(root) = doc
+        .catalog()
+        .and_then(|catalog| catalog.get(b"StructTreeRoot"))
+        .and_then(Object::as_reference)
+    else {
+        return;
+    };
+    let mut dropped = Vec::new();
+    prune_struct_kids(doc, root, None, pages, &mut dropped, 0);
+    for id in dropped {
+        doc.objects.insert(id, Object::Null);
+    }
+}
+
+/// what a structure tree kid is: marked content on a page, or an element
+enum StructKid {
+    Content(Option<ObjectId>),
+    Element(ObjectId, Option<ObjectId>),
+}
+
+fn struct_kid(doc: &Document, kid: &Object, page: Option<ObjectId>) -> StructKid {
+    let id = kid.as_reference().ok();
+    let Ok((_, Object::Dictionary(dict))) = doc.dereference(kid) else {
+        // a marked-content ID on the parent's page
+        return StructKid::Content(page);
+    };
+    let page = dict.get(b"Pg").and_then(Object::as_reference).ok().or(page);
+    let kind = dict.get(b"Type").and_then(Object::as_name).unwrap_or_default();
+    match id {
+        Some(id) if !matches!(kind, b"MCR" | b"OBJR") => StructKid::Element(id, page),
+        _ => StructKid::Content(page),
+    }
+}
+
+fn prune_struct_kids(
+    doc: &mut Document,
+    parent: ObjectId,
+    page: Option<ObjectId>,
+    pages: &BTreeSet<ObjectId>,
+    dropped: &mut Vec<ObjectId>,
+    depth: usize,
+) {
+    let kids = match doc.get_dictionary(parent).and_then(|elem| elem.get(b"K")) {
+        Ok(Object::Array(kids)) => kids.clone(),
+        Ok(kid) => vec![kid.clone()],
+        Err(_) => return,
+    };
+    let on_redacted = |page: Option<ObjectId>| page.is_some_and(|page| pages.contains(&page));
+    let mut kept = Vec::new();
+    for kid in kids {
+        match struct_kid(doc, &kid, page) {
+            StructKid::Content(page) if on_redacted(page) => {}
+            StructKid::Element(id, page) if on_redacted(page) => {
+                collect_struct_elements(doc, id, dropped, depth + 1);
+            }
+            StructKid::Element(id, page) if depth < 64 => {
+                prune_struct_kids(doc, id, page, pages, dropped, depth + 1);
+                kept.push(kid);
+            }
+            _ => kept.push(kid),
+        }
+    }
+    if let Ok(elem) = doc.get_dictionary_mut(parent) {
+        if kept.is_empty() {
+            elem.remove(b"K");
+        } else {
+            elem.set("K", kept);
+        }
+    }
+}
+
+/// an element and the elements under it
+fn collect_struct_elements(doc: &Document, id: ObjectId, out: &mut Vec<ObjectId>, depth: usize) {
+    out.push(id);
+    let kids = match doc.get_dictionary(id).and_then(|elem| elem.get(b"K")) {
+        Ok(Object::Array(kids)) => kids.clone(),
+        Ok(kid) => vec![kid.clone()],
+        Err(_) => return,
+    };
+    for kid in kids {
+        if let (StructKid::Element(kid, _), true) = (struct_kid(doc, &kid, None), depth < 64) {
+            collect_struct_elements(doc, kid, out, depth + 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(view: [f32; 6], width: f32, height: f32) -> PageJob {
+        PageJob {
+            number: 1,
+            page_id: (1, 0),
+            view,
+            width,
+            height,
+            rects: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn page_boxes_to_pixels() {
+        // 200 x 100 pt page at 2 pixels per point
+        let upright = job([1.0, 0.0, 0.0, 1.0, 0.0, 0.0], 200.0, 100.0);
+        let rect = PageRect([10.0, 80.0, 50.5, 90.0]);
+        assert_eq!(pixel_rect(&upright, &rect, 400, 200), Some([20, 20, 101, 40]));
+        // partly off the page is clipped, wholly off is skipped
+        let rect = PageRect([150.0, -20.0, 300.0, 10.0]);
+        assert_eq!(pixel_rect(&upright, &rect, 400, 200), Some([300, 180, 400, 200]));
+        assert_eq!(pixel_rect(&upright, &PageRect([0.0, 200.0, 10.0, 210.0]), 400, 200), None);
+
+        // the same 100 x 200 pt page with /Rotate 90 shows 200 wide: its bottom
+        // left corner is the view's top left
+        let turned = job([0.0, 1.0, -1.0, 0.0, 100.0, 0.0], 200.0, 100.0);
+        let rect = PageRect([0.0, 0.0, 10.0, 20.0]);
+        assert_eq!(pixel_rect(&turned, &rect, 400, 200), Some([0, 0, 40, 20]));
+    }
+}
@@ -0,0 +1,79 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+use tracing::info;
+
+use crate::pdf;
+
+/// rewrite a damaged PDF through MuPDF, which rebuilds a broken xref table while
+/// opening. pages that still can't be loaded are dropped, unreferenced objects
+/// are garbage collected, and what was salvaged is reported
+pub fn repair_pdf(input: &Path, output: &Path, quiet: bool) -> Result<()> {
+    let start = std::time::Instant::now();
+    let input_str = input.to_str().context("Invalid path")?.to_string();
+    if !quiet {
+        info!("Repairing {} -> {}", input.display(), output.display());
+    }
+    // what a strict parser makes of it, for the report
+    let strict = lopdf::Document::load(input).err();
+
+    let mut doc = mupdf::pdf::PdfDocument::open(&input_str)
+        .with_context(|| format!("{} is damaged beyond repair", input.display()))?;
+    let objects_in = doc.count_objects()?;
+    let num_pages = doc.page_count()?;
+    let broken: Vec<i32> = (0..num_pages).filter(|&i| !page_readable(&doc, i)).collect();
+    anyhow::ensure!(
+        (broken.len() as i32) < num_pages,
+        "No readable pages left in {}",
+        input.display()
+    );
+    // from the back, so the numbers stay valid
+    for &i in broken.iter().rev() {
+        doc.delete_page(i)?;
+    }
+
+    let mut options = mupdf::pdf::PdfWriteOptions::default();
+    // drop unreferenced objects and renumber the rest
+    options.set_garbage_level(2).set_compress(true);
+    let mut data = Vec::new();
+    doc.write_to_with_options(&mut data, options)?;
+    let objects_out = mupdf::pdf::PdfDocument::from_bytes(&data)
+        .and_then(|repaired| repaired.count_objects())
+        .context("Repaired PDF doesn't open again")?;
+    pdf::write_output(output, |out| Ok(out.write_all(&data)?))?;
+
+    if !quiet {
+        match strict {
+            Some(err) => info!("  structure: damaged ({}), rebuilt", err),
+            None => info!("  structure: readable, rewritten"),
+        }
+        if broken.is_empty() {
+            info!("  pages: all {} recovered", num_pages);
+        } else {
+            let dropped: Vec<String> = broken.iter().map(|i| (i + 1).to_string()).collect();
+            info!(
+                "  pages: {} of {} recovered, dropped unreadable page(s) {}",
+                num_pages as usize - broken.len(),
+                num_pages,
+                dropped.join(", ")
+            );
+        }
+        info!("  objects: {} kept of {}", objects_out, objects_in);
+        info!("Done. PDF saved in {:.2}s", start.elapsed().as_secs_f64());
+    }
+    Ok(())
+}
+
+/// whether page `i` loads and its contents run through a device without an
+/// error: a page that loads may still have contents MuPDF can't make out
+fn page_readable(doc: &mupdf::Document, i: i32) -> bool {
+    let run = || -> Result<bool, mupdf::Error> {
+        let page = doc.load_page(i)?;
+        let list = mupdf::DisplayList::new(page.bounds()?)?;
+        let device = mupdf::Device::from_display_list(&list)?;
+        // errors interpreting the contents are counted here, not returned
+        let cookie = mupdf::Cookie::new()?;
+        page.run_with_cookie(&device, &mupdf::Matrix::IDENTITY, &cookie)?;
+        Ok(cookie.errors() == 0)
+    };
+    run().unwrap_or(false)
+}
@@ -0,0 +1,566 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use bytes::Bytes;
+use percent_encoding::percent_decode_str;
+use std::future::Future;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll, Waker};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::archive;
+use crate::merge::{self, MergeOptions};
+use crate::parse;
+use crate::split::{self, SplitOptions};
+use crate::tmp::with_scratch_dir;
+
+/// longest request line plus headers accepted
+const MAX_HEADER: usize = 16 << 10;
+/// most headers accepted
+const MAX_HEADERS: usize = 64;
+
+/// per-request limits
+pub struct ServeOptions {
+    /// largest request body, in bytes
+    pub max_upload: u64,
+    /// most pages one request may render or create
+    pub max_pages: usize,
+    /// highest DPI /split renders at
+    pub max_dpi: u32,
+    /// requests handled at once; more are turned away with 503
+    pub workers: usize,
+    /// how long to wait on a silent client
+    pub timeout: Duration,
+    pub quiet: bool,
+}
+
+/// a failure answered with a specific status; other errors while converting are 422
+#[derive(Debug)]
+struct HttpError(u16, String);
+
+impl std::fmt::Display for HttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.1)
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+fn http_error(status: u16, message: impl Into<String>) -> anyhow::Error {
+    HttpError(status, message.into()).into()
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: Vec<(String, String)>,
+    headers: Vec<(String, String)>,
+    body: Bytes,
+}
+
+impl Request {
+    /// a request from its parsed head, without the body yet
+    fn from_head(head: &httparse::Request) -> Result<Request> {
+        let (path, query) = split_target(head.path.unwrap_or_default())?;
+        let headers = head
+            .headers
+            .iter()
+            .map(|header| {
+                let value = std::str::from_utf8(header.value)
+                    .map_err(|_| http_error(400, "Request headers aren't valid UTF-8"))?;
+                Ok((header.name.to_string(), value.trim().to_string()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Request {
+            method: head.method.unwrap_or_default().to_string(),
+            path,
+            query,
+            headers,
+            body: Bytes::new(),
+        })
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    /// suggested download name
+    filename: Option<String>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn text(status: u16, text: &str) -> Self {
+        Response {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            filename: None,
+            body: format!("{}\n", text).into_bytes(),
+        }
+    }
+}
+
+/// one uploaded file of a multipart/form-data body
+#[derive(Debug)]
+struct Part {
+    filename: String,
+    data: Bytes,
+}
+
+/// serve split and merge over HTTP until killed:
+///
+///   POST /split  one PDF in, a ZIP of page images out
+///   POST /merge  images in, in upload order, one PDF out
+///   GET  /health
+///
+/// options go in the query string, files in a multipart/form-data body
+pub fn serve(bind: &str, opts: ServeOptions) -> Result<()> {
+    let listener = TcpListener::bind(bind).with_context(|| format!("Cannot listen on {}", bind))?;
+    if !opts.quiet {
+        info!("Listening on http://{}", listener.local_addr()?);
+    }
+    let opts = Arc::new(opts);
+    let busy = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("failed to accept a connection: {}", e);
+                continue;
+            }
+        };
+        if busy.fetch_add(1, Ordering::SeqCst) >= opts.workers {
+            busy.fetch_sub(1, Ordering::SeqCst);
+            let _ = write_response(&mut stream, &Response::text(503, "Busy, try again later"));
+            continue;
+        }
+        let opts = Arc::clone(&opts);
+        let slot = BusySlot(Arc::clone(&busy));
+        std::thread::spawn(move || {
+            let _slot = slot;
+            handle_connection(stream, &opts);
+        });
+    }
+    Ok(())
+}
+
+/// a taken worker slot, given back when the connection's thread ends, even by a
+/// panic
+struct BusySlot(Arc<AtomicUsize>);
+
+impl Drop for BusySlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn handle_connection(mut stream: TcpStream, opts: &ServeOptions) {
+    let start = Instant::now();
+    let _ = stream.set_read_timeout(Some(opts.timeout));
+    let _ = stream.set_write_timeout(Some(opts.timeout));
+    let peer = stream
+        .peer_addr()
+        .map_or_else(|_| "?".to_string(), |addr| addr.to_string());
+    let mut target = String::new();
+    let response = read_request(&mut stream, opts.max_upload)
+        .and_then(|request| {
+            target = format!("{} {}", request.method, request.path);
+            route(&request, opts)
+        })
+        .unwrap_or_else(|e| {
+            let status = e.downcast_ref::<HttpError>().map_or(422, |e| e.0);
+            Response::text(status, &format!("{:#}", e))
+        });
+    if let Err(e) = write_response(&mut stream, &response) {
+        warn!("failed to answer {}: {}", peer, e);
+    }
+    if !opts.quiet {
+        info!(
+            "{} {} -> {} ({} bytes) in {:.2}s",
+            peer,
+            if target.is_empty() { "-" } else { &target },
+            response.status,
+            response.body.len(),
+            start.elapsed().as_secs_f64()
+        );
+    }
+}
+
+/// read one request. the body needs a Content-Length, checked against `max_upload`
+/// before any of it is read
+fn read_request(stream: &mut TcpStream, max_upload: u64) -> Result<Request> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let (mut request, header_end) = loop {
+        let n = stream.read(&mut chunk).context("Failed to read request")?;
+        if n == 0 {
+            return Err(http_error(400, "Incomplete request"));
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let mut head = httparse::Request::new(&mut headers);
+        match head.parse(&buf) {
+            Ok(httparse::Status::Complete(end)) => break (Request::from_head(&head)?, end),
+            Ok(httparse::Status::Partial) if buf.len() <= MAX_HEADER => {}
+            Ok(httparse::Status::Partial) | Err(httparse::Error::TooManyHeaders) => {
+                return Err(http_error(431, "Request headers too large"));
+            }
+            Err(e) => return Err(http_error(400, format!("Malformed request: {}", e))),
+        }
+    };
+
+    if request.header("Transfer-Encoding").is_some() {
+        return Err(http_error(411, "Chunked uploads aren't supported; send a Content-Length"));
+    }
+    let length: u64 = match request.header("Content-Length") {
+        Some(value) => value
+            .parse()
+            .map_err(|_| http_error(400, "Invalid Content-Length"))?,
+        None => 0,
+    };
+    if length > max_upload {
+        return Err(http_error(
+            413,
+            format!(
+                "Upload of {} exceeds the limit of {}",
+                parse::ByteSize(length),
+                parse::ByteSize(max_upload)
+            ),
+        ));
+    }
+    if length > 0
+        && request
+            .header("Expect")
+            .is_some_and(|v| v.eq_ignore_ascii_case("100-continue"))
+    {
+        stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+    }
+    let mut body = buf.split_off(header_end);
+    body.truncate(length as usize);
+    let missing = length - body.len() as u64;
+    stream
+        .take(missing)
+        .read_to_end(&mut body)
+        .context("Failed to read request body")?;
+    if (body.len() as u64) < length {
+        return Err(http_error(400, "Request body shorter than its Content-Length"));
+    }
+    request.body = body.into();
+    Ok(request)
+}
+
+fn write_response(stream: &mut TcpStream, response: &Response) -> std::io::Result<()> {
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        reason(response.status),
+        response.content_type,
+        response.body.len()
+    );
+    if let Some(filename) = &response.filename {
+        head.push_str(&format!(
+            "Content-Disposition: attachment; filename=\"{}\"\r\n",
+            filename
+        ));
+    }
+    if response.status == 405 {
+        head.push_str("Allow: POST\r\n");
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(&response.body)?;
+    stream.flush()
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        422 => "Unprocessable Entity",
+        431 => "Request Header Fields Too Large",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
+
+fn route(request: &Request, opts: &ServeOptions) -> Result<Response> {
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/health") => Ok(Response::text(200, "ok")),
+        ("POST", "/split") => with_scratch_dir("serve", |dir| split_request(request, opts, dir)),
+        ("POST", "/merge") => with_scratch_dir("serve", |dir| merge_request(request, opts, dir)),
+        (_, "/split" | "/merge") => {
+            Err(http_error(405, "Use POST with a multipart/form-data body"))
+        }
+        (_, path) => Err(http_error(404, format!("No endpoint {}", path))),
+    }
+}
+
+/// POST /split?format=&dpi=&pages=&gray=&quality=&compress=
+fn split_request(request: &Request, opts: &ServeOptions, dir: &Path) -> Result<Response> {
+    let mut split_opts = SplitOptions {
+        dpi: 150u32.min(opts.max_dpi),
+        quiet: true,
+        ..Default::default()
+    };
+    for (key, value) in &request.query {
+        match key.as_str() {
+            "format" => split_opts.format = enum_param(key, value)?,
+            "dpi" => split_opts.dpi = number_param(key, value, 72, opts.max_dpi)?,
+            "pages" => split_opts.pages = Some(value.clone()),
+            "gray" => split_opts.gray = bool_param(key, value)?,
+            "quality" => split_opts.quality = number_param(key, value, 1, 100)?,
+            "compress" => split_opts.compress = enum_param(key, value)?,
+            _ => return Err(unknown_param(key)),
+        }
+    }
+    let parts = uploads(request)?;
+    let [part] = parts.as_slice() else {
+        return Err(http_error(400, "Upload exactly one PDF"));
+    };
+    let stem = safe_name(&part.filename, "document");
+    let stem = stem.strip_suffix(".pdf").unwrap_or(&stem).to_string();
+    let input = dir.join(format!("{}.pdf", stem));
+    std::fs::write(&input, &part.data)?;
+
+    // MuPDF reads objects lazily; loading with lopdf would parse all of them
+    let num_pages = mupdf::Document::open(input.to_str().context("Invalid path")?)
+        .with_context(|| format!("Failed to open {}", input.display()))?
+        .page_count()? as usize;
+    let count = match split_opts.pages.as_deref() {
+        Some(spec) => parse::parse_page_ranges(spec, num_pages as i32)
+            .map_err(|e| http_error(400, format!("{:#}", e)))?
+            .len(),
+        None => num_pages,
+    };
+    if count > opts.max_pages {
+        return Err(http_error(
+            413,
+            format!("{} pages requested, the limit is {}", count, opts.max_pages),
+        ));
+    }
+
+    let output_dir = dir.join("pages");
+    let report = split::split_pdf(&input, &output_dir, &split_opts)?;
+    let entries = report
+        .files
+        .iter()
+        .map(|path| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+            Ok((name, std::fs::read(path)?))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut body = Vec::new();
+    archive::write_stored(entries, &mut body)?;
+    Ok(Response {
+        status: 200,
+        content_type: "application/zip",
+        filename: Some(format!("{}.zip", stem)),
+        body,
+    })
+}
+
+/// POST /merge?dpi=&pagesize=&orientation=&gray=&title=&author=
+fn merge_request(request: &Request, opts: &ServeOptions, dir: &Path) -> Result<Response> {
+    let mut merge_opts = MergeOptions {
+        quiet: true,
+        max_pixels: Some(200_000_000),
+        ..Default::default()
+    };
+    for (key, value) in &request.query {
+        match key.as_str() {
+            "dpi" => merge_opts.dpi = Some(number_param(key, value, 72, 2400)?),
+            "pagesize" => merge_opts.pagesize = Some(enum_param(key, value)?),
+            "orientation" => merge_opts.orientation = enum_param(key, value)?,
+            "gray" => merge_opts.gray = bool_param(key, value)?,
+            "title" => merge_opts.title = Some(value.clone()),
+            "author" => merge_opts.author = Some(value.clone()),
+            _ => return Err(unknown_param(key)),
+        }
+    }
+    let parts = uploads(request)?;
+    if parts.is_empty() {
+        return Err(http_error(400, "Upload at least one image"));
+    }
+    if parts.len() > opts.max_pages {
+        return Err(http_error(
+            413,
+            format!("{} images uploaded, the limit is {}", parts.len(), opts.max_pages),
+        ));
+    }
+    // numbered, so names can't clash and the upload order is kept
+    let mut images = Vec::with_capacity(parts.len());
+    for (i, part) in parts.iter().enumerate() {
+        let name = safe_name(&part.filename, "image");
+        let path = dir.join(format!("{:04}_{}", i + 1, name));
+        std::fs::write(&path, &part.data)?;
+        images.push(path);
+    }
+    let output = dir.join("merged.pdf");
+    merge::merge_images(&images, &output, &merge_opts)?;
+    Ok(Response {
+        status: 200,
+        content_type: "application/pdf",
+        filename: Some("merged.pdf".to_string()),
+        body: std::fs::read(&output)?,
+    })
+}
+
+/// the file parts of a multipart/form-data request, in order
+fn uploads(request: &Request) -> Result<Vec<Part>> {
+    let boundary = request
+        .header("Content-Type")
+        .and_then(|value| multer::parse_boundary(value).ok())
+        .ok_or_else(|| http_error(400, "Expected a multipart/form-data upload"))?;
+    read_parts(request.body.clone(), boundary).map_err(|e| http_error(400, format!("{:#}", e)))
+}
+
+/// the parts of a multipart body that carry a file name; other fields are skipped
+fn read_parts(body: Bytes, boundary: String) -> Result<Vec<Part>> {
+    let body = futures_util::stream::once(async { Ok::<_, std::io::Error>(body) });
+    let mut multipart = multer::Multipart::new(body, boundary);
+    let parts = ready(async {
+        let mut parts = Vec::new();
+        while let Some(field) = multipart.next_field().await? {
+            let Some(filename) = field.file_name().map(str::to_string) else {
+                continue;
+            };
+            let data = field.bytes().await?;
+            parts.push(Part { filename, data });
+        }
+        Ok::<_, multer::Error>(parts)
+    });
+    parts.context("Malformed multipart body")
+}
+
+/// run `future` to the end on this thread. multer parses asynchronously, but a
+/// body already in memory never makes it wait
+fn ready<T>(future: impl Future<Output = T>) -> T {
+    let mut future = std::pin::pin!(future);
+    let mut cx = TaskContext::from_waker(Waker::noop());
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
+
+/// the path and the query pairs of a request target. `+` is a space only in the
+/// query, as in HTML form submissions
+fn split_target(target: &str) -> Result<(String, Vec<(String, String)>)> {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let path = percent_decode_str(path)
+        .decode_utf8()
+        .map_err(|_| http_error(400, "URL isn't valid UTF-8"))?;
+    let query = form_urlencoded::parse(query.as_bytes()).into_owned().collect();
+    Ok((path.into_owned(), query))
+}
+
+/// an uploaded file name reduced to a plain file name, `fallback` if nothing is left
+fn safe_name(filename: &str, fallback: &str) -> String {
+    let base = filename.rsplit(['/', '\\']).next().unwrap_or_default();
+    let name: String = base
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect();
+    let name = name.trim_start_matches('.');
+    if name.is_empty() {
+        fallback.to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+fn unknown_param(key: &str) -> anyhow::Error {
+    http_error(400, format!("Unknown parameter '{}'", key))
+}
+
+fn enum_param<T: ValueEnum>(key: &str, value: &str) -> Result<T> {
+    T::from_str(value, true).map_err(|_| {
+        let choices: Vec<String> = T::value_variants()
+            .iter()
+            .filter_map(|v| v.to_possible_value())
+            .map(|v| v.get_name().to_string())
+            .collect();
+        http_error(
+            400,
+            format!("Invalid {} '{}' (expected {})", key, value, choices.join(", ")),
+        )
+    })
+}
+
+fn number_param<T>(key: &str, value: &str, min: T, max: T) -> Result<T>
+where
+    T: std::str::FromStr + PartialOrd + Copy + std::fmt::Display,
+{
+    value
+        .parse()
+        .ok()
+        .filter(|n| (min..=max).contains(n))
+        .ok_or_else(|| http_error(400, format!("{} must be {}-{}, got '{}'", key, min, max, value)))
+}
+
+fn bool_param(key: &str, value: &str) -> Result<bool> {
+    match value {
+        "" | "1" | "true" | "yes" => Ok(true),
+        "0" | "false" | "no" => Ok(false),
+        _ => Err(http_error(400, format!("{} must be true or false, got '{}'", key, value))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn multipart_file_parts_in_order() {
+        let body = b"preamble\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"a b.png\"\r\n\
+            Content-Type: image/png\r\n\r\n\
+            first\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"note\"\r\n\r\n\
+            text\r\n--XyZ\r\n\
+            content-disposition: form-data; name=file; filename=\"x\\\"y;z.jpg\"\r\n\r\n\
+            second\r\n\r\n--XyZ--\r\n";
+        let parts = read_parts(Bytes::from_static(body), "XyZ".to_string()).unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].filename, "a b.png");
+        assert_eq!(&parts[0].data[..], b"first");
+        assert_eq!(parts[1].filename, "x\"y;z.jpg");
+        assert_eq!(&parts[1].data[..], b"second\r\n");
+        let unfinished = b"--XyZ\r\nContent-Disposition: form-data; filename=\"a\"\r\n\r\nno end";
+        assert!(read_parts(Bytes::from_static(unfinished), "XyZ".to_string()).is_err());
+    }
+
+    #[test]
+    fn targets_and_names() {
+        let (path, query) = split_target("/a+b%20c?title=Q3+report%21&gray&dpi=200").unwrap();
+        // `+` is a space in the query only
+        assert_eq!(path, "/a+b c");
+        assert_eq!(
+            query,
+            [("title", "Q3 report!"), ("gray", ""), ("dpi", "200")]
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+        );
+        assert!(split_target("/%ff").is_err());
+        assert_eq!(safe_name("C:\\scans\\page 1.png", "image"), "page_1.png");
+        assert_eq!(safe_name("../..", "image"), "image");
+    }
+}
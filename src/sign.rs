@@ -0,0 +1,499 @@
+use anyhow::{Context, Result};
+use chrono::Local;
+use lopdf::content::{Content, Operation};
+use lopdf::xref::XrefType;
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream, StringFormat};
+use serde::Serialize;
+use std::io::Write;
+use std::path::Path;
+use tracing::info;
+
+use crate::parse::PageRect;
+use crate::watermark::{encode_win_ansi, standard_font, text_width_em, HELVETICA_WIDTHS};
+use crate::{crypto, merge, pdf, pkcs, writer};
+
+/// ByteRange entries until the real offsets are known; same width as any offset
+const BYTE_RANGE_PLACEHOLDER: i64 = 9_999_999_999;
+/// annotation flags Print | Locked
+const WIDGET_FLAGS: i64 = 4 | 128;
+/// AcroForm SigFlags SignaturesExist | AppendOnly
+const SIG_FLAGS: i64 = 3;
+
+pub struct SignOptions<'a> {
+    /// PKCS#12 file with the private key and certificate
+    pub key: &'a Path,
+    pub password: &'a str,
+    /// page (1-based) and box of a visible signature; None signs invisibly
+    pub visible: Option<(u32, PageRect)>,
+    /// signature field name (default: the first free SignatureN)
+    pub field: Option<&'a str>,
+    pub reason: Option<&'a str>,
+    pub location: Option<&'a str>,
+    pub quiet: bool,
+}
+
+/// sign a PDF with an incremental update: a signature field whose value holds a
+/// detached CMS signature (adbe.pkcs7.detached) over every byte of the file but
+/// the signature itself, so earlier revisions and signatures stay valid
+pub fn sign_pdf(input: &Path, output: Option<&Path>, opts: &SignOptions) -> Result<()> {
+    let start = std::time::Instant::now();
+    let original =
+        std::fs::read(input).with_context(|| format!("Failed to read {}", input.display()))?;
+    let doc = Document::load_mem(&original)
+        .with_context(|| format!("Failed to open PDF {}", input.display()))?;
+    // new strings would have to be encrypted too
+    anyhow::ensure!(
+        !doc.is_encrypted(),
+        "{} is encrypted; decrypt it before signing",
+        input.display()
+    );
+    let p12 = std::fs::read(opts.key)
+        .with_context(|| format!("Failed to read {}", opts.key.display()))?;
+    let identity = pkcs::load_pkcs12(&p12, opts.password)
+        .with_context(|| format!("Failed to load key {}", opts.key.display()))?;
+    let target = output.unwrap_or(input);
+    if !opts.quiet {
+        info!(
+            "Signing {} as {} -> {}",
+            input.display(),
+            identity.name,
+            target.display()
+        );
+    }
+
+    let now = Local::now();
+    let page_number = opts.visible.map_or(1, |(page, _)| page);
+    let page_id = *doc
+        .get_pages()
+        .get(&page_number)
+        .with_context(|| format!("{} has no page {}", input.display(), page_number))?;
+    let catalog_id = doc.trailer.get(b"Root")?.as_reference()?;
+    let mut catalog = doc.get_dictionary(catalog_id)?.clone();
+    let (acroform_id, mut acroform) = match catalog.get(b"AcroForm") {
+        Ok(Object::Reference(id)) => (Some(*id), doc.get_dictionary(*id)?.clone()),
+        Ok(Object::Dictionary(dict)) => (None, dict.clone()),
+        _ => (None, Dictionary::new()),
+    };
+    let mut fields = acroform
+        .get(b"Fields")
+        .and_then(|fields| doc.dereference(fields))
+        .and_then(|(_, fields)| fields.as_array())
+        .cloned()
+        .unwrap_or_default();
+    let taken: Vec<String> = fields
+        .iter()
+        .filter_map(|field| doc.dereference(field).ok()?.1.as_dict().ok())
+        .filter_map(|field| field.get(b"T").ok()?.as_str().ok())
+        .map(pdf::decode_text)
+        .collect();
+    let name = match opts.field {
+        Some(name) => {
+            anyhow::ensure!(
+                !taken.iter().any(|t| t == name),
+                "{} already has a field named {}",
+                input.display(),
+                name
+            );
+            name.to_string()
+        }
+        None => (1..)
+            .map(|i| format!("Signature{}", i))
+            .find(|name| !taken.contains(name))
+            .unwrap(),
+    };
+
+    let mut next_num = doc
+        .trailer
+        .get(b"Size")
+        .and_then(Object::as_i64)
+        .map_or(0, |size| size as u32)
+        .max(doc.max_id + 1);
+    let mut new_id = || {
+        next_num += 1;
+        (next_num - 1, 0)
+    };
+    let sig_id = new_id();
+    let widget_id = new_id();
+    let size = pkcs::signature_size(&identity, now.to_utc())?;
+    let mut sig = dictionary! {
+        "Type" => "Sig",
+        "Filter" => "Adobe.PPKLite",
+        "SubFilter" => "adbe.pkcs7.detached",
+        "ByteRange" => [0, BYTE_RANGE_PLACEHOLDER, BYTE_RANGE_PLACEHOLDER, BYTE_RANGE_PLACEHOLDER]
+            .map(Object::Integer)
+            .to_vec(),
+        "Contents" => Object::String(vec![0; size], StringFormat::Hexadecimal),
+        "M" => Object::string_literal(merge::pdf_date(now.fixed_offset())),
+        "Name" => writer::text_string(&identity.name),
+    };
+    if let Some(reason) = opts.reason {
+        sig.set("Reason", writer::text_string(reason));
+    }
+    if let Some(location) = opts.location {
+        sig.set("Location", writer::text_string(location));
+    }
+    let mut widget = dictionary! {
+        "Type" => "Annot",
+        "Subtype" => "Widget",
+        "FT" => "Sig",
+        "T" => writer::text_string(&name),
+        "V" => sig_id,
+        "F" => WIDGET_FLAGS,
+        "P" => page_id,
+        "Rect" => vec![0.into(), 0.into(), 0.into(), 0.into()],
+    };
+    let mut objects: Vec<(ObjectId, Object)> = vec![(sig_id, Object::Dictionary(sig))];
+    if let Some((_, PageRect(rect))) = opts.visible {
+        let mut lines = vec![
+            format!("Digitally signed by {}", identity.name),
+            format!("Date: {}", now.format("%Y-%m-%d %H:%M:%S %:z")),
+        ];
+        lines.extend(opts.reason.map(|reason| format!("Reason: {}", reason)));
+        lines.extend(opts.location.map(|location| format!("Location: {}", location)));
+        let appearance_id = new_id();
+        let appearance = appearance(rect[2] - rect[0], rect[3] - rect[1], &lines)?;
+        objects.push((appearance_id, Object::Stream(appearance)));
+        widget.set("Rect", rect.map(Object::Real).to_vec());
+        widget.set("AP", dictionary! { "N" => appearance_id });
+    }
+    objects.push((widget_id, Object::Dictionary(widget)));
+
+    let mut page = doc.get_dictionary(page_id)?.clone();
+    match page.get(b"Annots") {
+        Ok(Object::Reference(annots_id)) => {
+            let mut annots = doc.get_object(*annots_id)?.as_array()?.clone();
+            annots.push(widget_id.into());
+            objects.push((*annots_id, Object::Array(annots)));
+        }
+        annots => {
+            let mut annots = annots.and_then(Object::as_array).cloned().unwrap_or_default();
+            annots.push(widget_id.into());
+            page.set("Annots", annots);
+            objects.push((page_id, Object::Dictionary(page)));
+        }
+    }
+    fields.push(widget_id.into());
+    acroform.set("Fields", fields);
+    acroform.set("SigFlags", SIG_FLAGS);
+    match acroform_id {
+        Some(id) => objects.push((id, Object::Dictionary(acroform))),
+        None => {
+            catalog.set("AcroForm", acroform);
+            objects.push((catalog_id, Object::Dictionary(catalog)));
+        }
+    }
+
+    let mut trailer = Dictionary::new();
+    trailer.set("Size", next_num as i64);
+    trailer.set("Root", catalog_id);
+    if let Ok(info) = doc.trailer.get(b"Info") {
+        trailer.set("Info", info.clone());
+    }
+    // the second ID changes with every revision of the file
+    if let Ok(Object::Array(ids)) = doc.trailer.get(b"ID") {
+        if let Some(first) = ids.first() {
+            let tail = &original[original.len().saturating_sub(1024)..];
+            let revision = crypto::md5(&[tail, now.to_rfc3339().as_bytes()]);
+            let revision = Object::String(revision.to_vec(), StringFormat::Hexadecimal);
+            trailer.set("ID", vec![first.clone(), revision]);
+        }
+    }
+    let xref_stream = matches!(
+        doc.reference_table.cross_reference_type,
+        XrefType::CrossReferenceStream
+    );
+    let mut update =
+        writer::write_update(&original, doc.xref_start as u64, xref_stream, &objects, trailer)?;
+
+    // the signature covers everything but its own <...> hex string
+    let contents = find(&update, b"/Contents <").context("Signature placeholder not found")?
+        + b"/Contents ".len();
+    let contents_end = contents + 2 * size + 2;
+    let base = original.len();
+    let total = base + update.len();
+    let (before, after) = (base + contents, base + contents_end);
+    let placeholder = format!(
+        "[0 {} {} {}]",
+        BYTE_RANGE_PLACEHOLDER, BYTE_RANGE_PLACEHOLDER, BYTE_RANGE_PLACEHOLDER
+    );
+    let at = find(&update, placeholder.as_bytes()).context("ByteRange placeholder not found")?;
+    let actual = format!("[0 {} {} {}", before, after, total - after);
+    let actual = format!("{:<width$}]", actual, width = placeholder.len() - 1);
+    update[at..at + placeholder.len()].copy_from_slice(actual.as_bytes());
+
+    let signed = [&original[..], &update[..contents], &update[contents_end..]].concat();
+    let cms = pkcs::sign_detached(&identity, &signed, now.to_utc())?;
+    anyhow::ensure!(cms.len() <= size, "Signature is larger than the space reserved for it");
+    let hex: String = cms.iter().map(|b| format!("{:02X}", b)).collect();
+    update[contents + 1..contents + 1 + hex.len()].copy_from_slice(hex.as_bytes());
+
+    if target == input {
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(input)
+            .and_then(|mut file| file.write_all(&update))
+            .with_context(|| format!("Failed to update {}", input.display()))?;
+    } else {
+        pdf::write_output(target, |out| {
+            out.write_all(&original)?;
+            out.write_all(&update)?;
+            Ok(())
+        })?;
+    }
+
+    if !opts.quiet {
+        info!("Done. PDF signed in {:.2}s", start.elapsed().as_secs_f64());
+    }
+    Ok(())
+}
+
+/// first position of `needle` in `haystack`
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// form XObject for a visible signature: a frame and `lines` of Helvetica, sized
+/// to fit the box
+fn appearance(w: f32, h: f32, lines: &[String]) -> Result<Stream> {
+    let encoded: Vec<Vec<u8>> = lines.iter().map(|line| encode_win_ansi(line)).collect();
+    let widest = encoded
+        .iter()
+        .map(|line| text_width_em(&HELVETICA_WIDTHS, line))
+        .fold(f32::EPSILON, f32::max);
+    let size = 10f32
+        .min((w - 6.0) / widest)
+        .min((h - 6.0) / (lines.len() as f32 * 1.2))
+        .max(1.0);
+    let mut ops = vec![
+        Operation::new("q", vec![]),
+        Operation::new("G", vec![Object::Real(0.4)]),
+        Operation::new("w", vec![Object::Real(0.5)]),
+        Operation::new(
+            "re",
+            [0.25, 0.25, w - 0.5, h - 0.5].map(Object::Real).to_vec(),
+        ),
+        Operation::new("S", vec![]),
+        Operation::new("BT", vec![]),
+        Operation::new("Tf", vec![Object::Name(b"Helv".to_vec()), Object::Real(size)]),
+        Operation::new("TL", vec![Object::Real(size * 1.2)]),
+        Operation::new("g", vec![Object::Integer(0)]),
+        Operation::new("Td", vec![Object::Real(3.0), Object::Real(h - 3.0 - size)]),
+    ];
+    for (i, line) in encoded.into_iter().enumerate() {
+        if i > 0 {
+            ops.push(Operation::new("T*", vec![]));
+        }
+        ops.push(Operation::new("Tj", vec![Object::String(line, StringFormat::Literal)]));
+    }
+    ops.push(Operation::new("ET", vec![]));
+    ops.push(Operation::new("Q", vec![]));
+    let content = Content { operations: ops }.encode()?;
+    Ok(Stream::new(
+        dictionary! {
+            "Type" => "XObject",
+            "Subtype" => "Form",
+            "BBox" => [0.0, 0.0, w, h].map(Object::Real).to_vec(),
+            "Resources" => dictionary! {
+                "Font" => dictionary! { "Helv" => standard_font(b"Helvetica") },
+            },
+        },
+        content,
+    ))
+}
+
+/// one signature field and what checking it found
+struct Checked {
+    field: String,
+    result: Result<pkcs::Signer>,
+    /// where the signed byte range ends; 0 when the signature doesn't hold
+    signed_end: usize,
+}
+
+/// `verify --json`'s report on a file
+#[derive(Serialize)]
+struct VerifyJson<'a> {
+    file: String,
+    signatures: Vec<SignatureJson<'a>>,
+    file_bytes: usize,
+    unsigned_bytes: usize,
+}
+
+#[derive(Serialize)]
+struct SignatureJson<'a> {
+    field: &'a str,
+    valid: bool,
+    signer: Option<&'a str>,
+    time: Option<&'a str>,
+    whole_document: bool,
+    signed_bytes: usize,
+    error: Option<String>,
+}
+
+/// check every signature in a PDF: that the signed bytes are unchanged and the
+/// signature matches the signer's certificate. returns whether there are
+/// signatures, all of them hold, and one of them covers the whole file, so
+/// nothing was appended unsigned. certificates aren't checked against any
+/// trusted root
+pub fn verify_pdf(input: &Path, json: bool) -> Result<bool> {
+    let data =
+        std::fs::read(input).with_context(|| format!("Failed to read {}", input.display()))?;
+    let doc = Document::load_mem(&data)
+        .with_context(|| format!("Failed to open PDF {}", input.display()))?;
+    let mut fields = Vec::new();
+    if let Ok(list) = doc
+        .catalog()?
+        .get(b"AcroForm")
+        .and_then(|form| doc.dereference(form))
+        .and_then(|(_, form)| form.as_dict())
+        .and_then(|form| form.get(b"Fields"))
+        .and_then(|list| doc.dereference(list))
+        .and_then(|(_, list)| list.as_array())
+    {
+        collect_signatures(&doc, list, "", 0, &mut fields);
+    }
+
+    let checked: Vec<Checked> = fields
+        .into_iter()
+        .map(|(field, sig)| {
+            let (result, signed_end) = match check_signature(&data, sig) {
+                Ok((signer, end)) => (Ok(signer), end),
+                Err(e) => (Err(e), 0),
+            };
+            Checked {
+                field,
+                result,
+                signed_end,
+            }
+        })
+        .collect();
+    let covered = checked.iter().map(|c| c.signed_end).max().unwrap_or(0);
+    let all_valid = !checked.is_empty()
+        && checked.iter().all(|c| c.result.is_ok())
+        && covered == data.len();
+
+    if json {
+        let signatures = checked
+            .iter()
+            .map(|c| {
+                let signer = c.result.as_ref().ok();
+                SignatureJson {
+                    field: &c.field,
+                    valid: c.result.is_ok(),
+                    signer: signer.map(|s| s.name.as_str()),
+                    time: signer.and_then(|s| s.signing_time.as_deref()),
+                    whole_document: c.signed_end == data.len(),
+                    signed_bytes: c.signed_end,
+                    error: c.result.as_ref().err().map(|e| e.to_string()),
+                }
+            })
+            .collect();
+        let report = VerifyJson {
+            file: input.display().to_string(),
+            signatures,
+            file_bytes: data.len(),
+            unsigned_bytes: data.len() - covered,
+        };
+        println!("{}", serde_json::to_string(&report)?);
+        return Ok(all_valid);
+    }
+
+    if checked.is_empty() {
+        println!("{}: no signatures", input.display());
+        return Ok(false);
+    }
+    println!("{}: {} signature(s)", input.display(), checked.len());
+    for c in &checked {
+        match &c.result {
+            Ok(signer) => {
+                let time = signer.signing_time.as_deref().unwrap_or("unknown time");
+                let scope = if c.signed_end == data.len() {
+                    "whole document".to_string()
+                } else {
+                    format!(
+                        "covers {} of {} bytes; the document was modified after signing",
+                        c.signed_end,
+                        data.len()
+                    )
+                };
+                println!("  {}: valid, signed by {} at {} ({})", c.field, signer.name, time, scope);
+            }
+            Err(e) => println!("  {}: INVALID: {}", c.field, e),
+        }
+    }
+    if checked.iter().any(|c| c.result.is_ok()) && covered < data.len() {
+        println!(
+            "  NOT COVERED: the last {} bytes were added after the last signature",
+            data.len() - covered
+        );
+    }
+    println!("  (signer certificates are not checked against trusted roots)");
+    Ok(all_valid)
+}
+
+/// signature fields with a value, by full field name
+fn collect_signatures<'a>(
+    doc: &'a Document,
+    list: &'a [Object],
+    prefix: &str,
+    depth: usize,
+    out: &mut Vec<(String, &'a Dictionary)>,
+) {
+    if depth > 32 {
+        return;
+    }
+    for field in list {
+        let Ok((_, Object::Dictionary(field))) = doc.dereference(field) else {
+            continue;
+        };
+        let partial = field.get(b"T").and_then(Object::as_str).map(pdf::decode_text);
+        let name = match (prefix, partial) {
+            ("", Ok(partial)) => partial,
+            (_, Ok(partial)) => format!("{}.{}", prefix, partial),
+            (_, Err(_)) => prefix.to_string(),
+        };
+        let value = field
+            .get(b"V")
+            .and_then(|v| doc.dereference(v))
+            .and_then(|(_, v)| v.as_dict());
+        let is_signature = field.get(b"FT").and_then(Object::as_name).ok() == Some(b"Sig");
+        if let (true, Ok(sig)) = (is_signature, value) {
+            out.push((name.clone(), sig));
+        }
+        if let Ok(kids) = field.get(b"Kids").and_then(Object::as_array) {
+            collect_signatures(doc, kids, &name, depth + 1, out);
+        }
+    }
+}
+
+/// verify one signature dictionary against the file, returning the signer and
+/// where the signed bytes end
+fn check_signature(data: &[u8], sig: &Dictionary) -> Result<(pkcs::Signer, usize)> {
+    let sub_filter = sig.get(b"SubFilter").and_then(Object::as_name).unwrap_or_default();
+    anyhow::ensure!(
+        matches!(sub_filter, b"adbe.pkcs7.detached" | b"ETSI.CAdES.detached"),
+        "Unsupported signature type {}",
+        String::from_utf8_lossy(sub_filter)
+    );
+    let range: Vec<usize> = sig
+        .get(b"ByteRange")
+        .and_then(Object::as_array)?
+        .iter()
+        .map(|n| n.as_i64().map(|n| n.max(0) as usize))
+        .collect::<lopdf::Result<_>>()?;
+    anyhow::ensure!(range.len() == 4, "Malformed ByteRange");
+    let [start, first_len, second, second_len] = [range[0], range[1], range[2], range[3]];
+    let end = second + second_len;
+    anyhow::ensure!(
+        first_len < second && end <= data.len(),
+        "ByteRange runs past the end of the file"
+    );
+    // only the signature's own <...> string may be left out
+    anyhow::ensure!(
+        start == 0 && data[first_len] == b'<' && data[second - 1] == b'>',
+        "ByteRange leaves out more than the signature"
+    );
+    let signed = [&data[..first_len], &data[second..end]].concat();
+    let cms = sig.get(b"Contents").and_then(Object::as_str)?;
+    let signer = pkcs::verify_detached(cms, &signed)?;
+    Ok((signer, end))
+}
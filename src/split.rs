@@ -1,19 +1,37 @@
 use anyhow::{Context, Result};
 use rayon::prelude::*;
+use std::collections::BTreeMap;
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Condvar, Mutex};
+use std::time::Duration;
+use tracing::{debug, debug_span, error, info, trace};
 
-use crate::parse::{parse_page_ranges, ImageFormat, PngCompression};
+use crate::cache::RenderCache;
+use crate::interrupt::{CancelToken, Interrupted};
+use crate::parse::{parse_page_ranges, ByteSize, ImageFormat, PngCompression};
+use crate::pdf::{self, Xfa};
+use crate::progress::{self, Event, Progress};
+use crate::stats::{PageStats, RunStats};
 
-fn encode_png(
+pub fn encode_png(
     data: &[u8],
     width: u32,
     height: u32,
     gray: bool,
     compress: PngCompression,
-    writer: impl Write,
+    mut writer: impl Write,
 ) -> Result<()> {
+    if let PngCompression::Max = compress {
+        // a quick encode for oxipng to start from; it decodes it again, tries
+        // each filter and keeps the smallest deflated at level 12
+        let mut png = Vec::new();
+        encode_png(data, width, height, gray, PngCompression::Fast, &mut png)?;
+        let png = oxipng::optimize_from_memory(&png, &oxipng::Options::from_preset(4))
+            .context("Failed to optimize PNG")?;
+        return writer.write_all(&png).context("Failed to write PNG");
+    }
     let writer = std::io::BufWriter::new(writer);
     let mut encoder = png::Encoder::new(writer, width, height);
     encoder.set_color(if gray {
@@ -26,6 +44,7 @@ fn encode_png(
     // set compression and filter based on level:
     // - fast: fastest encoding, larger files (fdeflate + Paeth)
     // - small: smaller files, slower encoding (zlib + NoFilter)
+    // - max: handled above
     match compress {
         PngCompression::Fast => {
             encoder.set_compression(png::Compression::Fast);
@@ -35,6 +54,7 @@ fn encode_png(
             encoder.set_compression(png::Compression::Balanced);
             encoder.set_filter(png::Filter::NoFilter);
         }
+        PngCompression::Max => unreachable!("max is optimized above"),
     }
 
     let mut writer = encoder
@@ -46,7 +66,7 @@ fn encode_png(
     Ok(())
 }
 
-fn encode_jpg(
+pub fn encode_jpg(
     data: &[u8],
     width: u32,
     height: u32,
@@ -66,6 +86,12 @@ fn encode_jpg(
         pitch: width as usize * if gray { 1 } else { 3 },
         format: pixel_format,
     };
+    let jpeg_data = jpeg_compressor(gray, quality)?.compress_to_vec(image)?;
+    writer.write_all(&jpeg_data)?;
+    Ok(())
+}
+
+fn jpeg_compressor(gray: bool, quality: u8) -> Result<turbojpeg::Compressor> {
     let mut compressor = turbojpeg::Compressor::new()?;
     compressor.set_quality(quality as i32)?;
     compressor.set_subsamp(if gray {
@@ -73,33 +99,522 @@ fn encode_jpg(
     } else {
         turbojpeg::Subsamp::Sub2x2
     })?;
-    let jpeg_data = compressor.compress_to_vec(image)?;
-    writer.write_all(&jpeg_data)?;
-    Ok(())
+    Ok(compressor)
 }
 
-pub fn split_pdf(
-    input: &Path,
-    output_dir: &Path,
-    format: ImageFormat,
-    dpi: u32,
-    compress: PngCompression,
+/// a compressor and output buffer a split worker keeps for all its pages, so
+/// each JPEG doesn't set up libjpeg-turbo and allocate its output again
+struct JpegEncoder {
+    compressor: turbojpeg::Compressor,
     gray: bool,
-    pages: Option<&str>,
-    quality: u8,
-    quiet: bool,
+    out: Vec<u8>,
+}
+
+impl JpegEncoder {
+    fn new(gray: bool, quality: u8) -> Result<Self> {
+        Ok(JpegEncoder {
+            compressor: jpeg_compressor(gray, quality)?,
+            gray,
+            out: Vec::new(),
+        })
+    }
+
+    /// the JPEG of `data`, good until the next page
+    fn encode(&mut self, data: &[u8], width: u32, height: u32) -> Result<&[u8]> {
+        let (width, height) = (width as usize, height as usize);
+        let image = turbojpeg::Image {
+            pixels: data,
+            width,
+            height,
+            pitch: width * if self.gray { 1 } else { 3 },
+            format: if self.gray {
+                turbojpeg::PixelFormat::GRAY
+            } else {
+                turbojpeg::PixelFormat::RGB
+            },
+        };
+        // grown to the worst case once, and kept at that length
+        let bound = self.compressor.buf_len(width, height)?;
+        if self.out.len() < bound {
+            self.out.resize(bound, 0);
+        }
+        let len = self.compressor.compress_to_slice(image, &mut self.out)?;
+        Ok(&self.out[..len])
+    }
+}
+
+/// a rendered pixmap, handed from the render pool to the encoders and back
+struct Frame(mupdf::Pixmap);
+
+// SAFETY: a pixmap is one allocation that only one thread uses at a time here;
+// its reference count is taken under the locks every thread's MuPDF context
+// shares, so it can be dropped on another thread than it was made on
+unsafe impl Send for Frame {}
+
+/// a page rendered, on its way to the encoders
+struct RenderedPage<'a> {
+    index: i32,
+    width: u32,
+    height: u32,
+    frame: Frame,
+    render: Duration,
+    /// the page's share of the memory budget, given back once it's written
+    _permit: Option<Permit<'a>>,
+}
+
+/// bytes the pages in flight may take together, for `--low-memory`. a page
+/// waits until its share fits, or until nothing else is held, so one page
+/// bigger than the whole budget still goes through, alone
+struct MemoryBudget {
+    limit: u64,
+    held: Mutex<u64>,
+    freed: Condvar,
+}
+
+impl MemoryBudget {
+    fn new(limit: u64) -> Self {
+        MemoryBudget {
+            limit,
+            held: Mutex::new(0),
+            freed: Condvar::new(),
+        }
+    }
+
+    fn acquire(&self, bytes: u64) -> Permit<'_> {
+        let mut held = self.held.lock().unwrap();
+        while *held > 0 && *held + bytes > self.limit {
+            held = self.freed.wait(held).unwrap();
+        }
+        *held += bytes;
+        Permit {
+            budget: self,
+            bytes,
+        }
+    }
+}
+
+/// bytes taken from a memory budget, given back when dropped
+struct Permit<'a> {
+    budget: &'a MemoryBudget,
+    bytes: u64,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        *self.budget.held.lock().unwrap() -= self.bytes;
+        self.budget.freed.notify_all();
+    }
+}
+
+/// what an encoding worker keeps from page to page rather than allocate again:
+/// the JPEG encoder and the buffer PNGs are encoded into. encoded pixmaps go
+/// back to the renderers, to be drawn into again by a page of the same size.
+/// with a memory budget none of this is kept, as it isn't counted against it
+#[derive(Default)]
+struct EncodeBuffers {
+    jpeg: Option<JpegEncoder>,
+    png: Vec<u8>,
+}
+
+/// render `list` into a spare pixmap of the page's size, or a new one, on white
+/// as MuPDF renders a page by itself
+fn render_into(
+    spare: &Mutex<Vec<Frame>>,
+    list: &mupdf::DisplayList,
+    matrix: &mupdf::Matrix,
+    colorspace: &mupdf::Colorspace,
+) -> Result<mupdf::Pixmap> {
+    let bbox = list.bounds().transform(matrix).round();
+    let reused = {
+        let mut spare = spare.lock().unwrap();
+        let fits = spare.iter().position(|frame| frame.0.rect() == bbox);
+        fits.map(|at| spare.swap_remove(at).0)
+    };
+    let mut pixmap = match reused {
+        Some(pixmap) => pixmap,
+        None => mupdf::Pixmap::new_with_rect(colorspace, bbox, false)?,
+    };
+    pixmap.clear_with(255)?;
+    {
+        let device = mupdf::Device::from_pixmap(&pixmap)?;
+        list.run(&device, matrix, mupdf::Rect::INF)?;
+        // dropping the device closes it, finishing the drawing
+    }
+    Ok(pixmap)
+}
+
+/// run `each` over pages `indices` of `doc` on the rayon pool and hand the
+/// results to `write` in order. as in `split_pdf`, the document is parsed once,
+/// on this thread, into display lists the pool takes a page at a time; a page
+/// is only started while fewer than two per thread are between their display
+/// list and `write`, to bound memory
+pub fn pages_in_order<T: Send>(
+    doc: &mupdf::Document,
+    indices: &[i32],
+    each: impl Fn(i32, mupdf::DisplayList) -> Result<T> + Sync,
+    write: impl FnOnce(&mut dyn Iterator<Item = Result<T>>) -> Result<()> + Send,
+) -> Result<()> {
+    // annotations included, as a page renders by itself
+    let load = |i| Ok(doc.load_page(i).and_then(|page| page.to_display_list(true))?);
+    lists_in_order(indices, load, each, write)
+}
+
+/// `pages_in_order` with what each page needs loaded by `load`, on this
+/// thread: display lists from more than one document, say
+pub fn lists_in_order<L: Send, T: Send>(
+    indices: &[i32],
+    mut load: impl FnMut(i32) -> Result<L>,
+    each: impl Fn(i32, L) -> Result<T> + Sync,
+    write: impl FnOnce(&mut dyn Iterator<Item = Result<T>>) -> Result<()> + Send,
 ) -> Result<()> {
-    let input_str = input.to_str().context("Invalid path")?.to_string();
-    let num_pages = {
-        let doc = mupdf::Document::open(&input_str)?;
-        doc.page_count()?
+    let window = rayon::current_num_threads() * 2;
+    let (queue, lists) = mpsc::sync_channel::<(usize, i32, Result<L>)>(window);
+    let (done_queue, done) = mpsc::sync_channel::<(usize, Result<T>)>(window);
+    let (slot_back, slots) = mpsc::sync_channel::<()>(window);
+    for _ in 0..window {
+        slot_back.send(())?;
+    }
+    let each = &each;
+    std::thread::scope(|scope| {
+        scope.spawn(move || {
+            lists.into_iter().par_bridge().for_each(|(at, i, list)| {
+                let _page = debug_span!("page", page = i + 1).entered();
+                // fails only once the writer has stopped, on an error of its own
+                let _ = done_queue.send((at, list.and_then(|list| each(i, list))));
+            })
+        });
+        let writer = scope.spawn(move || {
+            // results that came in ahead of their turn, by position
+            let mut waiting = BTreeMap::new();
+            let mut results = indices.iter().enumerate().map(|(at, &i)| {
+                while !waiting.contains_key(&at) {
+                    match done.recv() {
+                        Ok((done_at, result)) => waiting.insert(done_at, result),
+                        Err(_) => return Err(anyhow::anyhow!("page {} was left out", i + 1)),
+                    };
+                }
+                let _ = slot_back.send(());
+                waiting.remove(&at).unwrap()
+            });
+            write(&mut results)
+        });
+        for (at, &i) in indices.iter().enumerate() {
+            // the writer is gone once it failed
+            if slots.recv().is_err() {
+                break;
+            }
+            if queue.send((at, i, load(i))).is_err() {
+                break;
+            }
+        }
+        drop(queue);
+        writer.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+}
+
+/// a pixmap's samples, `channels` to a pixel, without the padding its rows may
+/// end with
+pub fn packed_samples(pixmap: &mupdf::Pixmap, channels: usize) -> Vec<u8> {
+    let row = pixmap.width() as usize * channels;
+    let stride = pixmap.stride() as usize;
+    pixmap
+        .samples()
+        .chunks(stride.max(row).max(1))
+        .take(pixmap.height() as usize)
+        .flat_map(|line| &line[..row])
+        .copied()
+        .collect()
+}
+
+/// an input as MuPDF opened it: a PDF, or another document it reads (XPS, EPUB,
+/// CBZ, ...). either renders; only a PDF has a form to check and objects for
+/// the render cache
+enum Opened {
+    Pdf(mupdf::pdf::PdfDocument),
+    Other(mupdf::Document),
+}
+
+impl Opened {
+    fn open(input: &Path) -> Result<Self> {
+        let input_str = input.to_str().context("Invalid path")?;
+        let doc = mupdf::Document::open(input_str)
+            .with_context(|| format!("Failed to open {}", input.display()))?;
+        if !doc.is_pdf() {
+            return Ok(Self::Other(doc));
+        }
+        let pdf = mupdf::pdf::PdfDocument::try_from(doc)
+            .with_context(|| format!("Failed to open {}", input.display()))?;
+        Ok(Self::Pdf(pdf))
+    }
+
+    fn pdf(&self) -> Option<&mupdf::pdf::PdfDocument> {
+        match self {
+            Self::Pdf(pdf) => Some(pdf),
+            Self::Other(_) => None,
+        }
+    }
+}
+
+impl std::ops::Deref for Opened {
+    type Target = mupdf::Document;
+
+    fn deref(&self) -> &mupdf::Document {
+        match self {
+            Self::Pdf(pdf) => pdf,
+            Self::Other(doc) => doc,
+        }
+    }
+}
+
+/// an XFA form's pages as stored are what MuPDF renders: a static form's,
+/// with the fields as AcroForm widgets, are fine, but a dynamic form's are
+/// only a placeholder, so the split stops there unless `force_static`
+fn check_xfa(input: &Path, doc: &Opened, opts: &SplitOptions) -> Result<()> {
+    let Some(doc) = doc.pdf() else {
+        return Ok(());
     };
+    let form = pdf::xfa_form(doc)
+        .with_context(|| format!("Failed to read the form of {}", input.display()))?;
+    match form {
+        Xfa::None => {}
+        Xfa::Static if !opts.quiet => {
+            info!("{} is an XFA form; rendering its AcroForm fields", input.display());
+        }
+        Xfa::Static => {}
+        Xfa::Dynamic if opts.force_static => {
+            let message = format!(
+                "{} is a dynamic XFA form; rendering its placeholder pages",
+                input.display()
+            );
+            progress::warn(&opts.progress, None, &message);
+        }
+        Xfa::Dynamic => anyhow::bail!(
+            "{} is a dynamic XFA form: its pages are laid out by the viewer from the form's \
+             XFA data, which ovid can't do, and the pages stored in the file are only a \
+             placeholder asking for another viewer. Print it to a PDF from Adobe Reader \
+             first, or pass --force-static to render the placeholder anyway",
+            input.display()
+        ),
+    }
+    Ok(())
+}
+
+/// settings for a split run
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct SplitOptions {
+    /// image format pages are saved in
+    pub format: ImageFormat,
+    /// rendering resolution
+    pub dpi: u32,
+    /// PNG compression level
+    pub compress: PngCompression,
+    /// render in grayscale instead of RGB
+    pub gray: bool,
+    /// page selection like "1,3-5"; None for every page
+    pub pages: Option<String>,
+    /// JPEG quality (1-100)
+    pub quality: u8,
+    /// don't print progress to stderr
+    pub quiet: bool,
+    /// called as each page is saved
+    pub progress: Option<Progress>,
+    /// warn about pages that fail and save the rest, instead of failing the
+    /// split; it still fails if no page could be saved
+    pub skip_errors: bool,
+    /// stops the split when cancelled
+    pub cancel: CancelToken,
+    /// bytes the pages being rendered, encoded and written may take together,
+    /// as estimated from their size in pixels; fewer pages are in flight at
+    /// once to stay under it (None: as many as there are workers)
+    pub memory_budget: Option<u64>,
+    /// dir recording the pages rendered, so pages unchanged since, whose
+    /// images are still there as written, are skipped
+    pub cache: Option<PathBuf>,
+    /// render a dynamic XFA form's placeholder pages instead of failing
+    pub force_static: bool,
+}
+
+impl Default for SplitOptions {
+    fn default() -> Self {
+        SplitOptions {
+            format: ImageFormat::Png,
+            dpi: 300,
+            compress: PngCompression::Fast,
+            gray: false,
+            pages: None,
+            quality: 75,
+            quiet: false,
+            progress: None,
+            skip_errors: false,
+            cancel: CancelToken::new(),
+            memory_budget: None,
+            cache: None,
+            force_static: false,
+        }
+    }
+}
+
+/// one setter per field, to build the options up from the defaults:
+/// `SplitOptions::default().dpi(150).gray(true)`. new fields get a setter
+/// and a default, so callers setting only what they need keep compiling
+impl SplitOptions {
+    pub fn format(mut self, format: ImageFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn dpi(mut self, dpi: u32) -> Self {
+        self.dpi = dpi;
+        self
+    }
+
+    pub fn compress(mut self, compress: PngCompression) -> Self {
+        self.compress = compress;
+        self
+    }
+
+    pub fn gray(mut self, gray: bool) -> Self {
+        self.gray = gray;
+        self
+    }
+
+    pub fn pages(mut self, pages: impl Into<Option<String>>) -> Self {
+        self.pages = pages.into();
+        self
+    }
+
+    pub fn quality(mut self, quality: u8) -> Self {
+        self.quality = quality;
+        self
+    }
+
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    pub fn progress(mut self, progress: impl Into<Option<Progress>>) -> Self {
+        self.progress = progress.into();
+        self
+    }
+
+    pub fn skip_errors(mut self, skip_errors: bool) -> Self {
+        self.skip_errors = skip_errors;
+        self
+    }
+
+    pub fn cancel(mut self, cancel: CancelToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    pub fn memory_budget(mut self, memory_budget: impl Into<Option<u64>>) -> Self {
+        self.memory_budget = memory_budget.into();
+        self
+    }
+
+    pub fn cache(mut self, cache: impl Into<Option<PathBuf>>) -> Self {
+        self.cache = cache.into();
+        self
+    }
+
+    pub fn force_static(mut self, force_static: bool) -> Self {
+        self.force_static = force_static;
+        self
+    }
+}
+
+/// what a split wrote
+#[derive(Debug, Clone)]
+pub struct SplitReport {
+    /// pages in the input PDF
+    pub page_count: usize,
+    /// images written, in page order; empty when the page went to stdout
+    pub files: Vec<PathBuf>,
+    /// pages (1-based) that failed and were skipped, with `skip_errors`
+    pub failed: Vec<usize>,
+    /// time and size of each page saved; none for a page to stdout
+    pub stats: RunStats,
+}
+
+/// the name of the image of page `index` (0-based): report_0001.png, ...
+fn page_file_name(input: &Path, index: i32, format: ImageFormat) -> String {
+    let stem = input.file_stem().and_then(|s| s.to_str()).unwrap_or("page");
+    let ext = match format {
+        ImageFormat::Png => "png",
+        ImageFormat::Jpg => "jpg",
+    };
+    format!("{}_{:04}.{}", stem, index + 1, ext)
+}
+
+/// print what `split_pdf` would do, without rendering or writing anything: the
+/// pages selected, each with the image it would be saved as and its size in
+/// pixels
+pub fn print_split_plan(input: &Path, output_dir: &Path, opts: &SplitOptions) -> Result<()> {
+    let doc = Opened::open(input)?;
+    let num_pages = doc.page_count()?;
+    check_xfa(input, &doc, opts)?;
+    let page_indices: Vec<i32> = match opts.pages.as_deref() {
+        Some(s) => parse_page_ranges(s, num_pages)?,
+        None => (0..num_pages).collect(),
+    };
+    let to_stdout = output_dir == Path::new("-");
+    anyhow::ensure!(
+        !to_stdout || page_indices.len() == 1,
+        "Stdout output requires exactly one page (got {}). Use --pages to select one.",
+        page_indices.len()
+    );
+    println!(
+        "Split {} ({} of {} page(s)) at {} DPI -> {}",
+        input.display(),
+        page_indices.len(),
+        num_pages,
+        opts.dpi,
+        if to_stdout { "stdout".into() } else { output_dir.display().to_string() }
+    );
+    let scale = opts.dpi as f32 / 72.0;
+    let digits = num_pages.to_string().len();
+    for &i in &page_indices {
+        let bounds = doc.load_page(i)?.bounds()?;
+        // MuPDF rounds the scaled page box out to whole pixels
+        let width = (bounds.width() * scale - 0.001).ceil() as u32;
+        let height = (bounds.height() * scale - 0.001).ceil() as u32;
+        let file = match to_stdout {
+            true => "stdout".into(),
+            false => output_dir.join(page_file_name(input, i, opts.format)).display().to_string(),
+        };
+        println!("  {:>digits$}  {}  {}x{} px", i + 1, file, width, height);
+    }
+    Ok(())
+}
+
+/// render pages of a PDF, or another document MuPDF reads, to images in
+/// `output_dir`, named after the input and numbered by page (report_0001.png,
+/// ...), or the one selected page to stdout for "-". pages are rendered in
+/// parallel on the rayon pool. cancelled, it starts no more pages and keeps the
+/// ones saved, listed in the [`Interrupted`] error
+pub fn split_pdf(input: &Path, output_dir: &Path, opts: &SplitOptions) -> Result<SplitReport> {
+    let SplitOptions {
+        format,
+        dpi,
+        compress,
+        gray,
+        quality,
+        quiet,
+        ..
+    } = *opts;
+    let pages = opts.pages.as_deref();
+    let doc = Opened::open(input)?;
+    let num_pages = doc.page_count()?;
+    check_xfa(input, &doc, opts)?;
 
     let page_indices: Vec<i32> = match pages {
         Some(s) => parse_page_ranges(s, num_pages)?,
         None => (0..num_pages).collect(),
     };
     let total = page_indices.len();
+    progress::emit(&opts.progress, Event::Start { total });
 
     let to_stdout = output_dir == Path::new("-");
 
@@ -111,7 +626,6 @@ pub fn split_pdf(
             total
         );
         let page_idx = page_indices[0];
-        let doc = mupdf::Document::open(&input_str)?;
         let page = doc.load_page(page_idx)?;
         let scale = dpi as f32 / 72.0;
         let matrix = mupdf::Matrix::new_scale(scale, scale);
@@ -133,27 +647,34 @@ pub fn split_pdf(
                 encode_jpg(pixmap.samples(), width, height, gray, quality, out)?;
             }
         }
-        return Ok(());
+        let page = page_idx as usize + 1;
+        progress::emit(
+            &opts.progress,
+            Event::PageDone {
+                page,
+                done: 1,
+                total,
+                file: None,
+            },
+        );
+        progress::emit(&opts.progress, Event::Finish { saved: 1, failed: 0 });
+        return Ok(SplitReport {
+            page_count: num_pages as usize,
+            files: Vec::new(),
+            failed: Vec::new(),
+            stats: RunStats::default(),
+        });
     }
 
     // dir output
     std::fs::create_dir_all(output_dir)
         .with_context(|| format!("Cannot create output dir: {}", output_dir.display()))?;
 
-    let stem = input
-        .file_stem()
-        .and_then(|s| s.to_str())
-        .unwrap_or("page")
-        .to_string();
-
-    let ext = match format {
-        ImageFormat::Png => "png",
-        ImageFormat::Jpg => "jpg",
-    };
+    let page_file_name = |i: i32| page_file_name(input, i, format);
 
     if !quiet {
         if pages.is_some() {
-            eprintln!(
+            info!(
                 "Splitting {} ({} of {} page{}) at {} DPI -> {}",
                 input.display(),
                 total,
@@ -163,7 +684,7 @@ pub fn split_pdf(
                 output_dir.display()
             );
         } else {
-            eprintln!(
+            info!(
                 "Splitting {} ({} page{}) at {} DPI -> {}",
                 input.display(),
                 num_pages,
@@ -177,85 +698,321 @@ pub fn split_pdf(
     let start = std::time::Instant::now();
     let done_count = AtomicUsize::new(0);
 
-    // divide pages into N chunks; each chunk is one rayon task that opens
-    // MuPDF Document once and processes its pages sequentially
-    // chunk count bounds concurrency (and thus peak memory)
+    // pages unchanged since the cache recorded them, their images as written,
+    // count as saved without being rendered again
+    let cache = opts.cache.as_deref().and_then(|dir| {
+        let settings = format!(
+            "ovid {} {:?} {} dpi {:?} gray {} quality {}",
+            env!("CARGO_PKG_VERSION"),
+            format,
+            dpi,
+            compress,
+            gray,
+            quality
+        );
+        let Some(pdf) = doc.pdf() else {
+            let message = format!("not using the cache: {} isn't a PDF", input.display());
+            progress::warn(&opts.progress, None, &message);
+            return None;
+        };
+        match RenderCache::open(dir, pdf, &page_indices, settings) {
+            Ok(cache) => Some(cache),
+            Err(e) => {
+                let message = format!("not using the cache: {:#}", e);
+                progress::warn(&opts.progress, None, &message);
+                None
+            }
+        }
+    });
+    let (cached, to_render): (Vec<i32>, Vec<i32>) = page_indices.par_iter().partition(|&&i| {
+        let image = output_dir.join(page_file_name(i));
+        cache.as_ref().is_some_and(|cache| cache.is_fresh(i, &image))
+    });
+    if !quiet && !cached.is_empty() {
+        info!("{} page(s) unchanged since the last run, not rendered again", cached.len());
+    }
+    for &i in &cached {
+        let done = done_count.fetch_add(1, Ordering::Relaxed) + 1;
+        let file = output_dir.join(page_file_name(i));
+        progress::emit(
+            &opts.progress,
+            Event::PageDone {
+                page: i as usize + 1,
+                done,
+                total,
+                file: Some(&file),
+            },
+        );
+    }
+
+    // three stages, joined by bounded queues that keep a few pages ahead of
+    // the next stage, and no more, to bound memory:
+    // - the document is opened and parsed once, and can't leave this thread: it
+    //   turns pages into display lists
+    // - the pool renders them. pages are handed out one at a time, not in
+    //   fixed shares, so a worker done with cheap pages takes the next one
+    //   while another is still on a heavy page
+    // - a pool of its own encodes and writes the images, so slow compression
+    //   doesn't hold up rendering
     let num_workers = rayon::current_num_threads();
-    let chunk_size = (page_indices.len() + num_workers - 1) / num_workers;
-
-    let errors: Vec<_> = page_indices
-        .chunks(chunk_size)
-        .par_bridge()
-        .flat_map(|chunk| {
-            let doc = mupdf::Document::open(&input_str)
-                .unwrap_or_else(|e| panic!("Failed to open {}: {}", input_str, e));
-            chunk
-                .iter()
-                .filter_map(|&i| {
-                    let result: Result<()> = (|| {
-                        let page = doc.load_page(i)?;
-
-                        let scale = dpi as f32 / 72.0;
-                        let matrix = mupdf::Matrix::new_scale(scale, scale);
-                        let colorspace = if gray {
-                            mupdf::Colorspace::device_gray()
-                        } else {
-                            mupdf::Colorspace::device_rgb()
-                        };
-                        let pixmap = page.to_pixmap(&matrix, &colorspace, false, true)?;
-
-                        let width = pixmap.width();
-                        let height = pixmap.height();
-                        let filename = format!("{}_{:04}.{}", stem, i + 1, ext);
-                        let out_path = output_dir.join(&filename);
-
-                        match format {
-                            ImageFormat::Png => {
-                                let file = std::fs::File::create(&out_path).with_context(
-                                    || format!("Failed to create {}", out_path.display()),
-                                )?;
-                                encode_png(
-                                    pixmap.samples(),
-                                    width,
-                                    height,
-                                    gray,
-                                    compress,
-                                    file,
-                                )?;
-                            }
-                            ImageFormat::Jpg => {
-                                let file = std::fs::File::create(&out_path).with_context(
-                                    || format!("Failed to create {}", out_path.display()),
-                                )?;
-                                let out = std::io::BufWriter::new(file);
-                                encode_jpg(
-                                    pixmap.samples(),
-                                    width,
-                                    height,
-                                    gray,
-                                    quality,
-                                    out,
-                                )?;
+    let encoders = rayon::ThreadPoolBuilder::new()
+        .num_threads(num_workers)
+        .thread_name(|i| format!("ovid-encode-{}", i))
+        .build()
+        .context("Failed to start the encoding threads")?;
+    // with a memory budget a page is only rendered once its share fits next
+    // to the pages still being rendered, encoded and written
+    let budget = opts.memory_budget.map(MemoryBudget::new);
+    let (queue, lists) = mpsc::sync_channel::<(i32, Result<mupdf::DisplayList>)>(num_workers * 2);
+    let (rendered_queue, rendered) = mpsc::sync_channel::<RenderedPage>(num_workers);
+    // pixmaps come back here once encoded, for the pages after; a few sizes'
+    // worth, as pages mostly repeat one
+    let spare: Mutex<Vec<Frame>> = Mutex::new(Vec::new());
+    let spare_limit = num_workers * 2;
+    let page_stats: Mutex<Vec<PageStats>> = Mutex::new(Vec::new());
+
+    let failed = |i: i32, error: anyhow::Error| {
+        let done = done_count.fetch_add(1, Ordering::Relaxed) + 1;
+        let page = i as usize + 1;
+        progress::emit(
+            &opts.progress,
+            Event::PageFailed {
+                page,
+                done,
+                total,
+                error: &error,
+            },
+        );
+        (i, Err(error))
+    };
+
+    let render = |list: mupdf::DisplayList, i: i32| {
+        let scale = dpi as f32 / 72.0;
+        let matrix = mupdf::Matrix::new_scale(scale, scale);
+        // the pixmap, then the encoded image at worst half as big
+        let permit = budget.as_ref().map(|budget| {
+            let bbox = list.bounds().transform(&matrix).round();
+            let (width, height) = ((bbox.x1 - bbox.x0).max(0), (bbox.y1 - bbox.y0).max(0));
+            let raw = width as u64 * height as u64 * if gray { 1 } else { 3 };
+            budget.acquire(raw + raw / 2)
+        });
+        let started = std::time::Instant::now();
+        let colorspace = if gray {
+            mupdf::Colorspace::device_gray()
+        } else {
+            mupdf::Colorspace::device_rgb()
+        };
+        let pixmap = render_into(&spare, &list, &matrix, &colorspace)?;
+        anyhow::Ok(RenderedPage {
+            index: i,
+            width: pixmap.width(),
+            height: pixmap.height(),
+            frame: Frame(pixmap),
+            render: started.elapsed(),
+            _permit: permit,
+        })
+    };
+
+    // encoded in memory, so the file is only created for a whole image and
+    // writing can be timed on its own
+    let save = |buffers: &mut EncodeBuffers, rendered: &RenderedPage| -> Result<()> {
+        let started = std::time::Instant::now();
+        let RenderedPage {
+            index: i,
+            width,
+            height,
+            ref frame,
+            render,
+            ..
+        } = *rendered;
+        let samples = frame.0.samples();
+        let page = i as usize + 1;
+        let filename = page_file_name(i);
+        let out_path = output_dir.join(&filename);
+        let EncodeBuffers { jpeg, png } = buffers;
+        let encoded: &[u8] = match format {
+            ImageFormat::Png => {
+                png.clear();
+                encode_png(samples, width, height, gray, compress, &mut *png)?;
+                png
+            }
+            ImageFormat::Jpg => {
+                let jpeg = match jpeg {
+                    Some(jpeg) => jpeg,
+                    None => jpeg.insert(JpegEncoder::new(gray, quality)?),
+                };
+                jpeg.encode(samples, width, height)?
+            }
+        };
+        let encoded_at = started.elapsed();
+        if let Err(e) = std::fs::write(&out_path, encoded) {
+            // don't leave a truncated image behind
+            let _ = std::fs::remove_file(&out_path);
+            return Err(e).with_context(|| format!("Failed to write {}", out_path.display()));
+        }
+        if let Some(Err(e)) = cache.as_ref().map(|cache| cache.record(i, encoded)) {
+            progress::warn(&opts.progress, Some(page), &format!("{:#}", e));
+        }
+        let bytes = encoded.len() as u64;
+        progress::emit(&opts.progress, Event::BytesWritten { page, bytes });
+
+        let channels = if gray { 1 } else { 3 };
+        let raw = width as u64 * height as u64 * channels;
+        debug!(
+            worker = rayon::current_thread_index(),
+            "saved {}: {}x{} {}, {} -> {} ({:.1}:1)",
+            filename,
+            width,
+            height,
+            if gray { "gray" } else { "RGB" },
+            ByteSize(raw),
+            ByteSize(bytes),
+            raw as f64 / bytes.max(1) as f64
+        );
+        let written_at = started.elapsed();
+        trace!(
+            render_ms = render.as_millis() as u64,
+            encode_ms = encoded_at.as_millis() as u64,
+            write_ms = (written_at - encoded_at).as_millis() as u64,
+            "timings"
+        );
+        page_stats.lock().unwrap().push(PageStats {
+            page,
+            stages: [
+                ("render", render),
+                ("encode", encoded_at),
+                ("write", written_at - encoded_at),
+            ],
+            raw_bytes: raw,
+            bytes,
+        });
+        let done = done_count.fetch_add(1, Ordering::Relaxed) + 1;
+        if !quiet {
+            info!("  [{}/{}] {}", done, total, filename);
+        }
+        progress::emit(
+            &opts.progress,
+            Event::PageDone {
+                page,
+                done,
+                total,
+                file: Some(&out_path),
+            },
+        );
+        Ok(())
+    };
+
+    let outcomes: Vec<(i32, Result<()>)> = std::thread::scope(|scope| {
+        let renderers = scope.spawn(|| {
+            let failures: Vec<_> = lists
+                .into_iter()
+                .par_bridge()
+                .map(|(i, list)| {
+                    // once cancelled, the pages queued are left out
+                    if opts.cancel.is_cancelled() {
+                        return None;
+                    }
+                    let _page = debug_span!("page", page = i + 1).entered();
+                    let page = i as usize + 1;
+                    progress::emit(&opts.progress, Event::PageStarted { page, total });
+                    match list.and_then(|list| render(list, i)) {
+                        Ok(rendered) => {
+                            // fails only if the encoders panicked, which is
+                            // raised below
+                            let _ = rendered_queue.send(rendered);
+                            None
+                        }
+                        Err(error) => Some(failed(i, error)),
+                    }
+                })
+                .flatten()
+                .collect();
+            // the encoders finish once the last page is in
+            drop(rendered_queue);
+            failures
+        });
+        let writers = scope.spawn(|| {
+            encoders.install(|| {
+                rendered
+                    .into_iter()
+                    .par_bridge()
+                    .map_init(EncodeBuffers::default, |buffers, rendered| {
+                        if opts.cancel.is_cancelled() {
+                            return None;
+                        }
+                        let i = rendered.index;
+                        let _page = debug_span!("page", page = i + 1).entered();
+                        let result = save(buffers, &rendered);
+                        if budget.is_none() {
+                            let mut spare = spare.lock().unwrap();
+                            if spare.len() == spare_limit {
+                                spare.remove(0);
                             }
+                            spare.push(rendered.frame);
+                        } else {
+                            *buffers = EncodeBuffers::default();
                         }
+                        Some(match result {
+                            Ok(()) => (i, Ok(())),
+                            Err(error) => failed(i, error),
+                        })
+                    })
+                    .flatten()
+                    .collect::<Vec<_>>()
+            })
+        });
+        for &i in &to_render {
+            if opts.cancel.is_cancelled() {
+                break;
+            }
+            // annotations included, as a page renders by itself
+            let list = doc.load_page(i).and_then(|loaded| loaded.to_display_list(true));
+            if queue.send((i, list.map_err(Into::into))).is_err() {
+                break;
+            }
+        }
+        drop(queue);
+        let join = |stage: std::thread::ScopedJoinHandle<Vec<(i32, Result<()>)>>| {
+            stage.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+        };
+        let mut outcomes = join(renderers);
+        outcomes.extend(join(writers));
+        outcomes.extend(cached.iter().map(|&i| (i, Ok(()))));
+        outcomes
+    });
 
-                        if !quiet {
-                            let done = done_count.fetch_add(1, Ordering::Relaxed) + 1;
-                            eprintln!("  [{}/{}] {}", done, total, filename);
-                        }
-                        Ok(())
-                    })();
+    if opts.cancel.is_cancelled() {
+        let mut saved: Vec<i32> = outcomes
+            .iter()
+            .filter(|(_, result)| result.is_ok())
+            .map(|&(i, _)| i)
+            .collect();
+        saved.sort();
+        return Err(Interrupted {
+            done: outcomes.len(),
+            total,
+            files: saved.iter().map(|&i| output_dir.join(page_file_name(i))).collect(),
+        }
+        .into());
+    }
 
-                    result.err().map(|e| (i, e))
-                })
-                .collect::<Vec<_>>()
-        })
+    let mut errors: Vec<_> = outcomes
+        .into_iter()
+        .filter_map(|(i, result)| result.err().map(|e| (i, e)))
         .collect();
-
-    if !errors.is_empty() {
+    errors.sort_by_key(|&(page, _)| page);
+    let failed: Vec<i32> = errors.iter().map(|&(page, _)| page).collect();
+    if opts.skip_errors && !errors.is_empty() && errors.len() < total {
+        for (page, err) in &errors {
+            let message = format!("skipping page {}: {:#}", page + 1, err);
+            progress::warn(&opts.progress, Some(*page as usize + 1), &message);
+        }
+        let message = format!("{} of {} page(s) failed", errors.len(), total);
+        progress::warn(&opts.progress, None, &message);
+    } else if !errors.is_empty() {
         let count = errors.len();
         for &(page, ref err) in &errors {
-            eprintln!("  error: page {}: {}", page + 1, err);
+            error!("page {}: {}", page + 1, err);
         }
         let (page, err) = errors.into_iter().next().unwrap();
         return Err(err.context(format!(
@@ -266,13 +1023,30 @@ pub fn split_pdf(
         )));
     }
 
+    let saved = total - failed.len();
+    progress::emit(
+        &opts.progress,
+        Event::Finish {
+            saved,
+            failed: failed.len(),
+        },
+    );
     if !quiet {
         let elapsed = start.elapsed();
-        eprintln!(
+        info!(
             "Done. {} images in {:.2}s",
-            total,
+            saved,
             elapsed.as_secs_f64()
         );
     }
-    Ok(())
+    Ok(SplitReport {
+        page_count: num_pages as usize,
+        files: page_indices
+            .iter()
+            .filter(|i| !failed.contains(i))
+            .map(|&i| output_dir.join(page_file_name(i)))
+            .collect(),
+        failed: failed.iter().map(|&i| i as usize + 1).collect(),
+        stats: RunStats::new(page_stats.into_inner().unwrap(), start.elapsed()),
+    })
 }
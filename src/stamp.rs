@@ -0,0 +1,172 @@
+use anyhow::{Context, Result};
+use lopdf::content::{Content, Operation};
+use lopdf::{dictionary, Object, ObjectId, Stream, StringFormat};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::parse::{Color, Position, StampFont};
+use crate::pdf;
+use crate::watermark::{
+    encode_win_ansi, standard_font, text_width_em, HELVETICA_BOLD_WIDTHS, HELVETICA_WIDTHS,
+};
+
+/// font resource name in stamp forms
+const FONT_NAME: &str = "Fbn";
+/// cap height of the standard fonts, roughly, in 1/1000 em
+const CAP_HEIGHT: f32 = 718.0;
+
+pub struct BatesOptions<'a> {
+    pub prefix: &'a str,
+    /// number of the first page
+    pub start: u64,
+    /// counter width, zero-padded
+    pub digits: usize,
+    pub position: Position,
+    /// distance from the page edge for named positions, in points
+    pub margin: f32,
+    pub font: StampFont,
+    pub size: f32,
+    pub color: Color,
+    pub json: bool,
+    pub quiet: bool,
+}
+
+/// Bates numbers given to one file
+#[derive(Serialize)]
+struct Assigned {
+    #[serde(rename = "file")]
+    input: PathBuf,
+    output: PathBuf,
+    first: String,
+    last: String,
+    pages: usize,
+}
+
+/// Bates-number the pages of `inputs`, one counter running through all of them
+/// in order, writing each file under its own name to `output_dir`. prints the
+/// range each file got
+pub fn bates_stamp(inputs: &[PathBuf], output_dir: &Path, opts: &BatesOptions) -> Result<()> {
+    let start = std::time::Instant::now();
+    std::fs::create_dir_all(output_dir)
+        .with_context(|| format!("Failed to create {}", output_dir.display()))?;
+    let mut outputs: Vec<PathBuf> = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        let name = input.file_name().context("Input has no file name")?;
+        let output = output_dir.join(name);
+        anyhow::ensure!(
+            !outputs.contains(&output),
+            "Two inputs are named {}; stamp them into separate directories",
+            name.to_string_lossy()
+        );
+        let same = std::fs::canonicalize(input)
+            .is_ok_and(|input| std::fs::canonicalize(&output).is_ok_and(|out| out == input));
+        anyhow::ensure!(
+            !same,
+            "{} would be overwritten; choose another output directory",
+            input.display()
+        );
+        outputs.push(output);
+    }
+
+    let mut next = opts.start;
+    let mut assigned = Vec::with_capacity(inputs.len());
+    for (input, output) in inputs.iter().zip(outputs) {
+        let mut doc = pdf::load(input)?;
+        let page_ids: Vec<ObjectId> = doc.get_pages().into_values().collect();
+        anyhow::ensure!(!page_ids.is_empty(), "{} has no pages", input.display());
+        if !opts.quiet {
+            info!("Stamping {} -> {}", input.display(), output.display());
+        }
+        let font_id = doc.add_object(standard_font(opts.font.base_font()));
+        let first = label(opts, next);
+        for &page_id in &page_ids {
+            let text = label(opts, next);
+            let (m, w, h) = pdf::display_transform(&doc, page_id);
+            let content = Content {
+                operations: operations(opts, &text, w, h),
+            };
+            let mut form = Stream::new(
+                dictionary! {
+                    "Type" => "XObject",
+                    "Subtype" => "Form",
+                    "BBox" => [0.0, 0.0, w, h].map(Object::Real).to_vec(),
+                    "Matrix" => m.map(Object::Real).to_vec(),
+                    "Resources" => dictionary! {
+                        "Font" => dictionary! { FONT_NAME => font_id },
+                    },
+                },
+                content.encode()?,
+            );
+            form.compress()?;
+            let form_id = doc.add_object(form);
+            pdf::stamp_form(&mut doc, page_id, form_id)?;
+            next += 1;
+        }
+        pdf::save(&mut doc, &output)?;
+        assigned.push(Assigned {
+            input: input.clone(),
+            output,
+            first,
+            last: label(opts, next - 1),
+            pages: page_ids.len(),
+        });
+    }
+
+    if opts.json {
+        println!("{}", serde_json::to_string(&assigned)?);
+    } else {
+        for a in &assigned {
+            println!("{}-{}  {:>5} page(s)  {}", a.first, a.last, a.pages, a.input.display());
+        }
+    }
+    if !opts.quiet {
+        info!(
+            "Done. {} page(s) stamped in {:.2}s",
+            next - opts.start,
+            start.elapsed().as_secs_f64()
+        );
+    }
+    Ok(())
+}
+
+/// prefix and zero-padded counter, e.g. ABC000042
+fn label(opts: &BatesOptions, n: u64) -> String {
+    format!("{}{:0width$}", opts.prefix, n, width = opts.digits)
+}
+
+/// content stream operations printing `text` on a `page_w` x `page_h` page
+fn operations(opts: &BatesOptions, text: &str, page_w: f32, page_h: f32) -> Vec<Operation> {
+    let encoded = encode_win_ansi(text);
+    let em = match opts.font {
+        StampFont::Helvetica => text_width_em(&HELVETICA_WIDTHS, &encoded),
+        StampFont::HelveticaBold => text_width_em(&HELVETICA_BOLD_WIDTHS, &encoded),
+        StampFont::Courier => 0.6 * encoded.len() as f32,
+    };
+    let (w, h) = (em * opts.size, CAP_HEIGHT / 1000.0 * opts.size);
+    let margin = opts.margin;
+    let (x, y) = match opts.position {
+        Position::Anchor { .. } => {
+            let (x, y) = opts
+                .position
+                .place(page_w - 2.0 * margin, page_h - 2.0 * margin, w, h);
+            (x + margin, y + margin)
+        }
+        Position::Offset { .. } => opts.position.place(page_w, page_h, w, h),
+    };
+    let c = opts.color;
+    vec![
+        Operation::new("BT", vec![]),
+        Operation::new(
+            "Tf",
+            vec![Object::Name(FONT_NAME.as_bytes().to_vec()), Object::Real(opts.size)],
+        ),
+        Operation::new(
+            "rg",
+            [c.r, c.g, c.b].map(|v| Object::Real(v as f32 / 255.0)).to_vec(),
+        ),
+        Operation::new("Td", vec![Object::Real(x), Object::Real(y)]),
+        Operation::new("Tj", vec![Object::String(encoded, StringFormat::Literal)]),
+        Operation::new("ET", vec![]),
+    ]
+}
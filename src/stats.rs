@@ -0,0 +1,260 @@
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::parse::ByteSize;
+
+/// pages listed as the slowest of a run
+const SLOWEST: usize = 5;
+
+/// where one page's time went and how well it compressed. `page` is 1-based;
+/// the stages are render, encode and write for a split, and read, prepare
+/// (decode and compress) and write for a merge
+#[derive(Debug, Clone)]
+pub struct PageStats {
+    pub page: usize,
+    pub stages: [(&'static str, Duration); 3],
+    /// the page's pixels, uncompressed
+    pub raw_bytes: u64,
+    /// what was written for it
+    pub bytes: u64,
+}
+
+impl PageStats {
+    /// time spent on the page, all stages
+    pub fn total(&self) -> Duration {
+        self.stages.iter().map(|&(_, time)| time).sum()
+    }
+
+    fn ratio(&self) -> f64 {
+        self.raw_bytes as f64 / self.bytes.max(1) as f64
+    }
+}
+
+/// timings and sizes of a split or merge, for `--stats`
+#[derive(Debug, Clone, Default)]
+pub struct RunStats {
+    /// the pages done, in page order
+    pub pages: Vec<PageStats>,
+    /// wall-clock time of the run
+    pub elapsed: Duration,
+    /// the most memory the process has had resident, where the OS tells
+    pub peak_memory: Option<u64>,
+}
+
+impl RunStats {
+    /// stats for `pages` done in `elapsed`, taking the peak memory so far
+    pub fn new(mut pages: Vec<PageStats>, elapsed: Duration) -> Self {
+        pages.sort_by_key(|page| page.page);
+        RunStats {
+            pages,
+            elapsed,
+            peak_memory: peak_memory(),
+        }
+    }
+
+    fn raw_bytes(&self) -> u64 {
+        self.pages.iter().map(|page| page.raw_bytes).sum()
+    }
+
+    fn bytes(&self) -> u64 {
+        self.pages.iter().map(|page| page.bytes).sum()
+    }
+
+    /// each stage's time, summed over the pages (and so over the workers)
+    fn stage_totals(&self) -> Vec<(&'static str, Duration)> {
+        let Some(first) = self.pages.first() else {
+            return Vec::new();
+        };
+        (0..first.stages.len())
+            .map(|i| {
+                let time = self.pages.iter().map(|page| page.stages[i].1).sum();
+                (first.stages[i].0, time)
+            })
+            .collect()
+    }
+
+    /// the pages that took longest, slowest first
+    fn slowest(&self) -> Vec<&PageStats> {
+        let mut pages: Vec<&PageStats> = self.pages.iter().collect();
+        pages.sort_by_key(|page| std::cmp::Reverse(page.total()));
+        pages.truncate(SLOWEST);
+        pages
+    }
+
+    fn per_second(&self, n: f64) -> f64 {
+        n / self.elapsed.as_secs_f64().max(1e-9)
+    }
+
+    /// the report for people: totals and throughput, time per stage, the
+    /// slowest pages, then a line per page
+    pub fn to_text(&self) -> String {
+        let (raw, bytes) = (self.raw_bytes(), self.bytes());
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{} page(s) in {:.2}s: {:.1} pages/s, {} of pixels at {}/s -> {} ({:.1}:1)",
+            self.pages.len(),
+            self.elapsed.as_secs_f64(),
+            self.per_second(self.pages.len() as f64),
+            ByteSize(raw),
+            ByteSize(self.per_second(raw as f64) as u64),
+            ByteSize(bytes),
+            raw as f64 / bytes.max(1) as f64
+        );
+        let stages: Vec<String> = self
+            .stage_totals()
+            .iter()
+            .map(|(name, time)| format!("{} {:.2}s", name, time.as_secs_f64()))
+            .collect();
+        if !stages.is_empty() {
+            let _ = writeln!(out, "time per stage, over all workers: {}", stages.join(", "));
+        }
+        let peak = self.peak_memory.map_or("unknown".into(), |bytes| ByteSize(bytes).to_string());
+        let _ = writeln!(out, "peak memory: {}", peak);
+        if self.pages.is_empty() {
+            return out;
+        }
+        let _ = writeln!(out, "slowest pages:");
+        for page in self.slowest() {
+            let _ = writeln!(out, "  {}", page_line(page));
+        }
+        let _ = writeln!(out, "per page:");
+        for page in &self.pages {
+            let _ = writeln!(out, "  {}", page_line(page));
+        }
+        out
+    }
+
+    /// the report as one JSON object, pages included
+    pub fn to_json(&self) -> String {
+        let (raw, bytes) = (self.raw_bytes(), self.bytes());
+        let report = StatsJson {
+            pages: self.pages.len(),
+            elapsed_ms: self.elapsed.as_millis() as u64,
+            pages_per_sec: self.per_second(self.pages.len() as f64),
+            raw_bytes: raw,
+            bytes,
+            ratio: raw as f64 / bytes.max(1) as f64,
+            raw_bytes_per_sec: self.per_second(raw as f64) as u64,
+            peak_memory: self.peak_memory,
+            stages: stage_ms(self.stage_totals()),
+            slowest: self.slowest().iter().map(|page| page.page).collect(),
+            per_page: self.pages.iter().map(PageJson::from).collect(),
+        };
+        serde_json::to_string(&report).expect("stats are plain numbers")
+    }
+}
+
+#[derive(Serialize)]
+struct StatsJson {
+    pages: usize,
+    elapsed_ms: u64,
+    pages_per_sec: f64,
+    raw_bytes: u64,
+    bytes: u64,
+    ratio: f64,
+    raw_bytes_per_sec: u64,
+    peak_memory: Option<u64>,
+    stages: Map<String, Value>,
+    slowest: Vec<usize>,
+    per_page: Vec<PageJson>,
+}
+
+#[derive(Serialize)]
+struct PageJson {
+    page: usize,
+    #[serde(flatten)]
+    stages: Map<String, Value>,
+    raw_bytes: u64,
+    bytes: u64,
+    ratio: f64,
+}
+
+impl From<&PageStats> for PageJson {
+    fn from(page: &PageStats) -> Self {
+        PageJson {
+            page: page.page,
+            stages: stage_ms(page.stages),
+            raw_bytes: page.raw_bytes,
+            bytes: page.bytes,
+            ratio: page.ratio(),
+        }
+    }
+}
+
+/// stage times as `render_ms` and so on, in stage order
+fn stage_ms(stages: impl IntoIterator<Item = (&'static str, Duration)>) -> Map<String, Value> {
+    stages
+        .into_iter()
+        .map(|(name, time)| (format!("{}_ms", name), (time.as_millis() as u64).into()))
+        .collect()
+}
+
+/// `page 12: 1.50s (render 1203 ms, ...), 24.9 MB -> 1.2 MB (20.7:1)`
+fn page_line(page: &PageStats) -> String {
+    let stages: Vec<String> = page
+        .stages
+        .iter()
+        .map(|(name, time)| format!("{} {} ms", name, time.as_millis()))
+        .collect();
+    format!(
+        "page {}: {:.2}s ({}), {} -> {} ({:.1}:1)",
+        page.page,
+        page.total().as_secs_f64(),
+        stages.join(", "),
+        ByteSize(page.raw_bytes),
+        ByteSize(page.bytes),
+        page.ratio()
+    )
+}
+
+/// the process's peak resident memory, from /proc on Linux; None elsewhere
+fn peak_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find_map(|line| line.strip_prefix("VmHWM:"))?;
+    let kb: u64 = line.trim().trim_end_matches("kB").trim().parse().ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(page: usize, render_ms: u64, bytes: u64) -> PageStats {
+        let ms = Duration::from_millis;
+        PageStats {
+            page,
+            stages: [("render", ms(render_ms)), ("encode", ms(10)), ("write", ms(1))],
+            raw_bytes: 1000,
+            bytes,
+        }
+    }
+
+    #[test]
+    fn slowest_pages_and_totals() {
+        let pages = (1..=8).map(|n| page(n, n as u64 * 7 % 9 * 100, 400)).collect();
+        let stats = RunStats::new(pages, Duration::from_millis(2500));
+        let slowest: Vec<usize> = stats.slowest().iter().map(|page| page.page).collect();
+        assert_eq!(slowest, [5, 1, 6, 2, 7]);
+        assert_eq!(stats.stage_totals()[1], ("encode", Duration::from_millis(80)));
+
+        let json: Value = serde_json::from_str(&stats.to_json()).unwrap();
+        assert_eq!(json["pages"], 8);
+        assert_eq!(json["ratio"], 2.5);
+        assert_eq!(json["pages_per_sec"], 3.2);
+        assert_eq!(json["stages"]["encode_ms"], 80);
+        assert_eq!(json["per_page"][4]["render_ms"], 800);
+    }
+
+    #[test]
+    fn text_lists_each_page() {
+        let stats = RunStats::new(vec![page(2, 300, 50), page(1, 100, 100)], Duration::ZERO);
+        let text = stats.to_text();
+        assert!(text.starts_with("2 page(s) in 0.00s"), "{}", text);
+        assert!(text.contains("time per stage, over all workers: render 0.40s"));
+        let per_page = text.split("per page:\n").nth(1).unwrap();
+        assert!(per_page.starts_with("  page 1: 0.11s (render 100 ms, encode 10 ms, write 1 ms)"));
+    }
+}
@@ -0,0 +1,126 @@
+use anyhow::Result;
+use std::future::Future;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use crate::interrupt::CancelToken;
+use crate::merge::{self, MergeOptions, MergeReport};
+#[cfg(feature = "native")]
+use crate::split::{self, SplitOptions, SplitReport};
+
+/// a split or merge running on a thread of its own, for hosts that can't block
+/// on one: `.await` it from any executor, [`Task::wait`] for it, or
+/// [`Task::cancel`] it. a task dropped before it's done is cancelled
+pub struct Task<T> {
+    shared: Arc<Shared<T>>,
+    cancel: CancelToken,
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    done: Condvar,
+}
+
+struct State<T> {
+    result: Option<Result<T>>,
+    finished: bool,
+    waker: Option<Waker>,
+}
+
+/// render pages of a PDF to images in the background, as
+/// [`split::split_pdf`]; `opts.cancel` is the task's
+#[cfg(feature = "native")]
+pub fn split(input: PathBuf, output_dir: PathBuf, opts: SplitOptions) -> Task<SplitReport> {
+    let cancel = opts.cancel.clone();
+    spawn(cancel, move || split::split_pdf(&input, &output_dir, &opts))
+}
+
+/// build a PDF or EPUB from images in the background, as
+/// [`merge::merge_images`]; `opts.cancel` is the task's
+pub fn merge(images: Vec<PathBuf>, output: PathBuf, opts: MergeOptions) -> Task<MergeReport> {
+    let cancel = opts.cancel.clone();
+    spawn(cancel, move || merge::merge_images(&images, &output, &opts))
+}
+
+fn spawn<T: Send + 'static>(
+    cancel: CancelToken,
+    run: impl FnOnce() -> Result<T> + Send + 'static,
+) -> Task<T> {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            result: None,
+            finished: false,
+            waker: None,
+        }),
+        done: Condvar::new(),
+    });
+    let task = Task {
+        shared: shared.clone(),
+        cancel,
+    };
+    std::thread::Builder::new()
+        .name("ovid-task".into())
+        .spawn(move || {
+            // a panic is the task's error, not a waiter's hang
+            let result = catch_unwind(AssertUnwindSafe(run))
+                .unwrap_or_else(|_| Err(anyhow::anyhow!("ovid task panicked")));
+            let mut state = shared.state.lock().unwrap_or_else(|e| e.into_inner());
+            state.result = Some(result);
+            state.finished = true;
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+            shared.done.notify_all();
+        })
+        .expect("failed to spawn a task thread");
+    task
+}
+
+impl<T> Task<T> {
+    /// stop the run; it returns [`crate::interrupt::Interrupted`] with what it
+    /// got done
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.lock().finished
+    }
+
+    /// block until the run is over
+    pub fn wait(self) -> Result<T> {
+        let mut state = self.lock();
+        while !state.finished {
+            state = self.shared.done.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+        state.result.take().expect("task result taken twice")
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State<T>> {
+        self.shared.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<T> Future for Task<T> {
+    type Output = Result<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.lock();
+        if state.finished {
+            return Poll::Ready(state.result.take().expect("task polled after completion"));
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for Task<T> {
+    fn drop(&mut self) {
+        if !self.lock().finished {
+            self.cancel.cancel();
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use anyhow::{Context, Result};
+use serde::{Serialize, Serializer};
+use std::io::Write;
+use std::path::Path;
+use tracing::info;
+
+use crate::parse::parse_page_ranges;
+use crate::split;
+
+pub struct TextOptions<'a> {
+    /// page selection like "1,3-5"
+    pub pages: Option<&'a str>,
+    /// one file per page instead of a single output
+    pub per_page: bool,
+    /// JSON with word bounding boxes instead of plain text
+    pub json: bool,
+    pub quiet: bool,
+}
+
+/// a run of non-space characters on one line, with its bounding box in page
+/// space (points, origin at the top left)
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Word {
+    pub text: String,
+    #[serde(serialize_with = "rounded")]
+    pub bbox: [f32; 4],
+}
+
+#[derive(Serialize)]
+struct PageText {
+    /// 1-based
+    #[serde(rename = "page")]
+    number: u32,
+    width: f32,
+    height: f32,
+    text: String,
+    words: Vec<Word>,
+}
+
+/// a box to the hundredth of a point
+fn rounded<S: Serializer>(bbox: &[f32; 4], s: S) -> Result<S::Ok, S::Error> {
+    bbox.map(round2).serialize(s)
+}
+
+/// extract text with MuPDF's structured text: to `output` (a file, or "-" for
+/// stdout), or with `per_page` into `output` as <name>_0001.txt (or .json) files
+pub fn extract_text(input: &Path, output: &Path, opts: &TextOptions) -> Result<()> {
+    let input_str = input.to_str().context("Invalid path")?;
+    let doc = mupdf::Document::open(input_str)
+        .with_context(|| format!("Failed to open {}", input.display()))?;
+    let num_pages = doc.page_count()?;
+    let page_indices: Vec<i32> = match opts.pages {
+        Some(s) => parse_page_ranges(s, num_pages)?,
+        None => (0..num_pages).collect(),
+    };
+    let start = std::time::Instant::now();
+    if !opts.quiet {
+        info!(
+            "Extracting text from {} ({} page{}) -> {}",
+            input.display(),
+            page_indices.len(),
+            if page_indices.len() == 1 { "" } else { "s" },
+            output.display()
+        );
+    }
+
+    // extracted on the pool from one parse of the document, and written out a
+    // page at a time, in order
+    let extract = |i: i32, list: mupdf::DisplayList| {
+        extract_page(&list, i, opts.json)
+            .with_context(|| format!("Failed to extract text from page {}", i + 1))
+    };
+    if opts.per_page {
+        anyhow::ensure!(output != Path::new("-"), "Per-page output needs a directory");
+        std::fs::create_dir_all(output)
+            .with_context(|| format!("Cannot create output dir: {}", output.display()))?;
+        let stem = input.file_stem().and_then(|s| s.to_str()).unwrap_or("page");
+        let ext = if opts.json { "json" } else { "txt" };
+        split::pages_in_order(&doc, &page_indices, extract, |pages| {
+            for page in pages {
+                let page = page?;
+                let path = output.join(format!("{}_{:04}.{}", stem, page.number, ext));
+                let data = if opts.json {
+                    format!("{}\n", serde_json::to_string(&page)?)
+                } else {
+                    page.text
+                };
+                std::fs::write(&path, data)
+                    .with_context(|| format!("Failed to write {}", path.display()))?;
+            }
+            Ok(())
+        })?;
+    } else if output == Path::new("-") {
+        split::pages_in_order(&doc, &page_indices, extract, |pages| {
+            let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+            write_pages(&mut out, "text to stdout", input, pages, opts.json)
+        })?;
+    } else {
+        let to = output.display().to_string();
+        split::pages_in_order(&doc, &page_indices, extract, |pages| {
+            let file = std::fs::File::create(output)
+                .with_context(|| format!("Failed to write {}", to))?;
+            let mut out = std::io::BufWriter::new(file);
+            let result = write_pages(&mut out, &to, input, pages, opts.json);
+            drop(out);
+            // no text cut off partway
+            if result.is_err() {
+                let _ = std::fs::remove_file(output);
+            }
+            result
+        })?;
+    }
+
+    if !opts.quiet {
+        info!("Done. Text extracted in {:.2}s", start.elapsed().as_secs_f64());
+    }
+    Ok(())
+}
+
+/// the pages as one text, with form feeds between pages like pdftotext, or as
+/// one JSON object with an array of the pages, written as they come in
+fn write_pages(
+    out: &mut dyn Write,
+    to: &str,
+    input: &Path,
+    pages: &mut dyn Iterator<Item = Result<PageText>>,
+    json: bool,
+) -> Result<()> {
+    let failed = || format!("Failed to write {}", to);
+    if json {
+        let file = serde_json::to_string(&input.display().to_string())?;
+        write!(out, "{{\"file\":{},\"pages\":[", file).with_context(failed)?;
+    }
+    for (n, page) in pages.enumerate() {
+        let page = page?;
+        if n > 0 {
+            out.write_all(if json { b"," } else { b"\x0c" }).with_context(failed)?;
+        }
+        if json {
+            serde_json::to_writer(&mut *out, &page).with_context(failed)?;
+        } else {
+            out.write_all(page.text.as_bytes()).with_context(failed)?;
+        }
+    }
+    if json {
+        writeln!(out, "]}}").with_context(failed)?;
+    }
+    out.flush().with_context(failed)
+}
+
+fn extract_page(list: &mupdf::DisplayList, index: i32, words: bool) -> Result<PageText> {
+    let bounds = list.bounds();
+    let text_page = list.to_text_page(mupdf::TextPageFlags::empty())?;
+    let text = text_page.to_text()?;
+    let mut page_words = Vec::new();
+    if words {
+        for block in text_page.blocks() {
+            for line in block.lines() {
+                let chars: Vec<(char, [f32; 4])> = line
+                    .chars()
+                    .filter_map(|c| {
+                        let q = c.quad();
+                        let xs = [q.ul.x, q.ur.x, q.ll.x, q.lr.x];
+                        let ys = [q.ul.y, q.ur.y, q.ll.y, q.lr.y];
+                        let bbox = [
+                            xs.iter().copied().fold(f32::INFINITY, f32::min),
+                            ys.iter().copied().fold(f32::INFINITY, f32::min),
+                            xs.iter().copied().fold(f32::NEG_INFINITY, f32::max),
+                            ys.iter().copied().fold(f32::NEG_INFINITY, f32::max),
+                        ];
+                        Some((c.char()?, bbox))
+                    })
+                    .collect();
+                page_words.extend(split_words(&chars));
+            }
+        }
+    }
+    Ok(PageText {
+        number: index as u32 + 1,
+        width: bounds.width(),
+        height: bounds.height(),
+        text,
+        words: page_words,
+    })
+}
+
+/// group a line's characters into words at whitespace, merging their boxes
+pub fn split_words(chars: &[(char, [f32; 4])]) -> Vec<Word> {
+    let mut words: Vec<Word> = Vec::new();
+    let mut current: Option<Word> = None;
+    for &(c, bbox) in chars {
+        if c.is_whitespace() {
+            words.extend(current.take());
+            continue;
+        }
+        match &mut current {
+            Some(word) => {
+                word.text.push(c);
+                word.bbox = [
+                    word.bbox[0].min(bbox[0]),
+                    word.bbox[1].min(bbox[1]),
+                    word.bbox[2].max(bbox[2]),
+                    word.bbox[3].max(bbox[3]),
+                ];
+            }
+            None => {
+                current = Some(Word {
+                    text: c.to_string(),
+                    bbox,
+                })
+            }
+        }
+    }
+    words.extend(current);
+    words
+}
+
+/// two decimals are plenty for coordinates in points
+fn round2(v: f32) -> f64 {
+    (v as f64 * 100.0).round() / 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_words_merges_boxes() {
+        let chars: Vec<(char, [f32; 4])> = "Hi  yo"
+            .chars()
+            .enumerate()
+            .map(|(i, c)| (c, [i as f32 * 5.0, 10.0 - i as f32, i as f32 * 5.0 + 5.0, 20.0]))
+            .collect();
+        assert_eq!(
+            split_words(&chars),
+            vec![
+                Word {
+                    text: "Hi".into(),
+                    bbox: [0.0, 9.0, 10.0, 20.0],
+                },
+                Word {
+                    text: "yo".into(),
+                    bbox: [20.0, 5.0, 30.0, 20.0],
+                },
+            ]
+        );
+        assert!(split_words(&[(' ', [0.0; 4])]).is_empty());
+    }
+
+    #[test]
+    fn page_json_rounds_boxes() {
+        let page = PageText {
+            number: 2,
+            width: 612.0,
+            height: 792.0,
+            text: "Hi\n".into(),
+            words: vec![Word {
+                text: "Hi".into(),
+                bbox: [72.004, 90.5, 84.333, 102.0],
+            }],
+        };
+        assert_eq!(
+            serde_json::to_string(&page).unwrap(),
+            r#"{"page":2,"width":612.0,"height":792.0,"text":"Hi\n","words":[{"text":"Hi","bbox":[72.0,90.5,84.33,102.0]}]}"#
+        );
+    }
+}
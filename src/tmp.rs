@@ -0,0 +1,16 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+
+/// run `f` with a fresh temporary directory named after `purpose`, removed
+/// afterwards
+pub fn with_scratch_dir<T>(purpose: &str, f: impl FnOnce(&Path) -> Result<T>) -> Result<T> {
+    let mut id = [0u8; 8];
+    getrandom::getrandom(&mut id)
+        .map_err(|e| anyhow::anyhow!("Cannot get random bytes for a temporary name: {}", e))?;
+    let hex: String = id.iter().map(|b| format!("{:02x}", b)).collect();
+    let dir = std::env::temp_dir().join(format!("ovid-{}-{}", purpose, hex));
+    std::fs::create_dir(&dir).with_context(|| format!("Cannot create {}", dir.display()))?;
+    let result = f(&dir);
+    let _ = std::fs::remove_dir_all(&dir);
+    result
+}
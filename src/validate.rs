@@ -0,0 +1,592 @@
+use anyhow::{Context, Result};
+use lopdf::content::Content;
+use lopdf::xref::{XrefEntry, XrefType};
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashSet};
+use std::path::PathBuf;
+
+use crate::parse::PdfaLevel;
+use crate::pdf;
+
+/// deepest page tree accepted, against loops through Kids
+const MAX_TREE_DEPTH: usize = 64;
+
+/// problems found in one file, each reported once
+#[derive(Debug, Default, Serialize)]
+struct Report {
+    errors: Vec<String>,
+    warnings: Vec<String>,
+}
+
+/// one file's entry in the `--json` output
+#[derive(Serialize)]
+struct Checked {
+    file: String,
+    valid: bool,
+    pdfa: Option<&'static str>,
+    #[serde(flatten)]
+    report: Report,
+}
+
+impl Report {
+    fn error(&mut self, message: String) {
+        if !self.errors.contains(&message) {
+            self.errors.push(message);
+        }
+    }
+
+    fn warning(&mut self, message: String) {
+        if !self.warnings.contains(&message) {
+            self.warnings.push(message);
+        }
+    }
+}
+
+/// check the structure of each input (header, xref, trailer, catalog, page tree) and,
+/// with `pdfa`, the rules of that PDF/A level a file can be checked for without
+/// rendering. prints what's wrong; returns whether every file is free of errors
+pub fn validate_pdfs(inputs: &[PathBuf], pdfa: Option<PdfaLevel>, json: bool) -> Result<bool> {
+    let mut all_valid = true;
+    let mut results = Vec::new();
+    for input in inputs {
+        let data =
+            std::fs::read(input).with_context(|| format!("Failed to read {}", input.display()))?;
+        let report = check(&data, pdfa);
+        let valid = report.errors.is_empty();
+        all_valid &= valid;
+        if json {
+            results.push(Checked {
+                file: input.display().to_string(),
+                valid,
+                pdfa: pdfa.map(PdfaLevel::name),
+                report,
+            });
+            continue;
+        }
+        let rules = pdfa.map_or(String::new(), |level| format!(" as {}", level.name()));
+        if valid && report.warnings.is_empty() {
+            println!("{}: valid{}", input.display(), rules);
+            continue;
+        }
+        println!(
+            "{}: {}{} ({} error(s), {} warning(s))",
+            input.display(),
+            if valid { "valid" } else { "INVALID" },
+            rules,
+            report.errors.len(),
+            report.warnings.len()
+        );
+        for error in &report.errors {
+            println!("  error: {}", error);
+        }
+        for warning in &report.warnings {
+            println!("  warning: {}", warning);
+        }
+    }
+    if json {
+        match &results[..] {
+            [result] => println!("{}", serde_json::to_string(result)?),
+            _ => println!("{}", serde_json::to_string(&results)?),
+        }
+    }
+    Ok(all_valid)
+}
+
+fn check(data: &[u8], pdfa: Option<PdfaLevel>) -> Report {
+    let mut report = Report::default();
+    check_layout(data, pdfa, &mut report);
+    let doc = match Document::load_mem(data) {
+        Ok(doc) => doc,
+        Err(e) => {
+            report.error(format!("unreadable: {}", e));
+            return report;
+        }
+    };
+    check_xref(&doc, &mut report);
+    check_references(&doc, &mut report);
+    let pages = check_page_tree(&doc, &mut report);
+    if let Some(level) = pdfa {
+        check_pdfa(&doc, &pages, level, &mut report);
+    }
+    report
+}
+
+/// header, startxref and %%EOF, read from the raw bytes
+fn check_layout(data: &[u8], pdfa: Option<PdfaLevel>, report: &mut Report) {
+    match find(data, b"%PDF-") {
+        Some(0) => {}
+        Some(junk) if pdfa.is_some() => {
+            report.error(format!("{} byte(s) before the %PDF- header", junk))
+        }
+        Some(junk) => report.warning(format!("{} byte(s) before the %PDF- header", junk)),
+        None => report.error("no %PDF- header".to_string()),
+    }
+    if pdfa.is_some() && !has_binary_comment(data) {
+        report.error("no binary comment (4+ bytes above 127) after the header".to_string());
+    }
+
+    let tail = &data[data.len().saturating_sub(1024)..];
+    let Some(eof) = rfind(tail, b"%%EOF") else {
+        report.error("no %%EOF marker at the end of the file".to_string());
+        return;
+    };
+    let after_eof = &tail[eof + 5..];
+    if pdfa.is_some() && !after_eof.iter().all(|&b| b == b'\r' || b == b'\n') {
+        report.error("data after the final %%EOF".to_string());
+    }
+    let startxref = rfind(tail, b"startxref").and_then(|i| {
+        let digits: String = tail[i + 9..]
+            .iter()
+            .map(|&b| b as char)
+            .skip_while(char::is_ascii_whitespace)
+            .take_while(char::is_ascii_digit)
+            .collect();
+        digits.parse::<usize>().ok()
+    });
+    match startxref {
+        None => report.error("no startxref before %%EOF".to_string()),
+        Some(offset) if !points_at_xref(data, offset) => report.error(format!(
+            "startxref points to offset {}, where there's no cross-reference table",
+            offset
+        )),
+        Some(_) => {}
+    }
+}
+
+/// whether the header line is followed by a comment of high bytes, which marks the
+/// file as binary for transfer tools
+fn has_binary_comment(data: &[u8]) -> bool {
+    let Some(end) = data.iter().position(|&b| b == b'\r' || b == b'\n') else {
+        return false;
+    };
+    let rest = &data[end..];
+    let rest = rest.strip_prefix(b"\r").unwrap_or(rest);
+    let rest = rest.strip_prefix(b"\n").unwrap_or(rest);
+    let Some(comment) = rest.strip_prefix(b"%") else {
+        return false;
+    };
+    comment
+        .iter()
+        .take_while(|&&b| b != b'\r' && b != b'\n')
+        .filter(|&&b| b > 127)
+        .count()
+        >= 4
+}
+
+/// whether `offset` starts an xref table or an xref stream object
+fn points_at_xref(data: &[u8], offset: usize) -> bool {
+    let Some(rest) = data.get(offset..) else {
+        return false;
+    };
+    if rest.starts_with(b"xref") {
+        return true;
+    }
+    // "12 0 obj"
+    let head: String = rest.iter().take(32).map(|&b| b as char).collect();
+    let mut words = head.split_ascii_whitespace();
+    let number = |w: Option<&str>| w.is_some_and(|w| w.bytes().all(|b| b.is_ascii_digit()));
+    number(words.next())
+        && number(words.next())
+        && words.next().is_some_and(|w| w.starts_with("obj"))
+}
+
+/// every xref entry leads to an object, and the trailer's Size covers them all
+fn check_xref(doc: &Document, report: &mut Report) {
+    for (&id, entry) in &doc.reference_table.entries {
+        match *entry {
+            XrefEntry::Normal { generation, .. } => {
+                if !doc.objects.contains_key(&(id, generation)) {
+                    report.error(format!(
+                        "object {} {} can't be read at the offset the xref table gives",
+                        id, generation
+                    ));
+                }
+            }
+            XrefEntry::Compressed { container, .. } => {
+                if !doc.objects.contains_key(&(id, 0)) {
+                    report.error(format!(
+                        "object {} can't be read from object stream {}",
+                        id, container
+                    ));
+                }
+            }
+            XrefEntry::Free | XrefEntry::UnusableFree => {}
+        }
+    }
+    let needed = doc.reference_table.max_id() as i64 + 1;
+    match doc.trailer.get(b"Size").and_then(Object::as_i64) {
+        Ok(size) if size < needed => report.error(format!(
+            "trailer /Size is {}, but objects are numbered up to {}",
+            size,
+            needed - 1
+        )),
+        Ok(_) => {}
+        Err(_) => report.error("trailer has no /Size".to_string()),
+    }
+}
+
+/// references to objects that don't exist are read as null, which is legal but
+/// usually means something was lost
+fn check_references(doc: &Document, report: &mut Report) {
+    let mut missing = BTreeSet::new();
+    for object in doc.objects.values() {
+        let mut refs = Vec::new();
+        references(object, &mut refs);
+        missing.extend(refs.into_iter().filter(|id| !doc.objects.contains_key(id)));
+    }
+    for (id, generation) in missing {
+        report.warning(format!("reference to missing object {} {}", id, generation));
+    }
+}
+
+fn references(object: &Object, out: &mut Vec<ObjectId>) {
+    match object {
+        Object::Reference(id) => out.push(*id),
+        Object::Array(items) => items.iter().for_each(|item| references(item, out)),
+        Object::Dictionary(dict) => dict.iter().for_each(|(_, value)| references(value, out)),
+        Object::Stream(stream) => stream.dict.iter().for_each(|(_, value)| references(value, out)),
+        _ => {}
+    }
+}
+
+/// catalog and page tree; returns the pages found, in order
+fn check_page_tree(doc: &Document, report: &mut Report) -> Vec<ObjectId> {
+    let Some(catalog) = catalog(doc) else {
+        report.error("trailer has no /Root catalog dictionary".to_string());
+        return Vec::new();
+    };
+    if !catalog.type_is(b"Catalog") {
+        report.error("catalog /Type isn't /Catalog".to_string());
+    }
+    let Ok(root) = catalog.get(b"Pages").and_then(Object::as_reference) else {
+        report.error("catalog has no /Pages".to_string());
+        return Vec::new();
+    };
+    let mut pages = Vec::new();
+    let mut seen = HashSet::new();
+    walk_pages(doc, root, None, 0, &mut seen, &mut pages, report);
+    if pages.is_empty() {
+        report.error("document has no pages".to_string());
+    }
+
+    for (i, &page_id) in pages.iter().enumerate() {
+        let media_box = pdf::page_attribute(doc, page_id, b"MediaBox");
+        let values: Option<Vec<f32>> = media_box
+            .and_then(|b| b.as_array().ok())
+            .map(|items| items.iter().filter_map(pdf::number).collect());
+        match values.as_deref() {
+            None => report.error(format!("page {} has no /MediaBox", i + 1)),
+            Some(&[x0, y0, x1, y1]) if (x1 - x0).abs() > 0.0 && (y1 - y0).abs() > 0.0 => {}
+            Some(_) => report.error(format!("page {} has an invalid /MediaBox", i + 1)),
+        }
+        if pdf::page_attribute(doc, page_id, b"Resources").is_none() {
+            report.warning(format!("page {} has no /Resources", i + 1));
+        }
+    }
+    pages
+}
+
+/// visit a page tree node, checking Type, Parent and Count; returns its page count
+fn walk_pages(
+    doc: &Document,
+    id: ObjectId,
+    parent: Option<ObjectId>,
+    depth: usize,
+    seen: &mut HashSet<ObjectId>,
+    pages: &mut Vec<ObjectId>,
+    report: &mut Report,
+) -> i64 {
+    if !seen.insert(id) || depth > MAX_TREE_DEPTH {
+        report.error(format!("page tree node {} {} is reached twice", id.0, id.1));
+        return 0;
+    }
+    let Ok(node) = doc.get_dictionary(id) else {
+        report.error(format!("page tree node {} {} isn't a dictionary", id.0, id.1));
+        return 0;
+    };
+    if let Some(parent) = parent {
+        if node.get(b"Parent").and_then(Object::as_reference).ok() != Some(parent) {
+            report.warning(format!("page tree node {} {} has a wrong /Parent", id.0, id.1));
+        }
+    }
+    if node.type_is(b"Page") {
+        pages.push(id);
+        return 1;
+    }
+    if !node.type_is(b"Pages") {
+        report.error(format!("page tree node {} {} isn't /Page or /Pages", id.0, id.1));
+        return 0;
+    }
+    let Ok(kids) = node.get(b"Kids").and_then(Object::as_array) else {
+        report.error(format!("page tree node {} {} has no /Kids", id.0, id.1));
+        return 0;
+    };
+    let mut count = 0;
+    for kid in kids {
+        match kid.as_reference() {
+            Ok(kid) => count += walk_pages(doc, kid, Some(id), depth + 1, seen, pages, report),
+            Err(_) => report.error(format!("page tree node {} {} has a direct kid", id.0, id.1)),
+        }
+    }
+    match node.get(b"Count").and_then(Object::as_i64) {
+        Ok(declared) if declared == count => {}
+        Ok(declared) => report.error(format!(
+            "page tree node {} {} says /Count {} but holds {} page(s)",
+            id.0, id.1, declared, count
+        )),
+        Err(_) => report.error(format!("page tree node {} {} has no /Count", id.0, id.1)),
+    }
+    count
+}
+
+fn catalog(doc: &Document) -> Option<&Dictionary> {
+    let root = doc.trailer.get(b"Root").ok()?;
+    doc.dereference(root).ok()?.1.as_dict().ok()
+}
+
+/// the PDF/A rules that can be read off the file's objects: version, encryption,
+/// ID, XMP identification, output intent, embedded fonts, and forbidden features
+fn check_pdfa(doc: &Document, pages: &[ObjectId], level: PdfaLevel, report: &mut Report) {
+    let name = level.name();
+    let max_version = match level {
+        PdfaLevel::A1b => "1.4",
+        PdfaLevel::A2b => "1.7",
+    };
+    if doc.version.as_str() > max_version {
+        report.error(format!(
+            "{} allows PDF {} at most, the file is PDF {}",
+            name, max_version, doc.version
+        ));
+    }
+    let encrypted = doc.trailer.get(b"Encrypt").is_ok();
+    if encrypted {
+        report.error(format!("{} doesn't allow encryption", name));
+    }
+    if doc.trailer.get(b"ID").is_err() {
+        report.error(format!("{} needs a file identifier (/ID in the trailer)", name));
+    }
+    let catalog = catalog(doc).cloned().unwrap_or_default();
+    check_xmp(doc, &catalog, level, report);
+
+    let has_intent = catalog
+        .get(b"OutputIntents")
+        .and_then(|intents| doc.dereference(intents))
+        .and_then(|(_, intents)| intents.as_array())
+        .is_ok_and(|intents| {
+            intents.iter().any(|intent| {
+                doc.dereference(intent)
+                    .and_then(|(_, intent)| intent.as_dict())
+                    .is_ok_and(|intent| {
+                        intent.get(b"S").and_then(Object::as_name).ok() == Some(b"GTS_PDFA1")
+                            && intent.has(b"DestOutputProfile")
+                    })
+            })
+        });
+    if !has_intent && uses_device_color(doc, pages, encrypted) {
+        report.error(format!(
+            "{} needs an output intent (GTS_PDFA1 with an ICC profile) for device RGB or CMYK",
+            name
+        ));
+    }
+
+    if let Ok(names) = catalog.get(b"Names").and_then(|n| doc.dereference(n)) {
+        let names = names.1.as_dict().cloned().unwrap_or_default();
+        if names.has(b"JavaScript") {
+            report.error(format!("{} doesn't allow JavaScript", name));
+        }
+        if names.has(b"EmbeddedFiles") {
+            match level {
+                PdfaLevel::A1b => report.error("PDF/A-1 doesn't allow embedded files".to_string()),
+                PdfaLevel::A2b => report.warning(
+                    "embedded files have to be PDF/A themselves (not checked)".to_string(),
+                ),
+            }
+        }
+    }
+
+    for object in doc.objects.values() {
+        let dict = match object {
+            Object::Dictionary(dict) => dict,
+            Object::Stream(stream) => &stream.dict,
+            _ => continue,
+        };
+        match dict.get(b"S").and_then(Object::as_name) {
+            Ok(b"JavaScript") => report.error(format!("{} doesn't allow JavaScript", name)),
+            Ok(b"Launch") => report.error(format!("{} doesn't allow Launch actions", name)),
+            _ => {}
+        }
+        if dict.type_is(b"Font") {
+            if let Some(font) = unembedded_font(doc, dict) {
+                report.error(format!("font {} isn't embedded", font));
+            }
+        }
+        if let Object::Stream(stream) = object {
+            if stream.filters().is_ok_and(|f| f.iter().any(|f| f == "LZWDecode")) {
+                report.error(format!("{} doesn't allow LZW compression", name));
+            }
+        }
+        if level == PdfaLevel::A1b {
+            check_transparency(dict, report);
+        }
+    }
+
+    if level == PdfaLevel::A1b {
+        let object_streams = doc
+            .reference_table
+            .entries
+            .values()
+            .any(|entry| matches!(entry, XrefEntry::Compressed { .. }));
+        if object_streams
+            || matches!(doc.reference_table.cross_reference_type, XrefType::CrossReferenceStream)
+        {
+            report.error("PDF/A-1 doesn't allow cross-reference or object streams".to_string());
+        }
+    }
+}
+
+/// the catalog's XMP metadata has to identify the PDF/A part
+fn check_xmp(doc: &Document, catalog: &Dictionary, level: PdfaLevel, report: &mut Report) {
+    let name = level.name();
+    let stream = catalog
+        .get(b"Metadata")
+        .and_then(|m| doc.dereference(m))
+        .and_then(|(_, m)| m.as_stream());
+    let Ok(stream) = stream else {
+        report.error(format!("{} needs XMP metadata in the catalog", name));
+        return;
+    };
+    if level == PdfaLevel::A1b && stream.dict.has(b"Filter") {
+        report.error("PDF/A-1 doesn't allow a compressed metadata stream".to_string());
+    }
+    let xmp = stream.get_plain_content().unwrap_or_default();
+    let xmp = String::from_utf8_lossy(&xmp);
+    match xmp_value(&xmp, "pdfaid:part") {
+        None => report.error("XMP metadata doesn't declare PDF/A (pdfaid:part)".to_string()),
+        Some(part) if part != level.part().to_string() => report.error(format!(
+            "XMP metadata declares PDF/A-{}, not {}",
+            part, name
+        )),
+        Some(_) => {}
+    }
+}
+
+/// value of an XMP property written as an attribute or an element
+fn xmp_value(xmp: &str, property: &str) -> Option<String> {
+    let start = xmp.find(property)? + property.len();
+    let rest = xmp[start..].trim_start();
+    let rest = rest
+        .strip_prefix('=')
+        .map(|r| r.trim_start().trim_start_matches(['"', '\'']))
+        .or_else(|| rest.strip_prefix('>'))?;
+    let value: String = rest.chars().take_while(|&c| !"\"'<".contains(c)).collect();
+    Some(value.trim().to_string())
+}
+
+/// whether images or page content use DeviceRGB or DeviceCMYK
+fn uses_device_color(doc: &Document, pages: &[ObjectId], encrypted: bool) -> bool {
+    let device = |obj: &Object| {
+        matches!(obj.as_name(), Ok(b"DeviceRGB" | b"DeviceCMYK"))
+    };
+    let images = doc.objects.values().any(|object| {
+        object.as_stream().is_ok_and(|stream| {
+            stream.dict.get(b"Subtype").and_then(Object::as_name).ok() == Some(b"Image")
+                && stream.dict.get(b"ColorSpace").is_ok_and(device)
+        })
+    });
+    // encrypted content streams can't be decoded
+    images
+        || (!encrypted
+            && pages.iter().any(|&page| {
+                doc.get_page_content(page)
+                    .ok()
+                    .and_then(|content| Content::decode(&content).ok())
+                    .is_some_and(|content| {
+                        content
+                            .operations
+                            .iter()
+                            .any(|op| matches!(op.operator.as_str(), "rg" | "RG" | "k" | "K"))
+                    })
+            }))
+}
+
+/// BaseFont of a font that needs but lacks an embedded font program
+fn unembedded_font(doc: &Document, font: &Dictionary) -> Option<String> {
+    let subtype = font.get(b"Subtype").and_then(Object::as_name).ok()?;
+    let descriptor_holder = match subtype {
+        b"Type3" => return None,
+        b"Type0" => {
+            let descendants = font.get(b"DescendantFonts").ok()?;
+            let descendants = doc.dereference(descendants).ok()?.1.as_array().ok()?;
+            doc.dereference(descendants.first()?).ok()?.1.as_dict().ok()?
+        }
+        _ => font,
+    };
+    let embedded = descriptor_holder
+        .get(b"FontDescriptor")
+        .and_then(|d| doc.dereference(d))
+        .and_then(|(_, d)| d.as_dict())
+        .is_ok_and(|d| d.has(b"FontFile") || d.has(b"FontFile2") || d.has(b"FontFile3"));
+    if embedded {
+        return None;
+    }
+    let base = font.get(b"BaseFont").and_then(Object::as_name).unwrap_or(b"(unnamed)");
+    Some(String::from_utf8_lossy(base).into_owned())
+}
+
+/// PDF/A-1 has no transparency: soft masks, constant alpha, or transparency groups
+fn check_transparency(dict: &Dictionary, report: &mut Report) {
+    let soft_mask = dict
+        .get(b"SMask")
+        .is_ok_and(|mask| mask.as_name().ok() != Some(b"None"));
+    let alpha = [&b"CA"[..], &b"ca"[..]].iter().any(|key| {
+        dict.get(key)
+            .ok()
+            .and_then(pdf::number)
+            .is_some_and(|a| a < 1.0)
+    });
+    let group = dict.get(b"S").and_then(Object::as_name).ok() == Some(b"Transparency");
+    if soft_mask || alpha || group {
+        report.error("PDF/A-1 doesn't allow transparency".to_string());
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn rfind(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).rposition(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layout_and_xmp() {
+        let mut report = Report::default();
+        let data: &[u8] =
+            b"junk%PDF-1.4\n%\xe2\xe3\xcf\xd3\nxref\n0 1\ntrailer\n<<>>\nstartxref\n19\n%%EOF\n";
+        check_layout(data, None, &mut report);
+        assert!(report.errors.is_empty(), "{:?}", report);
+        assert_eq!(report.warnings, ["4 byte(s) before the %PDF- header"]);
+
+        let mut report = Report::default();
+        check_layout(b"%PDF-1.4\n1 0 obj\nstartxref\n9\n%%EOF\ngarbage", None, &mut report);
+        assert!(report.errors.is_empty(), "{:?}", report);
+        check_layout(b"%PDF-1.4\nstartxref\n3\n%%EOF", None, &mut report);
+        assert_eq!(
+            report.errors,
+            ["startxref points to offset 3, where there's no cross-reference table"]
+        );
+        assert!(has_binary_comment(data.strip_prefix(b"junk").unwrap()));
+        assert!(!has_binary_comment(b"%PDF-1.4\n%abc\xff\n"));
+
+        let attribute = r#"<rdf:Description pdfaid:part="2" pdfaid:conformance="B"/>"#;
+        assert_eq!(xmp_value(attribute, "pdfaid:part").as_deref(), Some("2"));
+        let element = "<pdfaid:part>1</pdfaid:part>";
+        assert_eq!(xmp_value(element, "pdfaid:part").as_deref(), Some("1"));
+        assert_eq!(xmp_value(element, "pdfaid:conformance"), None);
+    }
+}
@@ -0,0 +1,87 @@
+use clap::ValueEnum;
+use wasm_bindgen::prelude::*;
+
+use crate::merge::{self, MergeOptions};
+use crate::parse::{Direction, PageSize};
+
+/// builds a PDF from images in the browser: add each image's bytes in page
+/// order, then `finish` for the PDF's bytes
+///
+/// ```js
+/// const merger = new Merger();
+/// merger.setPageSize("a4");
+/// for (const file of input.files) {
+///   merger.addImage(new Uint8Array(await file.arrayBuffer()));
+/// }
+/// const pdf = new Blob([merger.finish()], { type: "application/pdf" });
+/// ```
+#[wasm_bindgen]
+pub struct Merger {
+    images: Vec<Vec<u8>>,
+    opts: MergeOptions,
+}
+
+#[wasm_bindgen]
+impl Merger {
+    #[wasm_bindgen(constructor)]
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Merger {
+        Merger {
+            images: Vec::new(),
+            opts: MergeOptions {
+                quiet: true,
+                max_pixels: Some(200_000_000),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// a PNG, JPEG, TIFF, BMP or GIF image for the next page
+    #[wasm_bindgen(js_name = addImage)]
+    pub fn add_image(&mut self, data: Vec<u8>) {
+        self.images.push(data);
+    }
+
+    /// "a4", "letter", "legal" or "a3"; pages are sized to the images otherwise
+    #[wasm_bindgen(js_name = setPageSize)]
+    pub fn set_page_size(&mut self, pagesize: &str) -> Result<(), JsError> {
+        self.opts.pagesize = Some(value::<PageSize>(pagesize, "pagesize")?);
+        Ok(())
+    }
+
+    /// DPI for every image, instead of each image's own
+    #[wasm_bindgen(js_name = setDpi)]
+    pub fn set_dpi(&mut self, dpi: u32) {
+        self.opts.dpi = (dpi > 0).then_some(dpi);
+    }
+
+    #[wasm_bindgen(js_name = setTitle)]
+    pub fn set_title(&mut self, title: String) {
+        self.opts.title = Some(title);
+    }
+
+    #[wasm_bindgen(js_name = setAuthor)]
+    pub fn set_author(&mut self, author: String) {
+        self.opts.author = Some(author);
+    }
+
+    /// "ltr" or "rtl", the reading order viewers lay out spreads in
+    #[wasm_bindgen(js_name = setDirection)]
+    pub fn set_direction(&mut self, direction: &str) -> Result<(), JsError> {
+        self.opts.direction = value::<Direction>(direction, "direction")?;
+        Ok(())
+    }
+
+    /// the PDF's bytes, a page per image in the order added
+    pub fn finish(self) -> Result<Vec<u8>, JsError> {
+        if self.images.is_empty() {
+            return Err(JsError::new("No input images provided"));
+        }
+        merge::merge_image_data(&self.images, &self.opts)
+            .map_err(|e| JsError::new(&format!("{:#}", e)))
+    }
+}
+
+fn value<T: ValueEnum>(s: &str, name: &str) -> Result<T, JsError> {
+    T::from_str(s, true).map_err(|_| JsError::new(&format!("invalid {}: {}", name, s)))
+}
@@ -0,0 +1,208 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{error, info};
+
+use crate::merge::{self, MergeOptions};
+use crate::parse::{self, ImageFormat, Orientation, PageSize, PngCompression};
+use crate::split::{self, SplitOptions};
+
+/// where finished inputs are moved, inside the watched dir
+const PROCESSED: &str = "processed";
+const FAILED: &str = "failed";
+
+pub struct WatchOptions<'a> {
+    /// results go here: a dir of images per PDF, a PDF per image folder
+    pub output: &'a Path,
+    /// time between scans of the watched dir
+    pub interval: Duration,
+    /// how long an input must stay unchanged before it counts as complete
+    pub settle: Duration,
+    /// process what's there now and return, without waiting for inputs to settle
+    pub once: bool,
+    pub format: ImageFormat,
+    pub dpi: u32,
+    pub compress: PngCompression,
+    pub quality: u8,
+    pub gray: bool,
+    pub pagesize: Option<PageSize>,
+    pub orientation: Orientation,
+    pub quiet: bool,
+}
+
+/// size and modification time of a file, or of every file in a folder
+#[derive(Debug, PartialEq, Eq)]
+struct Fingerprint(Vec<(PathBuf, u64, Option<SystemTime>)>);
+
+/// watch `dir` for PDFs, which are split, and folders of images, which are merged.
+/// once an input has been unchanged for `settle` it's converted and moved to
+/// processed/, or failed/ with an .error.txt next to it
+pub fn watch(dir: &Path, opts: &WatchOptions) -> Result<()> {
+    anyhow::ensure!(dir.is_dir(), "{} is not a directory", dir.display());
+    for sub in [PROCESSED, FAILED] {
+        std::fs::create_dir_all(dir.join(sub))
+            .with_context(|| format!("Cannot create {}", dir.join(sub).display()))?;
+    }
+    std::fs::create_dir_all(opts.output)
+        .with_context(|| format!("Cannot create output dir: {}", opts.output.display()))?;
+    if !opts.quiet && !opts.once {
+        info!(
+            "Watching {} -> {} (Ctrl-C to stop)",
+            dir.display(),
+            opts.output.display()
+        );
+    }
+
+    // inputs seen changing, with the fingerprint they had since when
+    let mut pending: HashMap<PathBuf, (Fingerprint, Instant)> = HashMap::new();
+    loop {
+        let inputs = scan(dir, opts.output)?;
+        pending.retain(|path, _| inputs.contains(path));
+        for input in inputs {
+            let Ok(fingerprint) = fingerprint(&input) else {
+                continue;
+            };
+            let settled = match pending.get(&input) {
+                Some((seen, since)) => *seen == fingerprint && since.elapsed() >= opts.settle,
+                None => false,
+            };
+            if !(settled || opts.once) {
+                if pending.get(&input).is_none_or(|(seen, _)| *seen != fingerprint) {
+                    pending.insert(input, (fingerprint, Instant::now()));
+                }
+                continue;
+            }
+            pending.remove(&input);
+            process(dir, &input, opts)?;
+        }
+        if opts.once {
+            return Ok(());
+        }
+        std::thread::sleep(opts.interval);
+    }
+}
+
+/// candidate inputs: PDFs and folders holding images, skipping hidden entries and
+/// ovid's own dirs
+fn scan(dir: &Path, output: &Path) -> Result<Vec<PathBuf>> {
+    let skip = [dir.join(PROCESSED), dir.join(FAILED)];
+    let output = std::fs::canonicalize(output).ok();
+    let mut inputs = Vec::new();
+    for entry in std::fs::read_dir(dir).with_context(|| format!("Cannot read {}", dir.display()))? {
+        let path = entry?.path();
+        let hidden = path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        if hidden || skip.contains(&path) {
+            continue;
+        }
+        if path.is_dir() {
+            if output.is_some() && std::fs::canonicalize(&path).ok() == output {
+                continue;
+            }
+            if parse::expand_image_paths(std::slice::from_ref(&path)).is_ok() {
+                inputs.push(path);
+            }
+        } else if is_pdf(&path) {
+            inputs.push(path);
+        }
+    }
+    inputs.sort();
+    Ok(inputs)
+}
+
+fn is_pdf(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("pdf"))
+}
+
+fn fingerprint(path: &Path) -> Result<Fingerprint> {
+    let files = if path.is_dir() {
+        let mut files: Vec<PathBuf> = std::fs::read_dir(path)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<std::io::Result<_>>()?;
+        files.sort();
+        files
+    } else {
+        vec![path.to_path_buf()]
+    };
+    let entries = files
+        .into_iter()
+        .map(|file| {
+            let meta = std::fs::metadata(&file)?;
+            Ok((file, meta.len(), meta.modified().ok()))
+        })
+        .collect::<Result<_>>()?;
+    Ok(Fingerprint(entries))
+}
+
+/// convert one settled input, then file it under processed/ or failed/
+fn process(dir: &Path, input: &Path, opts: &WatchOptions) -> Result<()> {
+    let name = input.file_name().context("Input has no file name")?;
+    let stem = input
+        .file_stem()
+        .map_or_else(|| "output".into(), |s| s.to_string_lossy().into_owned());
+    let result = if input.is_dir() {
+        let output = opts.output.join(format!("{}.pdf", stem));
+        if !opts.quiet {
+            info!("Merging {} -> {}", input.display(), output.display());
+        }
+        parse::expand_image_paths(&[input.to_path_buf()]).and_then(|images| {
+            let merge_opts = MergeOptions {
+                quiet: true,
+                gray: opts.gray,
+                pagesize: opts.pagesize,
+                orientation: opts.orientation,
+                max_pixels: Some(200_000_000),
+                ..Default::default()
+            };
+            merge::merge_images(&images, &output, &merge_opts).map(drop)
+        })
+    } else {
+        let output = opts.output.join(&stem);
+        if !opts.quiet {
+            info!("Splitting {} -> {}", input.display(), output.display());
+        }
+        let split_opts = SplitOptions {
+            format: opts.format,
+            dpi: opts.dpi,
+            compress: opts.compress,
+            gray: opts.gray,
+            quality: opts.quality,
+            quiet: true,
+            ..Default::default()
+        };
+        split::split_pdf(input, &output, &split_opts).map(drop)
+    };
+
+    let target_dir = dir.join(if result.is_ok() { PROCESSED } else { FAILED });
+    let target = free_path(&target_dir.join(name));
+    std::fs::rename(input, &target).with_context(|| {
+        format!("Cannot move {} to {}", input.display(), target_dir.display())
+    })?;
+    if let Err(e) = result {
+        error!("{}: {:#}", input.display(), e);
+        let mut log = target.clone().into_os_string();
+        log.push(".error.txt");
+        std::fs::write(&log, format!("{:#}\n", e))
+            .with_context(|| format!("Cannot write {}", Path::new(&log).display()))?;
+    }
+    Ok(())
+}
+
+/// `path`, or `path` with -1, -2, ... added to the stem if it's taken
+fn free_path(path: &Path) -> PathBuf {
+    if !path.exists() {
+        return path.to_path_buf();
+    }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = path
+        .extension()
+        .map_or_else(String::new, |e| format!(".{}", e.to_string_lossy()));
+    (1..)
+        .map(|n| path.with_file_name(format!("{}-{}{}", stem, n, ext)))
+        .find(|candidate| !candidate.exists())
+        .unwrap()
+}
@@ -0,0 +1,438 @@
+use anyhow::{Context, Result};
+use lopdf::content::{Content, Operation};
+use lopdf::{dictionary, Dictionary, Object, ObjectId, Stream, StringFormat};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::merge;
+use crate::parse::{parse_page_ranges, Color, Position};
+use crate::pdf;
+
+/// resource names used in watermark content streams
+pub const GS_NAME: &str = "GSwm";
+pub const FONT_NAME: &str = "Fwm";
+pub const IMAGE_NAME: &str = "Imwm";
+pub const PAGE_NUMBER_FONT_NAME: &str = "Fpn";
+pub const PLACEHOLDER_FONT_NAME: &str = "Fph";
+
+/// Helvetica-Bold cap height, in 1/1000 em
+const CAP_HEIGHT: f32 = 718.0;
+
+/// fraction of the page a watermark may cover at most
+const MAX_COVERAGE: f32 = 0.8;
+
+/// page number text size and distance from the page edge, in points
+const PAGE_NUMBER_SIZE: f32 = 10.0;
+const PAGE_NUMBER_MARGIN: f32 = 18.0;
+
+/// placeholder caption size in points, before shrinking to fit
+const PLACEHOLDER_SIZE: f32 = 12.0;
+
+#[derive(Debug, Clone)]
+pub enum WatermarkContent {
+    /// text set in Helvetica-Bold, sized to span the page
+    Text(String),
+    /// image drawn at its natural size (shrunk to fit the page)
+    Image(PathBuf),
+}
+
+/// stamp drawn over each page after its content
+#[derive(Debug, Clone)]
+pub struct Watermark {
+    pub content: WatermarkContent,
+    /// 0.0 (invisible) to 1.0 (opaque)
+    pub opacity: f32,
+    /// counter-clockwise rotation in degrees
+    pub rotation: f32,
+    pub position: Position,
+    /// text fill color
+    pub color: Color,
+}
+
+impl Watermark {
+    /// graphics state applying the watermark opacity
+    pub fn ext_gstate(&self) -> Dictionary {
+        dictionary! {
+            "Type" => Object::Name(b"ExtGState".to_vec()),
+            "ca" => Object::Real(self.opacity),
+            "CA" => Object::Real(self.opacity),
+        }
+    }
+
+    /// standard font for text watermarks (no embedding needed)
+    pub fn font() -> Dictionary {
+        standard_font(b"Helvetica-Bold")
+    }
+
+    /// content stream operations drawing the watermark on a `page_w` x `page_h` page;
+    /// `image_size` is the natural size in points of an image watermark
+    pub fn operations(
+        &self,
+        page_w: f32,
+        page_h: f32,
+        image_size: Option<(f32, f32)>,
+    ) -> Vec<Operation> {
+        let (sin, cos) = self.rotation.to_radians().sin_cos();
+        let (sin_abs, cos_abs) = (sin.abs(), cos.abs());
+        // largest scale at which a w x h box, rotated, still fits the page
+        let fit = |w: f32, h: f32| {
+            (MAX_COVERAGE * page_w / (w * cos_abs + h * sin_abs))
+                .min(MAX_COVERAGE * page_h / (w * sin_abs + h * cos_abs))
+        };
+
+        let (w, h, draw) = match &self.content {
+            WatermarkContent::Text(text) => {
+                let encoded = encode_win_ansi(text);
+                let em = text_width_em(&HELVETICA_BOLD_WIDTHS, &encoded);
+                let cap = CAP_HEIGHT / 1000.0;
+                let size = fit(em, cap);
+                let (w, h) = (em * size, cap * size);
+                let c = self.color;
+                let draw = vec![
+                    Operation::new("BT", vec![]),
+                    Operation::new(
+                        "Tf",
+                        vec![Object::Name(FONT_NAME.as_bytes().to_vec()), Object::Real(size)],
+                    ),
+                    Operation::new(
+                        "rg",
+                        vec![
+                            Object::Real(c.r as f32 / 255.0),
+                            Object::Real(c.g as f32 / 255.0),
+                            Object::Real(c.b as f32 / 255.0),
+                        ],
+                    ),
+                    Operation::new("Td", vec![Object::Real(-w / 2.0), Object::Real(-h / 2.0)]),
+                    Operation::new("Tj", vec![Object::String(encoded, StringFormat::Literal)]),
+                    Operation::new("ET", vec![]),
+                ];
+                (w, h, draw)
+            }
+            WatermarkContent::Image(_) => {
+                let (w, h) = image_size.unwrap_or((page_w, page_h));
+                let scale = fit(w, h).min(1.0);
+                let (w, h) = (w * scale, h * scale);
+                let draw = vec![
+                    matrix(w, 0.0, 0.0, h, -w / 2.0, -h / 2.0),
+                    Operation::new("Do", vec![Object::Name(IMAGE_NAME.as_bytes().to_vec())]),
+                ];
+                (w, h, draw)
+            }
+        };
+
+        // place the rotated bounding box, then draw around its center
+        let (box_w, box_h) = (w * cos_abs + h * sin_abs, w * sin_abs + h * cos_abs);
+        let (x, y) = self.position.place(page_w, page_h, box_w, box_h);
+        let mut ops = vec![
+            Operation::new("q", vec![]),
+            Operation::new("gs", vec![Object::Name(GS_NAME.as_bytes().to_vec())]),
+            matrix(1.0, 0.0, 0.0, 1.0, x + box_w / 2.0, y + box_h / 2.0),
+            matrix(cos, sin, -sin, cos, 0.0, 0.0),
+        ];
+        ops.extend(draw);
+        ops.push(Operation::new("Q", vec![]));
+        ops
+    }
+}
+
+/// stamp a watermark onto pages of an existing PDF. it is drawn by a form XObject
+/// appended to each page's content, so the original content stays untouched;
+/// pages of the same shown size share one form
+pub fn watermark_pdf(
+    input: &Path,
+    output: &Path,
+    mark: &Watermark,
+    pages: Option<&str>,
+    quiet: bool,
+) -> Result<()> {
+    let start = std::time::Instant::now();
+    let mut doc = pdf::load(input)?;
+    let page_ids: Vec<ObjectId> = doc.get_pages().into_values().collect();
+    let mut selected: Vec<usize> = match pages {
+        Some(s) => parse_page_ranges(s, page_ids.len() as i32)?
+            .into_iter()
+            .map(|p| p as usize)
+            .collect(),
+        None => (0..page_ids.len()).collect(),
+    };
+    // a page listed twice is still stamped once
+    selected.sort_unstable();
+    selected.dedup();
+    if !quiet {
+        info!(
+            "Watermarking {} of {} page(s) -> {}",
+            selected.len(),
+            page_ids.len(),
+            output.display()
+        );
+    }
+
+    let mut resources = dictionary! {
+        "ExtGState" => dictionary! { GS_NAME => doc.add_object(mark.ext_gstate()) },
+    };
+    let image_size = match &mark.content {
+        WatermarkContent::Text(_) => {
+            let font_id = doc.add_object(Watermark::font());
+            resources.set("Font", dictionary! { FONT_NAME => font_id });
+            None
+        }
+        WatermarkContent::Image(path) => {
+            let (image_id, size) = merge::import_image_xobject(&mut doc, path)
+                .with_context(|| format!("Failed to load watermark image {}", path.display()))?;
+            resources.set("XObject", dictionary! { IMAGE_NAME => image_id });
+            Some(size)
+        }
+    };
+
+    let mut forms: HashMap<[u32; 8], ObjectId> = HashMap::new();
+    for &i in &selected {
+        let page_id = page_ids[i];
+        let (m, w, h) = pdf::display_transform(&doc, page_id);
+        let key = [m[0], m[1], m[2], m[3], m[4], m[5], w, h].map(f32::to_bits);
+        let form_id = match forms.get(&key) {
+            Some(&id) => id,
+            None => {
+                let content = Content {
+                    operations: mark.operations(w, h, image_size),
+                };
+                let mut form = Stream::new(
+                    dictionary! {
+                        "Type" => "XObject",
+                        "Subtype" => "Form",
+                        "BBox" => [0.0, 0.0, w, h].map(Object::Real).to_vec(),
+                        "Matrix" => m.map(Object::Real).to_vec(),
+                        "Resources" => resources.clone(),
+                    },
+                    content.encode()?,
+                );
+                form.compress()?;
+                let id = doc.add_object(form);
+                forms.insert(key, id);
+                id
+            }
+        };
+        pdf::stamp_form(&mut doc, page_id, form_id)?;
+    }
+    pdf::save(&mut doc, output)?;
+
+    if !quiet {
+        info!("Done. PDF saved in {:.2}s", start.elapsed().as_secs_f64());
+    }
+    Ok(())
+}
+
+/// printed "Page N of M" style numbering
+#[derive(Debug, Clone)]
+pub struct PageNumbers {
+    /// text with `{n}` (page number) and `{total}` (page count) placeholders
+    pub format: String,
+    pub position: Position,
+}
+
+impl PageNumbers {
+    /// standard font for page numbers (no embedding needed)
+    pub fn font() -> Dictionary {
+        standard_font(b"Helvetica")
+    }
+
+    /// content stream operations printing number `n` of `total` inside the page margin
+    pub fn operations(&self, n: usize, total: usize, page_w: f32, page_h: f32) -> Vec<Operation> {
+        let text = self
+            .format
+            .replace("{n}", &n.to_string())
+            .replace("{total}", &total.to_string());
+        let encoded = encode_win_ansi(&text);
+        let w = text_width_em(&HELVETICA_WIDTHS, &encoded) * PAGE_NUMBER_SIZE;
+        let h = CAP_HEIGHT / 1000.0 * PAGE_NUMBER_SIZE;
+        let margin = PAGE_NUMBER_MARGIN;
+        let (x, y) = match self.position {
+            Position::Anchor { .. } => {
+                let (x, y) = self
+                    .position
+                    .place(page_w - 2.0 * margin, page_h - 2.0 * margin, w, h);
+                (x + margin, y + margin)
+            }
+            Position::Offset { .. } => self.position.place(page_w, page_h, w, h),
+        };
+        vec![
+            Operation::new("BT", vec![]),
+            Operation::new(
+                "Tf",
+                vec![
+                    Object::Name(PAGE_NUMBER_FONT_NAME.as_bytes().to_vec()),
+                    Object::Real(PAGE_NUMBER_SIZE),
+                ],
+            ),
+            Operation::new("g", vec![Object::Integer(0)]),
+            Operation::new("Td", vec![Object::Real(x), Object::Real(y)]),
+            Operation::new("Tj", vec![Object::String(encoded, StringFormat::Literal)]),
+            Operation::new("ET", vec![]),
+        ]
+    }
+}
+
+/// standard font for placeholder page captions
+pub fn placeholder_font() -> Dictionary {
+    standard_font(b"Helvetica")
+}
+
+/// content stream operations for a page standing in for an unreadable image:
+/// a gray frame with `caption` centered inside it
+pub fn placeholder_operations(caption: &str, page_w: f32, page_h: f32) -> Vec<Operation> {
+    let margin = PAGE_NUMBER_MARGIN;
+    let encoded = encode_win_ansi(caption);
+    let max_w = page_w - 4.0 * margin;
+    let width_em = text_width_em(&HELVETICA_WIDTHS, &encoded);
+    let size = PLACEHOLDER_SIZE.min(max_w / width_em.max(f32::EPSILON));
+    let x = (page_w - width_em * size) / 2.0;
+    let y = (page_h - CAP_HEIGHT / 1000.0 * size) / 2.0;
+    vec![
+        Operation::new("q", vec![]),
+        Operation::new("G", vec![Object::Real(0.6)]),
+        Operation::new(
+            "re",
+            [margin, margin, page_w - 2.0 * margin, page_h - 2.0 * margin]
+                .map(Object::Real)
+                .to_vec(),
+        ),
+        Operation::new("S", vec![]),
+        Operation::new("BT", vec![]),
+        Operation::new(
+            "Tf",
+            vec![
+                Object::Name(PLACEHOLDER_FONT_NAME.as_bytes().to_vec()),
+                Object::Real(size),
+            ],
+        ),
+        Operation::new("g", vec![Object::Real(0.4)]),
+        Operation::new("Td", vec![Object::Real(x), Object::Real(y)]),
+        Operation::new("Tj", vec![Object::String(encoded, StringFormat::Literal)]),
+        Operation::new("ET", vec![]),
+        Operation::new("Q", vec![]),
+    ]
+}
+
+pub fn standard_font(base_font: &[u8]) -> Dictionary {
+    dictionary! {
+        "Type" => Object::Name(b"Font".to_vec()),
+        "Subtype" => Object::Name(b"Type1".to_vec()),
+        "BaseFont" => Object::Name(base_font.to_vec()),
+        "Encoding" => Object::Name(b"WinAnsiEncoding".to_vec()),
+    }
+}
+
+fn matrix(a: f32, b: f32, c: f32, d: f32, e: f32, f: f32) -> Operation {
+    Operation::new("cm", [a, b, c, d, e, f].map(Object::Real).to_vec())
+}
+
+/// encode text for a WinAnsiEncoding font (Latin-1 subset, others become '?')
+pub fn encode_win_ansi(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|c| match c as u32 {
+            code @ (32..=126 | 160..=255) => code as u8,
+            _ => b'?',
+        })
+        .collect()
+}
+
+/// Helvetica advance widths for 32..=126 (from the standard AFM)
+pub const HELVETICA_WIDTHS: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278, // 32
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556, // 48
+    1015, 667, 667, 722, 722, 667, 611, 778, 722, 278, 500, 667, 556, 833, 722, 778, // 64
+    667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 278, 278, 278, 469, 556, // 80
+    333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500, 222, 833, 556, 556, // 96
+    556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584, // 112
+];
+
+/// Helvetica-Bold advance widths for 32..=126 (from the standard AFM)
+pub const HELVETICA_BOLD_WIDTHS: [u16; 95] = [
+    278, 333, 474, 556, 556, 889, 722, 238, 333, 333, 389, 584, 278, 333, 278, 278, // 32
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 333, 333, 584, 584, 584, 611, // 48
+    975, 722, 722, 722, 722, 667, 611, 778, 722, 278, 556, 722, 611, 833, 722, 778, // 64
+    667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 333, 278, 333, 584, 556, // 80
+    333, 556, 611, 556, 611, 556, 333, 611, 611, 278, 278, 556, 278, 889, 611, 611, // 96
+    611, 611, 389, 556, 333, 611, 556, 778, 556, 556, 500, 389, 280, 389, 584, // 112
+];
+
+/// width of WinAnsi-encoded text in ems, given a font's widths for 32..=126
+pub fn text_width_em(widths: &[u16; 95], text: &[u8]) -> f32 {
+    let units: u32 = text
+        .iter()
+        .map(|&b| match b {
+            32..=126 => widths[(b - 32) as usize] as u32,
+            // accented Latin-1 letters are close to the average lowercase width
+            _ => 556,
+        })
+        .sum();
+    units as f32 / 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text_mark(text: &str, rotation: f32) -> Watermark {
+        Watermark {
+            content: WatermarkContent::Text(text.to_string()),
+            opacity: 0.3,
+            rotation,
+            position: Position::default(),
+            color: Color { r: 255, g: 0, b: 0 },
+        }
+    }
+
+    #[test]
+    fn win_ansi_replaces_unsupported_chars() {
+        assert_eq!(encode_win_ansi("Café ✓"), b"Caf\xE9 ?".to_vec());
+    }
+
+    #[test]
+    fn text_width_uses_font_metrics() {
+        assert!((text_width_em(&HELVETICA_BOLD_WIDTHS, b"DRAFT") - 3.388).abs() < 0.001);
+        assert!((text_width_em(&HELVETICA_WIDTHS, b"Page 1") - 3.169).abs() < 0.001);
+    }
+
+    #[test]
+    fn page_numbers_substitute_and_center() {
+        let numbers = PageNumbers {
+            format: "Page {n} of {total}".to_string(),
+            position: "bottom".parse().unwrap(),
+        };
+        let ops = numbers.operations(3, 12, 612.0, 792.0);
+        assert_eq!(ops[4].operands[0].as_str().unwrap(), b"Page 3 of 12");
+        let w = text_width_em(&HELVETICA_WIDTHS, b"Page 3 of 12") * PAGE_NUMBER_SIZE;
+        let td: Vec<f32> = ops[3].operands.iter().map(|o| o.as_float().unwrap()).collect();
+        assert!((td[0] - (612.0 - w) / 2.0).abs() < 0.01);
+        assert_eq!(td[1], PAGE_NUMBER_MARGIN);
+    }
+
+    #[test]
+    fn text_watermark_is_centered_and_fits() {
+        let ops = text_mark("DRAFT", 0.0).operations(600.0, 800.0, None);
+        let names: Vec<&str> = ops.iter().map(|op| op.operator.as_str()).collect();
+        assert_eq!(
+            names,
+            ["q", "gs", "cm", "cm", "BT", "Tf", "rg", "Td", "Tj", "ET", "Q"]
+        );
+        // unrotated: translation to the page center
+        let translate: Vec<f32> = ops[2].operands.iter().map(|o| o.as_float().unwrap()).collect();
+        assert_eq!(&translate[4..], &[300.0, 400.0]);
+        // 80% of the page width
+        let size = ops[5].operands[1].as_float().unwrap();
+        assert!((size * 3.388 - 480.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn image_watermark_keeps_natural_size_when_it_fits() {
+        let mark = Watermark {
+            content: WatermarkContent::Image(PathBuf::from("logo.png")),
+            rotation: 90.0,
+            ..text_mark("", 0.0)
+        };
+        let ops = mark.operations(600.0, 800.0, Some((100.0, 50.0)));
+        let scale: Vec<f32> = ops[4].operands.iter().map(|o| o.as_float().unwrap()).collect();
+        assert_eq!(&scale[..4], &[100.0, 0.0, 0.0, 50.0]);
+    }
+}
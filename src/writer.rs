@@ -0,0 +1,542 @@
+use anyhow::{Context, Result};
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, StringFormat};
+use std::io::Write;
+
+/// incremental PDF writer: objects are serialized as soon as they are added,
+/// only their byte offsets are kept until the xref table is written at the end
+pub struct PdfWriter<W: Write> {
+    out: W,
+    /// bytes written so far (offset of the next object)
+    offset: u64,
+    /// byte offset per object number - 1 (None = reserved, not yet written)
+    offsets: Vec<Option<u64>>,
+}
+
+impl<W: Write> PdfWriter<W> {
+    /// start a new PDF, writing the header immediately
+    pub fn new(out: W, version: &str) -> Result<Self> {
+        let mut writer = PdfWriter {
+            out,
+            offset: 0,
+            offsets: Vec::new(),
+        };
+        // binary comment marks the file as containing 8-bit data
+        writer.write_raw(format!("%PDF-{}\n", version).as_bytes())?;
+        writer.write_raw(b"%\xE2\xE3\xCF\xD3\n")?;
+        Ok(writer)
+    }
+
+    /// reserve an object id to be written later (e.g. the page tree root)
+    pub fn reserve_id(&mut self) -> ObjectId {
+        self.offsets.push(None);
+        (self.offsets.len() as u32, 0)
+    }
+
+    /// serialize a non-stream object under a previously reserved id
+    pub fn write_object(&mut self, id: ObjectId, object: &Object) -> Result<()> {
+        self.begin_object(id)?;
+        let mut buf = Vec::new();
+        write_object(&mut buf, object)?;
+        self.write_raw(&buf)?;
+        self.write_raw(b"\nendobj\n")
+    }
+
+    /// serialize a stream object under a previously reserved id, setting /Length
+    pub fn write_stream(&mut self, id: ObjectId, dict: Dictionary, data: &[u8]) -> Result<()> {
+        self.write_stream_parts(id, dict, &[data])
+    }
+
+    /// serialize a stream whose data is `parts` one after the other, written
+    /// straight from where they are without joining them first
+    pub fn write_stream_parts(
+        &mut self,
+        id: ObjectId,
+        mut dict: Dictionary,
+        parts: &[&[u8]],
+    ) -> Result<()> {
+        let len: usize = parts.iter().map(|part| part.len()).sum();
+        dict.set("Length", len as i64);
+        self.begin_object(id)?;
+        let mut buf = Vec::new();
+        write_dictionary(&mut buf, &dict)?;
+        buf.extend_from_slice(b"\nstream\n");
+        self.write_raw(&buf)?;
+        for part in parts {
+            self.write_raw(part)?;
+        }
+        self.write_raw(b"\nendstream\nendobj\n")
+    }
+
+    /// reserve an id and write a non-stream object in one step
+    pub fn add_object(&mut self, object: impl Into<Object>) -> Result<ObjectId> {
+        let id = self.reserve_id();
+        self.write_object(id, &object.into())?;
+        Ok(id)
+    }
+
+    /// reserve an id and write a stream object in one step
+    pub fn add_stream(&mut self, dict: Dictionary, data: &[u8]) -> Result<ObjectId> {
+        let id = self.reserve_id();
+        self.write_stream(id, dict, data)?;
+        Ok(id)
+    }
+
+    /// bytes written so far, not counting the xref table and trailer
+    pub fn bytes_written(&self) -> u64 {
+        self.offset
+    }
+
+    /// write the xref table and trailer, returning the underlying writer
+    pub fn finish(mut self, root: ObjectId, info: Option<ObjectId>) -> Result<W> {
+        let xref_offset = self.offset;
+        let size = self.offsets.len() + 1;
+        let mut xref = format!("xref\n0 {}\n0000000000 65535 f \n", size);
+        for entry in &self.offsets {
+            match entry {
+                Some(off) => xref.push_str(&format!("{:010} 00000 n \n", off)),
+                None => xref.push_str("0000000000 65535 f \n"),
+            }
+        }
+        self.write_raw(xref.as_bytes())?;
+
+        let mut trailer = Dictionary::new();
+        trailer.set("Size", size as i64);
+        trailer.set("Root", root);
+        if let Some(info) = info {
+            trailer.set("Info", info);
+        }
+        let mut buf = b"trailer\n".to_vec();
+        write_dictionary(&mut buf, &trailer)?;
+        buf.extend_from_slice(format!("\nstartxref\n{}\n%%EOF\n", xref_offset).as_bytes());
+        self.write_raw(&buf)?;
+        self.out.flush().context("Failed to flush PDF output")?;
+        Ok(self.out)
+    }
+
+    /// write a stream under an id not managed by the reserve/write bookkeeping
+    fn write_indirect_stream(&mut self, num: u32, mut dict: Dictionary, data: &[u8]) -> Result<()> {
+        dict.set("Length", data.len() as i64);
+        let mut buf = format!("{} 0 obj\n", num).into_bytes();
+        write_dictionary(&mut buf, &dict)?;
+        buf.extend_from_slice(b"\nstream\n");
+        self.write_raw(&buf)?;
+        self.write_raw(data)?;
+        self.write_raw(b"\nendstream\nendobj\n")
+    }
+
+    fn begin_object(&mut self, id: ObjectId) -> Result<()> {
+        let slot = (id.0 as usize)
+            .checked_sub(1)
+            .and_then(|i| self.offsets.get_mut(i))
+            .context("Object id was not reserved by this writer")?;
+        anyhow::ensure!(slot.is_none(), "Object {} written twice", id.0);
+        *slot = Some(self.offset);
+        self.write_raw(format!("{} {} obj\n", id.0, id.1).as_bytes())
+    }
+
+    fn write_raw(&mut self, bytes: &[u8]) -> Result<()> {
+        self.out
+            .write_all(bytes)
+            .context("Failed to write PDF output")?;
+        self.offset += bytes.len() as u64;
+        Ok(())
+    }
+}
+
+/// non-stream objects per object stream; readers load a whole object stream to
+/// reach any object in it, so they stay modest
+const OBJECTS_PER_STREAM: usize = 100;
+
+/// serialize a whole document compactly: non-stream objects are packed into
+/// Flate-compressed object streams and the xref table becomes a compressed
+/// cross-reference stream (both PDF 1.5)
+pub fn write_compact<W: Write>(doc: &Document, out: W) -> Result<W> {
+    /// where each object number lives in the output
+    #[derive(Clone, Copy)]
+    enum Entry {
+        Free,
+        Offset(u64, u16),
+        Packed(u32, u32),
+    }
+
+    let version = if doc.version.as_str() < "1.5" { "1.5" } else { doc.version.as_str() };
+    let mut writer = PdfWriter::new(out, version)?;
+    let max_id = doc.objects.keys().map(|id| id.0).max().unwrap_or(0);
+    let mut entries = vec![Entry::Free; max_id as usize + 1];
+    let mut next_id = max_id + 1;
+
+    let mut packable = Vec::new();
+    for (&(num, generation), object) in &doc.objects {
+        // only generation 0 objects may sit in object streams
+        if matches!(object, Object::Stream(_)) || generation != 0 {
+            entries[num as usize] = Entry::Offset(writer.offset, generation);
+            let mut buf = format!("{} {} obj\n", num, generation).into_bytes();
+            write_object(&mut buf, object)?;
+            buf.extend_from_slice(b"\nendobj\n");
+            writer.write_raw(&buf)?;
+        } else {
+            packable.push((num, object));
+        }
+    }
+
+    for chunk in packable.chunks(OBJECTS_PER_STREAM) {
+        let stream_id = next_id;
+        next_id += 1;
+        let mut header = Vec::new();
+        let mut body = Vec::new();
+        for (index, &(num, object)) in chunk.iter().enumerate() {
+            write!(header, "{} {} ", num, body.len())?;
+            write_object(&mut body, object)?;
+            body.push(b' ');
+            entries[num as usize] = Entry::Packed(stream_id, index as u32);
+        }
+        let first = header.len();
+        header.extend_from_slice(&body);
+        let dict = dictionary! {
+            "Type" => "ObjStm",
+            "N" => chunk.len() as i64,
+            "First" => first as i64,
+            "Filter" => "FlateDecode",
+        };
+        entries.push(Entry::Offset(writer.offset, 0));
+        writer.write_indirect_stream(stream_id, dict, &deflate(&header)?)?;
+    }
+
+    // the xref stream lists itself too
+    let xref_id = next_id;
+    let xref_offset = writer.offset;
+    entries.push(Entry::Offset(xref_offset, 0));
+    let offset_width = (64 - xref_offset.leading_zeros()).div_ceil(8).max(1) as usize;
+    let mut table = Vec::with_capacity(entries.len() * (offset_width + 3));
+    for entry in &entries {
+        let (kind, field2, field3) = match *entry {
+            Entry::Free => (0, 0, 0xFFFF),
+            Entry::Offset(offset, generation) => (1, offset, generation as u32),
+            Entry::Packed(stream, index) => (2, stream as u64, index),
+        };
+        table.push(kind);
+        table.extend_from_slice(&field2.to_be_bytes()[8 - offset_width..]);
+        table.extend_from_slice(&(field3 as u16).to_be_bytes());
+    }
+    let mut dict = dictionary! {
+        "Type" => "XRef",
+        "Size" => entries.len() as i64,
+        "W" => vec![1.into(), (offset_width as i64).into(), 2.into()],
+        "Filter" => "FlateDecode",
+    };
+    for key in [b"Root".as_slice(), b"Info", b"ID"] {
+        if let Ok(value) = doc.trailer.get(key) {
+            dict.set(key, value.clone());
+        }
+    }
+    writer.write_indirect_stream(xref_id, dict, &deflate(&table)?)?;
+    writer.write_raw(format!("startxref\n{}\n%%EOF\n", xref_offset).as_bytes())?;
+    writer.out.flush().context("Failed to flush PDF output")?;
+    Ok(writer.out)
+}
+
+/// bytes to append to an existing PDF for an incremental update: new versions of
+/// `objects`, then a cross-reference section in the file's own style (table or
+/// stream) chained to the previous one with /Prev. `trailer` carries /Root, /Info,
+/// /ID and the old /Size
+pub fn write_update(
+    original: &[u8],
+    prev_xref: u64,
+    xref_stream: bool,
+    objects: &[(ObjectId, Object)],
+    mut trailer: Dictionary,
+) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    if !original.ends_with(b"\n") && !original.ends_with(b"\r") {
+        out.push(b'\n');
+    }
+    let base = original.len() as u64;
+    let mut entries: Vec<(u32, u64, u16)> = Vec::new();
+    for (id, object) in objects {
+        entries.push((id.0, base + out.len() as u64, id.1));
+        writeln!(out, "{} {} obj", id.0, id.1)?;
+        write_object(&mut out, object)?;
+        out.extend_from_slice(b"\nendobj\n");
+    }
+    let xref_offset = base + out.len() as u64;
+    let max_num = entries.iter().map(|e| e.0).max().unwrap_or(0);
+    let mut size = trailer.get(b"Size").and_then(Object::as_i64).unwrap_or(0);
+    size = size.max(max_num as i64 + 1);
+    trailer.set("Prev", prev_xref as i64);
+
+    if xref_stream {
+        // the xref stream lists itself too
+        let xref_num = size as u32;
+        entries.push((xref_num, xref_offset, 0));
+        entries.sort_unstable();
+        let offset_width = (64 - xref_offset.leading_zeros()).div_ceil(8).max(1) as usize;
+        let mut table = Vec::new();
+        let mut index = Vec::new();
+        for &(num, offset, generation) in &entries {
+            table.push(1);
+            table.extend_from_slice(&offset.to_be_bytes()[8 - offset_width..]);
+            table.extend_from_slice(&generation.to_be_bytes());
+            index.extend([Object::Integer(num as i64), Object::Integer(1)]);
+        }
+        trailer.set("Type", "XRef");
+        trailer.set("Size", size + 1);
+        trailer.set("W", vec![1.into(), (offset_width as i64).into(), 2.into()]);
+        trailer.set("Index", index);
+        trailer.set("Filter", "FlateDecode");
+        let data = deflate(&table)?;
+        trailer.set("Length", data.len() as i64);
+        writeln!(out, "{} 0 obj", xref_num)?;
+        write_dictionary(&mut out, &trailer)?;
+        out.extend_from_slice(b"\nstream\n");
+        out.extend_from_slice(&data);
+        out.extend_from_slice(b"\nendstream\nendobj\n");
+    } else {
+        entries.sort_unstable();
+        out.extend_from_slice(b"xref\n");
+        for &(num, offset, generation) in &entries {
+            write!(out, "{} 1\n{:010} {:05} n \n", num, offset, generation)?;
+        }
+        trailer.set("Size", size);
+        out.extend_from_slice(b"trailer\n");
+        write_dictionary(&mut out, &trailer)?;
+        out.push(b'\n');
+    }
+    write!(out, "startxref\n{}\n%%EOF\n", xref_offset)?;
+    Ok(out)
+}
+
+/// zlib-compress data for a FlateDecode stream
+fn deflate(data: &[u8]) -> Result<Vec<u8>> {
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+/// PDF text string (e.g. for the Info dict): ASCII as a literal, anything else
+/// as UTF-16BE with a byte order mark so viewers don't read it as PDFDocEncoding
+pub fn text_string(text: &str) -> Object {
+    if text.is_ascii() {
+        return Object::String(text.as_bytes().to_vec(), StringFormat::Literal);
+    }
+    let mut bytes = vec![0xFE, 0xFF];
+    for unit in text.encode_utf16() {
+        bytes.extend_from_slice(&unit.to_be_bytes());
+    }
+    Object::String(bytes, StringFormat::Hexadecimal)
+}
+
+/// serialize a PDF object (streams are written inline with their content)
+fn write_object(out: &mut Vec<u8>, object: &Object) -> Result<()> {
+    match object {
+        Object::Null => out.extend_from_slice(b"null"),
+        Object::Boolean(b) => out.extend_from_slice(if *b { b"true" } else { b"false" }),
+        Object::Integer(i) => write!(out, "{}", i)?,
+        Object::Real(r) => write!(out, "{}", r)?,
+        Object::Name(name) => write_name(out, name),
+        Object::String(text, format) => write_string(out, text, format),
+        Object::Array(items) => {
+            out.push(b'[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(b' ');
+                }
+                write_object(out, item)?;
+            }
+            out.push(b']');
+        }
+        Object::Dictionary(dict) => write_dictionary(out, dict)?,
+        Object::Stream(stream) => {
+            let mut dict = stream.dict.clone();
+            dict.set("Length", stream.content.len() as i64);
+            write_dictionary(out, &dict)?;
+            out.extend_from_slice(b"\nstream\n");
+            out.extend_from_slice(&stream.content);
+            out.extend_from_slice(b"\nendstream");
+        }
+        Object::Reference(id) => write!(out, "{} {} R", id.0, id.1)?,
+    }
+    Ok(())
+}
+
+fn write_dictionary(out: &mut Vec<u8>, dict: &Dictionary) -> Result<()> {
+    out.extend_from_slice(b"<<");
+    for (key, value) in dict.iter() {
+        write_name(out, key);
+        out.push(b' ');
+        write_object(out, value)?;
+    }
+    out.extend_from_slice(b">>");
+    Ok(())
+}
+
+fn write_name(out: &mut Vec<u8>, name: &[u8]) {
+    out.push(b'/');
+    for &byte in name {
+        // delimiters, whitespace, and non-printable bytes use #xx escapes
+        if b"()<>[]{}/%#".contains(&byte) || !(33..=126).contains(&byte) {
+            out.extend_from_slice(format!("#{:02X}", byte).as_bytes());
+        } else {
+            out.push(byte);
+        }
+    }
+}
+
+fn write_string(out: &mut Vec<u8>, text: &[u8], format: &StringFormat) {
+    match format {
+        StringFormat::Literal => {
+            out.push(b'(');
+            for &byte in text {
+                match byte {
+                    b'(' | b')' | b'\\' => {
+                        out.push(b'\\');
+                        out.push(byte);
+                    }
+                    b'\r' => out.extend_from_slice(b"\\r"),
+                    _ => out.push(byte),
+                }
+            }
+            out.push(b')');
+        }
+        StringFormat::Hexadecimal => {
+            out.push(b'<');
+            for &byte in text {
+                out.extend_from_slice(format!("{:02X}", byte).as_bytes());
+            }
+            out.push(b'>');
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn serialize(object: &Object) -> String {
+        let mut buf = Vec::new();
+        write_object(&mut buf, object).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn serialize_name_escapes() {
+        assert_eq!(serialize(&Object::Name(b"DeviceRGB".to_vec())), "/DeviceRGB");
+        assert_eq!(serialize(&Object::Name(b"A B#".to_vec())), "/A#20B#23");
+    }
+
+    #[test]
+    fn serialize_literal_string_escapes() {
+        let s = Object::String(b"a(b)\\c".to_vec(), StringFormat::Literal);
+        assert_eq!(serialize(&s), "(a\\(b\\)\\\\c)");
+    }
+
+    #[test]
+    fn serialize_hex_string() {
+        let s = Object::String(vec![0xFF, 0x00, 0x1A], StringFormat::Hexadecimal);
+        assert_eq!(serialize(&s), "<FF001A>");
+    }
+
+    #[test]
+    fn text_string_encoding() {
+        assert_eq!(serialize(&text_string("Slides (v2)")), "(Slides \\(v2\\))");
+        // U+00E9 and a surrogate pair (U+1F600)
+        assert_eq!(serialize(&text_string("é😀")), "<FEFF00E9D83DDE00>");
+    }
+
+    #[test]
+    fn compact_output_reloads() {
+        let mut doc = Document::with_version("1.4");
+        let pages_id = doc.new_object_id();
+        let content_id = doc.add_object(lopdf::Stream::new(dictionary! {}, b"0 0 m".to_vec()));
+        let page_id = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "Contents" => content_id,
+        });
+        doc.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => vec![page_id.into()],
+                "Count" => 1,
+            }),
+        );
+        let catalog_id = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        doc.trailer.set("Root", catalog_id);
+
+        let bytes = write_compact(&doc, Vec::new()).unwrap();
+        assert!(bytes.starts_with(b"%PDF-1.5"));
+        let reloaded = Document::load_mem(&bytes).unwrap();
+        assert_eq!(reloaded.get_pages().len(), 1);
+        assert_eq!(reloaded.get_page_content(page_id).unwrap(), b"0 0 m");
+    }
+
+    #[test]
+    fn serialize_array_and_dict() {
+        let obj = Object::Dictionary(dictionary! {
+            "Kids" => vec![Object::Reference((3, 0)), Object::Reference((4, 0))],
+            "Count" => 2,
+        });
+        assert_eq!(serialize(&obj), "<</Kids [3 0 R 4 0 R]/Count 2>>");
+    }
+
+    #[test]
+    fn unreserved_ids_are_rejected() {
+        let mut pdf = PdfWriter::new(Vec::new(), "1.5").unwrap();
+        for id in [(0, 0), (1, 0)] {
+            let err = pdf.write_object(id, &Object::Null).unwrap_err();
+            assert!(err.to_string().contains("not reserved"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn written_pdf_loads_with_lopdf() {
+        let mut pdf = PdfWriter::new(Vec::new(), "1.5").unwrap();
+        let pages_id = pdf.reserve_id();
+        let content_id = pdf.add_stream(dictionary! {}, b"q Q").unwrap();
+        let page_id = pdf
+            .add_object(dictionary! {
+                "Type" => "Page",
+                "Parent" => pages_id,
+                "MediaBox" => vec![0.into(), 0.into(), 100.into(), 100.into()],
+                "Contents" => content_id,
+            })
+            .unwrap();
+        pdf.write_object(
+            pages_id,
+            &Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => vec![page_id.into()],
+                "Count" => 1,
+            }),
+        )
+        .unwrap();
+        let catalog_id = pdf
+            .add_object(dictionary! {
+                "Type" => "Catalog",
+                "Pages" => pages_id,
+            })
+            .unwrap();
+        let bytes = pdf.finish(catalog_id, None).unwrap();
+
+        let doc = lopdf::Document::load_mem(&bytes).unwrap();
+        assert_eq!(doc.get_pages().len(), 1);
+        let content = doc.get_page_content(page_id).unwrap();
+        assert_eq!(content, b"q Q");
+    }
+
+    #[test]
+    fn write_unreserved_id_fails() {
+        let mut pdf = PdfWriter::new(Vec::new(), "1.5").unwrap();
+        assert!(pdf.write_object((5, 0), &Object::Null).is_err());
+    }
+
+    #[test]
+    fn write_same_id_twice_fails() {
+        let mut pdf = PdfWriter::new(Vec::new(), "1.5").unwrap();
+        let id = pdf.add_object(Object::Null).unwrap();
+        assert!(pdf.write_object(id, &Object::Null).is_err());
+    }
+}
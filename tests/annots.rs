@@ -0,0 +1,65 @@
+mod common;
+
+use common::{path_str, run_ok, tmp_dir, write_text_pdf};
+use lopdf::{dictionary, Document, Object};
+
+/// a highlight with a reply on page 2, and a link
+fn add_comments(path: &std::path::Path) {
+    let mut doc = Document::load(path).unwrap();
+    let page_id = doc.get_pages()[&2];
+    let highlight = doc.add_object(dictionary! {
+        "Type" => "Annot",
+        "Subtype" => "Highlight",
+        "Rect" => vec![70.into(), 690.into(), 130.5.into(), 720.into()],
+        "QuadPoints" => [70, 720, 130, 720, 70, 690, 130, 690].map(Object::from).to_vec(),
+        "Contents" => Object::string_literal("Check this figure"),
+        "T" => Object::string_literal("Grace"),
+        "M" => Object::string_literal("D:20240501093000+02'00'"),
+        "C" => vec![1.into(), 1.into(), 0.into()],
+    });
+    let reply = doc.add_object(dictionary! {
+        "Type" => "Annot",
+        "Subtype" => "Text",
+        "Rect" => vec![140.into(), 700.into(), 160.into(), 720.into()],
+        "Contents" => Object::string_literal("Fixed"),
+        "T" => Object::string_literal("Ada"),
+        "IRT" => highlight,
+    });
+    let link = doc.add_object(dictionary! {
+        "Type" => "Annot",
+        "Subtype" => "Link",
+        "Rect" => vec![0.into(), 0.into(), 100.into(), 20.into()],
+    });
+    doc.get_dictionary_mut(page_id)
+        .unwrap()
+        .set("Annots", vec![highlight.into(), reply.into(), link.into()]);
+    doc.save(path).unwrap();
+}
+
+#[test]
+fn test_annots_export_and_strip() {
+    let dir = tmp_dir("annots");
+    let input = dir.join("in.pdf");
+    write_text_pdf(&input, &["One", "Two"]);
+    add_comments(&input);
+    let stripped = dir.join("stripped.pdf");
+
+    let json = run_ok(&["annots", path_str(&input), "--strip", path_str(&stripped), "-q"]);
+    assert_eq!(
+        json,
+        "[\n  {\"id\":1,\"page\":2,\"type\":\"Highlight\",\"rect\":[70.0,690.0,130.5,720.0],\
+         \"contents\":\"Check this figure\",\"author\":\"Grace\",\"subject\":null,\
+         \"modified\":\"2024-05-01T09:30:00+02:00\",\"color\":[1.0,1.0,0.0],\
+         \"quads\":[[70.0,720.0,130.0,720.0,70.0,690.0,130.0,690.0]],\"reply_to\":null},\n  \
+         {\"id\":2,\"page\":2,\"type\":\"Text\",\"rect\":[140.0,700.0,160.0,720.0],\
+         \"contents\":\"Fixed\",\"author\":\"Ada\",\"subject\":null,\"modified\":null,\
+         \"color\":null,\"quads\":null,\"reply_to\":1}\n]\n"
+    );
+
+    // the link stays
+    let doc = Document::load(&stripped).unwrap();
+    let page = doc.get_dictionary(doc.get_pages()[&2]).unwrap();
+    let annots = page.get(b"Annots").and_then(Object::as_array).unwrap();
+    assert_eq!(annots.len(), 1);
+    assert_eq!(run_ok(&["annots", path_str(&stripped)]), "[]\n");
+}
@@ -0,0 +1,90 @@
+mod common;
+
+use common::*;
+
+#[test]
+fn batch_runs_every_job_and_reports_failures() {
+    let dir = tmp_dir("batch_run");
+    std::fs::create_dir(dir.join("scans")).unwrap();
+    write_tiny_png_rgb(&dir.join("scans/1.png"));
+    write_tiny_jpeg_rgb(&dir.join("scans/2.jpg"));
+    write_tiny_png_rgb(&dir.join("cover.png"));
+    let jobs = dir.join("jobs.toml");
+    std::fs::write(
+        &jobs,
+        r#"
+# paths are relative to this file
+[[job]]
+type = "merge"
+images = "scans"
+output = "scans.pdf"
+pagesize = "a4"
+
+[[job]]
+type = "merge"
+images = ["cover.png", "scans/*.jpg"]
+output = "cover.pdf"
+title = "Cover"
+
+[[job]]
+type = "merge"
+images = "missing/*.png"
+output = "missing.pdf"
+"#,
+    )
+    .unwrap();
+
+    let output = run(&["batch", path_str(&jobs), "--json", "--parallel", "2"]);
+    assert_eq!(output.status.code(), Some(2));
+    let report = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(report.matches("\"ok\":true").count(), 2, "{}", report);
+    assert!(report.contains("No files matched pattern"), "{}", report);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("1 of 3 job(s) failed"));
+    let scans = lopdf::Document::load(dir.join("scans.pdf")).unwrap();
+    assert_eq!(scans.get_pages().len(), 2);
+    let cover = lopdf::Document::load(dir.join("cover.pdf")).unwrap();
+    assert_eq!(cover.get_pages().len(), 2);
+}
+
+#[test]
+fn batch_checks_the_whole_file_first() {
+    let dir = tmp_dir("batch_check");
+    write_tiny_png_rgb(&dir.join("a.png"));
+    let jobs = dir.join("jobs.toml");
+    std::fs::write(
+        &jobs,
+        "[[job]]\ntype = \"merge\"\nimages = \"a.png\"\noutput = \"a.pdf\"\n\n\
+         [[job]]\ntype = \"split\"\ninput = \"a.pdf\"\nresolution = 150\n",
+    )
+    .unwrap();
+    let output = run(&["batch", path_str(&jobs)]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("job 2"), "{}", stderr);
+    assert!(stderr.contains("unknown field `resolution`"), "{}", stderr);
+    assert!(!dir.join("a.pdf").exists());
+}
+
+#[test]
+fn batch_reads_yaml_job_files() {
+    let dir = tmp_dir("batch_yaml");
+    write_tiny_png_rgb(&dir.join("a.png"));
+    write_tiny_png_rgb(&dir.join("b.png"));
+    let jobs = dir.join("jobs.yml");
+    std::fs::write(
+        &jobs,
+        "job:\n  - type: merge\n    images: [a.png, b.png]\n    output: ab.pdf\n    \
+         pagesize: a4\n    gray: true\n",
+    )
+    .unwrap();
+    run_ok(&["batch", path_str(&jobs), "-q"]);
+    let doc = lopdf::Document::load(dir.join("ab.pdf")).unwrap();
+    assert_eq!(doc.get_pages().len(), 2);
+
+    let bad = "job:\n  - type: merge\n    images: a.png\n    output: a.pdf\n    pagesize: b9\n";
+    std::fs::write(&jobs, bad).unwrap();
+    let output = run(&["batch", path_str(&jobs)]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("unknown value 'b9', expected one of a4"), "{}", stderr);
+}
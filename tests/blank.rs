@@ -0,0 +1,48 @@
+mod common;
+
+use common::{path_str, run_ok, tmp_dir};
+use lopdf::{Document, Object};
+
+fn media_box(doc: &Document, page: u32) -> Vec<f32> {
+    let page = doc.get_dictionary(doc.get_pages()[&page]).unwrap();
+    let media_box = page.get(b"MediaBox").and_then(Object::as_array).unwrap();
+    media_box.iter().map(|v| v.as_float().unwrap()).collect()
+}
+
+fn page_content(doc: &Document, page: u32) -> String {
+    let content = doc.get_page_content(doc.get_pages()[&page]).unwrap();
+    String::from_utf8_lossy(&content).into_owned()
+}
+
+#[test]
+fn test_blank_pages() {
+    let dir = tmp_dir("blank");
+    let out = dir.join("blank.pdf");
+    run_ok(&["blank", "-o", path_str(&out), "-n", "2", "-q"]);
+
+    let doc = Document::load(&out).unwrap();
+    assert_eq!(doc.get_pages().len(), 2);
+    assert_eq!(media_box(&doc, 1), vec![0.0, 0.0, 612.0, 792.0]);
+    assert!(!page_content(&doc, 2).contains(" l"));
+}
+
+#[test]
+fn test_blank_grid_template() {
+    let dir = tmp_dir("blank_grid");
+    let out = dir.join("grid.pdf");
+    let args = ["--page-size", "a4", "--orientation", "landscape", "--template", "grid"];
+    run_ok(&[&["blank", "-o", path_str(&out), "-n", "3", "-q"][..], &args].concat());
+
+    let doc = Document::load(&out).unwrap();
+    assert_eq!(doc.get_pages().len(), 3);
+    let media_box = media_box(&doc, 3);
+    assert!((media_box[2] - 841.89).abs() < 0.01 && (media_box[3] - 595.28).abs() < 0.01);
+    let content = page_content(&doc, 3);
+    assert!(content.contains(" l"), "{}", content);
+    assert!(content.contains("\nS"), "{}", content);
+
+    let dots = dir.join("dots.pdf");
+    run_ok(&["blank", "-o", path_str(&dots), "--template", "dot", "--spacing", "1cm", "-q"]);
+    let content = page_content(&Document::load(&dots).unwrap(), 1);
+    assert!(content.contains("1 J"), "{}", content);
+}
@@ -0,0 +1,134 @@
+mod common;
+
+use common::{make_pdf, path_str, run, run_ok, tmp_dir, write_text_pdf};
+use lopdf::Document;
+
+#[test]
+fn test_bookmarks_apply_and_export_round_trip() {
+    let dir = tmp_dir("bookmarks_round_trip");
+    let input = dir.join("in.pdf");
+    write_text_pdf(&input, &["Cover", "One", "Two", "Index"]);
+    let outline = dir.join("outline.toml");
+    std::fs::write(
+        &outline,
+        "[[bookmark]]\ntitle = \"Cover\"\npage = 1\n\n\
+         [[bookmark]]\ntitle = \"Chapters\"\n\n\
+         [[bookmark]]\ntitle = \"Chapter 1\"\npage = 2\nlevel = 2\n\n\
+         [[bookmark]]\ntitle = \"Chapter 2 — Über\"\npage = 3\nlevel = 2\n\n\
+         [[bookmark]]\ntitle = \"Index\"\npage = 4\n",
+    )
+    .unwrap();
+    let out = dir.join("out.pdf");
+    run_ok(&[
+        "bookmarks",
+        path_str(&input),
+        "--apply",
+        path_str(&outline),
+        "-o",
+        path_str(&out),
+        "-q",
+    ]);
+
+    let json = run_ok(&["bookmarks", path_str(&out)]);
+    assert_eq!(
+        json,
+        "[\n  {\"title\":\"Cover\",\"page\":1,\"level\":1},\n  \
+         {\"title\":\"Chapters\",\"page\":null,\"level\":1},\n  \
+         {\"title\":\"Chapter 1\",\"page\":2,\"level\":2},\n  \
+         {\"title\":\"Chapter 2 — Über\",\"page\":3,\"level\":2},\n  \
+         {\"title\":\"Index\",\"page\":4,\"level\":1}\n]\n"
+    );
+    // the TOML export applies back to the same outline
+    let exported = dir.join("exported.toml");
+    run_ok(&["bookmarks", path_str(&out), "-o", path_str(&exported)]);
+    let again = dir.join("again.pdf");
+    run_ok(&[
+        "bookmarks",
+        path_str(&input),
+        "--apply",
+        path_str(&exported),
+        "-o",
+        path_str(&again),
+        "-q",
+    ]);
+    assert_eq!(run_ok(&["bookmarks", path_str(&again)]), json);
+
+    // an empty outline removes them
+    let empty = dir.join("empty.json");
+    std::fs::write(&empty, "[]").unwrap();
+    let cleared = dir.join("cleared.pdf");
+    run_ok(&[
+        "bookmarks",
+        path_str(&out),
+        "--apply",
+        path_str(&empty),
+        "-o",
+        path_str(&cleared),
+        "-q",
+    ]);
+    assert_eq!(run_ok(&["bookmarks", path_str(&cleared)]), "[]\n");
+}
+
+#[test]
+fn test_merge_with_bookmarks() {
+    let dir = tmp_dir("bookmarks_merge");
+    let outline = dir.join("outline.json");
+    std::fs::write(&outline, r#"[{"title": "Start", "page": 1}, {"title": "End", "page": 3}]"#)
+        .unwrap();
+    let pdf = make_pdf(&dir, "scans", 3, &["--bookmarks", path_str(&outline)]);
+    let toml = run_ok(&["bookmarks", path_str(&pdf), "--format", "toml"]);
+    assert!(toml.contains("title = \"End\"\npage = 3\nlevel = 1\n"), "{}", toml);
+
+    // pages past the end are refused up front
+    std::fs::write(&outline, r#"[{"title": "Gone", "page": 9}]"#).unwrap();
+    let output = run(&[
+        "bookmarks",
+        path_str(&pdf),
+        "--apply",
+        path_str(&outline),
+        "-o",
+        path_str(&dir.join("x.pdf")),
+    ]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("only 3 page(s)"));
+}
+
+#[test]
+fn test_split_bookmarks() {
+    let dir = tmp_dir("split_bookmarks");
+    let input = dir.join("ledger.pdf");
+    write_text_pdf(&input, &["Title", "A1", "A2", "B1"]);
+    let outline = dir.join("outline.json");
+    std::fs::write(
+        &outline,
+        r#"[{"title": "Part A", "page": 2},
+            {"title": "Section A.2", "page": 3, "level": 2},
+            {"title": "Part B: Über/End", "page": 4}]"#,
+    )
+    .unwrap();
+    let marked = dir.join("marked.pdf");
+    let apply = ["bookmarks", path_str(&input), "--apply", path_str(&outline)];
+    run_ok(&[&apply[..], &["-o", path_str(&marked), "-q"]].concat());
+    let parts = dir.join("parts");
+
+    run_ok(&["split-bookmarks", path_str(&marked), "-o", path_str(&parts), "-q"]);
+
+    let mut names: Vec<String> = std::fs::read_dir(&parts)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["01_front.pdf", "02_Part_A.pdf", "03_Part_B_Über_End.pdf"]);
+    let part = parts.join("02_Part_A.pdf");
+    assert_eq!(Document::load(&part).unwrap().get_pages().len(), 2);
+    // the part keeps its own bookmarks, pointing at its pages
+    assert_eq!(
+        run_ok(&["bookmarks", path_str(&part)]),
+        "[\n  {\"title\":\"Part A\",\"page\":1,\"level\":1},\n  \
+         {\"title\":\"Section A.2\",\"page\":2,\"level\":2}\n]\n"
+    );
+
+    let sections = dir.join("sections");
+    run_ok(&["split-bookmarks", path_str(&marked), "-o", path_str(&sections), "--level", "2"]);
+    assert_eq!(std::fs::read_dir(&sections).unwrap().count(), 4);
+}
@@ -0,0 +1,56 @@
+mod common;
+
+use common::{path_str, run, run_ok, tmp_dir, write_text_pdf};
+use std::path::Path;
+
+/// the text each page of a PDF shows
+fn page_texts(path: &Path) -> Vec<String> {
+    let doc = lopdf::Document::load(path).unwrap();
+    doc.get_pages()
+        .into_values()
+        .map(|id| String::from_utf8_lossy(&doc.get_page_content(id).unwrap()).into_owned())
+        .collect()
+}
+
+#[test]
+fn test_chunk_every_n_pages() {
+    let dir = tmp_dir("chunk_every");
+    let input = dir.join("book.pdf");
+    let names: Vec<String> = (1..=7).map(|n| format!("Page{}", n)).collect();
+    write_text_pdf(&input, &names.iter().map(String::as_str).collect::<Vec<_>>());
+
+    run_ok(&["chunk", path_str(&input), "--every", "3", "-q"]);
+    let mut seen = Vec::new();
+    for (part, pages) in [("book_001.pdf", 3), ("book_002.pdf", 3), ("book_003.pdf", 1)] {
+        let texts = page_texts(&dir.join(part));
+        assert_eq!(texts.len(), pages, "{}", part);
+        seen.extend(texts);
+    }
+    assert!(!dir.join("book_004.pdf").exists());
+    for (text, name) in seen.iter().zip(&names) {
+        assert!(text.contains(&format!("({})", name)), "{} not in {}", name, text);
+    }
+}
+
+#[test]
+fn test_chunk_max_size() {
+    let dir = tmp_dir("chunk_size");
+    let input = dir.join("book.pdf");
+    let names: Vec<String> = (1..=20).map(|n| format!("Page{}", n)).collect();
+    write_text_pdf(&input, &names.iter().map(String::as_str).collect::<Vec<_>>());
+    let output = dir.join("parts").join("part.pdf");
+
+    run_ok(&["chunk", path_str(&input), "--max-size", "2KB", "-o", path_str(&output), "-q"]);
+    let mut pages = 0;
+    let mut n = 1;
+    while let Ok(meta) = std::fs::metadata(dir.join("parts").join(format!("part_{:03}.pdf", n))) {
+        assert!(meta.len() <= 2048, "part {} is {} bytes", n, meta.len());
+        pages += page_texts(&dir.join("parts").join(format!("part_{:03}.pdf", n))).len();
+        n += 1;
+    }
+    assert!(n > 3, "expected several parts, got {}", n - 1);
+    assert_eq!(pages, 20);
+
+    let output = run(&["chunk", path_str(&input), "-o", path_str(&output)]);
+    assert!(!output.status.success());
+}
@@ -0,0 +1,70 @@
+mod common;
+
+use common::{make_pdf, path_str, run, run_ok, tmp_dir, write_text_pdf};
+
+#[test]
+fn test_combine_keeps_page_order_and_images() {
+    let dir = tmp_dir("combine_order");
+    let first = make_pdf(&dir, "first", 2, &["--pagesize", "a4"]);
+    let second = make_pdf(&dir, "second", 3, &["--pagesize", "letter"]);
+    let out = dir.join("combined.pdf");
+
+    run_ok(&["combine", path_str(&first), path_str(&second), "-o", path_str(&out), "-q"]);
+
+    let doc = lopdf::Document::load(&out).unwrap();
+    let pages = doc.get_pages();
+    assert_eq!(pages.len(), 5);
+    let widths: Vec<f32> = pages
+        .values()
+        .map(|&id| {
+            let page = doc.get_dictionary(id).unwrap();
+            let media_box = page.get(b"MediaBox").unwrap().as_array().unwrap();
+            media_box[2].as_float().unwrap()
+        })
+        .collect();
+    assert!((widths[0] - 595.28).abs() < 0.1, "{:?}", widths);
+    assert!((widths[1] - 595.28).abs() < 0.1, "{:?}", widths);
+    assert_eq!(&widths[2..], &[612.0, 612.0, 612.0]);
+
+    let images = doc
+        .objects
+        .values()
+        .filter_map(|o| o.as_stream().ok())
+        .filter(|s| s.dict.get(b"Subtype").and_then(|t| t.as_name()).ok() == Some(b"Image"))
+        .count();
+    assert_eq!(images, 5);
+}
+
+#[test]
+fn test_combine_keeps_text_and_inherited_attributes() {
+    let dir = tmp_dir("combine_text");
+    let a = dir.join("a.pdf");
+    let b = dir.join("b.pdf");
+    write_text_pdf(&a, &["Alpha", "Beta"]);
+    write_text_pdf(&b, &["Gamma"]);
+    let out = dir.join("combined.pdf");
+
+    run_ok(&["combine", path_str(&a), path_str(&b), "-o", path_str(&out), "-q"]);
+
+    let doc = lopdf::Document::load(&out).unwrap();
+    assert_eq!(doc.get_pages().len(), 3);
+    assert!(doc.extract_text(&[1]).unwrap().contains("Alpha"));
+    assert!(doc.extract_text(&[3]).unwrap().contains("Gamma"));
+    // the page tree root with the shared MediaBox and fonts was replaced
+    for &id in doc.get_pages().values() {
+        let page = doc.get_dictionary(id).unwrap();
+        assert!(page.has(b"MediaBox"));
+        assert!(page.has(b"Resources"));
+    }
+}
+
+#[test]
+fn test_combine_missing_input_fails() {
+    let dir = tmp_dir("combine_missing");
+    let out = dir.join("combined.pdf");
+    let missing = dir.join("missing.pdf");
+
+    let output = run(&["combine", path_str(&missing), "-o", path_str(&out)]);
+    assert!(!output.status.success());
+    assert!(!out.exists());
+}
@@ -0,0 +1,117 @@
+// shared by the per-subcommand test binaries; each uses only some helpers
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+pub fn ovid_bin() -> PathBuf {
+    // cargo test builds the binary in the target directory
+    let mut path = std::env::current_exe().unwrap();
+    // tests/<name>-<hash> -> deps dir -> debug dir
+    path.pop();
+    path.pop();
+    path.push("ovid");
+    path
+}
+
+pub fn tmp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("ovid_test_{}", name));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// run ovid with the given arguments, without checking the exit status
+pub fn run(args: &[&str]) -> Output {
+    Command::new(ovid_bin())
+        .args(args)
+        .output()
+        .expect("failed to run ovid")
+}
+
+/// run ovid and return stdout, panicking if it fails
+pub fn run_ok(args: &[&str]) -> String {
+    let output = run(args);
+    if !output.status.success() {
+        panic!(
+            "ovid {} failed:\nstdout: {}\nstderr: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr),
+        );
+    }
+    String::from_utf8(output.stdout).unwrap()
+}
+
+pub fn path_str(path: &Path) -> &str {
+    path.to_str().unwrap()
+}
+
+pub fn write_tiny_png_rgb(path: &Path) {
+    let img = image::RgbImage::from_fn(4, 4, |x, y| {
+        image::Rgb([(x * 60) as u8, (y * 60) as u8, 200])
+    });
+    img.save(path).unwrap();
+}
+
+pub fn write_tiny_jpeg_rgb(path: &Path) {
+    let img = image::RgbImage::from_fn(4, 4, |x, y| {
+        image::Rgb([(x * 60) as u8, (y * 60) as u8, 128])
+    });
+    img.save(path).unwrap();
+}
+
+/// build a PDF with `pages` pages from tiny PNGs via `ovid merge`, with extra merge flags
+pub fn make_pdf(dir: &Path, name: &str, pages: usize, args: &[&str]) -> PathBuf {
+    let mut images = Vec::new();
+    for i in 0..pages {
+        let img = dir.join(format!("{}_page{}.png", name, i));
+        write_tiny_png_rgb(&img);
+        images.push(img);
+    }
+    let pdf = dir.join(format!("{}.pdf", name));
+    let mut cmd: Vec<&str> = vec!["merge", "--quiet", "-o", path_str(&pdf)];
+    cmd.extend(images.iter().map(|p| path_str(p)));
+    cmd.extend_from_slice(args);
+    run_ok(&cmd);
+    pdf
+}
+
+/// write a text-only PDF with one page per entry of `texts`, built directly with lopdf;
+/// MediaBox and Resources sit on the page tree root so pages inherit them
+pub fn write_text_pdf(path: &Path, texts: &[&str]) {
+    use lopdf::{dictionary, Document, Object, Stream};
+
+    let mut doc = Document::with_version("1.5");
+    let pages_id = doc.new_object_id();
+    let font_id = doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Helvetica",
+    });
+    let mut kids = Vec::new();
+    for text in texts {
+        let content = format!("BT /F1 24 Tf 72 700 Td ({}) Tj ET", text);
+        let content_id = doc.add_object(Stream::new(dictionary! {}, content.into_bytes()));
+        let page_id = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "Contents" => content_id,
+        });
+        kids.push(Object::Reference(page_id));
+    }
+    let count = kids.len() as i64;
+    doc.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => kids,
+            "Count" => count,
+            "MediaBox" => vec![0.into(), 0.into(), 300.into(), 400.into()],
+            "Resources" => dictionary! { "Font" => dictionary! { "F1" => font_id } },
+        }),
+    );
+    let catalog_id = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+    doc.trailer.set("Root", catalog_id);
+    doc.save(path).unwrap();
+}
@@ -0,0 +1,74 @@
+mod common;
+
+use common::{path_str, run, tmp_dir, write_text_pdf};
+
+#[test]
+fn compare_identical_pdfs() {
+    let dir = tmp_dir("compare_same");
+    let first = dir.join("first.pdf");
+    let second = dir.join("second.pdf");
+    write_text_pdf(&first, &["Invoice 1", "Page two"]);
+    write_text_pdf(&second, &["Invoice 1", "Page two"]);
+    let diffs = dir.join("diffs");
+
+    let out = run(&[
+        "compare",
+        path_str(&first),
+        path_str(&second),
+        "--diff-dir",
+        path_str(&diffs),
+        "--json",
+        "-q",
+    ]);
+    assert_eq!(out.status.code(), Some(0));
+    let json = String::from_utf8_lossy(&out.stdout);
+    assert!(json.contains("\"first_pages\":2,\"second_pages\":2"), "{}", json);
+    assert!(json.contains("\"match\":true"), "{}", json);
+    assert_eq!(json.matches("\"status\":\"identical\"").count(), 2, "{}", json);
+    assert!(json.contains("\"diff_image\":null"), "{}", json);
+    // identical pages leave no diff images behind
+    assert_eq!(std::fs::read_dir(&diffs).unwrap().count(), 0);
+}
+
+#[test]
+fn compare_differing_pdfs() {
+    let dir = tmp_dir("compare_differ");
+    let first = dir.join("first.pdf");
+    let second = dir.join("second.pdf");
+    write_text_pdf(&first, &["Invoice 1", "Page two"]);
+    write_text_pdf(&second, &["Invoice 2 - revised", "Page two", "Page three"]);
+    let diffs = dir.join("diffs");
+
+    let out = run(&[
+        "compare",
+        path_str(&first),
+        path_str(&second),
+        "--diff-dir",
+        path_str(&diffs),
+        "--json",
+        "-q",
+    ]);
+    assert_eq!(out.status.code(), Some(1));
+    let json = String::from_utf8_lossy(&out.stdout);
+    assert!(json.contains("\"first_pages\":2,\"second_pages\":3"), "{}", json);
+    assert!(json.contains("\"match\":false"), "{}", json);
+    assert!(json.contains("{\"page\":1,\"status\":\"different\""), "{}", json);
+    assert!(json.contains("{\"page\":2,\"status\":\"identical\""), "{}", json);
+    assert!(json.contains("{\"page\":3,\"status\":\"missing\""), "{}", json);
+
+    let diff = diffs.join("first_diff_0001.png");
+    assert!(json.contains("first_diff_0001.png"), "{}", json);
+    let image = image::open(&diff).unwrap().to_rgb8();
+    assert!(image.pixels().any(|p| p.0 == [255, 0, 0]));
+    assert!(!diffs.join("first_diff_0002.png").exists());
+}
+
+#[test]
+fn compare_rejects_missing_input() {
+    let dir = tmp_dir("compare_missing");
+    let first = dir.join("first.pdf");
+    write_text_pdf(&first, &["Invoice 1"]);
+    let out = run(&["compare", path_str(&dir.join("nope.pdf")), path_str(&first)]);
+    assert_eq!(out.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("nope.pdf"));
+}
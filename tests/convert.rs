@@ -0,0 +1,115 @@
+mod common;
+
+use common::{path_str, run, run_ok, tmp_dir, write_tiny_jpeg_rgb, write_tiny_png_rgb};
+
+#[test]
+fn test_convert_to_tiff_and_bmp() {
+    let dir = tmp_dir("convert_formats");
+    let png = dir.join("a.png");
+    let jpg = dir.join("b.jpg");
+    write_tiny_png_rgb(&png);
+    write_tiny_jpeg_rgb(&jpg);
+    let out = dir.join("out");
+
+    run_ok(&["convert", path_str(&png), path_str(&jpg), "-f", "tiff", "-o", path_str(&out), "-q"]);
+    for name in ["a.tiff", "b.tiff"] {
+        let img = image::open(out.join(name)).unwrap();
+        assert_eq!((img.width(), img.height()), (4, 4));
+    }
+
+    // a whole directory, written next to the inputs
+    run_ok(&["convert", path_str(&dir), "-f", "bmp", "-q"]);
+    assert!(dir.join("a.bmp").exists());
+    assert!(dir.join("b.bmp").exists());
+}
+
+#[test]
+fn test_convert_resize_and_gray() {
+    let dir = tmp_dir("convert_resize");
+    let src = dir.join("photo.bmp");
+    image::RgbImage::from_fn(40, 20, |x, _| image::Rgb([x as u8 * 6, 100, 50]))
+        .save(&src)
+        .unwrap();
+
+    run_ok(&["convert", path_str(&src), "-f", "png", "--max-width", "10", "--gray", "-q"]);
+    let img = image::open(dir.join("photo.png")).unwrap();
+    assert_eq!((img.width(), img.height()), (10, 5));
+    assert_eq!(img.color(), image::ColorType::L8);
+
+    run_ok(&["convert", path_str(&src), "-f", "gif", "--scale", "50%", "-q"]);
+    let img = image::open(dir.join("photo.gif")).unwrap();
+    assert_eq!((img.width(), img.height()), (20, 10));
+}
+
+#[test]
+fn test_convert_png_max_compression() {
+    let dir = tmp_dir("convert_png_max");
+    let src = dir.join("scan.bmp");
+    image::RgbImage::from_fn(64, 64, |x, y| image::Rgb([(x * 4) as u8, (y * 4) as u8, 128]))
+        .save(&src)
+        .unwrap();
+    let (fast, max) = (dir.join("fast"), dir.join("max"));
+
+    run_ok(&["convert", path_str(&src), "-f", "png", "-o", path_str(&fast), "-q"]);
+    run_ok(&["convert", path_str(&src), "-f", "png", "-c", "max", "-o", path_str(&max), "-q"]);
+    let (fast, max) = (fast.join("scan.png"), max.join("scan.png"));
+    // the same pixels, in no more bytes
+    let pixels = |path| image::open(path).unwrap().to_rgb8();
+    assert!(pixels(&fast) == pixels(&max));
+    let len = |path| std::fs::metadata(path).unwrap().len();
+    assert!(len(&max) <= len(&fast));
+}
+
+#[test]
+fn test_convert_refuses_to_overwrite_input() {
+    let dir = tmp_dir("convert_overwrite");
+    let png = dir.join("a.png");
+    write_tiny_png_rgb(&png);
+
+    let output = run(&["convert", path_str(&png), "-f", "png"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("already png"));
+}
+
+#[test]
+fn test_deskew_straightens_scan() {
+    let dir = tmp_dir("deskew");
+    // rows of dashes rising 3 degrees to the right
+    let mut page = image::GrayImage::from_pixel(500, 400, image::Luma([255]));
+    let slope = 3f32.to_radians().tan();
+    for row in (80..340).step_by(24) {
+        for x in 40..460u32 {
+            if x % 13 < 9 {
+                let y = row as f32 - x as f32 * slope;
+                for dy in 0..7 {
+                    page.put_pixel(x, y as u32 + dy, image::Luma([0]));
+                }
+            }
+        }
+    }
+    let scan = dir.join("scan.png");
+    page.save(&scan).unwrap();
+    let out = dir.join("out");
+
+    let output = run(&["deskew", path_str(&scan), "-o", path_str(&out)]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let angle: f32 = stderr
+        .split("straightened by ")
+        .nth(1)
+        .and_then(|rest| rest.split('°').next())
+        .and_then(|angle| angle.parse().ok())
+        .unwrap_or_else(|| panic!("no angle in {}", stderr));
+    assert!((angle - 3.0).abs() < 0.3, "{}", angle);
+    let straight = image::open(out.join("scan.png")).unwrap();
+    assert_eq!((straight.width(), straight.height()), (500, 400));
+
+    // straightening again finds nothing left to do
+    let again = dir.join("again");
+    let output = run(&["deskew", path_str(&out.join("scan.png")), "-o", path_str(&again)]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("already straight"));
+
+    let pdf = dir.join("scan.pdf");
+    run_ok(&["merge", path_str(&scan), "--deskew", "-o", path_str(&pdf), "-q"]);
+    assert_eq!(lopdf::Document::load(&pdf).unwrap().get_pages().len(), 1);
+}
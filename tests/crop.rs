@@ -0,0 +1,82 @@
+mod common;
+
+use common::{path_str, run, run_ok, tmp_dir, write_text_pdf};
+use lopdf::{Document, Object};
+
+/// a page's own box entry, if set
+fn page_box(doc: &Document, page: u32, key: &[u8]) -> Option<Vec<f32>> {
+    let page = doc.get_dictionary(doc.get_pages()[&page]).unwrap();
+    let arr = page.get(key).and_then(Object::as_array).ok()?;
+    Some(arr.iter().map(|o| o.as_float().unwrap()).collect())
+}
+
+#[test]
+fn test_crop_margins_on_selected_pages() {
+    let dir = tmp_dir("crop_margins");
+    let input = dir.join("in.pdf");
+    // 300x400 pages
+    write_text_pdf(&input, &["P1", "P2"]);
+    let out = dir.join("out.pdf");
+
+    run_ok(&[
+        "crop",
+        path_str(&input),
+        "--margins",
+        "10,20,30,40",
+        "-p",
+        "2",
+        "-o",
+        path_str(&out),
+        "-q",
+    ]);
+    let doc = Document::load(&out).unwrap();
+    assert_eq!(page_box(&doc, 1, b"CropBox"), None);
+    assert_eq!(page_box(&doc, 2, b"CropBox"), Some(vec![40.0, 30.0, 280.0, 390.0]));
+    assert_eq!(page_box(&doc, 2, b"TrimBox"), None);
+
+    // margins trim the current crop box further; both boxes on request
+    let again = dir.join("again.pdf");
+    run_ok(&[
+        "crop",
+        path_str(&out),
+        "-m",
+        "10",
+        "--target",
+        "both",
+        "-o",
+        path_str(&again),
+        "-q",
+    ]);
+    let doc = Document::load(&again).unwrap();
+    assert_eq!(page_box(&doc, 1, b"TrimBox"), Some(vec![10.0, 10.0, 290.0, 390.0]));
+    assert_eq!(page_box(&doc, 2, b"CropBox"), Some(vec![50.0, 40.0, 270.0, 380.0]));
+    assert_eq!(page_box(&doc, 2, b"TrimBox"), page_box(&doc, 2, b"CropBox"));
+}
+
+#[test]
+fn test_crop_absolute_box_clipped_to_media_box() {
+    let dir = tmp_dir("crop_box");
+    let input = dir.join("in.pdf");
+    write_text_pdf(&input, &["P1"]);
+    let out = dir.join("out.pdf");
+
+    run_ok(&[
+        "crop",
+        path_str(&input),
+        "--box",
+        "-5,50,1in,500",
+        "--target",
+        "trim",
+        "-o",
+        path_str(&out),
+        "-q",
+    ]);
+    let doc = Document::load(&out).unwrap();
+    assert_eq!(page_box(&doc, 1, b"TrimBox"), Some(vec![0.0, 50.0, 72.0, 400.0]));
+    assert_eq!(page_box(&doc, 1, b"CropBox"), None);
+
+    let nothing = dir.join("nothing.pdf");
+    let output = run(&["crop", path_str(&input), "-m", "200", "-o", path_str(&nothing)]);
+    assert!(!output.status.success());
+    assert!(!nothing.exists());
+}
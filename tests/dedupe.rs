@@ -0,0 +1,36 @@
+mod common;
+
+use common::{path_str, run, tmp_dir, write_text_pdf};
+use lopdf::Document;
+
+fn page_texts(path: &std::path::Path) -> Vec<String> {
+    let doc = Document::load(path).unwrap();
+    doc.get_pages()
+        .into_values()
+        .map(|id| String::from_utf8_lossy(&doc.get_page_content(id).unwrap()).into_owned())
+        .collect()
+}
+
+#[test]
+fn test_dedupe_drops_repeated_pages() {
+    let dir = tmp_dir("dedupe");
+    let input = dir.join("in.pdf");
+    write_text_pdf(&input, &["A", "A", "B", "A", "C", "B"]);
+    let out = dir.join("out.pdf");
+
+    let result = run(&["dedupe", path_str(&input), "-o", path_str(&out)]);
+    assert!(result.status.success());
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("page 2: same as page 1"), "{}", stderr);
+    assert!(stderr.contains("page 6: same as page 3"), "{}", stderr);
+    let texts = page_texts(&out);
+    assert_eq!(texts.len(), 3);
+    for (text, expected) in texts.iter().zip(["(A)", "(B)", "(C)"]) {
+        assert!(text.contains(expected), "{}", text);
+    }
+
+    // only runs of the same page
+    let result = run(&["dedupe", path_str(&input), "-o", path_str(&out), "--adjacent", "-q"]);
+    assert!(result.status.success());
+    assert_eq!(page_texts(&out).len(), 5);
+}
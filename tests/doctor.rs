@@ -0,0 +1,22 @@
+mod common;
+
+use common::run;
+
+#[test]
+fn doctor_reports_environment() {
+    // the render self-test needs MuPDF, so only the report itself is checked
+    let out = run(&["doctor"]);
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(stdout.contains("Threads:"), "{}", stdout);
+    assert!(stdout.contains("Decoders:") && stdout.contains("png"), "{}", stdout);
+    assert!(stdout.contains("PNG encode/decode"), "{}", stdout);
+    assert!(stdout.lines().any(|l| l.contains("PNG encode/decode") && l.ends_with("ok")));
+}
+
+#[test]
+fn doctor_json() {
+    let out = run(&["-j", "3", "doctor", "--json"]);
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(stdout.contains("\"threads\":3"), "{}", stdout);
+    assert!(stdout.contains("\"self_tests\""), "{}", stdout);
+}
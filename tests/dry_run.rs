@@ -0,0 +1,44 @@
+mod common;
+
+use common::{path_str, run, run_ok, tmp_dir, write_tiny_png_rgb};
+
+#[test]
+fn merge_plan_writes_nothing() {
+    let dir = tmp_dir("dry_run_merge");
+    let images = dir.join("scans");
+    std::fs::create_dir_all(&images).unwrap();
+    for name in ["a.png", "b.png", "c.png"] {
+        write_tiny_png_rgb(&images.join(name));
+    }
+    std::fs::write(images.join("bad.png"), b"not a png").unwrap();
+    let output = dir.join("book.pdf");
+
+    let plan = run_ok(&[
+        "merge",
+        path_str(&images),
+        "-o",
+        path_str(&output),
+        "--pagesize",
+        "a4",
+        "--max-pages",
+        "2",
+        "--skip-errors",
+        "--dry-run",
+    ]);
+    let lines: Vec<&str> = plan.lines().collect();
+    assert!(lines[0].starts_with("Merge 4 image(s) -> "), "{}", plan);
+    assert!(lines[1].ends_with("a.png  4x4 px, 595.3x841.9 pt page"), "{}", plan);
+    assert!(plan.contains("bad.png  skipped: Unrecognized image format"), "{}", plan);
+    assert_eq!(lines[5], "Would write 3 page(s) in 2 parts:");
+    assert!(lines[6].ends_with("book_001.pdf  pages 1-2"), "{}", plan);
+    assert!(lines[7].ends_with("book_002.pdf  page 3"), "{}", plan);
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+    // without --skip-errors the run would fail, and so does the plan
+    let out = run(&["merge", path_str(&images), "-o", path_str(&output), "--dry-run"]);
+    assert_eq!(out.status.code(), Some(1));
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(stdout.contains("bad.png  fails: "), "{}", stdout);
+    assert!(String::from_utf8_lossy(&out.stderr).contains("1 of 4 image(s) can't be read"));
+    assert!(!output.exists());
+}
@@ -0,0 +1,136 @@
+mod common;
+
+use common::{path_str, run, run_ok, tmp_dir, write_text_pdf};
+
+fn page_text(path: &std::path::Path) -> String {
+    let doc = lopdf::Document::load(path).unwrap();
+    let content = doc.get_page_content(doc.get_pages()[&1]).unwrap();
+    String::from_utf8_lossy(&content).into_owned()
+}
+
+#[test]
+fn test_encrypt_decrypt_round_trip() {
+    let dir = tmp_dir("encrypt_round_trip");
+    let input = dir.join("in.pdf");
+    write_text_pdf(&input, &["Secret"]);
+    let enc = dir.join("enc.pdf");
+
+    run_ok(&[
+        "encrypt",
+        path_str(&input),
+        "--user-password",
+        "open",
+        "--owner-password",
+        "boss",
+        "--no-copy",
+        "-o",
+        path_str(&enc),
+        "-q",
+    ]);
+    let raw = std::fs::read(&enc).unwrap();
+    assert!(!raw.windows(8).any(|w| w == b"(Secret)"));
+    let info = run_ok(&["info", path_str(&enc)]);
+    assert!(info.contains("yes (password required)"), "{}", info);
+
+    // either password opens it
+    for password in ["open", "boss"] {
+        let dec = dir.join(format!("dec_{}.pdf", password));
+        run_ok(&["decrypt", path_str(&enc), "--password", password, "-o", path_str(&dec), "-q"]);
+        assert!(page_text(&dec).contains("(Secret)"));
+        assert!(!lopdf::Document::load(&dec).unwrap().is_encrypted());
+    }
+
+    let wrong = dir.join("wrong.pdf");
+    let output = run(&["decrypt", path_str(&enc), "--password", "nope", "-o", path_str(&wrong)]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Incorrect password"));
+    assert!(!wrong.exists());
+}
+
+#[test]
+fn test_owner_only_encryption_opens_without_password() {
+    let dir = tmp_dir("encrypt_owner_only");
+    let input = dir.join("in.pdf");
+    write_text_pdf(&input, &["One", "Two"]);
+    let enc = dir.join("enc.pdf");
+
+    run_ok(&[
+        "encrypt",
+        path_str(&input),
+        "--owner-password",
+        "boss",
+        "--no-print",
+        "-o",
+        path_str(&enc),
+        "-q",
+    ]);
+    let info = run_ok(&["info", path_str(&enc)]);
+    assert!(info.contains("yes (no user password)"), "{}", info);
+
+    // other commands read it as is
+    let rotated = dir.join("rotated.pdf");
+    run_ok(&["rotate", path_str(&enc), "-o", path_str(&rotated), "-q"]);
+    assert!(page_text(&rotated).contains("(One)"));
+}
+
+#[test]
+fn test_decrypt_rejects_plain_pdf() {
+    let dir = tmp_dir("decrypt_plain");
+    let input = dir.join("in.pdf");
+    write_text_pdf(&input, &["Open"]);
+    let out = dir.join("out.pdf");
+
+    let output = run(&["decrypt", path_str(&input), "-o", path_str(&out)]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("not encrypted"));
+}
+
+#[test]
+fn test_unlock_lifts_restrictions() {
+    let dir = tmp_dir("unlock");
+    let input = dir.join("in.pdf");
+    write_text_pdf(&input, &["Locked"]);
+    let owner_only = dir.join("owner_only.pdf");
+    run_ok(&[
+        "encrypt",
+        path_str(&input),
+        "--owner-password",
+        "boss",
+        "--no-print",
+        "--no-copy",
+        "-o",
+        path_str(&owner_only),
+        "-q",
+    ]);
+
+    // no password needed when only the owner password is set
+    let unlocked = dir.join("unlocked.pdf");
+    let output = run(&["unlock", path_str(&owner_only), "-o", path_str(&unlocked)]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("lifted restrictions: printing, copying"), "{}", stderr);
+    assert!(!lopdf::Document::load(&unlocked).unwrap().is_encrypted());
+    assert!(page_text(&unlocked).contains("(Locked)"));
+
+    // a password to open it needs the owner password too
+    let both = dir.join("both.pdf");
+    run_ok(&[
+        "encrypt",
+        path_str(&input),
+        "--user-password",
+        "open",
+        "--owner-password",
+        "boss",
+        "-o",
+        path_str(&both),
+        "-q",
+    ]);
+    let output = run(&["unlock", path_str(&both), "-o", path_str(&unlocked)]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--password"));
+    run_ok(&["unlock", path_str(&both), "--password", "boss", "-o", path_str(&unlocked), "-q"]);
+    assert!(!lopdf::Document::load(&unlocked).unwrap().is_encrypted());
+
+    let output = run(&["unlock", path_str(&input), "-o", path_str(&unlocked)]);
+    assert!(!output.status.success());
+}
@@ -0,0 +1,38 @@
+mod common;
+
+use common::{path_str, run, tmp_dir, write_tiny_png_rgb};
+
+#[test]
+fn bad_arguments_exit_3() {
+    assert_eq!(run(&["merge"]).status.code(), Some(3));
+    assert_eq!(run(&["split", "a.pdf", "--dpi", "9"]).status.code(), Some(3));
+    assert_eq!(run(&["no-such-command"]).status.code(), Some(3));
+    let help = run(&["merge", "--help"]);
+    assert_eq!(help.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&help.stdout).contains("--skip-errors"));
+}
+
+#[test]
+fn skipped_images_exit_2() {
+    let dir = tmp_dir("exit_codes_merge");
+    let good = dir.join("good.png");
+    write_tiny_png_rgb(&good);
+    let bad = dir.join("bad.png");
+    std::fs::write(&bad, b"not a png").unwrap();
+    let output = dir.join("out.pdf");
+
+    let merge = |images: &[&str], extra: &[&str]| {
+        let mut args = vec!["merge"];
+        args.extend(images);
+        args.extend(["-o", path_str(&output)]);
+        args.extend(extra);
+        run(&args).status.code()
+    };
+    let (good, bad) = (path_str(&good), path_str(&bad));
+    assert_eq!(merge(&[good, good], &["--skip-errors"]), Some(0));
+    assert_eq!(merge(&[good, bad], &["--skip-errors"]), Some(2));
+    assert_eq!(merge(&[good, bad], &["--skip-errors", "--placeholder"]), Some(2));
+    // nothing to show for it: a failure, not a partial one
+    assert_eq!(merge(&[bad, bad], &["--skip-errors"]), Some(1));
+    assert_eq!(merge(&[good, bad], &[]), Some(1));
+}
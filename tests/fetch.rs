@@ -0,0 +1,73 @@
+mod common;
+
+use common::{path_str, run, run_ok, tmp_dir, write_tiny_png_rgb};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::mpsc;
+
+/// serve `data` at any path, cutting the first response off halfway so the
+/// client has to resume; sends each request's Range header (or "") back
+fn serve_flaky(data: Vec<u8>, requests: usize) -> (u16, mpsc::Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        for (n, stream) in listener.incoming().take(requests).enumerate() {
+            let mut stream = stream.unwrap();
+            let mut range = String::new();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some(value) = line.to_ascii_lowercase().strip_prefix("range: bytes=") {
+                    range = value.trim().trim_end_matches('-').to_string();
+                }
+            }
+            tx.send(range.clone()).unwrap();
+            let from: usize = range.parse().unwrap_or(0);
+            let status = if from > 0 { "206 Partial Content" } else { "200 OK" };
+            let head = format!(
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                status,
+                data.len() - from
+            );
+            stream.write_all(head.as_bytes()).unwrap();
+            let end = if n == 0 { data.len() / 2 } else { data.len() };
+            stream.write_all(&data[from..end]).unwrap();
+        }
+    });
+    (port, rx)
+}
+
+#[test]
+fn test_merge_downloads_and_resumes() {
+    let dir = tmp_dir("fetch");
+    let png = dir.join("tiny.png");
+    write_tiny_png_rgb(&png);
+    let data = std::fs::read(&png).unwrap();
+    let half = (data.len() / 2).to_string();
+    let (port, requests) = serve_flaky(data, 2);
+
+    let url = format!("http://127.0.0.1:{}/scans/tiny.png?token=abc", port);
+    let out = dir.join("out.pdf");
+    run_ok(&["merge", &url, path_str(&png), "-o", path_str(&out), "-q"]);
+
+    assert_eq!(requests.recv().unwrap(), "");
+    assert_eq!(requests.recv().unwrap(), half);
+    let doc = lopdf::Document::load(&out).unwrap();
+    assert_eq!(doc.get_pages().len(), 2);
+}
+
+#[test]
+fn test_download_size_limit() {
+    let dir = tmp_dir("fetch_limit");
+    let (port, _requests) = serve_flaky(vec![0u8; 4096], 1);
+    let url = format!("http://127.0.0.1:{}/big.png", port);
+    let out = run(&["merge", &url, "-o", path_str(&dir.join("out.pdf")), "--max-download", "1k"]);
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("larger than the 1.0 KB download limit"), "{}", stderr);
+}
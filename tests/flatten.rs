@@ -0,0 +1,117 @@
+mod common;
+
+use common::{path_str, run_ok, tmp_dir, write_text_pdf};
+use lopdf::{dictionary, Document, Object, Stream};
+
+/// add a filled-in form to a one-page PDF: a text field without an appearance
+/// (NeedAppearances), a checked box, a hidden note, and a link
+fn add_form(path: &std::path::Path) {
+    let mut doc = Document::load(path).unwrap();
+    let page_id = doc.get_pages()[&1];
+    let helv = doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Helvetica",
+    });
+    let name = doc.add_object(dictionary! {
+        "Type" => "Annot",
+        "Subtype" => "Widget",
+        "FT" => "Tx",
+        "T" => Object::string_literal("name"),
+        "V" => Object::string_literal("Ada"),
+        "DA" => Object::string_literal("/Helv 0 Tf 0 g"),
+        "Rect" => vec![50.into(), 300.into(), 250.into(), 320.into()],
+        "P" => page_id,
+    });
+    let on = doc.add_object(Stream::new(
+        dictionary! {
+            "Type" => "XObject",
+            "Subtype" => "Form",
+            "BBox" => vec![0.into(), 0.into(), 10.into(), 10.into()],
+        },
+        b"0 0 10 10 re f".to_vec(),
+    ));
+    let check = doc.add_object(dictionary! {
+        "Type" => "Annot",
+        "Subtype" => "Widget",
+        "FT" => "Btn",
+        "T" => Object::string_literal("agree"),
+        "V" => "Yes",
+        "AS" => "Yes",
+        "AP" => dictionary! { "N" => dictionary! { "Yes" => on } },
+        "Rect" => vec![50.into(), 250.into(), 70.into(), 270.into()],
+    });
+    let hidden = doc.add_object(dictionary! {
+        "Type" => "Annot",
+        "Subtype" => "Square",
+        "F" => 2,
+        "AP" => dictionary! { "N" => on },
+        "Rect" => vec![0.into(), 0.into(), 10.into(), 10.into()],
+    });
+    let link = doc.add_object(dictionary! {
+        "Type" => "Annot",
+        "Subtype" => "Link",
+        "Rect" => vec![0.into(), 0.into(), 100.into(), 20.into()],
+        "A" => dictionary! { "S" => "URI", "URI" => Object::string_literal("https://example.com") },
+    });
+    doc.get_dictionary_mut(page_id).unwrap().set(
+        "Annots",
+        vec![name.into(), check.into(), hidden.into(), link.into()],
+    );
+    let catalog_id = doc.trailer.get(b"Root").unwrap().as_reference().unwrap();
+    doc.get_dictionary_mut(catalog_id).unwrap().set(
+        "AcroForm",
+        dictionary! {
+            "Fields" => vec![name.into(), check.into()],
+            "NeedAppearances" => true,
+            "DR" => dictionary! { "Font" => dictionary! { "Helv" => helv } },
+        },
+    );
+    doc.save(path).unwrap();
+}
+
+fn form_text(doc: &Document, id: lopdf::ObjectId) -> String {
+    let form = doc.get_object(id).unwrap().as_stream().unwrap();
+    let data = form.decompressed_content().unwrap_or_else(|_| form.content.clone());
+    String::from_utf8_lossy(&data).into_owned()
+}
+
+#[test]
+fn test_flatten_bakes_fields_into_content() {
+    let dir = tmp_dir("flatten_fields");
+    let input = dir.join("in.pdf");
+    write_text_pdf(&input, &["Form"]);
+    add_form(&input);
+    let out = dir.join("out.pdf");
+
+    run_ok(&["flatten", path_str(&input), "-o", path_str(&out), "-q"]);
+
+    let doc = Document::load(&out).unwrap();
+    assert!(!doc.catalog().unwrap().has(b"AcroForm"));
+    let page_id = doc.get_pages()[&1];
+    let page = doc.get_dictionary(page_id).unwrap();
+    // only the link is left
+    let annots = page.get(b"Annots").and_then(Object::as_array).unwrap();
+    assert_eq!(annots.len(), 1);
+    let link = doc.get_dictionary(annots[0].as_reference().unwrap()).unwrap();
+    assert_eq!(link.get(b"Subtype").unwrap().as_name().unwrap(), b"Link");
+
+    let xobjects = page
+        .get(b"Resources")
+        .and_then(Object::as_dict)
+        .and_then(|r| r.get(b"XObject"))
+        .and_then(Object::as_dict)
+        .unwrap();
+    let forms: Vec<String> = xobjects
+        .iter()
+        .map(|(_, id)| form_text(&doc, id.as_reference().unwrap()))
+        .collect();
+    assert_eq!(forms.len(), 2);
+    assert!(forms.iter().any(|f| f.contains("(Ada) Tj")), "{:?}", forms);
+    assert!(forms.iter().any(|f| f.contains("0 0 10 10 re f")), "{:?}", forms);
+
+    // the checkbox appearance is scaled onto its 20x20 rect
+    let content = String::from_utf8_lossy(&doc.get_page_content(page_id).unwrap()).into_owned();
+    assert!(content.contains("q 2 0 0 2 50 250 cm /Ov1 Do Q"), "{}", content);
+    assert!(content.contains("(Form)"));
+}
@@ -0,0 +1,69 @@
+mod common;
+
+use common::{path_str, run, run_ok, tmp_dir, write_text_pdf};
+use lopdf::{dictionary, Document, Stream};
+
+#[test]
+fn test_fonts_embedding() {
+    let dir = tmp_dir("fonts");
+    let input = dir.join("in.pdf");
+    write_text_pdf(&input, &["Cover", "Body"]);
+    // page 2 also draws a form using an embedded subset TrueType font
+    let mut doc = Document::load(&input).unwrap();
+    let page_id = doc.get_pages()[&2];
+    let file = doc.add_object(Stream::new(dictionary! {}, vec![0; 16]));
+    let descriptor = doc.add_object(dictionary! {
+        "Type" => "FontDescriptor",
+        "FontName" => "ABCDEF+Body",
+        "FontFile2" => file,
+    });
+    let body = doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "TrueType",
+        "BaseFont" => "ABCDEF+Body",
+        "Encoding" => "WinAnsiEncoding",
+        "FontDescriptor" => descriptor,
+    });
+    let form = doc.add_object(Stream::new(
+        dictionary! {
+            "Type" => "XObject",
+            "Subtype" => "Form",
+            "BBox" => vec![0.into(), 0.into(), 100.into(), 20.into()],
+            "Resources" => dictionary! { "Font" => dictionary! { "F2" => body } },
+        },
+        b"BT /F2 12 Tf (x) Tj ET".to_vec(),
+    ));
+    // the page's own resources: the inherited ones plus the form
+    let parent = doc.get_dictionary(page_id).unwrap().get(b"Parent").unwrap().as_reference();
+    let parent = doc.get_dictionary(parent.unwrap()).unwrap();
+    let mut resources = parent.get(b"Resources").unwrap().as_dict().unwrap().clone();
+    resources.set("XObject", dictionary! { "Fm1" => form });
+    doc.get_dictionary_mut(page_id).unwrap().set("Resources", resources);
+    doc.save(&input).unwrap();
+
+    let table = run_ok(&["fonts", path_str(&input)]);
+    let lines: Vec<&str> = table.lines().collect();
+    assert_eq!(lines.len(), 3, "{}", table);
+    assert!(lines[0].starts_with("name"));
+    let columns = |line: &str| line.split_whitespace().map(str::to_string).collect::<Vec<_>>();
+    assert_eq!(columns(lines[1]), ["Helvetica", "Type1", "built-in", "no", "no", "1-2"]);
+    assert_eq!(
+        columns(lines[2]),
+        ["ABCDEF+Body", "TrueType", "WinAnsiEncoding", "yes", "yes", "2"]
+    );
+
+    let json = run_ok(&["fonts", path_str(&input), "--json", "--by-page"]);
+    assert!(json.starts_with(r#"[{"page":1,"fonts":[{"name":"Helvetica","type":"Type1""#));
+    let body = concat!(
+        r#"{"name":"ABCDEF+Body","type":"TrueType","encoding":"WinAnsiEncoding","#,
+        r#""embedded":true,"subset":true}]}]"#
+    );
+    assert!(json.trim_end().ends_with(body), "{}", json);
+
+    let result = run(&["fonts", path_str(&input), "--check"]);
+    assert!(!result.status.success());
+    assert!(result.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("1 font(s) not embedded"), "{}", stderr);
+    assert!(stderr.contains("Helvetica (pages 1-2)"), "{}", stderr);
+}
@@ -0,0 +1,211 @@
+mod common;
+
+use common::{path_str, run, run_ok, tmp_dir, write_text_pdf};
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream};
+
+/// add an empty form to a one-page PDF: a text field, a checkbox, a radio group
+/// of two sizes (a parent field with two widgets) and a list of colors
+fn add_form(path: &std::path::Path) {
+    let mut doc = Document::load(path).unwrap();
+    let page_id = doc.get_pages()[&1];
+    let mut square = |w: i64| {
+        doc.add_object(Stream::new(
+            dictionary! {
+                "Type" => "XObject",
+                "Subtype" => "Form",
+                "BBox" => vec![0.into(), 0.into(), w.into(), 10.into()],
+            },
+            format!("0 0 {} 10 re f", w).into_bytes(),
+        ))
+    };
+    let (on, small, large) = (square(10), square(11), square(12));
+    let widget = |rect: [i64; 4], field: Dictionary| -> Dictionary {
+        let mut widget = dictionary! {
+            "Type" => "Annot",
+            "Subtype" => "Widget",
+            "Rect" => rect.map(Object::from).to_vec(),
+            "P" => page_id,
+        };
+        for (key, value) in field.iter() {
+            widget.set(key.clone(), value.clone());
+        }
+        widget
+    };
+    let name = widget(
+        [50, 300, 250, 320],
+        dictionary! {
+            "FT" => "Tx",
+            "T" => Object::string_literal("name"),
+            "DA" => Object::string_literal("/Helv 0 Tf 0 g"),
+        },
+    );
+    let agree = widget(
+        [50, 250, 70, 270],
+        dictionary! {
+            "FT" => "Btn",
+            "T" => Object::string_literal("agree"),
+            "V" => "Off",
+            "AS" => "Off",
+            "AP" => dictionary! { "N" => dictionary! { "Yes" => on } },
+        },
+    );
+    let color = widget(
+        [50, 150, 150, 170],
+        dictionary! {
+            "FT" => "Ch",
+            "T" => Object::string_literal("color"),
+            "Opt" => vec![
+                vec![Object::string_literal("r"), Object::string_literal("Red")].into(),
+                Object::string_literal("Blue"),
+            ],
+        },
+    );
+    let (name, agree, color) =
+        (doc.add_object(name), doc.add_object(agree), doc.add_object(color));
+    let size = doc.new_object_id();
+    let mut kids = Vec::new();
+    for (state, look, x) in [("S", small, 50), ("L", large, 80)] {
+        let kid = widget(
+            [x, 200, x + 20, 220],
+            dictionary! {
+                "Parent" => size,
+                "AS" => "Off",
+                "AP" => dictionary! { "N" => Dictionary::from_iter([(state, look.into())]) },
+            },
+        );
+        kids.push(Object::from(doc.add_object(kid)));
+    }
+    let mut annots: Vec<Object> = vec![name.into(), agree.into(), color.into()];
+    annots.extend(kids.iter().cloned());
+    doc.objects.insert(
+        size,
+        dictionary! {
+            "FT" => "Btn",
+            "Ff" => 1 << 15,
+            "T" => Object::string_literal("size"),
+            "Kids" => kids,
+        }
+        .into(),
+    );
+    doc.get_dictionary_mut(page_id).unwrap().set("Annots", annots);
+    let catalog_id = doc.trailer.get(b"Root").unwrap().as_reference().unwrap();
+    doc.get_dictionary_mut(catalog_id).unwrap().set(
+        "AcroForm",
+        dictionary! { "Fields" => vec![name.into(), agree.into(), size.into(), color.into()] },
+    );
+    doc.save(path).unwrap();
+}
+
+fn field<'a>(doc: &'a Document, name: &str) -> (ObjectId, &'a Dictionary) {
+    let fields = doc
+        .catalog()
+        .unwrap()
+        .get(b"AcroForm")
+        .and_then(Object::as_dict)
+        .and_then(|f| f.get(b"Fields"))
+        .and_then(Object::as_array)
+        .unwrap();
+    fields
+        .iter()
+        .map(|f| f.as_reference().unwrap())
+        .map(|id| (id, doc.get_dictionary(id).unwrap()))
+        .find(|(_, f)| f.get(b"T").unwrap().as_str().unwrap() == name.as_bytes())
+        .unwrap()
+}
+
+#[test]
+fn test_form_fills_fields() {
+    let dir = tmp_dir("form_fill");
+    let input = dir.join("in.pdf");
+    write_text_pdf(&input, &["Application"]);
+    add_form(&input);
+
+    let listed = run_ok(&["form", path_str(&input), "--json"]);
+    assert!(listed.contains(r#""name":"size","type":"radio","value":null,"options":["S","L"]"#));
+    assert!(listed.contains(r#""name":"color","type":"choice""#), "{}", listed);
+
+    let data = dir.join("answers.json");
+    std::fs::write(&data, r#"{"name": "Ada", "agree": true, "size": "L", "color": "Red"}"#)
+        .unwrap();
+    let out = dir.join("out.pdf");
+    run_ok(&["form", path_str(&input), "--data", path_str(&data), "-o", path_str(&out), "-q"]);
+
+    let doc = Document::load(&out).unwrap();
+    let (_, name) = field(&doc, "name");
+    assert_eq!(name.get(b"V").unwrap().as_str().unwrap(), b"Ada");
+    // the text field got an appearance showing its value
+    let ap = name.get(b"AP").and_then(Object::as_dict).unwrap();
+    let form = doc.get_object(ap.get(b"N").unwrap().as_reference().unwrap()).unwrap();
+    let form = form.as_stream().unwrap();
+    let content = form.decompressed_content().unwrap_or_else(|_| form.content.clone());
+    assert!(String::from_utf8_lossy(&content).contains("(Ada) Tj"));
+    let (_, agree) = field(&doc, "agree");
+    assert_eq!(agree.get(b"AS").unwrap().as_name().unwrap(), b"Yes");
+    let (_, size) = field(&doc, "size");
+    assert_eq!(size.get(b"V").unwrap().as_name().unwrap(), b"L");
+    let states: Vec<&[u8]> = size
+        .get(b"Kids")
+        .and_then(Object::as_array)
+        .unwrap()
+        .iter()
+        .map(|kid| doc.get_dictionary(kid.as_reference().unwrap()).unwrap())
+        .map(|kid| kid.get(b"AS").unwrap().as_name().unwrap())
+        .collect();
+    assert_eq!(states, [b"Off".as_slice(), b"L"]);
+    // the option is stored by its export value
+    let (_, color) = field(&doc, "color");
+    assert_eq!(color.get(b"V").unwrap().as_str().unwrap(), b"r");
+
+    // values are checked before anything is written
+    std::fs::write(&data, r#"{"size": "M"}"#).unwrap();
+    let bad = dir.join("bad.pdf");
+    let result = run(&["form", path_str(&input), "--data", path_str(&data), "-o", path_str(&bad)]);
+    assert!(!result.status.success());
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("field 'size'"), "{}", stderr);
+    assert!(stderr.contains("expected one of S, L"), "{}", stderr);
+    assert!(!bad.exists());
+}
+
+#[test]
+fn test_form_mail_merge_flattened() {
+    let dir = tmp_dir("form_merge");
+    let input = dir.join("letter.pdf");
+    write_text_pdf(&input, &["Dear"]);
+    add_form(&input);
+    let data = dir.join("people.csv");
+    std::fs::write(&data, "name,agree\nAda,yes\n\"Hopper, Grace\",\n").unwrap();
+    let out = dir.join("letters.pdf");
+
+    run_ok(&[
+        "form",
+        path_str(&input),
+        "--data",
+        path_str(&data),
+        "-o",
+        path_str(&out),
+        "--flatten",
+        "-q",
+    ]);
+
+    assert!(!out.exists());
+    let parts = [
+        ("letters_001.pdf", "(Ada) Tj", true),
+        ("letters_002.pdf", "(Hopper, Grace) Tj", false),
+    ];
+    for (part, name, checked) in parts {
+        let doc = Document::load(dir.join(part)).unwrap();
+        assert!(!doc.catalog().unwrap().has(b"AcroForm"));
+        let page_id = doc.get_pages()[&1];
+        assert!(!doc.get_dictionary(page_id).unwrap().has(b"Annots"));
+        let forms: Vec<String> = doc
+            .objects
+            .values()
+            .filter_map(|obj| obj.as_stream().ok())
+            .map(|s| s.decompressed_content().unwrap_or_else(|_| s.content.clone()))
+            .map(|data| String::from_utf8_lossy(&data).into_owned())
+            .collect();
+        assert!(forms.iter().any(|f| f.contains(name)), "{}: {:?}", part, forms);
+        assert_eq!(forms.iter().any(|f| f.contains("0 0 10 10 re f")), checked, "{}", part);
+    }
+}
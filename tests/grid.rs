@@ -0,0 +1,49 @@
+mod common;
+
+use common::{path_str, run, run_ok, tmp_dir, write_tiny_png_rgb};
+
+#[test]
+fn test_grid_contact_sheet() {
+    let dir = tmp_dir("grid");
+    let scans = dir.join("scans");
+    std::fs::create_dir_all(&scans).unwrap();
+    for n in 1..=5 {
+        write_tiny_png_rgb(&scans.join(format!("scan_{}.png", n)));
+    }
+    let out = dir.join("sheet.png");
+    let args = ["--cols", "3", "--cell-size", "100x80", "--gap", "10", "-q"];
+    run_ok(&[&["grid", path_str(&scans), "-o", path_str(&out)][..], &args].concat());
+
+    // 3 x 2 cells of 100 x 80 with an 11px caption band, 10px apart
+    let sheet = image::open(&out).unwrap().to_rgb8();
+    assert_eq!(sheet.dimensions(), (10 + 3 * 110, 10 + 2 * (80 + 11 + 10)));
+    // the square image fills the cell's height, centered across
+    assert_ne!(sheet.get_pixel(10 + 50, 10 + 40), &image::Rgb([255, 255, 255]));
+    assert_eq!(sheet.get_pixel(10 + 5, 10 + 40), &image::Rgb([255, 255, 255]));
+}
+
+#[test]
+fn test_grid_numbered_sheets() {
+    let dir = tmp_dir("grid_sheets");
+    let mut inputs = Vec::new();
+    for n in 1..=5 {
+        let path = dir.join(format!("img{}.png", n));
+        write_tiny_png_rgb(&path);
+        inputs.push(path);
+    }
+    let out = dir.join("sheet.png");
+    let mut args: Vec<&str> = vec!["grid", "-o", path_str(&out), "--cols", "2", "--rows", "1"];
+    args.extend(inputs.iter().map(|p| path_str(p)));
+    args.extend(["--caption", "none", "--cell-size", "64", "-q"]);
+    run_ok(&args);
+
+    for n in 1..=3 {
+        assert!(dir.join(format!("sheet_{:03}.png", n)).exists());
+    }
+    assert!(!out.exists());
+    let last = image::open(dir.join("sheet_003.png")).unwrap();
+    assert_eq!((last.width(), last.height()), (8 + 72, 8 + 72));
+
+    let bad = run(&["grid", path_str(&inputs[0]), "-o", path_str(&dir.join("sheet.pdf"))]);
+    assert!(!bad.status.success());
+}
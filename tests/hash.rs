@@ -0,0 +1,42 @@
+mod common;
+
+use common::{path_str, run, run_ok, tmp_dir};
+
+#[test]
+fn hash_images() {
+    let dir = tmp_dir("hash_images");
+    let page = |noise: u8| {
+        image::GrayImage::from_fn(120, 160, move |x, y| {
+            let ink = y % 24 < 5 && x > 10 && x < 100;
+            image::Luma([if ink { noise } else { 255 - noise }])
+        })
+    };
+    let scan = dir.join("scan.png");
+    let rescan = dir.join("rescan.jpg");
+    page(0).save(&scan).unwrap();
+    page(15).save(&rescan).unwrap();
+
+    let out = run_ok(&["hash", path_str(&scan), path_str(&rescan)]);
+    let hex = |name: &str, key: &str| {
+        let at = out.find(name).unwrap();
+        let field = format!("\"{}\":\"", key);
+        let start = at + out[at..].find(&field).unwrap() + field.len();
+        u64::from_str_radix(&out[start..start + 16], 16).unwrap()
+    };
+    assert!(out.starts_with('['), "{}", out);
+    assert!(out.contains("\"page\":1"), "{}", out);
+    for key in ["phash", "dhash"] {
+        let distance = (hex("scan.png", key) ^ hex("rescan.jpg", key)).count_ones();
+        assert!(distance <= 6, "{} differs by {} bits", key, distance);
+    }
+}
+
+#[test]
+fn hash_rejects_unreadable_input() {
+    let dir = tmp_dir("hash_bad");
+    let junk = dir.join("junk.png");
+    std::fs::write(&junk, b"not an image").unwrap();
+    let out = run(&["hash", path_str(&junk)]);
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("Failed to decode"));
+}
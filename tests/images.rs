@@ -0,0 +1,46 @@
+mod common;
+
+use common::{make_pdf, path_str, run_ok, tmp_dir};
+use lopdf::{dictionary, Document, Stream};
+
+#[test]
+fn test_images_report() {
+    let dir = tmp_dir("images");
+    let input = make_pdf(&dir, "scan", 2, &["--dpi", "150"]);
+
+    let table = run_ok(&["images", path_str(&input)]);
+    let lines: Vec<&str> = table.lines().collect();
+    assert!(lines[0].starts_with("page  object  pixels"), "{}", table);
+    let columns: Vec<&str> = lines[1].split_whitespace().collect();
+    assert_eq!(columns[0], "1");
+    assert_eq!(columns[2..6], ["4x4", "FlateDecode", "DeviceRGB", "8"]);
+    assert_eq!(columns.last(), Some(&"150"));
+    assert!(lines[2].starts_with("2 "), "{}", table);
+    let summary = lines.last().unwrap();
+    assert!(summary.starts_with("2 image(s), "), "{}", table);
+    assert!(summary.ends_with(", drawn at 150 dpi"), "{}", table);
+
+    // an image nothing draws is listed without a page
+    let mut doc = Document::load(&input).unwrap();
+    doc.add_object(Stream::new(
+        dictionary! {
+            "Type" => "XObject",
+            "Subtype" => "Image",
+            "Width" => 2,
+            "Height" => 1,
+            "ColorSpace" => "DeviceGray",
+            "BitsPerComponent" => 8,
+        },
+        vec![0, 255],
+    ));
+    doc.save(&input).unwrap();
+    let json = run_ok(&["images", path_str(&input), "--json"]);
+    assert!(json.contains(r#"{"page":1,"#), "{}", json);
+    assert!(json.contains(r#""dpi_x":150,"dpi_y":150}"#), "{}", json);
+    let unused = concat!(
+        r#""width":2,"height":1,"filter":"none","color_space":"DeviceGray","bits":8,"#,
+        r#""bytes":2,"drawn_width":null,"drawn_height":null,"dpi_x":null,"dpi_y":null}]"#
+    );
+    assert!(json.trim_end().ends_with(unused), "{}", json);
+    assert_eq!(json.matches(r#""page":null"#).count(), 1, "{}", json);
+}
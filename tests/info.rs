@@ -0,0 +1,85 @@
+mod common;
+
+use common::{make_pdf, path_str, run, run_ok, tmp_dir, write_tiny_jpeg_rgb};
+
+#[test]
+fn test_info_pdf_text() {
+    let dir = tmp_dir("info_pdf_text");
+    let pdf = make_pdf(&dir, "doc", 3, &["--pagesize", "a4", "--title", "Report"]);
+
+    let out = run_ok(&["info", path_str(&pdf)]);
+    assert!(out.contains("Pages:        3"), "{}", out);
+    assert!(out.contains("Encrypted:    no"), "{}", out);
+    assert!(out.contains("Title:        Report"), "{}", out);
+    assert!(out.contains("a4 (pages 1-3)"), "{}", out);
+    assert!(out.contains("Images:       3 (3 FlateDecode)"), "{}", out);
+}
+
+#[test]
+fn test_info_json() {
+    let dir = tmp_dir("info_json");
+    let pdf = make_pdf(&dir, "doc", 2, &[]);
+    let jpg = dir.join("photo.jpg");
+    write_tiny_jpeg_rgb(&jpg);
+
+    let out = run_ok(&["info", "--json", path_str(&pdf)]);
+    assert!(out.starts_with('{'), "{}", out);
+    assert!(out.contains(r#""type":"pdf""#), "{}", out);
+    assert!(out.contains(r#""pages":2"#), "{}", out);
+    assert!(out.contains(r#""encrypted":false"#), "{}", out);
+
+    // several inputs give an array
+    let out = run_ok(&["info", "--json", path_str(&pdf), path_str(&jpg)]);
+    assert!(out.starts_with('['), "{}", out);
+    assert!(out.contains(r#""format":"JPEG""#), "{}", out);
+    assert!(out.contains(r#""width":4,"height":4"#), "{}", out);
+    assert!(out.contains(r#""color":"rgb""#), "{}", out);
+}
+
+#[test]
+fn test_info_image_text() {
+    let dir = tmp_dir("info_image_text");
+    let jpg = dir.join("photo.jpg");
+    write_tiny_jpeg_rgb(&jpg);
+
+    let out = run_ok(&["info", path_str(&jpg)]);
+    assert!(out.contains("Dimensions:   4 x 4 px"), "{}", out);
+    assert!(out.contains("Color:        rgb, 8-bit"), "{}", out);
+}
+
+#[test]
+fn test_info_rejects_unknown_file() {
+    let dir = tmp_dir("info_unknown");
+    let txt = dir.join("notes.txt");
+    std::fs::write(&txt, "hello").unwrap();
+
+    let output = run(&["info", path_str(&txt)]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Not a PDF or supported image"));
+}
+
+#[test]
+fn test_pagecount() {
+    let dir = tmp_dir("pagecount");
+    let three = make_pdf(&dir, "three", 3, &[]);
+    let one = make_pdf(&dir, "one", 1, &[]);
+
+    assert_eq!(run_ok(&["pagecount", path_str(&three)]), "3\n");
+    let out = run_ok(&["pagecount", path_str(&three), path_str(&one)]);
+    assert_eq!(out, format!("3  {}\n1  {}\n", three.display(), one.display()));
+    let out = run_ok(&["pagecount", "--json", path_str(&one)]);
+    assert_eq!(out, format!("{{\"file\":\"{}\",\"pages\":1}}\n", one.display()));
+
+    // the page tree can be read without the password
+    let locked = dir.join("locked.pdf");
+    run_ok(&[
+        "encrypt",
+        path_str(&three),
+        "-o",
+        path_str(&locked),
+        "--user-password",
+        "secret",
+        "-q",
+    ]);
+    assert_eq!(run_ok(&["pagecount", path_str(&locked)]), "3\n");
+}
@@ -0,0 +1,37 @@
+// the stop request is process-wide, so these tests get a binary of their own
+mod common;
+
+use common::{tmp_dir, write_tiny_png_rgb};
+use ovid::interrupt::{self, Interrupted};
+use ovid::merge::{merge_images, MergeOptions};
+use ovid::progress::Progress;
+
+#[test]
+fn interrupted_merge_leaves_no_output() {
+    let dir = tmp_dir("interrupt_merge");
+    let image = dir.join("a.png");
+    write_tiny_png_rgb(&image);
+    let images = vec![image; 8];
+
+    // stopped halfway, with the first part of three pages already written
+    let opts = MergeOptions::default()
+        .quiet(true)
+        .max_pages(3)
+        .progress(Progress::new(|done, _| {
+            if done == 4 {
+                interrupt::request();
+            }
+        }));
+    let err = merge_images(&images, &dir.join("out.pdf"), &opts).unwrap_err();
+    let interrupted = err.downcast_ref::<Interrupted>().unwrap();
+    assert_eq!((interrupted.done, interrupted.total), (4, 8));
+    assert!(interrupted.files.is_empty());
+    assert_eq!(err.to_string(), "interrupted (4 of 8 done)");
+
+    let opts = MergeOptions::default().quiet(true);
+    let err = merge_images(&images, &dir.join("out.epub"), &opts).unwrap_err();
+    assert!(err.is::<Interrupted>(), "{:#}", err);
+
+    let left: Vec<_> = std::fs::read_dir(&dir).unwrap().map(|e| e.unwrap().file_name()).collect();
+    assert_eq!(left, ["a.png"]);
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use common::{path_str, run, run_ok, tmp_dir, write_text_pdf};
+use lopdf::{dictionary, Document, Object, Stream};
+
+/// a page with text on no layer, on "Dimensions" (shown) and on "Notes" (hidden)
+fn write_layered_pdf(path: &std::path::Path) {
+    write_text_pdf(path, &["Plan"]);
+    let mut doc = Document::load(path).unwrap();
+    let page_id = doc.get_pages()[&1];
+    let dimensions = doc.add_object(dictionary! {
+        "Type" => "OCG",
+        "Name" => Object::string_literal("Dimensions"),
+    });
+    let notes = doc.add_object(dictionary! {
+        "Type" => "OCG",
+        "Name" => Object::string_literal("Notes"),
+    });
+    let content = concat!(
+        "BT /F1 12 Tf 72 700 Td (base) Tj ET\n",
+        "/OC /L1 BDC BT /F1 12 Tf 72 650 Td (dims) Tj ET EMC\n",
+        "/OC /L2 BDC /Span << /ActualText (x) >> BDC BT /F1 12 Tf 72 600 Td (note) Tj ET EMC EMC\n",
+    );
+    let content = doc.add_object(Stream::new(dictionary! {}, content.as_bytes().to_vec()));
+    let parent = doc.get_dictionary(page_id).unwrap().get(b"Parent").unwrap().as_reference();
+    let font = doc
+        .get_dictionary(parent.unwrap())
+        .and_then(|p| p.get(b"Resources"))
+        .and_then(Object::as_dict)
+        .and_then(|r| r.get(b"Font"))
+        .unwrap()
+        .clone();
+    let page = doc.get_dictionary_mut(page_id).unwrap();
+    page.set("Contents", content);
+    page.set(
+        "Resources",
+        dictionary! {
+            "Font" => font,
+            "Properties" => dictionary! { "L1" => dimensions, "L2" => notes },
+        },
+    );
+    let catalog_id = doc.trailer.get(b"Root").unwrap().as_reference().unwrap();
+    doc.get_dictionary_mut(catalog_id).unwrap().set(
+        "OCProperties",
+        dictionary! {
+            "OCGs" => vec![dimensions.into(), notes.into()],
+            "D" => dictionary! {
+                "OFF" => vec![notes.into()],
+                "Order" => vec![dimensions.into(), notes.into()],
+            },
+        },
+    );
+    doc.save(path).unwrap();
+}
+
+fn page_text(path: &std::path::Path) -> String {
+    let doc = Document::load(path).unwrap();
+    let content = doc.get_page_content(doc.get_pages()[&1]).unwrap();
+    String::from_utf8_lossy(&content).into_owned()
+}
+
+#[test]
+fn test_layers_list_toggle_and_remove() {
+    let dir = tmp_dir("layers");
+    let input = dir.join("plan.pdf");
+    write_layered_pdf(&input);
+
+    let listed = run_ok(&["layers", path_str(&input), "--json"]);
+    assert_eq!(
+        listed.trim(),
+        concat!(
+            r#"[{"name":"Dimensions","on":true,"locked":false},"#,
+            r#"{"name":"Notes","on":false,"locked":false}]"#
+        )
+    );
+
+    let toggled = dir.join("toggled.pdf");
+    let args = ["--on", "Notes", "--off", "Dimensions", "-o", path_str(&toggled), "-q"];
+    run_ok(&[&["layers", path_str(&input)][..], &args].concat());
+    let table = run_ok(&["layers", path_str(&toggled)]);
+    let rows: Vec<Vec<&str>> = table.lines().map(|l| l.split_whitespace().collect()).collect();
+    assert_eq!(
+        rows,
+        [["name", "default", "locked"], ["Dimensions", "off", "no"], ["Notes", "on", "no"]]
+    );
+    // switching layers leaves the content alone
+    assert_eq!(page_text(&toggled), page_text(&input));
+
+    let removed = dir.join("removed.pdf");
+    run_ok(&["layers", path_str(&input), "--remove", "Notes", "-o", path_str(&removed), "-q"]);
+    let listed = run_ok(&["layers", path_str(&removed), "--json"]);
+    assert_eq!(listed.trim(), r#"[{"name":"Dimensions","on":true,"locked":false}]"#);
+    let text = page_text(&removed);
+    assert!(text.contains("(base)") && text.contains("(dims)"), "{}", text);
+    assert!(!text.contains("(note)") && !text.contains("L2"), "{}", text);
+
+    let result = run(&["layers", path_str(&input), "--off", "Walls", "-o", path_str(&removed)]);
+    assert!(!result.status.success());
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("No layer named 'Walls' (layers: Dimensions, Notes)"), "{}", stderr);
+}
@@ -0,0 +1,141 @@
+mod common;
+
+use common::{tmp_dir, write_tiny_png_rgb};
+use ovid::interrupt::{CancelToken, Interrupted};
+use ovid::merge::{merge_image_data, merge_images, MergeOptions};
+use ovid::parse::PageSize;
+use ovid::progress::{Event, Progress};
+use ovid::task;
+use std::future::Future;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+
+#[test]
+fn merge_through_the_library() {
+    let dir = tmp_dir("library_merge");
+    let mut images = Vec::new();
+    for name in ["a.png", "b.png", "c.png"] {
+        let path = dir.join(name);
+        write_tiny_png_rgb(&path);
+        images.push(path);
+    }
+    let broken = dir.join("broken.png");
+    std::fs::write(&broken, b"not a png").unwrap();
+    images.insert(1, broken);
+
+    let opts = MergeOptions::default()
+        .quiet(true)
+        .pagesize(PageSize::A4)
+        .max_pages(2)
+        .skip_errors(true);
+    let output = dir.join("out.pdf");
+    let report = merge_images(&images, &output, &opts).unwrap();
+    assert_eq!(report.files, [dir.join("out_001.pdf"), dir.join("out_002.pdf")]);
+    assert_eq!((report.pages, report.skipped), (3, 1));
+    let doc = lopdf::Document::load(&report.files[1]).unwrap();
+    assert_eq!(doc.get_pages().len(), 1);
+}
+
+#[test]
+fn merge_images_in_memory() {
+    let dir = tmp_dir("library_merge_data");
+    let path = dir.join("a.png");
+    write_tiny_png_rgb(&path);
+    let png = std::fs::read(&path).unwrap();
+
+    let opts = MergeOptions::default().quiet(true);
+    let pdf = merge_image_data(&[png.clone(), png], &opts).unwrap();
+    let doc = lopdf::Document::load_mem(&pdf).unwrap();
+    assert_eq!(doc.get_pages().len(), 2);
+
+    let err = merge_image_data(&[b"not an image".to_vec()], &opts).unwrap_err();
+    assert_eq!(err.to_string(), "Unrecognized image format: image 1");
+}
+
+/// a minimal executor: poll, and park until woken
+fn block_on<F: Future>(future: F) -> F::Output {
+    struct Unpark(std::thread::Thread);
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+    let waker = Waker::from(Arc::new(Unpark(std::thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = std::pin::pin!(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        std::thread::park();
+    }
+}
+
+#[test]
+fn merge_as_a_task() {
+    let dir = tmp_dir("library_task");
+    let image = dir.join("a.png");
+    write_tiny_png_rgb(&image);
+    let images = vec![image; 5];
+    let output = dir.join("out.pdf");
+
+    let opts = MergeOptions::default().quiet(true);
+    let report = block_on(task::merge(images.clone(), output.clone(), opts)).unwrap();
+    assert_eq!(report.pages, 5);
+
+    // cancelled from the progress callback after two pages
+    let cancel = CancelToken::new();
+    let opts = MergeOptions::default()
+        .quiet(true)
+        .progress(Progress::new({
+            let cancel = cancel.clone();
+            move |done, _| {
+                if done == 2 {
+                    cancel.cancel();
+                }
+            }
+        }))
+        .cancel(cancel);
+    std::fs::remove_file(&output).unwrap();
+    let err = task::merge(images, output.clone(), opts).wait().unwrap_err();
+    let interrupted = err.downcast_ref::<Interrupted>().unwrap();
+    assert_eq!((interrupted.done, interrupted.total), (2, 5));
+    assert!(!output.exists());
+}
+
+#[test]
+fn merge_event_hooks() {
+    let dir = tmp_dir("library_events");
+    let good = dir.join("good.png");
+    write_tiny_png_rgb(&good);
+    let bad = dir.join("bad.png");
+    std::fs::write(&bad, b"not a png").unwrap();
+
+    let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let opts = MergeOptions::default()
+        .quiet(true)
+        .skip_errors(true)
+        .progress(Progress::events({
+            let events = events.clone();
+            move |event| {
+                let summary = match *event {
+                    Event::PageStarted { page, .. } => format!("started {}", page),
+                    Event::BytesWritten { page, bytes } => {
+                        assert!(bytes > 0);
+                        format!("wrote {}", page)
+                    }
+                    Event::Warning { page, message } => format!("warning {:?} {}", page, message),
+                    _ => return,
+                };
+                events.lock().unwrap().push(summary);
+            }
+        }));
+    merge_images(&[good.clone(), bad, good], &dir.join("out.pdf"), &opts).unwrap();
+
+    let mut events = events.lock().unwrap().clone();
+    events.sort();
+    assert_eq!(events[..3], ["started 1", "started 2", "started 3"]);
+    assert_eq!(events[3], "warning None skipped 1 of 3 image(s)");
+    assert!(events[4].starts_with("warning Some(2) skipping "), "{}", events[4]);
+    assert_eq!(events[5..], ["wrote 1", "wrote 3"]);
+}
@@ -0,0 +1,64 @@
+mod common;
+
+use common::{path_str, run_ok, tmp_dir, write_text_pdf};
+use lopdf::{dictionary, Document, Object};
+
+#[test]
+fn test_links_list_and_rewrite() {
+    let dir = tmp_dir("links");
+    let input = dir.join("in.pdf");
+    write_text_pdf(&input, &["Contents", "Chapter 1"]);
+    let mut doc = Document::load(&input).unwrap();
+    let pages = doc.get_pages();
+    let (first, second) = (pages[&1], pages[&2]);
+    // a shared action object, an inline one, a jump to page 2 and another file
+    let site = doc.add_object(dictionary! {
+        "S" => "URI",
+        "URI" => Object::string_literal("http://example.com/docs"),
+    });
+    let links = [
+        dictionary! { "A" => site },
+        dictionary! { "A" => site },
+        dictionary! { "A" => dictionary! {
+            "S" => "URI",
+            "URI" => Object::string_literal("mailto:team@example.com"),
+        } },
+        dictionary! { "Dest" => vec![second.into(), "Fit".into()] },
+        dictionary! { "A" => dictionary! {
+            "S" => "GoToR",
+            "F" => Object::string_literal("appendix.pdf"),
+            "D" => vec![0.into(), "Fit".into()],
+        } },
+    ];
+    let mut annots = Vec::new();
+    for (i, mut link) in links.into_iter().enumerate() {
+        let y = 700 - 20 * i as i64;
+        link.set("Type", "Annot");
+        link.set("Subtype", "Link");
+        link.set("Rect", vec![72.into(), y.into(), 200.into(), (y + 12).into()]);
+        annots.push(Object::from(doc.add_object(link)));
+    }
+    doc.get_dictionary_mut(first).unwrap().set("Annots", annots);
+    doc.save(&input).unwrap();
+
+    let listed = run_ok(&["links", path_str(&input)]);
+    assert_eq!(listed.lines().count(), 7, "{}", listed);
+    assert!(listed.contains(concat!(
+        r#"{"page":1,"rect":[72.0,700.0,200.0,712.0],"#,
+        r#""action":"URI","uri":"http://example.com/docs","#
+    )));
+    assert!(listed.contains(r#""action":"GoTo","uri":null,"dest_page":2,"file":null}"#));
+    assert!(listed
+        .contains(r#""action":"GoToR","uri":null,"dest_page":null,"file":"appendix.pdf""#));
+
+    let out = dir.join("out.pdf");
+    let rules = ["--replace", "http://=https://", "--replace", "mailto:team@=mailto:help@"];
+    let mut args = vec!["links", path_str(&input), "-o", path_str(&out), "-q"];
+    args.extend(rules);
+    run_ok(&args);
+    let listed = run_ok(&["links", path_str(&out)]);
+    assert_eq!(listed.matches(r#""uri":"https://example.com/docs""#).count(), 2, "{}", listed);
+    assert!(listed.contains(r#""uri":"mailto:help@example.com""#), "{}", listed);
+    assert!(listed.contains(r#""dest_page":2"#));
+    assert!(!listed.contains("http:"));
+}
@@ -0,0 +1,81 @@
+mod common;
+
+use common::{path_str, run, tmp_dir, write_tiny_png_rgb};
+
+#[test]
+fn log_file_gets_debug_events() {
+    let dir = tmp_dir("logging_file");
+    let (a, b) = (dir.join("a.png"), dir.join("b.png"));
+    write_tiny_png_rgb(&a);
+    write_tiny_png_rgb(&b);
+    let log = dir.join("run.log");
+    let out = run(&[
+        "--log-level",
+        "debug",
+        "--log-file",
+        path_str(&log),
+        "merge",
+        path_str(&a),
+        path_str(&b),
+        "-o",
+        path_str(&dir.join("out.pdf")),
+    ]);
+    assert!(out.status.success());
+
+    // stderr keeps the usual progress, without the debug events
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("[2/2]"), "{}", stderr);
+    assert!(!stderr.contains("prepared"), "{}", stderr);
+
+    let log = std::fs::read_to_string(&log).unwrap();
+    assert!(log.contains("INFO"), "{}", log);
+    assert!(log.contains("Merging 2 image(s)"), "{}", log);
+    assert!(log.contains("image{image=2}"), "{}", log);
+    assert!(log.contains("worker="), "{}", log);
+}
+
+#[test]
+fn warnings_keep_their_prefix() {
+    let dir = tmp_dir("logging_levels");
+    let good = dir.join("good.png");
+    write_tiny_png_rgb(&good);
+    let bad = dir.join("bad.png");
+    std::fs::write(&bad, b"not a png").unwrap();
+    let output = dir.join("out.pdf");
+    let args = [
+        "merge",
+        path_str(&good),
+        path_str(&bad),
+        "--skip-errors",
+        "-o",
+        path_str(&output),
+    ];
+
+    let stderr = String::from_utf8_lossy(&run(&args).stderr).into_owned();
+    assert!(stderr.contains("warning: skipping"), "{}", stderr);
+    assert!(stderr.contains("Done."), "{}", stderr);
+
+    let quiet: Vec<&str> = ["--log-level", "warn"].into_iter().chain(args).collect();
+    let stderr = String::from_utf8_lossy(&run(&quiet).stderr).into_owned();
+    assert!(stderr.contains("warning: skipping"), "{}", stderr);
+    assert!(!stderr.contains("Done."), "{}", stderr);
+}
+
+#[test]
+fn verbose_flags_add_detail() {
+    let dir = tmp_dir("logging_verbose");
+    let image = dir.join("a.png");
+    write_tiny_png_rgb(&image);
+    let output = dir.join("out.pdf");
+    let merge = ["merge", path_str(&image), "-o", path_str(&output)];
+
+    let verbose: Vec<&str> = ["-v"].into_iter().chain(merge).collect();
+    let stderr = String::from_utf8_lossy(&run(&verbose).stderr).into_owned();
+    assert!(stderr.contains("debug: image{image=1}: prepared 4x4 RGB 8-bit"), "{}", stderr);
+    assert!(!stderr.contains("timings"), "{}", stderr);
+
+    let very: Vec<&str> = ["-vv"].into_iter().chain(merge).collect();
+    let stderr = String::from_utf8_lossy(&run(&very).stderr).into_owned();
+    assert!(stderr.contains("trace: image{image=1}: timings read_ms="), "{}", stderr);
+    assert!(stderr.contains("trace: written image=1 write_ms="), "{}", stderr);
+}
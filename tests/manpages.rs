@@ -0,0 +1,19 @@
+mod common;
+
+use common::{path_str, run_ok, tmp_dir};
+
+#[test]
+fn manpages_for_every_subcommand() {
+    let dir = tmp_dir("manpages").join("man1");
+    run_ok(&["manpages", path_str(&dir)]);
+
+    let main = std::fs::read_to_string(dir.join("ovid.1")).unwrap();
+    assert!(main.contains(".TH ovid 1"), "{}", main);
+    assert!(main.contains("ovid\\-split(1)"), "{}", main);
+
+    let split = std::fs::read_to_string(dir.join("ovid-split.1")).unwrap();
+    assert!(split.contains("\\-\\-dpi"), "{}", split);
+    for name in ["merge", "completions", "manpages"] {
+        assert!(dir.join(format!("ovid-{}.1", name)).exists(), "{}", name);
+    }
+}
@@ -1,3 +1,4 @@
+use std::io::Read;
 use std::path::PathBuf;
 use std::process::Command;
 
@@ -20,6 +21,11 @@ fn tmp_dir(name: &str) -> PathBuf {
 
 /// run ovid merge and return the output PDF path
 fn run_merge(images: &[PathBuf], out_pdf: &PathBuf) {
+    run_merge_with(images, out_pdf, &[]);
+}
+
+/// run ovid merge with extra command-line flags
+fn run_merge_with(images: &[PathBuf], out_pdf: &PathBuf, args: &[&str]) {
     let mut cmd = Command::new(ovid_bin());
     cmd.arg("merge");
     for img in images {
@@ -27,6 +33,7 @@ fn run_merge(images: &[PathBuf], out_pdf: &PathBuf) {
     }
     cmd.arg("-o").arg(out_pdf);
     cmd.arg("--quiet");
+    cmd.args(args);
     let output = cmd.output().expect("failed to run ovid");
     if !output.status.success() {
         panic!(
@@ -43,7 +50,12 @@ fn get_first_page_image_dict(
 ) -> &lopdf::Dictionary {
     let pages = doc.get_pages();
     let page_id = pages.values().next().expect("no pages");
-    let page_dict = doc.get_dictionary(*page_id).unwrap();
+    page_image_dict(doc, *page_id)
+}
+
+/// get the XObject image stream dictionary for "Im0" on the given page
+fn page_image_dict(doc: &lopdf::Document, page_id: lopdf::ObjectId) -> &lopdf::Dictionary {
+    let page_dict = doc.get_dictionary(page_id).unwrap();
     let resources_ref = page_dict.get(b"Resources").unwrap();
     let (_, resources_obj) = doc.dereference(resources_ref).unwrap();
     let resources = resources_obj.as_dict().unwrap();
@@ -84,6 +96,20 @@ fn write_tiny_png_rgba(path: &PathBuf) {
     img.save(path).unwrap();
 }
 
+fn write_tiny_png_rgb16(path: &PathBuf) {
+    let img = image::ImageBuffer::<image::Rgb<u16>, _>::from_fn(4, 4, |x, y| {
+        image::Rgb([(x * 16000) as u16, (y * 16000) as u16, 51200])
+    });
+    img.save(path).unwrap();
+}
+
+fn write_tiny_png_rgba16(path: &PathBuf) {
+    let img = image::ImageBuffer::<image::Rgba<u16>, _>::from_fn(4, 4, |x, y| {
+        image::Rgba([(x * 16000) as u16, (y * 16000) as u16, 25600, 51200])
+    });
+    img.save(path).unwrap();
+}
+
 /// write a tiny palette PNG using the png crate directly
 fn write_tiny_png_palette(path: &PathBuf) {
     use std::io::BufWriter;
@@ -126,6 +152,34 @@ fn test_merge_jpeg_rgb() {
     assert_eq!(cs.as_name_str().unwrap(), "DeviceRGB");
 }
 
+#[test]
+fn test_merge_large_jpeg_passthrough() {
+    // big enough to be mapped rather than read
+    let dir = tmp_dir("jpeg_large");
+    let img = dir.join("noise.jpg");
+    let pdf = dir.join("out.pdf");
+    let noise = image::RgbImage::from_fn(400, 400, |x, y| {
+        let n = (x * 7919 + y * 104729) ^ (x * y);
+        image::Rgb([n as u8, (n >> 8) as u8, (n >> 3) as u8])
+    });
+    noise.save(&img).unwrap();
+    let jpeg = std::fs::read(&img).unwrap();
+    assert!(jpeg.len() > 64 * 1024, "{}", jpeg.len());
+    run_merge(&[img], &pdf);
+
+    let doc = lopdf::Document::load(&pdf).unwrap();
+    let embedded = doc
+        .objects
+        .values()
+        .filter_map(|obj| obj.as_stream().ok())
+        .find(|stream| {
+            let filter = stream.dict.get(b"Filter").and_then(|f| f.as_name_str());
+            filter.is_ok_and(|f| f == "DCTDecode")
+        })
+        .expect("no JPEG stream");
+    assert!(embedded.content == jpeg);
+}
+
 #[test]
 fn test_merge_png_rgb() {
     let dir = tmp_dir("png_rgb");
@@ -182,6 +236,72 @@ fn test_merge_png_rgba() {
     assert!(smask.is_ok(), "RGBA image should have SMask for alpha channel");
 }
 
+#[test]
+fn test_merge_flatten_alpha() {
+    let dir = tmp_dir("flatten_alpha");
+    let img = dir.join("test.png");
+    let pdf = dir.join("out.pdf");
+    write_tiny_png_rgba(&img);
+    run_merge_with(&[img], &pdf, &["--flatten-alpha=#336699"]);
+
+    let doc = lopdf::Document::load(&pdf).unwrap();
+    let dict = get_first_page_image_dict(&doc);
+    assert!(dict.get(b"SMask").is_err(), "flattened image should not have an SMask");
+    let cs = dict.get(b"ColorSpace").unwrap();
+    assert_eq!(cs.as_name_str().unwrap(), "DeviceRGB");
+}
+
+#[test]
+fn test_merge_flatten_gray_alpha_default_white() {
+    let dir = tmp_dir("flatten_gray_alpha");
+    let img = dir.join("test.png");
+    let pdf = dir.join("out.pdf");
+    image::GrayAlphaImage::from_pixel(4, 4, image::LumaA([10, 100]))
+        .save(&img)
+        .unwrap();
+    run_merge_with(&[img], &pdf, &["--flatten-alpha"]);
+
+    let doc = lopdf::Document::load(&pdf).unwrap();
+    let dict = get_first_page_image_dict(&doc);
+    assert!(dict.get(b"SMask").is_err());
+    let cs = dict.get(b"ColorSpace").unwrap();
+    assert_eq!(cs.as_name_str().unwrap(), "DeviceGray");
+}
+
+#[test]
+fn test_merge_gray_converts_all_inputs() {
+    let dir = tmp_dir("gray");
+    let jpg = dir.join("a.jpg");
+    let png_rgb = dir.join("b.png");
+    let png_rgba = dir.join("c.png");
+    let png_palette = dir.join("d.png");
+    let pdf = dir.join("out.pdf");
+    write_tiny_jpeg_rgb(&jpg);
+    write_tiny_png_rgb(&png_rgb);
+    write_tiny_png_rgba(&png_rgba);
+    write_tiny_png_palette(&png_palette);
+    run_merge_with(&[jpg, png_rgb, png_rgba, png_palette], &pdf, &["--gray"]);
+
+    let doc = lopdf::Document::load(&pdf).unwrap();
+    let pages = doc.get_pages();
+    assert_eq!(pages.len(), 4);
+    for (n, page_id) in pages.values().enumerate() {
+        let dict = page_image_dict(&doc, *page_id);
+        let cs = dict.get(b"ColorSpace").unwrap();
+        match cs {
+            lopdf::Object::Array(arr) => {
+                assert_eq!(arr[0].as_name_str().unwrap(), "Indexed");
+                assert_eq!(arr[1].as_name_str().unwrap(), "DeviceGray");
+            }
+            _ => assert_eq!(cs.as_name_str().unwrap(), "DeviceGray", "page {}", n + 1),
+        }
+    }
+    let first = page_image_dict(&doc, pages[&1]);
+    assert_eq!(first.get(b"Filter").unwrap().as_name_str().unwrap(), "DCTDecode");
+    let third = page_image_dict(&doc, pages[&3]);
+    assert!(third.get(b"SMask").is_ok(), "alpha should survive gray conversion");
+}
+
 #[test]
 fn test_merge_png_palette() {
     let dir = tmp_dir("png_palette");
@@ -230,6 +350,56 @@ fn test_merge_multiple_images() {
     assert_eq!(doc.get_pages().len(), 3);
 }
 
+#[test]
+fn test_merge_preserves_input_order() {
+    let dir = tmp_dir("order");
+    // distinct widths identify each input on its page
+    let images: Vec<PathBuf> = (1..=40u32)
+        .map(|w| {
+            let path = dir.join(format!("{:03}.png", w));
+            image::GrayImage::from_pixel(w, 2, image::Luma([w as u8])).save(&path).unwrap();
+            path
+        })
+        .collect();
+    let pdf = dir.join("out.pdf");
+    run_merge(&images, &pdf);
+
+    let doc = lopdf::Document::load(&pdf).unwrap();
+    let pages = doc.get_pages();
+    assert_eq!(pages.len(), 40);
+    for (n, page_id) in pages.values().enumerate() {
+        let page = doc.get_dictionary(*page_id).unwrap();
+        let media_box = page.get(b"MediaBox").unwrap().as_array().unwrap();
+        let width_pts = media_box[2].as_float().unwrap();
+        // 300 DPI default: pixels * 72 / 300
+        let expected = (n as f32 + 1.0) * 72.0 / 300.0;
+        assert!((width_pts - expected).abs() < 0.01, "page {} out of order", n + 1);
+    }
+}
+
+#[test]
+fn test_merge_failure_removes_partial_output() {
+    let dir = tmp_dir("partial_output");
+    let good = dir.join("a.png");
+    let bad = dir.join("b.png");
+    let pdf = dir.join("out.pdf");
+    write_tiny_png_rgb(&good);
+    // valid PNG signature followed by garbage
+    std::fs::write(&bad, [137, 80, 78, 71, 13, 10, 26, 10, 0, 0, 0, 1]).unwrap();
+
+    let output = Command::new(ovid_bin())
+        .arg("merge")
+        .arg(&good)
+        .arg(&bad)
+        .arg("-o")
+        .arg(&pdf)
+        .arg("--quiet")
+        .output()
+        .expect("failed to run ovid");
+    assert!(!output.status.success());
+    assert!(!pdf.exists(), "failed merge should not leave a partial PDF");
+}
+
 #[test]
 fn test_roundtrip_split_merge() {
     // pick the first available test PDF
@@ -299,3 +469,1185 @@ fn test_roundtrip_split_merge() {
         "Merged PDF should have same page count as source"
     );
 }
+
+#[test]
+fn test_merge_png_rgb16_keeps_depth() {
+    let dir = tmp_dir("png_rgb16");
+    let img = dir.join("test.png");
+    let pdf = dir.join("out.pdf");
+    write_tiny_png_rgb16(&img);
+    run_merge(&[img], &pdf);
+
+    let doc = lopdf::Document::load(&pdf).unwrap();
+    let dict = get_first_page_image_dict(&doc);
+    assert_eq!(dict.get(b"BitsPerComponent").unwrap().as_i64().unwrap(), 16);
+}
+
+#[test]
+fn test_merge_png_rgba16_keeps_depth() {
+    let dir = tmp_dir("png_rgba16");
+    let img = dir.join("test.png");
+    let pdf = dir.join("out.pdf");
+    write_tiny_png_rgba16(&img);
+    run_merge(std::slice::from_ref(&img), &pdf);
+
+    let doc = lopdf::Document::load(&pdf).unwrap();
+    let dict = get_first_page_image_dict(&doc);
+    assert_eq!(dict.get(b"BitsPerComponent").unwrap().as_i64().unwrap(), 16);
+    let smask_ref = dict.get(b"SMask").unwrap().as_reference().unwrap();
+    let smask = doc.get_object(smask_ref).unwrap().as_stream().unwrap();
+    assert_eq!(smask.dict.get(b"BitsPerComponent").unwrap().as_i64().unwrap(), 16);
+    // 4x4 pixels, 2 bytes per alpha sample
+    let mut alpha = Vec::new();
+    flate2::read::ZlibDecoder::new(&smask.content[..])
+        .read_to_end(&mut alpha)
+        .unwrap();
+    assert_eq!(alpha.len(), 32);
+
+    // gray conversion stays at 16 bits
+    let gray_pdf = dir.join("gray.pdf");
+    run_merge_with(std::slice::from_ref(&img), &gray_pdf, &["--gray"]);
+    let doc = lopdf::Document::load(&gray_pdf).unwrap();
+    let dict = get_first_page_image_dict(&doc);
+    assert_eq!(dict.get(b"BitsPerComponent").unwrap().as_i64().unwrap(), 16);
+    assert_eq!(dict.get(b"ColorSpace").unwrap().as_name_str().unwrap(), "DeviceGray");
+
+    // flattening is 8-bit
+    let flat_pdf = dir.join("flat.pdf");
+    run_merge_with(&[img], &flat_pdf, &["--flatten-alpha"]);
+    let doc = lopdf::Document::load(&flat_pdf).unwrap();
+    let dict = get_first_page_image_dict(&doc);
+    assert_eq!(dict.get(b"BitsPerComponent").unwrap().as_i64().unwrap(), 8);
+    assert!(dict.get(b"SMask").is_err());
+}
+
+#[test]
+fn test_merge_non_square_dpi() {
+    use std::io::BufWriter;
+
+    let dir = tmp_dir("non_square_dpi");
+    let img = dir.join("fax.png");
+    let pdf = dir.join("out.pdf");
+    // 204x98 DPI fax resolution, so a 204x98 pixel image is one inch square
+    let file = std::fs::File::create(&img).unwrap();
+    let mut encoder = png::Encoder::new(BufWriter::new(file), 204, 98);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_pixel_dims(Some(png::PixelDimensions {
+        xppu: 8032,
+        yppu: 3858,
+        unit: png::Unit::Meter,
+    }));
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(&vec![128u8; 204 * 98]).unwrap();
+    writer.finish().unwrap();
+    run_merge(std::slice::from_ref(&img), &pdf);
+
+    let doc = lopdf::Document::load(&pdf).unwrap();
+    let page_id = *doc.get_pages().values().next().unwrap();
+    let page = doc.get_dictionary(page_id).unwrap();
+    let media_box = page.get(b"MediaBox").unwrap().as_array().unwrap();
+    assert!((media_box[2].as_float().unwrap() - 72.0).abs() < 0.01);
+    assert!((media_box[3].as_float().unwrap() - 72.0).abs() < 0.01);
+}
+
+#[test]
+fn test_merge_position_and_scale() {
+    let dir = tmp_dir("position_scale");
+    let img = dir.join("receipt.png");
+    let pdf = dir.join("out.pdf");
+    // 300x300 px at the default 300 DPI is one inch square
+    image::GrayImage::from_pixel(300, 300, image::Luma([200]))
+        .save(&img)
+        .unwrap();
+    run_merge_with(
+        std::slice::from_ref(&img),
+        &pdf,
+        &["--pagesize", "letter", "--position", "top", "--scale", "100%"],
+    );
+
+    let doc = lopdf::Document::load(&pdf).unwrap();
+    let page_id = *doc.get_pages().values().next().unwrap();
+    let content = doc.get_and_decode_page_content(page_id).unwrap();
+    let cm = content
+        .operations
+        .iter()
+        .find(|op| op.operator == "cm")
+        .unwrap();
+    let nums: Vec<f32> = cm.operands.iter().map(|o| o.as_float().unwrap()).collect();
+    // actual size, horizontally centered, flush with the top of the 612x792 page
+    assert_eq!(nums, vec![72.0, 0.0, 0.0, 72.0, 270.0, 720.0]);
+}
+
+#[test]
+fn test_merge_watermark_text() {
+    let dir = tmp_dir("watermark_text");
+    let img = dir.join("page.png");
+    let pdf = dir.join("out.pdf");
+    write_tiny_png_rgb(&img);
+    run_merge_with(
+        std::slice::from_ref(&img),
+        &pdf,
+        &["--watermark-text", "DRAFT", "--watermark-opacity", "0.5"],
+    );
+
+    let doc = lopdf::Document::load(&pdf).unwrap();
+    let page_id = *doc.get_pages().values().next().unwrap();
+    let content = doc.get_and_decode_page_content(page_id).unwrap();
+    let tj = content
+        .operations
+        .iter()
+        .find(|op| op.operator == "Tj")
+        .unwrap();
+    assert_eq!(tj.operands[0].as_str().unwrap(), b"DRAFT");
+
+    let fonts = doc.get_page_fonts(page_id).unwrap();
+    let font = fonts.values().next().unwrap();
+    assert_eq!(font.get(b"BaseFont").unwrap().as_name_str().unwrap(), "Helvetica-Bold");
+
+    let page = doc.get_dictionary(page_id).unwrap();
+    let (_, resources) = doc.dereference(page.get(b"Resources").unwrap()).unwrap();
+    let gs = resources.as_dict().unwrap().get(b"ExtGState").unwrap();
+    let gs = gs.as_dict().unwrap().get(b"GSwm").unwrap().as_reference().unwrap();
+    let gs = doc.get_dictionary(gs).unwrap();
+    assert_eq!(gs.get(b"ca").unwrap().as_float().unwrap(), 0.5);
+}
+
+#[test]
+fn test_merge_watermark_image() {
+    let dir = tmp_dir("watermark_image");
+    let img = dir.join("page.png");
+    let logo = dir.join("logo.png");
+    let pdf = dir.join("out.pdf");
+    write_tiny_png_rgb(&img);
+    write_tiny_png_rgba(&logo);
+    run_merge_with(
+        &[img.clone(), img],
+        &pdf,
+        &["--watermark-image", logo.to_str().unwrap()],
+    );
+
+    let doc = lopdf::Document::load(&pdf).unwrap();
+    let pages: Vec<_> = doc.get_pages().values().copied().collect();
+    assert_eq!(pages.len(), 2);
+    // both pages share one watermark XObject
+    let stamp_ids: Vec<_> = pages
+        .iter()
+        .map(|&page_id| {
+            let page = doc.get_dictionary(page_id).unwrap();
+            let (_, resources) = doc.dereference(page.get(b"Resources").unwrap()).unwrap();
+            let xobjects = resources.as_dict().unwrap().get(b"XObject").unwrap();
+            xobjects.as_dict().unwrap().get(b"Imwm").unwrap().as_reference().unwrap()
+        })
+        .collect();
+    assert_eq!(stamp_ids[0], stamp_ids[1]);
+}
+
+#[test]
+fn test_merge_page_numbers() {
+    let dir = tmp_dir("page_numbers");
+    let img = dir.join("page.png");
+    let pdf = dir.join("out.pdf");
+    write_tiny_png_gray(&img);
+    run_merge_with(
+        &[img.clone(), img.clone(), img],
+        &pdf,
+        &["--page-numbers", "--pagesize", "letter"],
+    );
+
+    let doc = lopdf::Document::load(&pdf).unwrap();
+    for (n, page_id) in doc.get_pages().values().enumerate() {
+        let content = doc.get_and_decode_page_content(*page_id).unwrap();
+        let tj = content
+            .operations
+            .iter()
+            .find(|op| op.operator == "Tj")
+            .unwrap();
+        let expected = format!("Page {} of 3", n + 1);
+        assert_eq!(tj.operands[0].as_str().unwrap(), expected.as_bytes());
+        let fonts = doc.get_page_fonts(*page_id).unwrap();
+        let font = fonts.values().next().unwrap();
+        assert_eq!(font.get(b"BaseFont").unwrap().as_name_str().unwrap(), "Helvetica");
+    }
+
+    // custom format
+    let custom = dir.join("custom.pdf");
+    run_merge_with(
+        std::slice::from_ref(&dir.join("page.png")),
+        &custom,
+        &["--page-numbers=Exhibit A-{n}"],
+    );
+    let doc = lopdf::Document::load(&custom).unwrap();
+    let page_id = *doc.get_pages().values().next().unwrap();
+    let content = doc.get_and_decode_page_content(page_id).unwrap();
+    let tj = content
+        .operations
+        .iter()
+        .find(|op| op.operator == "Tj")
+        .unwrap();
+    assert_eq!(tj.operands[0].as_str().unwrap(), b"Exhibit A-1");
+}
+
+#[test]
+fn test_merge_viewer_preferences() {
+    let dir = tmp_dir("viewer_prefs");
+    let img = dir.join("page.png");
+    let pdf = dir.join("out.pdf");
+    write_tiny_png_gray(&img);
+    run_merge_with(
+        &[img.clone(), img],
+        &pdf,
+        &[
+            "--page-layout",
+            "two-up",
+            "--page-mode",
+            "outlines",
+            "--fit-window",
+            "--direction",
+            "rtl",
+        ],
+    );
+
+    let doc = lopdf::Document::load(&pdf).unwrap();
+    let catalog = doc.catalog().unwrap();
+    assert_eq!(catalog.get(b"PageLayout").unwrap().as_name_str().unwrap(), "TwoPageLeft");
+    assert_eq!(catalog.get(b"PageMode").unwrap().as_name_str().unwrap(), "UseOutlines");
+    let prefs = catalog.get(b"ViewerPreferences").unwrap().as_dict().unwrap();
+    assert!(prefs.get(b"FitWindow").unwrap().as_bool().unwrap());
+    assert_eq!(prefs.get(b"Direction").unwrap().as_name_str().unwrap(), "R2L");
+    let action = catalog.get(b"OpenAction").unwrap().as_array().unwrap();
+    let first_page = *doc.get_pages().values().next().unwrap();
+    assert_eq!(action[0].as_reference().unwrap(), first_page);
+    assert_eq!(action[1].as_name_str().unwrap(), "Fit");
+}
+
+#[test]
+fn test_merge_epub() {
+    let dir = tmp_dir("epub");
+    let png = dir.join("cover.png");
+    let jpg = dir.join("page.jpg");
+    let bmp = dir.join("page.bmp");
+    write_tiny_png_rgb(&png);
+    write_tiny_jpeg_rgb(&jpg);
+    image::RgbImage::new(6, 9).save(&bmp).unwrap();
+    let epub = dir.join("book.epub");
+    run_merge_with(
+        &[png.clone(), jpg.clone(), bmp],
+        &epub,
+        &["--direction", "rtl", "--title", "Tiny <Comic>"],
+    );
+
+    // stored ZIP entries: the mimetype comes first, right after its local header
+    let data = std::fs::read(&epub).unwrap();
+    assert_eq!(&data[30..38], b"mimetype");
+    assert_eq!(&data[38..58], b"application/epub+zip");
+    let contains = |needle: &str| data.windows(needle.len()).any(|w| w == needle.as_bytes());
+    assert!(contains(r#"<spine page-progression-direction="rtl">"#));
+    assert!(contains(r#"<meta property="rendition:layout">pre-paginated</meta>"#));
+    assert!(contains("<dc:title>Tiny &lt;Comic&gt;</dc:title>"));
+    // PNG and JPEG go in as they are, BMP as PNG
+    assert!(contains("OEBPS/images/page_0001.png"));
+    assert!(contains("OEBPS/images/page_0002.jpg"));
+    assert!(contains("OEBPS/images/page_0003.png"));
+    assert!(contains(r#"<meta name="viewport" content="width=6, height=9"/>"#));
+    let jpeg = std::fs::read(&jpg).unwrap();
+    assert!(data.windows(jpeg.len()).any(|w| w == jpeg));
+
+    // PDF-only options are refused rather than ignored
+    let output = Command::new(ovid_bin())
+        .args(["merge", "--quiet", "--pagesize", "a4", "-o"])
+        .arg(&epub)
+        .arg(&png)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--pagesize only applies to PDF"));
+}
+
+#[test]
+fn test_merge_max_pages_splits_output() {
+    let dir = tmp_dir("max_pages");
+    let img = dir.join("page.png");
+    let pdf = dir.join("out.pdf");
+    write_tiny_png_gray(&img);
+    let images = vec![img.clone(); 5];
+    run_merge_with(&images, &pdf, &["--max-pages", "2"]);
+
+    assert!(!pdf.exists());
+    let counts: Vec<usize> = ["out_001.pdf", "out_002.pdf", "out_003.pdf"]
+        .iter()
+        .map(|name| lopdf::Document::load(dir.join(name)).unwrap().get_pages().len())
+        .collect();
+    assert_eq!(counts, vec![2, 2, 1]);
+    assert!(!dir.join("out_004.pdf").exists());
+
+    // a single part keeps the plain output name
+    let single = dir.join("single.pdf");
+    run_merge_with(&images, &single, &["--max-pages", "10"]);
+    assert_eq!(lopdf::Document::load(&single).unwrap().get_pages().len(), 5);
+    assert!(!dir.join("single_001.pdf").exists());
+}
+
+#[test]
+fn test_merge_max_size_splits_output() {
+    let dir = tmp_dir("max_size");
+    let img = dir.join("noise.png");
+    let pdf = dir.join("out.pdf");
+    // incompressible pixels so every page carries ~30KB of image data
+    let mut state = 12345u32;
+    let noise = image::GrayImage::from_fn(200, 150, |_, _| {
+        state = state.wrapping_mul(1103515245).wrapping_add(12345);
+        image::Luma([(state >> 16) as u8])
+    });
+    noise.save(&img).unwrap();
+    run_merge_with(&vec![img; 4], &pdf, &["--max-size", "70KB"]);
+
+    let mut total_pages = 0;
+    for n in 1..=2 {
+        let part = dir.join(format!("out_{:03}.pdf", n));
+        assert!(std::fs::metadata(&part).unwrap().len() <= 70 * 1024);
+        total_pages += lopdf::Document::load(&part).unwrap().get_pages().len();
+    }
+    assert_eq!(total_pages, 4);
+    assert!(!dir.join("out_003.pdf").exists());
+}
+
+#[test]
+fn test_merge_interleave_duplex() {
+    let dir = tmp_dir("interleave");
+    let fronts = dir.join("fronts");
+    let backs = dir.join("backs");
+    std::fs::create_dir_all(&fronts).unwrap();
+    std::fs::create_dir_all(&backs).unwrap();
+    // page widths identify the source image
+    for (i, w) in [1u32, 2].iter().enumerate() {
+        image::GrayImage::new(*w, 4)
+            .save(fronts.join(format!("{}.png", i)))
+            .unwrap();
+    }
+    for (i, w) in [10u32, 20].iter().enumerate() {
+        image::GrayImage::new(*w, 4)
+            .save(backs.join(format!("{}.png", i)))
+            .unwrap();
+    }
+    let pdf = dir.join("out.pdf");
+    run_merge_with(
+        &[],
+        &pdf,
+        &[
+            "--interleave",
+            fronts.to_str().unwrap(),
+            backs.to_str().unwrap(),
+            "--reverse-second",
+        ],
+    );
+
+    let doc = lopdf::Document::load(&pdf).unwrap();
+    let widths: Vec<f32> = doc
+        .get_pages()
+        .values()
+        .map(|&page_id| {
+            let page = doc.get_dictionary(page_id).unwrap();
+            let media_box = page.get(b"MediaBox").unwrap().as_array().unwrap();
+            // 300 DPI default: pixels * 72 / 300
+            media_box[2].as_float().unwrap() * 300.0 / 72.0
+        })
+        .collect();
+    let widths: Vec<u32> = widths.iter().map(|w| w.round() as u32).collect();
+    assert_eq!(widths, vec![1, 20, 2, 10]);
+}
+
+/// write an 8x8 all-white Group 4 fax TIFF (one V0 code per row, then EOFB)
+fn write_tiny_tiff_g4(path: &PathBuf) -> Vec<u8> {
+    let strip = vec![0xFF, 0x00, 0x10, 0x01];
+    let entries: [(u16, u16, u32); 9] = [
+        (256, 3, 8),
+        (257, 3, 8),
+        (258, 3, 1),
+        (259, 3, 4),
+        (262, 3, 0),
+        (273, 4, 8 + 2 + 9 * 12 + 4),
+        (277, 3, 1),
+        (278, 3, 8),
+        (279, 4, strip.len() as u32),
+    ];
+    let mut buf = b"II".to_vec();
+    buf.extend_from_slice(&42u16.to_le_bytes());
+    buf.extend_from_slice(&8u32.to_le_bytes());
+    buf.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    for (tag, typ, value) in entries {
+        buf.extend_from_slice(&tag.to_le_bytes());
+        buf.extend_from_slice(&typ.to_le_bytes());
+        buf.extend_from_slice(&1u32.to_le_bytes());
+        buf.extend_from_slice(&value.to_le_bytes());
+    }
+    buf.extend_from_slice(&0u32.to_le_bytes());
+    buf.extend_from_slice(&strip);
+    std::fs::write(path, &buf).unwrap();
+    strip
+}
+
+#[test]
+fn test_merge_tiff_g4_passthrough() {
+    let dir = tmp_dir("tiff_g4");
+    let img = dir.join("fax.tiff");
+    let pdf = dir.join("out.pdf");
+    let strip = write_tiny_tiff_g4(&img);
+    run_merge(&[img], &pdf);
+
+    let doc = lopdf::Document::load(&pdf).unwrap();
+    let dict = get_first_page_image_dict(&doc);
+    assert_eq!(dict.get(b"Filter").unwrap().as_name_str().unwrap(), "CCITTFaxDecode");
+    assert_eq!(dict.get(b"BitsPerComponent").unwrap().as_i64().unwrap(), 1);
+    let parms = dict.get(b"DecodeParms").unwrap().as_dict().unwrap();
+    assert_eq!(parms.get(b"K").unwrap().as_i64().unwrap(), -1);
+    assert_eq!(parms.get(b"Columns").unwrap().as_i64().unwrap(), 8);
+
+    let page_id = *doc.get_pages().values().next().unwrap();
+    let page = doc.get_dictionary(page_id).unwrap();
+    let (_, resources) = doc.dereference(page.get(b"Resources").unwrap()).unwrap();
+    let xobjects = resources.as_dict().unwrap().get(b"XObject").unwrap();
+    let im0 = xobjects.as_dict().unwrap().get(b"Im0").unwrap().as_reference().unwrap();
+    let stream = doc.get_object(im0).unwrap().as_stream().unwrap();
+    assert_eq!(stream.content, strip);
+}
+
+/// write a PNG chunk with its CRC
+fn png_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let mut crc = flate2::Crc::new();
+    crc.update(kind);
+    crc.update(data);
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    out.extend_from_slice(&crc.sum().to_be_bytes());
+}
+
+/// write an Adam7-interlaced 1-bit grayscale PNG (checkerboard)
+fn write_interlaced_png_1bit(path: &PathBuf, size: u32) {
+    use std::io::Write;
+
+    const PASSES: [(u32, u32, u32, u32); 7] = [
+        (0, 0, 8, 8),
+        (4, 0, 8, 8),
+        (0, 4, 4, 8),
+        (2, 0, 4, 4),
+        (0, 2, 2, 4),
+        (1, 0, 2, 2),
+        (0, 1, 1, 2),
+    ];
+    let pixel = |x: u32, y: u32| ((x + y) % 2) as u8;
+    let mut raw = Vec::new();
+    for (x0, y0, dx, dy) in PASSES {
+        let xs: Vec<u32> = (x0..size).step_by(dx as usize).collect();
+        for y in (y0..size).step_by(dy as usize) {
+            if xs.is_empty() {
+                continue;
+            }
+            raw.push(0);
+            let mut row = vec![0u8; xs.len().div_ceil(8)];
+            for (i, &x) in xs.iter().enumerate() {
+                row[i / 8] |= pixel(x, y) << (7 - i % 8);
+            }
+            raw.extend_from_slice(&row);
+        }
+    }
+    let mut enc = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    enc.write_all(&raw).unwrap();
+
+    let mut png = vec![137, 80, 78, 71, 13, 10, 26, 10];
+    let mut ihdr = Vec::new();
+    ihdr.extend_from_slice(&size.to_be_bytes());
+    ihdr.extend_from_slice(&size.to_be_bytes());
+    ihdr.extend_from_slice(&[1, 0, 0, 0, 1]); // 1-bit gray, Adam7
+    png_chunk(&mut png, b"IHDR", &ihdr);
+    png_chunk(&mut png, b"IDAT", &enc.finish().unwrap());
+    png_chunk(&mut png, b"IEND", &[]);
+    std::fs::write(path, png).unwrap();
+}
+
+#[test]
+fn test_merge_interlaced_1bit_keeps_depth() {
+    let dir = tmp_dir("interlaced_1bit");
+    let img = dir.join("lineart.png");
+    let pdf = dir.join("out.pdf");
+    write_interlaced_png_1bit(&img, 13);
+    run_merge(std::slice::from_ref(&img), &pdf);
+
+    let doc = lopdf::Document::load(&pdf).unwrap();
+    let dict = get_first_page_image_dict(&doc);
+    assert_eq!(dict.get(b"BitsPerComponent").unwrap().as_i64().unwrap(), 1);
+    assert_eq!(dict.get(b"ColorSpace").unwrap().as_name_str().unwrap(), "DeviceGray");
+
+    // rows decode back to the checkerboard: filter byte + 2 packed bytes per row
+    let page_id = *doc.get_pages().values().next().unwrap();
+    let page = doc.get_dictionary(page_id).unwrap();
+    let (_, resources) = doc.dereference(page.get(b"Resources").unwrap()).unwrap();
+    let xobjects = resources.as_dict().unwrap().get(b"XObject").unwrap();
+    let im0 = xobjects.as_dict().unwrap().get(b"Im0").unwrap().as_reference().unwrap();
+    let stream = doc.get_object(im0).unwrap().as_stream().unwrap();
+    let mut rows = Vec::new();
+    flate2::read::ZlibDecoder::new(&stream.content[..])
+        .read_to_end(&mut rows)
+        .unwrap();
+    assert_eq!(rows.len(), 13 * 3);
+    assert_eq!(&rows[..3], &[0, 0b0101_0101, 0b0101_0000]);
+    assert_eq!(&rows[3..6], &[0, 0b1010_1010, 0b1010_1000]);
+}
+
+/// write a 4x4 RGB PNG carrying an iCCP chunk with the given profile bytes
+fn write_tiny_png_with_icc(path: &PathBuf, profile: &[u8]) {
+    let mut info = png::Info::with_size(4, 4);
+    info.color_type = png::ColorType::Rgb;
+    info.bit_depth = png::BitDepth::Eight;
+    info.icc_profile = Some(profile.to_vec().into());
+    let file = std::fs::File::create(path).unwrap();
+    let encoder = png::Encoder::with_info(std::io::BufWriter::new(file), info).unwrap();
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(&[90u8; 4 * 4 * 3]).unwrap();
+}
+
+/// decompressed ICC stream behind an [/ICCBased ref] color space, if any
+fn icc_stream(doc: &lopdf::Document, color_space: &lopdf::Object) -> Option<Vec<u8>> {
+    let cs = color_space.as_array().ok()?;
+    assert_eq!(cs[0].as_name_str().unwrap(), "ICCBased");
+    let stream = doc
+        .get_object(cs[1].as_reference().unwrap())
+        .unwrap()
+        .as_stream()
+        .unwrap();
+    let mut profile = Vec::new();
+    flate2::read::ZlibDecoder::new(&stream.content[..])
+        .read_to_end(&mut profile)
+        .unwrap();
+    Some(profile)
+}
+
+#[test]
+fn test_merge_icc_modes() {
+    let dir = tmp_dir("icc_modes");
+    let img = dir.join("camera.png");
+    write_tiny_png_with_icc(&img, b"exotic camera profile");
+
+    let keep = dir.join("keep.pdf");
+    run_merge(std::slice::from_ref(&img), &keep);
+    let doc = lopdf::Document::load(&keep).unwrap();
+    let cs = get_first_page_image_dict(&doc).get(b"ColorSpace").unwrap().clone();
+    assert_eq!(icc_stream(&doc, &cs).unwrap(), b"exotic camera profile");
+
+    let strip = dir.join("strip.pdf");
+    run_merge_with(std::slice::from_ref(&img), &strip, &["--icc", "strip"]);
+    let doc = lopdf::Document::load(&strip).unwrap();
+    let cs = get_first_page_image_dict(&doc).get(b"ColorSpace").unwrap().clone();
+    assert_eq!(cs.as_name_str().unwrap(), "DeviceRGB");
+
+    let srgb = dir.join("srgb.pdf");
+    run_merge_with(std::slice::from_ref(&img), &srgb, &["--icc", "srgb"]);
+    let doc = lopdf::Document::load(&srgb).unwrap();
+    let cs = get_first_page_image_dict(&doc).get(b"ColorSpace").unwrap().clone();
+    let profile = icc_stream(&doc, &cs).unwrap();
+    assert_eq!(&profile[36..40], b"acsp");
+    assert_eq!(&profile[16..20], b"RGB ");
+}
+
+/// write a 4x4 PNG tagged with a gAMA chunk, or with an sRGB chunk if `gamma`
+/// is None, and no iCCP
+fn write_tiny_png_tagged(path: &PathBuf, color: png::ColorType, gamma: Option<f32>) {
+    let file = std::fs::File::create(path).unwrap();
+    let mut encoder = png::Encoder::new(std::io::BufWriter::new(file), 4, 4);
+    encoder.set_color(color);
+    encoder.set_depth(png::BitDepth::Eight);
+    match gamma {
+        Some(gamma) => encoder.set_source_gamma(png::ScaledFloat::new(gamma)),
+        None => encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual),
+    }
+    let mut writer = encoder.write_header().unwrap();
+    writer
+        .write_image_data(&vec![90u8; 16 * color.samples()])
+        .unwrap();
+}
+
+#[test]
+fn test_merge_png_gamma_and_srgb_chunks() {
+    let dir = tmp_dir("png_gamma");
+    let gamma = dir.join("gamma.png");
+    write_tiny_png_tagged(&gamma, png::ColorType::Rgb, Some(1.0 / 1.8));
+    let srgb = dir.join("srgb.png");
+    write_tiny_png_tagged(&srgb, png::ColorType::Grayscale, None);
+
+    let pdf = dir.join("out.pdf");
+    run_merge(&[gamma.clone(), srgb], &pdf);
+    let doc = lopdf::Document::load(&pdf).unwrap();
+    let pages: Vec<_> = doc.get_pages().into_values().collect();
+    let profile = |page| {
+        let cs = page_image_dict(&doc, page).get(b"ColorSpace").unwrap();
+        icc_stream(&doc, cs).unwrap()
+    };
+    // a curve of one entry, the decoding gamma 1.8 in 8.8 fixed point
+    let gamma_profile = profile(pages[0]);
+    assert_eq!(&gamma_profile[16..20], b"RGB ");
+    assert!(gamma_profile.windows(14).any(|tag| tag == b"curv\0\0\0\0\0\0\0\x01\x01\xCD"));
+    let srgb_profile = profile(pages[1]);
+    assert_eq!(&srgb_profile[16..20], b"GRAY");
+
+    // the profiles go through --icc like embedded ones
+    let strip = dir.join("strip.pdf");
+    run_merge_with(std::slice::from_ref(&gamma), &strip, &["--icc", "strip"]);
+    let doc = lopdf::Document::load(&strip).unwrap();
+    let cs = get_first_page_image_dict(&doc).get(b"ColorSpace").unwrap();
+    assert_eq!(cs.as_name_str().unwrap(), "DeviceRGB");
+}
+
+/// write a 4x4 PNG with a tRNS chunk using the png crate directly
+fn write_tiny_png_trns(
+    path: &PathBuf,
+    color: png::ColorType,
+    palette: &[u8],
+    trns: &[u8],
+    data: &[u8],
+) {
+    let file = std::fs::File::create(path).unwrap();
+    let mut encoder = png::Encoder::new(std::io::BufWriter::new(file), 4, 4);
+    encoder.set_color(color);
+    encoder.set_depth(png::BitDepth::Eight);
+    if !palette.is_empty() {
+        encoder.set_palette(palette.to_vec());
+    }
+    encoder.set_trns(trns.to_vec());
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(data).unwrap();
+}
+
+#[test]
+fn test_merge_gif_transparency() {
+    let dir = tmp_dir("gif_transparency");
+    // left half the transparent index, right half red
+    let logo = dir.join("logo.gif");
+    image::RgbaImage::from_fn(8, 8, |x, _| match x < 4 {
+        true => image::Rgba([0, 0, 0, 0]),
+        false => image::Rgba([255, 0, 0, 255]),
+    })
+    .save(&logo)
+    .unwrap();
+    let opaque = dir.join("opaque.gif");
+    image::RgbImage::from_pixel(8, 8, image::Rgb([0, 0, 255]))
+        .save(&opaque)
+        .unwrap();
+
+    let pdf = dir.join("out.pdf");
+    run_merge(&[logo, opaque], &pdf);
+    let doc = lopdf::Document::load(&pdf).unwrap();
+    let pages: Vec<_> = doc.get_pages().into_values().collect();
+    let smask = page_image_dict(&doc, pages[0]).get(b"SMask").unwrap();
+    let stream = doc.get_object(smask.as_reference().unwrap()).unwrap();
+    let mut alpha = Vec::new();
+    flate2::read::ZlibDecoder::new(&stream.as_stream().unwrap().content[..])
+        .read_to_end(&mut alpha)
+        .unwrap();
+    assert_eq!(&alpha[..8], &[0, 0, 0, 0, 255, 255, 255, 255]);
+    // no transparent index, no soft mask
+    assert!(page_image_dict(&doc, pages[1]).get(b"SMask").is_err());
+}
+
+/// write a 4x4 animated RGB PNG with a frame of each color, the first being
+/// the still image
+fn write_apng(path: &PathBuf, colors: &[[u8; 3]]) {
+    let file = std::fs::File::create(path).unwrap();
+    let mut encoder = png::Encoder::new(std::io::BufWriter::new(file), 4, 4);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_animated(colors.len() as u32, 0).unwrap();
+    let mut writer = encoder.write_header().unwrap();
+    for color in colors {
+        writer.write_image_data(&color.repeat(16)).unwrap();
+    }
+}
+
+#[test]
+fn test_merge_apng_frames() {
+    let dir = tmp_dir("apng_frames");
+    let sticker = dir.join("sticker.png");
+    let colors = [[255, 0, 0], [0, 255, 0], [0, 0, 255]];
+    write_apng(&sticker, &colors);
+    let still = dir.join("still.png");
+    write_tiny_png_rgb(&still);
+    let inputs = [sticker, still];
+    // the inflated samples of a page's image
+    let samples = |doc: &lopdf::Document, page| {
+        let page = doc.get_dictionary(page).unwrap();
+        let (_, resources) = doc.dereference(page.get(b"Resources").unwrap()).unwrap();
+        let xobjects = resources.as_dict().unwrap().get(b"XObject").unwrap();
+        let im0 = xobjects.as_dict().unwrap().get(b"Im0").unwrap();
+        let (_, im0) = doc.dereference(im0).unwrap();
+        let mut samples = Vec::new();
+        flate2::read::ZlibDecoder::new(&im0.as_stream().unwrap().content[..])
+            .read_to_end(&mut samples)
+            .unwrap();
+        samples
+    };
+
+    // the first frame only, by default
+    let pdf = dir.join("first.pdf");
+    run_merge(&inputs, &pdf);
+    let doc = lopdf::Document::load(&pdf).unwrap();
+    let pages: Vec<_> = doc.get_pages().into_values().collect();
+    assert_eq!(pages.len(), 2);
+    // copied as is: a row filter byte, then the pixels
+    assert_eq!(samples(&doc, pages[0])[1..4], colors[0]);
+
+    let pdf = dir.join("frames.pdf");
+    run_merge_with(&inputs, &pdf, &["--apng-frames"]);
+    let doc = lopdf::Document::load(&pdf).unwrap();
+    let pages: Vec<_> = doc.get_pages().into_values().collect();
+    assert_eq!(pages.len(), 4);
+    for (&page, color) in pages.iter().zip(&colors) {
+        assert_eq!(samples(&doc, page)[..3], *color);
+    }
+}
+
+#[test]
+fn test_merge_png_trns_color_key() {
+    let dir = tmp_dir("png_trns");
+    let palette = [255, 0, 0, 0, 255, 0, 0, 0, 255, 255, 255, 255];
+    let indices: Vec<u8> = (0..16).map(|i| i % 4).collect();
+
+    // RGB color key: white is transparent
+    let rgb = dir.join("rgb.png");
+    write_tiny_png_trns(&rgb, png::ColorType::Rgb, &[], &[0, 255, 0, 255, 0, 255], &[255u8; 48]);
+    // palette with on/off alpha: entries 1 and 2 are transparent
+    let binary = dir.join("binary.png");
+    write_tiny_png_trns(&binary, png::ColorType::Indexed, &palette, &[255, 0, 0], &indices);
+    // palette with partial alpha needs a soft mask
+    let partial = dir.join("partial.png");
+    write_tiny_png_trns(&partial, png::ColorType::Indexed, &palette, &[128, 255, 0, 255], &indices);
+
+    let ints = |obj: &lopdf::Object| -> Vec<i64> {
+        obj.as_array().unwrap().iter().map(|v| v.as_i64().unwrap()).collect()
+    };
+    for (img, expected) in [(&rgb, vec![255, 255, 255, 255, 255, 255]), (&binary, vec![1, 2])] {
+        let pdf = img.with_extension("pdf");
+        run_merge(std::slice::from_ref(img), &pdf);
+        let doc = lopdf::Document::load(&pdf).unwrap();
+        let dict = get_first_page_image_dict(&doc);
+        // IDAT still passes through with the PNG predictor
+        assert!(dict.get(b"DecodeParms").is_ok());
+        assert_eq!(ints(dict.get(b"Mask").unwrap()), expected);
+        assert!(dict.get(b"SMask").is_err());
+    }
+
+    let pdf = dir.join("partial.pdf");
+    run_merge(std::slice::from_ref(&partial), &pdf);
+    let doc = lopdf::Document::load(&pdf).unwrap();
+    let dict = get_first_page_image_dict(&doc);
+    assert!(dict.get(b"DecodeParms").is_ok());
+    assert!(dict.get(b"Mask").is_err());
+    let smask = doc
+        .get_object(dict.get(b"SMask").unwrap().as_reference().unwrap())
+        .unwrap()
+        .as_stream()
+        .unwrap();
+    let mut alpha = Vec::new();
+    flate2::read::ZlibDecoder::new(&smask.content[..])
+        .read_to_end(&mut alpha)
+        .unwrap();
+    assert_eq!(&alpha[..4], &[128, 255, 0, 255]);
+    assert_eq!(alpha.len(), 16);
+}
+
+#[test]
+fn test_merge_cmyk_tiff() {
+    use tiff::encoder::{colortype, TiffEncoder};
+
+    let dir = tmp_dir("cmyk_tiff");
+    let img = dir.join("press.tiff");
+    let pdf = dir.join("out.pdf");
+    let samples: Vec<u8> = (0..4 * 4).flat_map(|i| [i as u8 * 10, 0, 200, 30]).collect();
+    let file = std::fs::File::create(&img).unwrap();
+    TiffEncoder::new(file)
+        .unwrap()
+        .write_image::<colortype::CMYK8>(4, 4, &samples)
+        .unwrap();
+    run_merge(&[img], &pdf);
+
+    let doc = lopdf::Document::load(&pdf).unwrap();
+    let dict = get_first_page_image_dict(&doc);
+    assert_eq!(dict.get(b"ColorSpace").unwrap().as_name_str().unwrap(), "DeviceCMYK");
+    assert_eq!(dict.get(b"BitsPerComponent").unwrap().as_i64().unwrap(), 8);
+    assert!(dict.get(b"SMask").is_err());
+
+    // separations are embedded unchanged
+    let stream = doc
+        .objects
+        .values()
+        .filter_map(|obj| obj.as_stream().ok())
+        .find(|stream| std::ptr::eq(&stream.dict, dict))
+        .unwrap();
+    let mut decoded = Vec::new();
+    flate2::read::ZlibDecoder::new(&stream.content[..])
+        .read_to_end(&mut decoded)
+        .unwrap();
+    assert_eq!(decoded, samples);
+}
+
+#[test]
+fn test_merge_trusts_content_over_extension() {
+    let dir = tmp_dir("sniff");
+    let jpeg = dir.join("real.jpg");
+    write_tiny_jpeg_rgb(&jpeg);
+    let mislabeled = dir.join("photo.png");
+    std::fs::rename(&jpeg, &mislabeled).unwrap();
+    let pdf = dir.join("out.pdf");
+
+    let output = Command::new(ovid_bin())
+        .arg("merge")
+        .arg(&mislabeled)
+        .arg("-o")
+        .arg(&pdf)
+        .arg("--quiet")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("contains JPEG data, not PNG"), "stderr: {stderr}");
+
+    let doc = lopdf::Document::load(&pdf).unwrap();
+    let dict = get_first_page_image_dict(&doc);
+    assert_eq!(dict.get(b"Filter").unwrap().as_name_str().unwrap(), "DCTDecode");
+}
+
+#[test]
+fn test_merge_skip_errors() {
+    let dir = tmp_dir("skip_errors");
+    let good = dir.join("a.png");
+    let bad = dir.join("b.png");
+    write_tiny_png_rgb(&good);
+    let mut truncated = std::fs::read(&good).unwrap();
+    truncated.truncate(40);
+    std::fs::write(&bad, truncated).unwrap();
+    let images = [good.clone(), bad.clone(), good.clone()];
+
+    // aborts by default
+    let pdf = dir.join("strict.pdf");
+    let output = Command::new(ovid_bin())
+        .arg("merge")
+        .args(&images)
+        .arg("-o")
+        .arg(&pdf)
+        .arg("--quiet")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(!pdf.exists());
+
+    // the rest is merged, with exit status 2 for the skipped image
+    let merge_skipping = |pdf: &PathBuf, args: &[&str]| {
+        let output = Command::new(ovid_bin())
+            .arg("merge")
+            .args(&images)
+            .arg("-o")
+            .arg(pdf)
+            .arg("--quiet")
+            .args(args)
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(2));
+    };
+    let skip = dir.join("skip.pdf");
+    merge_skipping(&skip, &["--skip-errors"]);
+    let doc = lopdf::Document::load(&skip).unwrap();
+    assert_eq!(doc.get_pages().len(), 2);
+
+    let placeholder = dir.join("placeholder.pdf");
+    merge_skipping(&placeholder, &["--skip-errors", "--placeholder"]);
+    let doc = lopdf::Document::load(&placeholder).unwrap();
+    let pages = doc.get_pages();
+    assert_eq!(pages.len(), 3);
+    let text = doc.extract_text(&[2]).unwrap();
+    assert!(text.contains("Unreadable image: b.png"), "text: {text}");
+}
+
+#[test]
+fn test_merge_max_pixels() {
+    let dir = tmp_dir("max_pixels");
+    let img = dir.join("big.png");
+    image::RgbaImage::from_pixel(100, 60, image::Rgba([10, 20, 30, 128]))
+        .save(&img)
+        .unwrap();
+    let pdf = dir.join("out.pdf");
+
+    let output = Command::new(ovid_bin())
+        .arg("merge")
+        .arg(&img)
+        .arg("-o")
+        .arg(&pdf)
+        .args(["--quiet", "--max-pixels", "5000"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("100x60 (6000 pixels)"), "stderr: {stderr}");
+
+    run_merge_with(std::slice::from_ref(&img), &pdf, &["--max-pixels", "6000"]);
+    assert!(lopdf::Document::load(&pdf).is_ok());
+}
+
+#[test]
+fn test_merge_unicode_metadata() {
+    let dir = tmp_dir("unicode_metadata");
+    let img = dir.join("a.png");
+    let pdf = dir.join("out.pdf");
+    write_tiny_png_rgb(&img);
+    run_merge_with(&[img], &pdf, &["--title", "Café 東京", "--author", "Jane Doe"]);
+
+    let doc = lopdf::Document::load(&pdf).unwrap();
+    let info_ref = doc.trailer.get(b"Info").unwrap().as_reference().unwrap();
+    let info = doc.get_dictionary(info_ref).unwrap();
+    let title = info.get(b"Title").unwrap().as_str().unwrap();
+    assert_eq!(&title[..2], &[0xFE, 0xFF]);
+    let units: Vec<u16> = title[2..]
+        .chunks_exact(2)
+        .map(|c| u16::from_be_bytes([c[0], c[1]]))
+        .collect();
+    assert_eq!(String::from_utf16(&units).unwrap(), "Café 東京");
+    assert_eq!(info.get(b"Author").unwrap().as_str().unwrap(), b"Jane Doe");
+}
+
+#[test]
+fn test_merge_dates() {
+    let dir = tmp_dir("dates");
+    let img = dir.join("a.png");
+    write_tiny_png_rgb(&img);
+    let info = |pdf: &PathBuf, key: &[u8]| -> String {
+        let doc = lopdf::Document::load(pdf).unwrap();
+        let info_ref = doc.trailer.get(b"Info").unwrap().as_reference().unwrap();
+        let info = doc.get_dictionary(info_ref).unwrap();
+        String::from_utf8(info.get(key).unwrap().as_str().unwrap().to_vec()).unwrap()
+    };
+
+    let fixed = dir.join("fixed.pdf");
+    run_merge_with(std::slice::from_ref(&img), &fixed, &["--date", "2024-05-01T09:30:00+05:30"]);
+    assert_eq!(info(&fixed, b"CreationDate"), "D:20240501093000+05'30'");
+    assert_eq!(info(&fixed, b"ModDate"), "D:20240501093000+05'30'");
+
+    // current local time always carries an explicit offset
+    let now = dir.join("now.pdf");
+    run_merge(std::slice::from_ref(&img), &now);
+    let created = info(&now, b"CreationDate");
+    assert_eq!(created.len(), "D:20240501093000+05'30'".len());
+    assert!(created.ends_with('\''), "{created}");
+    assert_eq!(info(&now, b"ModDate"), created);
+}
+
+/// tiny JPEG with an EXIF APP1 segment naming the camera maker
+fn write_tiny_jpeg_with_exif(path: &PathBuf) {
+    let plain = tiny_jpeg_bytes();
+    // IFD0 with a single inline Make = "Nik"
+    let mut exif = b"Exif\0\0II*\0".to_vec();
+    exif.extend_from_slice(&8u32.to_le_bytes());
+    exif.extend_from_slice(&1u16.to_le_bytes());
+    exif.extend_from_slice(&0x010Fu16.to_le_bytes());
+    exif.extend_from_slice(&2u16.to_le_bytes());
+    exif.extend_from_slice(&4u32.to_le_bytes());
+    exif.extend_from_slice(b"Nik\0");
+    exif.extend_from_slice(&0u32.to_le_bytes());
+    let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE1];
+    jpeg.extend_from_slice(&(exif.len() as u16 + 2).to_be_bytes());
+    jpeg.extend_from_slice(&exif);
+    jpeg.extend_from_slice(&plain[2..]);
+    std::fs::write(path, jpeg).unwrap();
+}
+
+#[test]
+fn test_merge_stats_report() {
+    let dir = tmp_dir("stats");
+    let png = dir.join("a.png");
+    let jpg = dir.join("b.jpg");
+    write_tiny_png_rgb(&png);
+    write_tiny_jpeg_rgb(&jpg);
+    let pdf = dir.join("out.pdf");
+    let merge = |stats: &[&str]| {
+        let output = Command::new(ovid_bin())
+            .arg("merge")
+            .args([&png, &jpg])
+            .arg("-o")
+            .arg(&pdf)
+            .arg("-q")
+            .args(stats)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stderr).unwrap()
+    };
+
+    let stderr = merge(&["--stats", "json"]);
+    assert!(stderr.starts_with(r#"{"pages":2,"elapsed_ms":"#), "{}", stderr);
+    // 4x4 RGB pixels each
+    assert!(stderr.contains(r#""per_page":[{"page":1,"read_ms":"#), "{}", stderr);
+    assert!(stderr.contains(r#""raw_bytes":48,"#), "{}", stderr);
+
+    let stderr = merge(&["--stats"]);
+    assert!(stderr.starts_with("2 page(s) in "), "{}", stderr);
+    assert!(stderr.contains("\n  page 2: "), "{}", stderr);
+}
+
+#[test]
+fn test_merge_low_memory() {
+    let dir = tmp_dir("low_memory");
+    let images: Vec<PathBuf> = (0..6)
+        .map(|n| {
+            let img = dir.join(format!("{}.png", n));
+            image::RgbImage::from_pixel(40 + n * 10, 30, image::Rgb([200, 100, n as u8]))
+                .save(&img)
+                .unwrap();
+            img
+        })
+        .collect();
+
+    // every image alone is over the budget, so they go one at a time, in order
+    let pdf = dir.join("out.pdf");
+    run_merge_with(&images, &pdf, &["--low-memory", "1KB"]);
+    let doc = lopdf::Document::load(&pdf).unwrap();
+    let widths: Vec<f32> = doc
+        .get_pages()
+        .values()
+        .map(|&id| {
+            let page = doc.get_dictionary(id).unwrap();
+            page.get(b"MediaBox").unwrap().as_array().unwrap()[2].as_float().unwrap()
+        })
+        .collect();
+    assert_eq!(widths.len(), 6);
+    assert!(widths.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", widths);
+}
+
+#[test]
+fn test_merge_low_memory_big_images() {
+    let dir = tmp_dir("low_memory_big");
+    // over the 4 MiB deflate strip each once decoded, so prepare workers
+    // compress strips while others wait for the budget
+    let images: Vec<PathBuf> = (0..6u32)
+        .map(|n| {
+            let img = dir.join(format!("{}.png", n));
+            image::RgbaImage::from_fn(1100, 1000, |x, y| {
+                image::Rgba([(x ^ y) as u8, (x + n) as u8, y as u8, (x % 5 * 50) as u8])
+            })
+            .save(&img)
+            .unwrap();
+            img
+        })
+        .collect();
+
+    let pdf = dir.join("out.pdf");
+    run_merge_with(&images, &pdf, &["--low-memory", "16MB"]);
+    let doc = lopdf::Document::load(&pdf).unwrap();
+    assert_eq!(doc.get_pages().len(), 6);
+    let smasks = doc
+        .objects
+        .values()
+        .filter_map(|o| o.as_stream().ok())
+        .filter(|s| s.dict.get(b"SMask").is_ok())
+        .count();
+    assert_eq!(smasks, 6);
+}
+
+#[test]
+fn test_merge_deflate_standard() {
+    let dir = tmp_dir("deflate_standard");
+    let img = dir.join("scan.png");
+    // an alpha channel, so both the color and the soft mask are deflated
+    image::RgbaImage::from_fn(300, 200, |x, y| {
+        image::Rgba([(x * y % 251) as u8, (x / 3) as u8, (y / 2) as u8, (x % 7 * 30) as u8])
+    })
+    .save(&img)
+    .unwrap();
+
+    let fast = dir.join("fast.pdf");
+    run_merge(std::slice::from_ref(&img), &fast);
+    let standard = dir.join("standard.pdf");
+    run_merge_with(std::slice::from_ref(&img), &standard, &["--deflate", "standard"]);
+    let doc = lopdf::Document::load(&standard).unwrap();
+    let dict = get_first_page_image_dict(&doc);
+    assert!(dict.get(b"SMask").is_ok());
+    let len = |path: &PathBuf| std::fs::metadata(path).unwrap().len();
+    assert!(len(&standard) < len(&fast));
+}
+
+#[test]
+fn test_merge_strip_metadata() {
+    let dir = tmp_dir("strip_metadata");
+    let img = dir.join("camera.jpg");
+    write_tiny_jpeg_with_exif(&img);
+    // a multi-picture index and an XMP packet after the EXIF
+    let mut jpeg = std::fs::read(&img).unwrap();
+    for (marker, body) in [(0xE2, &b"MPF\0II*\0"[..]), (0xE1, b"http://ns.adobe.com/xap/1.0/\0")] {
+        let mut segment = vec![0xFF, marker];
+        segment.extend_from_slice(&(body.len() as u16 + 2).to_be_bytes());
+        segment.extend_from_slice(body);
+        jpeg.splice(2..2, segment);
+    }
+    std::fs::write(&img, &jpeg).unwrap();
+
+    let pdf = dir.join("out.pdf");
+    run_merge_with(std::slice::from_ref(&img), &pdf, &["--strip-metadata"]);
+    let doc = lopdf::Document::load(&pdf).unwrap();
+    let dict = get_first_page_image_dict(&doc);
+    let data = &doc
+        .objects
+        .values()
+        .filter_map(|obj| obj.as_stream().ok())
+        .find(|stream| std::ptr::eq(&stream.dict, dict))
+        .unwrap()
+        .content;
+    for tag in [&b"Exif\0\0"[..], b"MPF\0", b"http://ns.adobe.com"] {
+        assert!(!data.windows(tag.len()).any(|w| w == tag));
+    }
+    assert!(data.len() < jpeg.len());
+    assert!(image::load_from_memory(data).is_ok());
+}
+
+fn tiny_jpeg_bytes() -> Vec<u8> {
+    let mut out = std::io::Cursor::new(Vec::new());
+    image::RgbImage::from_pixel(4, 4, image::Rgb([200, 100, 50]))
+        .write_to(&mut out, image::ImageFormat::Jpeg)
+        .unwrap();
+    out.into_inner()
+}
+
+#[test]
+fn test_merge_exif_keep_and_strip() {
+    let dir = tmp_dir("exif");
+    let img = dir.join("photo.jpg");
+    write_tiny_jpeg_with_exif(&img);
+    let image_data = |pdf: &PathBuf| -> Vec<u8> {
+        let doc = lopdf::Document::load(pdf).unwrap();
+        let dict = get_first_page_image_dict(&doc);
+        doc.objects
+            .values()
+            .filter_map(|obj| obj.as_stream().ok())
+            .find(|stream| std::ptr::eq(&stream.dict, dict))
+            .unwrap()
+            .content
+            .clone()
+    };
+    let has_exif = |data: &[u8]| data.windows(6).any(|w| w == b"Exif\0\0");
+
+    // by default the JPEG is embedded untouched, EXIF included
+    let plain = dir.join("plain.pdf");
+    run_merge(std::slice::from_ref(&img), &plain);
+    assert!(has_exif(&image_data(&plain)));
+
+    let stripped = dir.join("stripped.pdf");
+    run_merge_with(std::slice::from_ref(&img), &stripped, &["--strip-exif"]);
+    let data = image_data(&stripped);
+    assert!(!has_exif(&data));
+    assert!(image::load_from_memory(&data).is_ok());
+
+    let kept = dir.join("kept.pdf");
+    run_merge_with(std::slice::from_ref(&img), &kept, &["--keep-exif"]);
+    let doc = lopdf::Document::load(&kept).unwrap();
+    let page = doc.get_dictionary(*doc.get_pages().values().next().unwrap()).unwrap();
+    let piece = page.get(b"PieceInfo").unwrap().as_dict().unwrap();
+    let data = piece.get(b"ovid").unwrap().as_dict().unwrap();
+    assert_eq!(data.get(b"LastModified").unwrap(), page.get(b"LastModified").unwrap());
+    let private = data.get(b"Private").unwrap().as_dict().unwrap();
+    assert_eq!(private.get(b"Make").unwrap().as_str().unwrap(), b"Nik");
+}
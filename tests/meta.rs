@@ -0,0 +1,87 @@
+mod common;
+
+use common::{make_pdf, path_str, run, run_ok, tmp_dir};
+use lopdf::{Document, Object};
+
+fn info_text(doc: &Document, key: &[u8]) -> Option<String> {
+    let info = doc.trailer.get(b"Info").ok()?;
+    let (_, info) = doc.dereference(info).ok()?;
+    let value = info.as_dict().ok()?.get(key).ok()?;
+    match value {
+        Object::String(bytes, _) => Some(String::from_utf8_lossy(bytes).into_owned()),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_meta_set_appends_incremental_update() {
+    let dir = tmp_dir("meta_set");
+    let pdf = make_pdf(&dir, "doc", 2, &["--title", "Draft", "--author", "Jane"]);
+    let original = std::fs::read(&pdf).unwrap();
+
+    run_ok(&["meta", path_str(&pdf), "--set", "title=Q3 Report", "--set", "Author=", "-q"]);
+
+    // the original bytes are untouched
+    let updated = std::fs::read(&pdf).unwrap();
+    assert!(updated.len() > original.len());
+    assert_eq!(&updated[..original.len()], &original[..]);
+
+    let doc = Document::load(&pdf).unwrap();
+    assert_eq!(doc.get_pages().len(), 2);
+    assert_eq!(info_text(&doc, b"Title").as_deref(), Some("Q3 Report"));
+    assert_eq!(info_text(&doc, b"Author"), None);
+    assert!(info_text(&doc, b"ModDate").unwrap().starts_with("D:"));
+
+    let shown = run_ok(&["meta", path_str(&pdf)]);
+    assert!(shown.contains("Title:        Q3 Report"), "{}", shown);
+    assert!(!shown.contains("Jane"), "{}", shown);
+    let json = run_ok(&["meta", path_str(&pdf), "--json"]);
+    assert!(json.contains("\"Title\":\"Q3 Report\""), "{}", json);
+}
+
+#[test]
+fn test_meta_set_on_xref_stream_pdf_to_output() {
+    let dir = tmp_dir("meta_xref_stream");
+    let pdf = make_pdf(&dir, "doc", 1, &[]);
+    let compact = dir.join("compact.pdf");
+    run_ok(&["optimize", path_str(&pdf), "-o", path_str(&compact), "-q"]);
+    let original = std::fs::read(&compact).unwrap();
+    let out = dir.join("out.pdf");
+
+    run_ok(&[
+        "meta",
+        path_str(&compact),
+        "--set",
+        "subject=Quarterly",
+        "--set",
+        "CreationDate=2024-05-01T09:30:00+02:00",
+        "--set",
+        "Department=Finance",
+        "-o",
+        path_str(&out),
+        "-q",
+    ]);
+
+    // the input stays as it was
+    assert_eq!(std::fs::read(&compact).unwrap(), original);
+    let doc = Document::load(&out).unwrap();
+    assert_eq!(info_text(&doc, b"Subject").as_deref(), Some("Quarterly"));
+    assert_eq!(info_text(&doc, b"Department").as_deref(), Some("Finance"));
+    assert_eq!(
+        info_text(&doc, b"CreationDate").as_deref(),
+        Some("D:20240501093000+02'00'")
+    );
+}
+
+#[test]
+fn test_meta_rejects_bad_fields() {
+    let dir = tmp_dir("meta_bad");
+    let pdf = make_pdf(&dir, "doc", 1, &[]);
+    let original = std::fs::read(&pdf).unwrap();
+
+    for field in ["title", "bad key=x", "ModDate=yesterday"] {
+        let output = run(&["meta", path_str(&pdf), "--set", field, "-q"]);
+        assert!(!output.status.success(), "{}", field);
+    }
+    assert_eq!(std::fs::read(&pdf).unwrap(), original);
+}
@@ -0,0 +1,50 @@
+mod common;
+
+use common::{path_str, run_ok, tmp_dir, write_text_pdf};
+use lopdf::{Document, Object};
+
+#[test]
+fn test_nup_places_pages_as_forms() {
+    let dir = tmp_dir("nup_forms");
+    let input = dir.join("in.pdf");
+    // 300x400 pages
+    write_text_pdf(&input, &["One", "Two", "Three"]);
+    let out = dir.join("out.pdf");
+
+    run_ok(&["nup", path_str(&input), "-n", "2", "--border", "-o", path_str(&out), "-q"]);
+
+    let doc = Document::load(&out).unwrap();
+    let pages = doc.get_pages();
+    assert_eq!(pages.len(), 2);
+    let sheet = doc.get_dictionary(pages[&1]).unwrap();
+    // side by side on a landscape sheet of the page's size
+    let media: Vec<f32> = sheet
+        .get(b"MediaBox")
+        .and_then(Object::as_array)
+        .unwrap()
+        .iter()
+        .map(|o| o.as_float().unwrap())
+        .collect();
+    assert_eq!(media, vec![0.0, 0.0, 400.0, 300.0]);
+
+    let xobjects = sheet
+        .get(b"Resources")
+        .and_then(Object::as_dict)
+        .and_then(|r| r.get(b"XObject"))
+        .and_then(Object::as_dict)
+        .unwrap();
+    assert_eq!(xobjects.len(), 2);
+    let form = xobjects.get(b"P1").and_then(Object::as_reference).unwrap();
+    let form = doc.get_object(form).unwrap().as_stream().unwrap();
+    assert_eq!(form.dict.get(b"Subtype").unwrap().as_name().unwrap(), b"Form");
+    let data = form.decompressed_content().unwrap_or_else(|_| form.content.clone());
+    let text = String::from_utf8_lossy(&data).into_owned();
+    assert!(text.contains("(Two)"), "{}", text);
+
+    let content = doc.get_page_content(pages[&1]).unwrap();
+    let content = String::from_utf8_lossy(&content).into_owned();
+    assert!(content.contains("/P0 Do") && content.contains(" re S"), "{}", content);
+    // the odd page out gets a sheet of its own
+    let last = doc.get_page_content(pages[&2]).unwrap();
+    assert!(String::from_utf8_lossy(&last).contains("/P0 Do"));
+}
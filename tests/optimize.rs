@@ -0,0 +1,77 @@
+mod common;
+
+use common::{path_str, run_ok, tmp_dir, write_text_pdf};
+
+/// a 600x600 photo-like RGB PNG with plenty of distinct colors
+fn write_photo_png(path: &std::path::Path) {
+    let img = image::RgbImage::from_fn(600, 600, |x, y| {
+        image::Rgb([(x % 256) as u8, (y % 256) as u8, ((x * y) % 251) as u8])
+    });
+    img.save(path).unwrap();
+}
+
+fn image_streams(doc: &lopdf::Document) -> Vec<&lopdf::Stream> {
+    doc.objects
+        .values()
+        .filter_map(|o| o.as_stream().ok())
+        .filter(|s| s.dict.get(b"Subtype").and_then(|t| t.as_name()).ok() == Some(b"Image"))
+        .collect()
+}
+
+#[test]
+fn test_optimize_downsamples_and_dedupes_images() {
+    let dir = tmp_dir("optimize_images");
+    let photo = dir.join("photo.png");
+    write_photo_png(&photo);
+    let input = dir.join("in.pdf");
+    // 600 px at 300 dpi: a 2 inch page, twice with the same image
+    run_ok(&["merge", path_str(&photo), path_str(&photo), "-o", path_str(&input), "-q"]);
+    let out = dir.join("out.pdf");
+
+    run_ok(&["optimize", path_str(&input), "--dpi", "150", "-o", path_str(&out), "-q"]);
+
+    let doc = lopdf::Document::load(&out).unwrap();
+    assert_eq!(doc.get_pages().len(), 2);
+    let images = image_streams(&doc);
+    assert_eq!(images.len(), 1, "identical images should be merged");
+    let dict = &images[0].dict;
+    assert_eq!(dict.get(b"Width").unwrap().as_i64().unwrap(), 300);
+    assert_eq!(dict.get(b"Filter").unwrap().as_name().unwrap(), b"DCTDecode");
+    assert!(std::fs::metadata(&out).unwrap().len() < std::fs::metadata(&input).unwrap().len());
+}
+
+#[test]
+fn test_optimize_lossless_keeps_pixels() {
+    let dir = tmp_dir("optimize_lossless");
+    let photo = dir.join("photo.png");
+    write_photo_png(&photo);
+    let input = dir.join("in.pdf");
+    run_ok(&["merge", path_str(&photo), "-o", path_str(&input), "-q"]);
+    let out = dir.join("out.pdf");
+
+    run_ok(&["optimize", path_str(&input), "--dpi", "0", "--no-jpeg", "-o", path_str(&out), "-q"]);
+
+    let doc = lopdf::Document::load(&out).unwrap();
+    let images = image_streams(&doc);
+    assert_eq!(images.len(), 1);
+    assert_eq!(images[0].dict.get(b"Width").unwrap().as_i64().unwrap(), 600);
+    assert_eq!(images[0].dict.get(b"Filter").unwrap().as_name().unwrap(), b"FlateDecode");
+}
+
+#[test]
+fn test_optimize_uses_object_streams_and_keeps_text() {
+    let dir = tmp_dir("optimize_text");
+    let input = dir.join("in.pdf");
+    write_text_pdf(&input, &["Alpha", "Beta"]);
+    let out = dir.join("out.pdf");
+
+    run_ok(&["optimize", path_str(&input), "-o", path_str(&out), "-q"]);
+
+    let bytes = std::fs::read(&out).unwrap();
+    assert!(bytes.windows(13).any(|w| w == b"/Type /ObjStm"));
+    assert!(bytes.windows(11).any(|w| w == b"/Type /XRef"));
+    let doc = lopdf::Document::load(&out).unwrap();
+    assert_eq!(doc.get_pages().len(), 2);
+    let content = doc.get_page_content(doc.get_pages()[&2]).unwrap();
+    assert!(content.windows(6).any(|w| w == b"(Beta)"));
+}
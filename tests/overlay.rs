@@ -0,0 +1,93 @@
+mod common;
+
+use common::{path_str, run_ok, tmp_dir, write_text_pdf};
+use lopdf::{Document, Object, ObjectId};
+
+/// text of the form XObject a page places, if any
+fn overlay_text(doc: &Document, page_id: ObjectId) -> Option<String> {
+    let page = doc.get_dictionary(page_id).unwrap();
+    let resources = page.get(b"Resources").and_then(Object::as_dict).ok()?;
+    let xobjects = resources.get(b"XObject").and_then(Object::as_dict).ok()?;
+    let form_id = xobjects.get(b"Ov0").and_then(Object::as_reference).ok()?;
+    let form = doc.get_object(form_id).and_then(Object::as_stream).unwrap();
+    let data = form.decompressed_content().unwrap_or_else(|_| form.content.clone());
+    Some(String::from_utf8_lossy(&data).into_owned())
+}
+
+/// a page's content streams, in drawing order
+fn page_streams(doc: &Document, page_id: ObjectId) -> Vec<String> {
+    doc.get_page_contents(page_id)
+        .into_iter()
+        .map(|id| {
+            let stream = doc.get_object(id).and_then(Object::as_stream).unwrap();
+            let data = stream.decompressed_content().unwrap_or_else(|_| stream.content.clone());
+            String::from_utf8_lossy(&data).into_owned()
+        })
+        .collect()
+}
+
+#[test]
+fn test_overlay_under_matches_pages() {
+    let dir = tmp_dir("overlay_under");
+    let input = dir.join("in.pdf");
+    write_text_pdf(&input, &["One", "Two", "Three"]);
+    let letterhead = dir.join("letterhead.pdf");
+    write_text_pdf(&letterhead, &["First", "Rest"]);
+    let out = dir.join("out.pdf");
+
+    run_ok(&[
+        "overlay",
+        path_str(&input),
+        path_str(&letterhead),
+        "--under",
+        "--multi",
+        "-o",
+        path_str(&out),
+        "-q",
+    ]);
+    let doc = Document::load(&out).unwrap();
+    let pages: Vec<ObjectId> = doc.get_pages().into_values().collect();
+    assert_eq!(pages.len(), 3);
+    let texts: Vec<String> = pages.iter().map(|&p| overlay_text(&doc, p).unwrap()).collect();
+    assert!(texts[0].contains("(First)"));
+    assert!(texts[1].contains("(Rest)") && texts[2].contains("(Rest)"));
+
+    // drawn first, with the page content after it
+    let streams = page_streams(&doc, pages[0]);
+    assert!(streams[0].contains("/Ov0 Do"), "{:?}", streams);
+    assert!(streams[1].contains("(One)"));
+    // the letterhead's own page tree didn't come along
+    let page_count = doc
+        .objects
+        .values()
+        .filter(|o| o.type_name().is_ok_and(|t| t == "Page"))
+        .count();
+    assert_eq!(page_count, 3);
+}
+
+#[test]
+fn test_overlay_over_selected_pages() {
+    let dir = tmp_dir("overlay_over");
+    let input = dir.join("in.pdf");
+    write_text_pdf(&input, &["One", "Two"]);
+    let stamp = dir.join("stamp.pdf");
+    write_text_pdf(&stamp, &["Copy"]);
+    let out = dir.join("out.pdf");
+
+    run_ok(&[
+        "overlay",
+        path_str(&input),
+        path_str(&stamp),
+        "--pages",
+        "2",
+        "-o",
+        path_str(&out),
+        "-q",
+    ]);
+    let doc = Document::load(&out).unwrap();
+    let pages: Vec<ObjectId> = doc.get_pages().into_values().collect();
+    assert!(overlay_text(&doc, pages[0]).is_none());
+    assert!(overlay_text(&doc, pages[1]).unwrap().contains("(Copy)"));
+    let streams = page_streams(&doc, pages[1]);
+    assert!(streams.last().unwrap().contains("/Ov0 Do"), "{:?}", streams);
+}
@@ -0,0 +1,73 @@
+mod common;
+
+use common::{path_str, run, run_ok, tmp_dir, write_text_pdf};
+
+/// text of each page, in page order
+fn page_texts(path: &std::path::Path) -> Vec<String> {
+    let doc = lopdf::Document::load(path).unwrap();
+    (1..=doc.get_pages().len() as u32)
+        .map(|n| doc.extract_text(&[n]).unwrap().trim().to_string())
+        .collect()
+}
+
+#[test]
+fn test_pages_keep_and_move() {
+    let dir = tmp_dir("pages_keep_move");
+    let input = dir.join("in.pdf");
+    write_text_pdf(&input, &["P1", "P2", "P3", "P4", "P5"]);
+    let out = dir.join("out.pdf");
+
+    run_ok(&[
+        "pages",
+        path_str(&input),
+        "--keep",
+        "1,3-5",
+        "--move",
+        "3:1",
+        "-o",
+        path_str(&out),
+        "-q",
+    ]);
+
+    assert_eq!(page_texts(&out), vec!["P4", "P1", "P3", "P5"]);
+    // the dropped page's content is gone from the file
+    let doc = lopdf::Document::load(&out).unwrap();
+    let contents = doc
+        .objects
+        .values()
+        .filter_map(|o| o.as_stream().ok())
+        .filter(|s| s.content.windows(4).any(|w| w == b"(P2)"))
+        .count();
+    assert_eq!(contents, 0);
+    // inherited MediaBox was copied onto the pages
+    for &id in doc.get_pages().values() {
+        assert!(doc.get_dictionary(id).unwrap().has(b"MediaBox"));
+    }
+}
+
+#[test]
+fn test_pages_delete_and_reverse() {
+    let dir = tmp_dir("pages_delete");
+    let input = dir.join("in.pdf");
+    write_text_pdf(&input, &["P1", "P2", "P3"]);
+    let out = dir.join("out.pdf");
+
+    run_ok(&["pages", path_str(&input), "--delete", "2", "-o", path_str(&out), "-q"]);
+    assert_eq!(page_texts(&out), vec!["P1", "P3"]);
+
+    run_ok(&["pages", path_str(&input), "--keep", "3,2,1", "-o", path_str(&out), "-q"]);
+    assert_eq!(page_texts(&out), vec!["P3", "P2", "P1"]);
+}
+
+#[test]
+fn test_pages_out_of_bounds_fails() {
+    let dir = tmp_dir("pages_bounds");
+    let input = dir.join("in.pdf");
+    write_text_pdf(&input, &["P1", "P2"]);
+    let out = dir.join("out.pdf");
+
+    let output = run(&["pages", path_str(&input), "--keep", "5", "-o", path_str(&out)]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("out of bounds"));
+    assert!(!out.exists());
+}
@@ -0,0 +1,59 @@
+mod common;
+
+use common::{path_str, run_ok, tmp_dir, write_text_pdf};
+use lopdf::{Document, Object};
+
+fn page_content(doc: &Document, page: u32) -> String {
+    let content = doc.get_page_content(doc.get_pages()[&page]).unwrap();
+    String::from_utf8_lossy(&content).into_owned()
+}
+
+#[test]
+fn test_poster_tiles_page() {
+    let dir = tmp_dir("poster");
+    let input = dir.join("plan.pdf");
+    write_text_pdf(&input, &["Floor plan"]);
+    let out = dir.join("poster.pdf");
+
+    // 300 x 400 pt at 400% is 1200 x 1600: 2 x 4 landscape A4 sheets beat 3 x 3
+    // portrait ones
+    run_ok(&["poster", path_str(&input), "-o", path_str(&out), "--scale", "400%", "-q"]);
+
+    let doc = Document::load(&out).unwrap();
+    assert_eq!(doc.get_pages().len(), 8);
+    for (_, page_id) in doc.get_pages() {
+        let page = doc.get_dictionary(page_id).unwrap();
+        let media_box: Vec<f32> = page
+            .get(b"MediaBox")
+            .and_then(Object::as_array)
+            .unwrap()
+            .iter()
+            .map(|v| v.as_float().unwrap())
+            .collect();
+        assert!((media_box[2] - 841.89).abs() < 0.01 && (media_box[3] - 595.28).abs() < 0.01);
+    }
+    let first = page_content(&doc, 1);
+    assert!(first.contains("re W n"), "{}", first);
+    assert!(first.contains("4 0 0 4 "), "{}", first);
+    assert!(first.contains("(page 1: row 1 of 4, column 1 of 2) Tj"), "{}", first);
+    assert!(page_content(&doc, 8).contains("(page 1: row 4 of 4, column 2 of 2) Tj"));
+
+    // the page itself is still vector text, drawn by every sheet
+    let forms: Vec<String> = doc
+        .objects
+        .values()
+        .filter_map(|obj| obj.as_stream().ok())
+        .filter(|s| s.dict.get(b"Subtype").and_then(Object::as_name).ok() == Some(b"Form"))
+        .map(|s| s.decompressed_content().unwrap_or_else(|_| s.content.clone()))
+        .map(|data| String::from_utf8_lossy(&data).into_owned())
+        .collect();
+    assert_eq!(forms.len(), 1);
+    assert!(forms[0].contains("(Floor plan) Tj"));
+
+    let plain = dir.join("plain.pdf");
+    let args = ["--scale", "400%", "--orientation", "portrait", "--no-marks", "-q"];
+    run_ok(&[&["poster", path_str(&input), "-o", path_str(&plain)][..], &args].concat());
+    let doc = Document::load(&plain).unwrap();
+    assert_eq!(doc.get_pages().len(), 9);
+    assert!(!page_content(&doc, 1).contains("Tj"));
+}
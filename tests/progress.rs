@@ -0,0 +1,50 @@
+mod common;
+
+use common::{path_str, run, tmp_dir, write_tiny_png_rgb};
+
+#[test]
+fn merge_progress_as_json_lines() {
+    let dir = tmp_dir("progress_json");
+    let good = dir.join("good.png");
+    write_tiny_png_rgb(&good);
+    let bad = dir.join("bad.png");
+    std::fs::write(&bad, b"not a png").unwrap();
+    let output = dir.join("out.pdf");
+    let out = run(&[
+        "--progress",
+        "json",
+        "merge",
+        path_str(&good),
+        path_str(&bad),
+        path_str(&good),
+        "--skip-errors",
+        "-o",
+        path_str(&output),
+    ]);
+    assert_eq!(out.status.code(), Some(2));
+
+    // every line is an event, human progress included
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    let lines: Vec<&str> = stderr.lines().collect();
+    assert!(lines.iter().all(|l| l.starts_with("{\"event\":")), "{}", stderr);
+    assert_eq!(lines[0], r#"{"event":"start","total":3}"#);
+    assert!(lines.contains(&r#"{"event":"page_started","page":3,"total":3}"#));
+    assert!(lines.iter().any(|l| l.starts_with(r#"{"event":"bytes_written","page":1,"bytes":"#)));
+    assert!(lines.iter().any(|l| l.starts_with(r#"{"event":"page_done","page":1,"done":1,"#)));
+    assert!(lines.iter().any(|l| l.starts_with(r#"{"event":"page_failed","page":2,"#)));
+    assert!(lines.iter().any(|l| l.starts_with(r#"{"event":"log","level":"warn""#)));
+    assert_eq!(lines.last().unwrap(), &r#"{"event":"finish","saved":2,"failed":1}"#);
+}
+
+#[test]
+fn failure_as_json_line() {
+    let dir = tmp_dir("progress_json_failure");
+    let bad = dir.join("bad.png");
+    std::fs::write(&bad, b"not a png").unwrap();
+    let output = dir.join("out.pdf");
+    let out = run(&["--progress", "json", "merge", path_str(&bad), "-o", path_str(&output)]);
+    assert_eq!(out.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    let last = stderr.lines().last().unwrap();
+    assert!(last.starts_with(r#"{"event":"log","level":"error","message":"#), "{}", stderr);
+}
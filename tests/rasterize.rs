@@ -0,0 +1,47 @@
+mod common;
+
+use common::{path_str, run_ok, tmp_dir, write_text_pdf};
+use lopdf::{Document, Object};
+
+fn media_box(doc: &Document, page: u32) -> Vec<f32> {
+    let page = doc.get_dictionary(doc.get_pages()[&page]).unwrap();
+    let media_box = page.get(b"MediaBox").and_then(Object::as_array).unwrap();
+    media_box.iter().map(|v| v.as_float().unwrap()).collect()
+}
+
+#[test]
+fn test_rasterize_leaves_only_images() {
+    let dir = tmp_dir("rasterize");
+    let input = dir.join("in.pdf");
+    write_text_pdf(&input, &["Account 1234", "Landscape", "Page three"]);
+    // the second page turned landscape, to check each page keeps its own size
+    let mut doc = Document::load(&input).unwrap();
+    let second = doc.get_pages()[&2];
+    let landscape: Vec<Object> = vec![0.into(), 0.into(), 400.into(), 300.into()];
+    doc.get_dictionary_mut(second).unwrap().set("MediaBox", landscape);
+    doc.save(&input).unwrap();
+
+    let out = dir.join("out.pdf");
+    run_ok(&["rasterize", path_str(&input), "-o", path_str(&out), "--dpi", "72", "-q"]);
+
+    let mut doc = Document::load(&out).unwrap();
+    assert_eq!(doc.get_pages().len(), 3);
+    let sizes = [(300.0, 400.0), (400.0, 300.0), (300.0, 400.0)];
+    for (page, (width, height)) in (1..).zip(sizes) {
+        let media_box = media_box(&doc, page);
+        let size = (media_box[2] - media_box[0], media_box[3] - media_box[1]);
+        assert!((size.0 - width).abs() < 1.0 && (size.1 - height).abs() < 1.0, "{:?}", size);
+    }
+    let fonts = doc.objects.values().filter(|object| {
+        let dict = object.as_dict().or_else(|_| object.as_stream().map(|s| &s.dict));
+        dict.is_ok_and(|dict| dict.type_is(b"Font") || dict.has(b"FontFile2"))
+    });
+    assert_eq!(fonts.count(), 0);
+    // every object and stream, uncompressed
+    doc.decompress();
+    let mut bytes = Vec::new();
+    doc.save_to(&mut bytes).unwrap();
+    let contains = |needle: &[u8]| bytes.windows(needle.len()).any(|w| w == needle);
+    assert!(!contains(b"Account") && !contains(b"Page three"));
+    assert!(!contains(b"Tj"));
+}
@@ -0,0 +1,103 @@
+mod common;
+
+use common::{path_str, run, run_ok, tmp_dir, write_text_pdf};
+use lopdf::{dictionary, Document, Object, Stream};
+
+fn stderr_of(args: &[&str]) -> String {
+    let out = run(args);
+    assert!(!out.status.success());
+    String::from_utf8_lossy(&out.stderr).into_owned()
+}
+
+#[test]
+fn test_redact_rejects_bad_regions() {
+    let dir = tmp_dir("redact");
+    let input = dir.join("in.pdf");
+    write_text_pdf(&input, &["Account 1234", "Page two"]);
+    let out = dir.join("out.pdf");
+    let redact = |extra: &[&str]| {
+        stderr_of(&[&["redact", path_str(&input), "-o", path_str(&out)][..], extra].concat())
+    };
+
+    assert!(redact(&[]).contains("Nothing to redact"));
+    assert!(redact(&["--region", "0,0,10,10"]).contains("invalid region"));
+    assert!(redact(&["--region", "3:0,0,10,10"]).contains("out of bounds"));
+
+    let regions = dir.join("regions.json");
+    std::fs::write(&regions, r#"[{"page": 1, "rect": [0, 0, 10]}]"#).unwrap();
+    assert!(redact(&["--regions", path_str(&regions)]).contains("region 1: rect must be"));
+    std::fs::write(&regions, r#"[{"page": 0, "rect": [0, 0, 10, 10]}]"#).unwrap();
+    assert!(redact(&["--regions", path_str(&regions)]).contains("region 1: page must be"));
+    assert!(!out.exists());
+}
+
+/// give page 1 a filled text field and tag both pages, with the page's text as
+/// each element's /ActualText
+fn add_field_and_tags(path: &std::path::Path) {
+    let mut doc = Document::load(path).unwrap();
+    let pages = doc.get_pages();
+    let (first, second) = (pages[&1], pages[&2]);
+    let bbox: Vec<Object> = vec![0.into(), 0.into(), 200.into(), 20.into()];
+    let appearance = Stream::new(
+        dictionary! { "Type" => "XObject", "Subtype" => "Form", "BBox" => bbox },
+        b"BT /F1 12 Tf 2 5 Td (Secret 1234) Tj ET".to_vec(),
+    );
+    let appearance = doc.add_object(appearance);
+    let field = doc.add_object(dictionary! {
+        "Type" => "Annot",
+        "Subtype" => "Widget",
+        "FT" => "Tx",
+        "T" => Object::string_literal("pin"),
+        "V" => Object::string_literal("Secret 1234"),
+        "Rect" => vec![50.into(), 600.into(), 250.into(), 620.into()],
+        "P" => first,
+        "AP" => dictionary! { "N" => appearance },
+    });
+    doc.get_dictionary_mut(first).unwrap().set("Annots", vec![field.into()]);
+    let form = dictionary! { "Fields" => vec![field.into()] };
+    doc.catalog_mut().unwrap().set("AcroForm", form);
+
+    let root = doc.new_object_id();
+    let element = |page, text: &str| {
+        dictionary! {
+            "Type" => "StructElem",
+            "S" => "P",
+            "P" => root,
+            "Pg" => page,
+            "K" => 0,
+            "ActualText" => Object::string_literal(text),
+            "Alt" => Object::string_literal(text),
+        }
+    };
+    let kids: Vec<Object> = vec![
+        doc.add_object(element(first, "Account 1234")).into(),
+        doc.add_object(element(second, "Page two")).into(),
+    ];
+    doc.objects.insert(root, dictionary! { "Type" => "StructTreeRoot", "K" => kids }.into());
+    doc.catalog_mut().unwrap().set("StructTreeRoot", root);
+    doc.save(path).unwrap();
+}
+
+#[test]
+fn test_redact_leaves_no_trace() {
+    let dir = tmp_dir("redact_trace");
+    let input = dir.join("in.pdf");
+    write_text_pdf(&input, &["Account 1234", "Page two"]);
+    add_field_and_tags(&input);
+    let out = dir.join("out.pdf");
+    let region = ["--region", "1:0,0,300,400", "-q"];
+    run_ok(&[&["redact", path_str(&input), "-o", path_str(&out)][..], &region].concat());
+
+    let mut doc = Document::load(&out).unwrap();
+    assert_eq!(doc.get_pages().len(), 2);
+    let form = doc.catalog().unwrap().get(b"AcroForm").unwrap().as_dict().unwrap();
+    assert!(form.get(b"Fields").unwrap().as_array().unwrap().is_empty());
+    // every object and stream, uncompressed
+    doc.decompress();
+    let mut bytes = Vec::new();
+    doc.save_to(&mut bytes).unwrap();
+    let contains = |needle: &[u8]| bytes.windows(needle.len()).any(|w| w == needle);
+    assert!(!contains(b"1234"), "the redacted text survived");
+    // the untouched page keeps its text and its tags
+    assert!(contains(b"(Page two)"));
+}
@@ -0,0 +1,56 @@
+mod common;
+
+use common::{path_str, run, tmp_dir, write_text_pdf};
+use lopdf::{dictionary, Document, Object, Stream};
+use std::path::Path;
+
+/// repair `input`, returning the salvage report
+fn repair(input: &Path, output: &Path) -> String {
+    let out = run(&["repair", path_str(input), "-o", path_str(output)]);
+    let stderr = String::from_utf8_lossy(&out.stderr).into_owned();
+    assert!(out.status.success(), "{}", stderr);
+    stderr
+}
+
+#[test]
+fn test_repair_truncated_xref() {
+    let dir = tmp_dir("repair_xref");
+    let input = dir.join("in.pdf");
+    write_text_pdf(&input, &["Page one", "Page two"]);
+    // cut the file off partway through its cross-reference stream
+    let mut bytes = std::fs::read(&input).unwrap();
+    let tail = String::from_utf8_lossy(&bytes[bytes.len() - 30..]).into_owned();
+    let xref: usize = tail.split_whitespace().rev().nth(1).unwrap().parse().unwrap();
+    bytes.truncate(xref + 20);
+    std::fs::write(&input, &bytes).unwrap();
+    assert!(Document::load(&input).is_err());
+
+    let output = dir.join("out.pdf");
+    let report = repair(&input, &output);
+    assert!(report.contains("structure: damaged"), "{}", report);
+    assert!(report.contains("pages: all 2 recovered"), "{}", report);
+    assert_eq!(Document::load(&output).unwrap().get_pages().len(), 2);
+}
+
+#[test]
+fn test_repair_drops_bad_content_stream() {
+    let dir = tmp_dir("repair_content");
+    let input = dir.join("in.pdf");
+    write_text_pdf(&input, &["Page one", "Page two", "Page three"]);
+    // page 2 draws an image its resources don't have
+    let mut doc = Document::load(&input).unwrap();
+    let second = doc.get_pages()[&2];
+    let contents = Stream::new(dictionary! {}, b"q 100 0 0 100 0 0 cm /Missing Do Q".to_vec());
+    let contents = doc.add_object(contents);
+    doc.get_dictionary_mut(second).unwrap().set("Contents", Object::Reference(contents));
+    doc.save(&input).unwrap();
+
+    let output = dir.join("out.pdf");
+    let report = repair(&input, &output);
+    assert!(report.contains("structure: readable"), "{}", report);
+    assert!(report.contains("2 of 3 recovered, dropped unreadable page(s) 2"), "{}", report);
+    let doc = Document::load(&output).unwrap();
+    assert_eq!(doc.get_pages().len(), 2);
+    let last = doc.get_page_content(doc.get_pages()[&2]).unwrap();
+    assert!(String::from_utf8_lossy(&last).contains("Page three"));
+}
@@ -0,0 +1,50 @@
+mod common;
+
+use common::{path_str, run_ok, tmp_dir, write_text_pdf};
+
+fn rotations(path: &std::path::Path) -> Vec<i64> {
+    let doc = lopdf::Document::load(path).unwrap();
+    doc.get_pages()
+        .values()
+        .map(|&id| {
+            let page = doc.get_dictionary(id).unwrap();
+            page.get(b"Rotate").and_then(|r| r.as_i64()).unwrap_or(0)
+        })
+        .collect()
+}
+
+#[test]
+fn test_rotate_selected_pages() {
+    let dir = tmp_dir("rotate_selected");
+    let input = dir.join("in.pdf");
+    write_text_pdf(&input, &["P1", "P2", "P3"]);
+    let out = dir.join("out.pdf");
+
+    run_ok(&["rotate", path_str(&input), "--pages", "1,3", "-o", path_str(&out), "-q"]);
+    assert_eq!(rotations(&out), vec![90, 0, 90]);
+
+    // rotation adds to the existing one
+    let again = dir.join("again.pdf");
+    run_ok(&["rotate", path_str(&out), "--angle", "-180", "-o", path_str(&again), "-q"]);
+    assert_eq!(rotations(&again), vec![270, 180, 270]);
+
+    // content streams are untouched
+    let before = lopdf::Document::load(&input).unwrap();
+    let after = lopdf::Document::load(&again).unwrap();
+    assert_eq!(
+        before.get_page_content(before.get_pages()[&1]).unwrap(),
+        after.get_page_content(after.get_pages()[&1]).unwrap()
+    );
+}
+
+#[test]
+fn test_rotate_rejects_odd_angle() {
+    let dir = tmp_dir("rotate_odd");
+    let input = dir.join("in.pdf");
+    write_text_pdf(&input, &["P1"]);
+    let out = dir.join("out.pdf");
+
+    let output = common::run(&["rotate", path_str(&input), "-a", "45", "-o", path_str(&out)]);
+    assert!(!output.status.success());
+    assert!(!out.exists());
+}
@@ -0,0 +1,138 @@
+mod common;
+
+use common::*;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::process::{Child, Command, Stdio};
+
+/// a running `ovid serve`, killed when dropped
+struct Server {
+    child: Child,
+    port: u16,
+}
+
+impl Server {
+    fn start(args: &[&str]) -> Server {
+        let mut child = Command::new(ovid_bin())
+            .args(["serve", "--bind", "127.0.0.1:0"])
+            .args(args)
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("failed to run ovid serve");
+        let mut stderr = BufReader::new(child.stderr.take().unwrap());
+        let mut line = String::new();
+        stderr.read_line(&mut line).unwrap();
+        // keep draining, so the request log never blocks or breaks the server
+        std::thread::spawn(move || std::io::copy(&mut stderr, &mut std::io::sink()));
+        let port = line
+            .trim()
+            .rsplit(':')
+            .next()
+            .and_then(|port| port.parse().ok())
+            .unwrap_or_else(|| panic!("unexpected first line: {}", line));
+        Server { child, port }
+    }
+
+    /// send a raw request, returning the status, the head and the body
+    fn request(&self, head: &str, body: &[u8]) -> (u16, String, Vec<u8>) {
+        let mut stream = TcpStream::connect(("127.0.0.1", self.port)).unwrap();
+        stream.write_all(head.as_bytes()).unwrap();
+        stream.write_all(body).unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        let split = response
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .expect("no end of headers");
+        let head = String::from_utf8(response[..split].to_vec()).unwrap();
+        let status = head[9..12].parse().unwrap();
+        (status, head, response[split + 4..].to_vec())
+    }
+
+    fn post(&self, target: &str, files: &[(&str, Vec<u8>)]) -> (u16, String, Vec<u8>) {
+        let mut body = Vec::new();
+        for (name, data) in files {
+            body.extend_from_slice(
+                format!(
+                    "--BOUNDARY\r\nContent-Disposition: form-data; name=\"file\"; \
+                     filename=\"{}\"\r\n\r\n",
+                    name
+                )
+                .as_bytes(),
+            );
+            body.extend_from_slice(data);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(b"--BOUNDARY--\r\n");
+        let head = format!(
+            "POST {} HTTP/1.1\r\nHost: localhost\r\n\
+             Content-Type: multipart/form-data; boundary=BOUNDARY\r\n\
+             Content-Length: {}\r\n\r\n",
+            target,
+            body.len()
+        );
+        self.request(&head, &body)
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[test]
+fn serve_merges_uploaded_images() {
+    let dir = tmp_dir("serve_merge");
+    let png = dir.join("a.png");
+    let jpg = dir.join("b.jpg");
+    write_tiny_png_rgb(&png);
+    write_tiny_jpeg_rgb(&jpg);
+    let server = Server::start(&[]);
+
+    let (status, _, body) = server.request("GET /health HTTP/1.1\r\n\r\n", b"");
+    assert_eq!(status, 200);
+    assert_eq!(body, b"ok\n");
+
+    let files = [
+        ("b.jpg", std::fs::read(&jpg).unwrap()),
+        ("a.png", std::fs::read(&png).unwrap()),
+    ];
+    let (status, head, body) = server.post("/merge?title=Two+pages&pagesize=a4", &files);
+    assert_eq!(status, 200, "{}", String::from_utf8_lossy(&body));
+    assert!(head.contains("Content-Type: application/pdf"));
+    let doc = lopdf::Document::load_mem(&body).unwrap();
+    assert_eq!(doc.get_pages().len(), 2);
+    let info_ref = doc.trailer.get(b"Info").unwrap().as_reference().unwrap();
+    let title = doc.get_dictionary(info_ref).unwrap().get(b"Title").unwrap();
+    let title = title.as_str().unwrap();
+    let units: Vec<u16> = title
+        .strip_prefix(&[0xFE, 0xFF])
+        .map(|utf16| utf16.chunks_exact(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect())
+        .unwrap_or_else(|| title.iter().map(|&b| b as u16).collect());
+    assert_eq!(String::from_utf16(&units).unwrap(), "Two pages");
+}
+
+#[test]
+fn serve_enforces_request_limits() {
+    let dir = tmp_dir("serve_limits");
+    let png = dir.join("a.png");
+    write_tiny_png_rgb(&png);
+    let data = std::fs::read(&png).unwrap();
+    let server = Server::start(&["--max-upload", "2KB", "--max-pages", "1"]);
+
+    let big = "POST /merge HTTP/1.1\r\nContent-Length: 999999\r\n\r\n";
+    assert_eq!(server.request(big, b"").0, 413);
+    let two = [("a.png", data.clone()), ("b.png", data.clone())];
+    let (status, _, body) = server.post("/merge", &two);
+    assert_eq!(status, 413);
+    assert!(String::from_utf8_lossy(&body).contains("limit is 1"));
+
+    assert_eq!(server.post("/merge?dpi=10", &[("a.png", data.clone())]).0, 400);
+    assert_eq!(server.post("/merge?colour=red", &[("a.png", data.clone())]).0, 400);
+    assert_eq!(server.post("/merge", &[("a.png", b"not an image".to_vec())]).0, 422);
+    assert_eq!(server.request("GET /merge HTTP/1.1\r\n\r\n", b"").0, 405);
+    assert_eq!(server.request("GET /nope HTTP/1.1\r\n\r\n", b"").0, 404);
+    assert_eq!(server.post("/merge", &[("a.png", data)]).0, 200);
+}
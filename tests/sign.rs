@@ -0,0 +1,98 @@
+mod common;
+
+use common::{path_str, run, run_ok, tmp_dir, write_text_pdf};
+use lopdf::{Document, Object};
+
+const KEY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/signer.p12");
+
+fn sign_args<'a>(input: &'a str, extra: &[&'a str]) -> Vec<&'a str> {
+    let mut args = vec!["sign", input, "--key", KEY, "--password", "ovid-test", "-q"];
+    args.extend_from_slice(extra);
+    args
+}
+
+#[test]
+fn test_sign_and_verify() {
+    let dir = tmp_dir("sign_verify");
+    let input = dir.join("in.pdf");
+    write_text_pdf(&input, &["P1", "P2"]);
+    let signed = dir.join("signed.pdf");
+
+    run_ok(&sign_args(path_str(&input), &["-o", path_str(&signed)]));
+    let original = std::fs::read(&input).unwrap();
+    let data = std::fs::read(&signed).unwrap();
+    // an incremental update: the original bytes are untouched
+    assert!(data.starts_with(&original));
+    let doc = Document::load_mem(&data).unwrap();
+    let form = doc.catalog().unwrap().get(b"AcroForm").unwrap().as_dict().unwrap();
+    assert_eq!(form.get(b"SigFlags").unwrap().as_i64().unwrap(), 3);
+
+    let report = run_ok(&["sign", "--verify", path_str(&signed)]);
+    assert!(
+        report.contains("Signature1: valid, signed by Ovid Test Signer"),
+        "{}",
+        report
+    );
+    assert!(report.contains("(whole document)"), "{}", report);
+
+    // a second, visible signature appended in place keeps the first one valid
+    run_ok(&sign_args(
+        path_str(&signed),
+        &["--box", "36,36,236,96", "--page", "2", "--reason", "Approved"],
+    ));
+    let report = run_ok(&["sign", "--verify", path_str(&signed)]);
+    assert!(report.contains("2 signature(s)"), "{}", report);
+    assert!(report.contains("Signature1: valid"), "{}", report);
+    assert!(report.contains("the document was modified after signing"), "{}", report);
+    assert!(report.contains("Signature2: valid"), "{}", report);
+    let doc = Document::load(&signed).unwrap();
+    let page = doc.get_dictionary(doc.get_pages()[&2]).unwrap();
+    let annots = page.get(b"Annots").and_then(Object::as_array).unwrap();
+    let widget = doc.get_dictionary(annots[0].as_reference().unwrap()).unwrap();
+    assert!(widget.get(b"AP").is_ok());
+
+    let json = run_ok(&["sign", "--verify", "--json", path_str(&signed)]);
+    assert!(json.contains(r#""field":"Signature2","valid":true"#), "{}", json);
+    assert!(json.contains(r#""unsigned_bytes":0"#), "{}", json);
+
+    // bytes appended without a new signature leave every signature intact, but
+    // the file no longer verifies as a whole
+    let mut data = std::fs::read(&signed).unwrap();
+    data.extend_from_slice(b"\n% appended after signing\n");
+    let appended = dir.join("appended.pdf");
+    std::fs::write(&appended, &data).unwrap();
+    let output = run(&["sign", "--verify", path_str(&appended)]);
+    assert_eq!(output.status.code(), Some(1));
+    let report = String::from_utf8_lossy(&output.stdout);
+    assert!(report.contains("Signature2: valid"), "{}", report);
+    assert!(report.contains("modified after signing"), "{}", report);
+    assert!(report.contains("NOT COVERED: the last 27 bytes"), "{}", report);
+}
+
+#[test]
+fn test_verify_detects_changes() {
+    let dir = tmp_dir("sign_tamper");
+    let input = dir.join("in.pdf");
+    write_text_pdf(&input, &["Pay 100"]);
+    let signed = dir.join("signed.pdf");
+    run_ok(&sign_args(path_str(&input), &["-o", path_str(&signed)]));
+
+    let mut data = std::fs::read(&signed).unwrap();
+    let at = data.windows(7).position(|w| w == b"Pay 100").unwrap();
+    data[at + 4] = b'9';
+    let tampered = dir.join("tampered.pdf");
+    std::fs::write(&tampered, &data).unwrap();
+    let output = run(&["sign", "--verify", path_str(&tampered)]);
+    assert_eq!(output.status.code(), Some(1));
+    let report = String::from_utf8_lossy(&output.stdout);
+    assert!(report.contains("INVALID: Document was changed after signing"), "{}", report);
+
+    // unsigned files don't pass either
+    let output = run(&["sign", "--verify", path_str(&input)]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("no signatures"));
+
+    let output = run(&["sign", path_str(&input), "--key", KEY, "--password", "nope"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Wrong password"));
+}
@@ -0,0 +1,85 @@
+mod common;
+
+use common::{path_str, run, run_ok, tmp_dir, write_text_pdf};
+use lopdf::{dictionary, Document, Object, Stream};
+use std::path::Path;
+
+/// the pages a split rendered, from its progress events
+fn pages_rendered(args: &[&str]) -> Vec<usize> {
+    let out = run(&[&["--progress", "json", "split"][..], args].concat());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(out.status.success(), "{}", stderr);
+    stderr
+        .lines()
+        .filter_map(|line| line.strip_prefix(r#"{"event":"page_started","page":"#))
+        .map(|rest| rest.split(',').next().unwrap().parse().unwrap())
+        .collect()
+}
+
+#[test]
+fn test_split_cache_renders_only_changed_pages() {
+    let dir = tmp_dir("split_cache");
+    let input = dir.join("in.pdf");
+    write_text_pdf(&input, &["Page one", "Page two", "Page three"]);
+    let (pages, cache) = (dir.join("pages"), dir.join("cache"));
+    let args = [path_str(&input), "-o", path_str(&pages), "--cache", path_str(&cache)];
+
+    let mut first = pages_rendered(&args);
+    first.sort();
+    assert_eq!(first, vec![1, 2, 3]);
+    assert!(pages_rendered(&args).is_empty());
+
+    // page 2 says something else
+    let mut doc = Document::load(&input).unwrap();
+    let second = doc.get_pages()[&2];
+    let contents = Stream::new(dictionary! {}, b"BT /F1 24 Tf 72 700 Td (Edited) Tj ET".to_vec());
+    let contents = doc.add_object(contents);
+    doc.get_dictionary_mut(second).unwrap().set("Contents", Object::Reference(contents));
+    doc.save(&input).unwrap();
+    assert_eq!(pages_rendered(&args), vec![2]);
+
+    // the images are all there, and a split without the cache agrees with them
+    let fresh = dir.join("fresh");
+    run_ok(&["split", path_str(&input), "-o", path_str(&fresh), "-q"]);
+    for entry in std::fs::read_dir(&fresh).unwrap() {
+        let path = entry.unwrap().path();
+        let cached = pages.join(path.file_name().unwrap());
+        assert_eq!(std::fs::read(&path).unwrap(), std::fs::read(cached).unwrap());
+    }
+}
+
+/// turn `path` into a dynamic XFA form, its pages the placeholder
+fn make_dynamic_xfa(path: &Path) {
+    let mut doc = Document::load(path).unwrap();
+    let template = doc.add_object(Stream::new(dictionary! {}, b"<template/>".to_vec()));
+    let form = dictionary! {
+        "Fields" => Vec::<Object>::new(),
+        "XFA" => vec![Object::string_literal("template"), template.into()],
+    };
+    let catalog = doc.catalog_mut().unwrap();
+    catalog.set("AcroForm", form);
+    catalog.set("NeedsRendering", true);
+    doc.save(path).unwrap();
+}
+
+#[test]
+fn test_split_refuses_dynamic_xfa() {
+    let dir = tmp_dir("split_xfa");
+    let input = dir.join("form.pdf");
+    write_text_pdf(&input, &["Please wait..."]);
+    make_dynamic_xfa(&input);
+    let pages = dir.join("pages");
+
+    let out = run(&["split", path_str(&input), "-o", path_str(&pages)]);
+    assert_eq!(out.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("is a dynamic XFA form"), "{}", stderr);
+    assert!(stderr.contains("--force-static"), "{}", stderr);
+    assert!(!pages.exists());
+
+    let out = run(&["split", path_str(&input), "-o", path_str(&pages), "--force-static"]);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(out.status.success(), "{}", stderr);
+    assert!(stderr.contains("rendering its placeholder pages"), "{}", stderr);
+    assert_eq!(std::fs::read_dir(&pages).unwrap().count(), 1);
+}
@@ -0,0 +1,69 @@
+mod common;
+
+use common::{path_str, run, run_ok, tmp_dir, write_text_pdf};
+use lopdf::{Document, Object};
+
+/// text drawn by the stamp forms on a page
+fn stamped_text(doc: &Document, page: u32) -> String {
+    let page = doc.get_dictionary(doc.get_pages()[&page]).unwrap();
+    let xobjects = page
+        .get(b"Resources")
+        .and_then(Object::as_dict)
+        .and_then(|r| r.get(b"XObject"))
+        .and_then(Object::as_dict)
+        .unwrap();
+    let mut text = String::new();
+    for (_, id) in xobjects.iter() {
+        let form = doc.get_object(id.as_reference().unwrap()).unwrap().as_stream().unwrap();
+        let data = form.decompressed_content().unwrap_or_else(|_| form.content.clone());
+        text.push_str(&String::from_utf8_lossy(&data));
+    }
+    text
+}
+
+#[test]
+fn test_stamp_numbers_across_files() {
+    let dir = tmp_dir("stamp_bates");
+    let first = dir.join("first.pdf");
+    let second = dir.join("second.pdf");
+    write_text_pdf(&first, &["A", "B"]);
+    write_text_pdf(&second, &["C", "D", "E"]);
+    let out = dir.join("out");
+
+    let report = run_ok(&[
+        "stamp",
+        path_str(&first),
+        path_str(&second),
+        "-o",
+        path_str(&out),
+        "--prefix",
+        "ABC",
+        "--start",
+        "10",
+        "--digits",
+        "4",
+        "-q",
+    ]);
+    let lines: Vec<&str> = report.lines().collect();
+    assert_eq!(lines.len(), 2, "{}", report);
+    assert!(lines[0].starts_with("ABC0010-ABC0011"), "{}", report);
+    assert!(lines[1].starts_with("ABC0012-ABC0014"), "{}", report);
+
+    let doc = Document::load(out.join("second.pdf")).unwrap();
+    assert!(stamped_text(&doc, 2).contains("(ABC0013) Tj"));
+    let doc = Document::load(out.join("first.pdf")).unwrap();
+    assert!(stamped_text(&doc, 1).contains("(ABC0010) Tj"));
+
+    let json = run_ok(&["stamp", path_str(&first), "-o", path_str(&out), "--json", "-q"]);
+    assert!(json.contains(r#""first":"000001","last":"000002","pages":2"#), "{}", json);
+}
+
+#[test]
+fn test_stamp_refuses_to_overwrite_input() {
+    let dir = tmp_dir("stamp_overwrite");
+    let input = dir.join("in.pdf");
+    write_text_pdf(&input, &["A"]);
+    let output = run(&["stamp", path_str(&input), "-o", path_str(&dir)]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("would be overwritten"));
+}
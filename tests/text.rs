@@ -0,0 +1,63 @@
+mod common;
+
+use common::{path_str, run_ok, tmp_dir, write_text_pdf};
+
+#[test]
+fn test_text_plain() {
+    let dir = tmp_dir("text_plain");
+    let input = dir.join("in.pdf");
+    write_text_pdf(&input, &["Account 1234", "Page two", "Page three"]);
+
+    let text = run_ok(&["text", path_str(&input), "-q"]);
+    // form feeds between pages, in page order
+    let pages: Vec<&str> = text.split('\x0c').map(str::trim).collect();
+    assert_eq!(pages, vec!["Account 1234", "Page two", "Page three"]);
+
+    let out = dir.join("out.txt");
+    run_ok(&["text", path_str(&input), "-o", path_str(&out), "--pages", "3,1", "-q"]);
+    let text = std::fs::read_to_string(&out).unwrap();
+    let pages: Vec<&str> = text.split('\x0c').map(str::trim).collect();
+    assert_eq!(pages, vec!["Page three", "Account 1234"]);
+}
+
+#[test]
+fn test_text_per_page() {
+    let dir = tmp_dir("text_per_page");
+    let input = dir.join("in.pdf");
+    write_text_pdf(&input, &["Account 1234", "Page two", "Page three"]);
+    let out = dir.join("pages");
+
+    run_ok(&["text", path_str(&input), "-o", path_str(&out), "--per-page", "-q"]);
+    let mut files: Vec<String> = std::fs::read_dir(&out)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    files.sort();
+    assert_eq!(files, vec!["in_0001.txt", "in_0002.txt", "in_0003.txt"]);
+    let second = std::fs::read_to_string(out.join("in_0002.txt")).unwrap();
+    assert_eq!(second.trim(), "Page two");
+
+    let json = dir.join("json");
+    let args = ["-o", path_str(&json), "--per-page", "--json", "--pages", "2", "-q"];
+    run_ok(&[&["text", path_str(&input)][..], &args].concat());
+    let page = std::fs::read_to_string(json.join("in_0002.json")).unwrap();
+    assert!(page.starts_with(r#"{"page":2,"width":300,"height":400,"text":"Page two"#), "{}", page);
+    assert!(!json.join("in_0001.json").exists());
+}
+
+#[test]
+fn test_text_json_word_boxes() {
+    let dir = tmp_dir("text_json");
+    let input = dir.join("in.pdf");
+    write_text_pdf(&input, &["Account 1234", "Page two"]);
+
+    let json = run_ok(&["text", path_str(&input), "--json", "-q"]);
+    let file = format!(r#"{{"file":"{}","pages":[{{"page":1,"#, path_str(&input));
+    assert!(json.starts_with(&file), "{}", json);
+    assert!(json.ends_with("]}\n"), "{}", json);
+    assert!(json.contains(r#""page":2,"width":300.0,"height":400.0,"#), "{}", json);
+    // the words of a line, split at the space, the first where the text starts
+    let first = json.find(r#"{"text":"Account","bbox":[72.0,"#).expect(&json);
+    let second = json.find(r#"{"text":"1234","bbox":["#).expect(&json);
+    assert!(first < second && second < json.find(r#"{"text":"two","#).unwrap());
+}
@@ -0,0 +1,56 @@
+mod common;
+
+use common::*;
+
+#[test]
+fn validate_passes_merge_output_and_flags_pdfa_gaps() {
+    let dir = tmp_dir("validate_pdfa");
+    let merged = make_pdf(&dir, "merged", 2, &[]);
+    let out = run_ok(&["validate", path_str(&merged)]);
+    assert_eq!(out.trim(), format!("{}: valid", merged.display()));
+
+    let text = dir.join("text.pdf");
+    write_text_pdf(&text, &["hello"]);
+    let output = run(&["validate", "--pdfa", "2b", "--json", path_str(&text)]);
+    assert_eq!(output.status.code(), Some(1));
+    let json = String::from_utf8(output.stdout).unwrap();
+    assert!(json.contains("\"valid\":false"), "{}", json);
+    assert!(json.contains("\"pdfa\":\"PDF/A-2b\""), "{}", json);
+    assert!(json.contains("font Helvetica isn't embedded"), "{}", json);
+    assert!(json.contains("needs XMP metadata"), "{}", json);
+}
+
+#[test]
+fn validate_reports_page_tree_problems() {
+    use lopdf::{dictionary, Document, Object};
+
+    let dir = tmp_dir("validate_tree");
+    let mut doc = Document::with_version("1.5");
+    let pages_id = doc.new_object_id();
+    let page_id = doc.add_object(dictionary! {
+        "Type" => "Page",
+        "Parent" => pages_id,
+        "Contents" => Object::Reference((99, 0)),
+        "Resources" => dictionary! {},
+    });
+    doc.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => vec![page_id.into()],
+            "Count" => 3,
+        }),
+    );
+    let catalog_id = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+    doc.trailer.set("Root", catalog_id);
+    let broken = dir.join("broken.pdf");
+    doc.save(&broken).unwrap();
+
+    let output = run(&["validate", path_str(&broken)]);
+    assert_eq!(output.status.code(), Some(1));
+    let out = String::from_utf8(output.stdout).unwrap();
+    assert!(out.contains("INVALID (2 error(s), 1 warning(s))"), "{}", out);
+    assert!(out.contains("error: page 1 has no /MediaBox"), "{}", out);
+    assert!(out.contains("says /Count 3 but holds 1 page(s)"), "{}", out);
+    assert!(out.contains("warning: reference to missing object 99 0"), "{}", out);
+}
@@ -0,0 +1,32 @@
+mod common;
+
+use common::*;
+
+#[test]
+fn watch_once_merges_folders_and_files_failures() {
+    let dir = tmp_dir("watch_once");
+    let scans = dir.join("scans");
+    std::fs::create_dir(&scans).unwrap();
+    write_tiny_png_rgb(&scans.join("1.png"));
+    write_tiny_jpeg_rgb(&scans.join("2.jpg"));
+    std::fs::write(dir.join("broken.pdf"), b"not a pdf").unwrap();
+    std::fs::write(dir.join("notes.txt"), b"left alone").unwrap();
+
+    run_ok(&["watch", "--once", path_str(&dir)]);
+
+    let merged = lopdf::Document::load(dir.join("output/scans.pdf")).unwrap();
+    assert_eq!(merged.get_pages().len(), 2);
+    assert!(dir.join("processed/scans/1.png").exists());
+    assert!(!scans.exists());
+    assert!(dir.join("failed/broken.pdf").exists());
+    assert!(dir.join("failed/broken.pdf.error.txt").exists());
+    assert!(dir.join("notes.txt").exists());
+
+    // a second folder of the same name doesn't overwrite the first in processed/
+    std::fs::create_dir(&scans).unwrap();
+    write_tiny_png_rgb(&scans.join("1.png"));
+    run_ok(&["watch", "--once", "--quiet", path_str(&dir)]);
+    assert!(dir.join("processed/scans-1/1.png").exists());
+    let merged = lopdf::Document::load(dir.join("output/scans.pdf")).unwrap();
+    assert_eq!(merged.get_pages().len(), 1);
+}
@@ -0,0 +1,120 @@
+mod common;
+
+use common::{path_str, run_ok, tmp_dir, write_text_pdf, write_tiny_png_rgb};
+use lopdf::{Document, Object, ObjectId};
+
+/// form XObjects a page stamps, by resource name
+fn stamped_forms(doc: &Document, page_id: ObjectId) -> Vec<ObjectId> {
+    let page = doc.get_dictionary(page_id).unwrap();
+    let Ok(resources) = page.get(b"Resources").and_then(Object::as_dict) else {
+        return Vec::new();
+    };
+    let Ok(xobjects) = resources.get(b"XObject").and_then(Object::as_dict) else {
+        return Vec::new();
+    };
+    xobjects
+        .iter()
+        .filter(|(name, _)| name.starts_with(b"Ov"))
+        .map(|(_, id)| id.as_reference().unwrap())
+        .collect()
+}
+
+#[test]
+fn test_watermark_text_on_selected_pages() {
+    let dir = tmp_dir("watermark_text");
+    let input = dir.join("in.pdf");
+    write_text_pdf(&input, &["Alpha", "Beta", "Gamma"]);
+    let out = dir.join("out.pdf");
+
+    run_ok(&[
+        "watermark",
+        path_str(&input),
+        "--text",
+        "DRAFT",
+        "--pages",
+        "1,3",
+        "-o",
+        path_str(&out),
+        "-q",
+    ]);
+
+    let doc = Document::load(&out).unwrap();
+    let pages = doc.get_pages();
+    let first = stamped_forms(&doc, pages[&1]);
+    assert_eq!(first.len(), 1);
+    assert!(stamped_forms(&doc, pages[&2]).is_empty());
+    // same page size, same form
+    assert_eq!(stamped_forms(&doc, pages[&3]), first);
+
+    let form = doc.get_object(first[0]).unwrap().as_stream().unwrap();
+    let resources = form.dict.get(b"Resources").unwrap().as_dict().unwrap();
+    assert!(resources.get(b"ExtGState").unwrap().as_dict().unwrap().has(b"GSwm"));
+    assert!(resources.get(b"Font").unwrap().as_dict().unwrap().has(b"Fwm"));
+    let text = String::from_utf8_lossy(&form.decompressed_content().unwrap()).into_owned();
+    assert!(text.contains("(DRAFT)"), "{}", text);
+
+    // the original content is still drawn, first
+    let content = doc.get_page_content(pages[&1]).unwrap();
+    let content = String::from_utf8_lossy(&content);
+    let alpha = content.find("(Alpha)").unwrap();
+    let stamp = content.find("/Ov0 Do").unwrap();
+    assert!(alpha < stamp, "{}", content);
+}
+
+#[test]
+fn test_watermark_image_on_rotated_page() {
+    let dir = tmp_dir("watermark_image");
+    let input = dir.join("in.pdf");
+    write_text_pdf(&input, &["Alpha"]);
+    let turned = dir.join("turned.pdf");
+    run_ok(&["rotate", path_str(&input), "-o", path_str(&turned), "-q"]);
+    let logo = dir.join("logo.png");
+    write_tiny_png_rgb(&logo);
+    let out = dir.join("out.pdf");
+
+    run_ok(&[
+        "watermark",
+        path_str(&turned),
+        "--image",
+        path_str(&logo),
+        "-o",
+        path_str(&out),
+        "-q",
+    ]);
+
+    let doc = Document::load(&out).unwrap();
+    let page_id = doc.get_pages()[&1];
+    let form = stamped_forms(&doc, page_id)[0];
+    let form = doc.get_object(form).unwrap().as_stream().unwrap();
+    // 300x400 page turned a quarter: drawn into a 400x300 upright view
+    let bbox: Vec<f32> = form
+        .dict
+        .get(b"BBox")
+        .and_then(Object::as_array)
+        .unwrap()
+        .iter()
+        .map(|o| o.as_float().unwrap())
+        .collect();
+    assert_eq!(bbox, vec![0.0, 0.0, 400.0, 300.0]);
+    let resources = form.dict.get(b"Resources").unwrap().as_dict().unwrap();
+    let image = resources
+        .get(b"XObject")
+        .and_then(Object::as_dict)
+        .and_then(|x| x.get(b"Imwm"))
+        .and_then(Object::as_reference)
+        .unwrap();
+    let image = doc.get_object(image).unwrap().as_stream().unwrap();
+    assert_eq!(image.dict.get(b"Subtype").unwrap().as_name().unwrap(), b"Image");
+}
+
+#[test]
+fn test_watermark_requires_content() {
+    let dir = tmp_dir("watermark_none");
+    let input = dir.join("in.pdf");
+    write_text_pdf(&input, &["Alpha"]);
+    let out = dir.join("out.pdf");
+
+    let output = common::run(&["watermark", path_str(&input), "-o", path_str(&out)]);
+    assert!(!output.status.success());
+    assert!(!out.exists());
+}